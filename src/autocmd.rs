@@ -0,0 +1,342 @@
+//! Autocommands: named events the editor fires as files are read and
+//! written, modes change and the cursor or text moves on. Plugins subscribe
+//! through `Plugin::events` and `Plugin::on_autocmd`; users with
+//! `:autocmd Event pattern command`.
+
+use std::collections::VecDeque;
+
+use regex::Regex;
+
+use crate::editor::{Cursor, Mode};
+use crate::file_index;
+
+/// An event autocommands can run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AutoEvent {
+    /// A file was read into the buffer.
+    BufReadPost,
+    /// Editing started on a file that does not exist yet.
+    BufNewFile,
+    /// The buffer is about to be written.
+    BufWritePre,
+    /// The buffer was written.
+    BufWritePost,
+    InsertEnter,
+    InsertLeave,
+    /// The mode changed; the pattern matches `old:new`, as `n:i`.
+    ModeChanged,
+    /// The cursor moved outside Insert mode.
+    CursorMoved,
+    /// The text changed outside Insert mode.
+    TextChanged,
+    /// The terminal got the focus back.
+    FocusGained,
+    /// The terminal lost the focus.
+    FocusLost,
+}
+
+impl AutoEvent {
+    pub const ALL: [AutoEvent; 11] = [
+        AutoEvent::BufReadPost,
+        AutoEvent::BufNewFile,
+        AutoEvent::BufWritePre,
+        AutoEvent::BufWritePost,
+        AutoEvent::InsertEnter,
+        AutoEvent::InsertLeave,
+        AutoEvent::ModeChanged,
+        AutoEvent::CursorMoved,
+        AutoEvent::TextChanged,
+        AutoEvent::FocusGained,
+        AutoEvent::FocusLost,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AutoEvent::BufReadPost => "BufReadPost",
+            AutoEvent::BufNewFile => "BufNewFile",
+            AutoEvent::BufWritePre => "BufWritePre",
+            AutoEvent::BufWritePost => "BufWritePost",
+            AutoEvent::InsertEnter => "InsertEnter",
+            AutoEvent::InsertLeave => "InsertLeave",
+            AutoEvent::ModeChanged => "ModeChanged",
+            AutoEvent::CursorMoved => "CursorMoved",
+            AutoEvent::TextChanged => "TextChanged",
+            AutoEvent::FocusGained => "FocusGained",
+            AutoEvent::FocusLost => "FocusLost",
+        }
+    }
+
+    /// The event called `name`, ignoring case; `BufRead` and `BufWrite`
+    /// are the Vim aliases of `BufReadPost` and `BufWritePre`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "bufread" => return Some(AutoEvent::BufReadPost),
+            "bufwrite" => return Some(AutoEvent::BufWritePre),
+            _ => {}
+        }
+        Self::ALL
+            .into_iter()
+            .find(|event| event.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// The letter `ModeChanged` patterns use for `mode`.
+pub fn mode_letter(mode: Mode) -> char {
+    match mode {
+        Mode::Normal => 'n',
+        Mode::Insert => 'i',
+        Mode::Visual => 'v',
+        Mode::Command => 'c',
+    }
+}
+
+/// A command to run when `event` fires for a name matching `pattern`.
+#[derive(Debug, Clone)]
+pub struct Autocmd {
+    pub event: AutoEvent,
+    pub pattern: String,
+    pub command: String,
+    /// `pattern`'s comma-separated globs.
+    globs: Vec<Regex>,
+}
+
+impl Autocmd {
+    /// Whether `pattern` matches `name`: the file path for buffer events,
+    /// `old:new` for `ModeChanged`. A glob without a `/` is matched against
+    /// the last component of the path only.
+    fn matches(&self, name: &str) -> bool {
+        let tail = name.rsplit('/').next().unwrap_or(name);
+        self.pattern
+            .split(',')
+            .zip(&self.globs)
+            .any(|(glob, regex)| regex.is_match(if glob.contains('/') { name } else { tail }))
+    }
+}
+
+/// The state the editor last fired events for, to tell what changed since.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seen {
+    pub mode: Mode,
+    pub cursor: Cursor,
+    pub revision: u64,
+}
+
+/// The user's autocommands and the events waiting to be handled.
+#[derive(Debug, Default)]
+pub struct Autocmds {
+    list: Vec<Autocmd>,
+    /// Fired events and the name their patterns match; `None` for the
+    /// file open when the event is handled.
+    queue: VecDeque<(AutoEvent, Option<String>)>,
+    pub seen: Option<Seen>,
+    /// A write waits behind its `BufWritePre` autocommands.
+    pub deferred_write: bool,
+    /// The number of queued commands behind those autocommands queued;
+    /// while more remain, an autocommand is running.
+    running_until: Option<usize>,
+}
+
+impl Autocmds {
+    /// Adds `command` for each of `events` on names matching `pattern`.
+    pub fn define(
+        &mut self,
+        events: &[AutoEvent],
+        pattern: &str,
+        command: &str,
+    ) -> Result<(), String> {
+        let globs = pattern
+            .split(',')
+            .map(|glob| Regex::new(&format!("^{}$", file_index::glob_to_regex(glob))))
+            .collect::<Result<Vec<Regex>, _>>()
+            .map_err(|err| format!("Invalid pattern {}: {}", pattern, err))?;
+        for &event in events {
+            self.list.push(Autocmd {
+                event,
+                pattern: pattern.to_string(),
+                command: command.to_string(),
+                globs: globs.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Removes the autocommands for `event` (all events if `None`) and
+    /// `pattern` (all patterns if `None`).
+    pub fn remove(&mut self, event: Option<AutoEvent>, pattern: Option<&str>) {
+        self.list.retain(|autocmd| {
+            event.is_some_and(|event| event != autocmd.event)
+                || pattern.is_some_and(|pattern| pattern != autocmd.pattern)
+        });
+    }
+
+    pub fn has(&self, event: AutoEvent) -> bool {
+        self.list.iter().any(|autocmd| autocmd.event == event)
+    }
+
+    /// The commands to run for `event` on `name`, in the order defined.
+    pub fn commands_for(&self, event: AutoEvent, name: &str) -> Vec<String> {
+        self.list
+            .iter()
+            .filter(|autocmd| autocmd.event == event && autocmd.matches(name))
+            .map(|autocmd| autocmd.command.clone())
+            .collect()
+    }
+
+    /// The autocommands as `:autocmd` lists them, grouped by event.
+    pub fn describe(&self, event: Option<AutoEvent>) -> Vec<String> {
+        let mut lines = Vec::new();
+        for listed in AutoEvent::ALL {
+            if event.is_some_and(|event| event != listed) {
+                continue;
+            }
+            let autocmds: Vec<&Autocmd> = self
+                .list
+                .iter()
+                .filter(|autocmd| autocmd.event == listed)
+                .collect();
+            if autocmds.is_empty() {
+                continue;
+            }
+            lines.push(listed.name().to_string());
+            for autocmd in autocmds {
+                lines.push(format!("    {:<12} {}", autocmd.pattern, autocmd.command));
+            }
+        }
+        lines
+    }
+
+    /// Queues `event` for `name` (`None` for the open file), unless an
+    /// autocommand is running: as in Vim, autocommands do not set off others.
+    pub fn fire(&mut self, event: AutoEvent, name: Option<String>) {
+        if self.running_until.is_none() {
+            self.queue.push_back((event, name));
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<(AutoEvent, Option<String>)> {
+        self.queue.pop_front()
+    }
+
+    /// Whether no fired event is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Notes that autocommands were queued in front of `behind` commands.
+    pub fn start_running(&mut self, behind: usize) {
+        self.running_until.get_or_insert(behind);
+    }
+
+    /// Called as a command is taken off the queue, leaving `remaining`
+    /// (`None` once it is empty): whether it was queued by an autocommand.
+    pub fn update_running(&mut self, remaining: Option<usize>) -> bool {
+        match (self.running_until, remaining) {
+            (Some(behind), Some(remaining)) if remaining >= behind => true,
+            _ => {
+                self.running_until = None;
+                false
+            }
+        }
+    }
+
+    /// Whether the command being run was queued by an autocommand.
+    pub fn is_running(&self) -> bool {
+        self.running_until.is_some()
+    }
+}
+
+/// The arguments of `:autocmd`; the pattern and command may be missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition<'a> {
+    /// `None` when no event was given; `*` gives them all.
+    pub events: Option<Vec<AutoEvent>>,
+    pub pattern: Option<&'a str>,
+    pub command: &'a str,
+}
+
+/// Splits `:autocmd` arguments into the events, the pattern and the
+/// command.
+pub fn parse(args: &str) -> Result<Definition<'_>, String> {
+    let mut parts = args.trim().splitn(3, char::is_whitespace);
+    let events = match parts.next().filter(|part| !part.is_empty()) {
+        Some("*") => Some(AutoEvent::ALL.to_vec()),
+        Some(names) => Some(
+            names
+                .split(',')
+                .map(|name| {
+                    AutoEvent::parse(name).ok_or_else(|| format!("No such event: {}", name))
+                })
+                .collect::<Result<Vec<AutoEvent>, String>>()?,
+        ),
+        None => None,
+    };
+    Ok(Definition {
+        events,
+        pattern: parts.next().filter(|part| !part.is_empty()),
+        command: parts.next().unwrap_or("").trim(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn autocommands_match_file_names_and_mode_changes() {
+        let mut autocmds = Autocmds::default();
+        let definition = parse("BufWritePre,bufread *.rs,*.toml echo rust").unwrap();
+        let events = definition.events.unwrap();
+        assert_eq!(events, [AutoEvent::BufWritePre, AutoEvent::BufReadPost]);
+        autocmds
+            .define(&events, definition.pattern.unwrap(), definition.command)
+            .unwrap();
+        autocmds
+            .define(&[AutoEvent::ModeChanged], "*:i", "echo insert")
+            .unwrap();
+        autocmds
+            .define(&[AutoEvent::BufWritePre], "src/*", "echo src")
+            .unwrap();
+        assert_eq!(
+            autocmds.commands_for(AutoEvent::BufWritePre, "src/main.rs"),
+            ["echo rust", "echo src"]
+        );
+        assert_eq!(
+            autocmds.commands_for(AutoEvent::BufReadPost, "/tmp/Cargo.toml"),
+            ["echo rust"]
+        );
+        assert!(
+            autocmds
+                .commands_for(AutoEvent::BufReadPost, "notes.md")
+                .is_empty()
+        );
+        assert_eq!(
+            autocmds.commands_for(AutoEvent::ModeChanged, "n:i"),
+            ["echo insert"]
+        );
+        assert!(
+            autocmds
+                .commands_for(AutoEvent::ModeChanged, "i:n")
+                .is_empty()
+        );
+        assert_eq!(
+            autocmds.describe(Some(AutoEvent::ModeChanged)),
+            ["ModeChanged", "    *:i          echo insert"]
+        );
+        assert!(parse("Nope * x").is_err());
+
+        autocmds.remove(Some(AutoEvent::BufWritePre), None);
+        assert!(!autocmds.has(AutoEvent::BufWritePre));
+        assert!(autocmds.has(AutoEvent::BufReadPost));
+        autocmds.remove(None, None);
+        assert!(!autocmds.has(AutoEvent::ModeChanged));
+
+        // Commands queued by an autocommand fire no events of their own.
+        autocmds.start_running(1);
+        assert!(autocmds.update_running(Some(2)));
+        autocmds.fire(AutoEvent::TextChanged, None);
+        assert!(autocmds.next_event().is_none());
+        assert!(!autocmds.update_running(Some(0)));
+        autocmds.fire(AutoEvent::TextChanged, None);
+        assert_eq!(autocmds.next_event().unwrap().0, AutoEvent::TextChanged);
+    }
+}
@@ -0,0 +1,194 @@
+//! Command-line argument parsing for minivim.
+
+use std::path::{Path, PathBuf};
+
+use crate::color::ColorDepth;
+
+pub const USAGE: &str = "usage: minivim [--headless] [--clean] [--color truecolor|256|16] \
+                          [+line | +command]... [-c command]... [-S session] [--script file] \
+                          [file[:line[:col]]]";
+
+/// Options parsed from the process arguments.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub file: Option<PathBuf>,
+    /// 1-based line and column from a `file:line:col` argument.
+    pub position: Option<(usize, usize)>,
+    /// Run commands without a terminal UI.
+    pub headless: bool,
+    /// Skip the user config file.
+    pub clean: bool,
+    /// Ex commands from `+` and `-c`, run in order after the file is loaded.
+    pub commands: Vec<String>,
+    /// File of ex commands, one per line, run before `commands`.
+    pub script: Option<PathBuf>,
+    /// Palette from `--color`, overriding the detected one.
+    pub color: Option<ColorDepth>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        let mut only_files = false;
+
+        while let Some(arg) = args.next() {
+            if !only_files && let Some(command) = arg.strip_prefix('+') {
+                // A bare `+` jumps to the last line, like vim.
+                let command = if command.is_empty() { "$" } else { command };
+                parsed.commands.push(command.to_string());
+                continue;
+            }
+            if only_files || !arg.starts_with('-') || arg == "-" {
+                parsed.set_file(arg)?;
+                continue;
+            }
+            match arg.as_str() {
+                "--" => only_files = true,
+                "--headless" => parsed.headless = true,
+                "--clean" => parsed.clean = true,
+                "-c" => {
+                    let command = args.next().ok_or("-c requires a command")?;
+                    parsed.commands.push(command);
+                }
+                "-S" => {
+                    let session = args.next().ok_or("-S requires a session file")?;
+                    parsed.commands.push(format!("source {}", session));
+                }
+                "--script" => {
+                    let script = args.next().ok_or("--script requires a file")?;
+                    parsed.script = Some(PathBuf::from(script));
+                }
+                "--color" => {
+                    let value = args.next().ok_or("--color requires truecolor, 256 or 16")?;
+                    let depth = ColorDepth::parse(&value)
+                        .ok_or_else(|| format!("invalid --color value: {}", value))?;
+                    parsed.color = Some(depth);
+                }
+                _ => return Err(format!("unknown option: {}", arg)),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn set_file(&mut self, arg: String) -> Result<(), String> {
+        if self.file.is_some() {
+            return Err(format!("unexpected argument: {}", arg));
+        }
+        if !Path::new(&arg).exists()
+            && let Some((path, line, col)) = split_file_position(&arg)
+        {
+            self.file = Some(PathBuf::from(path));
+            self.position = Some((line, col.unwrap_or(1)));
+            return Ok(());
+        }
+        self.file = Some(PathBuf::from(arg));
+        Ok(())
+    }
+}
+
+/// Splits `path:line[:col]` (as printed by compilers and grep, optionally with a
+/// trailing `:`) into its parts.
+pub fn split_file_position(arg: &str) -> Option<(&str, usize, Option<usize>)> {
+    let arg = arg.strip_suffix(':').unwrap_or(arg);
+    let (rest, last) = arg.rsplit_once(':')?;
+    let last: usize = last.parse().ok()?;
+    if let Some((path, line)) = rest.rsplit_once(':')
+        && let Ok(line) = line.parse::<usize>()
+        && !path.is_empty()
+    {
+        return Some((path, line, Some(last)));
+    }
+    if rest.is_empty() {
+        return None;
+    }
+    Some((rest, last, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_headless_commands_in_order() {
+        let args = parse(&["--headless", "-c", "set kp=man", "-c", "wq", "file.txt"]).unwrap();
+        assert!(args.headless);
+        assert!(!args.clean);
+        assert_eq!(args.commands, vec!["set kp=man", "wq"]);
+        assert_eq!(args.file, Some(PathBuf::from("file.txt")));
+    }
+
+    #[test]
+    fn parse_plus_arguments_as_commands() {
+        let args = parse(&["+42", "file.txt", "+set kp=pydoc", "-c", "w", "+"]).unwrap();
+        assert_eq!(args.commands, vec!["42", "set kp=pydoc", "w", "$"]);
+        assert_eq!(args.file, Some(PathBuf::from("file.txt")));
+        assert_eq!(
+            parse(&["--", "+file"]).unwrap().file,
+            Some(PathBuf::from("+file"))
+        );
+    }
+
+    #[test]
+    fn parse_session_flag_sources_file() {
+        let args = parse(&["-S", "Session.minivim"]).unwrap();
+        assert_eq!(args.commands, vec!["source Session.minivim"]);
+        assert_eq!(args.file, None);
+    }
+
+    #[test]
+    fn parse_color_override() {
+        let args = parse(&["--color", "16", "file.txt"]).unwrap();
+        assert_eq!(args.color, Some(ColorDepth::Ansi16));
+        assert_eq!(parse(&["file.txt"]).unwrap().color, None);
+        assert!(parse(&["--color", "many"]).is_err());
+        assert!(parse(&["--color"]).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_options_and_extra_files() {
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["-c"]).is_err());
+        assert!(parse(&["a.txt", "b.txt"]).is_err());
+        assert_eq!(
+            parse(&["--", "-dash.txt"]).unwrap().file,
+            Some(PathBuf::from("-dash.txt"))
+        );
+    }
+
+    #[test]
+    fn split_file_position_handles_line_and_column() {
+        assert_eq!(
+            split_file_position("src/main.rs:120:8"),
+            Some(("src/main.rs", 120, Some(8)))
+        );
+        assert_eq!(
+            split_file_position("src/main.rs:12:"),
+            Some(("src/main.rs", 12, None))
+        );
+        assert_eq!(split_file_position("notes.txt"), None);
+        assert_eq!(split_file_position("notes:txt"), None);
+        assert_eq!(split_file_position(":12"), None);
+    }
+
+    #[test]
+    fn parse_prefers_existing_paths_with_colons() {
+        let dir = tempfile::tempdir().unwrap();
+        let literal = dir.path().join("odd:12");
+        std::fs::write(&literal, "").unwrap();
+        let arg = literal.display().to_string();
+
+        let args = parse(&[&arg]).unwrap();
+        assert_eq!(args.file, Some(literal.clone()));
+        assert_eq!(args.position, None);
+
+        let args = parse(&[&format!("{}:3:4", arg)]).unwrap();
+        assert_eq!(args.file, Some(literal));
+        assert_eq!(args.position, Some((3, 4)));
+    }
+}
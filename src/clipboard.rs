@@ -0,0 +1,91 @@
+//! The system clipboard behind the `+` and `*` registers, reached through
+//! whichever clipboard program is installed: `wl-copy` / `wl-paste` on
+//! Wayland, `xclip` or `xsel` on X11 and `pbcopy` / `pbpaste` on macOS.
+//! `+` is the clipboard and `*` the primary selection, which macOS does
+//! not have.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use crate::provider;
+
+/// Whether `name` is a clipboard register.
+pub fn is_clipboard(name: char) -> bool {
+    matches!(name, '+' | '*')
+}
+
+/// The programs and arguments that read (`paste`) or write the selection
+/// of register `name`, in the order they are tried.
+fn tools(name: char, paste: bool) -> Vec<(&'static str, Vec<&'static str>)> {
+    let primary = name == '*';
+    let mut tools = Vec::new();
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut args = if paste { vec!["--no-newline"] } else { vec![] };
+        if primary {
+            args.push("--primary");
+        }
+        tools.push((if paste { "wl-paste" } else { "wl-copy" }, args));
+    }
+    let selection = if primary { "primary" } else { "clipboard" };
+    let direction = if paste { "-o" } else { "-i" };
+    tools.push(("xclip", vec!["-selection", selection, direction]));
+    let selection = if primary { "--primary" } else { "--clipboard" };
+    let direction = if paste { "--output" } else { "--input" };
+    tools.push(("xsel", vec![selection, direction]));
+    tools.push((if paste { "pbpaste" } else { "pbcopy" }, vec![]));
+    tools
+}
+
+fn no_tool() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        "no clipboard program found (wl-copy, xclip, xsel or pbcopy)",
+    )
+}
+
+/// The text of register `name`'s selection.
+pub fn read(name: char) -> io::Result<String> {
+    for (program, args) in tools(name, true) {
+        let output = match Command::new(program)
+            .args(&args)
+            .stdin(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(provider::spawn_error(program, err)),
+        };
+        if !output.status.success() {
+            return Err(provider::command_error(program, &output));
+        }
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    Err(no_tool())
+}
+
+/// Puts `text` in register `name`'s selection. The programs keep serving
+/// it in the background, so their output is not waited for.
+pub fn write(name: char, text: &str) -> io::Result<()> {
+    for (program, args) in tools(name, false) {
+        let mut child = match Command::new(program)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(provider::spawn_error(program, err)),
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("{} failed: {}", program, status)));
+        }
+        return Ok(());
+    }
+    Err(no_tool())
+}
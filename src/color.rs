@@ -0,0 +1,182 @@
+//! How many colors the terminal can show, and mapping 24-bit colors down to
+//! the nearest entry of a smaller palette.
+
+use std::env;
+
+use crossterm::style::{Color, ContentStyle};
+
+/// The palette the terminal supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// 24-bit RGB.
+    TrueColor,
+    /// The xterm 256-color palette.
+    Ansi256,
+    /// The 16 basic ANSI colors.
+    Ansi16,
+}
+
+/// RGB values of the 16 basic colors, as xterm shows them.
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Channel levels of the 6x6x6 color cube in the 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl ColorDepth {
+    /// Parses a `--color` value: `truecolor` (or `24bit`), `256` or `16`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "truecolor" | "24bit" => Some(Self::TrueColor),
+            "256" => Some(Self::Ansi256),
+            "16" => Some(Self::Ansi16),
+            _ => None,
+        }
+    }
+
+    /// Guesses the terminal's palette from `COLORTERM` and `TERM`.
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        Self::from_env(&colorterm, &term)
+    }
+
+    fn from_env(colorterm: &str, term: &str) -> Self {
+        if matches!(colorterm, "truecolor" | "24bit") || term.ends_with("-direct") {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Ansi256
+        } else {
+            Self::Ansi16
+        }
+    }
+
+    /// Replaces colors in `style` that this palette cannot show with the
+    /// nearest ones it can.
+    pub fn adapt_style(self, mut style: ContentStyle) -> ContentStyle {
+        style.foreground_color = style.foreground_color.map(|color| self.adapt(color));
+        style.background_color = style.background_color.map(|color| self.adapt(color));
+        style.underline_color = style.underline_color.map(|color| self.adapt(color));
+        style
+    }
+
+    /// The nearest color to `color` in this palette.
+    pub fn adapt(self, color: Color) -> Color {
+        match (self, color) {
+            (Self::TrueColor, _) => color,
+            (Self::Ansi256, Color::Rgb { r, g, b }) => Color::AnsiValue(nearest_256((r, g, b))),
+            (Self::Ansi16, Color::Rgb { r, g, b }) => nearest_16((r, g, b)),
+            (Self::Ansi16, Color::AnsiValue(value)) if value >= 16 => {
+                nearest_16(ansi256_rgb(value))
+            }
+            _ => color,
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (i32::from(x) - i32::from(y)).unsigned_abs().pow(2);
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn nearest_16(rgb: (u8, u8, u8)) -> Color {
+    ANSI16
+        .iter()
+        .min_by_key(|(_, entry)| distance(rgb, *entry))
+        .map(|(color, _)| *color)
+        .unwrap_or(Color::Reset)
+}
+
+/// The closer of the nearest color cube entry and the nearest gray.
+fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |channel: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&idx| CUBE_LEVELS[idx].abs_diff(channel))
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * level(rgb.0) + 6 * level(rgb.1) + level(rgb.2);
+    let average = (u16::from(rgb.0) + u16::from(rgb.1) + u16::from(rgb.2)) / 3;
+    let gray = 232 + (average.saturating_sub(3) / 10).min(23) as u8;
+    if distance(rgb, ansi256_rgb(gray)) < distance(rgb, ansi256_rgb(cube)) {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// RGB value of an entry of the 256-color palette.
+fn ansi256_rgb(value: u8) -> (u8, u8, u8) {
+    match value {
+        0..16 => ANSI16[value as usize].1,
+        16..232 => {
+            let idx = value - 16;
+            (
+                CUBE_LEVELS[(idx / 36) as usize],
+                CUBE_LEVELS[(idx / 6 % 6) as usize],
+                CUBE_LEVELS[(idx % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (value - 232);
+            (level, level, level)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_comes_from_colorterm_then_term() {
+        assert_eq!(
+            ColorDepth::from_env("truecolor", "xterm"),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(
+            ColorDepth::from_env("", "xterm-256color"),
+            ColorDepth::Ansi256
+        );
+        assert_eq!(ColorDepth::from_env("", "linux"), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::parse("256"), Some(ColorDepth::Ansi256));
+        assert_eq!(ColorDepth::parse("8"), None);
+    }
+
+    #[test]
+    fn rgb_colors_map_to_the_nearest_palette_entry() {
+        let orange = Color::Rgb {
+            r: 208,
+            g: 135,
+            b: 112,
+        };
+        assert_eq!(ColorDepth::TrueColor.adapt(orange), orange);
+        assert_eq!(ColorDepth::Ansi256.adapt(orange), Color::AnsiValue(173));
+        assert_eq!(ColorDepth::Ansi16.adapt(orange), Color::DarkGrey);
+        let gray = Color::Rgb {
+            r: 43,
+            g: 48,
+            b: 59,
+        };
+        assert_eq!(ColorDepth::Ansi256.adapt(gray), Color::AnsiValue(236));
+        let red = Color::Rgb { r: 250, g: 0, b: 0 };
+        assert_eq!(ColorDepth::Ansi16.adapt(red), Color::Red);
+        assert_eq!(ColorDepth::Ansi16.adapt(Color::AnsiValue(196)), Color::Red);
+        assert_eq!(ColorDepth::Ansi16.adapt(Color::Blue), Color::Blue);
+    }
+}
@@ -0,0 +1,104 @@
+//! Line comments toggled by `gc`.
+
+/// Line-comment prefix for a file extension; unknown types use `#`.
+pub fn prefix_for(extension: &str) -> &'static str {
+    match extension {
+        "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "go" | "java" | "js" | "jsx" | "ts"
+        | "tsx" | "kt" | "swift" | "scala" | "dart" | "php" | "zig" | "proto" | "jsonc" => "//",
+        "lua" | "sql" | "hs" | "elm" | "ada" => "--",
+        "vim" => "\"",
+        "lisp" | "el" | "clj" | "scm" | "ini" | "asm" => ";",
+        "tex" | "sty" | "erl" | "m" => "%",
+        _ => "#",
+    }
+}
+
+fn leading_whitespace(line: &str) -> usize {
+    line.chars().take_while(|ch| ch.is_whitespace()).count()
+}
+
+/// Whether every non-blank line starts with `prefix` after its indent.
+pub fn all_commented(lines: &[String], prefix: &str) -> bool {
+    lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .all(|line| line.trim_start().starts_with(prefix))
+}
+
+/// Comments `lines` out, or uncomments them if they are all commented.
+/// Comments go at the shallowest indent so the block stays aligned, and
+/// blank lines are left alone.
+pub fn toggle(lines: &[String], prefix: &str) -> Vec<String> {
+    if all_commented(lines, prefix) {
+        return lines
+            .iter()
+            .map(|line| {
+                let indent = line.len() - line.trim_start().len();
+                let Some(rest) = line[indent..].strip_prefix(prefix) else {
+                    return line.clone();
+                };
+                let rest = rest.strip_prefix(' ').unwrap_or(rest);
+                format!("{}{}", &line[..indent], rest)
+            })
+            .collect();
+    }
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| leading_whitespace(line))
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim().is_empty() {
+                return line.clone();
+            }
+            let split = line
+                .char_indices()
+                .nth(indent)
+                .map_or(line.len(), |(byte, _)| byte);
+            format!("{}{} {}", &line[..split], prefix, &line[split..])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn comments_at_the_shallowest_indent() {
+        let block = lines(&["    if x {", "", "        y();", "    }"]);
+        assert_eq!(
+            toggle(&block, "//"),
+            lines(&["    // if x {", "", "    //     y();", "    // }"])
+        );
+    }
+
+    #[test]
+    fn uncomments_when_every_line_is_commented() {
+        let block = lines(&["  # a", "", "  #b"]);
+        assert_eq!(toggle(&block, "#"), lines(&["  a", "", "  b"]));
+        let mixed = lines(&["# a", "b"]);
+        assert_eq!(toggle(&mixed, "#"), lines(&["# # a", "# b"]));
+    }
+
+    #[test]
+    fn toggling_twice_restores_the_lines() {
+        let block = lines(&["\tfn f() {}", "\t\tlet x = 1;"]);
+        assert_eq!(toggle(&toggle(&block, "//"), "//"), block);
+    }
+
+    #[test]
+    fn prefixes_follow_the_file_type() {
+        assert_eq!(prefix_for("rs"), "//");
+        assert_eq!(prefix_for("lua"), "--");
+        assert_eq!(prefix_for("py"), "#");
+        assert_eq!(prefix_for(""), "#");
+    }
+}
@@ -0,0 +1,100 @@
+//! Compressed files, edited as their uncompressed text: `.gz` files through
+//! `gzip` and `.zst` files through `zstd`, and written back in the same
+//! format.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::provider::command_error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of the file at `path`, by its extension.
+    pub fn for_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Self::Gzip),
+            "zst" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    pub fn decompress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        filter(self.program(), &["--decompress", "--stdout"], bytes)
+    }
+
+    pub fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let args: &[&str] = match self {
+            Self::Gzip => &["--stdout"],
+            Self::Zstd => &["--quiet", "--stdout"],
+        };
+        filter(self.program(), args, bytes)
+    }
+}
+
+/// Runs `program` with `args`, feeding it `input`, and returns what it
+/// printed.
+fn filter(program: &str, args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| io::Error::other(format!("cannot run {}: {}", program, err)))?;
+    let stdin = child.stdin.take();
+    let output = thread::scope(|scope| {
+        if let Some(mut stdin) = stdin {
+            // The output is read meanwhile, or a full pipe would stall both.
+            scope.spawn(move || {
+                let _ = stdin.write_all(input);
+            });
+        }
+        child.wait_with_output()
+    })?;
+    if !output.status.success() {
+        return Err(command_error(program, &output));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_text_round_trips_through_its_program() {
+        assert_eq!(
+            Compression::for_path(Path::new("app.log.gz")),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::for_path(Path::new("dump.zst")),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::for_path(Path::new("notes.txt")), None);
+
+        let text = "line\n".repeat(50_000);
+        let packed = Compression::Gzip.compress(text.as_bytes()).unwrap();
+        assert!(packed.starts_with(&[0x1f, 0x8b]));
+        assert!(packed.len() < text.len());
+        let unpacked = Compression::Gzip.decompress(&packed).unwrap();
+        assert_eq!(unpacked, text.as_bytes());
+
+        let err = Compression::Gzip.decompress(b"not gzip").unwrap_err();
+        assert!(err.to_string().contains("not in gzip format"), "{}", err);
+    }
+}
@@ -0,0 +1,142 @@
+//! Files kept encrypted on disk: `.age` files with `age`, and `.gpg`, `.pgp`
+//! and `.asc` files with `gpg`. The text is piped through the program, which
+//! may ask for a passphrase, so the plaintext is only ever in the buffer and
+//! never written to a file.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Age,
+    /// `armor` for `.asc` files, which are written as text.
+    Gpg {
+        armor: bool,
+    },
+}
+
+impl Cipher {
+    /// The cipher of the file at `path`, by its extension.
+    pub fn for_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "age" => Some(Self::Age),
+            "gpg" | "pgp" => Some(Self::Gpg { armor: false }),
+            "asc" => Some(Self::Gpg { armor: true }),
+            _ => None,
+        }
+    }
+
+    pub fn program(self) -> &'static str {
+        match self {
+            Self::Age => "age",
+            Self::Gpg { .. } => "gpg",
+        }
+    }
+
+    /// The program reading ciphertext on its standard input and printing
+    /// the plaintext. age decrypts with the `identity` file when there is
+    /// one, and otherwise asks for the passphrase.
+    pub fn decrypt(self, identity: &str) -> Command {
+        let mut command = Command::new(self.program());
+        match self {
+            Self::Age => {
+                command.arg("--decrypt");
+                if !identity.is_empty() {
+                    command.args(["--identity", identity]);
+                }
+            }
+            Self::Gpg { .. } => {
+                command.args(["--quiet", "--decrypt"]);
+            }
+        }
+        command.stdout(Stdio::piped());
+        command
+    }
+
+    /// The program reading plaintext on its standard input and printing it
+    /// encrypted to the comma-separated `recipients`. Without any, age asks
+    /// for a passphrase and gpg encrypts to your own key.
+    pub fn encrypt(self, recipients: &str) -> Command {
+        let recipients: Vec<&str> = recipients
+            .split(',')
+            .map(str::trim)
+            .filter(|recipient| !recipient.is_empty())
+            .collect();
+        let mut command = Command::new(self.program());
+        match self {
+            Self::Age => {
+                command.arg("--encrypt");
+                if recipients.is_empty() {
+                    command.arg("--passphrase");
+                }
+            }
+            Self::Gpg { armor } => {
+                command.args(["--quiet", "--encrypt"]);
+                if armor {
+                    command.arg("--armor");
+                }
+                if recipients.is_empty() {
+                    command.arg("--default-recipient-self");
+                }
+            }
+        }
+        for recipient in recipients {
+            command.args(["--recipient", recipient]);
+        }
+        command.stdout(Stdio::piped());
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &Command) -> Vec<&str> {
+        command
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn ciphers_are_chosen_by_extension_and_run_their_program() {
+        assert_eq!(Cipher::for_path(Path::new("keys.age")), Some(Cipher::Age));
+        assert_eq!(
+            Cipher::for_path(Path::new("notes.txt.asc")),
+            Some(Cipher::Gpg { armor: true })
+        );
+        assert_eq!(Cipher::for_path(Path::new("notes.txt")), None);
+
+        let age = Cipher::Age;
+        assert_eq!(age.decrypt("").get_program(), "age");
+        assert_eq!(args(&age.decrypt("")), ["--decrypt"]);
+        assert_eq!(
+            args(&age.decrypt("/keys.txt")),
+            ["--decrypt", "--identity", "/keys.txt"]
+        );
+        assert_eq!(args(&age.encrypt("")), ["--encrypt", "--passphrase"]);
+        assert_eq!(
+            args(&age.encrypt("age1abc, age1def")),
+            [
+                "--encrypt",
+                "--recipient",
+                "age1abc",
+                "--recipient",
+                "age1def"
+            ]
+        );
+
+        let gpg = Cipher::Gpg { armor: true };
+        assert_eq!(
+            args(&gpg.encrypt("")),
+            [
+                "--quiet",
+                "--encrypt",
+                "--armor",
+                "--default-recipient-self"
+            ]
+        );
+        assert_eq!(args(&gpg.decrypt("ignored")), ["--quiet", "--decrypt"]);
+    }
+}
@@ -0,0 +1,141 @@
+//! Delimiter-separated files, as `:CsvAlign` shows them: each line split
+//! into fields at the delimiters outside double quotes, and the padding
+//! that lines the fields up in columns.
+
+use std::path::Path;
+
+use crate::editor::display_col;
+
+/// Delimiters guessed from a file's first line, most likely first.
+const DELIMITERS: [char; 4] = [',', '\t', ';', '|'];
+
+/// The delimiter of a file: a tab for `.tsv` and `.tab` files, otherwise
+/// whichever of `,`, tab, `;` and `|` its first line has most of.
+pub fn detect_delimiter(path: Option<&Path>, first_line: &str) -> char {
+    let extension = path.and_then(Path::extension).and_then(|ext| ext.to_str());
+    if matches!(extension, Some("tsv" | "tab")) {
+        return '\t';
+    }
+    DELIMITERS
+        .into_iter()
+        .rev()
+        .max_by_key(|delimiter| delimiters(first_line, *delimiter).len())
+        .filter(|delimiter| !delimiters(first_line, *delimiter).is_empty())
+        .unwrap_or(',')
+}
+
+/// Char columns of the delimiters separating the fields of `line`; those
+/// inside a double-quoted field are part of it.
+pub fn delimiters(line: &str, delimiter: char) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut quoted = false;
+    for (col, ch) in line.chars().enumerate() {
+        if ch == '"' {
+            quoted = !quoted;
+        } else if ch == delimiter && !quoted {
+            found.push(col);
+        }
+    }
+    found
+}
+
+/// The display width of each column but the last: the most any line takes
+/// from the start of the field to the start of the next, delimiter and all.
+pub fn column_widths(lines: &[String], delimiter: char, tabstop: usize) -> Vec<usize> {
+    let mut widths: Vec<usize> = Vec::new();
+    for line in lines {
+        let mut start = 0;
+        for (index, col) in delimiters(line, delimiter).into_iter().enumerate() {
+            let next = display_col(line, col + 1, tabstop);
+            if index == widths.len() {
+                widths.push(0);
+            }
+            widths[index] = widths[index].max(next - start);
+            start = next;
+        }
+    }
+    widths
+}
+
+/// The spaces to draw before each delimiter of `line` so its fields start
+/// in the columns `widths` make, as display columns and counts, left to
+/// right. Delimiters already in place get none.
+pub fn padding(
+    line: &str,
+    delimiter: char,
+    widths: &[usize],
+    tabstop: usize,
+) -> Vec<(usize, usize)> {
+    let mut pads = Vec::new();
+    let mut start = 0;
+    for (col, width) in delimiters(line, delimiter).into_iter().zip(widths) {
+        let at = display_col(line, col, tabstop);
+        let next = display_col(line, col + 1, tabstop);
+        if next - start < *width {
+            pads.push((at, width - (next - start)));
+        }
+        start = next;
+    }
+    pads
+}
+
+/// The char column of the field after the one at `col`, if `line` has one.
+pub fn next_field(line: &str, delimiter: char, col: usize) -> Option<usize> {
+    delimiters(line, delimiter)
+        .into_iter()
+        .find(|delim| *delim >= col)
+        .map(|delim| delim + 1)
+}
+
+/// The char column where the field before the one at `col` starts, if
+/// `line` has one.
+pub fn prev_field(line: &str, delimiter: char, col: usize) -> Option<usize> {
+    let starts: Vec<usize> = std::iter::once(0)
+        .chain(
+            delimiters(line, delimiter)
+                .into_iter()
+                .map(|delim| delim + 1),
+        )
+        .collect();
+    let current = starts.iter().rposition(|start| *start <= col)?;
+    current.checked_sub(1).map(|index| starts[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn fields_are_split_outside_quotes_and_padded_into_columns() {
+        assert_eq!(delimiters(r#"a,"b,c",d"#, ','), [1, 7]);
+        assert_eq!(detect_delimiter(None, "name;age;city"), ';');
+        assert_eq!(detect_delimiter(None, "a,b\tc,d"), ',');
+        assert_eq!(detect_delimiter(None, "plain"), ',');
+        assert_eq!(detect_delimiter(Some(Path::new("t.tsv")), "a,b"), '\t');
+
+        let table = lines(&["name,age,city", "Alexandra,7,Oslo", "Bo,42"]);
+        let widths = column_widths(&table, ',', 8);
+        assert_eq!(widths, [10, 4]);
+        assert_eq!(padding(&table[0], ',', &widths, 8), [(4, 5)]);
+        assert_eq!(padding(&table[1], ',', &widths, 8), [(11, 2)]);
+        assert_eq!(padding(&table[2], ',', &widths, 8), [(2, 7)]);
+
+        // A tab takes the columns to the next tab stop, however far that is.
+        let tabbed = lines(&["a\tb\tc", "abcdefgh\tb"]);
+        let widths = column_widths(&tabbed, '\t', 8);
+        assert_eq!(widths, [16, 8]);
+        assert_eq!(padding(&tabbed[0], '\t', &widths, 8), [(1, 8)]);
+
+        let line = "ab,cd,ef";
+        assert_eq!(next_field(line, ',', 0), Some(3));
+        assert_eq!(next_field(line, ',', 2), Some(3));
+        assert_eq!(next_field(line, ',', 6), None);
+        assert_eq!(prev_field(line, ',', 7), Some(3));
+        assert_eq!(prev_field(line, ',', 4), Some(0));
+        assert_eq!(prev_field(line, ',', 1), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! The edits made to the buffer, each as the text a range held and the text
+//! that replaced it, so plugins that keep state about the buffer can update
+//! just what changed rather than starting over on every keystroke.
+
+use std::collections::VecDeque;
+
+use crate::editor::Cursor;
+
+/// Most deltas kept; a plugin further behind starts over from the buffer.
+pub const DELTA_LOG_LIMIT: usize = 1000;
+
+/// One edit: the text from `start` up to (not including) `end`, positions in
+/// the buffer as it was, held `old_text` and now holds `new_text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub start: Cursor,
+    pub end: Cursor,
+    pub old_text: String,
+    pub new_text: String,
+}
+
+impl Delta {
+    /// Where the new text ends in the buffer after the edit.
+    pub fn new_end(&self) -> Cursor {
+        match self.new_text.rsplit_once('\n') {
+            Some((before, last)) => Cursor {
+                row: self.start.row + before.matches('\n').count() + 1,
+                col: last.chars().count(),
+            },
+            None => Cursor {
+                row: self.start.row,
+                col: self.start.col + self.new_text.chars().count(),
+            },
+        }
+    }
+}
+
+/// The recent deltas, numbered by revision. Edits made together, such as
+/// the halves of a replacement, share a revision.
+#[derive(Debug, Clone, Default)]
+pub struct DeltaLog {
+    revision: u64,
+    /// Deltas of revisions after `complete_from`, each with its revision,
+    /// oldest first. Deltas of `revision + 1` are still being made.
+    deltas: VecDeque<(u64, Delta)>,
+    /// The oldest revision the kept deltas lead on from; before it some were
+    /// dropped, or the whole buffer was replaced.
+    complete_from: u64,
+}
+
+impl DeltaLog {
+    /// Counts the edits: it goes up whenever the buffer changes.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Adds `delta` to the revision being made, which `commit` finishes,
+    /// and returns it.
+    pub fn record(&mut self, delta: Delta) -> &Delta {
+        self.deltas.push_back((self.revision + 1, delta));
+        if self.deltas.len() > DELTA_LOG_LIMIT
+            && let Some((revision, _)) = self.deltas.pop_front()
+        {
+            self.complete_from = self.complete_from.max(revision);
+        }
+        &self.deltas[self.deltas.len() - 1].1
+    }
+
+    /// Finishes the revision being made.
+    pub fn commit(&mut self) {
+        self.revision += 1;
+    }
+
+    /// Records that the whole buffer was replaced, as when a file is loaded:
+    /// nothing earlier leads to the new text.
+    pub fn reset(&mut self) {
+        self.revision += 1;
+        self.deltas.clear();
+        self.complete_from = self.revision;
+    }
+
+    /// The deltas made after `revision`, oldest first, or `None` when they
+    /// are not all known and the buffer has to be read afresh.
+    pub fn since(&self, revision: u64) -> Option<impl Iterator<Item = &Delta>> {
+        if revision < self.complete_from || revision > self.revision {
+            return None;
+        }
+        let current = self.revision;
+        Some(
+            self.deltas
+                .iter()
+                .filter(move |(made, _)| *made > revision && *made <= current)
+                .map(|(_, delta)| delta),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(row: usize, col: usize, new_text: &str) -> Delta {
+        let start = Cursor { row, col };
+        Delta {
+            start,
+            end: start,
+            old_text: String::new(),
+            new_text: new_text.to_string(),
+        }
+    }
+
+    #[test]
+    fn deltas_are_listed_since_a_revision_until_they_are_dropped() {
+        assert_eq!(delta(1, 2, "ab").new_end(), Cursor { row: 1, col: 4 });
+        assert_eq!(delta(1, 2, "a\n\nbc").new_end(), Cursor { row: 3, col: 2 });
+
+        let mut log = DeltaLog::default();
+        assert_eq!(log.since(0).map(Iterator::count), Some(0));
+        log.record(delta(0, 0, "a"));
+        log.record(delta(0, 1, "b"));
+        log.commit();
+        log.record(delta(0, 2, "c"));
+        log.commit();
+        assert_eq!(log.revision(), 2);
+        let texts = |log: &DeltaLog, revision| -> Option<Vec<String>> {
+            let since = log.since(revision)?;
+            Some(since.map(|delta| delta.new_text.clone()).collect())
+        };
+        assert_eq!(texts(&log, 0).unwrap(), ["a", "b", "c"]);
+        assert_eq!(texts(&log, 1).unwrap(), ["c"]);
+        assert!(texts(&log, 3).is_none());
+
+        log.reset();
+        assert!(texts(&log, 2).is_none());
+        assert_eq!(texts(&log, 3).unwrap(), Vec::<String>::new());
+        for col in 0..=DELTA_LOG_LIMIT {
+            log.record(delta(0, col, "x"));
+            log.commit();
+        }
+        assert!(texts(&log, 3).is_none());
+        assert_eq!(texts(&log, 4).unwrap().len(), DELTA_LOG_LIMIT);
+    }
+}
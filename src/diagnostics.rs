@@ -0,0 +1,183 @@
+//! Diagnostics from linters and compilers: parsing their
+//! `path:line:col: severity: message` output for the current file, and
+//! moving between the results with `]d` and `[d`.
+
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::editor::{Cursor, is_word_char};
+use crate::theme::HighlightGroup;
+
+/// Columns of the sign column: the severity letter and a space.
+pub const SIGN_COLUMN_WIDTH: usize = 2;
+
+/// How serious a diagnostic is, most serious first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    fn from_word(word: &str) -> Self {
+        match word.to_ascii_lowercase().as_str() {
+            "warning" | "warn" => Self::Warning,
+            "note" | "info" => Self::Info,
+            "help" | "hint" => Self::Hint,
+            _ => Self::Error,
+        }
+    }
+
+    /// The letter drawn in the sign column.
+    pub fn sign(self) -> char {
+        match self {
+            Self::Error => 'E',
+            Self::Warning => 'W',
+            Self::Info => 'I',
+            Self::Hint => 'H',
+        }
+    }
+
+    pub fn group(self) -> HighlightGroup {
+        match self {
+            Self::Error => HighlightGroup::DiagnosticError,
+            Self::Warning => HighlightGroup::DiagnosticWarn,
+            Self::Info => HighlightGroup::DiagnosticInfo,
+            Self::Hint => HighlightGroup::DiagnosticHint,
+        }
+    }
+}
+
+/// One problem reported in the buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub row: usize,
+    /// Char columns of the offending text on `row`, end exclusive.
+    pub col: usize,
+    pub end_col: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Parses the diagnostics for `path` out of a linter's output, sorted by
+/// position. A report without a column covers its whole line; one with a
+/// column covers the word there, or one character.
+pub fn parse(output: &str, path: &Path, lines: &[String]) -> Vec<Diagnostic> {
+    let re = Regex::new(
+        r"^(.+?):(\d+):(?:(\d+):)?\s*(?:(?:fatal )?(error|warning|warn|note|info|help|hint)(?:\[[^\]]*\])?:)?\s*(.*)$",
+    )
+    .expect("diagnostic pattern is valid");
+    let mut diagnostics: Vec<Diagnostic> = output
+        .lines()
+        .filter_map(|line| re.captures(line))
+        .filter(|caps| same_file(Path::new(&caps[1]), path))
+        .filter_map(|caps| {
+            let row = caps[2].parse::<usize>().ok()?.checked_sub(1)?;
+            let line = lines.get(row)?;
+            let (col, end_col) = match caps.get(3) {
+                Some(col) => word_at(line, col.as_str().parse::<usize>().ok()?.saturating_sub(1)),
+                None => (0, line.chars().count()),
+            };
+            Some(Diagnostic {
+                row,
+                col,
+                end_col,
+                severity: caps
+                    .get(4)
+                    .map_or(Severity::Error, |word| Severity::from_word(word.as_str())),
+                message: caps[5].trim().to_string(),
+            })
+        })
+        .collect();
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.row, diagnostic.col));
+    diagnostics
+}
+
+/// Whether a path in linter output names `path`: linters print paths
+/// relative to where they ran, or absolute.
+fn same_file(reported: &Path, path: &Path) -> bool {
+    reported.ends_with(path) || path.ends_with(reported)
+}
+
+/// The char range of the word starting at `col`, or of the one character
+/// there when it is not part of a word.
+fn word_at(line: &str, col: usize) -> (usize, usize) {
+    let len = line.chars().count();
+    let col = col.min(len.saturating_sub(1));
+    let word = line
+        .chars()
+        .skip(col)
+        .take_while(|ch| is_word_char(*ch))
+        .count();
+    (col, (col + word.max(1)).min(len))
+}
+
+/// The first diagnostic after `cursor`, wrapping around to the first one.
+pub fn next(diagnostics: &[Diagnostic], cursor: Cursor) -> Option<&Diagnostic> {
+    diagnostics
+        .iter()
+        .find(|diagnostic| (diagnostic.row, diagnostic.col) > (cursor.row, cursor.col))
+        .or_else(|| diagnostics.first())
+}
+
+/// The last diagnostic before `cursor`, wrapping around to the last one.
+pub fn prev(diagnostics: &[Diagnostic], cursor: Cursor) -> Option<&Diagnostic> {
+    diagnostics
+        .iter()
+        .rev()
+        .find(|diagnostic| (diagnostic.row, diagnostic.col) < (cursor.row, cursor.col))
+        .or_else(|| diagnostics.last())
+}
+
+/// The most serious diagnostic on `row`.
+pub fn worst_on_row(diagnostics: &[Diagnostic], row: usize) -> Option<Severity> {
+    diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.row == row)
+        .map(|diagnostic| diagnostic.severity)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_compiler_and_linter_output_for_the_file() {
+        let lines: Vec<String> = ["let x = 1;", "foo(bar)", "", "end"]
+            .map(String::from)
+            .to_vec();
+        let output = "\
+src/main.rs:2:5: warning: unused variable `bar`
+other.rs:1:1: error: not this file
+/home/me/src/main.rs:1:5: error[E0308]: mismatched types
+src/main.rs:4: trailing text
+src/main.rs:3:1: note: blank line
+make: *** [all] Error 1";
+        let diagnostics = parse(output, Path::new("src/main.rs"), &lines);
+        let found: Vec<(usize, usize, usize, Severity, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.row, d.col, d.end_col, d.severity, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, 4, 5, Severity::Error, "mismatched types"),
+                (1, 4, 7, Severity::Warning, "unused variable `bar`"),
+                (2, 0, 0, Severity::Info, "blank line"),
+                (3, 0, 3, Severity::Error, "trailing text"),
+            ]
+        );
+        assert_eq!(worst_on_row(&diagnostics, 1), Some(Severity::Warning));
+        assert_eq!(worst_on_row(&diagnostics, 5), None);
+
+        let cursor = |row, col| Cursor { row, col };
+        assert_eq!(next(&diagnostics, cursor(0, 4)).unwrap().row, 1);
+        assert_eq!(next(&diagnostics, cursor(3, 0)).unwrap().row, 0);
+        assert_eq!(prev(&diagnostics, cursor(1, 4)).unwrap().row, 0);
+        assert_eq!(prev(&diagnostics, cursor(0, 0)).unwrap().row, 3);
+    }
+}
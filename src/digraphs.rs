@@ -0,0 +1,289 @@
+//! RFC 1345 digraphs entered in Insert mode with `Ctrl-K {char1}{char2}`.
+
+/// A subset of the RFC 1345 mnemonics: Latin-1, common Latin Extended-A
+/// letters, Greek, arrows, math, punctuation and currency.
+pub const DIGRAPHS: &[(&str, char)] = &[
+    // Latin-1 punctuation and symbols.
+    ("NS", '\u{a0}'),
+    ("!I", '¡'),
+    ("Ct", '¢'),
+    ("Pd", '£'),
+    ("Cu", '¤'),
+    ("Ye", '¥'),
+    ("BB", '¦'),
+    ("SE", '§'),
+    ("':", '¨'),
+    ("Co", '©'),
+    ("-a", 'ª'),
+    ("<<", '«'),
+    ("NO", '¬'),
+    ("--", '\u{ad}'),
+    ("Rg", '®'),
+    ("'m", '¯'),
+    ("DG", '°'),
+    ("+-", '±'),
+    ("2S", '²'),
+    ("3S", '³'),
+    ("''", '´'),
+    ("My", 'µ'),
+    ("PI", '¶'),
+    (".M", '·'),
+    ("',", '¸'),
+    ("1S", '¹'),
+    ("-o", 'º'),
+    (">>", '»'),
+    ("14", '¼'),
+    ("12", '½'),
+    ("34", '¾'),
+    ("?I", '¿'),
+    ("*X", '×'),
+    ("-:", '÷'),
+    // Latin-1 letters.
+    ("A!", 'À'),
+    ("A'", 'Á'),
+    ("A>", 'Â'),
+    ("A?", 'Ã'),
+    ("A:", 'Ä'),
+    ("AA", 'Å'),
+    ("AE", 'Æ'),
+    ("C,", 'Ç'),
+    ("E!", 'È'),
+    ("E'", 'É'),
+    ("E>", 'Ê'),
+    ("E:", 'Ë'),
+    ("I!", 'Ì'),
+    ("I'", 'Í'),
+    ("I>", 'Î'),
+    ("I:", 'Ï'),
+    ("D-", 'Ð'),
+    ("N?", 'Ñ'),
+    ("O!", 'Ò'),
+    ("O'", 'Ó'),
+    ("O>", 'Ô'),
+    ("O?", 'Õ'),
+    ("O:", 'Ö'),
+    ("O/", 'Ø'),
+    ("U!", 'Ù'),
+    ("U'", 'Ú'),
+    ("U>", 'Û'),
+    ("U:", 'Ü'),
+    ("Y'", 'Ý'),
+    ("TH", 'Þ'),
+    ("ss", 'ß'),
+    ("a!", 'à'),
+    ("a'", 'á'),
+    ("a>", 'â'),
+    ("a?", 'ã'),
+    ("a:", 'ä'),
+    ("aa", 'å'),
+    ("ae", 'æ'),
+    ("c,", 'ç'),
+    ("e!", 'è'),
+    ("e'", 'é'),
+    ("e>", 'ê'),
+    ("e:", 'ë'),
+    ("i!", 'ì'),
+    ("i'", 'í'),
+    ("i>", 'î'),
+    ("i:", 'ï'),
+    ("d-", 'ð'),
+    ("n?", 'ñ'),
+    ("o!", 'ò'),
+    ("o'", 'ó'),
+    ("o>", 'ô'),
+    ("o?", 'õ'),
+    ("o:", 'ö'),
+    ("o/", 'ø'),
+    ("u!", 'ù'),
+    ("u'", 'ú'),
+    ("u>", 'û'),
+    ("u:", 'ü'),
+    ("y'", 'ý'),
+    ("th", 'þ'),
+    ("y:", 'ÿ'),
+    // Latin Extended-A.
+    ("C<", 'Č'),
+    ("c<", 'č'),
+    ("E<", 'Ě'),
+    ("e<", 'ě'),
+    ("G(", 'Ğ'),
+    ("g(", 'ğ'),
+    ("I.", 'İ'),
+    ("i.", 'ı'),
+    ("L/", 'Ł'),
+    ("l/", 'ł'),
+    ("N<", 'Ň'),
+    ("n<", 'ň'),
+    ("O\"", 'Ő'),
+    ("o\"", 'ő'),
+    ("OE", 'Œ'),
+    ("oe", 'œ'),
+    ("R<", 'Ř'),
+    ("r<", 'ř'),
+    ("S<", 'Š'),
+    ("s<", 'š'),
+    ("S,", 'Ş'),
+    ("s,", 'ş'),
+    ("U\"", 'Ű'),
+    ("u\"", 'ű'),
+    ("Z<", 'Ž'),
+    ("z<", 'ž'),
+    // Greek.
+    ("A*", 'Α'),
+    ("B*", 'Β'),
+    ("G*", 'Γ'),
+    ("D*", 'Δ'),
+    ("E*", 'Ε'),
+    ("Z*", 'Ζ'),
+    ("Y*", 'Η'),
+    ("H*", 'Θ'),
+    ("I*", 'Ι'),
+    ("K*", 'Κ'),
+    ("L*", 'Λ'),
+    ("M*", 'Μ'),
+    ("N*", 'Ν'),
+    ("C*", 'Ξ'),
+    ("O*", 'Ο'),
+    ("P*", 'Π'),
+    ("R*", 'Ρ'),
+    ("S*", 'Σ'),
+    ("T*", 'Τ'),
+    ("U*", 'Υ'),
+    ("F*", 'Φ'),
+    ("X*", 'Χ'),
+    ("Q*", 'Ψ'),
+    ("W*", 'Ω'),
+    ("a*", 'α'),
+    ("b*", 'β'),
+    ("g*", 'γ'),
+    ("d*", 'δ'),
+    ("e*", 'ε'),
+    ("z*", 'ζ'),
+    ("y*", 'η'),
+    ("h*", 'θ'),
+    ("i*", 'ι'),
+    ("k*", 'κ'),
+    ("l*", 'λ'),
+    ("m*", 'μ'),
+    ("n*", 'ν'),
+    ("c*", 'ξ'),
+    ("o*", 'ο'),
+    ("p*", 'π'),
+    ("r*", 'ρ'),
+    ("*s", 'ς'),
+    ("s*", 'σ'),
+    ("t*", 'τ'),
+    ("u*", 'υ'),
+    ("f*", 'φ'),
+    ("x*", 'χ'),
+    ("q*", 'ψ'),
+    ("w*", 'ω'),
+    // General punctuation.
+    ("-N", '–'),
+    ("-M", '—'),
+    ("'6", '‘'),
+    ("'9", '’'),
+    (".9", '‚'),
+    ("\"6", '“'),
+    ("\"9", '”'),
+    (":9", '„'),
+    ("/-", '†'),
+    ("/=", '‡'),
+    ("oo", '•'),
+    (",.", '…'),
+    ("%0", '‰'),
+    ("1'", '′'),
+    ("2'", '″'),
+    ("<1", '‹'),
+    (">1", '›'),
+    ("Eu", '€'),
+    ("=e", '€'),
+    ("oC", '℃'),
+    ("TM", '™'),
+    // Arrows.
+    ("<-", '←'),
+    ("-!", '↑'),
+    ("->", '→'),
+    ("-v", '↓'),
+    ("<>", '↔'),
+    ("UD", '↕'),
+    ("<=", '⇐'),
+    ("=>", '⇒'),
+    ("==", '⇔'),
+    // Mathematical operators.
+    ("FA", '∀'),
+    ("dP", '∂'),
+    ("TE", '∃'),
+    ("/0", '∅'),
+    ("DE", '∆'),
+    ("NB", '∇'),
+    ("(-", '∈'),
+    ("-)", '∋'),
+    ("*P", '∏'),
+    ("+Z", '∑'),
+    ("-2", '−'),
+    ("RT", '√'),
+    ("0(", '∝'),
+    ("00", '∞'),
+    ("AN", '∧'),
+    ("OR", '∨'),
+    ("(U", '∩'),
+    (")U", '∪'),
+    ("In", '∫'),
+    ("?1", '∼'),
+    ("?=", '≅'),
+    ("?2", '≈'),
+    ("!=", '≠'),
+    ("=3", '≡'),
+    ("=<", '≤'),
+    (">=", '≥'),
+    ("(C", '⊂'),
+    (")C", '⊃'),
+    ("(_", '⊆'),
+    (")_", '⊇'),
+    // Box drawing and miscellaneous symbols.
+    ("hh", '─'),
+    ("vv", '│'),
+    ("OK", '✓'),
+    ("XX", '✗'),
+    ("cH", '♥'),
+    ("*2", '★'),
+    ("*1", '☆'),
+];
+
+/// Looks up a digraph, also accepting the two characters in reverse order.
+pub fn lookup(first: char, second: char) -> Option<char> {
+    let find = |a: char, b: char| {
+        DIGRAPHS.iter().find_map(|(keys, ch)| {
+            let mut keys = keys.chars();
+            (keys.next() == Some(a) && keys.next() == Some(b)).then_some(*ch)
+        })
+    };
+    find(first, second).or_else(|| find(second, first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_finds_digraphs_in_either_order() {
+        assert_eq!(lookup('e', '\''), Some('é'));
+        assert_eq!(lookup('\'', 'e'), Some('é'));
+        assert_eq!(lookup('s', 's'), Some('ß'));
+        assert_eq!(lookup('-', '>'), Some('→'));
+        assert_eq!(lookup('q', 'q'), None);
+    }
+
+    #[test]
+    fn digraph_keys_are_unique() {
+        for (index, (keys, _)) in DIGRAPHS.iter().enumerate() {
+            assert_eq!(keys.chars().count(), 2, "{}", keys);
+            assert!(
+                DIGRAPHS[index + 1..].iter().all(|(other, _)| other != keys),
+                "duplicate digraph {}",
+                keys
+            );
+        }
+    }
+}
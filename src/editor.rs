@@ -1,11 +1,40 @@
 //! Core editor state and rendering types for minivim.
 
-use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::SystemTime;
 
-use crossterm::event::Event;
+use crossterm::event::{Event, KeyEvent};
+
+use crate::autocmd::{self, AutoEvent, Autocmds, Seen};
+use crate::clipboard;
+use crate::compress::Compression;
+use crate::crypt::Cipher;
+use crate::delta::{Delta, DeltaLog};
+use crate::diagnostics::{Diagnostic, SIGN_COLUMN_WIDTH};
+use crate::file_index::FileIndex;
+use crate::menu::Menu;
+use crate::palette::Palette;
+use crate::popup::Popup;
+use crate::quickfix::QuickfixList;
+use crate::registry::{CommandInfo, KeyInfo, Registry};
+use crate::spell::Dictionary;
+use crate::theme::UiTheme;
+use crate::undo::{UndoHistory, UndoSpan};
+use crate::virtual_text::VirtualTextSet;
+use crate::wakeup::Waker;
+use crate::{comment, expand, global_marks, indent, oldfiles, pattern, provider};
 use crossterm::style::ContentStyle;
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 
 /// Editor mode for key handling.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -13,10 +42,11 @@ pub enum Mode {
     Normal,
     Insert,
     Command,
+    Visual,
 }
 
 /// Cursor position in the buffer (0-based).
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Cursor {
     pub row: usize,
     pub col: usize,
@@ -26,9 +56,102 @@ pub struct Cursor {
 #[derive(Debug, Clone, Copy)]
 pub struct Viewport {
     pub row_offset: usize,
+    /// First visible display column (not char index).
     pub col_offset: usize,
 }
 
+/// Terminal columns taken by `ch`: 2 for wide CJK and emoji, 0 for combining
+/// marks. Control characters count as one column.
+pub fn char_width(ch: char) -> usize {
+    ch.width().unwrap_or(1)
+}
+
+/// Terminal columns taken by one grapheme cluster. Combining marks and
+/// zero-width joiners fold into their base, so a cluster is one or two
+/// columns wide.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.chars().map(char_width).sum::<usize>().clamp(1, 2)
+}
+
+/// Terminal columns taken by `text`.
+pub fn display_width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// The longest start of `text` that fits in `width` columns.
+pub fn truncate_to_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += grapheme_width(grapheme);
+        if used > width {
+            return &text[..index];
+        }
+    }
+    text
+}
+
+/// Columns taken by `grapheme` when it starts at display column `col`; a tab
+/// reaches the next multiple of `tabstop`.
+pub fn grapheme_columns(grapheme: &str, col: usize, tabstop: usize) -> usize {
+    if grapheme == "\t" {
+        tabstop - col % tabstop
+    } else {
+        grapheme_width(grapheme)
+    }
+}
+
+/// Display column at which char index `col` of `line` starts.
+pub fn display_col(line: &str, col: usize, tabstop: usize) -> usize {
+    let end = line
+        .char_indices()
+        .nth(col)
+        .map_or(line.len(), |(byte, _)| byte);
+    line[..end].graphemes(true).fold(0, |width, grapheme| {
+        width + grapheme_columns(grapheme, width, tabstop)
+    })
+}
+
+/// Char index of the grapheme covering display column `target`, or the line
+/// length when `target` is past the end.
+pub fn char_col_at(line: &str, target: usize, tabstop: usize) -> usize {
+    let mut width = 0;
+    let mut col = 0;
+    for grapheme in line.graphemes(true) {
+        width += grapheme_columns(grapheme, width, tabstop);
+        if width > target {
+            return col;
+        }
+        col += grapheme.chars().count();
+    }
+    col
+}
+
+/// Char index of the grapheme boundary before char index `col`.
+pub fn prev_grapheme_boundary(line: &str, col: usize) -> usize {
+    let mut prev = 0;
+    let mut start = 0;
+    for grapheme in line.graphemes(true) {
+        if start >= col {
+            break;
+        }
+        prev = start;
+        start += grapheme.chars().count();
+    }
+    prev
+}
+
+/// Char index of the grapheme boundary after char index `col`.
+pub fn next_grapheme_boundary(line: &str, col: usize) -> usize {
+    let mut end = 0;
+    for grapheme in line.graphemes(true) {
+        end += grapheme.chars().count();
+        if end > col {
+            return end;
+        }
+    }
+    end
+}
+
 /// In-memory text buffer stored as lines.
 #[derive(Debug, Clone)]
 pub struct Buffer {
@@ -50,8 +173,106 @@ impl Buffer {
         Self { lines }
     }
 
-    pub fn to_string(&self) -> String {
-        self.lines.join("\n")
+    /// `pos` moved onto the text: before the end of the last line at most,
+    /// and no further along its line than the line's end.
+    pub fn clamp_position(&self, pos: Cursor) -> Cursor {
+        let last = self.lines.len().saturating_sub(1);
+        let row = pos.row.min(last);
+        let len = self.lines.get(row).map_or(0, |line| line.chars().count());
+        let col = if pos.row > last {
+            len
+        } else {
+            pos.col.min(len)
+        };
+        Cursor { row, col }
+    }
+
+    /// The text from `start` up to (not including) `end`, with line breaks
+    /// between rows.
+    pub fn text_in_range(&self, start: Cursor, end: Cursor) -> String {
+        let mut text = String::new();
+        for row in start.row..=end.row.min(self.lines.len().saturating_sub(1)) {
+            let line = &self.lines[row];
+            let from = if row == start.row { start.col } else { 0 };
+            let to = if row == end.row { end.col } else { usize::MAX };
+            if row > start.row {
+                text.push('\n');
+            }
+            text.extend(line.chars().skip(from).take(to.saturating_sub(from)));
+        }
+        text
+    }
+
+    /// Deletes the text from `start` up to (not including) `end`, joining
+    /// their lines when they differ, and returns it.
+    pub fn delete_range(&mut self, start: Cursor, end: Cursor) -> String {
+        self.replace_range(start, end, "").old_text
+    }
+
+    /// Replaces the text from `start` up to (not including) `end` with
+    /// `text`, which may span lines, and returns the edit made. Both
+    /// positions are clamped to the buffer; an `end` before `start` inserts
+    /// at `start`.
+    pub fn replace_range(&mut self, start: Cursor, end: Cursor, text: &str) -> Delta {
+        if self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        let start = self.clamp_position(start);
+        let end = self.clamp_position(end).max(start);
+        let old_text = self.text_in_range(start, end);
+        let end_line = &self.lines[end.row];
+        let tail = end_line[char_to_byte_index(end_line, end.col)..].to_string();
+        let line = &mut self.lines[start.row];
+        line.truncate(char_to_byte_index(line, start.col));
+        let mut pieces = text.split('\n');
+        line.push_str(pieces.next().unwrap_or(""));
+        let mut added: Vec<String> = pieces.map(String::from).collect();
+        match added.last_mut() {
+            Some(last) => last.push_str(&tail),
+            None => line.push_str(&tail),
+        }
+        self.lines.splice(start.row + 1..=end.row, added);
+        Delta {
+            start,
+            end,
+            old_text,
+            new_text: text.to_string(),
+        }
+    }
+}
+
+/// Where `pos` ends up after the text from `start` to `end` was replaced by
+/// text ending at `new_end`: positions before the range stay, ones inside
+/// it move to its start and ones after it move with the text that follows.
+pub fn adjust_position(pos: Cursor, start: Cursor, end: Cursor, new_end: Cursor) -> Cursor {
+    if pos < start {
+        pos
+    } else if pos < end {
+        start
+    } else if pos.row == end.row {
+        Cursor {
+            row: new_end.row,
+            col: new_end.col + (pos.col - end.col),
+        }
+    } else {
+        Cursor {
+            row: pos.row - end.row + new_end.row,
+            col: pos.col,
+        }
+    }
+}
+
+/// The byte offset of the `char_index`th character of `line`, or its length
+/// when the line is shorter.
+fn char_to_byte_index(line: &str, char_index: usize) -> usize {
+    line.char_indices()
+        .nth(char_index)
+        .map_or(line.len(), |(idx, _)| idx)
+}
+
+impl fmt::Display for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.lines.join("\n"))
     }
 }
 
@@ -60,6 +281,10 @@ impl Buffer {
 pub struct CommandLine {
     pub active: bool,
     pub input: String,
+    /// The char index in `input` that typing inserts at.
+    pub cursor: usize,
+    /// `:` for ex commands, `/` or `?` for searches.
+    pub prompt: char,
 }
 
 impl CommandLine {
@@ -67,271 +292,2815 @@ impl CommandLine {
         Self {
             active: false,
             input: String::new(),
+            cursor: 0,
+            prompt: ':',
+        }
+    }
+
+    pub fn open(&mut self, prompt: char) {
+        self.active = true;
+        self.input.clear();
+        self.cursor = 0;
+        self.prompt = prompt;
+    }
+
+    pub fn close(&mut self) {
+        self.active = false;
+        self.input.clear();
+        self.cursor = 0;
+    }
+
+    /// Replaces the input, leaving the cursor at its end.
+    pub fn set_input(&mut self, input: String) {
+        self.cursor = input.chars().count();
+        self.input = input;
+    }
+
+    /// The byte offset of char index `col` in the input.
+    fn byte_index(&self, col: usize) -> usize {
+        self.input
+            .char_indices()
+            .nth(col)
+            .map_or(self.input.len(), |(index, _)| index)
+    }
+
+    /// The input before the cursor.
+    pub fn before_cursor(&self) -> &str {
+        &self.input[..self.byte_index(self.cursor)]
+    }
+
+    pub fn insert_str(&mut self, text: &str) {
+        let at = self.byte_index(self.cursor);
+        self.input.insert_str(at, text);
+        self.cursor += text.chars().count();
+    }
+
+    pub fn insert_char(&mut self, ch: char) {
+        let at = self.byte_index(self.cursor);
+        self.input.insert(at, ch);
+        self.cursor += 1;
+    }
+
+    /// Deletes the chars from char index `from` up to the cursor, leaving
+    /// the cursor at `from`.
+    fn delete_back_to(&mut self, from: usize) {
+        let range = self.byte_index(from)..self.byte_index(self.cursor);
+        self.input.replace_range(range, "");
+        self.cursor = from;
+    }
+
+    /// Deletes the char before the cursor.
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.delete_back_to(self.cursor - 1);
+        }
+    }
+
+    /// Deletes the char under the cursor.
+    pub fn delete(&mut self) {
+        if self.cursor < self.input.chars().count() {
+            self.cursor += 1;
+            self.backspace();
+        }
+    }
+
+    /// Deletes the word before the cursor and the spaces after it, as
+    /// Ctrl-W does: a run of word chars, or of other non-blank chars.
+    pub fn delete_word_before(&mut self) {
+        let chars: Vec<char> = self.input.chars().take(self.cursor).collect();
+        let mut from = chars.len();
+        while from > 0 && chars[from - 1].is_whitespace() {
+            from -= 1;
+        }
+        if let Some(last) = from.checked_sub(1).map(|last| chars[last]) {
+            let word = is_word_char(last);
+            while from > 0
+                && !chars[from - 1].is_whitespace()
+                && is_word_char(chars[from - 1]) == word
+            {
+                from -= 1;
+            }
+        }
+        self.delete_back_to(from);
+    }
+
+    /// Deletes everything before the cursor.
+    pub fn delete_to_start(&mut self) {
+        self.delete_back_to(0);
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.input.chars().count());
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.input.chars().count();
+    }
+    /// The prompt and input as shown in `width` columns: when the cursor
+    /// would be past the last column, leading chars are dropped until it
+    /// fits. Returns the shown text and the cursor's column in it.
+    pub fn view(&self, width: usize) -> (String, usize) {
+        let mut text = format!("{}{}", self.prompt, self.input);
+        let mut col = 1 + display_width(self.before_cursor());
+        while col >= width.max(1) && !text.is_empty() {
+            let first = text.remove(0);
+            col -= display_width(first.encode_utf8(&mut [0; 4]));
         }
+        (text, col)
     }
 }
 
-/// Shared editor state used by plugins.
-#[derive(Debug)]
-pub struct Editor {
-    pub buffer: Buffer,
-    pub cursor: Cursor,
-    pub viewport: Viewport,
-    pub mode: Mode,
-    pub command_line: CommandLine,
-    pub status: String,
-    pub file_path: Option<PathBuf>,
-    pub should_quit: bool,
-    pub dirty: bool,
-    pub revision: u64,
-    pub screen_width: u16,
-    pub screen_height: u16,
-    command_queue: Vec<String>,
+/// The last search, repeated by `n` and `N`.
+#[derive(Debug, Clone)]
+pub struct SearchState {
+    pub pattern: Option<String>,
+    pub forward: bool,
+    /// Whether matches of `pattern` are highlighted; `:noh` turns this off
+    /// until the next search.
+    pub highlight: bool,
+    /// The match `:s///c` is asking about, from start to end.
+    pub candidate: Option<(Cursor, Cursor)>,
 }
 
-impl Editor {
-    pub fn new(screen_width: u16, screen_height: u16, file_path: Option<PathBuf>) -> Self {
+impl SearchState {
+    pub fn new() -> Self {
         Self {
-            buffer: Buffer::new(),
-            cursor: Cursor { row: 0, col: 0 },
-            viewport: Viewport {
-                row_offset: 0,
-                col_offset: 0,
-            },
-            mode: Mode::Normal,
-            command_line: CommandLine::new(),
-            status: String::new(),
-            file_path,
-            should_quit: false,
-            dirty: false,
-            revision: 0,
-            screen_width,
-            screen_height,
-            command_queue: Vec::new(),
+            pattern: None,
+            forward: true,
+            highlight: false,
+            candidate: None,
         }
     }
+}
 
-    pub fn set_screen_size(&mut self, width: u16, height: u16) {
-        self.screen_width = width;
-        self.screen_height = height;
-        self.ensure_cursor_visible();
+/// User-configurable settings changed with `:set`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Program used by `K`; `None` picks one based on the file type.
+    pub keywordprg: Option<String>,
+    /// Display columns between tab stops.
+    pub tabstop: usize,
+    /// Insert spaces instead of tab characters.
+    pub expandtab: bool,
+    /// Columns a Tab press moves in Insert mode; 0 uses `tabstop`.
+    pub softtabstop: usize,
+    /// Columns per indent level for `>>` and `<<`; 0 uses `tabstop`.
+    pub shiftwidth: usize,
+    /// Copy the previous line's indent to new lines.
+    pub autoindent: bool,
+    /// Indent new lines after block openers and dedent block closers.
+    pub smartindent: bool,
+    /// Insert closing brackets and quotes along with the opening ones.
+    pub autopairs: bool,
+    /// Realign pipe tables as `|` and `Tab` are typed in their rows.
+    pub tablemode: bool,
+    /// Match search and substitute patterns regardless of case.
+    pub ignorecase: bool,
+    /// With `ignorecase`, match case after all when the pattern has an
+    /// uppercase letter.
+    pub smartcase: bool,
+    /// Let searches wrap around the end of the buffer to its start, or its
+    /// start to its end.
+    pub wrapscan: bool,
+    /// Show what `:s` would change in the buffer while it is typed.
+    pub inccommand: bool,
+    /// Strip trailing whitespace from every line before writing.
+    pub trim_trailing_whitespace: bool,
+    /// Whether the last line ends with a newline when written; set from the
+    /// file when it is read.
+    pub endofline: bool,
+    /// Write a newline after the last line even when the file had none.
+    pub fixendofline: bool,
+    /// Flush written files and their directory to disk before `:w` reports
+    /// success.
+    pub fsync: bool,
+    /// Refuse to write the buffer to its file without `:w!`; set for files
+    /// opened from a provider that can only read them.
+    pub readonly: bool,
+    /// `nofile` for a scratch buffer: it has no file, is never modified and
+    /// `:w` will not write it over one; empty for ordinary buffers.
+    pub buftype: String,
+    /// Who `.age` and `.gpg` files are encrypted to when written, separated
+    /// by commas; empty uses a passphrase for age and your own key for gpg.
+    pub cryptrecipients: String,
+    /// The identity file age decrypts `.age` files with; empty asks for the
+    /// passphrase.
+    pub cryptidentity: String,
+    /// Mark words the `dictionary` files do not list.
+    pub spell: bool,
+    /// Word list files, one word per line, separated by commas.
+    pub dictionary: String,
+    /// Files of related words, a group per line, separated by commas.
+    pub thesaurus: String,
+    /// Directories `gf` looks for files in, separated by commas: `.` is the
+    /// current file's directory and an empty entry the working directory.
+    pub path: String,
+    /// Change the working directory to that of each file opened.
+    pub autochdir: bool,
+    /// Keep a modified buffer in memory when another file is edited,
+    /// instead of refusing to leave it unsaved.
+    pub hidden: bool,
+    /// Write the modified buffer to its file before Ctrl-^, `:!` and
+    /// `:Lint` leave it or run programs on it.
+    pub autowrite: bool,
+    /// Like `autowrite`, and also before `:e` and `:q`.
+    pub autowriteall: bool,
+    /// Write the modified buffer to its file when the terminal loses focus.
+    pub autosave: bool,
+    /// Draw a guide at each indent level in leading whitespace.
+    pub indentguides: bool,
+    /// Extensions of files whose brackets are colored by depth, separated by
+    /// commas, or `*` for every file.
+    pub rainbow: String,
+    /// Milliseconds an unfinished key sequence waits before the keys that
+    /// can follow it are listed; 0 never lists them.
+    pub whichkey: usize,
+    /// Rows the preview window takes below the main view, its title
+    /// included; it never takes more than half of them.
+    pub previewheight: usize,
+}
+
+/// Value type of an option, which decides how `:set` parses and shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OptionKind {
+    Bool,
+    Number,
+    Text,
+}
+
+/// Full name, abbreviation and kind of every option.
+const OPTION_TABLE: &[(&str, &str, OptionKind)] = &[
+    ("keywordprg", "kp", OptionKind::Text),
+    ("tabstop", "ts", OptionKind::Number),
+    ("expandtab", "et", OptionKind::Bool),
+    ("softtabstop", "sts", OptionKind::Number),
+    ("shiftwidth", "sw", OptionKind::Number),
+    ("autoindent", "ai", OptionKind::Bool),
+    ("smartindent", "si", OptionKind::Bool),
+    ("autopairs", "ap", OptionKind::Bool),
+    ("tablemode", "tbm", OptionKind::Bool),
+    ("ignorecase", "ic", OptionKind::Bool),
+    ("smartcase", "scs", OptionKind::Bool),
+    ("wrapscan", "ws", OptionKind::Bool),
+    ("inccommand", "icm", OptionKind::Bool),
+    ("trim_trailing_whitespace", "ttw", OptionKind::Bool),
+    ("endofline", "eol", OptionKind::Bool),
+    ("fixendofline", "fixeol", OptionKind::Bool),
+    ("fsync", "fs", OptionKind::Bool),
+    ("readonly", "ro", OptionKind::Bool),
+    ("buftype", "bt", OptionKind::Text),
+    ("cryptrecipients", "crr", OptionKind::Text),
+    ("cryptidentity", "cri", OptionKind::Text),
+    ("spell", "spell", OptionKind::Bool),
+    ("dictionary", "dict", OptionKind::Text),
+    ("thesaurus", "tsr", OptionKind::Text),
+    ("path", "pa", OptionKind::Text),
+    ("autochdir", "acd", OptionKind::Bool),
+    ("hidden", "hid", OptionKind::Bool),
+    ("autowrite", "aw", OptionKind::Bool),
+    ("autowriteall", "awa", OptionKind::Bool),
+    ("autosave", "as", OptionKind::Bool),
+    ("indentguides", "ig", OptionKind::Bool),
+    ("rainbow", "rb", OptionKind::Text),
+    ("whichkey", "wk", OptionKind::Number),
+    ("previewheight", "pvh", OptionKind::Number),
+];
+
+impl Options {
+    pub fn new() -> Self {
+        Self {
+            keywordprg: None,
+            tabstop: 8,
+            expandtab: true,
+            softtabstop: 4,
+            shiftwidth: 4,
+            autoindent: true,
+            smartindent: true,
+            autopairs: true,
+            tablemode: false,
+            ignorecase: false,
+            smartcase: false,
+            wrapscan: true,
+            inccommand: true,
+            trim_trailing_whitespace: false,
+            endofline: true,
+            fixendofline: true,
+            fsync: false,
+            readonly: false,
+            buftype: String::new(),
+            cryptrecipients: String::new(),
+            cryptidentity: String::new(),
+            spell: false,
+            dictionary: "/usr/share/dict/words".to_string(),
+            thesaurus: String::new(),
+            path: ".,,".to_string(),
+            autochdir: false,
+            hidden: false,
+            autowrite: false,
+            autowriteall: false,
+            autosave: false,
+            indentguides: false,
+            rainbow: String::new(),
+            whichkey: 500,
+            previewheight: 12,
+        }
     }
 
-    pub fn content_height(&self) -> u16 {
-        let gutter = if self.command_line.active { 2 } else { 1 };
-        self.screen_height.saturating_sub(gutter)
+    fn lookup(name: &str) -> Result<(&'static str, OptionKind), String> {
+        OPTION_TABLE
+            .iter()
+            .find(|(full, short, _)| name == *full || name == *short)
+            .map(|(full, _, kind)| (*full, *kind))
+            .ok_or_else(|| format!("Unknown option: {}", name))
     }
 
-    pub fn status_row(&self) -> u16 {
-        if self.command_line.active {
-            self.screen_height.saturating_sub(2)
-        } else {
-            self.screen_height.saturating_sub(1)
+    /// Applies a single `:set` argument such as `name=value`, `name?`, `name`,
+    /// `noname`, or `invname`. Returns a message to show when the argument
+    /// queries a value.
+    pub fn apply(&mut self, arg: &str) -> Result<Option<String>, String> {
+        if let Some(name) = arg.strip_suffix('?') {
+            return self.show(name).map(Some);
+        }
+        if let Some((name, value)) = arg.split_once('=') {
+            let (full, kind) = Self::lookup(name)?;
+            if kind == OptionKind::Bool {
+                return Err(format!("Invalid argument: {}", arg));
+            }
+            self.set_value(full, value)
+                .map_err(|err| format!("{}: {}", err, arg))?;
+            return Ok(None);
         }
+        if let Ok((full, OptionKind::Bool)) = Self::lookup(arg) {
+            self.set_flag(full, true);
+            return Ok(None);
+        }
+        if let Some(name) = arg.strip_prefix("no")
+            && let Ok((full, OptionKind::Bool)) = Self::lookup(name)
+        {
+            self.set_flag(full, false);
+            return Ok(None);
+        }
+        if let Some(name) = arg.strip_prefix("inv")
+            && let Ok((full, OptionKind::Bool)) = Self::lookup(name)
+        {
+            let current = self.flag(full);
+            self.set_flag(full, !current);
+            return Ok(None);
+        }
+        self.show(arg).map(Some)
     }
 
-    pub fn command_row(&self) -> u16 {
-        self.screen_height.saturating_sub(1)
+    /// Formats an option the way `:set name?` shows it.
+    pub fn show(&self, name: &str) -> Result<String, String> {
+        let (full, kind) = Self::lookup(name)?;
+        Ok(match kind {
+            OptionKind::Bool if self.flag(full) => name.to_string(),
+            OptionKind::Bool => format!("no{}", name),
+            _ => format!("{}={}", name, self.value(full)),
+        })
     }
 
-    pub fn set_status(&mut self, message: impl Into<String>) {
-        self.status = message.into();
+    fn set_value(&mut self, full: &str, value: &str) -> Result<(), String> {
+        let number = || -> Result<usize, String> {
+            value
+                .parse::<usize>()
+                .map_err(|_| "Number required after =".to_string())
+        };
+        match full {
+            "keywordprg" => self.keywordprg = (!value.is_empty()).then(|| value.to_string()),
+            "tabstop" => match number()? {
+                0 => return Err("Argument must be positive".to_string()),
+                tabstop => self.tabstop = tabstop,
+            },
+            "softtabstop" => self.softtabstop = number()?,
+            "shiftwidth" => self.shiftwidth = number()?,
+            "buftype" => match value {
+                "" | "nofile" => self.buftype = value.to_string(),
+                _ => return Err("Invalid argument".to_string()),
+            },
+            "cryptrecipients" => self.cryptrecipients = value.to_string(),
+            "cryptidentity" => self.cryptidentity = value.to_string(),
+            "dictionary" => self.dictionary = value.to_string(),
+            "thesaurus" => self.thesaurus = value.to_string(),
+            "path" => self.path = value.to_string(),
+            "rainbow" => self.rainbow = value.to_string(),
+            "whichkey" => self.whichkey = number()?,
+            "previewheight" => self.previewheight = number()?,
+            _ => unreachable!("option table entry without a setter: {}", full),
+        }
+        Ok(())
     }
 
-    pub fn push_command(&mut self, command: String) {
-        self.command_queue.push(command);
+    fn value(&self, full: &str) -> String {
+        match full {
+            "keywordprg" => self.keywordprg.clone().unwrap_or_default(),
+            "tabstop" => self.tabstop.to_string(),
+            "softtabstop" => self.softtabstop.to_string(),
+            "shiftwidth" => self.shiftwidth.to_string(),
+            "buftype" => self.buftype.clone(),
+            "cryptrecipients" => self.cryptrecipients.clone(),
+            "cryptidentity" => self.cryptidentity.clone(),
+            "dictionary" => self.dictionary.clone(),
+            "thesaurus" => self.thesaurus.clone(),
+            "path" => self.path.clone(),
+            "rainbow" => self.rainbow.clone(),
+            "whichkey" => self.whichkey.to_string(),
+            "previewheight" => self.previewheight.to_string(),
+            _ => unreachable!("option table entry without a getter: {}", full),
+        }
     }
 
-    pub fn take_commands(&mut self) -> Vec<String> {
-        std::mem::take(&mut self.command_queue)
+    fn flag(&self, full: &str) -> bool {
+        match full {
+            "expandtab" => self.expandtab,
+            "autoindent" => self.autoindent,
+            "smartindent" => self.smartindent,
+            "autopairs" => self.autopairs,
+            "tablemode" => self.tablemode,
+            "ignorecase" => self.ignorecase,
+            "smartcase" => self.smartcase,
+            "wrapscan" => self.wrapscan,
+            "inccommand" => self.inccommand,
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace,
+            "endofline" => self.endofline,
+            "fixendofline" => self.fixendofline,
+            "fsync" => self.fsync,
+            "readonly" => self.readonly,
+            "spell" => self.spell,
+            "autochdir" => self.autochdir,
+            "hidden" => self.hidden,
+            "autowrite" => self.autowrite,
+            "autowriteall" => self.autowriteall,
+            "autosave" => self.autosave,
+            "indentguides" => self.indentguides,
+            _ => unreachable!("option table entry without a flag: {}", full),
+        }
     }
 
-    pub fn load_from_path(&mut self, path: &PathBuf) -> io::Result<()> {
-        let contents = fs::read_to_string(path)?;
-        self.buffer = Buffer::from_string(contents);
-        self.cursor = Cursor { row: 0, col: 0 };
-        self.viewport = Viewport {
-            row_offset: 0,
-            col_offset: 0,
-        };
-        self.dirty = false;
-        self.revision = 0;
-        Ok(())
+    fn set_flag(&mut self, full: &str, on: bool) {
+        match full {
+            "expandtab" => self.expandtab = on,
+            "autoindent" => self.autoindent = on,
+            "smartindent" => self.smartindent = on,
+            "autopairs" => self.autopairs = on,
+            "tablemode" => self.tablemode = on,
+            "ignorecase" => self.ignorecase = on,
+            "smartcase" => self.smartcase = on,
+            "wrapscan" => self.wrapscan = on,
+            "inccommand" => self.inccommand = on,
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = on,
+            "endofline" => self.endofline = on,
+            "fixendofline" => self.fixendofline = on,
+            "fsync" => self.fsync = on,
+            "readonly" => self.readonly = on,
+            "spell" => self.spell = on,
+            "autochdir" => self.autochdir = on,
+            "hidden" => self.hidden = on,
+            "autowrite" => self.autowrite = on,
+            "autowriteall" => self.autowriteall = on,
+            "autosave" => self.autosave = on,
+            "indentguides" => self.indentguides = on,
+            _ => unreachable!("option table entry without a flag: {}", full),
+        }
     }
 
-    pub fn save_to_path(&mut self, path: &PathBuf) -> io::Result<()> {
-        fs::write(path, self.buffer.to_string())?;
-        self.dirty = false;
-        Ok(())
+    /// Whether `rainbow` lists `extension`.
+    pub fn rainbow_for(&self, extension: &str) -> bool {
+        self.rainbow
+            .split(',')
+            .any(|entry| entry == "*" || (!entry.is_empty() && entry == extension))
     }
 
-    pub fn current_line_len(&self) -> usize {
-        self.buffer
-            .lines
-            .get(self.cursor.row)
-            .map(|line| line.chars().count())
-            .unwrap_or(0)
+    /// Effective indent width, with 0 meaning `tabstop`.
+    pub fn shiftwidth(&self) -> usize {
+        if self.shiftwidth == 0 {
+            self.tabstop
+        } else {
+            self.shiftwidth
+        }
     }
 
-    pub fn clamp_cursor(&mut self) {
-        if self.cursor.row >= self.buffer.lines.len() {
-            self.cursor.row = self.buffer.lines.len().saturating_sub(1);
-            self.cursor.col = 0;
+    /// Whitespace spanning display columns `from` to `to`, using tabs where
+    /// they fit unless `expandtab` is set.
+    pub fn fill_whitespace(&self, from: usize, to: usize) -> String {
+        let mut fill = String::new();
+        let mut col = from;
+        if !self.expandtab {
+            loop {
+                let next_stop = (col / self.tabstop + 1) * self.tabstop;
+                if next_stop > to {
+                    break;
+                }
+                fill.push('\t');
+                col = next_stop;
+            }
         }
-        let line_len = self.current_line_len();
-        if self.cursor.col > line_len {
-            self.cursor.col = line_len;
+        fill.extend(std::iter::repeat_n(' ', to.saturating_sub(col)));
+        fill
+    }
+
+    /// `:set` commands that recreate every option changed from its default.
+    pub fn to_commands(&self) -> Vec<String> {
+        let defaults = Options::new();
+        OPTION_TABLE
+            .iter()
+            .filter_map(|(full, _, kind)| {
+                let command = match kind {
+                    OptionKind::Bool if self.flag(full) == defaults.flag(full) => return None,
+                    OptionKind::Bool if self.flag(full) => format!("set {}", full),
+                    OptionKind::Bool => format!("set no{}", full),
+                    _ if self.value(full) == defaults.value(full) => return None,
+                    _ => format!("set {}={}", full, escape_set_value(&self.value(full))),
+                };
+                Some(command)
+            })
+            .collect()
+    }
+}
+
+/// Escapes backslashes and spaces so a value survives `split_set_args`.
+fn escape_set_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(' ', "\\ ")
+}
+
+/// Splits `:set` arguments on whitespace, honoring backslash escapes.
+pub fn split_set_args(args: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut chars = args.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => current.extend(chars.next()),
+            ch if ch.is_whitespace() => {
+                if !current.is_empty() {
+                    parts.push(std::mem::take(&mut current));
+                }
+            }
+            ch => current.push(ch),
         }
     }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
 
-    pub fn ensure_cursor_visible(&mut self) {
-        let content_height = self.content_height() as usize;
-        if content_height == 0 {
-            self.viewport.row_offset = self.cursor.row;
-        } else if self.cursor.row < self.viewport.row_offset {
-            self.viewport.row_offset = self.cursor.row;
-        } else if self.cursor.row >= self.viewport.row_offset + content_height {
-            self.viewport.row_offset = self.cursor.row.saturating_sub(content_height - 1);
+/// Splits a script into ex commands, skipping blank lines and `"` comments.
+pub fn parse_script(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('"'))
+        .map(|line| line.strip_prefix(':').unwrap_or(line).to_string())
+        .collect()
+}
+
+/// Read-only text shown over the buffer area, such as command output.
+#[derive(Debug, Clone)]
+pub struct ScratchView {
+    pub title: String,
+    pub lines: Vec<String>,
+    pub scroll: usize,
+}
+
+/// A file shown below the main view around one of its lines, without
+/// leaving the buffer being edited.
+#[derive(Debug, Clone)]
+pub struct PreviewWindow {
+    pub path: PathBuf,
+    pub lines: Vec<String>,
+    /// The line previewed, highlighted and scrolled into view.
+    pub row: usize,
+}
+
+/// What to do once a `TerminalJob` has exited, given how it exited and
+/// what it printed to a piped standard output.
+pub type JobDone = Box<dyn FnOnce(&mut Editor, io::Result<Output>)>;
+
+/// A program that needs the terminal to itself, such as `sudo` asking for a
+/// password. The main loop puts the editor's screen away while it runs.
+pub struct TerminalJob {
+    pub command: Command,
+    /// Written to the program's standard input.
+    pub input: Vec<u8>,
+    pub done: JobDone,
+}
+
+impl TerminalJob {
+    /// Runs the program to completion, feeding it `input`, and hands how it
+    /// exited to `done`.
+    pub fn run(mut self, editor: &mut Editor) {
+        let input = self.input;
+        let result = self
+            .command
+            .stdin(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                let stdin = child.stdin.take();
+                thread::scope(|scope| {
+                    if let Some(mut stdin) = stdin {
+                        // Written alongside reading the output, which the
+                        // program may produce before it has read it all. A
+                        // program that exits without reading it, as sudo
+                        // does when refused, closes the pipe; its status
+                        // says why.
+                        scope.spawn(move || {
+                            let _ = stdin.write_all(&input);
+                        });
+                    }
+                    child.wait_with_output()
+                })
+            });
+        (self.done)(editor, result);
+    }
+}
+
+impl fmt::Debug for TerminalJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TerminalJob")
+            .field("command", &self.command)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Files at least this large are read on a background thread.
+pub const ASYNC_LOAD_THRESHOLD: u64 = 256 * 1024;
+
+/// Lines read per message by the background loader.
+const LOAD_CHUNK_LINES: usize = 4096;
+
+/// Progress reported by a background file load.
+#[derive(Debug)]
+pub enum LoadMessage {
+    Lines {
+        lines: Vec<String>,
+        bytes: u64,
+    },
+    /// The whole file was read; `eol` tells whether it ended with a newline.
+    Done {
+        eol: bool,
+    },
+    Failed(io::Error),
+}
+
+/// A file load running on a background thread; lines are appended as they arrive.
+#[derive(Debug)]
+pub struct LoadState {
+    pub path: PathBuf,
+    pub total_bytes: u64,
+    pub loaded_bytes: u64,
+    receiver: Receiver<LoadMessage>,
+    received_lines: bool,
+}
+
+/// Maximum number of locations kept in the jumplist.
+pub const JUMPLIST_LIMIT: usize = 100;
+
+/// A modified buffer kept in memory, with the `hidden` option set, while
+/// another file is edited in its place.
+#[derive(Debug, Clone)]
+pub struct HiddenBuffer {
+    pub path: PathBuf,
+    buffer: Buffer,
+    cursor: Cursor,
+    changes: ChangeList,
+    undo_history: UndoHistory,
+    endofline: bool,
+    readonly: bool,
+}
+
+/// A file and position: a location recorded before a jump, revisited with
+/// Ctrl-O and Ctrl-I, or the target of an uppercase mark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jump {
+    pub path: Option<PathBuf>,
+    pub cursor: Cursor,
+}
+
+/// Jump locations, oldest first. `index` equals `entries.len()` unless Ctrl-O
+/// has moved back through the list.
+#[derive(Debug, Clone, Default)]
+pub struct JumpList {
+    pub entries: Vec<Jump>,
+    pub index: usize,
+}
+
+impl JumpList {
+    /// Records `jump` as the newest entry, replacing any older entry on the same line.
+    pub fn push(&mut self, jump: Jump) {
+        self.entries
+            .retain(|entry| entry.path != jump.path || entry.cursor.row != jump.cursor.row);
+        self.entries.push(jump);
+        if self.entries.len() > JUMPLIST_LIMIT {
+            let excess = self.entries.len() - JUMPLIST_LIMIT;
+            self.entries.drain(..excess);
         }
+        self.index = self.entries.len();
+    }
 
-        let content_width = self.screen_width as usize;
-        if content_width == 0 {
-            self.viewport.col_offset = self.cursor.col;
-        } else if self.cursor.col < self.viewport.col_offset {
-            self.viewport.col_offset = self.cursor.col;
-        } else if self.cursor.col >= self.viewport.col_offset + content_width {
-            self.viewport.col_offset = self.cursor.col.saturating_sub(content_width - 1);
+    /// Steps back from `current`, remembering it first when starting at the newest
+    /// end so Ctrl-I can return to it.
+    pub fn back(&mut self, current: Jump) -> Option<Jump> {
+        if self.index == 0 {
+            return None;
         }
+        if self.index >= self.entries.len() {
+            self.push(current);
+            self.index = self.entries.len() - 1;
+        }
+        self.index -= 1;
+        self.entries.get(self.index).cloned()
     }
 
-    pub fn move_left(&mut self) {
-        if self.cursor.col > 0 {
-            self.cursor.col -= 1;
+    pub fn forward(&mut self) -> Option<Jump> {
+        if self.index + 1 >= self.entries.len() {
+            return None;
         }
-        self.ensure_cursor_visible();
+        self.index += 1;
+        self.entries.get(self.index).cloned()
     }
+}
 
-    pub fn move_right(&mut self) {
-        let line_len = self.current_line_len();
-        if self.cursor.col < line_len {
-            self.cursor.col += 1;
+/// Maximum number of edit locations kept in the changelist.
+pub const CHANGELIST_LIMIT: usize = 100;
+
+/// Locations of recent edits, oldest first, visited with `g;` and `g,`.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeList {
+    pub entries: Vec<Cursor>,
+    pub index: usize,
+}
+
+impl ChangeList {
+    /// Records an edit at `cursor`. Consecutive edits on one line share an entry.
+    pub fn record(&mut self, cursor: Cursor) {
+        match self.entries.last_mut() {
+            Some(last) if last.row == cursor.row => *last = cursor,
+            _ => {
+                self.entries.push(cursor);
+                if self.entries.len() > CHANGELIST_LIMIT {
+                    let excess = self.entries.len() - CHANGELIST_LIMIT;
+                    self.entries.drain(..excess);
+                }
+            }
         }
-        self.ensure_cursor_visible();
+        self.index = self.entries.len();
     }
 
-    pub fn move_up(&mut self) {
-        if self.cursor.row > 0 {
-            self.cursor.row -= 1;
-            self.clamp_cursor();
+    /// Steps to the next older change.
+    pub fn older(&mut self) -> Option<Cursor> {
+        let index = self.index.checked_sub(1)?;
+        self.index = index;
+        self.entries.get(index).copied()
+    }
+
+    /// Steps to the next newer change.
+    pub fn newer(&mut self) -> Option<Cursor> {
+        if self.index + 1 >= self.entries.len() {
+            return None;
+        }
+        self.index += 1;
+        self.entries.get(self.index).copied()
+    }
+}
+
+/// Maximum number of entries kept in each prompt history.
+pub const HISTORY_LIMIT: usize = 200;
+
+/// Previously entered prompt lines, oldest first, plus Up/Down recall state.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    pub entries: Vec<String>,
+    position: Option<usize>,
+    prefix: String,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an entry as the newest, dropping older duplicates.
+    pub fn push(&mut self, entry: &str) {
+        self.reset();
+        if entry.is_empty() {
+            return;
+        }
+        self.entries.retain(|existing| existing != entry);
+        self.entries.push(entry.to_string());
+        if self.entries.len() > HISTORY_LIMIT {
+            let excess = self.entries.len() - HISTORY_LIMIT;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Steps to the next older entry starting with the text typed before recall began.
+    pub fn previous(&mut self, current: &str) -> Option<String> {
+        let start = match self.position {
+            Some(position) => position,
+            None => {
+                self.prefix = current.to_string();
+                self.entries.len()
+            }
+        };
+        let found = self.entries[..start]
+            .iter()
+            .rposition(|entry| entry.starts_with(&self.prefix))?;
+        self.position = Some(found);
+        Some(self.entries[found].clone())
+    }
+
+    /// Steps to the next newer matching entry, returning the original typed text
+    /// once recall moves past the newest entry.
+    pub fn next(&mut self) -> Option<String> {
+        let position = self.position?;
+        let found = self.entries[position + 1..]
+            .iter()
+            .position(|entry| entry.starts_with(&self.prefix));
+        match found {
+            Some(offset) => {
+                self.position = Some(position + 1 + offset);
+                Some(self.entries[position + 1 + offset].clone())
+            }
+            None => {
+                self.position = None;
+                Some(std::mem::take(&mut self.prefix))
+            }
+        }
+    }
+
+    /// Ends recall so the next `previous` filters on freshly typed text.
+    pub fn reset(&mut self) {
+        self.position = None;
+        self.prefix.clear();
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut history = Self::new();
+        for line in contents.lines() {
+            history.push(line);
+        }
+        Ok(history)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut contents = self.entries.join("\n");
+        contents.push('\n');
+        write_private(path, &contents)
+    }
+}
+
+/// The directory, in the state directory, that unsaved buffers are written
+/// to when the editor is killed or loses its terminal.
+pub const RECOVERY_DIR: &str = "recovery";
+
+/// The name the emergency copy of the buffer of `path` is saved under: its
+/// absolute path with `%` for each separator, as Vim names swap files in a
+/// `directory` ending in `//`. An unnamed buffer is saved as `untitled`.
+fn recovery_name(path: Option<&Path>) -> String {
+    let Some(path) = path else {
+        return "untitled".to_string();
+    };
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    path.to_string_lossy()
+        .replace(std::path::MAIN_SEPARATOR, "%")
+}
+
+/// Creates `dir` and its parents, with `dir` only open to the user.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+    // The directory may be left from an earlier run.
+    #[cfg(unix)]
+    fs::set_permissions(dir, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+    Ok(())
+}
+
+/// Writes `contents` to `path`, a file only the user may read, before any
+/// of the text reaches it.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // An existing copy keeps its mode when opened.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+/// Directory for state kept between sessions, such as prompt history:
+/// `$MINIVIM_STATE_DIR`, else `$XDG_STATE_HOME/minivim`, else `~/.local/state/minivim`.
+pub fn state_dir() -> Option<PathBuf> {
+    let from_env = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(dir) = from_env("MINIVIM_STATE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = from_env("XDG_STATE_HOME") {
+        return Some(PathBuf::from(dir).join("minivim"));
+    }
+    from_env("HOME").map(|home| PathBuf::from(home).join(".local/state/minivim"))
+}
+
+/// Whether `a` and `b` name the same file, written differently or not.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+        || matches!((std::path::absolute(a), std::path::absolute(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// When the local file at `path` was last modified; `None` if missing or remote.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    if provider::is_remote(path) {
+        return None;
+    }
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// The text of the file at `path`, read through its provider and
+/// decompressed if its extension says it is compressed.
+pub fn read_file(path: &Path) -> io::Result<String> {
+    let mut bytes = provider::provider_for(path).read(path)?;
+    if let Some(compression) = Compression::for_path(path) {
+        bytes = compression.decompress(&bytes)?;
+    }
+    String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Whether `ch` belongs to a keyword, for word motions, abbreviations and
+/// completion.
+pub fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// User config file of ex commands run at startup: `$MINIVIM_CONFIG`, else
+/// `$XDG_CONFIG_HOME/minivim/minivimrc`, else `~/.config/minivim/minivimrc`.
+pub fn config_path() -> Option<PathBuf> {
+    let from_env = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(path) = from_env("MINIVIM_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = from_env("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| from_env("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("minivim").join("minivimrc"))
+}
+
+/// How register text is put: within a line, or as whole lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterKind {
+    Charwise,
+    Linewise,
+}
+
+/// The text of a register and how it was yanked. Linewise text (from `yy`
+/// or `dd`) ends with a newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn new(text: String, kind: RegisterKind) -> Self {
+        Self { text, kind }
+    }
+
+    /// Text from outside the editor, such as the clipboard: linewise if it
+    /// ends with a newline.
+    pub fn guess(text: String) -> Self {
+        let kind = if text.ends_with('\n') {
+            RegisterKind::Linewise
+        } else {
+            RegisterKind::Charwise
+        };
+        Self { text, kind }
+    }
+
+    pub fn is_linewise(&self) -> bool {
+        self.kind == RegisterKind::Linewise
+    }
+
+    /// This register with `other` appended, as `"A` does. Appending to or
+    /// from linewise text gives linewise text, with the charwise part on a
+    /// line of its own.
+    fn append(&self, other: Register) -> Register {
+        let mut text = self.text.clone();
+        if other.is_linewise() && !self.is_linewise() {
+            text.push('\n');
+        }
+        text.push_str(&other.text);
+        if self.is_linewise() && !other.is_linewise() {
+            text.push('\n');
+        }
+        let kind = if self.is_linewise() || other.is_linewise() {
+            RegisterKind::Linewise
+        } else {
+            RegisterKind::Charwise
+        };
+        Register { text, kind }
+    }
+}
+
+/// Text saved by yanks and deletes, by register name.
+#[derive(Debug, Clone, Default)]
+pub struct Registers {
+    contents: BTreeMap<char, Register>,
+}
+
+impl Registers {
+    /// Whether `name` is a register: `"` (unnamed), `0`-`9`, `a`-`z`, `A`-`Z`
+    /// (appending to `a`-`z`) or the clipboard registers `+` and `*`.
+    pub fn is_valid(name: char) -> bool {
+        name == '"'
+            || name.is_ascii_digit()
+            || name.is_ascii_alphabetic()
+            || clipboard::is_clipboard(name)
+    }
+
+    /// The register `name`; `A`-`Z` name `a`-`z`.
+    pub fn register(&self, name: char) -> Option<&Register> {
+        self.contents.get(&name.to_ascii_lowercase())
+    }
+
+    /// Registers that hold text, in name order.
+    pub fn iter(&self) -> impl Iterator<Item = (char, &Register)> {
+        self.contents
+            .iter()
+            .map(|(name, register)| (*name, register))
+    }
+
+    /// Saves yanked or deleted text in the unnamed register and in `name`
+    /// (appending to `a`-`z` for `A`-`Z`), or without a name in `0` for
+    /// yanks and `1` for deletes, shifting older deletes through `2`-`9`.
+    pub fn record(&mut self, name: Option<char>, register: Register, yank: bool) {
+        let register = match name {
+            Some(name) if name.is_ascii_uppercase() => {
+                let name = name.to_ascii_lowercase();
+                let appended = match self.contents.get(&name) {
+                    Some(old) => old.append(register),
+                    None => register,
+                };
+                self.contents.insert(name, appended.clone());
+                appended
+            }
+            Some(name) if name != '"' => {
+                self.contents.insert(name, register.clone());
+                register
+            }
+            _ if yank => {
+                self.contents.insert('0', register.clone());
+                register
+            }
+            _ => {
+                for digit in (1..9).rev() {
+                    let from = char::from(b'0' + digit);
+                    if let Some(older) = self.contents.remove(&from) {
+                        self.contents.insert(char::from(b'1' + digit), older);
+                    }
+                }
+                self.contents.insert('1', register.clone());
+                register
+            }
+        };
+        self.contents.insert('"', register);
+    }
+}
+
+/// Shared editor state used by plugins.
+#[derive(Debug)]
+pub struct Editor {
+    pub buffer: Buffer,
+    pub cursor: Cursor,
+    pub viewport: Viewport,
+    pub mode: Mode,
+    pub command_line: CommandLine,
+    pub command_history: History,
+    pub search_history: History,
+    pub search: SearchState,
+    pub jumps: JumpList,
+    pub changes: ChangeList,
+    /// Keys typed so far in an unfinished Normal-mode sequence, such as `g`.
+    pub pending_keys: String,
+    /// Where the Visual-mode selection started; the cursor is the other end.
+    pub visual_anchor: Option<Cursor>,
+    pub registers: Registers,
+    /// Positions saved with `m{a-z}`, plus `<` and `>` for the start and end
+    /// of the last Visual selection.
+    pub marks: BTreeMap<char, Cursor>,
+    pub quickfix: QuickfixList,
+    /// Files under the working directory, searched by `:grep` and
+    /// `:FindReplace` when given no paths.
+    pub file_index: FileIndex,
+    /// The working directory before the last `:cd`, for `:cd -`.
+    pub previous_dir: Option<PathBuf>,
+    /// Where `:oldfiles` and uppercase marks are kept between sessions.
+    /// Unset in headless runs, which leave no trace.
+    pub state_dir: Option<PathBuf>,
+    /// Marks `A`-`Z`, which remember a file as well as a position.
+    pub global_marks: BTreeMap<char, Jump>,
+    /// Register picked with `"x` for the next yank, delete or put.
+    pub pending_register: Option<char>,
+    pub status: String,
+    /// Set by `set_error` and left for the reader to clear, so a headless
+    /// run can tell a failed command from one that only reported.
+    pub error: bool,
+    pub file_path: Option<PathBuf>,
+    /// The file edited before the current one, `#` in file names.
+    pub alternate_file: Option<PathBuf>,
+    /// Where the cursor was when the alternate file was left.
+    pub alternate_cursor: Cursor,
+    /// When the open file was last read or written, to tell whether it has
+    /// been changed on disk since.
+    pub file_time: Option<SystemTime>,
+    /// Modified buffers left for other files with `hidden` set, none of
+    /// them the open file's.
+    pub hidden_buffers: Vec<HiddenBuffer>,
+    pub should_quit: bool,
+    /// Set by `:suspend` and Ctrl-Z for the main loop to stop the editor,
+    /// with the terminal given back, until the shell resumes it. Headless
+    /// runs ignore it.
+    pub suspend_requested: bool,
+    pub dirty: bool,
+    /// The edits made to the buffer, for plugins that follow its changes.
+    pub deltas: DeltaLog,
+    pub screen_width: u16,
+    pub screen_height: u16,
+    pub options: Options,
+    /// Styles of the status bar, selection and other UI elements.
+    pub theme: UiTheme,
+    /// Insert-mode abbreviations from `:iabbrev`, keyed by the typed word.
+    pub abbreviations: BTreeMap<String, String>,
+    pub scratch: Option<ScratchView>,
+    /// The preview window, opened by `:pedit`, `:cpreview` and pickers.
+    pub preview_window: Option<PreviewWindow>,
+    /// A popup shown over the window, next to the cursor.
+    pub popup: Option<Popup>,
+    /// The delimiter `:CsvAlign` lines the buffer's fields up at, while on.
+    pub csv_align: Option<char>,
+    /// Whether `:Preview` shows the buffer as Markdown beside it.
+    pub preview: bool,
+    /// The Insert-mode completion menu.
+    pub menu: Option<Menu>,
+    /// The words of the `dictionary` files, once read.
+    pub dictionary: Dictionary,
+    /// The command palette, while it is open.
+    pub palette: Option<Palette>,
+    /// A program for the main loop to run in the foreground.
+    pub terminal_job: Option<TerminalJob>,
+    /// The commands and key bindings of the loaded plugins.
+    pub registry: Registry,
+    /// The user's autocommands and the events fired for them.
+    pub autocmds: Autocmds,
+    /// Annotations plugins draw in buffer lines without changing the text.
+    pub virtual_text: VirtualTextSet,
+    /// Problems reported by the last `:Lint`, sorted by position.
+    pub diagnostics: Vec<Diagnostic>,
+    pub loading: Option<LoadState>,
+    /// Wakes the main loop when background work finishes.
+    pub waker: Waker,
+    command_queue: VecDeque<String>,
+    /// Key presses to handle as if typed, after the queued commands.
+    input_queue: VecDeque<Event>,
+    undo_history: UndoHistory,
+    /// Whether the buffer changed since the last undo step was recorded.
+    undo_pending: bool,
+    batch_depth: usize,
+    batch_changed: bool,
+}
+
+impl Editor {
+    pub fn new(screen_width: u16, screen_height: u16, file_path: Option<PathBuf>) -> Self {
+        Self {
+            buffer: Buffer::new(),
+            cursor: Cursor { row: 0, col: 0 },
+            viewport: Viewport {
+                row_offset: 0,
+                col_offset: 0,
+            },
+            mode: Mode::Normal,
+            command_line: CommandLine::new(),
+            command_history: History::new(),
+            search_history: History::new(),
+            search: SearchState::new(),
+            jumps: JumpList::default(),
+            changes: ChangeList::default(),
+            pending_keys: String::new(),
+            visual_anchor: None,
+            registers: Registers::default(),
+            marks: BTreeMap::new(),
+            quickfix: QuickfixList::default(),
+            file_index: FileIndex::new(PathBuf::from(".")),
+            previous_dir: None,
+            state_dir: None,
+            global_marks: BTreeMap::new(),
+            pending_register: None,
+            status: String::new(),
+            error: false,
+            file_path,
+            alternate_file: None,
+            alternate_cursor: Cursor { row: 0, col: 0 },
+            file_time: None,
+            hidden_buffers: Vec::new(),
+            should_quit: false,
+            suspend_requested: false,
+            dirty: false,
+            deltas: DeltaLog::default(),
+            screen_width,
+            screen_height,
+            options: Options::new(),
+            theme: UiTheme::default(),
+            abbreviations: BTreeMap::new(),
+            scratch: None,
+            preview_window: None,
+            popup: None,
+            csv_align: None,
+            preview: false,
+            menu: None,
+            dictionary: Dictionary::default(),
+            palette: None,
+            terminal_job: None,
+            registry: Registry::default(),
+            autocmds: Autocmds::default(),
+            virtual_text: VirtualTextSet::default(),
+            diagnostics: Vec::new(),
+            loading: None,
+            waker: Waker::default(),
+            command_queue: VecDeque::new(),
+            input_queue: VecDeque::new(),
+            undo_history: UndoHistory::new(&[String::new()]),
+            undo_pending: false,
+            batch_depth: 0,
+            batch_changed: false,
+        }
+    }
+
+    pub fn set_screen_size(&mut self, width: u16, height: u16) {
+        self.screen_width = width;
+        self.screen_height = height;
+        self.ensure_cursor_visible();
+    }
+
+    pub fn content_height(&self) -> u16 {
+        let gutter = if self.command_line.active { 2 } else { 1 };
+        self.screen_height
+            .saturating_sub(gutter)
+            .saturating_sub(self.preview_window_height())
+    }
+
+    /// Rows taken by the preview window, below the main view.
+    pub fn preview_window_height(&self) -> u16 {
+        if self.preview_window.is_none() {
+            return 0;
+        }
+        let gutter = if self.command_line.active { 2 } else { 1 };
+        let available = self.screen_height.saturating_sub(gutter) as usize / 2;
+        self.options.previewheight.max(2).min(available) as u16
+    }
+
+    /// Columns taken by the sign column, shown while there are
+    /// diagnostics.
+    pub fn sign_column_width(&self) -> usize {
+        if self.diagnostics.is_empty() {
+            0
+        } else {
+            SIGN_COLUMN_WIDTH
+        }
+    }
+
+    /// The text of register `name`; `+` and `*` read the system clipboard.
+    pub fn register(&self, name: char) -> Result<Option<Register>, String> {
+        if clipboard::is_clipboard(name) {
+            return clipboard::read(name)
+                .map(|text| Some(Register::guess(text)))
+                .map_err(|err| format!("Cannot read the clipboard: {}", err));
+        }
+        Ok(self.registers.register(name).cloned())
+    }
+
+    /// Saves yanked or deleted text as `Registers::record` does, copying it
+    /// to the system clipboard too for `+` and `*`.
+    pub fn record_register(&mut self, name: Option<char>, register: Register, yank: bool) {
+        if let Some(name) = name.filter(|name| clipboard::is_clipboard(*name))
+            && let Err(err) = clipboard::write(name, &register.text)
+        {
+            self.set_error(format!("Cannot write the clipboard: {}", err));
+        }
+        self.registers.record(name, register, yank);
+    }
+
+    /// Reads the `dictionary` files, unless they are the ones already read.
+    pub fn load_dictionary(&mut self) -> Result<(), String> {
+        if self.dictionary.source() != self.options.dictionary {
+            self.dictionary = Dictionary::load(&self.options.dictionary)?;
+        }
+        Ok(())
+    }
+
+    /// Columns the `:Preview` pane takes from the right of the window.
+    pub fn preview_width(&self) -> usize {
+        if self.preview {
+            self.screen_width as usize / 2
+        } else {
+            0
+        }
+    }
+
+    pub fn status_row(&self) -> u16 {
+        if self.command_line.active {
+            self.screen_height.saturating_sub(2)
+        } else {
+            self.screen_height.saturating_sub(1)
+        }
+    }
+
+    pub fn command_row(&self) -> u16 {
+        self.screen_height.saturating_sub(1)
+    }
+
+    pub fn set_status(&mut self, message: impl Into<String>) {
+        self.status = message.into();
+    }
+
+    /// Shows a message that reports a failure and flags it in `error`.
+    pub fn set_error(&mut self, message: impl Into<String>) {
+        self.set_status(message);
+        self.error = true;
+    }
+
+    pub fn push_command(&mut self, command: String) {
+        self.command_queue.push_back(command);
+    }
+
+    /// Queues `commands` to run before anything already queued, as `:source` does.
+    pub fn push_commands_front(&mut self, commands: Vec<String>) {
+        for command in commands.into_iter().rev() {
+            self.command_queue.push_front(command);
+        }
+    }
+
+    pub fn next_command(&mut self) -> Option<String> {
+        let command = self.command_queue.pop_front();
+        let remaining = command.as_ref().map(|_| self.command_queue.len());
+        self.autocmds.update_running(remaining);
+        command
+    }
+
+    /// Fires `event` for the open file, for the plugins and autocommands
+    /// subscribed to it to handle once the current command is done. The
+    /// file is the one open by then, so a command that reads or writes a
+    /// new file may fire before naming it.
+    pub fn fire(&mut self, event: AutoEvent) {
+        self.autocmds.fire(event, None);
+    }
+
+    /// The next fired event and the name its patterns match.
+    pub fn next_event(&mut self) -> Option<(AutoEvent, String)> {
+        let (event, name) = self.autocmds.next_event()?;
+        let name = name.unwrap_or_else(|| {
+            self.file_path
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        Some((event, name))
+    }
+
+    /// Whether a plugin or autocommand would handle `event`.
+    pub fn has_handlers(&self, event: AutoEvent) -> bool {
+        self.registry.events.contains(&event) || self.autocmds.has(event)
+    }
+
+    /// Queues the commands of autocommands in front of everything else,
+    /// marking them as run by an autocommand.
+    pub fn run_autocmds(&mut self, commands: Vec<String>) {
+        if commands.is_empty() {
+            return;
+        }
+        self.autocmds.start_running(self.command_queue.len());
+        self.push_commands_front(commands);
+    }
+
+    /// Fires the events for what changed since the last call: the mode,
+    /// with `InsertEnter` and `InsertLeave`, and outside Insert mode the
+    /// cursor position and the text.
+    pub fn fire_state_events(&mut self) {
+        let now = Seen {
+            mode: self.mode,
+            cursor: self.cursor,
+            revision: self.revision(),
+        };
+        let Some(seen) = self.autocmds.seen.replace(now) else {
+            return;
+        };
+        if seen.mode != now.mode {
+            let change = format!(
+                "{}:{}",
+                autocmd::mode_letter(seen.mode),
+                autocmd::mode_letter(now.mode)
+            );
+            if seen.mode == Mode::Insert {
+                self.fire(AutoEvent::InsertLeave);
+            }
+            if now.mode == Mode::Insert {
+                self.fire(AutoEvent::InsertEnter);
+            }
+            self.autocmds.fire(AutoEvent::ModeChanged, Some(change));
+        }
+        if now.mode != Mode::Insert {
+            if seen.revision != now.revision {
+                self.fire(AutoEvent::TextChanged);
+            }
+            if seen.cursor != now.cursor {
+                self.fire(AutoEvent::CursorMoved);
+            }
+        }
+    }
+
+    /// Queues `keys` to be handled as if typed, once the queued commands
+    /// have run.
+    pub fn feed_keys(&mut self, keys: Vec<KeyEvent>) {
+        self.input_queue.extend(keys.into_iter().map(Event::Key));
+    }
+
+    pub fn next_input(&mut self) -> Option<Event> {
+        self.input_queue.pop_front()
+    }
+
+    /// Where mark `name` is in the open file: a lowercase or Visual mark,
+    /// or an uppercase mark set in this file.
+    pub fn mark(&self, name: char) -> Option<Cursor> {
+        if !name.is_ascii_uppercase() {
+            return self.marks.get(&name).copied();
+        }
+        let mark = self.global_marks.get(&name)?;
+        let (Some(path), Some(open)) = (&mark.path, &self.file_path) else {
+            return None;
+        };
+        same_file(path, open).then_some(mark.cursor)
+    }
+
+    /// Sets or deletes uppercase marks with `change`, saving them in the
+    /// state directory when there is one.
+    pub fn update_global_marks(&mut self, change: impl Fn(&mut BTreeMap<char, Jump>)) {
+        change(&mut self.global_marks);
+        let Some(dir) = &self.state_dir else {
+            return;
+        };
+        match global_marks::update(&dir.join(global_marks::GLOBAL_MARKS_FILE), change) {
+            Ok(marks) => self.global_marks = marks,
+            Err(err) => self.set_error(format!("Cannot save marks: {}", err)),
+        }
+    }
+
+    /// Records the open file and cursor in the `:oldfiles` list, before
+    /// the file is closed.
+    pub fn remember_file(&self) {
+        if let (Some(dir), Some(path)) = (&self.state_dir, &self.file_path) {
+            let _ = oldfiles::record(&dir.join(oldfiles::OLDFILES_FILE), path, self.cursor);
+        }
+    }
+
+    /// The current location as a jumplist entry.
+    pub fn current_jump(&self) -> Jump {
+        Jump {
+            path: self.file_path.clone(),
+            cursor: self.cursor,
+        }
+    }
+
+    /// Records the current location before a jump motion moves the cursor.
+    pub fn push_jump(&mut self) {
+        let jump = self.current_jump();
+        self.jumps.push(jump);
+    }
+
+    /// Makes `dir` the working directory relative paths are resolved
+    /// against, as `:cd` does. The paths the editor holds keep naming the
+    /// same files: the open file's stays relative if it is under `dir`, and
+    /// the alternate file's and those in the jumplist and quickfix list
+    /// become absolute. Remote URLs are left as they are.
+    pub fn change_dir(&mut self, dir: &Path) -> Result<(), String> {
+        let old = env::current_dir()
+            .map_err(|err| format!("Cannot get the working directory: {}", err))?;
+        env::set_current_dir(dir)
+            .map_err(|err| format!("Cannot change directory to {}: {}", dir.display(), err))?;
+        let new = env::current_dir().unwrap_or_else(|_| old.join(dir));
+        let absolute = |path: &Path| {
+            if provider::is_remote(path) {
+                path.to_path_buf()
+            } else {
+                old.join(path)
+            }
+        };
+        if let Some(path) = &self.file_path {
+            let path = absolute(path);
+            self.file_path = Some(
+                path.strip_prefix(&new)
+                    .map_or(path.clone(), Path::to_path_buf),
+            );
+        }
+        if let Some(path) = &mut self.alternate_file {
+            *path = absolute(path);
+        }
+        for hidden in &mut self.hidden_buffers {
+            hidden.path = absolute(&hidden.path);
+        }
+        for path in self
+            .jumps
+            .entries
+            .iter_mut()
+            .filter_map(|jump| jump.path.as_mut())
+        {
+            *path = absolute(path);
+        }
+        for entry in &mut self.quickfix.entries {
+            entry.path = absolute(&entry.path);
+        }
+        self.file_index.root_changed();
+        if old != new {
+            self.previous_dir = Some(old);
+        }
+        Ok(())
+    }
+
+    /// `args` with `~`, environment variables, `%` and `#` expanded, as in
+    /// the arguments of ex commands that take file names.
+    pub fn expand_args(&self, args: &str) -> Result<String, String> {
+        let files = expand::Files {
+            current: self.file_path.as_deref(),
+            alternate: self.alternate_file.as_deref(),
+        };
+        expand::expand(args, files)
+    }
+
+    /// History recalled at the command-line prompt `prompt`.
+    pub fn history_mut(&mut self, prompt: char) -> &mut History {
+        if prompt == ':' {
+            &mut self.command_history
+        } else {
+            &mut self.search_history
+        }
+    }
+
+    pub fn open_scratch(&mut self, title: impl Into<String>, lines: Vec<String>) {
+        self.scratch = Some(ScratchView {
+            title: title.into(),
+            lines,
+            scroll: 0,
+        });
+    }
+
+    pub fn close_scratch(&mut self) {
+        self.scratch = None;
+    }
+
+    /// Shows line `row` of `path` in the preview window; the open file is
+    /// shown as it is in the buffer.
+    pub fn open_preview_window(&mut self, path: &Path, row: usize) -> io::Result<()> {
+        let own_file = self
+            .file_path
+            .as_deref()
+            .is_some_and(|file| same_file(file, path));
+        let lines = if own_file {
+            self.buffer.lines.clone()
+        } else {
+            read_file(path)?.lines().map(String::from).collect()
+        };
+        self.preview_window = Some(PreviewWindow {
+            path: path.to_path_buf(),
+            lines,
+            row,
+        });
+        self.ensure_cursor_visible();
+        Ok(())
+    }
+
+    pub fn close_preview_window(&mut self) {
+        self.preview_window = None;
+    }
+
+    pub fn load_from_path(&mut self, path: &Path) -> io::Result<()> {
+        let contents = read_file(path)?;
+        self.load_text(contents);
+        self.options.readonly = provider::provider_for(path).read_only();
+        self.note_file_time(path);
+        Ok(())
+    }
+
+    /// Remembers when `path`, now the open file, was last modified.
+    pub fn note_file_time(&mut self, path: &Path) {
+        self.file_time = modified_time(path);
+    }
+
+    /// Whether the open file has been modified or removed on disk since it
+    /// was last read or written.
+    pub fn changed_on_disk(&self) -> bool {
+        let Some(path) = self.file_path.as_deref() else {
+            return false;
+        };
+        self.file_time.is_some() && modified_time(path) != self.file_time
+    }
+
+    /// Writes the modified buffer and the hidden ones to the recovery
+    /// directory, when the editor must exit before they can be saved.
+    /// Encrypted files are left out, as their plaintext is never written.
+    /// Returns the copies written; there are none without a state directory.
+    pub fn emergency_save(&self) -> io::Result<Vec<PathBuf>> {
+        let Some(dir) = &self.state_dir else {
+            return Ok(Vec::new());
+        };
+        let encrypted = |path: &Path| Cipher::for_path(path).is_some();
+        let mut buffers: Vec<(Option<&Path>, String)> = self
+            .hidden_buffers
+            .iter()
+            .filter(|hidden| !encrypted(&hidden.path))
+            .map(|hidden| {
+                let mut contents = hidden.buffer.to_string();
+                if hidden.endofline {
+                    contents.push('\n');
+                }
+                (Some(hidden.path.as_path()), contents)
+            })
+            .collect();
+        if self.dirty && !self.file_path.as_deref().is_some_and(encrypted) {
+            buffers.push((self.file_path.as_deref(), self.file_contents()));
+        }
+        let dir = dir.join(RECOVERY_DIR);
+        if !buffers.is_empty() {
+            create_private_dir(&dir)?;
+        }
+        buffers
+            .into_iter()
+            .map(|(path, contents)| {
+                let copy = dir.join(recovery_name(path));
+                write_private(&copy, &contents)?;
+                Ok(copy)
+            })
+            .collect()
+    }
+
+    /// Keeps the buffer in memory, as `hidden` does, before another file is
+    /// edited in its place, leaving an empty unnamed one. Returns false if
+    /// it has no file name to be found again by.
+    pub fn hide_buffer(&mut self) -> bool {
+        let Some(path) = self.file_path.take() else {
+            return false;
+        };
+        self.commit_undo();
+        self.hidden_buffers.push(HiddenBuffer {
+            path,
+            buffer: std::mem::replace(&mut self.buffer, Buffer::new()),
+            cursor: std::mem::replace(&mut self.cursor, Cursor { row: 0, col: 0 }),
+            changes: std::mem::take(&mut self.changes),
+            undo_history: std::mem::take(&mut self.undo_history),
+            endofline: self.options.endofline,
+            readonly: self.options.readonly,
+        });
+        self.deltas.reset();
+        self.reset_undo();
+        self.dirty = false;
+        self.file_time = None;
+        true
+    }
+
+    /// Brings back the hidden buffer of `path` in place of the current one,
+    /// modified and with its undo history. Returns false if `path` has none.
+    pub fn unhide_buffer(&mut self, path: &Path) -> bool {
+        let Some(index) = self
+            .hidden_buffers
+            .iter()
+            .position(|hidden| same_file(&hidden.path, path))
+        else {
+            return false;
+        };
+        let hidden = self.hidden_buffers.remove(index);
+        self.buffer = hidden.buffer;
+        self.cursor = hidden.cursor;
+        self.viewport = Viewport {
+            row_offset: 0,
+            col_offset: 0,
+        };
+        self.dirty = true;
+        self.options.endofline = hidden.endofline;
+        self.options.readonly = hidden.readonly;
+        self.options.buftype.clear();
+        self.note_file_time(path);
+        self.deltas.reset();
+        self.changes = hidden.changes;
+        self.diagnostics.clear();
+        self.virtual_text = VirtualTextSet::default();
+        self.csv_align = None;
+        self.undo_history = hidden.undo_history;
+        self.undo_pending = false;
+        self.file_path = Some(hidden.path);
+        true
+    }
+
+    /// Replaces the buffer with the text of a file that was read some other
+    /// way than from its path, as when it was decrypted.
+    pub fn load_text(&mut self, mut contents: String) {
+        // The newline ending the last line does not start another one.
+        self.options.endofline = contents.ends_with('\n');
+        if self.options.endofline {
+            contents.pop();
+        }
+        self.replace_buffer(Buffer::from_string(contents));
+        self.fire(AutoEvent::BufReadPost);
+    }
+
+    /// Puts `lines` in a scratch buffer (`buftype=nofile`) in place of the
+    /// current one, which the caller has saved or hidden if it must be kept.
+    pub fn open_scratch_buffer(&mut self, lines: Vec<String>) {
+        self.replace_buffer(Buffer::from_string(lines.join("\n")));
+        self.file_path = None;
+        self.options.endofline = true;
+        self.options.buftype = "nofile".to_string();
+    }
+
+    pub fn is_scratch_buffer(&self) -> bool {
+        self.options.buftype == "nofile"
+    }
+
+    /// Starts afresh on `buffer`, unmodified, with nothing to undo.
+    fn replace_buffer(&mut self, buffer: Buffer) {
+        self.buffer = buffer;
+        self.cursor = Cursor { row: 0, col: 0 };
+        self.viewport = Viewport {
+            row_offset: 0,
+            col_offset: 0,
+        };
+        self.dirty = false;
+        self.file_time = None;
+        self.options.readonly = false;
+        self.options.buftype.clear();
+        self.deltas.reset();
+        self.changes = ChangeList::default();
+        self.diagnostics.clear();
+        self.virtual_text = VirtualTextSet::default();
+        self.csv_align = None;
+        self.reset_undo();
+    }
+
+    /// Installs the main loop's waker, for this editor's background work.
+    pub fn set_waker(&mut self, waker: Waker) {
+        self.file_index.set_waker(waker.clone());
+        self.waker = waker;
+    }
+
+    /// Loads `path`, reading large files on a background thread. The buffer fills in
+    /// as `poll_loading` receives lines; small, remote and compressed files are read
+    /// immediately.
+    pub fn start_loading(&mut self, path: &PathBuf) -> io::Result<()> {
+        if provider::is_remote(path) || Compression::for_path(path).is_some() {
+            return self.load_from_path(path);
+        }
+        let total_bytes = fs::metadata(path)?.len();
+        if total_bytes < ASYNC_LOAD_THRESHOLD {
+            return self.load_from_path(path);
+        }
+
+        let file = File::open(path)?;
+        let (sender, receiver) = mpsc::channel();
+        let waker = self.waker.clone();
+        thread::spawn(move || {
+            let message = match read_lines_in_chunks(file, |lines, bytes| {
+                let sent = sender.send(LoadMessage::Lines { lines, bytes }).is_ok();
+                waker.wake();
+                sent
+            }) {
+                Ok(eol) => LoadMessage::Done { eol },
+                Err(err) => LoadMessage::Failed(err),
+            };
+            let _ = sender.send(message);
+            waker.wake();
+        });
+
+        self.replace_buffer(Buffer::new());
+        self.note_file_time(path);
+        self.loading = Some(LoadState {
+            path: path.clone(),
+            total_bytes,
+            loaded_bytes: 0,
+            receiver,
+            received_lines: false,
+        });
+        Ok(())
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
+    /// Percentage of the file read so far by an in-flight background load.
+    pub fn load_progress(&self) -> Option<u8> {
+        let state = self.loading.as_ref()?;
+        let percent = state.loaded_bytes.saturating_mul(100) / state.total_bytes.max(1);
+        Some(percent.min(100) as u8)
+    }
+
+    /// Applies any lines received from the background loader. Returns true if the
+    /// buffer or load state changed.
+    pub fn poll_loading(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            let Some(state) = self.loading.as_mut() else {
+                return changed;
+            };
+            let message = match state.receiver.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => return changed,
+                Err(TryRecvError::Disconnected) => {
+                    LoadMessage::Failed(io::Error::other("loader stopped unexpectedly"))
+                }
+            };
+            changed = true;
+            match message {
+                LoadMessage::Lines { lines, bytes } => {
+                    if !state.received_lines {
+                        state.received_lines = true;
+                        self.buffer.lines.clear();
+                    }
+                    state.loaded_bytes += bytes;
+                    self.buffer.lines.extend(lines);
+                    self.deltas.reset();
+                }
+                LoadMessage::Done { eol } => {
+                    self.options.endofline = eol;
+                    let path = state.path.clone();
+                    self.loading = None;
+                    if self.buffer.lines.is_empty() {
+                        self.buffer.lines.push(String::new());
+                    }
+                    self.reset_undo();
+                    self.fire(AutoEvent::BufReadPost);
+                    self.set_status(format!("Opened {}", path.display()));
+                }
+                LoadMessage::Failed(err) => {
+                    self.loading = None;
+                    self.buffer = Buffer::new();
+                    self.deltas.reset();
+                    self.reset_undo();
+                    // The empty buffer must not be written over the file.
+                    self.file_path = None;
+                    self.file_time = None;
+                    self.set_error(format!("Open failed: {}", err));
+                }
+            }
+        }
+    }
+
+    /// Writes the buffer to `path` through its provider, compressed as its
+    /// extension says, keeping the permissions and owner of a local file
+    /// there and flushing it to disk if `fsync` is set. A read-only file is
+    /// only written over when `force`d. Returns a warning if the owner could
+    /// not be kept.
+    pub fn save_to_path(&mut self, path: &Path, force: bool) -> io::Result<Option<String>> {
+        let last = self.buffer.lines.len().saturating_sub(1);
+        let warning = self.write_lines(0, last, path, false, force)?;
+        self.dirty = false;
+        self.note_file_time(path);
+        Ok(warning)
+    }
+
+    /// Writes rows `first..=last` to `path` as `save_to_path` does, or
+    /// appends them to the local file there, without marking the buffer
+    /// saved. The whole buffer is written as `file_contents` gives it; part
+    /// of it has a newline after every line.
+    pub fn write_lines(
+        &self,
+        first: usize,
+        last: usize,
+        path: &Path,
+        append: bool,
+        force: bool,
+    ) -> io::Result<Option<String>> {
+        let mut contents = if first == 0 && last + 1 >= self.buffer.lines.len() {
+            self.file_contents()
+        } else {
+            self.buffer.lines[first..=last].join("\n") + "\n"
+        }
+        .into_bytes();
+        if append {
+            if provider::is_remote(path) || Compression::for_path(path).is_some() {
+                return Err(io::Error::other("cannot append to this kind of file"));
+            }
+            let mut file = fs::OpenOptions::new().append(true).open(path)?;
+            file.write_all(&contents)?;
+            if self.options.fsync {
+                file.sync_all()?;
+            }
+            return Ok(None);
+        }
+        if let Some(compression) = Compression::for_path(path) {
+            contents = compression.compress(&contents)?;
+        }
+        provider::provider_for(path).write(path, &contents, self.options.fsync, force)
+    }
+
+    /// The buffer as written to its file: the lines with a newline after
+    /// each but the last, which gets one if `endofline` or `fixendofline`
+    /// is set. A buffer holding one empty line and no `endofline` is an
+    /// empty file.
+    pub fn file_contents(&self) -> String {
+        let mut contents = self.buffer.to_string();
+        let empty = self.buffer.lines.len() == 1 && contents.is_empty();
+        if self.options.endofline || (self.options.fixendofline && !empty) {
+            contents.push('\n');
+        }
+        contents
+    }
+
+    pub fn current_line_len(&self) -> usize {
+        self.buffer
+            .lines
+            .get(self.cursor.row)
+            .map(|line| line.chars().count())
+            .unwrap_or(0)
+    }
+
+    /// Returns the keyword (letters, digits, `_`) under or after the cursor.
+    pub fn word_under_cursor(&self) -> Option<String> {
+        let line: Vec<char> = self.buffer.lines.get(self.cursor.row)?.chars().collect();
+        let is_word = |ch: &char| is_word_char(*ch);
+        let mut start = self.cursor.col.min(line.len());
+        while start < line.len() && !is_word(&line[start]) {
+            start += 1;
+        }
+        if start >= line.len() {
+            return None;
+        }
+        while start > 0 && is_word(&line[start - 1]) {
+            start -= 1;
+        }
+        let word: String = line[start..].iter().take_while(|ch| is_word(ch)).collect();
+        Some(word)
+    }
+
+    /// Compiles a search or substitute pattern, ignoring case as
+    /// `ignorecase` and `smartcase` say.
+    pub fn compile_pattern(&self, pattern: &str) -> Result<Regex, String> {
+        let ignore_case =
+            self.options.ignorecase && !(self.options.smartcase && pattern::has_uppercase(pattern));
+        pattern::compile(pattern, ignore_case)
+    }
+
+    /// Char ranges of the matches of `regex` in `row`.
+    pub fn line_matches(&self, row: usize, regex: &Regex) -> Vec<(usize, usize)> {
+        let Some(line) = self.buffer.lines.get(row) else {
+            return Vec::new();
+        };
+        let char_col = |byte: usize| line[..byte].chars().count();
+        regex
+            .find_iter(line)
+            .map(|m| (char_col(m.start()), char_col(m.end())))
+            .collect()
+    }
+
+    /// Finds the next match of `regex` after (or, searching backward,
+    /// before) `from`, wrapping around the end of the buffer unless
+    /// `wrapscan` is off.
+    pub fn find_pattern(&self, regex: &Regex, from: Cursor, forward: bool) -> Option<Cursor> {
+        let line_count = self.buffer.lines.len();
+        let matches_in = |row: usize| -> Vec<usize> {
+            self.line_matches(row, regex)
+                .into_iter()
+                .map(|(start, _)| start)
+                .collect()
+        };
+
+        for step in 0..=line_count {
+            let row = if forward {
+                (from.row + step) % line_count
+            } else {
+                (from.row + line_count * 2 - step) % line_count
+            };
+            let cols = matches_in(row);
+            let found = match (step, forward) {
+                (0, true) => cols.into_iter().find(|&col| col > from.col),
+                (0, false) => cols.into_iter().rev().find(|&col| col < from.col),
+                (_, true) if step == line_count => cols.into_iter().find(|&col| col <= from.col),
+                (_, false) if step == line_count => {
+                    cols.into_iter().rev().find(|&col| col >= from.col)
+                }
+                (_, true) => cols.into_iter().next(),
+                (_, false) => cols.into_iter().next_back(),
+            };
+            if let Some(col) = found {
+                let found = Cursor { row, col };
+                let wrapped = if forward {
+                    found <= from
+                } else {
+                    found >= from
+                };
+                return (self.options.wrapscan || !wrapped).then_some(found);
+            }
+        }
+        None
+    }
+
+    pub fn clamp_cursor(&mut self) {
+        if self.cursor.row >= self.buffer.lines.len() {
+            self.cursor.row = self.buffer.lines.len().saturating_sub(1);
+            self.cursor.col = 0;
+        }
+        let line_len = self.current_line_len();
+        if self.cursor.col > line_len {
+            self.cursor.col = line_len;
+        }
+    }
+
+    pub fn ensure_cursor_visible(&mut self) {
+        let content_height = self.content_height() as usize;
+        if content_height == 0 || self.cursor.row < self.viewport.row_offset {
+            self.viewport.row_offset = self.cursor.row;
+        } else if self.cursor.row >= self.viewport.row_offset + content_height {
+            self.viewport.row_offset = self.cursor.row.saturating_sub(content_height - 1);
+        }
+
+        let content_width = (self.screen_width as usize)
+            .saturating_sub(self.sign_column_width() + self.preview_width());
+        let line = self
+            .buffer
+            .lines
+            .get(self.cursor.row)
+            .map_or("", String::as_str);
+        let tabstop = self.options.tabstop;
+        let cursor_start = display_col(line, self.cursor.col, tabstop);
+        let cursor_end = display_col(line, self.cursor.col + 1, tabstop).max(cursor_start + 1);
+        if content_width == 0 || cursor_start < self.viewport.col_offset {
+            self.viewport.col_offset = cursor_start;
+        } else if cursor_end > self.viewport.col_offset + content_width {
+            self.viewport.col_offset = cursor_end - content_width;
+        }
+    }
+
+    pub fn current_line(&self) -> &str {
+        self.buffer
+            .lines
+            .get(self.cursor.row)
+            .map_or("", String::as_str)
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor.col = prev_grapheme_boundary(self.current_line(), self.cursor.col);
+        self.ensure_cursor_visible();
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor.col = next_grapheme_boundary(self.current_line(), self.cursor.col);
+        self.ensure_cursor_visible();
+    }
+
+    pub fn move_up(&mut self) {
+        if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+            self.clamp_cursor();
+        }
+        self.ensure_cursor_visible();
+    }
+
+    pub fn move_down(&mut self) {
+        if self.cursor.row + 1 < self.buffer.lines.len() {
+            self.cursor.row += 1;
+            self.clamp_cursor();
+        }
+        self.ensure_cursor_visible();
+    }
+
+    pub fn move_line_start(&mut self) {
+        self.cursor.col = 0;
+        self.ensure_cursor_visible();
+    }
+
+    pub fn move_line_end(&mut self) {
+        self.cursor.col = self.current_line_len();
+        self.ensure_cursor_visible();
+    }
+
+    /// Moves the cursor to the start of `row`, clamped to the buffer.
+    pub fn goto_line(&mut self, row: usize) {
+        self.goto_position(row, 0);
+    }
+
+    /// Moves the cursor to (`row`, `col`), clamped to the buffer.
+    pub fn goto_position(&mut self, row: usize, col: usize) {
+        self.cursor.row = row.min(self.buffer.lines.len().saturating_sub(1));
+        self.cursor.col = col;
+        self.clamp_cursor();
+        self.ensure_cursor_visible();
+    }
+
+    /// Display width of the leading whitespace of `row`.
+    pub fn indent_width(&self, row: usize) -> usize {
+        let Some(line) = self.buffer.lines.get(row) else {
+            return 0;
+        };
+        let indent_len = line
+            .chars()
+            .take_while(|ch| *ch == ' ' || *ch == '\t')
+            .count();
+        display_col(line, indent_len, self.options.tabstop)
+    }
+
+    /// Replaces the leading whitespace of `row` with an indent `width`
+    /// columns wide, keeping the cursor on the same text.
+    pub fn set_indent(&mut self, row: usize, width: usize) {
+        let Some(line) = self.buffer.lines.get(row) else {
+            return;
+        };
+        let old_len = line
+            .chars()
+            .take_while(|ch| *ch == ' ' || *ch == '\t')
+            .count();
+        let indent = self.options.fill_whitespace(0, width);
+        if line.chars().take(old_len).eq(indent.chars()) {
+            return;
+        }
+        let new_len = indent.chars().count();
+        self.edit_range(
+            Cursor { row, col: 0 },
+            Cursor { row, col: old_len },
+            &indent,
+        );
+        if self.cursor.row == row {
+            self.cursor.col = (self.cursor.col + new_len).saturating_sub(old_len);
+        }
+        self.clamp_cursor();
+        self.mark_changed();
+        self.ensure_cursor_visible();
+    }
+
+    /// Extension of the open file, used to pick per-filetype behavior.
+    pub fn file_extension(&self) -> &str {
+        self.file_path
+            .as_deref()
+            .and_then(Path::extension)
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+    }
+
+    /// Indent `row` should have given the lines above it: the previous
+    /// non-blank line's indent, one level deeper after a block opener and one
+    /// level shallower when `row` closes a block.
+    pub fn expected_indent(&self, row: usize) -> usize {
+        let rules = indent::rules_for(self.file_extension());
+        let smart = self.options.smartindent;
+        let shiftwidth = self.options.shiftwidth();
+        let previous = (0..row.min(self.buffer.lines.len()))
+            .rev()
+            .find(|&prev| !self.buffer.lines[prev].trim().is_empty());
+        let mut width = match previous {
+            Some(prev) => {
+                let opens = smart && indent::opens_block(&self.buffer.lines[prev], rules);
+                self.indent_width(prev) + if opens { shiftwidth } else { 0 }
+            }
+            None => 0,
+        };
+        if smart
+            && let Some(line) = self.buffer.lines.get(row)
+            && indent::closes_block(line, rules)
+        {
+            width = width.saturating_sub(shiftwidth);
+        }
+        width
+    }
+
+    /// Re-indents `row` with `expected_indent`, as `==` does.
+    pub fn reindent_line(&mut self, row: usize) {
+        let width = self.expected_indent(row);
+        self.set_indent(row, width);
+    }
+
+    /// Whether the current line starts with a block closer such as `}` or
+    /// `end` for the open file's type.
+    pub fn current_line_closes_block(&self) -> bool {
+        let rules = indent::rules_for(self.file_extension());
+        indent::closes_block(self.current_line(), rules)
+    }
+
+    /// Splits the line at the cursor like `insert_newline` and indents the
+    /// new line according to `autoindent` and `smartindent`.
+    pub fn insert_newline_indented(&mut self) {
+        self.begin_batch();
+        self.insert_newline();
+        if self.options.autoindent || self.options.smartindent {
+            // Like Vim, don't leave indent-only lines behind.
+            let previous = self.cursor.row - 1;
+            if self.buffer.lines[previous].trim().is_empty() {
+                self.set_indent(previous, 0);
+            }
+            self.reindent_line(self.cursor.row);
+        }
+        self.end_batch();
+    }
+
+    /// Opens an indented line below (`o`) or above (`O`) the cursor line.
+    pub fn open_line(&mut self, below: bool) {
+        self.begin_batch();
+        if below {
+            self.cursor.col = self.current_line_len();
+            self.insert_newline_indented();
+        } else {
+            let indent_below = self.indent_width(self.cursor.row);
+            self.cursor.col = 0;
+            self.insert_newline();
+            self.cursor.row -= 1;
+            if self.options.autoindent || self.options.smartindent {
+                let has_previous = self.buffer.lines[..self.cursor.row]
+                    .iter()
+                    .any(|line| !line.trim().is_empty());
+                let width = if has_previous {
+                    self.expected_indent(self.cursor.row)
+                } else {
+                    indent_below
+                };
+                self.set_indent(self.cursor.row, width);
+            }
+            self.cursor.col = self.current_line_len();
+            self.ensure_cursor_visible();
+        }
+        self.end_batch();
+    }
+
+    /// Shifts `row` by `levels` shiftwidths, rounding to a multiple of the
+    /// shiftwidth like Vim's `shiftround`.
+    pub fn shift_line(&mut self, row: usize, levels: isize) {
+        let Some(line) = self.buffer.lines.get(row) else {
+            return;
+        };
+        if line.trim().is_empty() {
+            return;
+        }
+        let shiftwidth = self.options.shiftwidth();
+        let current = self.indent_width(row);
+        let width = if levels >= 0 {
+            (current / shiftwidth + levels as usize) * shiftwidth
+        } else {
+            let levels = levels.unsigned_abs();
+            let rounded_up = current.div_ceil(shiftwidth);
+            rounded_up.saturating_sub(levels) * shiftwidth
+        };
+        self.set_indent(row, width);
+    }
+
+    /// Strips trailing whitespace from every line, leaving the cursor where
+    /// it was unless its line got shorter than its column. Returns the number
+    /// of lines changed.
+    pub fn trim_trailing_whitespace(&mut self) -> usize {
+        let cursor = self.cursor;
+        let mut changed = 0;
+        self.begin_batch();
+        for row in 0..self.buffer.lines.len() {
+            let line = &self.buffer.lines[row];
+            let kept = line.trim_end().chars().count();
+            let len = line.chars().count();
+            if kept < len {
+                self.delete_range(Cursor { row, col: kept }, Cursor { row, col: len });
+                changed += 1;
+            }
+        }
+        self.end_batch();
+        self.cursor = cursor;
+        self.clamp_cursor();
+        self.ensure_cursor_visible();
+        changed
+    }
+
+    /// Comments out rows `first..=last` with the file type's line-comment
+    /// prefix, or uncomments them if they are all commented already.
+    pub fn toggle_comment(&mut self, first: usize, last: usize) {
+        let last = last.min(self.buffer.lines.len().saturating_sub(1));
+        if first > last {
+            return;
+        }
+        let prefix = comment::prefix_for(self.file_extension());
+        let toggled = comment::toggle(&self.buffer.lines[first..=last], prefix);
+        if toggled[..] == self.buffer.lines[first..=last] {
+            return;
+        }
+        let cursor_line_len = self.current_line_len();
+        for (row, line) in (first..=last).zip(&toggled) {
+            self.replace_line(row, line);
+        }
+        if (first..=last).contains(&self.cursor.row) {
+            // Keep the cursor on the same text as the prefix comes and goes.
+            let new_len = self.current_line_len();
+            self.cursor.col = (self.cursor.col + new_len).saturating_sub(cursor_line_len);
+        }
+        self.clamp_cursor();
+        self.mark_changed();
+        self.ensure_cursor_visible();
+    }
+
+    /// Handles Tab in Insert mode using `softtabstop`, `tabstop` and
+    /// `expandtab`.
+    pub fn insert_tab(&mut self) {
+        let options = &self.options;
+        if !options.expandtab && options.softtabstop == 0 {
+            self.insert_char('\t');
+            return;
+        }
+        let step = if options.softtabstop > 0 {
+            options.softtabstop
+        } else {
+            options.tabstop
+        };
+        let line = self.current_line();
+        let chars: Vec<char> = line.chars().collect();
+        let mut run_start = self.cursor.col.min(chars.len());
+        while run_start > 0 && matches!(chars[run_start - 1], ' ' | '\t') {
+            run_start -= 1;
+        }
+        let vcol = display_col(line, self.cursor.col, options.tabstop);
+        let target = (vcol / step + 1) * step;
+        let from = display_col(line, run_start, options.tabstop);
+        let expandtab = options.expandtab;
+        let fill = if expandtab {
+            " ".repeat(target - vcol)
+        } else {
+            options.fill_whitespace(from, target)
+        };
+
+        self.begin_batch();
+        if !expandtab {
+            while self.cursor.col > run_start {
+                self.backspace();
+            }
+        }
+        for ch in fill.chars() {
+            self.insert_char(ch);
+        }
+        self.end_batch();
+    }
+
+    /// Backspace in Insert mode: within the indent, deletes spaces back to
+    /// the previous `softtabstop` column.
+    pub fn backspace_soft_tab(&mut self) {
+        let softtabstop = self.options.softtabstop;
+        let tabstop = self.options.tabstop;
+        let line = self.current_line();
+        let before: String = line.chars().take(self.cursor.col).collect();
+        if softtabstop == 0
+            || !before.ends_with(' ')
+            || !before.chars().all(|ch| ch == ' ' || ch == '\t')
+        {
+            self.backspace();
+            return;
+        }
+        let vcol = display_col(line, self.cursor.col, tabstop);
+        let target = (vcol - 1) / softtabstop * softtabstop;
+        self.begin_batch();
+        loop {
+            self.backspace();
+            let line = self.current_line();
+            let col = self.cursor.col;
+            if col == 0
+                || display_col(line, col, tabstop) <= target
+                || line.chars().nth(col - 1) != Some(' ')
+            {
+                break;
+            }
+        }
+        self.end_batch();
+    }
+
+    /// Scrolls the viewport by `delta` rows, keeping the cursor on screen.
+    pub fn scroll_viewport(&mut self, delta: isize) {
+        let max_offset = self.buffer.lines.len().saturating_sub(1);
+        self.viewport.row_offset = self
+            .viewport
+            .row_offset
+            .saturating_add_signed(delta)
+            .min(max_offset);
+        let last_visible = self.viewport.row_offset + (self.content_height() as usize).max(1) - 1;
+        self.cursor.row = self
+            .cursor
+            .row
+            .clamp(self.viewport.row_offset, last_visible.min(max_offset));
+        self.clamp_cursor();
+    }
+
+    /// Starts a charwise Visual selection anchored at the cursor.
+    pub fn start_visual(&mut self) {
+        self.mode = Mode::Visual;
+        self.visual_anchor = Some(self.cursor);
+    }
+
+    /// Leaves Visual mode, remembering the selection in the `<` and `>`
+    /// marks.
+    pub fn stop_visual(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            self.marks.insert('<', start);
+            let last = Cursor {
+                col: end.col.saturating_sub(1),
+                ..end
+            };
+            self.marks.insert('>', last.max(start));
+        }
+        if self.mode == Mode::Visual {
+            self.mode = Mode::Normal;
+        }
+        self.visual_anchor = None;
+    }
+
+    /// The Visual selection as a start and an exclusive end, both clamped to
+    /// their lines.
+    pub fn selection(&self) -> Option<(Cursor, Cursor)> {
+        let anchor = self.visual_anchor?;
+        let (start, mut end) = if anchor <= self.cursor {
+            (anchor, self.cursor)
+        } else {
+            (self.cursor, anchor)
+        };
+        let end_len = self
+            .buffer
+            .lines
+            .get(end.row)
+            .map_or(0, |l| l.chars().count());
+        end.col = (end.col + 1).min(end_len);
+        Some((start, end))
+    }
+
+    /// Deletes the text from `start` up to (not including) `end`, leaves
+    /// the cursor at `start` and returns the deleted text.
+    pub fn delete_range(&mut self, start: Cursor, end: Cursor) -> String {
+        if start >= end || end.row >= self.buffer.lines.len() {
+            return String::new();
+        }
+        let start = self.buffer.clamp_position(start);
+        let end = self.buffer.clamp_position(end).max(start);
+        let removed = self.buffer.delete_range(start, end);
+        self.note_edit(Delta {
+            start,
+            end,
+            old_text: removed.clone(),
+            new_text: String::new(),
+        });
+        self.cursor = start;
+        self.clamp_cursor();
+        self.mark_changed();
+        self.ensure_cursor_visible();
+        removed
+    }
+
+    /// The text from `start` up to (not including) `end`, with line breaks
+    /// between rows.
+    pub fn text_in_range(&self, start: Cursor, end: Cursor) -> String {
+        self.buffer.text_in_range(start, end)
+    }
+
+    /// Deletes rows `first..=last`, keeping at least one (empty) line, and
+    /// moves the cursor to the start of the line that took their place.
+    pub fn delete_lines(&mut self, first: usize, last: usize) {
+        let last = last.min(self.buffer.lines.len().saturating_sub(1));
+        if first > last {
+            return;
+        }
+        self.splice_lines(first, last + 1, &[]);
+        self.cursor = Cursor {
+            row: first.min(self.buffer.lines.len() - 1),
+            col: 0,
+        };
+        self.mark_changed();
+        self.ensure_cursor_visible();
+    }
+
+    /// Inserts `lines` before row `row` (or after the last row when `row` is
+    /// the line count) and moves the cursor to the first of them.
+    pub fn insert_lines(&mut self, row: usize, lines: Vec<String>) {
+        let row = row.min(self.buffer.lines.len());
+        if lines.is_empty() {
+            return;
+        }
+        self.splice_lines(row, row, &lines);
+        self.cursor = Cursor { row, col: 0 };
+        self.mark_changed();
+        self.ensure_cursor_visible();
+    }
+
+    /// Replaces the text from `start` up to `end` with `text`, which may span
+    /// lines, and leaves the cursor at `start`.
+    pub fn replace_range(&mut self, start: Cursor, end: Cursor, text: &str) {
+        if end.row >= self.buffer.lines.len() || end < start || (start == end && text.is_empty()) {
+            return;
+        }
+        self.edit_range(start, end, text);
+        self.cursor = start;
+        self.mark_changed();
+        self.ensure_cursor_visible();
+    }
+
+    /// Inserts `text`, which may span lines, at the cursor as one change and
+    /// leaves the cursor after it, as typing it would.
+    pub fn insert_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.cursor.row >= self.buffer.lines.len() {
+            self.buffer.lines.push(String::new());
         }
+        self.cursor = self.edit_range(self.cursor, self.cursor, text).new_end();
+        self.mark_changed();
         self.ensure_cursor_visible();
     }
 
-    pub fn move_down(&mut self) {
-        if self.cursor.row + 1 < self.buffer.lines.len() {
-            self.cursor.row += 1;
-            self.clamp_cursor();
-        }
-        self.ensure_cursor_visible();
+    /// Replaces the text from `start` up to `end` in the buffer, moves the
+    /// marks after it along with the text and records the edit in `deltas`.
+    /// The caller places the cursor and flags the change.
+    fn edit_range(&mut self, start: Cursor, end: Cursor, text: &str) -> &Delta {
+        let delta = self.buffer.replace_range(start, end, text);
+        self.note_edit(delta)
     }
 
-    pub fn move_line_start(&mut self) {
-        self.cursor.col = 0;
-        self.ensure_cursor_visible();
+    /// Moves the marks for an edit already made to the buffer and records it.
+    fn note_edit(&mut self, delta: Delta) -> &Delta {
+        self.adjust_marks(delta.start, delta.end, delta.new_end());
+        self.deltas.record(delta)
     }
 
-    pub fn move_line_end(&mut self) {
-        self.cursor.col = self.current_line_len();
-        self.ensure_cursor_visible();
+    /// Replaces rows `first..end` with `lines` as one edit, leaving an
+    /// empty line if they were all there was.
+    fn splice_lines(&mut self, first: usize, end: usize, lines: &[String]) {
+        let len = self.buffer.lines.len();
+        let end = end.min(len);
+        let first = first.min(end);
+        let line_end = |row: usize| Cursor {
+            row,
+            col: self.buffer.lines[row].chars().count(),
+        };
+        let (start, stop, text) = if end < len {
+            // Whole rows, each with the line break after it.
+            let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+            (
+                Cursor { row: first, col: 0 },
+                Cursor { row: end, col: 0 },
+                text,
+            )
+        } else if first > 0 {
+            // Through the last row, which has no line break after it: take
+            // the one before the rows instead.
+            let text: String = lines.iter().map(|line| format!("\n{}", line)).collect();
+            (line_end(first - 1), line_end(len - 1), text)
+        } else {
+            (
+                Cursor { row: 0, col: 0 },
+                line_end(len - 1),
+                lines.join("\n"),
+            )
+        };
+        self.edit_range(start, stop, &text);
     }
 
-    pub fn insert_char(&mut self, ch: char) {
-        if self.cursor.row >= self.buffer.lines.len() {
-            self.buffer.lines.push(String::new());
+    /// Replaces row `row` with `text`, editing only the part that differs.
+    fn replace_line(&mut self, row: usize, text: &str) {
+        let line = &self.buffer.lines[row];
+        let prefix = line
+            .chars()
+            .zip(text.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old: Vec<char> = line.chars().skip(prefix).collect();
+        let new: Vec<char> = text.chars().skip(prefix).collect();
+        let suffix = old
+            .iter()
+            .rev()
+            .zip(new.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let middle: String = new[..new.len() - suffix].iter().collect();
+        self.edit_range(
+            Cursor { row, col: prefix },
+            Cursor {
+                row,
+                col: prefix + old.len() - suffix,
+            },
+            &middle,
+        );
+    }
+
+    /// Moves the lowercase marks, and the uppercase ones in this file, as
+    /// the text from `start` to `end` became text ending at `new_end`.
+    fn adjust_marks(&mut self, start: Cursor, end: Cursor, new_end: Cursor) {
+        for mark in self.marks.values_mut() {
+            *mark = adjust_position(*mark, start, end, new_end);
+        }
+        let Some(open) = &self.file_path else {
+            return;
+        };
+        for mark in self.global_marks.values_mut() {
+            if mark.path.as_ref().is_some_and(|path| same_file(path, open)) {
+                mark.cursor = adjust_position(mark.cursor, start, end, new_end);
+            }
         }
-        let line = &mut self.buffer.lines[self.cursor.row];
-        let byte_idx = Self::char_to_byte_index(line, self.cursor.col);
-        line.insert(byte_idx, ch);
-        self.cursor.col += 1;
-        self.dirty = true;
-        self.bump_revision();
-        self.ensure_cursor_visible();
+    }
+
+    pub fn insert_char(&mut self, ch: char) {
+        self.insert_text(ch.encode_utf8(&mut [0; 4]));
     }
 
     pub fn insert_newline(&mut self) {
-        if self.cursor.row >= self.buffer.lines.len() {
-            self.buffer.lines.push(String::new());
-        }
-        let line = &mut self.buffer.lines[self.cursor.row];
-        let byte_idx = Self::char_to_byte_index(line, self.cursor.col);
-        let new_line = line.split_off(byte_idx);
-        self.buffer.lines.insert(self.cursor.row + 1, new_line);
-        self.cursor.row += 1;
-        self.cursor.col = 0;
-        self.dirty = true;
-        self.bump_revision();
-        self.ensure_cursor_visible();
+        self.insert_text("\n");
     }
 
     pub fn backspace(&mut self) {
         if self.cursor.row >= self.buffer.lines.len() {
             return;
         }
-        if self.cursor.col > 0 {
-            let line = &mut self.buffer.lines[self.cursor.row];
-            let remove_col = self.cursor.col - 1;
-            let byte_idx = Self::char_to_byte_index(line, remove_col);
-            line.remove(byte_idx);
-            self.cursor.col -= 1;
-            self.dirty = true;
-            self.bump_revision();
+        let start = if self.cursor.col > 0 {
+            let line = &self.buffer.lines[self.cursor.row];
+            Cursor {
+                row: self.cursor.row,
+                col: prev_grapheme_boundary(line, self.cursor.col),
+            }
         } else if self.cursor.row > 0 {
-            let current = self.buffer.lines.remove(self.cursor.row);
-            self.cursor.row -= 1;
-            let line = &mut self.buffer.lines[self.cursor.row];
-            let prev_len = line.len();
-            line.push_str(&current);
-            self.cursor.col = prev_len;
-            self.dirty = true;
-            self.bump_revision();
-        }
-        self.ensure_cursor_visible();
+            let row = self.cursor.row - 1;
+            Cursor {
+                row,
+                col: self.buffer.lines[row].chars().count(),
+            }
+        } else {
+            return;
+        };
+        self.delete_range(start, self.cursor);
     }
 
     pub fn delete_char(&mut self) {
         if self.cursor.row >= self.buffer.lines.len() {
             return;
         }
-        let line_len = self.current_line_len();
-        if self.cursor.col < line_len {
-            let line = &mut self.buffer.lines[self.cursor.row];
-            let byte_idx = Self::char_to_byte_index(line, self.cursor.col);
-            line.remove(byte_idx);
-            self.dirty = true;
-            self.bump_revision();
+        let end = if self.cursor.col < self.current_line_len() {
+            let line = &self.buffer.lines[self.cursor.row];
+            Cursor {
+                row: self.cursor.row,
+                col: next_grapheme_boundary(line, self.cursor.col),
+            }
         } else if self.cursor.row + 1 < self.buffer.lines.len() {
-            let next = self.buffer.lines.remove(self.cursor.row + 1);
-            let line = &mut self.buffer.lines[self.cursor.row];
-            line.push_str(&next);
-            self.dirty = true;
-            self.bump_revision();
+            Cursor {
+                row: self.cursor.row + 1,
+                col: 0,
+            }
+        } else {
+            return;
+        };
+        let cursor = self.cursor;
+        self.delete_range(cursor, end);
+    }
+
+    /// Starts a batch of edits that share a single revision bump.
+    pub fn begin_batch(&mut self) {
+        self.batch_depth += 1;
+    }
+
+    /// Ends a batch, bumping the revision once if anything changed inside it.
+    pub fn end_batch(&mut self) {
+        self.batch_depth = self.batch_depth.saturating_sub(1);
+        if self.batch_depth == 0 && self.batch_changed {
+            self.batch_changed = false;
+            self.deltas.commit();
         }
-        self.ensure_cursor_visible();
     }
 
-    fn char_to_byte_index(line: &str, char_index: usize) -> usize {
-        if char_index == 0 {
-            return 0;
+    /// Forgets the undo history, starting over from the current buffer.
+    pub fn reset_undo(&mut self) {
+        self.undo_history = UndoHistory::new(&self.buffer.lines);
+        self.undo_pending = false;
+    }
+
+    /// Records the changes made since the last call as one undo step.
+    pub fn commit_undo(&mut self) {
+        if self.undo_pending {
+            self.undo_pending = false;
+            self.undo_history.commit(&self.buffer.lines);
+        }
+    }
+
+    /// Reverts the last undo step. Returns false if there is none.
+    pub fn undo(&mut self) -> bool {
+        self.commit_undo();
+        let Some(change) = self.undo_history.undo() else {
+            return false;
+        };
+        let (start, col) = (change.start, change.column());
+        let end = start + change.after.len();
+        let lines = change.before.clone();
+        self.splice_lines(start, end, &lines);
+        self.restored(start, col);
+        true
+    }
+
+    /// Reapplies the last undone step. Returns false if there is none.
+    pub fn redo(&mut self) -> bool {
+        self.commit_undo();
+        let Some(change) = self.undo_history.redo() else {
+            return false;
+        };
+        let (start, col) = (change.start, change.column());
+        let end = start + change.before.len();
+        let lines = change.after.clone();
+        self.splice_lines(start, end, &lines);
+        self.restored(start, col);
+        true
+    }
+
+    /// Undoes (`back`) or redoes changes by `span`, as `:earlier` and
+    /// `:later` do. Returns how many steps were taken.
+    pub fn travel(&mut self, span: UndoSpan, back: bool) -> usize {
+        self.commit_undo();
+        let steps = match span {
+            UndoSpan::Steps(steps) => steps,
+            UndoSpan::Time(span) if back => self.undo_history.steps_back_within(span),
+            UndoSpan::Time(span) => self.undo_history.steps_forward_within(span),
+        };
+        let mut taken = 0;
+        while taken < steps && if back { self.undo() } else { self.redo() } {
+            taken += 1;
         }
-        line.char_indices()
-            .nth(char_index)
-            .map(|(idx, _)| idx)
-            .unwrap_or_else(|| line.len())
+        taken
+    }
+
+    /// Tidies up after undo or redo replaced rows from `row`.
+    fn restored(&mut self, row: usize, col: usize) {
+        self.dirty = !self.is_scratch_buffer();
+        self.bump_revision();
+        self.goto_position(row, col);
+    }
+
+    /// The number of the buffer's latest revision; see `deltas`.
+    pub fn revision(&self) -> u64 {
+        self.deltas.revision()
+    }
+
+    /// Flags the buffer as modified and records the cursor in the changelist.
+    fn mark_changed(&mut self) {
+        self.undo_pending = true;
+        self.dirty = !self.is_scratch_buffer();
+        self.bump_revision();
+        self.changes.record(self.cursor);
     }
 
     fn bump_revision(&mut self) {
-        self.revision = self.revision.wrapping_add(1);
+        if self.batch_depth > 0 {
+            self.batch_changed = true;
+        } else {
+            self.deltas.commit();
+        }
+    }
+}
+
+/// Reads `file` as lines split on `\n`, handing batches to `send` along with the
+/// number of bytes they covered. Stops early if `send` returns false. Returns
+/// whether the last line ended with a newline.
+fn read_lines_in_chunks(
+    file: File,
+    mut send: impl FnMut(Vec<String>, u64) -> bool,
+) -> io::Result<bool> {
+    let mut reader = BufReader::new(file);
+    let mut batch = Vec::with_capacity(LOAD_CHUNK_LINES);
+    let mut batch_bytes = 0u64;
+    let mut raw = Vec::new();
+    let mut ends_with_newline = false;
+
+    loop {
+        raw.clear();
+        let read = reader.read_until(b'\n', &mut raw)?;
+        if read == 0 {
+            break;
+        }
+        batch_bytes += read as u64;
+        ends_with_newline = raw.last() == Some(&b'\n');
+        if ends_with_newline {
+            raw.pop();
+        }
+        let line = String::from_utf8(std::mem::take(&mut raw))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        batch.push(line);
+        if batch.len() >= LOAD_CHUNK_LINES {
+            if !send(std::mem::take(&mut batch), batch_bytes) {
+                return Ok(ends_with_newline);
+            }
+            batch_bytes = 0;
+        }
     }
+
+    send(batch, batch_bytes);
+    Ok(ends_with_newline)
 }
 
 /// Result of handling an input event.
@@ -354,6 +3123,32 @@ pub trait Plugin {
     }
 
     fn on_render(&mut self, _editor: &Editor, _ctx: &mut RenderContext) {}
+
+    /// Does a slice of deferred work while no input is waiting. Returns true
+    /// if it did any, so the screen is redrawn and the plugin called again.
+    fn on_idle(&mut self, _editor: &Editor) -> bool {
+        false
+    }
+
+    /// The ex commands this plugin handles, listed by the command palette.
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[]
+    }
+
+    /// The key bindings this plugin handles, listed by the command palette.
+    fn keys(&self) -> &'static [KeyInfo] {
+        &[]
+    }
+
+    /// The autocommand events this plugin handles in `on_autocmd`.
+    fn events(&self) -> &'static [AutoEvent] {
+        &[]
+    }
+
+    /// Handles an event the plugin listed in `events`, or any event for a
+    /// plugin that runs the user's autocommands. `name` is what patterns
+    /// match: the file path, or `old:new` for `ModeChanged`.
+    fn on_autocmd(&mut self, _editor: &mut Editor, _event: AutoEvent, _name: &str) {}
 }
 
 /// Render buffer used by plugins to draw UI content.
@@ -398,12 +3193,155 @@ impl RenderContext {
         self.spans[row_index] = spans;
     }
 
+    /// Adds a span drawn over the ones already set for `row`.
+    pub fn add_span(&mut self, row: u16, span: StyledSpan) {
+        if let Some(spans) = self.spans.get_mut(row as usize) {
+            spans.push(span);
+        }
+    }
+
+    /// The style column `col` of `row` would be drawn with: that of the last
+    /// span covering it.
+    pub fn style_at(&self, row: u16, col: usize) -> ContentStyle {
+        self.spans
+            .get(row as usize)
+            .and_then(|spans| {
+                spans
+                    .iter()
+                    .rev()
+                    .find(|span| (span.start..span.start + span.len).contains(&col))
+            })
+            .map_or_else(ContentStyle::new, |span| span.style)
+    }
+
     pub fn set_cursor(&mut self, row: u16, col: u16) {
         self.cursor = Some((row, col));
     }
+
+    /// Draws `text` over `row` from display column `col`, replacing the
+    /// cells it covers and the styles over them. Half of a wide character
+    /// left at either edge becomes a space.
+    pub fn draw_text(&mut self, row: u16, col: usize, text: &str, style: ContentStyle) {
+        let Some(line) = self.lines.get(row as usize) else {
+            return;
+        };
+        let end = col + display_width(text);
+        let mut before = String::new();
+        let mut after = String::new();
+        let mut width = 0;
+        for grapheme in line.graphemes(true) {
+            let next = width + grapheme_width(grapheme);
+            if next <= col {
+                before.push_str(grapheme);
+            } else if width < col {
+                before.push_str(&" ".repeat(col - width));
+                if next > end {
+                    after.push_str(&" ".repeat(next - end));
+                }
+            } else if width >= end {
+                after.push_str(grapheme);
+            } else if next > end {
+                after.push_str(&" ".repeat(next - end));
+            }
+            width = next;
+        }
+        before.push_str(&" ".repeat(col.saturating_sub(width)));
+        before.push_str(text);
+        before.push_str(&after);
+        self.set_line(row, before);
+
+        let spans = &mut self.spans[row as usize];
+        let mut clipped = Vec::with_capacity(spans.len() + 2);
+        for span in spans.drain(..) {
+            let span_end = span.start + span.len;
+            if span.start < col {
+                clipped.push(StyledSpan {
+                    len: span_end.min(col) - span.start,
+                    ..span
+                });
+            }
+            if span_end > end {
+                let start = span.start.max(end);
+                clipped.push(StyledSpan {
+                    start,
+                    len: span_end - start,
+                    ..span
+                });
+            }
+        }
+        clipped.push(StyledSpan {
+            start: col,
+            len: end - col,
+            style,
+        });
+        *spans = clipped;
+    }
+
+    /// Inserts `text` into `row` at display column `col`, padding the line
+    /// with spaces to reach it. Spans and the cursor at or after `col` move
+    /// right by the text's width; a span crossing `col` is split around it.
+    pub fn insert_text(&mut self, row: u16, col: usize, text: &str, style: ContentStyle) {
+        let Some(line) = self.lines.get(row as usize) else {
+            return;
+        };
+        let mut width = 0;
+        let mut split = line.len();
+        for (byte, grapheme) in line.grapheme_indices(true) {
+            if width >= col {
+                split = byte;
+                break;
+            }
+            width += grapheme_width(grapheme);
+        }
+        let inserted = display_width(text);
+        let mut new_line = line[..split].to_string();
+        new_line.push_str(&" ".repeat(col.saturating_sub(width)));
+        new_line.push_str(text);
+        new_line.push_str(&line[split..]);
+        // A wide character covering `col` keeps the text after it.
+        let col = col.max(width);
+        self.set_line(row, new_line);
+
+        let spans = &mut self.spans[row as usize];
+        let mut shifted = Vec::with_capacity(spans.len() + 2);
+        for span in spans.drain(..) {
+            if span.start >= col {
+                shifted.push(StyledSpan {
+                    start: span.start + inserted,
+                    ..span
+                });
+            } else if span.start + span.len > col {
+                shifted.push(StyledSpan {
+                    len: col - span.start,
+                    ..span
+                });
+                shifted.push(StyledSpan {
+                    start: col + inserted,
+                    len: span.start + span.len - col,
+                    ..span
+                });
+            } else {
+                shifted.push(span);
+            }
+        }
+        shifted.push(StyledSpan {
+            start: col,
+            len: inserted,
+            style,
+        });
+        *spans = shifted;
+
+        if let Some((cursor_row, cursor_col)) = &mut self.cursor
+            && *cursor_row == row
+            && *cursor_col as usize >= col
+        {
+            let moved = *cursor_col as usize + inserted;
+            *cursor_col = moved.min(self.width.saturating_sub(1) as usize) as u16;
+        }
+    }
 }
 
-/// Styled span in a rendered line.
+/// Styled span in a rendered line, measured in display columns.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct StyledSpan {
     pub start: usize,
@@ -421,6 +3359,90 @@ mod tests {
         assert_eq!(buffer.lines, vec!["a", "b", ""]);
     }
 
+    #[test]
+    fn emergency_save_skips_encrypted_files_and_keeps_copies_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = Editor::new(80, 24, Some(dir.path().join("secret.txt.age")));
+        editor.state_dir = Some(dir.path().join("state"));
+        editor.buffer.lines[0] = "hunter2".to_string();
+        editor.dirty = true;
+        assert_eq!(editor.emergency_save().unwrap(), Vec::<PathBuf>::new());
+        assert!(!dir.path().join("state").exists());
+
+        editor.file_path = Some(dir.path().join("notes.txt"));
+        let saved = editor.emergency_save().unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(fs::read_to_string(&saved[0]).unwrap(), "hunter2\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&saved[0]), 0o600);
+            assert_eq!(mode(&dir.path().join("state").join(RECOVERY_DIR)), 0o700);
+        }
+    }
+
+    #[test]
+    fn changing_directory_leaves_remote_names_alone() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("scp://host/notes.txt")));
+        editor.alternate_file = Some(PathBuf::from("local.txt"));
+        editor.jumps.push(Jump {
+            path: Some(PathBuf::from("https://example.com/a.txt")),
+            cursor: Cursor { row: 0, col: 0 },
+        });
+        // The same directory, so other tests keep theirs.
+        let dir = env::current_dir().unwrap();
+        editor.change_dir(&dir).unwrap();
+        assert_eq!(
+            editor.file_path,
+            Some(PathBuf::from("scp://host/notes.txt"))
+        );
+        assert_eq!(editor.alternate_file, Some(dir.join("local.txt")));
+        assert_eq!(
+            editor.jumps.entries[0].path,
+            Some(PathBuf::from("https://example.com/a.txt"))
+        );
+    }
+
+    #[test]
+    fn files_keep_or_fix_their_final_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("noeol.txt");
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        let round_trip = |editor: &mut Editor, contents: &str| {
+            fs::write(&path, contents).unwrap();
+            editor.load_from_path(&path).unwrap();
+            editor.save_to_path(&path, false).unwrap();
+            fs::read_to_string(&path).unwrap()
+        };
+        assert_eq!(round_trip(&mut editor, "a\nb\n"), "a\nb\n");
+        assert_eq!(editor.buffer.lines, ["a", "b"]);
+        assert_eq!(round_trip(&mut editor, ""), "");
+        assert_eq!(round_trip(&mut editor, "\n"), "\n");
+        assert_eq!(round_trip(&mut editor, "a\nb"), "a\nb\n");
+        assert!(!editor.options.endofline);
+        editor.options.fixendofline = false;
+        assert_eq!(round_trip(&mut editor, "a\nb"), "a\nb");
+        editor.options.apply("eol").unwrap();
+        assert_eq!(editor.file_contents(), "a\nb\n");
+    }
+
+    #[test]
+    fn compressed_files_are_edited_as_text_and_written_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log.gz");
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        editor.buffer.lines = vec!["first".to_string(), "second".to_string()];
+        editor.save_to_path(&path, false).unwrap();
+        assert!(fs::read(&path).unwrap().starts_with(&[0x1f, 0x8b]));
+
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        editor.start_loading(&path).unwrap();
+        assert!(!editor.is_loading());
+        assert_eq!(editor.buffer.lines, ["first", "second"]);
+        assert_eq!(editor.file_contents(), "first\nsecond\n");
+    }
+
     #[test]
     fn insert_newline_splits_line() {
         let mut editor = Editor::new(80, 24, None);
@@ -457,21 +3479,511 @@ mod tests {
         assert_eq!(editor.cursor.col, 2);
     }
 
+    #[test]
+    fn replace_range_spans_lines() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["one two".to_string(), "three".to_string()];
+        let start = Cursor { row: 0, col: 4 };
+        editor.replace_range(start, Cursor { row: 1, col: 2 }, "2\nTH");
+        assert_eq!(editor.buffer.lines, vec!["one 2", "THree"]);
+        assert_eq!(editor.cursor, start);
+        let revision = editor.revision();
+        editor.replace_range(start, start, "(");
+        assert_eq!(editor.buffer.lines[0], "one (2");
+        assert_eq!(editor.revision(), revision + 1);
+    }
+
+    #[test]
+    fn buffer_ranges_span_lines_and_clamp() {
+        let mut buffer = Buffer::from_string("one two\nthree\nfour".to_string());
+        let at = |row, col| Cursor { row, col };
+        assert_eq!(buffer.delete_range(at(0, 3), at(1, 2)), " two\nth");
+        assert_eq!(buffer.lines, ["oneree", "four"]);
+        let delta = buffer.replace_range(at(0, 3), at(0, 3), "\nx\ny");
+        assert_eq!(delta.new_end(), at(2, 1));
+        assert_eq!(buffer.lines, ["one", "x", "yree", "four"]);
+        let delta = buffer.replace_range(at(3, 2), at(9, 0), "ür");
+        assert_eq!((delta.end, delta.old_text.as_str()), (at(3, 4), "ur"));
+        assert_eq!(delta.new_end(), at(3, 4));
+        assert_eq!(buffer.lines, ["one", "x", "yree", "foür"]);
+        assert_eq!(buffer.delete_range(at(0, 99), at(2, 1)), "\nx\ny");
+        assert_eq!(buffer.lines, ["oneree", "foür"]);
+    }
+
+    #[test]
+    fn marks_follow_the_text_around_edits() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["alpha beta".to_string(), "gamma".to_string()];
+        let at = |row, col| Cursor { row, col };
+        editor.marks.insert('a', at(0, 6));
+        editor.marks.insert('b', at(1, 2));
+        editor.marks.insert('c', at(0, 1));
+        editor.replace_range(at(0, 0), at(0, 5), "one\ntwo");
+        assert_eq!(editor.marks[&'a'], at(1, 4));
+        assert_eq!(editor.marks[&'b'], at(2, 2));
+        assert_eq!(editor.marks[&'c'], at(0, 0));
+        editor.cursor = at(0, 3);
+        editor.delete_char();
+        assert_eq!(editor.buffer.lines, ["onetwo beta", "gamma"]);
+        assert_eq!(editor.marks[&'a'], at(0, 7));
+        assert_eq!(editor.marks[&'b'], at(1, 2));
+        editor.insert_lines(0, vec!["zero".to_string()]);
+        assert_eq!(editor.marks[&'b'], at(2, 2));
+        editor.delete_lines(0, 1);
+        assert_eq!(editor.marks[&'a'], at(0, 0));
+        assert_eq!(editor.marks[&'b'], at(0, 2));
+    }
+
+    #[test]
+    fn deltas_replay_each_revision_of_the_buffer() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["fn main() {".to_string(), "}".to_string()];
+        editor.reset_undo();
+        let mut copy = editor.buffer.clone();
+        let mut seen = editor.revision();
+        // Applies the deltas since `seen` to `copy`, which then matches.
+        fn replay(editor: &Editor, copy: &mut Buffer, seen: &mut u64) {
+            let deltas: Vec<Delta> = editor.deltas.since(*seen).unwrap().cloned().collect();
+            assert!(!deltas.is_empty());
+            for delta in deltas {
+                assert_eq!(copy.text_in_range(delta.start, delta.end), delta.old_text);
+                copy.replace_range(delta.start, delta.end, &delta.new_text);
+            }
+            assert_eq!(copy.lines, editor.buffer.lines);
+            *seen = editor.revision();
+        }
+        editor.cursor = Cursor { row: 0, col: 11 };
+        editor.insert_newline();
+        editor.insert_text("    let x = 1;");
+        replay(&editor, &mut copy, &mut seen);
+        editor.toggle_comment(0, 2);
+        replay(&editor, &mut copy, &mut seen);
+        editor.delete_lines(2, 2);
+        replay(&editor, &mut copy, &mut seen);
+        editor.commit_undo();
+        editor.undo();
+        replay(&editor, &mut copy, &mut seen);
+        editor.begin_batch();
+        editor.replace_range(Cursor { row: 1, col: 0 }, Cursor { row: 1, col: 2 }, "");
+        editor.insert_lines(0, vec!["use std::io;".to_string()]);
+        editor.end_batch();
+        assert_eq!(editor.deltas.since(seen).unwrap().count(), 2);
+        assert_eq!(editor.revision(), seen + 1);
+        replay(&editor, &mut copy, &mut seen);
+    }
+
+    #[test]
+    fn insert_text_is_one_change_ending_after_the_text() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["abcd".to_string()];
+        editor.reset_undo();
+        editor.cursor.col = 2;
+        editor.insert_text("é1");
+        assert_eq!(editor.buffer.lines, ["abé1cd"]);
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 4 });
+        let revision = editor.revision();
+        editor.insert_text("x\n\ny");
+        assert_eq!(editor.buffer.lines, ["abé1x", "", "ycd"]);
+        assert_eq!(editor.cursor, Cursor { row: 2, col: 1 });
+        assert_eq!(editor.revision(), revision + 1);
+        editor.undo();
+        assert_eq!(editor.buffer.lines, ["abcd"]);
+    }
+
     #[test]
     fn revision_increments_on_edits() {
         let mut editor = Editor::new(80, 24, None);
-        assert_eq!(editor.revision, 0);
+        assert_eq!(editor.revision(), 0);
         editor.insert_char('a');
-        let after_insert = editor.revision;
+        let after_insert = editor.revision();
         editor.insert_newline();
-        let after_newline = editor.revision;
+        let after_newline = editor.revision();
         editor.backspace();
-        let after_backspace = editor.revision;
+        let after_backspace = editor.revision();
         assert!(after_insert > 0);
         assert!(after_newline > after_insert);
         assert!(after_backspace > after_newline);
     }
 
+    #[test]
+    fn word_under_cursor_finds_enclosing_word() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["let foo_bar = 1;".to_string()];
+        editor.cursor.col = 6;
+        assert_eq!(editor.word_under_cursor().as_deref(), Some("foo_bar"));
+        editor.cursor.col = 3;
+        assert_eq!(editor.word_under_cursor().as_deref(), Some("foo_bar"));
+        editor.cursor.col = 15;
+        assert_eq!(editor.word_under_cursor(), None);
+    }
+
+    #[test]
+    fn options_apply_sets_and_queries() {
+        let mut options = Options::new();
+        assert_eq!(options.apply("keywordprg=pydoc"), Ok(None));
+        assert_eq!(options.keywordprg.as_deref(), Some("pydoc"));
+        assert_eq!(options.apply("kp?"), Ok(Some("kp=pydoc".to_string())));
+        assert!(options.apply("bogus=1").is_err());
+        assert_eq!(options.apply("ts=4"), Ok(None));
+        assert_eq!(options.apply("tabstop"), Ok(Some("tabstop=4".to_string())));
+        assert_eq!(options.apply("noet"), Ok(None));
+        assert_eq!(options.apply("et?"), Ok(Some("noet".to_string())));
+        assert_eq!(options.apply("invexpandtab"), Ok(None));
+        assert!(options.expandtab);
+        assert!(options.apply("et=1").is_err());
+        assert!(options.apply("ts=0").is_err());
+        assert!(options.apply("ts=wide").is_err());
+    }
+
+    #[test]
+    fn batch_bumps_revision_once() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.begin_batch();
+        for ch in "hello".chars() {
+            editor.insert_char(ch);
+        }
+        editor.insert_newline();
+        assert_eq!(editor.revision(), 0);
+        editor.end_batch();
+        assert_eq!(editor.revision(), 1);
+        assert_eq!(editor.buffer.lines, vec!["hello", ""]);
+
+        editor.begin_batch();
+        editor.end_batch();
+        assert_eq!(editor.revision(), 1);
+    }
+
+    #[test]
+    fn start_loading_reads_large_files_in_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.txt");
+        let lines: Vec<String> = (0..40_000).map(|n| format!("line number {}", n)).collect();
+        let contents = format!("{}\n", lines.join("\n"));
+        assert!(contents.len() as u64 >= ASYNC_LOAD_THRESHOLD);
+        fs::write(&path, &contents).unwrap();
+
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        editor.start_loading(&path).unwrap();
+        assert!(editor.is_loading());
+        while editor.is_loading() {
+            editor.poll_loading();
+            thread::yield_now();
+        }
+
+        assert_eq!(editor.buffer.lines.len(), 40_000);
+        assert_eq!(editor.file_contents(), contents);
+        assert_eq!(editor.load_progress(), None);
+        assert!(!editor.dirty);
+    }
+
+    #[test]
+    fn set_values_round_trip_through_escaping() {
+        let mut options = Options::new();
+        options.keywordprg = Some("rustup doc --std".to_string());
+        let commands = options.to_commands();
+        assert_eq!(commands, vec!["set keywordprg=rustup\\ doc\\ --std"]);
+
+        let args = split_set_args(commands[0].trim_start_matches("set "));
+        let mut restored = Options::new();
+        restored.apply(&args[0]).unwrap();
+        assert_eq!(restored.keywordprg.as_deref(), Some("rustup doc --std"));
+    }
+
+    #[test]
+    fn parse_script_skips_comments_and_blanks() {
+        let commands = parse_script("\" setup\n:set kp=man\n\n  w out.txt\n");
+        assert_eq!(commands, vec!["set kp=man", "w out.txt"]);
+    }
+
+    #[test]
+    fn history_recall_filters_on_typed_prefix() {
+        let mut history = History::new();
+        for entry in ["w", "set kp=man", "wq", "w other.txt"] {
+            history.push(entry);
+        }
+        assert_eq!(history.previous("w").as_deref(), Some("w other.txt"));
+        assert_eq!(history.previous("ignored").as_deref(), Some("wq"));
+        assert_eq!(history.previous("").as_deref(), Some("w"));
+        assert_eq!(history.previous(""), None);
+        assert_eq!(history.next().as_deref(), Some("wq"));
+        assert_eq!(history.next().as_deref(), Some("w other.txt"));
+        assert_eq!(history.next().as_deref(), Some("w"));
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn history_push_dedupes_and_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/history");
+        let mut history = History::new();
+        history.push("w");
+        history.push("q");
+        history.push("w");
+        assert_eq!(history.entries, vec!["q", "w"]);
+        history.save(&path).unwrap();
+        assert_eq!(History::load(&path).unwrap().entries, vec!["q", "w"]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn find_pattern_wraps_in_both_directions() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec![
+            "foo bar foo".to_string(),
+            "baz".to_string(),
+            "é foo".to_string(),
+        ];
+        let at = |row, col| Cursor { row, col };
+        let found = |c: Option<Cursor>| c.map(|c| (c.row, c.col));
+        let foo = editor.compile_pattern("foo").unwrap();
+
+        assert_eq!(
+            found(editor.find_pattern(&foo, at(0, 0), true)),
+            Some((0, 8))
+        );
+        assert_eq!(
+            found(editor.find_pattern(&foo, at(0, 8), true)),
+            Some((2, 2))
+        );
+        assert_eq!(
+            found(editor.find_pattern(&foo, at(2, 2), true)),
+            Some((0, 0))
+        );
+        assert_eq!(
+            found(editor.find_pattern(&foo, at(0, 0), false)),
+            Some((2, 2))
+        );
+        assert_eq!(
+            found(editor.find_pattern(&foo, at(0, 8), false)),
+            Some((0, 0))
+        );
+        assert_eq!(
+            found(editor.find_pattern(&editor.compile_pattern("baz").unwrap(), at(1, 0), true)),
+            Some((1, 0))
+        );
+        assert_eq!(
+            found(editor.find_pattern(&editor.compile_pattern("nope").unwrap(), at(0, 0), true)),
+            None
+        );
+
+        editor.options.wrapscan = false;
+        assert_eq!(found(editor.find_pattern(&foo, at(2, 2), true)), None);
+        assert_eq!(found(editor.find_pattern(&foo, at(0, 0), false)), None);
+        assert_eq!(
+            found(editor.find_pattern(&foo, at(2, 2), false)),
+            Some((0, 8))
+        );
+    }
+
+    #[test]
+    fn jumplist_moves_back_and_forward() {
+        let jump = |row| Jump {
+            path: None,
+            cursor: Cursor { row, col: 0 },
+        };
+        let mut jumps = JumpList::default();
+        jumps.push(jump(1));
+        jumps.push(jump(5));
+        jumps.push(jump(1));
+        assert_eq!(jumps.entries, vec![jump(5), jump(1)]);
+
+        assert_eq!(jumps.back(jump(9)), Some(jump(1)));
+        assert_eq!(jumps.back(jump(1)), Some(jump(5)));
+        assert_eq!(jumps.back(jump(5)), None);
+        assert_eq!(jumps.forward(), Some(jump(1)));
+        assert_eq!(jumps.forward(), Some(jump(9)));
+        assert_eq!(jumps.forward(), None);
+    }
+
+    #[test]
+    fn changelist_merges_edits_on_one_line() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.insert_char('a');
+        editor.insert_char('b');
+        editor.insert_newline();
+        editor.insert_newline();
+        editor.insert_char('c');
+        let rows: Vec<(usize, usize)> = editor
+            .changes
+            .entries
+            .iter()
+            .map(|c| (c.row, c.col))
+            .collect();
+        assert_eq!(rows, vec![(0, 2), (1, 0), (2, 1)]);
+
+        assert_eq!(editor.changes.older().map(|c| c.row), Some(2));
+        assert_eq!(editor.changes.older().map(|c| c.row), Some(1));
+        assert_eq!(editor.changes.older().map(|c| c.row), Some(0));
+        assert_eq!(editor.changes.older(), None);
+        assert_eq!(editor.changes.newer().map(|c| c.row), Some(1));
+    }
+
+    #[test]
+    fn selection_deletes_across_lines() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["hello".to_string(), "big".to_string(), "world".to_string()];
+        editor.cursor = Cursor { row: 2, col: 1 };
+        editor.start_visual();
+        editor.cursor = Cursor { row: 0, col: 3 };
+        let (start, end) = editor.selection().unwrap();
+        assert_eq!(
+            (start, end),
+            (Cursor { row: 0, col: 3 }, Cursor { row: 2, col: 2 })
+        );
+        editor.delete_range(start, end);
+        assert_eq!(editor.buffer.lines, vec!["helrld"]);
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 3 });
+    }
+
+    #[test]
+    fn scroll_viewport_drags_cursor_along() {
+        let mut editor = Editor::new(80, 6, None);
+        editor.buffer.lines = (0..20).map(|n| n.to_string()).collect();
+        editor.scroll_viewport(3);
+        assert_eq!(editor.viewport.row_offset, 3);
+        assert_eq!(editor.cursor.row, 3);
+        editor.scroll_viewport(100);
+        assert_eq!(editor.viewport.row_offset, 19);
+        editor.scroll_viewport(-17);
+        assert_eq!(editor.viewport.row_offset, 2);
+        assert_eq!(editor.cursor.row, 6);
+    }
+
+    #[test]
+    fn display_columns_count_wide_characters() {
+        assert_eq!(display_width("a日本"), 5);
+        assert_eq!(display_col("a日本x", 2, 8), 3);
+        assert_eq!(char_col_at("a日本x", 2, 8), 1);
+        assert_eq!(char_col_at("a日本x", 3, 8), 2);
+        assert_eq!(char_col_at("a日本x", 9, 8), 4);
+    }
+
+    #[test]
+    fn tabs_expand_to_the_next_tabstop() {
+        assert_eq!(display_col("\tx", 1, 8), 8);
+        assert_eq!(display_col("ab\tx", 3, 4), 4);
+        assert_eq!(display_col("ab\t\tx", 4, 4), 8);
+        assert_eq!(char_col_at("ab\tx", 3, 4), 2);
+        assert_eq!(char_col_at("ab\tx", 4, 4), 3);
+    }
+
+    #[test]
+    fn editing_steps_over_whole_grapheme_clusters() {
+        let mut editor = Editor::new(80, 24, None);
+        // "e" + combining acute, then a family emoji joined with ZWJs.
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        editor.buffer.lines = vec![format!("e\u{301}{}x", family)];
+        editor.move_right();
+        assert_eq!(editor.cursor.col, 2);
+        editor.move_right();
+        assert_eq!(editor.cursor.col, 7);
+        assert_eq!(display_col(&editor.buffer.lines[0], 7, 8), 3);
+        editor.backspace();
+        assert_eq!(editor.buffer.lines[0], "e\u{301}x");
+        editor.move_left();
+        editor.delete_char();
+        assert_eq!(editor.buffer.lines[0], "x");
+    }
+
+    #[test]
+    fn backspace_joins_lines_at_char_column() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["héllo".to_string(), "world".to_string()];
+        editor.cursor = Cursor { row: 1, col: 0 };
+        editor.backspace();
+        assert_eq!(editor.buffer.lines, vec!["hélloworld"]);
+        assert_eq!(editor.cursor.col, 5);
+    }
+
+    #[test]
+    fn tab_and_backspace_follow_softtabstop() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.insert_char('a');
+        editor.insert_tab();
+        assert_eq!(editor.buffer.lines[0], "a   ");
+        editor.buffer.lines[0].clear();
+        editor.cursor.col = 0;
+        editor.insert_tab();
+        editor.insert_tab();
+        assert_eq!(editor.buffer.lines[0], "        ");
+        editor.backspace_soft_tab();
+        assert_eq!(editor.buffer.lines[0], "    ");
+
+        editor.options.expandtab = false;
+        editor.insert_tab();
+        assert_eq!(editor.buffer.lines[0], "\t");
+        editor.insert_tab();
+        assert_eq!(editor.buffer.lines[0], "\t    ");
+        editor.options.softtabstop = 0;
+        editor.insert_tab();
+        assert_eq!(editor.buffer.lines[0], "\t    \t");
+    }
+
+    #[test]
+    fn newlines_follow_smart_indent_rules() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("main.rs")));
+        editor.buffer.lines = vec!["fn main() {".to_string()];
+        editor.cursor.col = 11;
+        editor.insert_newline_indented();
+        assert_eq!(editor.buffer.lines[1], "    ");
+        editor.insert_char('x');
+        editor.insert_newline_indented();
+        editor.insert_newline_indented();
+        assert_eq!(editor.buffer.lines[2], "");
+        editor.backspace_soft_tab();
+        editor.backspace();
+        editor.insert_char('}');
+        assert!(editor.current_line_closes_block());
+        editor.reindent_line(2);
+        assert_eq!(editor.buffer.lines, vec!["fn main() {", "    x", "}"]);
+
+        editor.cursor.row = 0;
+        editor.open_line(true);
+        assert_eq!(editor.buffer.lines[1], "    ");
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 4 });
+        editor.cursor.row = 0;
+        editor.open_line(false);
+        assert_eq!(editor.buffer.lines[0], "");
+
+        editor.options.smartindent = false;
+        editor.options.autoindent = false;
+        editor.cursor = Cursor { row: 3, col: 5 };
+        editor.insert_newline_indented();
+        assert_eq!(editor.buffer.lines[4], "");
+    }
+
+    #[test]
+    fn shift_line_rounds_to_shiftwidth() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["  x".to_string(), String::new()];
+        editor.shift_line(0, 1);
+        assert_eq!(editor.buffer.lines[0], "    x");
+        assert_eq!(editor.cursor.col, 2);
+        editor.shift_line(0, -1);
+        assert_eq!(editor.buffer.lines[0], "x");
+        editor.options.expandtab = false;
+        editor.options.shiftwidth = 0;
+        editor.shift_line(0, 2);
+        assert_eq!(editor.buffer.lines[0], "\t\tx");
+        editor.shift_line(1, 1);
+        assert_eq!(editor.buffer.lines[1], "");
+    }
+
+    #[test]
+    fn horizontal_scroll_uses_display_width() {
+        let mut editor = Editor::new(6, 24, None);
+        editor.buffer.lines = vec!["日本語のテキスト".to_string()];
+        editor.goto_position(0, 3);
+        assert_eq!(editor.viewport.col_offset, 2);
+        editor.goto_position(0, 0);
+        assert_eq!(editor.viewport.col_offset, 0);
+    }
+
     #[test]
     fn clamp_cursor_trims_column() {
         let mut editor = Editor::new(80, 24, None);
@@ -0,0 +1,237 @@
+//! Line ranges in front of ex commands, such as `%`, `.,$` or `3,+2`.
+
+#[cfg(test)]
+use crate::editor::Cursor;
+use crate::editor::Editor;
+
+/// Rows `start..=end` of the buffer, zero-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A single line address: a number, `.`, `$` or `'x` for mark `x`, each
+/// optionally followed by `+N`/`-N` offsets. A bare offset counts from the
+/// cursor line.
+fn parse_address<'a>(input: &'a str, editor: &Editor) -> Result<(Option<isize>, &'a str), String> {
+    let last = editor.buffer.lines.len().saturating_sub(1) as isize;
+    let digits = |text: &str| text.chars().take_while(char::is_ascii_digit).count();
+    let (mut row, mut rest) = match input.chars().next() {
+        Some('.') => (Some(editor.cursor.row as isize), &input[1..]),
+        Some('$') => (Some(last), &input[1..]),
+        Some('\'') => {
+            let mut chars = input[1..].chars();
+            let name = chars.next().ok_or("Mark not set")?;
+            let mark = editor
+                .mark(name)
+                .ok_or_else(|| format!("Mark not set: {}", name))?;
+            (Some(mark.row as isize), chars.as_str())
+        }
+        Some(ch) if ch.is_ascii_digit() => {
+            let len = digits(input);
+            let line: isize = input[..len].parse().unwrap_or(isize::MAX);
+            (Some((line - 1).max(0)), &input[len..])
+        }
+        _ => (None, input),
+    };
+    while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+        let len = digits(&rest[1..]);
+        let amount: isize = if len == 0 {
+            1
+        } else {
+            rest[1..1 + len].parse().unwrap_or(isize::MAX)
+        };
+        let base = row.unwrap_or(editor.cursor.row as isize);
+        row = Some(if sign == '+' {
+            base.saturating_add(amount)
+        } else {
+            base.saturating_sub(amount)
+        });
+        rest = &rest[1 + len..];
+    }
+    Ok((row, rest))
+}
+
+/// Splits a leading range off `command`. Returns `None` for the range when
+/// the command has none, and an error for addresses outside the buffer.
+/// A backwards range is swapped, as Vim offers to do.
+pub fn parse_range<'a>(
+    command: &'a str,
+    editor: &Editor,
+) -> Result<(Option<LineRange>, &'a str), String> {
+    let last = editor.buffer.lines.len().saturating_sub(1);
+    let command = command.trim_start();
+    if let Some(rest) = command.strip_prefix('%') {
+        return Ok((
+            Some(LineRange {
+                start: 0,
+                end: last,
+            }),
+            rest,
+        ));
+    }
+    let (first, mut rest) = parse_address(command, editor)?;
+    let mut second = first;
+    if let Some(after) = rest.strip_prefix([',', ';']) {
+        let (address, after) = parse_address(after, editor)?;
+        second = Some(address.unwrap_or(editor.cursor.row as isize));
+        rest = after;
+    }
+    let (Some(first), Some(second)) = (first.or(second), second) else {
+        return Ok((None, rest));
+    };
+    let in_bounds = |row: isize| (0..=last as isize).contains(&row);
+    if !in_bounds(first) || !in_bounds(second) {
+        return Err("Invalid range".to_string());
+    }
+    let (start, end) = (first.min(second) as usize, first.max(second) as usize);
+    Ok((Some(LineRange { start, end }), rest))
+}
+
+/// The destination of `:m` and `:t`: the number of the line to put text
+/// below, where `0` puts it above the first line.
+pub fn parse_destination(input: &str, editor: &Editor) -> Result<usize, String> {
+    let input = input.trim();
+    if input == "0" {
+        return Ok(0);
+    }
+    match parse_address(input, editor)? {
+        (Some(row), "") if (0..editor.buffer.lines.len() as isize).contains(&row) => {
+            Ok(row as usize + 1)
+        }
+        (None, _) => Err("Argument required".to_string()),
+        (Some(_), "") => Err("Invalid range".to_string()),
+        (Some(_), rest) => Err(format!("Trailing characters: {}", rest)),
+    }
+}
+
+/// The name of an ex command after any leading range, such as `d` in
+/// `'a,.+2d x`.
+pub fn command_name(command: &str) -> &str {
+    let mut rest = command.trim_start();
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('\'') => {
+                chars.next();
+            }
+            Some(ch) if ch.is_ascii_digit() || "%.$,;+- ".contains(ch) => {}
+            _ => break,
+        }
+        rest = chars.as_str();
+    }
+    let len = rest
+        .find(|ch: char| !ch.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    &rest[..len]
+}
+
+/// Splits `commands` at each `|`, as in `:cfdo %s/a/b/ | w`. A `\|` stays
+/// in the command as a plain `|`.
+pub fn split_bar(commands: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = commands.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars.peek() == Some(&'|') => {
+                chars.next();
+                parts.last_mut().unwrap().push('|');
+            }
+            '|' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(ch),
+        }
+    }
+    parts
+        .into_iter()
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor() -> Editor {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = (1..=10).map(|n| n.to_string()).collect();
+        editor.cursor.row = 4;
+        editor
+    }
+
+    fn range(command: &str) -> (Option<(usize, usize)>, &str) {
+        let (range, rest) = parse_range(command, &editor()).unwrap();
+        (range.map(|range| (range.start, range.end)), rest)
+    }
+
+    #[test]
+    fn parses_numbers_symbols_and_offsets() {
+        assert_eq!(range("s/a/b/"), (None, "s/a/b/"));
+        assert_eq!(range("%s"), (Some((0, 9)), "s"));
+        assert_eq!(range("3d"), (Some((2, 2)), "d"));
+        assert_eq!(range(".,$y"), (Some((4, 9)), "y"));
+        assert_eq!(range("-2,+1m0"), (Some((2, 5)), "m0"));
+        assert_eq!(range(".+3"), (Some((7, 7)), ""));
+        assert_eq!(range("2,"), (Some((1, 4)), ""));
+        assert_eq!(range("8,3"), (Some((2, 7)), ""));
+    }
+
+    #[test]
+    fn marks_are_addresses() {
+        let mut editor = editor();
+        editor.marks.insert('a', Cursor { row: 2, col: 3 });
+        editor.marks.insert('<', Cursor { row: 6, col: 0 });
+        let (range, rest) = parse_range("'a,'<d", &editor).unwrap();
+        assert_eq!(range, Some(LineRange { start: 2, end: 6 }));
+        assert_eq!(rest, "d");
+        assert_eq!(
+            parse_range("'b", &editor),
+            Err("Mark not set: b".to_string())
+        );
+    }
+
+    #[test]
+    fn destinations_count_from_zero() {
+        let editor = editor();
+        assert_eq!(parse_destination("0", &editor), Ok(0));
+        assert_eq!(parse_destination(" $", &editor), Ok(10));
+        assert_eq!(parse_destination(".-1", &editor), Ok(4));
+        assert_eq!(
+            parse_destination("", &editor),
+            Err("Argument required".to_string())
+        );
+        assert_eq!(
+            parse_destination("3x", &editor),
+            Err("Trailing characters: x".to_string())
+        );
+    }
+
+    #[test]
+    fn command_name_skips_the_range() {
+        assert_eq!(command_name("d"), "d");
+        assert_eq!(command_name("'a,'bm0"), "m");
+        assert_eq!(command_name(" %s/x/y/"), "s");
+        assert_eq!(command_name(".,+2yank a"), "yank");
+        assert_eq!(command_name("42"), "");
+    }
+
+    #[test]
+    fn split_bar_separates_commands() {
+        assert_eq!(split_bar("%s/a/b/g | w"), ["%s/a/b/g", "w"]);
+        assert_eq!(split_bar(r"s/a\|b/c/|"), ["s/a|b/c/"]);
+    }
+
+    #[test]
+    fn rejects_addresses_outside_the_buffer() {
+        let editor = editor();
+        assert_eq!(
+            parse_range("0,11d", &editor),
+            Err("Invalid range".to_string())
+        );
+        assert_eq!(
+            parse_range(".-9", &editor),
+            Err("Invalid range".to_string())
+        );
+    }
+}
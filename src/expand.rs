@@ -0,0 +1,189 @@
+//! File name expansion in ex command arguments: `~` for the home
+//! directory, `$NAME` and `${NAME}` for environment variables, `%` for the
+//! current file and `#` for the alternate one. `%` and `#` take the
+//! modifiers `:p` (absolute path), `:h` (head, the directory), `:t` (tail,
+//! the last component), `:r` (root, without the extension) and `:e` (the
+//! extension), applied left to right as in `%:p:h`.
+
+use std::env;
+use std::path::Path;
+
+/// The files `%` and `#` stand for.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Files<'a> {
+    pub current: Option<&'a Path>,
+    pub alternate: Option<&'a Path>,
+}
+
+/// `args` with its file names expanded. `\%`, `\#` and `\$` stand for the
+/// characters themselves; unset variables are left as they are.
+pub fn expand(args: &str, files: Files) -> Result<String, String> {
+    let chars: Vec<char> = args.chars().collect();
+    let mut expanded = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let word_start = i == 0 || chars[i - 1].is_whitespace();
+        match ch {
+            '\\' if matches!(chars.get(i + 1), Some('%' | '#' | '$')) => {
+                expanded.push(chars[i + 1]);
+                i += 2;
+            }
+            '~' if word_start
+                && chars
+                    .get(i + 1)
+                    .is_none_or(|next| *next == '/' || next.is_whitespace()) =>
+            {
+                match env::var("HOME") {
+                    Ok(home) => expanded.push_str(&home),
+                    Err(_) => expanded.push('~'),
+                }
+                i += 1;
+            }
+            '$' => {
+                let (name, len) = variable_name(&chars[i + 1..]);
+                match env::var(&name) {
+                    Ok(value) if !name.is_empty() => expanded.push_str(&value),
+                    _ => expanded.extend(&chars[i..=i + len]),
+                }
+                i += len + 1;
+            }
+            '%' | '#' => {
+                let (path, missing) = if ch == '%' {
+                    (files.current, "No file name to substitute for '%'")
+                } else {
+                    (
+                        files.alternate,
+                        "No alternate file name to substitute for '#'",
+                    )
+                };
+                let mut name = path.ok_or(missing)?.to_string_lossy().into_owned();
+                i += 1;
+                while chars.get(i) == Some(&':')
+                    && let Some(&modifier) = chars.get(i + 1)
+                    && "phtre".contains(modifier)
+                {
+                    name = modify(&name, modifier);
+                    i += 2;
+                }
+                expanded.push_str(&name);
+            }
+            _ => {
+                expanded.push(ch);
+                i += 1;
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// `name` written so that `expand` gives it back as it is, for a file
+/// name put in an ex command.
+pub fn escape(name: &str) -> String {
+    let mut escaped = String::new();
+    if name == "~" || name.starts_with("~/") {
+        escaped.push_str("./");
+    }
+    for ch in name.chars() {
+        if matches!(ch, '%' | '#' | '$') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// The name of the variable at the start of `chars`, written `NAME` or
+/// `{NAME}`, and how many characters it takes.
+fn variable_name(chars: &[char]) -> (String, usize) {
+    if chars.first() == Some(&'{')
+        && let Some(close) = chars.iter().position(|ch| *ch == '}')
+    {
+        return (chars[1..close].iter().collect(), close + 1);
+    }
+    let len = chars
+        .iter()
+        .take_while(|ch| ch.is_ascii_alphanumeric() || **ch == '_')
+        .count();
+    (chars[..len].iter().collect(), len)
+}
+
+/// `name` with one `:x` modifier applied.
+fn modify(name: &str, modifier: char) -> String {
+    let path = Path::new(name);
+    match modifier {
+        'p' => env::current_dir()
+            .map(|dir| dir.join(path).to_string_lossy().into_owned())
+            .unwrap_or_else(|_| name.to_string()),
+        'h' => match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().into_owned(),
+            _ if path.has_root() => "/".to_string(),
+            _ => ".".to_string(),
+        },
+        't' => path.file_name().map_or_else(
+            || name.to_string(),
+            |tail| tail.to_string_lossy().into_owned(),
+        ),
+        'r' => match path.extension() {
+            Some(extension) => name[..name.len() - extension.len() - 1].to_string(),
+            None => name.to_string(),
+        },
+        _ => path
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_and_variables_are_expanded() {
+        let files = Files {
+            current: Some(Path::new("src/main.rs")),
+            alternate: Some(Path::new("notes.tar.gz")),
+        };
+        let expand = |args: &str| expand(args, files);
+        assert_eq!(expand("%").unwrap(), "src/main.rs");
+        assert_eq!(expand("%:h/lib.rs").unwrap(), "src/lib.rs");
+        assert_eq!(expand("%:t:r.bak").unwrap(), "main.bak");
+        assert_eq!(expand("%:r").unwrap(), "src/main");
+        assert_eq!(expand("%:e").unwrap(), "rs");
+        assert_eq!(expand("#:r:r").unwrap(), "notes");
+        assert_eq!(expand("#:h").unwrap(), ".");
+        assert_eq!(
+            expand("100\\% \\#1 a:b%").unwrap(),
+            "100% #1 a:bsrc/main.rs"
+        );
+        let absolute = expand("%:p").unwrap();
+        assert!(Path::new(&absolute).is_absolute() && absolute.ends_with("/src/main.rs"));
+
+        let home = env::var("HOME").unwrap_or_else(|_| "~".to_string());
+        assert_eq!(
+            expand("~/x ~ a~").unwrap(),
+            format!("{}/x {} a~", home, home)
+        );
+        let path = env::var("PATH").unwrap();
+        assert_eq!(
+            expand("$PATH:${PATH}").unwrap(),
+            format!("{}:{}", path, path)
+        );
+        assert_eq!(
+            expand("$MINIVIM_UNSET_VARIABLE $").unwrap(),
+            "$MINIVIM_UNSET_VARIABLE $"
+        );
+
+        let none = Files::default();
+        assert!(super::expand("plain", none).is_ok());
+        let round_trip = |name: &str| super::expand(&escape(name), none).unwrap();
+        assert_eq!(round_trip("/tmp/a%b#c$HOME.txt"), "/tmp/a%b#c$HOME.txt");
+        assert_eq!(round_trip("a\\%b"), "a\\%b");
+        assert_eq!(round_trip("~/x"), "./~/x");
+        assert_eq!(
+            super::expand("#", none).unwrap_err(),
+            "No alternate file name to substitute for '#'"
+        );
+    }
+}
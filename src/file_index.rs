@@ -0,0 +1,413 @@
+//! An index of the files under the working directory, built on a background
+//! thread. Paths matched by `.gitignore` files are left out, and the index
+//! stops growing at `FILE_INDEX_LIMIT` files. `:grep` and `:FindReplace`
+//! search it when given no paths.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::SystemTime;
+
+use regex::Regex;
+
+use crate::wakeup::Waker;
+
+/// Most files the index holds; a larger tree is indexed only in part.
+pub const FILE_INDEX_LIMIT: usize = 50_000;
+
+/// One line of a `.gitignore` file.
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+    /// Whether the pattern is matched against the whole path below the
+    /// `.gitignore` rather than just the file name.
+    anchored: bool,
+}
+
+/// The rules of one `.gitignore` file, applied to paths below `base`.
+#[derive(Debug, Clone)]
+struct Gitignore {
+    base: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl Gitignore {
+    fn parse(base: &Path, text: &str) -> Self {
+        let rules = text.lines().filter_map(parse_rule).collect();
+        Self {
+            base: base.to_path_buf(),
+            rules,
+        }
+    }
+
+    /// Whether the last rule matching `path` ignores it (`Some(true)`) or
+    /// re-includes it with `!` (`Some(false)`).
+    fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base).ok()?.to_string_lossy();
+        let relative = relative.replace('\\', "/");
+        let name = relative.rsplit('/').next().unwrap_or(&relative);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && rule
+                        .regex
+                        .is_match(if rule.anchored { &relative } else { name })
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Parses one `.gitignore` line; blank lines and `#` comments give `None`.
+fn parse_rule(line: &str) -> Option<IgnoreRule> {
+    let mut pattern = line.trim_end();
+    if pattern.is_empty() || pattern.starts_with('#') {
+        return None;
+    }
+    let negated = pattern.starts_with('!');
+    if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+        pattern = &pattern[1..];
+    }
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    if pattern.is_empty() {
+        return None;
+    }
+    let regex = Regex::new(&format!("^{}$", glob_to_regex(pattern))).ok()?;
+    Some(IgnoreRule {
+        regex,
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+/// Translates a gitignore glob: `*` and `?` stop at `/`, `**` crosses
+/// directories and `[...]` is a character class.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let class: String = chars.by_ref().take_while(|&ch| ch != ']').collect();
+                let class = class
+                    .strip_prefix('!')
+                    .map_or(class.clone(), |rest| format!("^{}", rest));
+                regex.push('[');
+                regex.push_str(&class.replace('\\', "\\\\"));
+                regex.push(']');
+            }
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    regex.push_str(&regex::escape(&next.to_string()));
+                }
+            }
+            _ => regex.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    regex
+}
+
+/// Whether the innermost `.gitignore` with an opinion on `path` ignores it.
+fn is_ignored(ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|ignore| ignore.matches(path, is_dir))
+        .unwrap_or(false)
+}
+
+/// Modification times that tell whether a directory needs to be read again.
+type Stamp = (Option<SystemTime>, Option<SystemTime>);
+
+fn stamp(dir: &Path) -> Stamp {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    (modified(dir), modified(&dir.join(".gitignore")))
+}
+
+/// The indexed contents of one directory.
+#[derive(Debug, Clone, Default)]
+struct DirEntry {
+    stamp: Stamp,
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+}
+
+/// The result of one walk over the tree.
+#[derive(Debug, Clone, Default)]
+struct Scan {
+    dirs: BTreeMap<PathBuf, DirEntry>,
+    files: usize,
+}
+
+impl Scan {
+    /// Walks the tree under `root`, reusing the entries of `previous` for
+    /// directories whose stamp has not changed.
+    fn run(root: &Path, previous: &BTreeMap<PathBuf, DirEntry>) -> Self {
+        let mut scan = Self::default();
+        let mut ignores = Vec::new();
+        scan.visit(root, previous, &mut ignores, false);
+        scan
+    }
+
+    fn visit(
+        &mut self,
+        dir: &Path,
+        previous: &BTreeMap<PathBuf, DirEntry>,
+        ignores: &mut Vec<Gitignore>,
+        mut stale: bool,
+    ) {
+        if self.files >= FILE_INDEX_LIMIT {
+            return;
+        }
+        let stamp = stamp(dir);
+        let gitignore = fs::read_to_string(dir.join(".gitignore")).ok();
+        if let Some(text) = &gitignore {
+            ignores.push(Gitignore::parse(dir, text));
+        }
+        let entry = match previous.get(dir) {
+            Some(entry) if !stale && entry.stamp == stamp => entry.clone(),
+            old => {
+                // Rules from a changed `.gitignore` apply to the whole subtree.
+                stale |= old.is_none_or(|entry| entry.stamp.1 != stamp.1);
+                read_dir(dir, stamp, ignores)
+            }
+        };
+        let room = FILE_INDEX_LIMIT - self.files;
+        let mut entry = entry;
+        if entry.files.len() > room {
+            // Read the directory again next time, when there may be room.
+            entry.stamp = Stamp::default();
+            entry.files.truncate(room);
+        }
+        self.files += entry.files.len();
+        let dirs = entry.dirs.clone();
+        self.dirs.insert(dir.to_path_buf(), entry);
+        for child in dirs {
+            self.visit(&child, previous, ignores, stale);
+        }
+        if gitignore.is_some() {
+            ignores.pop();
+        }
+    }
+}
+
+/// Reads the entries of `dir` that `ignores` lets through, in name order.
+fn read_dir(dir: &Path, stamp: Stamp, ignores: &[Gitignore]) -> DirEntry {
+    let mut entry = DirEntry {
+        stamp,
+        ..DirEntry::default()
+    };
+    let Ok(read) = fs::read_dir(dir) else {
+        return entry;
+    };
+    let mut children: Vec<(PathBuf, bool)> = read
+        .filter_map(Result::ok)
+        .filter(|child| child.file_name() != ".git")
+        .filter_map(|child| {
+            let is_dir = child.file_type().ok()?.is_dir();
+            Some((child.path(), is_dir))
+        })
+        .collect();
+    children.sort();
+    for (path, is_dir) in children {
+        if is_ignored(ignores, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            entry.dirs.push(path);
+        } else {
+            entry.files.push(path);
+        }
+    }
+    entry
+}
+
+/// The files under `root`, refreshed in the background when asked.
+#[derive(Debug)]
+pub struct FileIndex {
+    root: PathBuf,
+    scan: Option<Scan>,
+    receiver: Option<Receiver<Scan>>,
+    waker: Waker,
+}
+
+impl FileIndex {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            scan: None,
+            receiver: None,
+            waker: Waker::default(),
+        }
+    }
+
+    /// Sets the waker told when a background walk finishes.
+    pub fn set_waker(&mut self, waker: Waker) {
+        self.waker = waker;
+    }
+
+    /// Starts a background walk that rereads only the directories changed
+    /// since the last one. Does nothing while a walk is already running.
+    pub fn refresh(&mut self) {
+        if self.receiver.is_some() {
+            return;
+        }
+        let root = self.root.clone();
+        let previous = self.scan.as_ref().map(|scan| scan.dirs.clone());
+        let (sender, receiver) = mpsc::channel();
+        let waker = self.waker.clone();
+        thread::spawn(move || {
+            let _ = sender.send(Scan::run(&root, &previous.unwrap_or_default()));
+            waker.wake();
+        });
+        self.receiver = Some(receiver);
+    }
+
+    /// Refreshes an index that has already been built, after minivim
+    /// itself may have added a file.
+    pub fn file_written(&mut self) {
+        if self.scan.is_some() {
+            self.refresh();
+        }
+    }
+
+    /// Forgets the index after the working directory changed under the
+    /// `.` root, walking the new tree in the background if it was built.
+    pub fn root_changed(&mut self) {
+        self.receiver = None;
+        if self.scan.take().is_some() {
+            self.refresh();
+        }
+    }
+
+    /// Takes the result of a finished background walk. Returns true if the
+    /// index changed.
+    pub fn poll(&mut self) -> bool {
+        let Some(receiver) = &self.receiver else {
+            return false;
+        };
+        match receiver.try_recv() {
+            Ok(scan) => {
+                self.scan = Some(scan);
+                self.receiver = None;
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => {
+                self.receiver = None;
+                false
+            }
+        }
+    }
+
+    /// The indexed files in path order, relative to the working directory
+    /// when the root is `.`. Waits for a running walk, and walks the tree
+    /// right away if it has never been indexed.
+    pub fn files(&mut self) -> Vec<PathBuf> {
+        if let Some(receiver) = self.receiver.take()
+            && let Ok(scan) = receiver.recv()
+        {
+            self.scan = Some(scan);
+        }
+        let scan = self
+            .scan
+            .get_or_insert_with(|| Scan::run(&self.root, &BTreeMap::new()));
+        let mut files: Vec<PathBuf> = scan
+            .dirs
+            .values()
+            .flat_map(|entry| &entry.files)
+            .map(|path| path.strip_prefix(".").unwrap_or(path).to_path_buf())
+            .collect();
+        files.sort();
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(rules: &str, path: &str, is_dir: bool) -> bool {
+        let ignore = Gitignore::parse(Path::new("root"), rules);
+        is_ignored(&[ignore], &Path::new("root").join(path), is_dir)
+    }
+
+    #[test]
+    fn gitignore_patterns() {
+        assert!(ignored("*.log", "a/b/x.log", false));
+        assert!(!ignored("*.log", "a/x.txt", false));
+        assert!(ignored("/build", "build", true));
+        assert!(!ignored("/build", "src/build", true));
+        assert!(ignored("target/", "sub/target", true));
+        assert!(!ignored("target/", "target", false));
+        assert!(ignored("docs/**/*.md", "docs/a/b/c.md", false));
+        assert!(ignored("docs/**/*.md", "docs/c.md", false));
+        assert!(ignored("file?.[ch]", "file1.c", false));
+        assert!(!ignored("# comment\n\n*.log\n!keep.log", "keep.log", false));
+        assert!(ignored("\\#hash", "#hash", false));
+    }
+
+    fn names(index: &mut FileIndex, root: &Path) -> Vec<String> {
+        index
+            .files()
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn index_skips_ignored_files_and_refreshes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/gen")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), "*.o\ngen/\n").unwrap();
+        fs::write(root.join("src/.gitignore"), "!keep.o\n").unwrap();
+        fs::write(root.join("src/main.c"), "").unwrap();
+        fs::write(root.join("src/main.o"), "").unwrap();
+        fs::write(root.join("src/keep.o"), "").unwrap();
+        fs::write(root.join("src/gen/out.c"), "").unwrap();
+        fs::write(root.join(".git/HEAD"), "").unwrap();
+
+        let mut index = FileIndex::new(root.to_path_buf());
+        assert_eq!(
+            names(&mut index, root),
+            [".gitignore", "src/.gitignore", "src/keep.o", "src/main.c"]
+        );
+
+        fs::write(root.join("src/new.c"), "").unwrap();
+        fs::write(root.join(".gitignore"), "*.o\n").unwrap();
+        index.refresh();
+        assert_eq!(
+            names(&mut index, root),
+            [
+                ".gitignore",
+                "src/.gitignore",
+                "src/gen/out.c",
+                "src/keep.o",
+                "src/main.c",
+                "src/new.c"
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Project-wide replacements proposed by `:FindReplace`, reviewed one line
+//! at a time before any file is touched.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::{Captures, Regex};
+
+use crate::pattern;
+use crate::quickfix::{self, GREP_LIMIT};
+
+/// A proposed change to one line of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub path: PathBuf,
+    pub row: usize,
+    pub before: String,
+    pub after: String,
+    pub accepted: bool,
+}
+
+/// Every line under `paths` that replacing all matches of `regex` would
+/// change, accepted by default. Stops after `GREP_LIMIT` lines.
+pub fn proposals(regex: &Regex, replacement: &str, paths: &[PathBuf]) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    for file in quickfix::files(paths) {
+        let Ok(contents) = fs::read_to_string(&file) else {
+            continue;
+        };
+        let path = file.strip_prefix(".").unwrap_or(&file).to_path_buf();
+        for (row, line) in contents.split('\n').enumerate() {
+            let after = regex.replace_all(line, |caps: &Captures| {
+                pattern::expand_replacement(replacement, caps)
+            });
+            if after != line {
+                hunks.push(Hunk {
+                    path: path.clone(),
+                    row,
+                    before: line.to_string(),
+                    after: after.into_owned(),
+                    accepted: true,
+                });
+                if hunks.len() >= GREP_LIMIT {
+                    return hunks;
+                }
+            }
+        }
+    }
+    hunks
+}
+
+/// Applies `hunks` to the lines of `text`. A hunk whose line no longer
+/// reads as it did when proposed is skipped. Returns the new text and the
+/// number of hunks applied.
+pub fn apply(text: &str, hunks: &[&Hunk]) -> (String, usize) {
+    let mut lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+    let mut applied = 0;
+    for hunk in hunks {
+        if let Some(line) = lines.get_mut(hunk.row)
+            && *line == hunk.before
+        {
+            line.clone_from(&hunk.after);
+            applied += 1;
+        }
+    }
+    (lines.join("\n"), applied)
+}
+
+/// Applies `hunks` to the file at `path`, returning how many were applied.
+pub fn apply_to_file(path: &Path, hunks: &[&Hunk]) -> io::Result<usize> {
+    let text = fs::read_to_string(path)?;
+    let (text, applied) = apply(&text, hunks);
+    if applied > 0 {
+        fs::write(path, text)?;
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proposals_and_apply_skip_stale_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "foo = 1\nbar\nfoo(foo)\n").unwrap();
+        let regex = Regex::new(r"foo").unwrap();
+        let hunks = proposals(&regex, "[&]", &[dir.path().to_path_buf()]);
+        let rows: Vec<_> = hunks.iter().map(|h| (h.row, h.after.as_str())).collect();
+        assert_eq!(rows, [(0, "[foo] = 1"), (2, "[foo]([foo])")]);
+
+        fs::write(&file, "foo = 2\nbar\nfoo(foo)\n").unwrap();
+        let applied = apply_to_file(&file, &hunks.iter().collect::<Vec<_>>()).unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "foo = 2\nbar\n[foo]([foo])\n"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Uppercase marks, which name a file as well as a position, kept in a state
+//! file between sessions.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::editor::{Cursor, Jump};
+
+/// Name of the marks file in the state directory.
+pub const GLOBAL_MARKS_FILE: &str = "marks";
+
+/// Reads the marks in `state`. A missing file has no marks and malformed
+/// lines are skipped.
+pub fn load(state: &Path) -> BTreeMap<char, Jump> {
+    let Ok(contents) = fs::read_to_string(state) else {
+        return BTreeMap::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let name = fields.next()?.parse::<char>().ok()?;
+            let row: usize = fields.next()?.parse().ok()?;
+            let col: usize = fields.next()?.parse().ok()?;
+            let path = PathBuf::from(fields.next()?);
+            let cursor = Cursor {
+                row: row.checked_sub(1)?,
+                col,
+            };
+            name.is_ascii_uppercase().then_some((
+                name,
+                Jump {
+                    path: Some(path),
+                    cursor,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Applies `change` to the marks in `state` and writes them back, returning
+/// the result. The file is reread first so concurrent sessions keep each
+/// other's marks.
+pub fn update(
+    state: &Path,
+    change: impl FnOnce(&mut BTreeMap<char, Jump>),
+) -> io::Result<BTreeMap<char, Jump>> {
+    let mut marks = load(state);
+    change(&mut marks);
+    if let Some(parent) = state.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents: String = marks
+        .iter()
+        .filter_map(|(name, mark)| {
+            let path = mark.path.as_ref()?;
+            Some(format!(
+                "{}\t{}\t{}\t{}\n",
+                name,
+                mark.cursor.row + 1,
+                mark.cursor.col,
+                path.display()
+            ))
+        })
+        .collect();
+    fs::write(state, contents)?;
+    Ok(marks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_merges_with_the_saved_marks() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state/marks");
+        let mark = |path: &str, row| Jump {
+            path: Some(PathBuf::from(path)),
+            cursor: Cursor { row, col: 2 },
+        };
+        update(&state, |marks| {
+            marks.insert('A', mark("/a.txt", 3));
+        })
+        .unwrap();
+        let marks = update(&state, |marks| {
+            marks.insert('B', mark("/b b.txt", 0));
+        })
+        .unwrap();
+        assert_eq!(marks.len(), 2);
+        assert_eq!(load(&state), marks);
+        assert_eq!(marks[&'B'], mark("/b b.txt", 0));
+
+        let marks = update(&state, |marks| {
+            marks.remove(&'A');
+        })
+        .unwrap();
+        assert_eq!(marks.keys().collect::<Vec<_>>(), [&'B']);
+    }
+}
@@ -0,0 +1,370 @@
+//! Syntax highlighting with syntect, done lazily. Rows up to the end of the
+//! viewport are highlighted when drawn, and the rest of the file a slice at
+//! a time while the editor is idle. (syntect's parser state cannot move to
+//! another thread.) Parser states saved every `CHECKPOINT_LINES` rows let an
+//! edit re-highlight from just above the changed row.
+
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crossterm::style::{Attribute, Attributes, Color, ContentStyle};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{
+    Color as SyntectColor, FontStyle, HighlightState, Style, Theme, ThemeSet,
+};
+use syntect::parsing::{ParseState, SyntaxReference, SyntaxSet};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editor::{StyledSpan, grapheme_columns};
+use crate::wakeup::Waker;
+
+/// Rows between saved parser states.
+pub const CHECKPOINT_LINES: usize = 128;
+
+/// Rows highlighted per idle slice.
+const IDLE_LINES: usize = 1024;
+
+/// Most rows highlighted while drawing to reach the viewport; a longer gap
+/// is left plain until idle slices fill it in.
+const SYNC_LIMIT: usize = 4 * IDLE_LINES;
+
+/// Name of the bundled theme used for highlighting.
+const THEME: &str = "base16-ocean.dark";
+
+/// Syntax definitions and the color theme.
+pub struct Assets {
+    pub syntax_set: SyntaxSet,
+    pub theme: Theme,
+}
+
+impl Assets {
+    /// Loads syntect's bundled syntaxes and the theme. The syntaxes come from
+    /// a precompiled binary dump that only decodes a syntax's rules when a
+    /// file using it is first highlighted.
+    pub fn load() -> Self {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let mut themes = ThemeSet::load_defaults().themes;
+        let theme = themes
+            .remove(THEME)
+            .or_else(|| themes.into_values().next())
+            .expect("syntect themes are missing");
+        Self { syntax_set, theme }
+    }
+
+    /// Loads the assets on a background thread, so startup need not wait,
+    /// and wakes the main loop when they are ready.
+    pub fn load_in_background(waker: Waker) -> Receiver<Assets> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(Self::load());
+            waker.wake();
+        });
+        receiver
+    }
+}
+
+/// The highlighter's position in a file: syntect's state before some row.
+type State = (HighlightState, ParseState);
+
+/// Highlighted rows and the parser states met along the way.
+struct Chunk {
+    spans: Vec<Vec<StyledSpan>>,
+    checkpoints: Vec<(usize, State)>,
+    end: State,
+}
+
+/// Highlights `lines`, the rows from `start`, beginning in `state`.
+/// Returns each row's spans, the states before those rows that are
+/// multiples of `CHECKPOINT_LINES`, and the state after the last row.
+fn highlight_rows(
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+    state: State,
+    start: usize,
+    lines: &[String],
+    tabstop: usize,
+) -> Chunk {
+    let mut chunk = Chunk {
+        spans: Vec::with_capacity(lines.len()),
+        checkpoints: Vec::new(),
+        end: state,
+    };
+    let (highlight, parse) = chunk.end.clone();
+    let mut highlighter = HighlightLines::from_state(theme, highlight, parse);
+    for (row, line) in (start..).zip(lines) {
+        if row % CHECKPOINT_LINES == 0 {
+            let state = highlighter.state();
+            chunk.checkpoints.push((row, state.clone()));
+            highlighter = HighlightLines::from_state(theme, state.0, state.1);
+        }
+        let line = format!("{}\n", line);
+        let ranges = highlighter
+            .highlight_line(&line, syntax_set)
+            .unwrap_or_default();
+        chunk.spans.push(spans_from_ranges(&ranges, tabstop));
+    }
+    chunk.end = highlighter.state();
+    chunk
+}
+
+/// Highlighted rows of one file, contiguous from the first row.
+pub struct SyntaxCache {
+    syntax_set: SyntaxSet,
+    theme: Theme,
+    /// Index of the file's syntax in `syntax_set`.
+    syntax: usize,
+    tabstop: usize,
+    /// The text each cached row was highlighted from.
+    lines: Vec<String>,
+    spans: Vec<Vec<StyledSpan>>,
+    /// `checkpoints[i]` is the state before row `i * CHECKPOINT_LINES`.
+    checkpoints: Vec<State>,
+    /// The state after the last cached row.
+    end: State,
+}
+
+impl SyntaxCache {
+    pub fn new(assets: Assets) -> Self {
+        let Assets { syntax_set, theme } = assets;
+        let syntax = syntax_set.find_syntax_plain_text();
+        let start = Self::start_state(syntax, &theme);
+        let syntax = Self::index_of(&syntax_set, syntax);
+        Self {
+            syntax_set,
+            theme,
+            syntax,
+            tabstop: 0,
+            lines: Vec::new(),
+            spans: Vec::new(),
+            checkpoints: vec![start.clone()],
+            end: start,
+        }
+    }
+
+    fn start_state(syntax: &SyntaxReference, theme: &Theme) -> State {
+        HighlightLines::new(syntax, theme).state()
+    }
+
+    fn index_of(syntax_set: &SyntaxSet, syntax: &SyntaxReference) -> usize {
+        syntax_set
+            .syntaxes()
+            .iter()
+            .position(|candidate| std::ptr::eq(candidate, syntax))
+            .unwrap_or(0)
+    }
+
+    /// Switches to the syntax for `path` and to `tabstop`, dropping every
+    /// cached row if either changed.
+    pub fn configure(&mut self, path: Option<&Path>, tabstop: usize) {
+        let syntax = path
+            .and_then(|path| self.syntax_set.find_syntax_for_file(path).ok().flatten())
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let index = Self::index_of(&self.syntax_set, syntax);
+        if index == self.syntax && tabstop == self.tabstop {
+            return;
+        }
+        self.checkpoints = vec![Self::start_state(syntax, &self.theme)];
+        self.syntax = index;
+        self.tabstop = tabstop;
+        self.truncate(0);
+    }
+
+    /// Drops cached rows from the checkpoint at or above `row`.
+    fn truncate(&mut self, row: usize) {
+        let checkpoint = (row / CHECKPOINT_LINES).min(self.checkpoints.len() - 1);
+        let row = checkpoint * CHECKPOINT_LINES;
+        self.checkpoints.truncate(checkpoint + 1);
+        self.end = self.checkpoints[checkpoint].clone();
+        self.lines.truncate(row);
+        self.spans.truncate(row);
+    }
+
+    /// Drops the cached rows from the first one whose text differs from
+    /// `lines`.
+    pub fn invalidate(&mut self, lines: &[String]) {
+        let same = self
+            .lines
+            .iter()
+            .zip(lines)
+            .take_while(|(cached, line)| cached == line)
+            .count();
+        if same < self.lines.len() {
+            self.truncate(same);
+        }
+    }
+
+    /// Drops the cached rows from `row`, which was edited.
+    pub fn invalidate_from(&mut self, row: usize) {
+        if row < self.lines.len() {
+            self.truncate(row);
+        }
+    }
+
+    /// Highlighted spans for `row`, if it has been highlighted.
+    pub fn spans(&self, row: usize) -> Option<&[StyledSpan]> {
+        self.spans.get(row).map(Vec::as_slice)
+    }
+
+    /// Highlights `lines` up to row `end`, unless that is more than
+    /// `SYNC_LIMIT` rows past the cached ones.
+    pub fn highlight_to(&mut self, lines: &[String], end: usize) {
+        let end = end.min(lines.len());
+        let start = self.spans.len();
+        if start < end && end - start <= SYNC_LIMIT {
+            self.highlight(lines, end);
+        }
+    }
+
+    /// Highlights the next slice of rows. Returns false if every row was
+    /// already highlighted.
+    pub fn highlight_more(&mut self, lines: &[String]) -> bool {
+        let end = (self.spans.len() + IDLE_LINES).min(lines.len());
+        if self.spans.len() >= end {
+            return false;
+        }
+        self.highlight(lines, end);
+        true
+    }
+
+    fn highlight(&mut self, lines: &[String], end: usize) {
+        let start = self.spans.len();
+        let chunk = highlight_rows(
+            &self.syntax_set,
+            &self.theme,
+            self.end.clone(),
+            start,
+            &lines[start..end],
+            self.tabstop,
+        );
+        self.spans.extend(chunk.spans);
+        self.lines.extend_from_slice(&lines[start..end]);
+        for (row, state) in chunk.checkpoints {
+            if row / CHECKPOINT_LINES == self.checkpoints.len() {
+                self.checkpoints.push(state);
+            }
+        }
+        self.end = chunk.end;
+    }
+}
+
+/// Converts syntect ranges into spans measured in display columns.
+pub fn spans_from_ranges(ranges: &[(Style, &str)], tabstop: usize) -> Vec<StyledSpan> {
+    let mut spans: Vec<StyledSpan> = Vec::new();
+    let mut col = 0usize;
+
+    for (style, text) in ranges {
+        let mut len = 0usize;
+        let text = text.trim_end_matches(['\n', '\r']);
+        for grapheme in text.graphemes(true) {
+            len += grapheme_columns(grapheme, col + len, tabstop);
+        }
+        if len == 0 {
+            continue;
+        }
+
+        let content_style = map_style(*style);
+        if let Some(last) = spans.last_mut()
+            && last.style == content_style
+            && last.start + last.len == col
+        {
+            last.len += len;
+            col += len;
+            continue;
+        }
+
+        spans.push(StyledSpan {
+            start: col,
+            len,
+            style: content_style,
+        });
+        col += len;
+    }
+
+    spans
+}
+
+fn map_style(style: Style) -> ContentStyle {
+    let mut content = ContentStyle::new();
+    content.foreground_color = map_color(style.foreground);
+    content.background_color = map_color(style.background);
+    let mut attrs = Attributes::default();
+    if style.font_style.contains(FontStyle::BOLD) {
+        attrs.set(Attribute::Bold);
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        attrs.set(Attribute::Italic);
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        attrs.set(Attribute::Underlined);
+    }
+    content.attributes = attrs;
+    content
+}
+
+fn map_color(color: SyntectColor) -> Option<Color> {
+    if color.a == 0 {
+        None
+    } else {
+        Some(Color::Rgb {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> SyntaxCache {
+        let mut cache = SyntaxCache::new(Assets::load());
+        cache.configure(Some(Path::new("main.rs")), 4);
+        cache
+    }
+
+    #[test]
+    fn syntax_spans_are_measured_in_display_columns() {
+        let style = Style::default();
+        let spans = spans_from_ranges(&[(style, "\t"), (style, "x\n")], 4);
+        let total: usize = spans.iter().map(|span| span.len).sum();
+        assert_eq!(total, 5);
+    }
+
+    #[test]
+    fn highlights_the_viewport_then_the_rest_when_idle() {
+        let mut lines = vec!["/*".to_string()];
+        lines.extend((0..1000).map(|n| format!("let x{} = {};", n, n)));
+        lines.push("*/".to_string());
+        lines.push("let y = 1;".to_string());
+        let mut cache = cache();
+        cache.highlight_to(&lines, 40);
+        assert_eq!(cache.spans.len(), 40);
+        while cache.highlight_more(&lines) {}
+        assert_eq!(cache.spans.len(), lines.len());
+        let comment = cache.spans(500).unwrap().to_vec();
+        assert_eq!(comment.len(), 1);
+        let code = cache.spans(1002).unwrap().to_vec();
+        assert!(code.len() > 1);
+
+        // Closing the comment early changes everything below it.
+        lines.insert(1, "*/".to_string());
+        cache.invalidate(&lines);
+        assert_eq!(cache.spans.len(), 0);
+        while cache.highlight_more(&lines) {}
+        assert!(cache.spans(500).unwrap().len() > 1);
+        assert_eq!(
+            cache.checkpoints.len(),
+            lines.len().div_ceil(CHECKPOINT_LINES)
+        );
+
+        // An edit far down keeps the rows above the checkpoint before it.
+        lines[700] = "let z = 2;".to_string();
+        cache.invalidate(&lines);
+        assert_eq!(cache.spans.len(), 640);
+        cache.highlight_to(&lines, 740);
+        assert_eq!(cache.spans.len(), 740);
+        assert!(cache.spans(700).unwrap().len() > 1);
+    }
+}
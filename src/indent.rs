@@ -0,0 +1,179 @@
+//! Language-aware indentation rules used by `smartindent`.
+
+/// How a file type opens and closes indented blocks.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IndentRules {
+    /// A line ending with one of these opens a block.
+    pub open_suffixes: &'static [&'static str],
+    /// A line starting with one of these words opens a block.
+    pub open_words: &'static [&'static str],
+    /// A line starting with one of these words closes a block.
+    pub close_words: &'static [&'static str],
+    /// Unclosed `(`, `[` and `{` open a block; a leading closer closes one.
+    pub brackets: bool,
+}
+
+const C_LIKE: IndentRules = IndentRules {
+    open_suffixes: &[],
+    open_words: &[],
+    close_words: &[],
+    brackets: true,
+};
+
+const PYTHON: IndentRules = IndentRules {
+    open_suffixes: &[":"],
+    open_words: &[],
+    close_words: &["else", "elif", "except", "finally"],
+    brackets: true,
+};
+
+const RUBY: IndentRules = IndentRules {
+    open_suffixes: &[" do", "|"],
+    open_words: &[
+        "def", "class", "module", "if", "unless", "while", "until", "case", "begin", "else",
+        "elsif", "when", "rescue", "ensure",
+    ],
+    close_words: &["end", "else", "elsif", "when", "rescue", "ensure"],
+    brackets: true,
+};
+
+const LUA: IndentRules = IndentRules {
+    open_suffixes: &[" do", " then", "repeat"],
+    open_words: &["function", "local function", "else"],
+    close_words: &["end", "else", "elseif", "until"],
+    brackets: true,
+};
+
+const SHELL: IndentRules = IndentRules {
+    open_suffixes: &[" then", " do", " in"],
+    open_words: &["then", "do", "else"],
+    close_words: &["fi", "done", "else", "elif", "esac"],
+    brackets: true,
+};
+
+const YAML: IndentRules = IndentRules {
+    open_suffixes: &[":"],
+    open_words: &[],
+    close_words: &[],
+    brackets: false,
+};
+
+/// Rules for a file extension; unknown types indent after brackets.
+pub fn rules_for(extension: &str) -> &'static IndentRules {
+    match extension {
+        "py" | "pyw" => &PYTHON,
+        "rb" => &RUBY,
+        "lua" => &LUA,
+        "sh" | "bash" | "zsh" => &SHELL,
+        "yml" | "yaml" => &YAML,
+        _ => &C_LIKE,
+    }
+}
+
+/// Whether `line` starts with `word` followed by a non-keyword character.
+fn starts_with_word(line: &str, word: &str) -> bool {
+    line.strip_prefix(word)
+        .is_some_and(|rest| !rest.starts_with(|ch: char| ch.is_alphanumeric() || ch == '_'))
+}
+
+/// Whether `line` ends with `word` preceded by a non-keyword character.
+fn ends_with_word(line: &str, word: &str) -> bool {
+    line.strip_suffix(word)
+        .is_some_and(|rest| !rest.ends_with(|ch: char| ch.is_alphanumeric() || ch == '_'))
+}
+
+/// Net count of opening minus closing brackets outside string literals.
+fn bracket_balance(line: &str) -> isize {
+    let mut balance = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for ch in line.chars() {
+        if in_string {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' => in_string = true,
+            '(' | '[' | '{' => balance += 1,
+            ')' | ']' | '}' => balance -= 1,
+            _ => {}
+        }
+    }
+    balance
+}
+
+/// Whether the line after `line` should be indented one level deeper.
+pub fn opens_block(line: &str, rules: &IndentRules) -> bool {
+    let line = line.trim();
+    if rules.brackets {
+        // A leading closer was already dedented, so it does not count
+        // against brackets opened later on the line.
+        let body = line.trim_start_matches([')', ']', '}']);
+        if bracket_balance(body) > 0 {
+            return true;
+        }
+    }
+    // A one-line block such as `if x then y end` closes what it opens.
+    if rules.close_words.contains(&"end") && ends_with_word(line, "end") {
+        return false;
+    }
+    rules
+        .open_suffixes
+        .iter()
+        .any(|suffix| line.ends_with(suffix))
+        || rules
+            .open_words
+            .iter()
+            .any(|word| starts_with_word(line, word))
+}
+
+/// Whether `line` closes the block it is in, so it belongs one level out.
+pub fn closes_block(line: &str, rules: &IndentRules) -> bool {
+    let line = line.trim_start();
+    if rules.brackets && line.starts_with([')', ']', '}']) {
+        return true;
+    }
+    rules
+        .close_words
+        .iter()
+        .any(|word| starts_with_word(line, word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brackets_open_and_close_blocks() {
+        let rules = rules_for("rs");
+        assert!(opens_block("fn main() {", rules));
+        assert!(opens_block("let v = vec![", rules));
+        assert!(!opens_block("call(\"{\");", rules));
+        assert!(!opens_block("} else if x {}", rules));
+        assert!(opens_block("} else {", rules));
+        assert!(closes_block("    }", rules));
+        assert!(!closes_block("x }", rules));
+    }
+
+    #[test]
+    fn keywords_open_and_close_blocks() {
+        let python = rules_for("py");
+        assert!(opens_block("def f(x):", python));
+        assert!(closes_block("else:", python));
+        assert!(!closes_block("elsewhere = 1", python));
+
+        let ruby = rules_for("rb");
+        assert!(opens_block("items.each do", ruby));
+        assert!(opens_block("def greet(name)", ruby));
+        assert!(!opens_block("if ready then go end", ruby));
+        assert!(!opens_block("defaults = {}", ruby));
+        assert!(opens_block("def append", ruby));
+        assert!(closes_block("end", ruby));
+        assert!(!closes_block("ending = 1", ruby));
+    }
+}
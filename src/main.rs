@@ -1,176 +1,781 @@
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::fs;
+use std::io;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crossterm::{
     cursor,
-    event::{self, Event},
-    execute, queue,
-    style::{Print, PrintStyledContent},
-    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+    event::{
+        DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+        EnableFocusChange, EnableMouseCapture, Event,
+    },
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
+mod autocmd;
+mod cli;
+mod clipboard;
+mod color;
+mod comment;
+mod compress;
+mod crypt;
+mod csv;
+mod delta;
+mod diagnostics;
+mod digraphs;
 mod editor;
+mod ex;
+mod expand;
+mod file_index;
+mod find_replace;
+mod global_marks;
+mod highlight;
+mod indent;
+mod markdown;
+mod menu;
+mod motion;
+mod oldfiles;
+mod palette;
+mod pattern;
 mod plugins;
+mod popup;
+mod provider;
+mod quickfix;
+mod registry;
+mod render;
+mod save;
+mod spell;
+mod subvert;
+mod surround;
+mod table;
+mod theme;
+mod undo;
+mod virtual_text;
+mod wakeup;
 
-use editor::{Editor, EventResult, Plugin, RenderContext, StyledSpan};
+use color::ColorDepth;
+use editor::{Editor, EventResult, Mode, Plugin, RenderContext, TerminalJob};
 use plugins::{
-    BufferRenderPlugin, CommandLinePlugin, CommandLineRenderPlugin, CursorRenderPlugin,
-    FileCommandPlugin, InsertPlugin, ModePlugin, MotionPlugin, StatusBarPlugin,
-    SyntaxHighlightPlugin,
+    AbbreviationPlugin, AutoPairsPlugin, AutocmdPlugin, BufferRenderPlugin, ChangeListPlugin,
+    CheatSheetPlugin, CommandLinePlugin, CommandLineRenderPlugin, CommentPlugin, CompletionPlugin,
+    CsvPlugin, CsvRenderPlugin, CursorRenderPlugin, DiagnosticPlugin, DiagnosticRenderPlugin,
+    DigraphPlugin, DirectoryPlugin, ExplorerPlugin, FileCommandPlugin, FindReplacePlugin,
+    GotoFilePlugin, HelpPlugin, HighlightSearchPlugin, IndentGuidePlugin, IndentPlugin,
+    InsertPlugin, JumpListPlugin, KeywordLookupPlugin, LineCommandPlugin, MarkPlugin,
+    MarkdownPreviewPlugin, MenuRenderPlugin, ModePlugin, MotionPlugin, MousePlugin, OldFilesPlugin,
+    OperatorPlugin, OptionsPlugin, PalettePlugin, PaletteRenderPlugin, PastePlugin, PopupPlugin,
+    PopupRenderPlugin, PreviewWindowPlugin, QuickfixPlugin, RainbowParenPlugin,
+    RegisterInsertPlugin, ScratchViewPlugin, SearchPlugin, SelectionRenderPlugin, SessionPlugin,
+    ShellPlugin, SignColumnPlugin, SortPlugin, SpellPlugin, SpellRenderPlugin, StatusBarPlugin,
+    SubstitutePlugin, SubstitutePreviewPlugin, SurroundPlugin, SyntaxHighlightPlugin, TablePlugin,
+    ThemePlugin, TrailingWhitespacePlugin, UndoPlugin, UserCommandPlugin, VirtualTextRenderPlugin,
+    VisualPlugin, WhichKeyPlugin,
 };
+use render::{RenderBackend, Screen, TerminalBackend};
+use wakeup::{InputGate, Wakeup};
+
+/// How often a headless run checks on a background file load.
+const LOAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Minimum time between frames, capping redraws at roughly 60 per second.
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Coalesces redraw requests so bursts of state changes share one frame, while a
+/// request arriving after an idle period is drawn immediately.
+struct FrameScheduler {
+    last_frame: Option<Instant>,
+    pending: bool,
+}
+
+impl FrameScheduler {
+    fn new() -> Self {
+        Self {
+            last_frame: None,
+            pending: false,
+        }
+    }
+
+    fn request(&mut self) {
+        self.pending = true;
+    }
+
+    /// Time left before a pending frame may be drawn, or `None` if nothing is pending.
+    fn wait_time(&self, now: Instant) -> Option<Duration> {
+        if !self.pending {
+            return None;
+        }
+        let elapsed = self
+            .last_frame
+            .map_or(FRAME_INTERVAL, |last| now.saturating_duration_since(last));
+        Some(FRAME_INTERVAL.saturating_sub(elapsed))
+    }
+
+    fn rendered(&mut self, now: Instant) {
+        self.last_frame = Some(now);
+        self.pending = false;
+    }
+}
 
 struct TerminalGuard;
 
 impl TerminalGuard {
     fn new() -> io::Result<Self> {
-        terminal::enable_raw_mode()?;
-        execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+        Self::enter()?;
         Ok(Self)
     }
+
+    /// Switches the terminal to the editor's full-screen raw mode.
+    fn enter() -> io::Result<()> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            io::stdout(),
+            EnterAlternateScreen,
+            EnableMouseCapture,
+            EnableBracketedPaste,
+            EnableFocusChange,
+            cursor::Hide
+        )
+    }
+
+    /// Gives the terminal back as the editor found it.
+    fn leave() {
+        let _ = execute!(
+            io::stdout(),
+            DisableFocusChange,
+            DisableBracketedPaste,
+            DisableMouseCapture,
+            LeaveAlternateScreen,
+            cursor::Show
+        );
+        let _ = terminal::disable_raw_mode();
+    }
 }
 
 impl Drop for TerminalGuard {
     fn drop(&mut self) {
-        let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
-        let _ = terminal::disable_raw_mode();
+        Self::leave();
     }
 }
 
-fn main() -> io::Result<()> {
-    let _terminal = TerminalGuard::new()?;
+/// Runs the program a plugin queued with the terminal to itself: input
+/// reading stops and the editor's screen is put away until it exits, then
+/// the next frame repaints everything.
+fn run_terminal_job(
+    editor: &mut Editor,
+    screen: &mut Screen,
+    gate: &InputGate,
+    job: TerminalJob,
+) -> io::Result<()> {
+    gate.close();
+    TerminalGuard::leave();
+    job.run(editor);
+    let entered = TerminalGuard::enter();
+    gate.open();
+    screen.invalidate();
+    entered
+}
+
+/// Stops the editor as Ctrl-Z stops other programs: it raises SIGTSTP
+/// while the terminal is given back, and once the shell resumes it the
+/// screen is entered again at the terminal's size, which may have changed
+/// meanwhile, and repainted.
+#[cfg(unix)]
+fn suspend(editor: &mut Editor, screen: &mut Screen, gate: &InputGate) -> io::Result<()> {
+    gate.close();
+    TerminalGuard::leave();
+    // Returns once the process is continued.
+    let stopped = signal_hook::low_level::raise(signal_hook::consts::SIGTSTP);
+    let entered = TerminalGuard::enter();
+    gate.open();
+    screen.invalidate();
+    if let Err(err) = stopped {
+        editor.set_error(format!("Cannot suspend: {}", err));
+    }
+    entered?;
     let (width, height) = terminal::size()?;
-    let file_path = std::env::args().nth(1).map(PathBuf::from);
-    let mut editor = Editor::new(width, height, file_path);
+    editor.set_screen_size(width, height);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn suspend(editor: &mut Editor, _screen: &mut Screen, _gate: &InputGate) -> io::Result<()> {
+    editor.set_error("Cannot suspend on this platform");
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match cli::Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("minivim: {}\n{}", err, cli::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = if args.headless {
+        run_headless(args)
+    } else {
+        run_terminal(args)
+    };
+    match result {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("minivim: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let mut plugins: Vec<Box<dyn Plugin>> = vec![
+fn default_plugins() -> Vec<Box<dyn Plugin>> {
+    vec![
+        Box::new(PalettePlugin),
+        Box::new(SpellPlugin::new()),
+        Box::new(PopupPlugin),
         Box::new(FileCommandPlugin),
+        Box::new(DirectoryPlugin),
+        Box::new(ShellPlugin),
+        Box::new(OptionsPlugin),
+        Box::new(ThemePlugin),
+        Box::new(SessionPlugin),
+        Box::new(UserCommandPlugin),
+        Box::new(AutocmdPlugin),
+        Box::new(SubstitutePlugin::new()),
+        Box::new(FindReplacePlugin::new()),
+        Box::new(ExplorerPlugin::new()),
+        Box::new(OldFilesPlugin::new()),
+        Box::new(CheatSheetPlugin),
+        Box::new(ScratchViewPlugin),
+        Box::new(CompletionPlugin::new()),
+        Box::new(RegisterInsertPlugin::new()),
         Box::new(ModePlugin),
         Box::new(CommandLinePlugin),
+        Box::new(SearchPlugin),
+        Box::new(LineCommandPlugin),
+        Box::new(SortPlugin),
+        Box::new(UndoPlugin),
+        Box::new(QuickfixPlugin::new()),
+        Box::new(PreviewWindowPlugin),
+        Box::new(JumpListPlugin),
+        Box::new(MarkPlugin),
+        Box::new(ChangeListPlugin),
+        Box::new(CsvPlugin),
+        Box::new(DiagnosticPlugin),
+        Box::new(IndentPlugin),
+        Box::new(CommentPlugin),
+        Box::new(GotoFilePlugin),
+        Box::new(SurroundPlugin),
+        Box::new(OperatorPlugin),
+        Box::new(VisualPlugin),
         Box::new(MotionPlugin),
-        Box::new(InsertPlugin),
+        Box::new(MousePlugin),
+        Box::new(PastePlugin),
+        Box::new(DigraphPlugin::new()),
+        Box::new(TablePlugin),
+        Box::new(AutoPairsPlugin),
+        Box::new(InsertPlugin::new()),
+        Box::new(AbbreviationPlugin),
+        Box::new(KeywordLookupPlugin),
+        Box::new(HelpPlugin),
         Box::new(BufferRenderPlugin),
         Box::new(SyntaxHighlightPlugin::new()),
+        Box::new(RainbowParenPlugin::new()),
+        Box::new(IndentGuidePlugin),
+        Box::new(TrailingWhitespacePlugin),
+        Box::new(SpellRenderPlugin),
+        Box::new(HighlightSearchPlugin),
+        Box::new(SubstitutePreviewPlugin),
+        Box::new(DiagnosticRenderPlugin),
+        Box::new(SelectionRenderPlugin),
         Box::new(StatusBarPlugin),
         Box::new(CommandLineRenderPlugin),
         Box::new(CursorRenderPlugin),
-    ];
+        Box::new(CsvRenderPlugin::new()),
+        Box::new(VirtualTextRenderPlugin),
+        Box::new(SignColumnPlugin),
+        Box::new(MarkdownPreviewPlugin::new()),
+        Box::new(MenuRenderPlugin),
+        Box::new(PopupRenderPlugin),
+        Box::new(WhichKeyPlugin::new()),
+        Box::new(PaletteRenderPlugin),
+    ]
+}
 
+/// Lists the plugins' commands and keys in the registry and lets each one
+/// set itself up.
+fn init_plugins(editor: &mut Editor, plugins: &mut [Box<dyn Plugin>]) {
     for plugin in plugins.iter_mut() {
-        plugin.on_init(&mut editor);
+        editor.registry.add(plugin.as_ref());
+        plugin.on_init(editor);
     }
+}
 
-    render(&editor, &mut plugins)?;
+/// Work to do once the file has loaded: an initial cursor position, then ex commands.
+struct Startup {
+    position: Option<(usize, usize)>,
+    commands: Vec<String>,
+}
 
-    loop {
-        let event = event::read()?;
-        if let Event::Resize(width, height) = event {
-            editor.set_screen_size(width, height);
+impl Startup {
+    /// Collects the `file:line:col` position and the commands from the user
+    /// config, `--script`, and each `+` and `-c` argument, in that order.
+    fn from_args(args: &cli::Args) -> io::Result<Self> {
+        let mut commands = Vec::new();
+        if !args.clean
+            && let Some(path) = editor::config_path()
+        {
+            match fs::read_to_string(&path) {
+                Ok(contents) => commands.extend(editor::parse_script(&contents)),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if let Some(path) = args.script.as_ref() {
+            commands.extend(editor::parse_script(&fs::read_to_string(path)?));
         }
+        commands.extend(args.commands.iter().cloned());
+        Ok(Self {
+            position: args.position,
+            commands,
+        })
+    }
 
-        for plugin in plugins.iter_mut() {
-            if plugin.on_event(&mut editor, &event) == EventResult::Consumed {
-                break;
-            }
+    fn apply_position(&self, editor: &mut Editor) {
+        if let Some((line, col)) = self.position {
+            editor.goto_position(line.saturating_sub(1), col.saturating_sub(1));
         }
+    }
+}
 
-        for command in editor.take_commands() {
-            for plugin in plugins.iter_mut() {
-                if plugin.on_command(&mut editor, &command) == EventResult::Consumed {
-                    break;
-                }
+/// Applies the startup position and commands once the file has finished loading.
+fn run_startup(
+    editor: &mut Editor,
+    plugins: &mut [Box<dyn Plugin>],
+    startup: &mut Option<Startup>,
+) {
+    // The file is not there to move around in until it has loaded, or
+    // been decrypted.
+    if editor.is_loading() || editor.terminal_job.is_some() {
+        return;
+    }
+    let Some(startup) = startup.take() else {
+        return;
+    };
+    startup.apply_position(editor);
+    for command in startup.commands {
+        editor.push_command(command);
+    }
+    run_commands(editor, plugins);
+}
+
+/// Runs startup commands against the file without a terminal, reporting status
+/// messages on stderr. Fails if the file cannot be loaded, in which case no
+/// command runs, or if any command is unknown or reports an error.
+fn run_headless(args: cli::Args) -> io::Result<ExitCode> {
+    let startup = Startup::from_args(&args)?;
+    let mut editor = Editor::new(80, 24, args.file);
+    let mut plugins = default_plugins();
+    init_plugins(&mut editor, &mut plugins);
+    while editor.is_loading() {
+        if !editor.poll_loading() {
+            thread::sleep(LOAD_POLL_INTERVAL);
+        }
+    }
+    if let Some(job) = editor.terminal_job.take() {
+        job.run(&mut editor);
+    }
+    if !editor.status.is_empty() {
+        eprintln!("{}", editor.status);
+    }
+    if editor.error {
+        return Ok(ExitCode::FAILURE);
+    }
+
+    startup.apply_position(&mut editor);
+    let mut code = ExitCode::SUCCESS;
+    for command in startup.commands {
+        editor.set_status("");
+        editor.error = false;
+        editor.push_command(command);
+        run_commands(&mut editor, &mut plugins);
+        if let Some(job) = editor.terminal_job.take() {
+            job.run(&mut editor);
+        }
+        if !editor.status.is_empty() {
+            eprintln!("{}", editor.status);
+        }
+        if editor.error {
+            code = ExitCode::FAILURE;
+        }
+        if editor.should_quit {
+            break;
+        }
+    }
+    Ok(code)
+}
+
+fn run_terminal(args: cli::Args) -> io::Result<ExitCode> {
+    let startup = Startup::from_args(&args)?;
+    let _terminal = TerminalGuard::new()?;
+    let (width, height) = terminal::size()?;
+    let mut editor = Editor::new(width, height, args.file);
+    editor.state_dir = editor::state_dir();
+    let (waker, wakeups) = wakeup::channel();
+    editor.set_waker(waker.clone());
+    waker.forward_signals()?;
+    let mut plugins = default_plugins();
+    init_plugins(&mut editor, &mut plugins);
+    let mut startup = Some(startup);
+    run_startup(&mut editor, &mut plugins, &mut startup);
+    if editor.should_quit {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut screen = Screen::new();
+    let depth = args.color.unwrap_or_else(ColorDepth::detect);
+    let mut backend = TerminalBackend::new(io::stdout(), depth);
+    let mut scheduler = FrameScheduler::new();
+    render(&editor, &mut plugins, &mut screen, &mut backend)?;
+    scheduler.rendered(Instant::now());
+    let gate = waker.forward_input();
+    editor.file_index.refresh();
+    // Whether plugins may have deferred work; set after every frame.
+    let mut idle_work = true;
+
+    loop {
+        let timeout = if idle_work {
+            Some(Duration::ZERO)
+        } else {
+            scheduler.wait_time(Instant::now())
+        };
+
+        editor.begin_batch();
+        // The editor holds a waker, so the channel never disconnects.
+        let wakeup = match timeout {
+            Some(timeout) => wakeups.recv_timeout(timeout).ok(),
+            None => wakeups.recv().ok(),
+        };
+        let ready = wakeup.is_some();
+        if let Some(wakeup) = wakeup {
+            handle_wakeup(&mut editor, &mut plugins, &mut screen, wakeup)?;
+            while !editor.should_quit
+                && let Ok(wakeup) = wakeups.try_recv()
+            {
+                handle_wakeup(&mut editor, &mut plugins, &mut screen, wakeup)?;
             }
+            scheduler.request();
+        }
+        if let Some(job) = editor.terminal_job.take() {
+            run_terminal_job(&mut editor, &mut screen, &gate, job)?;
+            run_startup(&mut editor, &mut plugins, &mut startup);
+            scheduler.request();
+        }
+        if std::mem::take(&mut editor.suspend_requested) {
+            suspend(&mut editor, &mut screen, &gate)?;
+            scheduler.request();
         }
+        if editor.poll_loading() {
+            run_startup(&mut editor, &mut plugins, &mut startup);
+            scheduler.request();
+        }
+        if !editor.autocmds.is_empty() {
+            // A file read in the background, or once decrypted.
+            run_commands(&mut editor, &mut plugins);
+        }
+        editor.file_index.poll();
+        editor.end_batch();
 
         if editor.should_quit {
             break;
         }
 
-        render(&editor, &mut plugins)?;
+        if !ready && idle_work {
+            idle_work = run_idle(&editor, &mut plugins);
+            if idle_work {
+                scheduler.request();
+            }
+        }
+
+        let now = Instant::now();
+        if scheduler.wait_time(now) == Some(Duration::ZERO) {
+            render(&editor, &mut plugins, &mut screen, &mut backend)?;
+            scheduler.rendered(now);
+            idle_work = true;
+        }
     }
 
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Handles one message to the main loop. Input goes to the plugins; a
+/// redraw request needs nothing more, as the loop polls background work and
+/// draws a frame after every message.
+fn handle_wakeup(
+    editor: &mut Editor,
+    plugins: &mut [Box<dyn Plugin>],
+    screen: &mut Screen,
+    wakeup: Wakeup,
+) -> io::Result<()> {
+    match wakeup {
+        Wakeup::Input(Ok(event)) => handle_event(editor, plugins, screen, &event),
+        // The terminal is gone or cannot be read.
+        Wakeup::Input(Err(err)) => return Err(emergency_exit(editor, err.to_string())),
+        Wakeup::Terminate(signal) => {
+            return Err(emergency_exit(editor, format!("{} received", signal)));
+        }
+        Wakeup::Redraw => {}
+    }
     Ok(())
 }
 
-fn render(editor: &Editor, plugins: &mut [Box<dyn Plugin>]) -> io::Result<()> {
-    let mut ctx = RenderContext::new(editor.screen_width, editor.screen_height);
-    for plugin in plugins.iter_mut() {
-        plugin.on_render(editor, &mut ctx);
+/// The error to exit with when the editor must stop at once, once the
+/// unsaved buffers are written to the recovery directory. The terminal is
+/// restored as the error unwinds through `TerminalGuard`.
+fn emergency_exit(editor: &Editor, reason: String) -> io::Error {
+    let message = match editor.emergency_save() {
+        Ok(saved) if saved.is_empty() => reason,
+        Ok(saved) => {
+            let saved: Vec<String> = saved
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            format!(
+                "{}; unsaved changes written to {}",
+                reason,
+                saved.join(", ")
+            )
+        }
+        Err(err) => format!("{}; cannot save unsaved changes: {}", reason, err),
+    };
+    io::Error::other(message)
+}
+
+fn handle_event(
+    editor: &mut Editor,
+    plugins: &mut [Box<dyn Plugin>],
+    screen: &mut Screen,
+    event: &Event,
+) {
+    if let Event::Resize(width, height) = *event {
+        editor.set_screen_size(width, height);
+        screen.invalidate();
     }
 
-    let mut stdout = io::stdout();
-    queue!(stdout, cursor::Hide, Clear(ClearType::All))?;
-    for (row, line) in ctx.lines.iter().enumerate() {
-        queue!(
-            stdout,
-            cursor::MoveTo(0, row as u16),
-            Clear(ClearType::CurrentLine)
-        )?;
-        let spans = ctx.spans.get(row).map(Vec::as_slice).unwrap_or(&[]);
-        render_line(&mut stdout, line, spans, ctx.width as usize)?;
+    dispatch_event(editor, plugins, event);
+    run_commands(editor, plugins);
+}
+
+/// Offers `event` to the plugins in order until one consumes it.
+fn dispatch_event(editor: &mut Editor, plugins: &mut [Box<dyn Plugin>], event: &Event) {
+    let consumed = plugins
+        .iter_mut()
+        .any(|plugin| plugin.on_event(editor, event) == EventResult::Consumed);
+    if !consumed && matches!(event, Event::Key(_)) {
+        // No plugin continues the pending sequence, so drop it.
+        editor.pending_keys.clear();
+        editor.pending_register = None;
     }
+}
 
-    if let Some((row, col)) = ctx.cursor {
-        queue!(stdout, cursor::MoveTo(col, row), cursor::Show)?;
-    } else {
-        queue!(stdout, cursor::Hide)?;
+/// Gives every plugin a slice of idle time. Returns true if any did work.
+fn run_idle(editor: &Editor, plugins: &mut [Box<dyn Plugin>]) -> bool {
+    let mut worked = false;
+    for plugin in plugins.iter_mut() {
+        worked |= plugin.on_idle(editor);
     }
+    worked
+}
 
-    stdout.flush()
+/// Dispatches fired autocommand events and queued ex commands to plugins,
+/// including any they queue in turn, then the keys fed with
+/// `Editor::feed_keys`, and fires the events for what they changed. Returns
+/// false if any command was not handled by a plugin.
+fn run_commands(editor: &mut Editor, plugins: &mut [Box<dyn Plugin>]) -> bool {
+    let mut all_handled = true;
+    loop {
+        if let Some((event, name)) = editor.next_event() {
+            for plugin in plugins.iter_mut() {
+                plugin.on_autocmd(editor, event, &name);
+            }
+        } else if let Some(command) = editor.next_command() {
+            let handled = plugins
+                .iter_mut()
+                .any(|plugin| plugin.on_command(editor, &command) == EventResult::Consumed);
+            if !handled {
+                editor.set_error(format!("Not an editor command: {}", command));
+                all_handled = false;
+            }
+        } else if let Some(event) = editor.next_input() {
+            dispatch_event(editor, plugins, &event);
+        } else {
+            editor.fire_state_events();
+            if editor.autocmds.is_empty() {
+                break;
+            }
+        }
+    }
+    // Everything one key or command changed is undone together, and a whole
+    // Insert-mode session is one step, committed by the key that leaves it.
+    if editor.mode != Mode::Insert {
+        editor.commit_undo();
+    }
+    all_handled
 }
 
-fn render_line(
-    stdout: &mut impl Write,
-    line: &str,
-    spans: &[StyledSpan],
-    width: usize,
+fn render(
+    editor: &Editor,
+    plugins: &mut [Box<dyn Plugin>],
+    screen: &mut Screen,
+    backend: &mut impl RenderBackend,
 ) -> io::Result<()> {
-    let mut line_chars: Vec<char> = line.chars().collect();
-    if width == 0 {
-        return Ok(());
+    let mut ctx = RenderContext::new(editor.screen_width, editor.screen_height);
+    for plugin in plugins.iter_mut() {
+        plugin.on_render(editor, &mut ctx);
     }
-    if line_chars.len() > width {
-        line_chars.truncate(width);
+    screen.draw(editor, &ctx, backend)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    #[test]
+    fn an_insert_session_is_undone_in_one_step() {
+        let mut editor = Editor::new(80, 24, None);
+        let mut plugins = default_plugins();
+        let mut screen = Screen::new();
+        let mut press = |editor: &mut Editor, keys: &str| {
+            for ch in keys.chars() {
+                let code = match ch {
+                    '\r' => KeyCode::Enter,
+                    '\x1b' => KeyCode::Esc,
+                    ch => KeyCode::Char(ch),
+                };
+                let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+                handle_event(editor, &mut plugins, &mut screen, &event);
+            }
+        };
+        press(&mut editor, "ione\rtwo(\x1b");
+        assert_eq!(editor.buffer.lines, ["one", "two()"]);
+        press(&mut editor, "oab\x1b");
+        assert_eq!(editor.buffer.lines, ["one", "two()", "ab"]);
+        press(&mut editor, "u");
+        assert_eq!(editor.buffer.lines, ["one", "two()"]);
+        press(&mut editor, "u");
+        assert_eq!(editor.buffer.lines, [""]);
     }
 
-    if spans.is_empty() {
-        let rendered: String = line_chars.iter().collect();
-        queue!(stdout, Print(rendered))?;
-        let padding = width.saturating_sub(line_chars.len());
-        if padding > 0 {
-            queue!(stdout, Print(" ".repeat(padding)))?;
+    #[test]
+    fn ctrl_o_jumps_back_instead_of_opening_a_line() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = (0..50).map(|n| n.to_string()).collect();
+        let mut plugins = default_plugins();
+        let mut screen = Screen::new();
+        for (code, modifiers) in [
+            (KeyCode::Char('G'), KeyModifiers::SHIFT),
+            (KeyCode::Char('o'), KeyModifiers::CONTROL),
+        ] {
+            let event = Event::Key(KeyEvent::new(code, modifiers));
+            handle_event(&mut editor, &mut plugins, &mut screen, &event);
         }
-        return Ok(());
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.buffer.lines.len(), 50);
+        assert_eq!(editor.cursor.row, 0);
     }
 
-    let mut spans_sorted = spans.to_vec();
-    spans_sorted.sort_by_key(|span| span.start);
-    let mut cursor = 0usize;
-    let line_len = line_chars.len();
-
-    for span in spans_sorted {
-        let span_start = span.start.min(width).min(line_len);
-        if span_start > cursor {
-            let segment: String = line_chars[cursor..span_start].iter().collect();
-            queue!(stdout, Print(segment))?;
+    #[test]
+    fn autocommands_run_around_writes_and_mode_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "x\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        let mut plugins = default_plugins();
+        init_plugins(&mut editor, &mut plugins);
+        for command in [
+            "autocmd BufWritePre *.txt %s/x/y/g",
+            "autocmd BufWritePre *.txt w",
+            "autocmd BufWritePre *.md %s/y/z/g",
+            "autocmd InsertLeave * s/$/!/",
+            "autocmd BufReadPost * s/^/read /",
+        ] {
+            editor.push_command(command.to_string());
         }
+        editor.push_command("w".to_string());
+        assert!(run_commands(&mut editor, &mut plugins));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "y\n");
+        assert!(!editor.dirty);
 
-        let span_end = span.start.saturating_add(span.len).min(width).min(line_len);
-        if span_end > span_start {
-            let segment: String = line_chars[span_start..span_end].iter().collect();
-            queue!(stdout, PrintStyledContent(span.style.apply(segment)))?;
+        let mut screen = Screen::new();
+        for code in [KeyCode::Char('i'), KeyCode::Char('a'), KeyCode::Esc] {
+            let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+            handle_event(&mut editor, &mut plugins, &mut screen, &event);
         }
-        cursor = span_end;
+        assert_eq!(editor.buffer.lines, ["ay!"]);
+
+        editor.push_command(format!("e! {}", path.display()));
+        run_commands(&mut editor, &mut plugins);
+        assert_eq!(editor.buffer.lines, ["read y"]);
+
+        // Events fired as another file is opened match that file's name.
+        editor.push_command("autocmd BufNewFile *.md s/^/new /".to_string());
+        editor.push_command(format!("e! {}", dir.path().join("other.md").display()));
+        run_commands(&mut editor, &mut plugins);
+        assert_eq!(editor.buffer.lines, ["new "]);
     }
 
-    if cursor < line_len {
-        let segment: String = line_chars[cursor..line_len].iter().collect();
-        queue!(stdout, Print(segment))?;
+    #[test]
+    fn palette_runs_key_bindings_through_the_plugins() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["one", "two", "three"].map(String::from).to_vec();
+        editor.cursor.row = 2;
+        let mut plugins = default_plugins();
+        for plugin in &plugins {
+            editor.registry.add(plugin.as_ref());
+        }
+        let mut screen = Screen::new();
+        let ctrl_shift_p = Event::Key(KeyEvent::new(
+            KeyCode::Char('p'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+        ));
+        handle_event(&mut editor, &mut plugins, &mut screen, &ctrl_shift_p);
+        for ch in "first line\r".chars() {
+            let code = match ch {
+                '\r' => KeyCode::Enter,
+                ch => KeyCode::Char(ch),
+            };
+            let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+            handle_event(&mut editor, &mut plugins, &mut screen, &event);
+        }
+        assert!(editor.palette.is_none());
+        assert_eq!(editor.cursor.row, 0);
     }
 
-    let padding = width.saturating_sub(line_len);
-    if padding > 0 {
-        queue!(stdout, Print(" ".repeat(padding)))?;
+    #[test]
+    fn frame_scheduler_renders_idle_requests_immediately() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new();
+        assert_eq!(scheduler.wait_time(start), None);
+        scheduler.request();
+        assert_eq!(scheduler.wait_time(start), Some(Duration::ZERO));
+        scheduler.rendered(start);
+        assert_eq!(scheduler.wait_time(start), None);
+
+        let later = start + Duration::from_millis(100);
+        scheduler.request();
+        assert_eq!(scheduler.wait_time(later), Some(Duration::ZERO));
     }
 
-    Ok(())
+    #[test]
+    fn frame_scheduler_throttles_bursts() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new();
+        scheduler.rendered(start);
+        scheduler.request();
+        let soon = start + Duration::from_millis(6);
+        assert_eq!(scheduler.wait_time(soon), Some(Duration::from_millis(10)));
+        let next = start + FRAME_INTERVAL;
+        assert_eq!(scheduler.wait_time(next), Some(Duration::ZERO));
+    }
 }
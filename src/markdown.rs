@@ -0,0 +1,351 @@
+//! Markdown drawn for `:Preview`: headings, emphasis, inline code, links,
+//! lists, block quotes, rules and fenced code blocks, each source line
+//! wrapped to the width of the preview on its own so every preview line
+//! knows the line it came from.
+
+use crossterm::style::{Attribute, ContentStyle};
+use unicode_width::UnicodeWidthChar;
+
+use crate::editor::StyledSpan;
+
+/// The styles of the preview that the theme sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreviewStyles {
+    pub heading: ContentStyle,
+    pub code: ContentStyle,
+}
+
+/// One line of the preview.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewLine {
+    pub text: String,
+    /// Styles by display column.
+    pub spans: Vec<StyledSpan>,
+    /// The buffer row it shows.
+    pub source_row: usize,
+}
+
+/// `lines` rendered `width` columns wide.
+pub fn render(lines: &[String], width: usize, styles: PreviewStyles) -> Vec<PreviewLine> {
+    let width = width.max(1);
+    let mut preview = Vec::new();
+    let mut in_code = false;
+    for (row, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        let (prefix, cells) = if in_code {
+            let code = line.replace('\t', "    ");
+            (String::from("  "), plain(&code, styles.code))
+        } else {
+            block(line, width, styles)
+        };
+        for (text, spans) in wrap(&prefix, &cells, width) {
+            preview.push(PreviewLine {
+                text,
+                spans,
+                source_row: row,
+            });
+        }
+    }
+    preview
+}
+
+/// A char and the style it is drawn with.
+type Cell = (char, ContentStyle);
+
+/// The prefix drawn before a line outside code blocks, such as a bullet,
+/// and its text with inline styles.
+fn block(line: &str, width: usize, styles: PreviewStyles) -> (String, Vec<Cell>) {
+    let indent = line.len() - line.trim_start().len();
+    let text = line.trim_start();
+    let hashes = text.chars().take_while(|ch| *ch == '#').count();
+    if (1..=6).contains(&hashes) && (text[hashes..].starts_with(' ') || text[hashes..].is_empty()) {
+        let mut style = styles.heading;
+        style.attributes.set(Attribute::Bold);
+        if hashes == 1 {
+            style.attributes.set(Attribute::Underlined);
+        }
+        let title = text[hashes..].trim().trim_end_matches('#').trim_end();
+        return (String::new(), inline(title, style, styles.code));
+    }
+    if is_rule(text) {
+        let rule = "─".repeat(width);
+        return (String::new(), plain(&rule, ContentStyle::new()));
+    }
+    if let Some(quote) = text.strip_prefix('>') {
+        let mut style = ContentStyle::new();
+        style.attributes.set(Attribute::Italic);
+        let cells = inline(quote.trim_start(), style, styles.code);
+        return (format!("{}│ ", " ".repeat(indent)), cells);
+    }
+    let pad = " ".repeat(indent);
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(item) = text.strip_prefix(bullet) {
+            let (mark, item) = match item.strip_prefix("[ ] ") {
+                Some(rest) => ("☐ ", rest),
+                None => match item
+                    .strip_prefix("[x] ")
+                    .or_else(|| item.strip_prefix("[X] "))
+                {
+                    Some(rest) => ("☑ ", rest),
+                    None => ("• ", item),
+                },
+            };
+            let cells = inline(item, ContentStyle::new(), styles.code);
+            return (format!("{}{}", pad, mark), cells);
+        }
+    }
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0
+        && let Some(item) = text[digits..]
+            .strip_prefix(". ")
+            .or_else(|| text[digits..].strip_prefix(") "))
+    {
+        let cells = inline(item, ContentStyle::new(), styles.code);
+        return (format!("{}{}. ", pad, &text[..digits]), cells);
+    }
+    (pad, inline(text, ContentStyle::new(), styles.code))
+}
+
+/// Whether `text` is a thematic break: three or more `-`, `*` or `_`.
+fn is_rule(text: &str) -> bool {
+    let marks: String = text.chars().filter(|ch| !ch.is_whitespace()).collect();
+    marks.len() >= 3
+        && ["-", "*", "_"]
+            .iter()
+            .any(|mark| marks.chars().all(|ch| ch.to_string() == *mark))
+}
+
+fn plain(text: &str, style: ContentStyle) -> Vec<Cell> {
+    text.chars().map(|ch| (ch, style)).collect()
+}
+
+/// `text` with its `**strong**`, `*emphasis*`, `` `code` `` and
+/// `[link](url)` markup turned into styles over `base`.
+fn inline(text: &str, base: ContentStyle, code: ContentStyle) -> Vec<Cell> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut cells = Vec::new();
+    let (mut strong, mut emphasis) = (false, false);
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).copied();
+        match ch {
+            '\\' if next.is_some_and(|next| next.is_ascii_punctuation()) => {
+                cells.push((chars[i + 1], styled(base, strong, emphasis)));
+                i += 2;
+                continue;
+            }
+            '`' => {
+                if let Some(len) = chars[i + 1..].iter().position(|ch| *ch == '`') {
+                    cells.extend(chars[i + 1..i + 1 + len].iter().map(|ch| (*ch, code)));
+                    i += len + 2;
+                    continue;
+                }
+            }
+            '*' | '_' if next == Some(ch) => {
+                strong = !strong;
+                i += 2;
+                continue;
+            }
+            '*' | '_' => {
+                let prev = i.checked_sub(1).map(|prev| chars[prev]);
+                let word = |ch: Option<char>| ch.is_some_and(char::is_alphanumeric);
+                // Underscores inside words, as in snake_case, are text.
+                if ch == '*' || !word(prev) || !word(next) {
+                    emphasis = !emphasis;
+                    i += 1;
+                    continue;
+                }
+            }
+            '[' => {
+                if let Some((label, end)) = link(&chars, i) {
+                    let mut style = styled(base, strong, emphasis);
+                    style.attributes.set(Attribute::Underlined);
+                    cells.extend(label.iter().map(|ch| (*ch, style)));
+                    i = end;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        cells.push((ch, styled(base, strong, emphasis)));
+        i += 1;
+    }
+    cells
+}
+
+fn styled(base: ContentStyle, strong: bool, emphasis: bool) -> ContentStyle {
+    let mut style = base;
+    if strong {
+        style.attributes.set(Attribute::Bold);
+    }
+    if emphasis {
+        style.attributes.set(Attribute::Italic);
+    }
+    style
+}
+
+/// The label of a `[label](url)` link starting at `start`, and the index
+/// after it.
+fn link(chars: &[char], start: usize) -> Option<(&[char], usize)> {
+    let close = start + chars[start..].iter().position(|ch| *ch == ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 1 + chars[close + 1..].iter().position(|ch| *ch == ')')?;
+    Some((&chars[start + 1..close], end + 1))
+}
+
+/// `cells` after `prefix`, broken at spaces into lines `width` columns wide;
+/// lines after the first are indented to line up under the text.
+fn wrap(prefix: &str, cells: &[Cell], width: usize) -> Vec<(String, Vec<StyledSpan>)> {
+    let indent: usize = prefix.chars().filter_map(|ch| ch.width()).sum();
+    let indent = if indent * 2 > width { 0 } else { indent };
+    let room = width.saturating_sub(indent).max(1);
+    let mut lines = Vec::new();
+    let mut rest = cells;
+    let mut first = true;
+    loop {
+        let mut used = 0;
+        let mut fit = 0;
+        for (ch, _) in rest {
+            let w = ch.width().unwrap_or(0);
+            if used + w > room {
+                break;
+            }
+            used += w;
+            fit += 1;
+        }
+        let mut take = fit;
+        if fit < rest.len()
+            && let Some(space) = rest[..fit].iter().rposition(|(ch, _)| *ch == ' ')
+            && space > 0
+        {
+            take = space + 1;
+        }
+        let take = take.max(1).min(rest.len());
+        let lead = if first {
+            prefix.to_string()
+        } else {
+            " ".repeat(indent)
+        };
+        lines.push(line_with_spans(lead, &rest[..take]));
+        rest = &rest[take..];
+        first = false;
+        if rest.is_empty() {
+            break;
+        }
+    }
+    lines
+}
+
+/// The text of `cells` after `lead`, with a span for each run of one style
+/// other than the default, trailing spaces dropped.
+fn line_with_spans(lead: String, cells: &[Cell]) -> (String, Vec<StyledSpan>) {
+    let mut col = lead.chars().filter_map(|ch| ch.width()).sum();
+    let mut text = lead;
+    let mut spans: Vec<StyledSpan> = Vec::new();
+    let end = cells
+        .iter()
+        .rposition(|(ch, _)| *ch != ' ')
+        .map_or(0, |last| last + 1);
+    for (ch, style) in &cells[..end] {
+        let w = ch.width().unwrap_or(0);
+        text.push(*ch);
+        if *style != ContentStyle::new() {
+            match spans.last_mut() {
+                Some(span) if span.style == *style && span.start + span.len == col => {
+                    span.len += w;
+                }
+                _ => spans.push(StyledSpan {
+                    start: col,
+                    len: w,
+                    style: *style,
+                }),
+            }
+        }
+        col += w;
+    }
+    (text, spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::Color;
+
+    fn styles() -> PreviewStyles {
+        PreviewStyles {
+            heading: ContentStyle {
+                foreground_color: Some(Color::Magenta),
+                ..ContentStyle::new()
+            },
+            code: ContentStyle {
+                foreground_color: Some(Color::Green),
+                ..ContentStyle::new()
+            },
+        }
+    }
+
+    fn texts(markdown: &str, width: usize) -> Vec<String> {
+        let lines: Vec<String> = markdown.lines().map(String::from).collect();
+        render(&lines, width, styles())
+            .into_iter()
+            .map(|line| line.text)
+            .collect()
+    }
+
+    #[test]
+    fn blocks_are_drawn_without_their_markup() {
+        let markdown = "# Title #\n\n- one\n  * two\n3. three\n- [x] done\n> quoted\n---\n```\nlet x = 1;\n```\nplain";
+        assert_eq!(
+            texts(markdown, 12),
+            [
+                "Title",
+                "",
+                "• one",
+                "  • two",
+                "3. three",
+                "☑ done",
+                "│ quoted",
+                "────────────",
+                "  let x = 1;",
+                "plain",
+            ]
+        );
+        let lines: Vec<String> = markdown.lines().map(String::from).collect();
+        let preview = render(&lines, 12, styles());
+        assert_eq!(
+            preview[0].spans[0].style.foreground_color,
+            Some(Color::Magenta)
+        );
+        assert_eq!(preview[8].source_row, 9);
+        assert_eq!(
+            preview[8].spans[0].style.foreground_color,
+            Some(Color::Green)
+        );
+    }
+
+    #[test]
+    fn inline_markup_becomes_styles_and_long_lines_wrap() {
+        let lines = vec!["a **bold** and *it* `x` [site](http://a.b) snake_case".to_string()];
+        let preview = render(&lines, 80, styles());
+        assert_eq!(preview[0].text, "a bold and it x site snake_case");
+        let spans = &preview[0].spans;
+        assert_eq!((spans[0].start, spans[0].len), (2, 4));
+        assert!(spans[0].style.attributes.has(Attribute::Bold));
+        assert!(spans[1].style.attributes.has(Attribute::Italic));
+        assert_eq!(spans[2].style.foreground_color, Some(Color::Green));
+        assert!(spans[3].style.attributes.has(Attribute::Underlined));
+
+        assert_eq!(
+            texts("- the quick brown fox jumps", 12),
+            ["• the quick", "  brown fox", "  jumps"]
+        );
+        assert_eq!(texts("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+    }
+}
@@ -0,0 +1,140 @@
+//! The dropdown menu shown under the word being completed in Insert mode.
+//! The plugin that opens it keeps handling the keys: `Menu::handle_key`
+//! moves the selection and tells it what was chosen.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::editor::Cursor;
+
+/// Most items shown at once; the menu scrolls to keep the selection in view.
+pub const MENU_MAX_HEIGHT: usize = 10;
+
+/// Where a completion came from, shown as a letter before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemKind {
+    /// A word from the buffer.
+    Word,
+    /// A word from the `dictionary` files.
+    Dictionary,
+    /// A word on a `thesaurus` line with the one typed.
+    Thesaurus,
+}
+
+impl ItemKind {
+    pub fn icon(self) -> char {
+        match self {
+            ItemKind::Word => 'w',
+            ItemKind::Dictionary => 'k',
+            ItemKind::Thesaurus => 't',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuItem {
+    pub text: String,
+    pub kind: ItemKind,
+}
+
+/// What a key did to the menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    /// The selection moved, to an item or back to the typed text (`None`).
+    Moved(Option<usize>),
+    /// Enter took the selected item, if any.
+    Accept(Option<usize>),
+    /// Esc dismissed the menu.
+    Cancel,
+    /// The key is not for the menu.
+    Other,
+}
+
+/// Candidates listed under the cursor, one of them possibly selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Menu {
+    pub items: Vec<MenuItem>,
+    /// The selected item; `None` while the typed text is shown.
+    pub selected: Option<usize>,
+    /// The start of the word being completed, which the menu hangs under.
+    pub anchor: Cursor,
+}
+
+impl Menu {
+    pub fn new(items: Vec<MenuItem>, anchor: Cursor) -> Self {
+        Self {
+            items,
+            selected: None,
+            anchor,
+        }
+    }
+
+    /// Moves the selection down (`forward`) or up, passing through the
+    /// typed text between the last item and the first.
+    pub fn step(&mut self, forward: bool) {
+        let slots = self.items.len() + 1;
+        let index = self.selected.unwrap_or(self.items.len());
+        let index = if forward {
+            (index + 1) % slots
+        } else {
+            (index + slots - 1) % slots
+        };
+        self.selected = (index < self.items.len()).then_some(index);
+    }
+
+    /// Handles `Ctrl-N`/`Down`, `Ctrl-P`/`Up`, `Enter` and `Esc`.
+    pub fn handle_key(&mut self, key: &KeyEvent) -> MenuAction {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('n') if control => self.step(true),
+            KeyCode::Down => self.step(true),
+            KeyCode::Char('p') if control => self.step(false),
+            KeyCode::Up => self.step(false),
+            KeyCode::Enter => return MenuAction::Accept(self.selected),
+            KeyCode::Esc => return MenuAction::Cancel,
+            _ => return MenuAction::Other,
+        }
+        MenuAction::Moved(self.selected)
+    }
+
+    /// The first item shown when `height` fit, keeping the selection in view.
+    pub fn first_visible(&self, height: usize) -> usize {
+        let selected = self.selected.unwrap_or(0);
+        (selected + 1)
+            .saturating_sub(height)
+            .min(self.items.len().saturating_sub(height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn selection_cycles_through_the_typed_text() {
+        let items = ["alpha", "alpine", "also"]
+            .map(|text| MenuItem {
+                text: text.to_string(),
+                kind: ItemKind::Word,
+            })
+            .to_vec();
+        let mut menu = Menu::new(items, Cursor { row: 0, col: 0 });
+        let ctrl_n = key(KeyCode::Char('n'), KeyModifiers::CONTROL);
+        let ctrl_p = key(KeyCode::Char('p'), KeyModifiers::CONTROL);
+        assert_eq!(menu.handle_key(&ctrl_n), MenuAction::Moved(Some(0)));
+        assert_eq!(menu.handle_key(&ctrl_p), MenuAction::Moved(None));
+        assert_eq!(menu.handle_key(&ctrl_p), MenuAction::Moved(Some(2)));
+        assert_eq!(menu.first_visible(2), 1);
+        assert_eq!(
+            menu.handle_key(&key(KeyCode::Enter, KeyModifiers::NONE)),
+            MenuAction::Accept(Some(2))
+        );
+        assert_eq!(
+            menu.handle_key(&key(KeyCode::Char('x'), KeyModifiers::NONE)),
+            MenuAction::Other
+        );
+    }
+}
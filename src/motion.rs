@@ -0,0 +1,963 @@
+//! Cursor motions and text objects, shared by Normal-mode movement and the
+//! operators that act on the text they cover.
+
+use crate::editor::{Cursor, is_word_char, next_grapheme_boundary};
+
+/// Outcome of parsing a key sequence that may need more keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parse<T> {
+    /// The keys so far are a prefix of something valid.
+    Pending,
+    /// The keys can never become valid.
+    Invalid,
+    Done(T),
+}
+
+/// A region of the buffer from `start` up to (not including) `end`. Linewise
+/// ranges cover every line from `start.row` through `end.row`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextRange {
+    pub start: Cursor,
+    pub end: Cursor,
+    pub linewise: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    /// `w`, or `W` when `big`.
+    WordForward {
+        big: bool,
+    },
+    /// `b`, or `B` when `big`.
+    WordBackward {
+        big: bool,
+    },
+    /// `e`, or `E` when `big`.
+    WordEnd {
+        big: bool,
+    },
+    /// `]w`: the start of the next camelCase hump or `_`-separated part.
+    SubwordForward,
+    /// `[w`.
+    SubwordBackward,
+    ParagraphForward,
+    ParagraphBackward,
+    FirstLine,
+    LastLine,
+}
+
+impl Motion {
+    pub fn parse(keys: &str) -> Parse<Motion> {
+        let motion = match keys {
+            "h" => Motion::Left,
+            "l" => Motion::Right,
+            "k" => Motion::Up,
+            "j" => Motion::Down,
+            "0" => Motion::LineStart,
+            "^" => Motion::FirstNonBlank,
+            "$" => Motion::LineEnd,
+            "w" => Motion::WordForward { big: false },
+            "W" => Motion::WordForward { big: true },
+            "b" => Motion::WordBackward { big: false },
+            "B" => Motion::WordBackward { big: true },
+            "e" => Motion::WordEnd { big: false },
+            "E" => Motion::WordEnd { big: true },
+            "}" => Motion::ParagraphForward,
+            "{" => Motion::ParagraphBackward,
+            "gg" => Motion::FirstLine,
+            "G" => Motion::LastLine,
+            "]w" => Motion::SubwordForward,
+            "[w" => Motion::SubwordBackward,
+            "g" | "]" | "[" => return Parse::Pending,
+            _ => return Parse::Invalid,
+        };
+        Parse::Done(motion)
+    }
+
+    /// Whether an operator over this motion acts on whole lines.
+    pub fn linewise(self) -> bool {
+        matches!(
+            self,
+            Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine
+        )
+    }
+
+    /// Whether an operator over this motion includes the target character.
+    pub fn inclusive(self) -> bool {
+        matches!(self, Motion::WordEnd { .. })
+    }
+
+    /// Where the motion moves the cursor from `from`.
+    pub fn target(self, lines: &[String], from: Cursor) -> Cursor {
+        let text = Text(lines);
+        let last_row = lines.len().saturating_sub(1);
+        match self {
+            Motion::Left => Cursor {
+                col: from.col.saturating_sub(1),
+                ..from
+            },
+            Motion::Right => Cursor {
+                col: (from.col + 1).min(text.len(from.row)),
+                ..from
+            },
+            Motion::Up => Cursor {
+                row: from.row.saturating_sub(1),
+                col: from.col.min(text.len(from.row.saturating_sub(1))),
+            },
+            Motion::Down => {
+                let row = (from.row + 1).min(last_row);
+                Cursor {
+                    row,
+                    col: from.col.min(text.len(row)),
+                }
+            }
+            Motion::LineStart => Cursor { col: 0, ..from },
+            Motion::FirstNonBlank => Cursor {
+                col: text.first_non_blank(from.row),
+                ..from
+            },
+            Motion::LineEnd => Cursor {
+                col: text.len(from.row),
+                ..from
+            },
+            Motion::WordForward { big } => text.word_forward(from, big),
+            Motion::WordBackward { big } => text.word_backward(from, big),
+            Motion::WordEnd { big } => text.word_end(from, big),
+            Motion::SubwordForward => text.subword(from, true),
+            Motion::SubwordBackward => text.subword(from, false),
+            Motion::ParagraphForward => text.paragraph(from, true),
+            Motion::ParagraphBackward => text.paragraph(from, false),
+            Motion::FirstLine => Cursor {
+                row: 0,
+                col: text.first_non_blank(0),
+            },
+            Motion::LastLine => Cursor {
+                row: last_row,
+                col: text.first_non_blank(last_row),
+            },
+        }
+    }
+
+    /// The text an operator covers when applied with this motion from `from`.
+    pub fn range(self, lines: &[String], from: Cursor) -> TextRange {
+        let target = self.target(lines, from);
+        let (start, mut end) = if target < from {
+            (target, from)
+        } else {
+            (from, target)
+        };
+        let text = Text(lines);
+        if self.inclusive() {
+            end.col = lines
+                .get(end.row)
+                .map_or(end.col, |line| next_grapheme_boundary(line, end.col));
+        } else if end.col == 0 && end.row > start.row && !self.linewise() {
+            // An exclusive motion that lands at the start of a later line
+            // stops at the end of the line before it, as in Vim.
+            end = Cursor {
+                row: end.row - 1,
+                col: text.len(end.row - 1),
+            };
+        }
+        TextRange {
+            start,
+            end,
+            linewise: self.linewise(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObject {
+    /// `iw`/`aw`, or `iW`/`aW` when `big`.
+    Word { big: bool, around: bool },
+    /// `i(`, `a{` and friends.
+    Pair {
+        open: char,
+        close: char,
+        around: bool,
+    },
+    /// `i"`, `a'` and `` i` ``.
+    Quote { quote: char, around: bool },
+    /// `ip`/`ap`.
+    Paragraph { around: bool },
+    /// `iv`/`av`: a camelCase hump or `_`-separated part of an identifier.
+    Subword { around: bool },
+}
+
+impl TextObject {
+    pub fn parse(keys: &str) -> Parse<TextObject> {
+        let mut chars = keys.chars();
+        let around = match chars.next() {
+            Some('i') => false,
+            Some('a') => true,
+            Some(_) => return Parse::Invalid,
+            None => return Parse::Pending,
+        };
+        let Some(kind) = chars.next() else {
+            return Parse::Pending;
+        };
+        if chars.next().is_some() {
+            return Parse::Invalid;
+        }
+        let object = match kind {
+            'w' => TextObject::Word { big: false, around },
+            'W' => TextObject::Word { big: true, around },
+            'p' => TextObject::Paragraph { around },
+            'v' => TextObject::Subword { around },
+            '"' | '\'' | '`' => TextObject::Quote {
+                quote: kind,
+                around,
+            },
+            _ => match pair_for(kind) {
+                Some((open, close)) => TextObject::Pair {
+                    open,
+                    close,
+                    around,
+                },
+                None => return Parse::Invalid,
+            },
+        };
+        Parse::Done(object)
+    }
+
+    /// The text the object covers around `cursor`, if there is one.
+    pub fn range(self, lines: &[String], cursor: Cursor) -> Option<TextRange> {
+        let text = Text(lines);
+        match self {
+            TextObject::Word { big, around } => text.word_object(cursor, big, around),
+            TextObject::Pair {
+                open,
+                close,
+                around,
+            } => {
+                let (open_at, close_at) = text.enclosing_pair(cursor, open, close)?;
+                let (start, end) = if around {
+                    (
+                        open_at,
+                        Cursor {
+                            col: close_at.col + 1,
+                            ..close_at
+                        },
+                    )
+                } else {
+                    (
+                        Cursor {
+                            col: open_at.col + 1,
+                            ..open_at
+                        },
+                        close_at,
+                    )
+                };
+                Some(TextRange {
+                    start,
+                    end,
+                    linewise: false,
+                })
+            }
+            TextObject::Quote { quote, around } => text.quote_object(cursor, quote, around),
+            TextObject::Paragraph { around } => Some(text.paragraph_object(cursor, around)),
+            TextObject::Subword { around } => text.subword_object(cursor, around),
+        }
+    }
+}
+
+/// Opening and closing characters for a pair named by either delimiter or
+/// by Vim's `b` (parentheses) and `B` (braces) aliases.
+pub fn pair_for(ch: char) -> Option<(char, char)> {
+    match ch {
+        '(' | ')' | 'b' => Some(('(', ')')),
+        '{' | '}' | 'B' => Some(('{', '}')),
+        '[' | ']' => Some(('[', ']')),
+        '<' | '>' => Some(('<', '>')),
+        _ => None,
+    }
+}
+
+/// Parses what follows an operator: a motion or a text object.
+pub fn parse_target(keys: &str) -> Parse<Target> {
+    if keys.starts_with(['i', 'a']) {
+        return match TextObject::parse(keys) {
+            Parse::Done(object) => Parse::Done(Target::Object(object)),
+            Parse::Pending => Parse::Pending,
+            Parse::Invalid => Parse::Invalid,
+        };
+    }
+    match Motion::parse(keys) {
+        Parse::Done(motion) => Parse::Done(Target::Motion(motion)),
+        Parse::Pending => Parse::Pending,
+        Parse::Invalid => Parse::Invalid,
+    }
+}
+
+/// What an operator acts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Motion(Motion),
+    Object(TextObject),
+}
+
+impl Target {
+    pub fn range(self, lines: &[String], cursor: Cursor) -> Option<TextRange> {
+        match self {
+            Target::Motion(motion) => Some(motion.range(lines, cursor)),
+            Target::Object(object) => object.range(lines, cursor),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Punctuation,
+    Word,
+}
+
+fn class_of(ch: Option<char>, big: bool) -> CharClass {
+    match ch {
+        None => CharClass::Blank,
+        Some(ch) if ch.is_whitespace() => CharClass::Blank,
+        Some(_) if big => CharClass::Word,
+        Some(ch) if is_word_char(ch) => CharClass::Word,
+        Some(_) => CharClass::Punctuation,
+    }
+}
+
+/// Whether `ch` starts a sub-word given the characters around it: the
+/// start of an identifier, the part after an `_`, or a camelCase hump. A run
+/// of capitals is one sub-word, as `HTTP` in `HTTPServer`, and punctuation
+/// runs count as sub-words of their own.
+pub fn is_subword_start(previous: Option<char>, ch: char, next: Option<char>) -> bool {
+    if ch.is_whitespace() || ch == '_' {
+        return false;
+    }
+    let Some(previous) = previous else {
+        return true;
+    };
+    if !is_word_char(ch) {
+        return is_word_char(previous) || previous.is_whitespace();
+    }
+    !is_word_char(previous)
+        || previous == '_'
+        || (ch.is_uppercase()
+            && (previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next.is_some_and(char::is_lowercase))))
+}
+
+/// Buffer lines viewed as one stream of characters, where the position just
+/// past the end of each line stands for its line break.
+struct Text<'a>(&'a [String]);
+
+impl Text<'_> {
+    fn len(&self, row: usize) -> usize {
+        self.0.get(row).map_or(0, |line| line.chars().count())
+    }
+
+    fn char_at(&self, pos: Cursor) -> Option<char> {
+        self.0.get(pos.row)?.chars().nth(pos.col)
+    }
+
+    fn class_at(&self, pos: Cursor, big: bool) -> CharClass {
+        class_of(self.char_at(pos), big)
+    }
+
+    fn is_empty_line(&self, row: usize) -> bool {
+        self.0.get(row).is_some_and(|line| line.is_empty())
+    }
+
+    fn is_blank_line(&self, row: usize) -> bool {
+        self.0.get(row).is_none_or(|line| line.trim().is_empty())
+    }
+
+    fn first_non_blank(&self, row: usize) -> usize {
+        self.0.get(row).map_or(0, |line| {
+            line.chars().take_while(|ch| ch.is_whitespace()).count()
+        })
+    }
+
+    fn next(&self, pos: Cursor) -> Option<Cursor> {
+        if pos.col < self.len(pos.row) {
+            Some(Cursor {
+                col: pos.col + 1,
+                ..pos
+            })
+        } else if pos.row + 1 < self.0.len() {
+            Some(Cursor {
+                row: pos.row + 1,
+                col: 0,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn prev(&self, pos: Cursor) -> Option<Cursor> {
+        if pos.col > 0 {
+            Some(Cursor {
+                col: pos.col - 1,
+                ..pos
+            })
+        } else if pos.row > 0 {
+            Some(Cursor {
+                row: pos.row - 1,
+                col: self.len(pos.row - 1),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn end(&self) -> Cursor {
+        let row = self.0.len().saturating_sub(1);
+        Cursor {
+            row,
+            col: self.len(row),
+        }
+    }
+
+    fn word_forward(&self, from: Cursor, big: bool) -> Cursor {
+        let mut pos = from;
+        let class = self.class_at(pos, big);
+        if class != CharClass::Blank {
+            while self.class_at(pos, big) == class {
+                match self.next(pos) {
+                    Some(next) => pos = next,
+                    None => return self.end(),
+                }
+            }
+        }
+        // An empty line counts as a word of its own.
+        while self.class_at(pos, big) == CharClass::Blank
+            && !(self.is_empty_line(pos.row) && pos.row != from.row)
+        {
+            match self.next(pos) {
+                Some(next) => pos = next,
+                None => return self.end(),
+            }
+        }
+        pos
+    }
+
+    fn word_end(&self, from: Cursor, big: bool) -> Cursor {
+        let Some(mut pos) = self.next(from) else {
+            return from;
+        };
+        while self.class_at(pos, big) == CharClass::Blank {
+            match self.next(pos) {
+                Some(next) => pos = next,
+                None => return pos,
+            }
+        }
+        let class = self.class_at(pos, big);
+        while let Some(next) = self.next(pos) {
+            if self.class_at(next, big) != class {
+                break;
+            }
+            pos = next;
+        }
+        pos
+    }
+
+    fn word_backward(&self, from: Cursor, big: bool) -> Cursor {
+        let Some(mut pos) = self.prev(from) else {
+            return from;
+        };
+        while self.class_at(pos, big) == CharClass::Blank && !self.is_empty_line(pos.row) {
+            match self.prev(pos) {
+                Some(prev) => pos = prev,
+                None => return pos,
+            }
+        }
+        if self.is_empty_line(pos.row) {
+            return pos;
+        }
+        let class = self.class_at(pos, big);
+        while let Some(prev) = self.prev(pos) {
+            if prev.row != pos.row || self.class_at(prev, big) != class {
+                break;
+            }
+            pos = prev;
+        }
+        pos
+    }
+
+    fn is_subword_start_at(&self, pos: Cursor) -> bool {
+        let Some(line) = self.0.get(pos.row) else {
+            return false;
+        };
+        if line.is_empty() {
+            // An empty line stops sub-word motions as it does `w`.
+            return true;
+        }
+        let mut chars = line.chars().skip(pos.col.saturating_sub(1));
+        let previous = if pos.col > 0 { chars.next() } else { None };
+        match chars.next() {
+            Some(ch) => is_subword_start(previous, ch, chars.next()),
+            None => false,
+        }
+    }
+
+    fn subword(&self, from: Cursor, forward: bool) -> Cursor {
+        let mut pos = from;
+        loop {
+            let step = if forward {
+                self.next(pos)
+            } else {
+                self.prev(pos)
+            };
+            match step {
+                Some(next) => pos = next,
+                None if forward => return self.end(),
+                None => return pos,
+            }
+            if self.is_subword_start_at(pos) {
+                return pos;
+            }
+        }
+    }
+
+    /// The next (or previous) blank line past the current paragraph, after
+    /// skipping any blank lines the cursor starts on.
+    fn paragraph(&self, from: Cursor, forward: bool) -> Cursor {
+        let last = self.0.len().saturating_sub(1);
+        let step = |row: usize| {
+            if forward {
+                row + 1
+            } else {
+                row.wrapping_sub(1)
+            }
+        };
+        let in_bounds = |row: usize| row <= last;
+        let mut row = from.row;
+        while in_bounds(row) && self.is_blank_line(row) {
+            row = step(row);
+        }
+        while in_bounds(row) && !self.is_blank_line(row) {
+            row = step(row);
+        }
+        match (in_bounds(row), forward) {
+            (true, _) => Cursor { row, col: 0 },
+            (false, true) => self.end(),
+            (false, false) => Cursor { row: 0, col: 0 },
+        }
+    }
+
+    fn word_object(&self, cursor: Cursor, big: bool, around: bool) -> Option<TextRange> {
+        let line: Vec<char> = self.0.get(cursor.row)?.chars().collect();
+        if line.is_empty() {
+            return None;
+        }
+        let col = cursor.col.min(line.len() - 1);
+        let class = |i: usize| class_of(Some(line[i]), big);
+        let target = class(col);
+        let mut start = col;
+        while start > 0 && class(start - 1) == target {
+            start -= 1;
+        }
+        let mut end = col + 1;
+        while end < line.len() && class(end) == target {
+            end += 1;
+        }
+        if around {
+            if target == CharClass::Blank {
+                // Whitespace plus the word after it.
+                if end < line.len() {
+                    let word = class(end);
+                    while end < line.len() && class(end) == word {
+                        end += 1;
+                    }
+                }
+            } else if end < line.len() && class(end) == CharClass::Blank {
+                while end < line.len() && class(end) == CharClass::Blank {
+                    end += 1;
+                }
+            } else {
+                while start > 0 && class(start - 1) == CharClass::Blank {
+                    start -= 1;
+                }
+            }
+        }
+        Some(TextRange {
+            start: Cursor {
+                row: cursor.row,
+                col: start,
+            },
+            end: Cursor {
+                row: cursor.row,
+                col: end,
+            },
+            linewise: false,
+        })
+    }
+
+    /// The sub-word under the cursor, or after it when the cursor is on an
+    /// `_`; `av` adds the `_` separators after it, or else before it.
+    fn subword_object(&self, cursor: Cursor, around: bool) -> Option<TextRange> {
+        let line: Vec<char> = self.0.get(cursor.row)?.chars().collect();
+        let mut col = cursor.col.min(line.len().checked_sub(1)?);
+        if !is_word_char(line[col]) {
+            return None;
+        }
+        if line[col] == '_' {
+            col = (col..line.len())
+                .find(|&i| line[i] != '_')
+                .filter(|&i| is_word_char(line[i]))
+                .or_else(|| (0..col).rev().find(|&i| line[i] != '_'))
+                .filter(|&i| is_word_char(line[i]))?;
+        }
+        let starts = |i: usize| {
+            is_subword_start(
+                i.checked_sub(1).map(|i| line[i]),
+                line[i],
+                line.get(i + 1).copied(),
+            )
+        };
+        let mut start = col;
+        while !starts(start) {
+            start -= 1;
+        }
+        let mut end = col + 1;
+        while end < line.len() && line[end] != '_' && is_word_char(line[end]) && !starts(end) {
+            end += 1;
+        }
+        if around {
+            let trailing = line[end..].iter().take_while(|&&ch| ch == '_').count();
+            if trailing > 0 {
+                end += trailing;
+            } else {
+                start -= line[..start]
+                    .iter()
+                    .rev()
+                    .take_while(|&&ch| ch == '_')
+                    .count();
+            }
+        }
+        Some(TextRange {
+            start: Cursor {
+                row: cursor.row,
+                col: start,
+            },
+            end: Cursor {
+                row: cursor.row,
+                col: end,
+            },
+            linewise: false,
+        })
+    }
+
+    /// The nearest `open`/`close` pair around `cursor`, counting nesting.
+    fn enclosing_pair(&self, cursor: Cursor, open: char, close: char) -> Option<(Cursor, Cursor)> {
+        let mut pos = cursor;
+        let mut depth = 0usize;
+        let open_at = loop {
+            match self.char_at(pos) {
+                Some(ch) if ch == open => {
+                    if depth == 0 {
+                        break pos;
+                    }
+                    depth -= 1;
+                }
+                Some(ch) if ch == close && pos != cursor => depth += 1,
+                _ => {}
+            }
+            pos = self.prev(pos)?;
+        };
+        let mut pos = open_at;
+        let mut depth = 0usize;
+        loop {
+            pos = self.next(pos)?;
+            match self.char_at(pos) {
+                Some(ch) if ch == open => depth += 1,
+                Some(ch) if ch == close => {
+                    if depth == 0 {
+                        return Some((open_at, pos));
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The quoted string on the cursor line containing the cursor, or the
+    /// first one after it.
+    fn quote_object(&self, cursor: Cursor, quote: char, around: bool) -> Option<TextRange> {
+        let line: Vec<char> = self.0.get(cursor.row)?.chars().collect();
+        let quotes: Vec<usize> = (0..line.len())
+            .filter(|&i| line[i] == quote && (i == 0 || line[i - 1] != '\\'))
+            .collect();
+        let (open, close) = quotes
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|&(_, close)| close >= cursor.col)?;
+        let (mut start, mut end) = if around {
+            (open, close + 1)
+        } else {
+            (open + 1, close)
+        };
+        if around {
+            let trailing = line[end..]
+                .iter()
+                .take_while(|ch| ch.is_whitespace())
+                .count();
+            if trailing > 0 {
+                end += trailing;
+            } else {
+                start -= line[..start]
+                    .iter()
+                    .rev()
+                    .take_while(|ch| ch.is_whitespace())
+                    .count();
+            }
+        }
+        Some(TextRange {
+            start: Cursor {
+                row: cursor.row,
+                col: start,
+            },
+            end: Cursor {
+                row: cursor.row,
+                col: end,
+            },
+            linewise: false,
+        })
+    }
+
+    /// The run of lines sharing the cursor line's blankness, plus the blank
+    /// lines after it for `ap`.
+    fn paragraph_object(&self, cursor: Cursor, around: bool) -> TextRange {
+        let last = self.0.len().saturating_sub(1);
+        let blank = self.is_blank_line(cursor.row);
+        let mut first = cursor.row;
+        while first > 0 && self.is_blank_line(first - 1) == blank {
+            first -= 1;
+        }
+        let mut end = cursor.row;
+        while end < last && self.is_blank_line(end + 1) == blank {
+            end += 1;
+        }
+        if around {
+            while end < last && self.is_blank_line(end + 1) != blank {
+                end += 1;
+            }
+        }
+        TextRange {
+            start: Cursor { row: first, col: 0 },
+            end: Cursor {
+                row: end,
+                col: self.len(end),
+            },
+            linewise: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    fn at(row: usize, col: usize) -> Cursor {
+        Cursor { row, col }
+    }
+
+    #[test]
+    fn word_motions_step_between_classes() {
+        let text = lines("foo.bar  baz\n\nqux");
+        let w = Motion::WordForward { big: false };
+        assert_eq!(w.target(&text, at(0, 0)), at(0, 3));
+        assert_eq!(w.target(&text, at(0, 3)), at(0, 4));
+        assert_eq!(w.target(&text, at(0, 4)), at(0, 9));
+        assert_eq!(w.target(&text, at(0, 9)), at(1, 0));
+        assert_eq!(w.target(&text, at(1, 0)), at(2, 0));
+        let big = Motion::WordForward { big: true };
+        assert_eq!(big.target(&text, at(0, 0)), at(0, 9));
+
+        let e = Motion::WordEnd { big: false };
+        assert_eq!(e.target(&text, at(0, 0)), at(0, 2));
+        assert_eq!(e.target(&text, at(0, 9)), at(0, 11));
+        assert_eq!(e.target(&text, at(0, 11)), at(2, 2));
+
+        let b = Motion::WordBackward { big: false };
+        assert_eq!(b.target(&text, at(0, 9)), at(0, 4));
+        assert_eq!(b.target(&text, at(2, 0)), at(1, 0));
+        assert_eq!(b.target(&text, at(1, 0)), at(0, 9));
+    }
+
+    #[test]
+    fn subword_motions_stop_at_humps_and_underscores() {
+        let text = lines("let parseHTTPHeader = old_value2.x;\n\nnext");
+        let forward = Motion::SubwordForward;
+        let stops: Vec<usize> = std::iter::successors(Some(at(0, 0)), |&pos| {
+            Some(forward.target(&text, pos)).filter(|next| next.row == 0)
+        })
+        .map(|pos| pos.col)
+        .collect();
+        assert_eq!(stops, [0, 4, 9, 13, 20, 22, 26, 32, 33, 34]);
+        assert_eq!(forward.target(&text, at(0, 34)), at(1, 0));
+        assert_eq!(forward.target(&text, at(2, 0)), at(2, 4));
+        let backward = Motion::SubwordBackward;
+        assert_eq!(backward.target(&text, at(0, 27)), at(0, 26));
+        assert_eq!(backward.target(&text, at(0, 25)), at(0, 22));
+        assert_eq!(backward.target(&text, at(2, 0)), at(1, 0));
+        assert_eq!(backward.target(&text, at(0, 0)), at(0, 0));
+
+        let range = forward.range(&text, at(0, 4));
+        assert_eq!((range.start, range.end), (at(0, 4), at(0, 9)));
+        assert_eq!(parse_target("]"), Parse::Pending);
+        assert_eq!(
+            parse_target("[w"),
+            Parse::Done(Target::Motion(Motion::SubwordBackward))
+        );
+    }
+
+    #[test]
+    fn subword_objects_take_one_side_of_the_underscores() {
+        let text = lines("get_userName id");
+        let inner = TextObject::Subword { around: false };
+        let around = TextObject::Subword { around: true };
+        let span = |object: TextObject, col| {
+            let range = object.range(&text, at(0, col)).unwrap();
+            (range.start.col, range.end.col)
+        };
+        assert_eq!(span(inner, 5), (4, 8));
+        assert_eq!(span(inner, 9), (8, 12));
+        assert_eq!(span(inner, 3), (4, 8));
+        assert_eq!(span(around, 1), (0, 4));
+        assert_eq!(span(around, 5), (3, 8));
+        assert_eq!(span(around, 13), (13, 15));
+        assert_eq!(inner.range(&text, at(0, 12)), None);
+        assert_eq!(
+            parse_target("av"),
+            Parse::Done(Target::Object(TextObject::Subword { around: true }))
+        );
+    }
+
+    #[test]
+    fn exclusive_ranges_stop_at_the_line_break() {
+        let text = lines("one two\nthree");
+        let range = Motion::WordForward { big: false }.range(&text, at(0, 4));
+        assert_eq!(range.end, at(0, 7));
+        let range = Motion::WordEnd { big: false }.range(&text, at(0, 0));
+        assert_eq!(range.end, at(0, 3));
+        assert!(Motion::Down.range(&text, at(0, 0)).linewise);
+    }
+
+    #[test]
+    fn paragraph_motions_stop_at_blank_lines() {
+        let text = lines("a\nb\n\nc\n\n\nd");
+        assert_eq!(Motion::ParagraphForward.target(&text, at(0, 0)), at(2, 0));
+        assert_eq!(Motion::ParagraphForward.target(&text, at(2, 0)), at(4, 0));
+        assert_eq!(Motion::ParagraphForward.target(&text, at(6, 0)), at(6, 1));
+        assert_eq!(Motion::ParagraphBackward.target(&text, at(6, 0)), at(5, 0));
+        assert_eq!(Motion::ParagraphBackward.target(&text, at(1, 0)), at(0, 0));
+    }
+
+    #[test]
+    fn parse_targets() {
+        assert_eq!(parse_target("g"), Parse::Pending);
+        assert_eq!(
+            parse_target("gg"),
+            Parse::Done(Target::Motion(Motion::FirstLine))
+        );
+        assert_eq!(parse_target("i"), Parse::Pending);
+        assert_eq!(
+            parse_target("ib"),
+            Parse::Done(Target::Object(TextObject::Pair {
+                open: '(',
+                close: ')',
+                around: false
+            }))
+        );
+        assert_eq!(parse_target("iz"), Parse::Invalid);
+        assert_eq!(parse_target("z"), Parse::Invalid);
+    }
+
+    #[test]
+    fn word_objects_optionally_take_trailing_space() {
+        let text = lines("say hello  world");
+        let inner = TextObject::Word {
+            big: false,
+            around: false,
+        };
+        let range = inner.range(&text, at(0, 6)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 4), at(0, 9)));
+        let around = TextObject::Word {
+            big: false,
+            around: true,
+        };
+        let range = around.range(&text, at(0, 6)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 4), at(0, 11)));
+        let range = around.range(&text, at(0, 13)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 9), at(0, 16)));
+    }
+
+    #[test]
+    fn pair_objects_find_the_enclosing_pair() {
+        let text = lines("f(a, (b), c)\n{\n  x\n}");
+        let inner = TextObject::Pair {
+            open: '(',
+            close: ')',
+            around: false,
+        };
+        let range = inner.range(&text, at(0, 3)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 2), at(0, 11)));
+        let range = inner.range(&text, at(0, 6)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 6), at(0, 7)));
+        let range = inner.range(&text, at(0, 1)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 2), at(0, 11)));
+
+        let braces = TextObject::Pair {
+            open: '{',
+            close: '}',
+            around: true,
+        };
+        let range = braces.range(&text, at(2, 2)).unwrap();
+        assert_eq!((range.start, range.end), (at(1, 0), at(3, 1)));
+        assert_eq!(braces.range(&text, at(0, 3)), None);
+    }
+
+    #[test]
+    fn quote_objects_pair_quotes_on_the_line() {
+        let text = lines(r#"x = "a \"b\"" + "c" "#);
+        let inner = TextObject::Quote {
+            quote: '"',
+            around: false,
+        };
+        let range = inner.range(&text, at(0, 6)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 5), at(0, 12)));
+        let range = inner.range(&text, at(0, 0)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 5), at(0, 12)));
+        let around = TextObject::Quote {
+            quote: '"',
+            around: true,
+        };
+        let range = around.range(&text, at(0, 17)).unwrap();
+        assert_eq!((range.start, range.end), (at(0, 16), at(0, 20)));
+    }
+
+    #[test]
+    fn paragraph_objects_are_linewise() {
+        let text = lines("a\nb\n\n\nc");
+        let range = TextObject::Paragraph { around: false }
+            .range(&text, at(1, 0))
+            .unwrap();
+        assert_eq!((range.start.row, range.end.row), (0, 1));
+        assert!(range.linewise);
+        let range = TextObject::Paragraph { around: true }
+            .range(&text, at(1, 0))
+            .unwrap();
+        assert_eq!((range.start.row, range.end.row), (0, 3));
+    }
+}
@@ -0,0 +1,98 @@
+//! Recently edited files and where the cursor was left in each, kept in a
+//! state file between sessions and listed by `:oldfiles`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::editor::Cursor;
+
+/// Name of the list in the state directory.
+pub const OLDFILES_FILE: &str = "oldfiles";
+
+/// Most files remembered; older entries are dropped.
+pub const OLDFILES_LIMIT: usize = 100;
+
+/// A file and the cursor position it was left at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OldFile {
+    pub path: PathBuf,
+    pub cursor: Cursor,
+}
+
+/// Reads the list in `state`, newest first. A missing file is an empty list
+/// and malformed lines are skipped.
+pub fn load(state: &Path) -> Vec<OldFile> {
+    let Ok(contents) = fs::read_to_string(state) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let row: usize = fields.next()?.parse().ok()?;
+            let col: usize = fields.next()?.parse().ok()?;
+            let path = PathBuf::from(fields.next()?);
+            Some(OldFile {
+                path,
+                cursor: Cursor {
+                    row: row.checked_sub(1)?,
+                    col: col.checked_sub(1)?,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Moves `path` to the front of the list in `state` with `cursor` as its
+/// position. The file is reread first so concurrent sessions keep each
+/// other's entries.
+pub fn record(state: &Path, path: &Path, cursor: Cursor) -> io::Result<()> {
+    let path = std::path::absolute(path)?;
+    let mut files = load(state);
+    files.retain(|file| file.path != path);
+    files.insert(0, OldFile { path, cursor });
+    files.truncate(OLDFILES_LIMIT);
+    if let Some(parent) = state.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents: String = files
+        .iter()
+        .map(|file| {
+            format!(
+                "{}\t{}\t{}\n",
+                file.cursor.row + 1,
+                file.cursor.col + 1,
+                file.path.display()
+            )
+        })
+        .collect();
+    fs::write(state, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_moves_files_to_the_front() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state/oldfiles");
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        record(&state, &a, Cursor { row: 4, col: 2 }).unwrap();
+        record(&state, &b, Cursor { row: 0, col: 0 }).unwrap();
+        record(&state, &a, Cursor { row: 9, col: 1 }).unwrap();
+        let files = load(&state);
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0],
+            OldFile {
+                path: a,
+                cursor: Cursor { row: 9, col: 1 }
+            }
+        );
+        assert_eq!(files[1].path, b);
+        assert!(load(&dir.path().join("missing")).is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+//! The command palette: every registered ex command and key binding with
+//! its description, narrowed by a fuzzy query as it is typed.
+
+use crate::registry::{CommandInfo, KeyInfo, Registry};
+
+/// Most entries shown at once; the list scrolls to keep the selection in view.
+pub const PALETTE_MAX_HEIGHT: usize = 12;
+
+/// What choosing an entry does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Command(CommandInfo),
+    /// The user command at this index of `Registry::user_commands`.
+    UserCommand(usize),
+    Keys(KeyInfo),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// `:name args` for a command, the keys for a binding.
+    pub label: String,
+    pub description: String,
+    pub action: Action,
+}
+
+/// The entries matching the typed query, best first, one of them selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    entries: Vec<Entry>,
+    pub query: String,
+    /// Indices of the entries matching `query`, best match first.
+    matches: Vec<usize>,
+    /// Index into the matches.
+    pub selected: usize,
+}
+
+impl Palette {
+    pub fn new(registry: &Registry) -> Self {
+        let commands = registry.commands.iter().map(|info| Entry {
+            label: format!(":{} {}", info.name, info.args)
+                .trim_end()
+                .to_string(),
+            description: info.description.to_string(),
+            action: Action::Command(*info),
+        });
+        let user_commands = registry
+            .user_commands
+            .iter()
+            .enumerate()
+            .map(|(index, command)| Entry {
+                label: format!(":{} {}", command.name, command.args())
+                    .trim_end()
+                    .to_string(),
+                description: command.replacement.clone(),
+                action: Action::UserCommand(index),
+            });
+        let keys = registry.keys.iter().map(|info| Entry {
+            label: info.label(),
+            description: info.description.to_string(),
+            action: Action::Keys(*info),
+        });
+        let entries: Vec<Entry> = commands.chain(user_commands).chain(keys).collect();
+        Self {
+            matches: (0..entries.len()).collect(),
+            entries,
+            query: String::new(),
+            selected: 0,
+        }
+    }
+
+    /// Replaces the query, ranking the entries against it afresh and
+    /// selecting the best.
+    pub fn set_query(&mut self, query: String) {
+        let mut scored: Vec<(usize, usize)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                // A match in the label counts for more than one in the
+                // description.
+                fuzzy_score(&query, &entry.label)
+                    .map(|score| score * 2)
+                    .or_else(|| fuzzy_score(&query, &entry.description))
+                    .map(|score| (index, score))
+            })
+            .collect();
+        scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        self.matches = scored.into_iter().map(|(index, _)| index).collect();
+        self.query = query;
+        self.selected = 0;
+    }
+
+    /// The matching entries, best first.
+    pub fn matches(&self) -> impl ExactSizeIterator<Item = &Entry> {
+        self.matches.iter().map(|&index| &self.entries[index])
+    }
+
+    pub fn selected_entry(&self) -> Option<&Entry> {
+        self.matches
+            .get(self.selected)
+            .map(|&index| &self.entries[index])
+    }
+
+    /// Moves the selection down (`forward`) or up, wrapping around.
+    pub fn step(&mut self, forward: bool) {
+        let len = self.matches.len();
+        if len == 0 {
+            return;
+        }
+        self.selected = if forward {
+            (self.selected + 1) % len
+        } else {
+            (self.selected + len - 1) % len
+        };
+    }
+
+    /// The first match shown when `height` fit, keeping the selection in view.
+    pub fn first_visible(&self, height: usize) -> usize {
+        (self.selected + 1)
+            .saturating_sub(height)
+            .min(self.matches.len().saturating_sub(height))
+    }
+}
+
+/// How well `query` matches `text`, ignoring case, or `None` unless its
+/// characters appear in `text` in order. Consecutive characters and ones
+/// starting a word, including the humps of `CamelCase`, score higher. Spaces in the query are ignored.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<usize> {
+    let text: Vec<char> = text.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last: Option<usize> = None;
+    for wanted in query.chars().filter(|ch| !ch.is_whitespace()) {
+        let found = (next..text.len())
+            .find(|&index| text[index].to_lowercase().eq(wanted.to_lowercase()))?;
+        score += 1;
+        if found > 0 && last == Some(found - 1) {
+            score += 4;
+        }
+        if found == 0
+            || !text[found - 1].is_alphanumeric()
+            || (text[found - 1].is_lowercase() && text[found].is_uppercase())
+        {
+            score += 3;
+        }
+        last = Some(found);
+        next = found + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Mode;
+
+    #[test]
+    fn fuzzy_matches_prefer_consecutive_word_starts() {
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert_eq!(fuzzy_score("xz", "zx"), None);
+        assert!(fuzzy_score("tw", "TrimWhitespace") > fuzzy_score("tw", "it was"));
+        assert!(fuzzy_score("sort", ":sort") > fuzzy_score("sort", "show older rows"));
+    }
+
+    #[test]
+    fn palette_ranks_labels_then_descriptions() {
+        let registry = Registry {
+            commands: vec![
+                CommandInfo::new("noh", "", "Stop highlighting search matches"),
+                CommandInfo::new("sort", "[flags]", "Sort lines"),
+            ],
+            keys: vec![KeyInfo::new(Mode::Normal, "gg", "Go to the first line")],
+            ..Registry::default()
+        };
+        let mut palette = Palette::new(&registry);
+        let labels = |palette: &Palette| -> Vec<String> {
+            palette.matches().map(|entry| entry.label.clone()).collect()
+        };
+        assert_eq!(labels(&palette), [":noh", ":sort [flags]", "gg"]);
+        palette.set_query("so".to_string());
+        assert_eq!(labels(&palette), [":sort [flags]", ":noh"]);
+        palette.step(false);
+        assert_eq!(palette.selected_entry().unwrap().label, ":noh");
+        palette.set_query("first".to_string());
+        assert_eq!(labels(&palette), ["gg"]);
+        assert_eq!(
+            palette.selected_entry().unwrap().action,
+            Action::Keys(registry.keys[0])
+        );
+        palette.set_query("zzz".to_string());
+        assert!(palette.selected_entry().is_none());
+    }
+}
@@ -0,0 +1,102 @@
+//! Regular-expression patterns for `/`, `?` and `:s`.
+
+use regex::{Captures, Regex, RegexBuilder};
+
+/// Compiles `pattern`, turning a syntax error into a one-line message for
+/// the status bar.
+pub fn compile(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|err| {
+            // Syntax errors span several lines, pointing at the problem
+            // with a caret; the last line says what is wrong.
+            let message = err.to_string();
+            let reason = message
+                .lines()
+                .rev()
+                .find_map(|line| line.strip_prefix("error: "))
+                .unwrap_or_else(|| message.lines().next().unwrap_or_default());
+            format!("Invalid pattern: {}", reason)
+        })
+}
+
+/// Whether `pattern` has an uppercase letter outside escapes such as `\W`
+/// and `\p{Lu}`, which turns `smartcase` matching case-sensitive.
+pub fn has_uppercase(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                if let Some('p' | 'P') = chars.next() {
+                    // Skip a one-letter class name or a braced one.
+                    if chars.next() == Some('{') {
+                        chars.by_ref().find(|&ch| ch == '}');
+                    }
+                }
+            }
+            ch if ch.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Expands a `:s` replacement for one match: `&` and `\0` insert the whole
+/// match, `\1`…`\9` a capture group, `\n` or `\r` a line break and `\t` a
+/// tab; any other escaped character is taken literally.
+pub fn expand_replacement(replacement: &str, caps: &Captures) -> String {
+    let group = |index: usize| caps.get(index).map_or("", |m| m.as_str());
+    let mut out = String::new();
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '&' => out.push_str(group(0)),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    out.push_str(group(digit.to_digit(10).unwrap_or(0) as usize))
+                }
+                Some('n' | 'r') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_patterns_report_the_reason() {
+        assert_eq!(
+            compile("foo(", false).unwrap_err(),
+            "Invalid pattern: unclosed group"
+        );
+        assert!(compile("a|b", false).unwrap().is_match("b"));
+        assert!(compile("ABC", true).unwrap().is_match("abc"));
+    }
+
+    #[test]
+    fn uppercase_ignores_escapes() {
+        assert!(has_uppercase("Foo"));
+        assert!(!has_uppercase(r"foo\W\S\pL\p{Lu}"));
+        assert!(has_uppercase(r"\p{Lu}X"));
+    }
+
+    #[test]
+    fn replacements_expand_groups_and_escapes() {
+        let regex = compile(r"(\w+)=(\w+)", false).unwrap();
+        let caps = regex.captures("key=value").unwrap();
+        assert_eq!(expand_replacement(r"\2=\1", &caps), "value=key");
+        assert_eq!(
+            expand_replacement(r"[&] \& \\ $1", &caps),
+            "[key=value] & \\ $1"
+        );
+        assert_eq!(expand_replacement(r"\1\n\2\9", &caps), "key\nvalue");
+    }
+}
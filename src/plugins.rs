@@ -1,38 +1,625 @@
 //! Core plugins that implement minivim behaviors.
 
+use std::collections::VecDeque;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crossterm::event::{Event, KeyCode, KeyModifiers};
-use crossterm::style::{Attribute, Attributes, Color, ContentStyle};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEventKind};
+use crossterm::style::{Attribute, ContentStyle};
 
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{Color as SyntectColor, FontStyle, Style, Theme, ThemeSet};
-use syntect::parsing::{SyntaxReference, SyntaxSet};
+use regex::{Captures, Regex};
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::editor::{Editor, EventResult, Mode, Plugin, RenderContext, StyledSpan};
+use crate::autocmd::{self, AutoEvent};
+use crate::compress::Compression;
+use crate::crypt::Cipher;
+use crate::csv;
+use crate::diagnostics;
+use crate::digraphs;
+use crate::editor::{
+    Buffer, Cursor, Editor, EventResult, History, Jump, Mode, Plugin, Register, RegisterKind,
+    Registers, RenderContext, StyledSpan, TerminalJob, char_col_at, display_col, display_width,
+    grapheme_columns, grapheme_width, is_word_char, next_grapheme_boundary, parse_script,
+    read_file, same_file, split_set_args, truncate_to_width,
+};
+use crate::ex::{self, LineRange};
+use crate::expand;
+use crate::find_replace::{self, Hunk};
+use crate::global_marks;
+use crate::highlight::{Assets, SyntaxCache};
+use crate::markdown::{self, PreviewLine, PreviewStyles};
+use crate::menu::{ItemKind, MENU_MAX_HEIGHT, Menu, MenuAction, MenuItem};
+use crate::motion::{self, Motion, Parse, TextRange};
+use crate::oldfiles::{self, OldFile};
+use crate::palette::{self, Action, PALETTE_MAX_HEIGHT, Palette};
+use crate::pattern;
+use crate::popup::{self, Popup};
+use crate::provider;
+use crate::quickfix::{self, QuickfixList};
+use crate::registry::{self, CommandInfo, KeyInfo, RangeDefault, Registry, UserCommand};
+use crate::spell;
+use crate::subvert;
+use crate::surround;
+use crate::table;
+use crate::theme::HighlightGroup;
+use crate::undo::UndoSpan;
+use crate::virtual_text::VirtualText;
 
 pub struct FileCommandPlugin;
 
 impl FileCommandPlugin {
-    fn save_to_path(editor: &mut Editor, path: PathBuf) -> bool {
-        match editor.save_to_path(&path) {
-            Ok(()) => {
-                editor.file_path = Some(path.clone());
-                editor.set_status(format!("Wrote {}", path.display()));
-                true
+    /// Writes the buffer to `path`, then quits if `quit` is set. Encrypted
+    /// files are written once the main loop has run their cipher.
+    fn save_to_path(editor: &mut Editor, path: PathBuf, quit: bool, force: bool) {
+        if editor.options.trim_trailing_whitespace {
+            editor.trim_trailing_whitespace();
+        }
+        if let Some(cipher) = Cipher::for_path(&path) {
+            Self::save_encrypted(editor, path, cipher, quit, force);
+            return;
+        }
+        match editor.save_to_path(&path, force) {
+            Ok(warning) => Self::written(editor, path, warning, quit),
+            Err(err) => Self::write_failed(editor, err),
+        }
+    }
+
+    fn written(editor: &mut Editor, path: PathBuf, warning: Option<String>, quit: bool) {
+        match warning {
+            Some(warning) => {
+                editor.set_status(format!("Wrote {}, but {}", path.display(), warning))
+            }
+            None => editor.set_status(format!("Wrote {}", path.display())),
+        }
+        // A scratch buffer written out stays a scratch buffer.
+        if !editor.is_scratch_buffer() {
+            editor.file_path = Some(path);
+        }
+        editor.file_index.file_written();
+        editor.fire(AutoEvent::BufWritePost);
+        if quit && !Self::unsaved_hidden(editor) {
+            editor.remember_file();
+            editor.should_quit = true;
+        }
+    }
+
+    fn write_failed(editor: &mut Editor, err: io::Error) {
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            editor.set_error(format!("Write failed: {} (try :SudoWrite)", err));
+        } else {
+            editor.set_error(format!("Write failed: {}", err));
+        }
+    }
+
+    /// Encrypts the buffer with `cipher` in the foreground, where it may ask
+    /// for a passphrase, and writes the result to `path`.
+    fn save_encrypted(editor: &mut Editor, path: PathBuf, cipher: Cipher, quit: bool, force: bool) {
+        editor.terminal_job = Some(TerminalJob {
+            command: cipher.encrypt(&editor.options.cryptrecipients),
+            input: editor.file_contents().into_bytes(),
+            done: Box::new(move |editor, result| match result {
+                Ok(output) if output.status.success() => {
+                    let provider = provider::provider_for(&path);
+                    match provider.write(&path, &output.stdout, editor.options.fsync, force) {
+                        Ok(warning) => {
+                            editor.dirty = false;
+                            editor.note_file_time(&path);
+                            Self::written(editor, path, warning, quit);
+                        }
+                        Err(err) => Self::write_failed(editor, err),
+                    }
+                }
+                Ok(output) => editor.set_error(format!(
+                    "Encrypt failed: {} {}",
+                    cipher.program(),
+                    output.status
+                )),
+                Err(err) => editor.set_error(format!("Encrypt failed: {}", err)),
+            }),
+        });
+    }
+
+    /// Reads the encrypted file at `path` and has the main loop decrypt it
+    /// in the foreground, where `cipher` may ask for a passphrase. The file
+    /// becomes the buffer's only once decrypted, so a failure cannot leave
+    /// an empty buffer to be written over it.
+    fn open_encrypted(editor: &mut Editor, path: PathBuf, cipher: Cipher) -> io::Result<()> {
+        let input = provider::provider_for(&path).read(&path)?;
+        editor.set_status(format!("Decrypting {}", path.display()));
+        editor.terminal_job = Some(TerminalJob {
+            command: cipher.decrypt(&editor.options.cryptidentity),
+            input,
+            done: Box::new(move |editor, result| match result {
+                Ok(output) if output.status.success() => match String::from_utf8(output.stdout) {
+                    Ok(text) => {
+                        editor.load_text(text);
+                        editor.set_status(format!("Opened {}", path.display()));
+                        editor.note_file_time(&path);
+                        editor.file_path = Some(path);
+                        editor.ensure_cursor_visible();
+                    }
+                    Err(err) => editor.set_error(format!("Open failed: {}", err)),
+                },
+                Ok(output) => editor.set_error(format!(
+                    "Decrypt failed: {} {}",
+                    cipher.program(),
+                    output.status
+                )),
+                Err(err) => editor.set_error(format!("Decrypt failed: {}", err)),
+            }),
+        });
+        Ok(())
+    }
+
+    /// Starts an empty buffer for a file that does not exist yet.
+    fn new_file(editor: &mut Editor, path: &Path) {
+        editor.buffer = Buffer::new();
+        editor.deltas.reset();
+        editor.options.endofline = false;
+        editor.options.readonly = false;
+        editor.options.buftype.clear();
+        editor.csv_align = None;
+        editor.cursor = Cursor { row: 0, col: 0 };
+        editor.dirty = false;
+        editor.file_time = None;
+        editor.set_status(format!("New file {}", path.display()));
+        editor.fire(AutoEvent::BufNewFile);
+    }
+
+    /// Fires `BufWritePre` ahead of a write, queueing the write `command`
+    /// again to run once the handlers have. Returns true if the write was
+    /// put off; writes from autocommands are not.
+    fn defer_write(editor: &mut Editor, command: &str) -> bool {
+        if editor.autocmds.is_running() || !editor.has_handlers(AutoEvent::BufWritePre) {
+            return false;
+        }
+        if std::mem::take(&mut editor.autocmds.deferred_write) {
+            return false;
+        }
+        editor.autocmds.deferred_write = true;
+        editor.fire(AutoEvent::BufWritePre);
+        editor.push_commands_front(vec![command.to_string()]);
+        true
+    }
+
+    /// Whether the buffer may be written to `path`: not to its own file
+    /// while `readonly` is set, unless `force`d.
+    fn may_write(editor: &mut Editor, path: &Path, force: bool) -> bool {
+        let own_file = editor
+            .file_path
+            .as_deref()
+            .is_some_and(|file| same_file(file, path));
+        if editor.is_scratch_buffer() && own_file {
+            editor.set_error("Cannot write, 'buftype' is nofile");
+            return false;
+        }
+        if editor.options.readonly && own_file && !force {
+            editor.set_error("'readonly' is set (add ! to override)");
+            return false;
+        }
+        true
+    }
+
+    /// `:[range]w[!] [file]` and `:w[!] >> [file]`: writes some of the
+    /// lines to a file, or appends the buffer (or the range) to one,
+    /// leaving the buffer as unsaved as it was. Returns false for a write of
+    /// the whole buffer, which is left to the plain `:w`.
+    fn partial_write(editor: &mut Editor, command: &str) -> bool {
+        if ex::command_name(command) != "w" {
+            return false;
+        }
+        let (range, rest) = match ex::parse_range(command, editor) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                editor.set_error(message);
+                return true;
+            }
+        };
+        let rest = &rest.trim_start()[1..];
+        let (force, rest) = match rest.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let (append, file) = match rest.trim_start().strip_prefix(">>") {
+            Some(file) => (true, file.trim()),
+            None => (false, rest.trim()),
+        };
+        if range.is_none() && !append {
+            return false;
+        }
+        if editor.is_loading() {
+            editor.set_error("Cannot write while the file is still loading");
+            return true;
+        }
+        let file = match editor.expand_args(file) {
+            Ok(file) => file,
+            Err(message) => {
+                editor.set_error(message);
+                return true;
+            }
+        };
+        let path = match file.as_str() {
+            "" => editor.file_path.clone(),
+            file => Some(PathBuf::from(file)),
+        };
+        let Some(path) = path else {
+            editor.set_error("No file name");
+            return true;
+        };
+        let last = editor.buffer.lines.len() - 1;
+        let range = range.unwrap_or(LineRange {
+            start: 0,
+            end: last,
+        });
+        let own_file = editor
+            .file_path
+            .as_deref()
+            .is_some_and(|file| same_file(file, &path));
+        if own_file && !append && !force && (range.start, range.end) != (0, last) {
+            editor.set_error("Use ! to write partial buffer");
+            return true;
+        }
+        if !Self::may_write(editor, &path, force) {
+            return true;
+        }
+        if Cipher::for_path(&path).is_some() {
+            editor.set_error(format!(
+                "Cannot write part of the buffer to {}",
+                path.display()
+            ));
+            return true;
+        }
+        let lines = range.end - range.start + 1;
+        match editor.write_lines(range.start, range.end, &path, append, force) {
+            Ok(warning) => {
+                if own_file {
+                    editor.note_file_time(&path);
+                }
+                let done = if append { "Appended" } else { "Wrote" };
+                let mut status = format!("{} {} lines to {}", done, lines, path.display());
+                if let Some(warning) = warning {
+                    status.push_str(&format!(", but {}", warning));
+                }
+                editor.set_status(status);
+                editor.file_index.file_written();
             }
+            Err(err) => Self::write_failed(editor, err),
+        }
+        true
+    }
+
+    /// Writes the buffer to `path`, or its file, through `sudo tee`, for
+    /// files only root may write. sudo may ask for a password, so the main
+    /// loop runs it with the terminal to itself.
+    fn command_sudo_write(editor: &mut Editor, path: &str) {
+        let path = match path {
+            "" => editor.file_path.clone(),
+            path => Some(PathBuf::from(path)),
+        };
+        let Some(path) = path else {
+            editor.set_error("No file name");
+            return;
+        };
+        if provider::is_remote(&path) || Cipher::for_path(&path).is_some() {
+            editor.set_error(format!("SudoWrite cannot write {}", path.display()));
+            return;
+        }
+        // Forced like `:w!`: a file that needs sudo is read-only anyway.
+        if !Self::may_write(editor, &path, true) {
+            return;
+        }
+        if editor.options.trim_trailing_whitespace {
+            editor.trim_trailing_whitespace();
+        }
+        let mut input = editor.file_contents().into_bytes();
+        if let Some(compression) = Compression::for_path(&path) {
+            input = match compression.compress(&input) {
+                Ok(compressed) => compressed,
+                Err(err) => {
+                    editor.set_error(format!("SudoWrite failed: {}", err));
+                    return;
+                }
+            };
+        }
+        let mut command = Command::new("sudo");
+        command
+            .arg("tee")
+            .arg("--")
+            .arg(&path)
+            .stdout(Stdio::null());
+        editor.terminal_job = Some(TerminalJob {
+            command,
+            input,
+            done: Box::new(move |editor, result| match result {
+                Ok(output) if output.status.success() => {
+                    editor.dirty = false;
+                    editor.note_file_time(&path);
+                    editor.file_index.file_written();
+                    editor.set_status(format!("Wrote {} with sudo", path.display()));
+                    if !editor.is_scratch_buffer() {
+                        editor.file_path = Some(path);
+                    }
+                    editor.fire(AutoEvent::BufWritePost);
+                }
+                Ok(output) => editor.set_error(format!("SudoWrite failed: sudo {}", output.status)),
+                Err(err) => editor.set_error(format!("SudoWrite failed: {}", err)),
+            }),
+        });
+    }
+
+    /// Opens `path` in place of the current file, or reads the current
+    /// file again if `path` is empty. Returns false if it was not opened.
+    fn command_edit(editor: &mut Editor, path: &str, force: bool) -> bool {
+        if path.is_empty() {
+            return Self::command_reload(editor, force);
+        }
+        if Path::new(path).is_dir() {
+            editor.push_command(format!("Explore {}", path));
+            return true;
+        }
+        let path = PathBuf::from(path);
+        let previous = editor.file_path.clone();
+        // With `hidden` set, a modified buffer is kept for its file to be
+        // edited again, though not to be read over itself.
+        let hide = editor.dirty && !force;
+        if hide
+            && (!editor.options.hidden
+                || previous
+                    .as_deref()
+                    .is_none_or(|file| same_file(file, &path)))
+        {
+            editor.set_error("No write since last change (add ! to override)");
+            return false;
+        }
+        editor.remember_file();
+        let cursor = editor.cursor;
+        if hide {
+            editor.hide_buffer();
+        }
+        if editor.unhide_buffer(&path) {
+            editor.set_status(format!("Opened {}", path.display()));
+            Self::set_alternate(editor, previous, &path, cursor);
+            editor.ensure_cursor_visible();
+            return true;
+        }
+        let loaded = match Cipher::for_path(&path) {
+            Some(cipher) => match Self::open_encrypted(editor, path.clone(), cipher) {
+                // The file is opened once decrypted.
+                Ok(()) => {
+                    Self::set_alternate(editor, previous, &path, cursor);
+                    return true;
+                }
+                Err(err) => Err(err),
+            },
+            None => editor.load_from_path(&path),
+        };
+        match loaded {
+            Ok(()) => editor.set_status(format!("Opened {}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::new_file(editor, &path),
             Err(err) => {
-                editor.set_status(format!("Write failed: {}", err));
-                false
+                if hide && let Some(previous) = &previous {
+                    editor.unhide_buffer(previous);
+                }
+                editor.set_error(format!("Open failed: {}", err));
+                return false;
+            }
+        }
+        Self::set_alternate(editor, previous, &path, cursor);
+        editor.file_path = Some(path);
+        editor.ensure_cursor_visible();
+        true
+    }
+
+    /// Reads the open file again, discarding changes if `force`d. The
+    /// cursor stays where it was, as far as the new text reaches.
+    fn command_reload(editor: &mut Editor, force: bool) -> bool {
+        let Some(path) = editor.file_path.clone() else {
+            editor.set_error("No file name");
+            return false;
+        };
+        let cursor = editor.cursor;
+        if !Self::command_edit(editor, &path.to_string_lossy(), force) {
+            return false;
+        }
+        if !editor.is_loading() && editor.terminal_job.is_none() && path.exists() {
+            editor.goto_position(cursor.row, cursor.col);
+            editor.set_status(format!("Reloaded {}", path.display()));
+        }
+        true
+    }
+
+    /// Makes `previous`, the file left with the cursor at `cursor`, the
+    /// alternate one as `path` is edited in its place.
+    fn set_alternate(editor: &mut Editor, previous: Option<PathBuf>, path: &Path, cursor: Cursor) {
+        if let Some(previous) = previous
+            && !same_file(&previous, path)
+        {
+            editor.alternate_file = Some(previous);
+            editor.alternate_cursor = cursor;
+        }
+    }
+
+    /// Writes the modified buffer to its file, as `autowrite` asks before
+    /// the commands it names, or `autowriteall` before `:e` and `:q` too
+    /// (`all`). Encrypted files are written in the foreground after the
+    /// command has run, so they are left to be written by hand.
+    fn autowrite(editor: &mut Editor, all: bool) {
+        if editor.options.autowriteall || (editor.options.autowrite && !all) {
+            Self::write_modified(editor);
+        }
+    }
+
+    /// Writes the modified buffer to its file, unless it is still loading,
+    /// readonly or encrypted.
+    fn write_modified(editor: &mut Editor) {
+        if !editor.dirty || editor.is_loading() || editor.options.readonly {
+            return;
+        }
+        if let Some(path) = editor.file_path.clone()
+            && Cipher::for_path(&path).is_none()
+        {
+            Self::save_to_path(editor, path, false, false);
+        }
+    }
+
+    /// `:checktime`, and on regaining focus: warns if the open file was
+    /// changed or removed since it was read or written, once for each
+    /// change. Returns true if it was.
+    fn check_time(editor: &mut Editor) -> bool {
+        if !editor.changed_on_disk() {
+            return false;
+        }
+        let Some(path) = editor.file_path.clone() else {
+            return false;
+        };
+        if path.exists() {
+            editor.set_status(format!(
+                "\"{}\" has changed since editing started; :e! reloads it",
+                path.display()
+            ));
+        } else {
+            editor.set_status(format!("\"{}\" is no longer available", path.display()));
+        }
+        editor.note_file_time(&path);
+        true
+    }
+
+    /// Refuses to quit, with a message, while a hidden buffer is unsaved.
+    fn unsaved_hidden(editor: &mut Editor) -> bool {
+        let Some(hidden) = editor.hidden_buffers.first() else {
+            return false;
+        };
+        let message = format!(
+            "No write since last change for buffer \"{}\" (add ! to override)",
+            hidden.path.display()
+        );
+        editor.set_error(message);
+        true
+    }
+
+    /// `:bd[!] [file]`: deletes the hidden buffer of `file`, or the open
+    /// one, which gives way to the alternate file, then to a hidden buffer
+    /// and, with none left, to an empty unnamed buffer. A modified buffer
+    /// is only deleted if `force`d.
+    fn command_bdelete(editor: &mut Editor, file: &str, force: bool) {
+        let open = file.is_empty()
+            || editor
+                .file_path
+                .as_deref()
+                .is_some_and(|path| same_file(path, Path::new(file)));
+        if !open {
+            let Some(index) = editor
+                .hidden_buffers
+                .iter()
+                .position(|hidden| same_file(&hidden.path, Path::new(file)))
+            else {
+                editor.set_error(format!("No matching buffer for {}", file));
+                return;
+            };
+            if !force {
+                editor.set_error(format!(
+                    "No write since last change for buffer \"{}\" (add ! to override)",
+                    file
+                ));
+                return;
             }
+            let hidden = editor.hidden_buffers.remove(index);
+            editor.set_status(format!("Deleted buffer {}", hidden.path.display()));
+            return;
+        }
+        if editor.dirty && !force {
+            editor.set_error("No write since last change (add ! to override)");
+            return;
+        }
+        editor.remember_file();
+        let deleted = editor.file_path.take();
+        editor.dirty = false;
+        let next = editor
+            .alternate_file
+            .take()
+            .filter(|path| {
+                deleted
+                    .as_deref()
+                    .is_none_or(|deleted| !same_file(deleted, path))
+            })
+            .or_else(|| {
+                editor
+                    .hidden_buffers
+                    .last()
+                    .map(|hidden| hidden.path.clone())
+            });
+        if let Some(next) = next
+            && Self::command_edit(editor, &next.to_string_lossy(), true)
+        {
+            return;
+        }
+        editor.buffer = Buffer::new();
+        editor.cursor = Cursor { row: 0, col: 0 };
+        editor.deltas.reset();
+        editor.changes = Default::default();
+        editor.diagnostics.clear();
+        editor.csv_align = None;
+        editor.options.endofline = false;
+        editor.options.readonly = false;
+        editor.options.buftype.clear();
+        editor.file_time = None;
+        editor.reset_undo();
+        editor.set_status(match deleted {
+            Some(path) => format!("Deleted buffer {}", path.display()),
+            None => "Deleted buffer".to_string(),
+        });
+    }
+
+    /// `:scratch[!]`, or `e` in an output view: puts `lines` in a scratch
+    /// buffer in place of the open one, which is left as `:e` leaves it.
+    /// Returns false if the open buffer could not be left.
+    fn scratch_buffer(editor: &mut Editor, lines: Vec<String>, force: bool) -> bool {
+        let hide = editor.dirty && !force;
+        if hide && (!editor.options.hidden || editor.file_path.is_none()) {
+            editor.set_error("No write since last change (add ! to override)");
+            return false;
+        }
+        editor.remember_file();
+        let previous = editor.file_path.clone();
+        let cursor = editor.cursor;
+        if hide {
+            editor.hide_buffer();
+        }
+        editor.open_scratch_buffer(lines);
+        if let Some(previous) = previous {
+            editor.alternate_file = Some(previous);
+            editor.alternate_cursor = cursor;
+        }
+        true
+    }
+
+    /// Ctrl-^: edits the alternate file again, back where the cursor was
+    /// when it was left.
+    fn edit_alternate(editor: &mut Editor) {
+        let Some(path) = editor.alternate_file.clone() else {
+            editor.set_status("No alternate file");
+            return;
+        };
+        let cursor = editor.alternate_cursor;
+        Self::autowrite(editor, false);
+        if Self::command_edit(editor, &path.to_string_lossy(), false)
+            && !editor.is_loading()
+            && editor.terminal_job.is_none()
+        {
+            editor.goto_position(cursor.row, cursor.col);
+            editor.ensure_cursor_visible();
         }
     }
 
     fn command_quit(editor: &mut Editor, force: bool) {
         if editor.dirty && !force {
-            editor.set_status("No write since last change (add ! to override)");
-        } else {
+            editor.set_error("No write since last change (add ! to override)");
+        } else if force || !Self::unsaved_hidden(editor) {
+            editor.remember_file();
             editor.should_quit = true;
         }
     }
@@ -43,13 +630,38 @@ impl Plugin for FileCommandPlugin {
         let Some(path) = editor.file_path.clone() else {
             return;
         };
-        match editor.load_from_path(&path) {
+        if path.is_dir() {
+            editor.file_path = None;
+            editor.push_command(format!("Explore {}", path.display()));
+            return;
+        }
+        if let Some(cipher) = Cipher::for_path(&path) {
+            editor.file_path = None;
+            match Self::open_encrypted(editor, path.clone(), cipher) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    editor.options.endofline = false;
+                    editor.set_status(format!("New file {}", path.display()));
+                    editor.file_path = Some(path);
+                    editor.fire(AutoEvent::BufNewFile);
+                }
+                Err(err) => editor.set_error(format!("Open failed: {}", err)),
+            }
+            return;
+        }
+        match editor.start_loading(&path) {
+            Ok(()) if editor.is_loading() => {}
             Ok(()) => editor.set_status(format!("Opened {}", path.display())),
             Err(err) => {
                 if err.kind() == io::ErrorKind::NotFound {
+                    // Nothing to keep: an empty new file stays empty.
+                    editor.options.endofline = false;
                     editor.set_status(format!("New file {}", path.display()));
+                    editor.fire(AutoEvent::BufNewFile);
                 } else {
-                    editor.set_status(format!("Open failed: {}", err));
+                    // The empty buffer must not be written over the file.
+                    editor.file_path = None;
+                    editor.set_error(format!("Open failed: {}", err));
                 }
             }
         }
@@ -61,535 +673,10202 @@ impl Plugin for FileCommandPlugin {
             return EventResult::Consumed;
         }
 
-        let mut parts = trimmed.split_whitespace();
-        let verb = parts.next().unwrap_or("");
+        if Self::partial_write(editor, trimmed) {
+            return EventResult::Consumed;
+        }
+        let (verb, args) = split_command(trimmed);
+        if verb == "checktime" {
+            if !Self::check_time(editor) {
+                editor.set_status("File unchanged on disk");
+            }
+            return EventResult::Consumed;
+        }
+        if !matches!(
+            verb,
+            "w" | "wq"
+                | "x"
+                | "w!"
+                | "wq!"
+                | "x!"
+                | "SudoWrite"
+                | "e"
+                | "edit"
+                | "e!"
+                | "edit!"
+                | "q"
+                | "q!"
+                | "bd"
+                | "bd!"
+                | "bdelete"
+                | "bdelete!"
+                | "scratch"
+                | "scratch!"
+        ) {
+            return EventResult::Ignored;
+        }
+        let args = match editor.expand_args(args) {
+            Ok(args) => args,
+            Err(message) => {
+                editor.set_error(message);
+                return EventResult::Consumed;
+            }
+        };
+
+        if editor.is_loading()
+            && matches!(verb, "w" | "wq" | "x" | "w!" | "wq!" | "x!" | "SudoWrite")
+        {
+            editor.set_error("Cannot write while the file is still loading");
+            return EventResult::Consumed;
+        }
+        if matches!(verb, "w" | "wq" | "x" | "w!" | "wq!" | "x!" | "SudoWrite")
+            && Self::defer_write(editor, trimmed)
+        {
+            return EventResult::Consumed;
+        }
 
         match verb {
-            "w" => {
-                let path = parts
-                    .next()
-                    .map(PathBuf::from)
-                    .or_else(|| editor.file_path.clone());
+            "w" | "w!" | "wq" | "x" | "wq!" | "x!" => {
+                let path = match args.as_str() {
+                    "" => editor.file_path.clone(),
+                    path => Some(PathBuf::from(path)),
+                };
+                let quit = !matches!(verb, "w" | "w!");
                 if let Some(path) = path {
-                    Self::save_to_path(editor, path);
+                    let force = verb.ends_with('!');
+                    if Self::may_write(editor, &path, force) {
+                        Self::save_to_path(editor, path, quit, force);
+                    }
                 } else {
-                    editor.set_status("No file name");
+                    editor.set_error("No file name");
                 }
-                EventResult::Consumed
             }
-            "wq" | "x" => {
-                let path = parts
-                    .next()
-                    .map(PathBuf::from)
-                    .or_else(|| editor.file_path.clone());
-                if let Some(path) = path {
-                    if Self::save_to_path(editor, path) {
-                        editor.should_quit = true;
-                    }
-                } else {
-                    editor.set_status("No file name");
+            "e" | "edit" | "e!" | "edit!" => {
+                if !args.is_empty() && !verb.ends_with('!') {
+                    Self::autowrite(editor, true);
                 }
-                EventResult::Consumed
+                Self::command_edit(editor, &args, verb.ends_with('!'));
             }
-            "q" => {
-                Self::command_quit(editor, false);
-                EventResult::Consumed
+            "SudoWrite" => Self::command_sudo_write(editor, &args),
+            "bd" | "bd!" | "bdelete" | "bdelete!" => {
+                Self::command_bdelete(editor, &args, verb.ends_with('!'))
             }
-            "q!" => {
-                Self::command_quit(editor, true);
-                EventResult::Consumed
+            "scratch" | "scratch!" => {
+                if Self::scratch_buffer(editor, Vec::new(), verb.ends_with('!')) {
+                    editor.set_status("Scratch buffer");
+                }
+            }
+            _ => {
+                if verb == "q" {
+                    Self::autowrite(editor, true);
+                }
+                Self::command_quit(editor, verb == "q!")
             }
-            _ => EventResult::Ignored,
         }
+        EventResult::Consumed
     }
-}
-
-pub struct ModePlugin;
 
-impl Plugin for ModePlugin {
     fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        // Focus changes are left for later plugins to see as well.
+        match event {
+            Event::FocusGained => {
+                editor.fire(AutoEvent::FocusGained);
+                Self::check_time(editor);
+                return EventResult::Ignored;
+            }
+            Event::FocusLost => {
+                editor.fire(AutoEvent::FocusLost);
+                if editor.options.autosave {
+                    Self::write_modified(editor);
+                }
+                return EventResult::Ignored;
+            }
+            _ => {}
+        }
+        if editor.mode != Mode::Normal || !editor.pending_keys.is_empty() {
+            return EventResult::Ignored;
+        }
         let Event::Key(key) = event else {
             return EventResult::Ignored;
         };
-
+        // Terminals send Ctrl-^ as Ctrl-6 or as the control character.
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
         match key.code {
-            KeyCode::Esc => {
-                editor.mode = Mode::Normal;
-                editor.command_line.active = false;
-                editor.command_line.input.clear();
-                EventResult::Consumed
-            }
-            KeyCode::Char('i') if editor.mode == Mode::Normal => {
-                editor.mode = Mode::Insert;
-                EventResult::Consumed
-            }
-            KeyCode::Char(':') if editor.mode == Mode::Normal => {
-                editor.mode = Mode::Command;
-                editor.command_line.active = true;
-                editor.command_line.input.clear();
-                EventResult::Consumed
-            }
-            _ => EventResult::Ignored,
+            KeyCode::Char('^' | '6') if control => {}
+            KeyCode::Char('\u{1e}') => {}
+            _ => return EventResult::Ignored,
         }
+        Self::edit_alternate(editor);
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "w",
+                "[file]",
+                "Write the buffer, or write it to another file",
+            ),
+            CommandInfo::new("w!", "[file]", "Write the buffer even if it is readonly"),
+            CommandInfo::new(
+                "SudoWrite",
+                "[file]",
+                "Write the buffer with sudo, for files only root may write",
+            ),
+            CommandInfo::new("wq", "[file]", "Write the buffer and quit"),
+            CommandInfo::new("x", "[file]", "Write the buffer and quit"),
+            CommandInfo::new(
+                "e",
+                "[file]",
+                "Edit another file, a directory in the explorer, or reload this one",
+            ),
+            CommandInfo::new(
+                "e!",
+                "[file]",
+                "Edit another file or reload this one, discarding changes",
+            ),
+            CommandInfo::new("q", "", "Quit, unless there are unsaved changes"),
+            CommandInfo::new("q!", "", "Quit without saving"),
+            CommandInfo::new(
+                "bd",
+                "[file]",
+                "Delete the buffer, switching to the alternate file",
+            ),
+            CommandInfo::new("bd!", "[file]", "Delete the buffer, discarding changes"),
+            CommandInfo::new(
+                "checktime",
+                "",
+                "Check whether the file was changed on disk",
+            ),
+            CommandInfo::new(
+                "scratch",
+                "",
+                "Edit an empty scratch buffer that is never saved",
+            ),
+        ];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Normal,
+            "<C-^>",
+            "Edit the alternate file, where the cursor was left",
+        )];
+        KEYS
     }
 }
 
-pub struct CommandLinePlugin;
+/// `:cd [dir]` changes the working directory (home without a directory,
+/// the previous one with `-`), `:pwd` shows it and the `autochdir` option
+/// follows the directory of each file opened. There is one window, so
+/// `:lcd` is `:cd`.
+pub struct DirectoryPlugin;
 
-impl Plugin for CommandLinePlugin {
-    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
-        if editor.mode != Mode::Command {
-            return EventResult::Ignored;
-        }
-        let Event::Key(key) = event else {
-            return EventResult::Ignored;
+impl DirectoryPlugin {
+    fn command_cd(editor: &mut Editor, dir: &str) {
+        let dir = match editor.expand_args(dir) {
+            Ok(dir) => dir,
+            Err(message) => {
+                editor.set_error(message);
+                return;
+            }
         };
-
-        match key.code {
-            KeyCode::Enter => {
-                let command = editor.command_line.input.trim().to_string();
-                editor.command_line.input.clear();
-                editor.command_line.active = false;
-                editor.mode = Mode::Normal;
-                if !command.is_empty() {
-                    editor.push_command(command);
+        let dir = match dir.as_str() {
+            "-" => match editor.previous_dir.clone() {
+                Some(previous) => previous,
+                None => {
+                    editor.set_error("No previous directory");
+                    return;
                 }
-                EventResult::Consumed
-            }
-            KeyCode::Backspace => {
-                editor.command_line.input.pop();
-                EventResult::Consumed
-            }
-            KeyCode::Char(ch) => {
-                if key.modifiers.contains(KeyModifiers::CONTROL)
-                    || key.modifiers.contains(KeyModifiers::ALT)
-                {
-                    return EventResult::Ignored;
+            },
+            "" => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home),
+                None => {
+                    editor.set_error("HOME is not set");
+                    return;
                 }
-                editor.command_line.input.push(ch);
-                EventResult::Consumed
-            }
-            _ => EventResult::Ignored,
+            },
+            dir => PathBuf::from(dir),
+        };
+        match editor.change_dir(&dir) {
+            Ok(()) => Self::command_pwd(editor),
+            Err(message) => editor.set_error(message),
+        }
+    }
+
+    fn command_pwd(editor: &mut Editor) {
+        match std::env::current_dir() {
+            Ok(dir) => editor.set_status(dir.display().to_string()),
+            Err(err) => editor.set_error(format!("Cannot get the working directory: {}", err)),
         }
     }
 }
 
-pub struct MotionPlugin;
+impl Plugin for DirectoryPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command) {
+            ("cd" | "chdir" | "chd" | "lcd" | "lchdir" | "lch", dir) => {
+                Self::command_cd(editor, dir)
+            }
+            ("pwd" | "pw", "") => Self::command_pwd(editor),
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
 
-impl Plugin for MotionPlugin {
-    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
-        if editor.mode != Mode::Normal {
-            return EventResult::Ignored;
+    fn events(&self) -> &'static [AutoEvent] {
+        &[AutoEvent::BufReadPost, AutoEvent::BufNewFile]
+    }
+
+    fn on_autocmd(&mut self, editor: &mut Editor, event: AutoEvent, _name: &str) {
+        if !editor.options.autochdir || !self.events().contains(&event) {
+            return;
         }
-        let Event::Key(key) = event else {
-            return EventResult::Ignored;
+        // A remote file has no directory here to change to.
+        let Some(dir) = editor
+            .file_path
+            .as_deref()
+            .filter(|path| !provider::is_remote(path))
+            .and_then(Path::parent)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+        else {
+            return;
         };
-
-        if key.modifiers.contains(KeyModifiers::CONTROL) {
-            return EventResult::Ignored;
+        if let Err(message) = editor.change_dir(&dir) {
+            editor.set_error(message);
         }
+    }
 
-        match key.code {
-            KeyCode::Char('h') | KeyCode::Left => {
-                editor.move_left();
-                EventResult::Consumed
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "cd",
+                "[dir]",
+                "Change the working directory (home without one, back with -)",
+            ),
+            CommandInfo::new("lcd", "[dir]", "Change the working directory, as :cd"),
+            CommandInfo::new("pwd", "", "Show the working directory"),
+        ];
+        COMMANDS
+    }
+}
+
+/// `:!command` runs a shell command and shows what it printed; `%` and `#`
+/// in it name the current and alternate files.
+pub struct ShellPlugin;
+
+impl ShellPlugin {
+    fn run(editor: &mut Editor, command: &str) {
+        let command = match editor.expand_args(command) {
+            Ok(command) if !command.is_empty() => command,
+            Ok(_) => {
+                editor.set_error("Argument required");
+                return;
             }
-            KeyCode::Char('l') | KeyCode::Right => {
-                editor.move_right();
-                EventResult::Consumed
-            }
-            KeyCode::Char('k') | KeyCode::Up => {
-                editor.move_up();
-                EventResult::Consumed
-            }
-            KeyCode::Char('j') | KeyCode::Down => {
-                editor.move_down();
-                EventResult::Consumed
-            }
-            KeyCode::Char('0') => {
-                editor.move_line_start();
-                EventResult::Consumed
-            }
-            KeyCode::Char('$') => {
-                editor.move_line_end();
-                EventResult::Consumed
+            Err(message) => {
+                editor.set_error(message);
+                return;
             }
-            KeyCode::Char('x') => {
-                editor.delete_char();
-                EventResult::Consumed
+        };
+        FileCommandPlugin::autowrite(editor, false);
+        let output = match Command::new("sh").arg("-c").arg(&command).output() {
+            Ok(output) => output,
+            Err(err) => {
+                editor.set_error(format!("Cannot run sh: {}", err));
+                return;
             }
-            _ => EventResult::Ignored,
+        };
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        let lines: Vec<String> = text.lines().map(strip_terminal_formatting).collect();
+        if !lines.is_empty() {
+            editor.open_scratch(format!("!{}", command), lines);
+        }
+        match output.status.code() {
+            Some(0) => {}
+            Some(code) => editor.set_status(format!("shell returned {}", code)),
+            None => editor.set_status(format!("shell {}", output.status)),
         }
     }
 }
 
-pub struct InsertPlugin;
+impl Plugin for ShellPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        if let ("sus" | "suspend" | "st" | "stop" | "sus!" | "suspend!" | "st!" | "stop!", "") =
+            split_command(command)
+        {
+            if !command.ends_with('!') {
+                FileCommandPlugin::autowrite(editor, false);
+            }
+            editor.suspend_requested = true;
+            return EventResult::Consumed;
+        }
+        let Some(command) = command.trim_start().strip_prefix('!') else {
+            return EventResult::Ignored;
+        };
+        Self::run(editor, command.trim());
+        EventResult::Consumed
+    }
 
-impl Plugin for InsertPlugin {
     fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
-        if editor.mode != Mode::Insert {
+        if !matches!(editor.mode, Mode::Normal | Mode::Visual) || !editor.pending_keys.is_empty() {
             return EventResult::Ignored;
         }
         let Event::Key(key) = event else {
             return EventResult::Ignored;
         };
+        // Raw mode delivers Ctrl-Z as a key, or as the control character.
+        match key.code {
+            KeyCode::Char('z') if key.modifiers.contains(KeyModifiers::CONTROL) => {}
+            KeyCode::Char('\u{1a}') => {}
+            _ => return EventResult::Ignored,
+        }
+        FileCommandPlugin::autowrite(editor, false);
+        editor.suspend_requested = true;
+        EventResult::Consumed
+    }
 
-        if key.modifiers.contains(KeyModifiers::CONTROL)
-            || key.modifiers.contains(KeyModifiers::ALT)
-        {
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new("!", "{command}", "Run a shell command and show its output"),
+            CommandInfo::new(
+                "suspend",
+                "",
+                "Stop the editor and go back to the shell until it is resumed",
+            ),
+        ];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Normal,
+            "<C-z>",
+            "Stop the editor and go back to the shell",
+        )];
+        KEYS
+    }
+}
+
+pub struct OptionsPlugin;
+
+impl Plugin for OptionsPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (verb, rest) = split_command(command);
+        if !matches!(verb, "set" | "se") {
             return EventResult::Ignored;
         }
-
-        match key.code {
-            KeyCode::Char(ch) => {
-                editor.insert_char(ch);
-                EventResult::Consumed
-            }
-            KeyCode::Enter => {
-                editor.insert_newline();
-                EventResult::Consumed
-            }
-            KeyCode::Backspace => {
-                editor.backspace();
-                EventResult::Consumed
-            }
-            KeyCode::Delete => {
-                editor.delete_char();
-                EventResult::Consumed
-            }
-            KeyCode::Tab => {
-                for _ in 0..4 {
-                    editor.insert_char(' ');
+        for arg in split_set_args(rest) {
+            match editor.options.apply(&arg) {
+                Ok(Some(message)) => editor.set_status(message),
+                Ok(None) => {}
+                Err(err) => {
+                    editor.set_error(err);
+                    break;
                 }
-                EventResult::Consumed
-            }
-            KeyCode::Left => {
-                editor.move_left();
-                EventResult::Consumed
-            }
-            KeyCode::Right => {
-                editor.move_right();
-                EventResult::Consumed
-            }
-            KeyCode::Up => {
-                editor.move_up();
-                EventResult::Consumed
-            }
-            KeyCode::Down => {
-                editor.move_down();
-                EventResult::Consumed
             }
-            _ => EventResult::Ignored,
         }
+        if editor.options.spell
+            && let Err(err) = editor.load_dictionary()
+        {
+            editor.set_error(err);
+        }
+        if editor.is_scratch_buffer() {
+            editor.dirty = false;
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "set",
+            "{option}",
+            "Change an option, or show it with `name?`",
+        )];
+        COMMANDS
     }
 }
 
-pub struct BufferRenderPlugin;
+/// `:highlight` sets the colors of UI elements and lists them.
+pub struct ThemePlugin;
 
-impl Plugin for BufferRenderPlugin {
-    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
-        let content_height = editor.content_height();
-        let width = ctx.width as usize;
-        for row in 0..content_height {
-            let buffer_row = editor.viewport.row_offset + row as usize;
-            if buffer_row < editor.buffer.lines.len() {
-                let line = &editor.buffer.lines[buffer_row];
-                let slice = slice_line(line, editor.viewport.col_offset, width);
-                ctx.set_line(row, slice);
-            } else {
-                ctx.set_line(row, "~".to_string());
-            }
+impl Plugin for ThemePlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (verb, rest) = split_command(command);
+        if !matches!(verb, "highlight" | "hi") {
+            return EventResult::Ignored;
+        }
+        if rest.is_empty() {
+            let lines = editor.theme.describe_all();
+            editor.open_scratch("highlight", lines);
+            return EventResult::Consumed;
         }
+        match editor.theme.apply(rest) {
+            Ok(Some(message)) => editor.set_status(message),
+            Ok(None) => {}
+            Err(err) => editor.set_error(err),
+        }
+        EventResult::Consumed
     }
-}
 
-pub struct SyntaxHighlightPlugin {
-    syntax_set: SyntaxSet,
-    theme: Theme,
-    cached_spans: Vec<Vec<StyledSpan>>,
-    last_revision: u64,
-    last_path: Option<PathBuf>,
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "highlight",
+            "[group] [key=value...]",
+            "Show or restyle highlight groups",
+        )];
+        COMMANDS
+    }
 }
 
-impl SyntaxHighlightPlugin {
-    pub fn new() -> Self {
-        let syntax_set = SyntaxSet::load_defaults_newlines();
-        let theme_set = ThemeSet::load_defaults();
-        let theme = theme_set
-            .themes
-            .get("base16-ocean.dark")
-            .cloned()
-            .or_else(|| theme_set.themes.values().next().cloned())
-            .expect("syntect themes are missing");
+/// Default file written by `:mksession` without an argument.
+const DEFAULT_SESSION_FILE: &str = "Session.minivim";
 
-        Self {
-            syntax_set,
-            theme,
-            cached_spans: Vec::new(),
-            last_revision: u64::MAX,
-            last_path: None,
+pub struct SessionPlugin;
+
+impl SessionPlugin {
+    /// Builds the ex commands that restore the current editor state.
+    fn session_commands(editor: &Editor) -> io::Result<Vec<String>> {
+        let mut commands = vec!["\" minivim session".to_string()];
+        commands.extend(editor.options.to_commands());
+        commands.extend(
+            editor
+                .abbreviations
+                .iter()
+                .map(|(lhs, rhs)| format!("iabbrev {} {}", lhs, rhs)),
+        );
+        if let Some(path) = editor.file_path.as_ref() {
+            let path = std::path::absolute(path)?;
+            let path = expand::escape(&path.to_string_lossy());
+            commands.push(format!("edit! {}", path));
+            commands.push(format!(
+                "cursor {} {}",
+                editor.cursor.row + 1,
+                editor.cursor.col + 1
+            ));
         }
+        Ok(commands)
     }
 
-    fn needs_rehighlight(&self, editor: &Editor) -> bool {
-        editor.revision != self.last_revision
-            || editor.file_path != self.last_path
-            || editor.buffer.lines.len() != self.cached_spans.len()
+    fn command_mksession(editor: &mut Editor, path: &str, force: bool) {
+        let path = PathBuf::from(if path.is_empty() {
+            DEFAULT_SESSION_FILE
+        } else {
+            path
+        });
+        if path.exists() && !force {
+            editor.set_error(format!("{} exists (add ! to overwrite)", path.display()));
+            return;
+        }
+        let result = Self::session_commands(editor)
+            .and_then(|commands| std::fs::write(&path, commands.join("\n") + "\n"));
+        match result {
+            Ok(()) => editor.set_status(format!("Wrote session {}", path.display())),
+            Err(err) => editor.set_error(format!("mksession failed: {}", err)),
+        }
     }
 
-    fn syntax_for_editor(&self, editor: &Editor) -> &SyntaxReference {
-        if let Some(path) = editor.file_path.as_ref() {
-            if let Ok(Some(syntax)) = self.syntax_set.find_syntax_for_file(path) {
-                return syntax;
-            }
+    fn command_source(editor: &mut Editor, path: &str) {
+        if path.is_empty() {
+            editor.set_error("Argument required");
+            return;
+        }
+        match std::fs::read_to_string(path) {
+            Ok(contents) => editor.push_commands_front(parse_script(&contents)),
+            Err(err) => editor.set_error(format!("Cannot source {}: {}", path, err)),
         }
-        self.syntax_set.find_syntax_plain_text()
     }
+}
 
-    fn rehighlight(&mut self, editor: &Editor) {
-        let syntax = self.syntax_for_editor(editor);
-        let mut highlighter = HighlightLines::new(syntax, &self.theme);
-        let mut spans = Vec::with_capacity(editor.buffer.lines.len());
-
-        for (idx, line) in editor.buffer.lines.iter().enumerate() {
-            let mut owned = line.clone();
-            if idx + 1 < editor.buffer.lines.len() {
-                owned.push('\n');
+impl Plugin for SessionPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (verb, arg) = split_command(command);
+        match verb {
+            "mksession" | "mks" | "mksession!" | "mks!" => {
+                Self::command_mksession(editor, arg, verb.ends_with('!'));
+                EventResult::Consumed
             }
-            let ranges = match highlighter.highlight_line(&owned, &self.syntax_set) {
-                Ok(ranges) => ranges,
-                Err(_) => Vec::new(),
-            };
-            let line_spans = Self::spans_from_ranges(&ranges);
-            spans.push(line_spans);
+            "source" | "so" => {
+                Self::command_source(editor, arg);
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
         }
+    }
 
-        self.cached_spans = spans;
-        self.last_revision = editor.revision;
-        self.last_path = editor.file_path.clone();
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "mksession",
+                "[file]",
+                "Save the file, cursor, options and abbreviations",
+            ),
+            CommandInfo::new("source", "{file}", "Run ex commands from a file"),
+        ];
+        COMMANDS
     }
+}
 
-    fn spans_from_ranges(ranges: &[(Style, &str)]) -> Vec<StyledSpan> {
-        let mut spans: Vec<StyledSpan> = Vec::new();
-        let mut col = 0usize;
+/// `:command Name replacement` defines an ex command that runs the
+/// replacement, `:command` lists the defined ones, `:delcommand Name`
+/// removes one and `:comclear` all of them. The commands are kept in the
+/// registry, so the palette and command-line completion offer them too.
+pub struct UserCommandPlugin;
 
-        for (style, text) in ranges {
-            let mut len = 0usize;
-            for ch in text.chars() {
-                if ch == '\n' || ch == '\r' {
-                    break;
-                }
-                len += 1;
+impl UserCommandPlugin {
+    const TITLE: &'static str = "user commands";
+
+    fn command_define(editor: &mut Editor, args: &str, replace: bool) {
+        let listing = |editor: &mut Editor, prefix: &str| {
+            let mut lines = vec![format!(
+                "  {:<16} {:<5} {:<5} Definition",
+                "Name", "Args", "Range"
+            )];
+            lines.extend(
+                editor
+                    .registry
+                    .user_commands
+                    .iter()
+                    .filter(|command| command.name.starts_with(prefix))
+                    .map(UserCommand::describe),
+            );
+            if lines.len() == 1 {
+                editor.set_status("No user-defined commands found");
+            } else {
+                editor.open_scratch(Self::TITLE, lines);
             }
-            if len == 0 {
-                continue;
+        };
+        if args.is_empty() {
+            listing(editor, "");
+            return;
+        }
+        let command = match UserCommand::parse(args) {
+            Ok(command) => command,
+            Err(message) => {
+                editor.set_error(message);
+                return;
             }
+        };
+        if command.replacement.is_empty() {
+            listing(editor, &command.name);
+        } else if let Err(message) = editor.registry.define(command, replace) {
+            editor.set_error(message);
+        }
+    }
 
-            let content_style = Self::map_style(*style);
-            if let Some(last) = spans.last_mut() {
-                if last.style == content_style && last.start + last.len == col {
-                    last.len += len;
-                    col += len;
-                    continue;
+    /// Runs user command `command`, if it names one: checks its range,
+    /// bang and arguments, then runs the expanded replacement next.
+    fn run(editor: &mut Editor, command: &str) -> EventResult {
+        let (range, rest) = match ex::parse_range(command, editor) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                if editor
+                    .registry
+                    .user_command(ex::command_name(command))
+                    .is_none()
+                {
+                    return EventResult::Ignored;
                 }
+                editor.set_error(message);
+                return EventResult::Consumed;
+            }
+        };
+        let rest = rest.trim_start();
+        let end = rest
+            .find(|ch: char| !ch.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        let Some(user_command) = editor.registry.user_command(&rest[..end]) else {
+            return EventResult::Ignored;
+        };
+        let (bang, args) = match rest[end..].strip_prefix('!') {
+            Some(args) => (true, args.trim()),
+            None => (false, rest[end..].trim()),
+        };
+        let row = editor.cursor.row;
+        let last = editor.buffer.lines.len().saturating_sub(1);
+        let lines = match (range, user_command.range) {
+            (Some(_), RangeDefault::None) => Err("No range allowed".to_string()),
+            (Some(range), _) => Ok((range.start, range.end)),
+            (None, RangeDefault::File) => Ok((0, last)),
+            (None, _) => Ok((row, row)),
+        };
+        let result = lines.and_then(|(start, end)| {
+            if bang && !user_command.bang {
+                Err("No ! allowed".to_string())
+            } else if !user_command.nargs.accepts(args) {
+                Err(if args.is_empty() {
+                    "Argument required".to_string()
+                } else {
+                    "Invalid argument".to_string()
+                })
+            } else {
+                Ok(user_command.expand(args, (start + 1, end + 1), bang))
             }
+        });
+        match result {
+            Ok(expanded) => editor.push_commands_front(vec![expanded]),
+            Err(message) => editor.set_error(message),
+        }
+        EventResult::Consumed
+    }
+}
 
-            spans.push(StyledSpan {
-                start: col,
-                len,
-                style: content_style,
-            });
-            col += len;
+impl Plugin for UserCommandPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (verb, args) = split_command(command);
+        match verb {
+            "command" | "com" | "command!" | "com!" => {
+                Self::command_define(editor, args, verb.ends_with('!'));
+            }
+            "delcommand" | "delc" => {
+                if !editor.registry.undefine(args) {
+                    editor.set_error(format!("No such user-defined command: {}", args));
+                }
+            }
+            "comclear" | "comc" => editor.registry.user_commands.clear(),
+            _ => return Self::run(editor, command),
         }
+        EventResult::Consumed
+    }
 
-        spans
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "command",
+                "[-nargs=N] [-range] [-bang] {Name} {replacement}",
+                "Define an ex command that runs the replacement",
+            ),
+            CommandInfo::new("delcommand", "{Name}", "Remove a user-defined command"),
+            CommandInfo::new("comclear", "", "Remove every user-defined command"),
+        ];
+        COMMANDS
     }
+}
 
-    fn map_style(style: Style) -> ContentStyle {
-        let mut content = ContentStyle::new();
-        content.foreground_color = Self::map_color(style.foreground);
-        content.background_color = Self::map_color(style.background);
-        let mut attrs = Attributes::default();
-        if style.font_style.contains(FontStyle::BOLD) {
-            attrs.set(Attribute::Bold);
-        }
-        if style.font_style.contains(FontStyle::ITALIC) {
-            attrs.set(Attribute::Italic);
+/// `:autocmd Event pattern command` runs an ex command whenever the event
+/// fires for a file matching the pattern; `:autocmd!` removes autocommands
+/// and `:doautocmd` fires an event by hand.
+pub struct AutocmdPlugin;
+
+impl AutocmdPlugin {
+    fn command_autocmd(editor: &mut Editor, args: &str, remove: bool) {
+        let autocmd::Definition {
+            events,
+            pattern,
+            command,
+        } = match autocmd::parse(args) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                editor.set_error(message);
+                return;
+            }
+        };
+        if remove {
+            for event in events.clone().unwrap_or_default() {
+                editor.autocmds.remove(Some(event), pattern);
+            }
+            if events.is_none() {
+                editor.autocmds.remove(None, None);
+            }
         }
-        if style.font_style.contains(FontStyle::UNDERLINE) {
-            attrs.set(Attribute::Underlined);
+        match (events, pattern) {
+            (Some(events), Some(pattern)) if !command.is_empty() => {
+                if let Err(message) = editor.autocmds.define(&events, pattern, command) {
+                    editor.set_error(message);
+                }
+            }
+            _ if remove => {}
+            (events, _) => {
+                let event = events
+                    .filter(|events| events.len() == 1)
+                    .map(|events| events[0]);
+                let lines = editor.autocmds.describe(event);
+                if lines.is_empty() {
+                    editor.set_status("No autocommands");
+                } else {
+                    editor.open_scratch("autocommands", lines);
+                }
+            }
         }
-        content.attributes = attrs;
-        content
     }
 
-    fn map_color(color: SyntectColor) -> Option<Color> {
-        if color.a == 0 {
-            None
+    fn command_doautocmd(editor: &mut Editor, args: &str) {
+        let (name, file) = split_command(args);
+        let Some(event) = AutoEvent::parse(name) else {
+            editor.set_error(format!("No such event: {}", name));
+            return;
+        };
+        if file.is_empty() {
+            editor.fire(event);
         } else {
-            Some(Color::Rgb {
-                r: color.r,
-                g: color.g,
-                b: color.b,
-            })
+            editor.autocmds.fire(event, Some(file.to_string()));
         }
     }
+}
 
-    fn slice_spans(spans: &[StyledSpan], col_offset: usize, width: usize) -> Vec<StyledSpan> {
-        if width == 0 {
-            return Vec::new();
+impl Plugin for AutocmdPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (verb, args) = split_command(command);
+        match verb {
+            "autocmd" | "au" | "autocmd!" | "au!" => {
+                Self::command_autocmd(editor, args, verb.ends_with('!'));
+            }
+            "doautocmd" | "do" => Self::command_doautocmd(editor, args),
+            _ => return EventResult::Ignored,
         }
-        let end = col_offset.saturating_add(width);
-        let mut visible = Vec::new();
-        for span in spans {
-            let span_start = span.start;
-            let span_end = span.start + span.len;
-            if span_end <= col_offset || span_start >= end {
+        EventResult::Consumed
+    }
+
+    fn on_autocmd(&mut self, editor: &mut Editor, event: AutoEvent, name: &str) {
+        let commands = editor.autocmds.commands_for(event, name);
+        editor.run_autocmds(commands);
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "autocmd",
+                "[event] [pattern] [command]",
+                "Run a command when an event fires, or list autocommands",
+            ),
+            CommandInfo::new("autocmd!", "[event] [pattern]", "Remove autocommands"),
+            CommandInfo::new("doautocmd", "{event} [file]", "Fire an autocommand event"),
+        ];
+        COMMANDS
+    }
+}
+
+/// `g?` shows every key binding of the loaded plugins, by mode, in a
+/// full-screen view; `?`, `q` or `Esc` closes it.
+pub struct CheatSheetPlugin;
+
+impl CheatSheetPlugin {
+    const TITLE: &'static str = "cheat sheet";
+
+    /// The sheet's lines: a heading per mode, then its keys and what they
+    /// do, in the order the plugins are loaded.
+    fn lines(registry: &Registry) -> Vec<String> {
+        let keys_width = registry
+            .keys
+            .iter()
+            .map(|info| display_width(info.keys))
+            .max()
+            .unwrap_or(0)
+            .min(20);
+        let mut lines = Vec::new();
+        for (mode, heading) in [
+            (Mode::Normal, "Normal mode"),
+            (Mode::Visual, "Visual mode"),
+            (Mode::Insert, "Insert mode"),
+            (Mode::Command, "Command-line mode"),
+        ] {
+            let keys: Vec<&KeyInfo> = registry
+                .keys
+                .iter()
+                .filter(|info| info.mode == mode)
+                .collect();
+            if keys.is_empty() {
                 continue;
             }
-            let start = span_start.max(col_offset) - col_offset;
-            let end = span_end.min(end) - col_offset;
-            let len = end.saturating_sub(start);
-            if len == 0 {
-                continue;
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.push(heading.to_string());
+            for info in keys {
+                let padding = keys_width.saturating_sub(display_width(info.keys));
+                lines.push(format!(
+                    "  {}{}  {}",
+                    info.keys,
+                    " ".repeat(padding),
+                    info.description
+                ));
             }
-            visible.push(StyledSpan {
-                start,
-                len,
-                style: span.style,
-            });
         }
-        visible
+        lines
     }
 }
 
-impl Plugin for SyntaxHighlightPlugin {
-    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
-        if self.needs_rehighlight(editor) {
-            self.rehighlight(editor);
+impl Plugin for CheatSheetPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if key.code != KeyCode::Char('?') {
+            return EventResult::Ignored;
         }
-
-        let width = ctx.width as usize;
-        let content_height = editor.content_height();
-        for row in 0..content_height {
-            let buffer_row = editor.viewport.row_offset + row as usize;
-            if buffer_row >= self.cached_spans.len() {
-                continue;
+        match editor.scratch.as_ref() {
+            Some(view) if view.title == Self::TITLE => editor.close_scratch(),
+            None if editor.mode == Mode::Normal && editor.pending_keys == "g" => {
+                editor.pending_keys.clear();
+                let lines = Self::lines(&editor.registry);
+                editor.open_scratch(Self::TITLE, lines);
             }
-            let spans = Self::slice_spans(
-                &self.cached_spans[buffer_row],
-                editor.viewport.col_offset,
-                width,
-            );
-            ctx.set_spans(row, spans);
+            _ => return EventResult::Ignored,
         }
+        EventResult::Consumed
     }
-}
 
-pub struct StatusBarPlugin;
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Normal,
+            "g?",
+            "Show the key bindings of every mode",
+        )];
+        KEYS
+    }
+}
 
-impl Plugin for StatusBarPlugin {
-    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
-        if ctx.height == 0 {
-            return;
-        }
+pub struct ScratchViewPlugin;
 
-        let mode_label = match editor.mode {
-            Mode::Normal => "NORMAL",
-            Mode::Insert => "INSERT",
-            Mode::Command => "COMMAND",
+impl Plugin for ScratchViewPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let page = editor.content_height().max(1) as usize;
+        let Some(view) = editor.scratch.as_mut() else {
+            return EventResult::Ignored;
+        };
+        let max_scroll = view.lines.len().saturating_sub(page);
+        let key = match event {
+            Event::Key(key) => key,
+            Event::Mouse(mouse) => {
+                match mouse.kind {
+                    MouseEventKind::ScrollDown => {
+                        view.scroll = (view.scroll + MOUSE_SCROLL_LINES).min(max_scroll);
+                    }
+                    MouseEventKind::ScrollUp => {
+                        view.scroll = view.scroll.saturating_sub(MOUSE_SCROLL_LINES);
+                    }
+                    _ => {}
+                }
+                return EventResult::Consumed;
+            }
+            _ => return EventResult::Ignored,
         };
 
-        let name = editor
-            .file_path
-            .as_ref()
-            .map(|path| path.display().to_string())
-            .unwrap_or_else(|| "[No Name]".to_string());
-        let dirty = if editor.dirty { " [+]" } else { "" };
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                editor.close_scratch();
+            }
+            KeyCode::Char('e') => {
+                // The output goes into a scratch buffer, to search and yank.
+                let lines = view.lines.clone();
+                if FileCommandPlugin::scratch_buffer(editor, lines, false) {
+                    editor.close_scratch();
+                }
+            }
+            KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter => {
+                view.scroll = (view.scroll + 1).min(max_scroll);
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                view.scroll = view.scroll.saturating_sub(1);
+            }
+            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                view.scroll = (view.scroll + page / 2).min(max_scroll);
+            }
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                view.scroll = view.scroll.saturating_sub(page / 2);
+            }
+            KeyCode::Char(' ') | KeyCode::PageDown => {
+                view.scroll = (view.scroll + page).min(max_scroll);
+            }
+            KeyCode::Char('b') | KeyCode::PageUp => {
+                view.scroll = view.scroll.saturating_sub(page);
+            }
+            KeyCode::Char('g') | KeyCode::Home => {
+                view.scroll = 0;
+            }
+            KeyCode::Char('G') | KeyCode::End => {
+                view.scroll = max_scroll;
+            }
+            _ => {}
+        }
+        EventResult::Consumed
+    }
 
-        let left = format!("{} {}{}", mode_label, name, dirty);
-        let right = if editor.status.is_empty() {
-            format!(
-                "Ln {}, Col {}",
-                editor.cursor.row + 1,
-                editor.cursor.col + 1
-            )
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        let Some(view) = editor.scratch.as_ref() else {
+            return;
+        };
+        for row in 0..editor.content_height() {
+            let line = view
+                .lines
+                .get(view.scroll + row as usize)
+                .cloned()
+                .unwrap_or_else(|| "~".to_string());
+            ctx.set_line(row, line);
+            ctx.set_spans(row, Vec::new());
+        }
+    }
+}
+
+/// `:palette` or `Ctrl-Shift-P` opens the command palette over every
+/// registered command and key binding. Typing narrows it, `Up`/`Down`
+/// (`Ctrl-P`/`Ctrl-N`) move the selection, `Enter` runs it and `Esc` closes.
+pub struct PalettePlugin;
+
+impl PalettePlugin {
+    fn is_palette_key(key: &KeyEvent) -> bool {
+        // Terminals report Ctrl-Shift-P as `P` with Control, or as `p` with
+        // both modifiers under the kitty keyboard protocol.
+        key.modifiers.contains(KeyModifiers::CONTROL)
+            && (key.code == KeyCode::Char('P')
+                || (key.code == KeyCode::Char('p') && key.modifiers.contains(KeyModifiers::SHIFT)))
+    }
+
+    fn open(editor: &mut Editor) {
+        editor.popup = None;
+        editor.palette = Some(Palette::new(&editor.registry));
+    }
+
+    /// Runs a palette entry. A command needing arguments is left on the
+    /// command line to finish; keys are typed from Normal mode, after the
+    /// key that enters their own mode.
+    fn run(editor: &mut Editor, action: Action) {
+        match action {
+            Action::Command(info) if info.needs_args() => {
+                editor.mode = Mode::Command;
+                editor.command_line.open(':');
+                editor.command_line.set_input(info.template());
+            }
+            Action::Command(info) => editor.push_command(info.name.to_string()),
+            Action::UserCommand(index) => {
+                let Some(command) = editor.registry.user_commands.get(index) else {
+                    return;
+                };
+                let name = command.name.clone();
+                if command.nargs.accepts("") {
+                    editor.push_command(name);
+                } else {
+                    editor.mode = Mode::Command;
+                    editor.command_line.open(':');
+                    editor.command_line.set_input(format!("{} ", name));
+                }
+            }
+            Action::Keys(info) => {
+                let enter = match info.mode {
+                    Mode::Normal => "",
+                    Mode::Insert => "i",
+                    Mode::Visual => "v",
+                    Mode::Command => ":",
+                };
+                let keys = registry::parse_keys(&format!("{}{}", enter, info.keys));
+                editor.feed_keys(keys);
+            }
+        }
+    }
+}
+
+impl Plugin for PalettePlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Some(palette) = editor.palette.as_mut() else {
+            if let Event::Key(key) = event
+                && editor.mode == Mode::Normal
+                && editor.pending_keys.is_empty()
+                && Self::is_palette_key(key)
+            {
+                Self::open(editor);
+                return EventResult::Consumed;
+            }
+            return EventResult::Ignored;
+        };
+        let key = match event {
+            Event::Key(key) => key,
+            Event::Paste(text) => {
+                let line = text.lines().next().unwrap_or("");
+                palette.set_query(format!("{}{}", palette.query, line));
+                return EventResult::Consumed;
+            }
+            Event::Mouse(_) => {
+                editor.palette = None;
+                return EventResult::Ignored;
+            }
+            _ => return EventResult::Ignored,
+        };
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => editor.palette = None,
+            KeyCode::Enter => {
+                let action = palette.selected_entry().map(|entry| entry.action);
+                editor.palette = None;
+                if let Some(action) = action {
+                    Self::run(editor, action);
+                }
+            }
+            KeyCode::Down => palette.step(true),
+            KeyCode::Up => palette.step(false),
+            KeyCode::Char('n') if control => palette.step(true),
+            KeyCode::Char('p') if control => palette.step(false),
+            KeyCode::Char('u') if control => palette.set_query(String::new()),
+            KeyCode::Backspace => {
+                let mut query = palette.query.clone();
+                query.pop();
+                palette.set_query(query);
+            }
+            KeyCode::Char(ch) if !control => {
+                palette.set_query(format!("{}{}", palette.query, ch));
+            }
+            _ => {}
+        }
+        EventResult::Consumed
+    }
+
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        if command != "palette" {
+            return EventResult::Ignored;
+        }
+        Self::open(editor);
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] =
+            &[CommandInfo::new("palette", "", "Open the command palette")];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Normal,
+            "<C-S-p>",
+            "Open the command palette",
+        )];
+        KEYS
+    }
+}
+
+/// Draws the command palette at the top of the window: the query, then the
+/// matching entries with their descriptions.
+pub struct PaletteRenderPlugin;
+
+impl Plugin for PaletteRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        let Some(palette) = editor.palette.as_ref() else {
+            return;
+        };
+        let text_style = editor.theme.style(HighlightGroup::NormalFloat);
+        let border = editor.theme.style(HighlightGroup::FloatBorder);
+        let selected_style = editor.theme.style(HighlightGroup::PmenuSel);
+        let width = Popup::text_width(ctx.width as usize);
+        let left = (ctx.width as usize).saturating_sub(width + 2) / 2;
+        // The borders, the query and the line under it take four rows.
+        let height = palette
+            .matches()
+            .len()
+            .min(PALETTE_MAX_HEIGHT)
+            .min((editor.content_height() as usize).saturating_sub(4));
+        let first = palette.first_visible(height);
+        let shown: Vec<&palette::Entry> = palette.matches().skip(first).take(height).collect();
+        let label_width = shown
+            .iter()
+            .map(|entry| display_width(&entry.label))
+            .max()
+            .unwrap_or(0)
+            .min(width / 2);
+
+        let draw_row = |ctx: &mut RenderContext, row: u16, text: &str, style| {
+            let text = truncate_to_width(text, width);
+            let padding = width - display_width(text);
+            ctx.draw_text(row, left, "│", border);
+            ctx.draw_text(
+                row,
+                left + 1,
+                &format!("{}{}", text, " ".repeat(padding)),
+                style,
+            );
+            ctx.draw_text(row, left + 1 + width, "│", border);
+        };
+        let title = popup::border_row('┌', "Command Palette", '┐', width);
+        ctx.draw_text(0, left, &title, border);
+        // Shows the end of a query too long for the box.
+        // `> ` and the cursor take three columns.
+        let mut query = palette.query.as_str();
+        while display_width(query) + 3 > width {
+            let first = query.graphemes(true).next().map_or(0, str::len);
+            query = &query[first..];
+        }
+        draw_row(ctx, 1, &format!("> {}", query), text_style);
+        ctx.draw_text(2, left, &popup::border_row('├', "", '┤', width), border);
+        for (offset, entry) in shown.iter().enumerate() {
+            let label = truncate_to_width(&entry.label, label_width);
+            let padding = label_width - display_width(label);
+            let text = format!(" {}{}  {}", label, " ".repeat(padding), entry.description);
+            let style = if first + offset == palette.selected {
+                selected_style
+            } else {
+                text_style
+            };
+            draw_row(ctx, (3 + offset) as u16, &text, style);
+        }
+        let position = if palette.matches().len() == 0 {
+            "no matches".to_string()
         } else {
-            editor.status.clone()
+            format!("{}/{}", palette.selected + 1, palette.matches().len())
         };
+        let bottom = popup::border_row('└', &position, '┘', width);
+        ctx.draw_text((3 + height) as u16, left, &bottom, border);
+        ctx.set_cursor(1, (left + 3 + display_width(query)) as u16);
+    }
+}
 
-        let line = format_status_line(&left, &right, ctx.width as usize);
-        ctx.set_line(editor.status_row(), line);
+/// Scrolls and closes the popup. While one is shown, `j`/`k`, `Ctrl-D`/
+/// `Ctrl-U` and `g`/`G` scroll it and `Esc` or `q` close it; any other key
+/// closes it and then acts as usual.
+pub struct PopupPlugin;
+
+impl Plugin for PopupPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let half_page = (editor.content_height() / 4).max(1) as usize;
+        let width = Popup::text_width(editor.screen_width as usize);
+        let Some(popup) = editor.popup.as_mut() else {
+            return EventResult::Ignored;
+        };
+        let last = popup
+            .rows(width, ContentStyle::new())
+            .len()
+            .saturating_sub(1);
+        let scroll = popup.scroll;
+        popup.scroll = match event {
+            Event::Key(key) => match key.code {
+                KeyCode::Esc | KeyCode::Char('q') => {
+                    editor.popup = None;
+                    return EventResult::Consumed;
+                }
+                KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    scroll + half_page
+                }
+                KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    scroll.saturating_sub(half_page)
+                }
+                KeyCode::Char('j') | KeyCode::Down => scroll + 1,
+                KeyCode::Char('k') | KeyCode::Up => scroll.saturating_sub(1),
+                KeyCode::Char('g') | KeyCode::Home => 0,
+                KeyCode::Char('G') | KeyCode::End => last,
+                _ => {
+                    editor.popup = None;
+                    return EventResult::Ignored;
+                }
+            },
+            Event::Mouse(mouse) => match mouse.kind {
+                MouseEventKind::ScrollDown => scroll + MOUSE_SCROLL_LINES,
+                MouseEventKind::ScrollUp => scroll.saturating_sub(MOUSE_SCROLL_LINES),
+                _ => {
+                    editor.popup = None;
+                    return EventResult::Ignored;
+                }
+            },
+            _ => return EventResult::Ignored,
+        }
+        .min(last);
+        EventResult::Consumed
     }
 }
 
-pub struct CommandLineRenderPlugin;
+/// Draws the popup in a border below the cursor, or above it when there
+/// is more room there, scrolled to `Popup::scroll`.
+pub struct PopupRenderPlugin;
 
-impl Plugin for CommandLineRenderPlugin {
+impl Plugin for PopupRenderPlugin {
     fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
-        if !editor.command_line.active || ctx.height == 0 {
+        let Some(popup) = editor.popup.as_ref() else {
+            return;
+        };
+        let text_style = editor.theme.style(HighlightGroup::NormalFloat);
+        let border = editor.theme.style(HighlightGroup::FloatBorder);
+        let max_width = Popup::text_width(ctx.width as usize);
+        let rows = popup.rows(max_width, text_style);
+        let content_height = editor.content_height() as usize;
+        let (cursor_row, cursor_col) = ctx
+            .cursor
+            .map_or((0, 0), |(row, col)| (row as usize, col as usize));
+        let cursor_row = cursor_row.min(content_height.saturating_sub(1));
+        let below = content_height.saturating_sub(cursor_row + 1);
+        let above = cursor_row;
+        let (top, height) = if rows.len() + 2 <= below || below >= above {
+            (cursor_row + 1, rows.len().min(below.saturating_sub(2)))
+        } else {
+            let height = rows.len().min(above.saturating_sub(2));
+            (cursor_row - height - 2, height)
+        };
+        if height == 0 {
             return;
         }
-        let prompt = format!(":{}", editor.command_line.input);
-        ctx.set_line(editor.command_row(), prompt);
+        let scroll = popup.scroll.min(rows.len() - height);
+        let position = if rows.len() > height {
+            format!("{}-{}/{}", scroll + 1, scroll + height, rows.len())
+        } else {
+            String::new()
+        };
+        let width = popup::rows_width(&rows)
+            .max(display_width(&popup.title) + 2)
+            .max(display_width(&position) + 2)
+            .min(max_width);
+        let left = cursor_col.min((ctx.width as usize).saturating_sub(width + 2));
+
+        let top_row = popup::border_row('┌', &popup.title, '┐', width);
+        ctx.draw_text(top as u16, left, &top_row, border);
+        for (offset, (text, spans)) in rows[scroll..scroll + height].iter().enumerate() {
+            let row = (top + 1 + offset) as u16;
+            let padding = width - display_width(text);
+            ctx.draw_text(row, left, "│", border);
+            ctx.draw_text(
+                row,
+                left + 1,
+                &format!("{}{}", text, " ".repeat(padding)),
+                text_style,
+            );
+            for span in spans {
+                ctx.add_span(
+                    row,
+                    StyledSpan {
+                        start: left + 1 + span.start,
+                        ..*span
+                    },
+                );
+            }
+            ctx.draw_text(row, left + 1 + width, "│", border);
+        }
+        let bottom_row = popup::border_row('└', &position, '┘', width);
+        ctx.draw_text((top + 1 + height) as u16, left, &bottom_row, border);
     }
 }
 
-pub struct CursorRenderPlugin;
+/// Lists the keys that can follow an unfinished sequence such as `g`, `"`
+/// or an operator, in a box above the status line, once it has waited
+/// `whichkey` milliseconds. The list comes from the registry, so every
+/// plugin's bindings appear in it.
+pub struct WhichKeyPlugin {
+    /// The pending keys and when they were first drawn.
+    pending: Option<(String, Instant)>,
+}
 
-impl Plugin for CursorRenderPlugin {
+impl WhichKeyPlugin {
+    pub fn new() -> Self {
+        Self { pending: None }
+    }
+
+    /// Whether the hints for the pending keys are due, starting the wait
+    /// when the keys are new.
+    fn due(&mut self, editor: &Editor, delay: Duration) -> bool {
+        match &self.pending {
+            Some((keys, since)) if *keys == editor.pending_keys => since.elapsed() >= delay,
+            _ => {
+                self.pending = Some((editor.pending_keys.clone(), Instant::now()));
+                // Nothing else may be drawn until the next key, so wake the
+                // main loop to show the hints.
+                let waker = editor.waker.clone();
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    waker.wake();
+                });
+                false
+            }
+        }
+    }
+}
+
+impl Plugin for WhichKeyPlugin {
     fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
-        if ctx.height == 0 || ctx.width == 0 {
+        let delay = editor.options.whichkey;
+        if delay == 0
+            || editor.pending_keys.is_empty()
+            || !matches!(editor.mode, Mode::Normal | Mode::Visual)
+        {
+            self.pending = None;
             return;
         }
-        if editor.command_line.active {
-            let row = editor.command_row().min(ctx.height.saturating_sub(1));
-            let col = (1 + editor.command_line.input.chars().count()) as u16;
-            let clamped = col.min(ctx.width.saturating_sub(1));
-            ctx.set_cursor(row, clamped);
+        if !self.due(editor, Duration::from_millis(delay as u64)) {
+            return;
+        }
+        let hints = editor
+            .registry
+            .continuations(editor.mode, &editor.pending_keys);
+        let width = (ctx.width as usize).saturating_sub(2);
+        let content_height = editor.content_height() as usize;
+        if hints.is_empty() || width == 0 || content_height < 3 {
             return;
         }
 
-        let cursor_row = editor.cursor.row.saturating_sub(editor.viewport.row_offset) as u16;
-        let cursor_col = editor.cursor.col.saturating_sub(editor.viewport.col_offset) as u16;
-        let row = cursor_row.min(ctx.height.saturating_sub(1));
-        let col = cursor_col.min(ctx.width.saturating_sub(1));
-        ctx.set_cursor(row, col);
+        // Each hint is a space, its keys, two spaces and its description;
+        // columns of hints are two spaces apart.
+        let keys_width = hints
+            .iter()
+            .map(|(keys, _)| display_width(keys))
+            .max()
+            .unwrap_or(0)
+            .min(width / 2);
+        let cell_width = hints
+            .iter()
+            .map(|(_, description)| keys_width + 3 + display_width(description))
+            .max()
+            .unwrap_or(0)
+            .min(width);
+        let columns = ((width + 2) / (cell_width + 2)).max(1);
+        let rows = hints
+            .len()
+            .div_ceil(columns)
+            .min(content_height.saturating_sub(2));
+        let top = content_height - rows - 2;
+        let text_style = editor.theme.style(HighlightGroup::NormalFloat);
+        let border = editor.theme.style(HighlightGroup::FloatBorder);
+        let mut key_style = text_style;
+        key_style.attributes.set(Attribute::Bold);
+
+        let title = popup::border_row('┌', &editor.pending_keys, '┐', width);
+        ctx.draw_text(top as u16, 0, &title, border);
+        for row in 0..rows {
+            let screen_row = (top + 1 + row) as u16;
+            ctx.draw_text(screen_row, 0, "│", border);
+            ctx.draw_text(screen_row, 1, &" ".repeat(width), text_style);
+            for column in 0..columns {
+                let Some((keys, description)) = hints.get(row * columns + column) else {
+                    break;
+                };
+                let left = 1 + column * (cell_width + 2);
+                let keys = truncate_to_width(keys, keys_width);
+                let padding = keys_width - display_width(keys);
+                let cell = format!(" {}{}  {}", keys, " ".repeat(padding), description);
+                ctx.draw_text(
+                    screen_row,
+                    left,
+                    truncate_to_width(&cell, cell_width),
+                    text_style,
+                );
+                ctx.add_span(
+                    screen_row,
+                    StyledSpan {
+                        start: left + 1,
+                        len: display_width(keys),
+                        style: key_style,
+                    },
+                );
+            }
+            ctx.draw_text(screen_row, 1 + width, "│", border);
+        }
+        let bottom = popup::border_row('└', "", '┘', width);
+        ctx.draw_text((top + rows + 1) as u16, 0, &bottom, border);
     }
 }
 
-fn slice_line(line: &str, col_offset: usize, width: usize) -> String {
-    line.chars()
-        .skip(col_offset)
-        .take(width)
-        .collect::<String>()
+/// `:help {topic}` (`:h`) shows the README entry that documents `topic` in
+/// a popup; `:help` alone opens the whole README.
+pub struct HelpPlugin;
+
+impl HelpPlugin {
+    const README: &'static str = include_str!("../README.md");
+
+    /// The list item or paragraph that documents `topic`: preferably one
+    /// naming it in backticks, as in `` `:Lint `` or `` `]d` ``.
+    fn excerpt(topic: &str) -> Option<String> {
+        let mut entries: Vec<Vec<&str>> = Vec::new();
+        let mut continues = false;
+        for line in Self::README.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continues = false;
+                continue;
+            }
+            match entries.last_mut() {
+                Some(entry) if continues && !line.starts_with("- ") => entry.push(line),
+                _ => entries.push(vec![line]),
+            }
+            continues = true;
+        }
+        let named = [format!("`{}", topic), format!("`:{}", topic)];
+        let lower = topic.to_lowercase();
+        entries
+            .iter()
+            .find(|entry| named.iter().any(|name| entry[0].contains(name.as_str())))
+            .or_else(|| {
+                entries.iter().find(|entry| {
+                    entry
+                        .iter()
+                        .any(|line| line.to_lowercase().contains(&lower))
+                })
+            })
+            .map(|entry| entry.join("\n"))
+    }
 }
 
-fn format_status_line(left: &str, right: &str, width: usize) -> String {
-    if width == 0 {
-        return String::new();
+impl Plugin for HelpPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let ("h" | "help", topic) = split_command(command) else {
+            return EventResult::Ignored;
+        };
+        if topic.is_empty() {
+            let lines = Self::README.lines().map(String::from).collect();
+            editor.open_scratch("help", lines);
+        } else {
+            match Self::excerpt(topic) {
+                Some(text) => {
+                    editor.popup = Some(Popup::markdown(format!("help {}", topic), &text));
+                }
+                None => editor.set_error(format!("No help for {}", topic)),
+            }
+        }
+        EventResult::Consumed
     }
-    let right_len = right.chars().count();
 
-    if right_len >= width {
-        return right.chars().take(width).collect();
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "help",
+            "[topic]",
+            "Show the documentation for a command, key or option",
+        )];
+        COMMANDS
     }
+}
 
-    let available_left = width.saturating_sub(right_len + 1);
-    let left_trimmed: String = left.chars().take(available_left).collect();
-    let padding = width.saturating_sub(left_trimmed.chars().count() + right_len);
-    format!("{}{}{}", left_trimmed, " ".repeat(padding), right)
+pub struct KeywordLookupPlugin;
+
+impl KeywordLookupPlugin {
+    fn program_for(editor: &Editor) -> String {
+        if let Some(program) = editor.options.keywordprg.as_ref() {
+            return program.clone();
+        }
+        match editor.file_extension() {
+            "rs" => "rustup doc --std",
+            "py" => "pydoc3",
+            "pl" | "pm" => "perldoc",
+            _ => "man",
+        }
+        .to_string()
+    }
+
+    fn lookup(editor: &mut Editor, program: &str, word: &str) {
+        let mut args = program.split_whitespace();
+        let Some(bin) = args.next() else {
+            editor.set_error("keywordprg is empty");
+            return;
+        };
+        let output = Command::new(bin)
+            .args(args)
+            .arg(word)
+            .env("MANPAGER", "cat")
+            .env("PAGER", "cat")
+            .env(
+                "MANWIDTH",
+                Popup::text_width(editor.screen_width as usize).to_string(),
+            )
+            .output();
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                editor.set_error(format!("{}: {}", bin, err));
+                return;
+            }
+        };
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.trim().is_empty() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr.lines().next().unwrap_or("").trim();
+            if message.is_empty() {
+                editor.set_error(format!("No entry for {}", word));
+            } else {
+                editor.set_error(message.to_string());
+            }
+            return;
+        }
+
+        let lines = stdout.lines().map(strip_terminal_formatting).collect();
+        editor.popup = Some(Popup::plain(format!("{} {}", program, word), lines));
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Plugin for KeywordLookupPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if key.code != KeyCode::Char('K')
+            || key.modifiers.contains(KeyModifiers::CONTROL)
+            || !editor.pending_keys.is_empty()
+        {
+            return EventResult::Ignored;
+        }
 
-    #[test]
-    fn slice_line_respects_offset_and_width() {
-        let line = "abcdef";
-        let slice = slice_line(line, 2, 3);
-        assert_eq!(slice, "cde");
+        match editor.word_under_cursor() {
+            Some(word) => {
+                let program = Self::program_for(editor);
+                Self::lookup(editor, &program, &word);
+            }
+            None => editor.set_error("No identifier under cursor"),
+        }
+        EventResult::Consumed
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Normal,
+            "K",
+            "Look up the word under the cursor with keywordprg",
+        )];
+        KEYS
+    }
+}
+
+/// `gf` edits the file whose name is under the cursor, looked for in the
+/// `path` directories; `gF` also goes to the line number after the name,
+/// as in `src/main.rs:42`.
+pub struct GotoFilePlugin;
+
+impl GotoFilePlugin {
+    /// The file name at char column `col` of `line`, or the first one after
+    /// it, and the line number after a `:` that follows the name.
+    fn name_at(line: &str, col: usize) -> Option<(String, Option<usize>)> {
+        let chars: Vec<char> = line.chars().collect();
+        let is_name = |ch: char| ch.is_alphanumeric() || "/.-_+,#$%~=:".contains(ch);
+        let col = (col..chars.len()).find(|&col| is_name(chars[col]))?;
+        let start = chars[..col]
+            .iter()
+            .rposition(|&ch| !is_name(ch))
+            .map_or(0, |before| before + 1);
+        let end = (col..chars.len())
+            .find(|&col| !is_name(chars[col]))
+            .unwrap_or(chars.len());
+        let text: String = chars[start..end].iter().collect();
+        let (name, suffix) = text.split_once(':').unwrap_or((&text, ""));
+        // Punctuation ending a sentence is not part of the name.
+        let name = name.trim_end_matches(['.', ',']);
+        let digits: String = suffix.chars().take_while(char::is_ascii_digit).collect();
+        (!name.is_empty()).then(|| (name.to_string(), digits.parse().ok()))
+    }
+
+    /// Where `name` is: itself if absolute, else in the first `path`
+    /// directory that has it.
+    fn find(editor: &Editor, name: &str) -> Option<PathBuf> {
+        let name = match (name.strip_prefix("~/"), std::env::var_os("HOME")) {
+            (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+            _ => PathBuf::from(name),
+        };
+        if name.is_absolute() {
+            return name.exists().then_some(name);
+        }
+        let file_dir = editor
+            .file_path
+            .as_deref()
+            .and_then(Path::parent)
+            .filter(|dir| !dir.as_os_str().is_empty());
+        editor
+            .options
+            .path
+            .split(',')
+            .map(|dir| match (dir, file_dir) {
+                (".", Some(file_dir)) => file_dir.join(&name),
+                ("" | ".", _) => name.clone(),
+                (dir, _) => Path::new(dir).join(&name),
+            })
+            .find(|candidate| candidate.exists())
+    }
+
+    fn goto_file(editor: &mut Editor, with_line: bool) {
+        let Some((name, number)) = Self::name_at(editor.current_line(), editor.cursor.col) else {
+            editor.set_error("No file name under cursor");
+            return;
+        };
+        let Some(path) = Self::find(editor, &name) else {
+            editor.set_error(format!("Can't find file \"{}\" in path", name));
+            return;
+        };
+        let jump = editor.current_jump();
+        if !FileCommandPlugin::command_edit(editor, &path.to_string_lossy(), false) {
+            return;
+        }
+        editor.jumps.push(jump);
+        if with_line && let Some(number) = number {
+            editor.goto_position(number.saturating_sub(1), 0);
+        }
+    }
+}
+
+impl Plugin for GotoFilePlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let KeyCode::Char(ch @ ('f' | 'F')) = key.code else {
+            return EventResult::Ignored;
+        };
+        if editor.mode != Mode::Normal
+            || editor.pending_keys != "g"
+            || key.modifiers.contains(KeyModifiers::CONTROL)
+        {
+            return EventResult::Ignored;
+        }
+        editor.pending_keys.clear();
+        Self::goto_file(editor, ch == 'F');
+        EventResult::Consumed
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(
+                Mode::Normal,
+                "gf",
+                "Edit the file whose name is under the cursor",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "gF",
+                "Edit the file under the cursor at the line number after its name",
+            ),
+        ];
+        KEYS
+    }
+}
+
+/// `z=` lists spelling suggestions for the word under the cursor in a
+/// popup; typing a suggestion's number puts it in place of the word. It
+/// runs before `PopupPlugin`, which would close the popup on the digit.
+pub struct SpellPlugin {
+    /// The word being corrected, while its suggestions are shown.
+    choice: Option<SpellChoice>,
+}
+
+struct SpellChoice {
+    /// The popup's title, which tells it from popups opened since.
+    title: String,
+    start: Cursor,
+    end: Cursor,
+    suggestions: Vec<String>,
+}
+
+impl SpellPlugin {
+    pub fn new() -> Self {
+        Self { choice: None }
+    }
+
+    fn suggest(&mut self, editor: &mut Editor) {
+        if !editor.options.spell {
+            editor.set_error("Spell checking is not enabled");
+            return;
+        }
+        if let Err(err) = editor.load_dictionary() {
+            editor.set_error(err);
+            return;
+        }
+        let row = editor.cursor.row;
+        let line = editor.current_line();
+        let Some((start, end)) = spell::word_at(line, editor.cursor.col) else {
+            editor.set_error("No word under cursor");
+            return;
+        };
+        let word: String = line.chars().skip(start).take(end - start).collect();
+        let suggestions = editor.dictionary.suggest(&word);
+        if suggestions.is_empty() {
+            editor.set_status(format!("No suggestions for \"{}\"", word));
+            return;
+        }
+        let lines = suggestions
+            .iter()
+            .enumerate()
+            .map(|(index, suggestion)| format!("{} {}", index + 1, suggestion))
+            .collect();
+        let title = format!("Change \"{}\" to", word);
+        editor.popup = Some(Popup::plain(title.clone(), lines));
+        self.choice = Some(SpellChoice {
+            title,
+            start: Cursor { row, col: start },
+            end: Cursor { row, col: end },
+            suggestions,
+        });
+    }
+}
+
+impl Plugin for SpellPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if editor.mode != Mode::Normal || key.modifiers.contains(KeyModifiers::CONTROL) {
+            return EventResult::Ignored;
+        }
+        if let Some(choice) = self.choice.take()
+            && editor
+                .popup
+                .as_ref()
+                .is_some_and(|popup| popup.title == choice.title)
+        {
+            let KeyCode::Char(digit @ '1'..='9') = key.code else {
+                // Keys that scroll the popup leave it open for a choice.
+                self.choice = Some(choice);
+                return EventResult::Ignored;
+            };
+            editor.popup = None;
+            if let Some(suggestion) = choice.suggestions.get(digit as usize - '1' as usize) {
+                editor.replace_range(choice.start, choice.end, suggestion);
+            }
+            return EventResult::Consumed;
+        }
+        match key.code {
+            KeyCode::Char('z') if editor.pending_keys.is_empty() => {
+                editor.pending_keys.push('z');
+                EventResult::Consumed
+            }
+            KeyCode::Char('=') if editor.pending_keys == "z" => {
+                editor.pending_keys.clear();
+                self.suggest(editor);
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Normal,
+            "z=",
+            "Suggest spellings for the word under the cursor",
+        )];
+        KEYS
+    }
+}
+
+pub struct ModePlugin;
+
+impl Plugin for ModePlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+
+        match key.code {
+            KeyCode::Esc => {
+                editor.mode = Mode::Normal;
+                editor.visual_anchor = None;
+                editor.command_line.close();
+                editor.pending_keys.clear();
+                editor.pending_register = None;
+                EventResult::Consumed
+            }
+            _ if !editor.pending_keys.is_empty() => EventResult::Ignored,
+            // Ctrl-O, Ctrl-I and Ctrl-V belong to other plugins.
+            _ if key.modifiers.contains(KeyModifiers::CONTROL) => EventResult::Ignored,
+            KeyCode::Char(open @ ('o' | 'O')) if editor.mode == Mode::Normal => {
+                if editor.is_loading() {
+                    editor.set_error("Cannot edit while the file is still loading");
+                } else {
+                    editor.open_line(open == 'o');
+                    editor.mode = Mode::Insert;
+                }
+                EventResult::Consumed
+            }
+            KeyCode::Char('v') if editor.mode == Mode::Normal => {
+                editor.start_visual();
+                EventResult::Consumed
+            }
+            KeyCode::Char('v') if editor.mode == Mode::Visual => {
+                editor.stop_visual();
+                EventResult::Consumed
+            }
+            KeyCode::Char('i') if editor.mode == Mode::Normal => {
+                if editor.is_loading() {
+                    editor.set_error("Cannot edit while the file is still loading");
+                } else {
+                    editor.mode = Mode::Insert;
+                }
+                EventResult::Consumed
+            }
+            KeyCode::Char(prompt @ (':' | '/' | '?')) if editor.mode == Mode::Normal => {
+                editor.mode = Mode::Command;
+                editor.command_line.open(prompt);
+                EventResult::Consumed
+            }
+            KeyCode::Char(':') if editor.mode == Mode::Visual => {
+                // Ex commands typed from Visual mode act on the selected lines.
+                editor.stop_visual();
+                editor.mode = Mode::Command;
+                editor.command_line.open(':');
+                editor.command_line.insert_str("'<,'>");
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "i", "Enter Insert mode"),
+            KeyInfo::new(Mode::Normal, "o", "Open a line below and enter Insert mode"),
+            KeyInfo::new(Mode::Normal, "O", "Open a line above and enter Insert mode"),
+            KeyInfo::new(Mode::Normal, "v", "Start a Visual selection"),
+            KeyInfo::new(Mode::Normal, ":", "Enter an ex command"),
+            KeyInfo::new(Mode::Normal, "/", "Search forward"),
+            KeyInfo::new(Mode::Normal, "?", "Search backward"),
+            KeyInfo::new(
+                Mode::Visual,
+                ":",
+                "Enter an ex command for the selected lines",
+            ),
+            KeyInfo::new(Mode::Insert, "<Esc>", "Return to Normal mode"),
+        ];
+        KEYS
+    }
+}
+
+pub struct CommandLinePlugin;
+
+impl CommandLinePlugin {
+    fn history_path(editor: &Editor, prompt: char) -> Option<PathBuf> {
+        let name = if prompt == ':' {
+            "command_history"
+        } else {
+            "search_history"
+        };
+        editor.state_dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    /// Completes the command name before the cursor from the registry:
+    /// fully if only one name fits, else as far as the names agree, listing
+    /// them in the status line.
+    fn complete(editor: &mut Editor) {
+        let before = editor.command_line.before_cursor();
+        let start = before
+            .rfind(|ch: char| !ch.is_ascii_alphanumeric())
+            .map_or(0, |index| index + 1);
+        let (head, typed) = before.split_at(start);
+        if !head
+            .chars()
+            .all(|ch| ch.is_ascii_digit() || "%.$,;+-'<> ".contains(ch))
+        {
+            return;
+        }
+        let names = editor.registry.command_names(typed);
+        let (completion, listing) = match names.as_slice() {
+            [] => return,
+            [name] => (format!("{} ", name), None),
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(first.len(), |len, name| {
+                    first
+                        .bytes()
+                        .zip(name.bytes())
+                        .take(len)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                });
+                (first[..common].to_string(), Some(names.join("  ")))
+            }
+        };
+        let typed = typed.chars().count();
+        if let Some(listing) = listing {
+            editor.set_status(listing);
+        }
+        for _ in 0..typed {
+            editor.command_line.backspace();
+        }
+        editor.command_line.insert_str(&completion);
+    }
+
+    fn record_history(editor: &mut Editor, prompt: char, entry: &str) {
+        let path = Self::history_path(editor, prompt);
+        let history = editor.history_mut(prompt);
+        history.push(entry);
+        if let Some(path) = path {
+            let _ = history.save(&path);
+        }
+    }
+}
+
+impl Plugin for CommandLinePlugin {
+    fn on_init(&mut self, editor: &mut Editor) {
+        for prompt in [':', '/'] {
+            if let Some(history) =
+                Self::history_path(editor, prompt).and_then(|path| History::load(&path).ok())
+            {
+                *editor.history_mut(prompt) = history;
+            }
+        }
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Command {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+
+        match key.code {
+            KeyCode::Enter => {
+                let prompt = editor.command_line.prompt;
+                let input = std::mem::take(&mut editor.command_line.input);
+                editor.command_line.close();
+                editor.mode = Mode::Normal;
+                if prompt == ':' {
+                    let command = input.trim();
+                    if !command.is_empty() {
+                        Self::record_history(editor, prompt, command);
+                        editor.push_command(command.to_string());
+                    }
+                } else {
+                    // Searches run as `/pattern` or `?pattern` commands; an empty
+                    // pattern repeats the last search.
+                    Self::record_history(editor, prompt, &input);
+                    editor.push_command(format!("{}{}", prompt, input));
+                }
+                EventResult::Consumed
+            }
+            KeyCode::Up => {
+                let input = editor.command_line.input.clone();
+                let prompt = editor.command_line.prompt;
+                if let Some(entry) = editor.history_mut(prompt).previous(&input) {
+                    editor.command_line.set_input(entry);
+                }
+                EventResult::Consumed
+            }
+            KeyCode::Down => {
+                let prompt = editor.command_line.prompt;
+                if let Some(entry) = editor.history_mut(prompt).next() {
+                    editor.command_line.set_input(entry);
+                }
+                EventResult::Consumed
+            }
+            KeyCode::Tab if editor.command_line.prompt == ':' => {
+                Self::complete(editor);
+                EventResult::Consumed
+            }
+            KeyCode::Left => {
+                editor.command_line.move_left();
+                EventResult::Consumed
+            }
+            KeyCode::Right => {
+                editor.command_line.move_right();
+                EventResult::Consumed
+            }
+            KeyCode::Home => {
+                editor.command_line.move_home();
+                EventResult::Consumed
+            }
+            KeyCode::End => {
+                editor.command_line.move_end();
+                EventResult::Consumed
+            }
+            KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                editor.command_line.move_home();
+                EventResult::Consumed
+            }
+            KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                editor.command_line.move_end();
+                EventResult::Consumed
+            }
+            KeyCode::Backspace | KeyCode::Delete => {
+                let prompt = editor.command_line.prompt;
+                editor.history_mut(prompt).reset();
+                if key.code == KeyCode::Delete {
+                    editor.command_line.delete();
+                } else {
+                    editor.command_line.backspace();
+                }
+                EventResult::Consumed
+            }
+            KeyCode::Char(ch @ ('w' | 'u')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let prompt = editor.command_line.prompt;
+                editor.history_mut(prompt).reset();
+                if ch == 'w' {
+                    editor.command_line.delete_word_before();
+                } else {
+                    editor.command_line.delete_to_start();
+                }
+                EventResult::Consumed
+            }
+            KeyCode::Char(ch) => {
+                if key.modifiers.contains(KeyModifiers::CONTROL)
+                    || key.modifiers.contains(KeyModifiers::ALT)
+                {
+                    return EventResult::Ignored;
+                }
+                let prompt = editor.command_line.prompt;
+                editor.history_mut(prompt).reset();
+                editor.command_line.insert_char(ch);
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(
+                Mode::Command,
+                "<Up>",
+                "Recall an earlier command starting with the typed text",
+            ),
+            KeyInfo::new(
+                Mode::Command,
+                "<Down>",
+                "Recall a later command starting with the typed text",
+            ),
+            KeyInfo::new(Mode::Command, "<Tab>", "Complete the command name"),
+            KeyInfo::new(Mode::Command, "<Left>", "Move the cursor left"),
+            KeyInfo::new(Mode::Command, "<Right>", "Move the cursor right"),
+            KeyInfo::new(Mode::Command, "<C-a>", "Move the cursor to the start"),
+            KeyInfo::new(Mode::Command, "<C-e>", "Move the cursor to the end"),
+            KeyInfo::new(Mode::Command, "<C-w>", "Delete the word before the cursor"),
+            KeyInfo::new(
+                Mode::Command,
+                "<C-u>",
+                "Delete everything before the cursor",
+            ),
+        ];
+        KEYS
+    }
+}
+
+pub struct SearchPlugin;
+
+impl SearchPlugin {
+    fn search(editor: &mut Editor, forward: bool) {
+        let Some(pattern) = editor.search.pattern.clone() else {
+            editor.set_error("No previous search pattern");
+            return;
+        };
+        let regex = match editor.compile_pattern(&pattern) {
+            Ok(regex) => regex,
+            Err(message) => {
+                editor.set_error(message);
+                return;
+            }
+        };
+        editor.search.highlight = true;
+        let from = editor.cursor;
+        match editor.find_pattern(&regex, from, forward) {
+            Some(found) => {
+                editor.push_jump();
+                editor.cursor = found;
+                editor.ensure_cursor_visible();
+                if forward && found <= from {
+                    editor.set_status("search hit BOTTOM, continuing at TOP");
+                } else if !forward && found >= from {
+                    editor.set_status("search hit TOP, continuing at BOTTOM");
+                } else {
+                    editor.set_status(format!("{}{}", if forward { '/' } else { '?' }, pattern));
+                }
+            }
+            None if !editor.options.wrapscan
+                && (0..editor.buffer.lines.len())
+                    .any(|row| !editor.line_matches(row, &regex).is_empty()) =>
+            {
+                let end = if forward { "BOTTOM" } else { "TOP" };
+                editor.set_error(format!("Search hit {} without match for: {}", end, pattern));
+            }
+            None => editor.set_error(format!("Pattern not found: {}", pattern)),
+        }
+    }
+
+    /// Searches for the whole word under the cursor, or the next word after
+    /// it, as the last search pattern, so `n` and `N` repeat it.
+    fn search_word(editor: &mut Editor, forward: bool) {
+        let Some(word) = editor.word_under_cursor() else {
+            editor.set_error("No string under cursor");
+            return;
+        };
+        let pattern = format!(r"\b{}\b", regex::escape(&word));
+        // Search from the start of the word, so `#` skips the word itself.
+        if let Ok(regex) = editor.compile_pattern(&pattern)
+            && let Some((start, _)) = editor
+                .line_matches(editor.cursor.row, &regex)
+                .into_iter()
+                .find(|&(_, end)| end > editor.cursor.col)
+        {
+            editor.cursor.col = start;
+        }
+        editor.history_mut('/').push(&pattern);
+        editor.search.pattern = Some(pattern);
+        editor.search.forward = forward;
+        Self::search(editor, forward);
+    }
+}
+
+impl Plugin for SearchPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if key.modifiers.contains(KeyModifiers::CONTROL) || !editor.pending_keys.is_empty() {
+            return EventResult::Ignored;
+        }
+
+        match key.code {
+            KeyCode::Char('n') => {
+                Self::search(editor, editor.search.forward);
+                EventResult::Consumed
+            }
+            KeyCode::Char('N') => {
+                Self::search(editor, !editor.search.forward);
+                EventResult::Consumed
+            }
+            KeyCode::Char(ch @ ('*' | '#')) => {
+                Self::search_word(editor, ch == '*');
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        if matches!(command.trim(), "noh" | "nohlsearch") {
+            editor.search.highlight = false;
+            return EventResult::Consumed;
+        }
+        let forward = match command.chars().next() {
+            Some('/') => true,
+            Some('?') => false,
+            _ => return EventResult::Ignored,
+        };
+        let pattern = &command[1..];
+        if !pattern.is_empty() {
+            editor.search.pattern = Some(pattern.to_string());
+        }
+        editor.search.forward = forward;
+        Self::search(editor, forward);
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "nohlsearch",
+            "",
+            "Stop highlighting search matches until the next search",
+        )];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "n", "Repeat the last search"),
+            KeyInfo::new(
+                Mode::Normal,
+                "N",
+                "Repeat the last search in the opposite direction",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "*",
+                "Search forward for the word under the cursor",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "#",
+                "Search backward for the word under the cursor",
+            ),
+        ];
+        KEYS
+    }
+}
+
+/// A parsed `:s/pattern/replacement/flags`.
+struct Substitution {
+    pattern: String,
+    replacement: String,
+    /// Whether the delimiter after the pattern was given: until it is
+    /// typed, `inccommand` shows the matches rather than their removal.
+    replacing: bool,
+    global: bool,
+    /// `i` or `I` in the flags; `None` leaves case to the options.
+    ignore_case: Option<bool>,
+    /// `c` in the flags: ask before each replacement.
+    confirm: bool,
+    /// `:S`: match the pattern in each case style and keep the style.
+    subvert: bool,
+}
+
+/// The text replacing the match `caps`: `replacement` with its groups
+/// expanded or, for `:S`, written in the match's case style.
+fn replacement_for(replacement: &str, subvert: bool, caps: &Captures) -> String {
+    if subvert {
+        subvert::replace(&caps[0], replacement)
+    } else {
+        pattern::expand_replacement(replacement, caps)
+    }
+}
+
+/// A `:s///c` waiting for an answer about `editor.search.candidate`.
+struct Confirmation {
+    regex: Regex,
+    pattern: String,
+    replacement: String,
+    global: bool,
+    subvert: bool,
+    /// Where the search for the current candidate started.
+    from: Cursor,
+    end_row: usize,
+    count: usize,
+    lines: usize,
+    last_row: Option<usize>,
+}
+
+/// `:[range]s/pattern/replacement/[flags]` using regex patterns.
+pub struct SubstitutePlugin {
+    confirm: Option<Confirmation>,
+}
+
+impl SubstitutePlugin {
+    pub fn new() -> Self {
+        Self { confirm: None }
+    }
+
+    /// Parses what follows the range: `s` (`substitute`), or `S`
+    /// (`Subvert`) to keep the case style of what is replaced, and its
+    /// arguments. Returns `None` when no delimiter follows the command
+    /// name, so commands such as `:set` are left alone.
+    fn parse(command: &str) -> Option<Result<Substitution, String>> {
+        let (args, subvert) = match command
+            .strip_prefix("substitute")
+            .or_else(|| command.strip_prefix('s'))
+        {
+            Some(args) => (args, false),
+            None => (
+                command
+                    .strip_prefix("Subvert")
+                    .or_else(|| command.strip_prefix('S'))?,
+                true,
+            ),
+        };
+        let delimiter = args.chars().next()?;
+        if delimiter.is_alphanumeric() || delimiter.is_whitespace() || delimiter == '"' {
+            return None;
+        }
+        let mut parts = vec![String::new()];
+        let mut chars = args[delimiter.len_utf8()..].chars();
+        while let Some(ch) = chars.next() {
+            if parts.len() == 3 {
+                parts[2].push(ch);
+            } else if ch == '\\' {
+                match chars.next() {
+                    Some(next) if next == delimiter => parts.last_mut()?.push(next),
+                    Some(next) => {
+                        parts.last_mut()?.push(ch);
+                        parts.last_mut()?.push(next);
+                    }
+                    None => parts.last_mut()?.push(ch),
+                }
+            } else if ch == delimiter {
+                parts.push(String::new());
+            } else {
+                parts.last_mut()?.push(ch);
+            }
+        }
+        let replacing = parts.len() > 1;
+        let mut parts = parts.into_iter();
+        let pattern = parts.next().unwrap_or_default();
+        let replacement = parts.next().unwrap_or_default();
+        let mut substitution = Substitution {
+            pattern,
+            replacement,
+            replacing,
+            global: false,
+            ignore_case: None,
+            confirm: false,
+            subvert,
+        };
+        for flag in parts.next().unwrap_or_default().trim().chars() {
+            match flag {
+                'g' => substitution.global = true,
+                'i' => substitution.ignore_case = Some(true),
+                'I' => substitution.ignore_case = Some(false),
+                'c' => substitution.confirm = true,
+                _ => return Some(Err(format!("Invalid flag: {}", flag))),
+            }
+        }
+        Some(Ok(substitution))
+    }
+
+    /// The pattern `substitution` searches for, the last search pattern if
+    /// it gives none, and its regex. For `:S`, the pattern to search for is
+    /// the regex matching each case style.
+    fn compile(editor: &Editor, substitution: &Substitution) -> Result<(String, Regex), String> {
+        let pattern = if substitution.pattern.is_empty() {
+            editor
+                .search
+                .pattern
+                .clone()
+                .ok_or("No previous search pattern")?
+        } else {
+            substitution.pattern.clone()
+        };
+        if substitution.subvert {
+            let regex = subvert::pattern(&pattern)?;
+            return Ok((regex.as_str().to_string(), regex));
+        }
+        let regex = match substitution.ignore_case {
+            Some(ignore_case) => pattern::compile(&pattern, ignore_case),
+            None => editor.compile_pattern(&pattern),
+        }?;
+        Ok((pattern, regex))
+    }
+
+    fn substitute(&mut self, editor: &mut Editor, range: LineRange, substitution: Substitution) {
+        let (pattern, regex) = match Self::compile(editor, &substitution) {
+            Ok(compiled) => compiled,
+            Err(message) => {
+                editor.set_error(message);
+                return;
+            }
+        };
+        editor.search.pattern = Some(pattern.clone());
+        editor.search.highlight = true;
+
+        if substitution.confirm {
+            let mut confirmation = Confirmation {
+                regex,
+                pattern,
+                replacement: substitution.replacement,
+                global: substitution.global,
+                subvert: substitution.subvert,
+                from: Cursor {
+                    row: range.start,
+                    col: 0,
+                },
+                end_row: range.end,
+                count: 0,
+                lines: 0,
+                last_row: None,
+            };
+            if Self::next_candidate(editor, &mut confirmation) {
+                self.confirm = Some(confirmation);
+            } else {
+                editor.set_error(format!("Pattern not found: {}", confirmation.pattern));
+            }
+            return;
+        }
+
+        let limit = if substitution.global { 0 } else { 1 };
+        let (mut row, mut end) = (range.start, range.end);
+        let (mut count, mut lines, mut last_row) = (0, 0, None);
+        editor.begin_batch();
+        while row <= end {
+            let line = editor.buffer.lines[row].clone();
+            let mut found = 0;
+            let replaced = regex.replacen(&line, limit, |caps: &Captures| {
+                found += 1;
+                replacement_for(&substitution.replacement, substitution.subvert, caps)
+            });
+            if found > 0 {
+                count += found;
+                lines += 1;
+                if replaced != line {
+                    let line_end = Cursor {
+                        row,
+                        col: line.chars().count(),
+                    };
+                    editor.replace_range(Cursor { row, col: 0 }, line_end, &replaced);
+                }
+                // A `\r` in the replacement splits the line.
+                let added = replaced.matches('\n').count();
+                row += added;
+                end += added;
+                last_row = Some(row);
+            }
+            row += 1;
+        }
+        editor.end_batch();
+        Self::report(editor, &pattern, count, lines, last_row);
+    }
+
+    /// Finds the next match at or after `confirmation.from`, shows it as the
+    /// search candidate and scrolls to it. Returns false when none is left.
+    fn next_candidate(editor: &mut Editor, confirmation: &mut Confirmation) -> bool {
+        let end_row = confirmation.end_row.min(editor.buffer.lines.len() - 1);
+        while confirmation.from.row <= end_row {
+            let Cursor { row, col } = confirmation.from;
+            let line = &editor.buffer.lines[row];
+            if col <= line.chars().count() {
+                let byte = line.char_indices().nth(col).map_or(line.len(), |(i, _)| i);
+                if let Some(found) = confirmation.regex.find_at(line, byte) {
+                    let start = line[..found.start()].chars().count();
+                    let end = start + found.as_str().chars().count();
+                    editor.search.candidate =
+                        Some((Cursor { row, col: start }, Cursor { row, col: end }));
+                    editor.goto_position(row, start);
+                    editor.set_status("replace? (y/n/a/q/l)");
+                    return true;
+                }
+            }
+            confirmation.from = Cursor {
+                row: row + 1,
+                col: 0,
+            };
+        }
+        editor.search.candidate = None;
+        false
+    }
+
+    /// Replaces the current candidate and moves the search past it, or just
+    /// moves past it when `replace` is false.
+    fn answer(editor: &mut Editor, confirmation: &mut Confirmation, replace: bool) {
+        let Some((start, end)) = editor.search.candidate.take() else {
+            return;
+        };
+        let mut after = end;
+        if replace {
+            let line = &editor.buffer.lines[start.row];
+            let byte = line
+                .char_indices()
+                .nth(confirmation.from.col)
+                .map_or(line.len(), |(i, _)| i);
+            let text = confirmation
+                .regex
+                .captures_at(line, byte)
+                .map(|caps| replacement_for(&confirmation.replacement, confirmation.subvert, &caps))
+                .unwrap_or_default();
+            editor.replace_range(start, end, &text);
+            // A `\r` in the replacement splits the line.
+            let added = text.matches('\n').count();
+            let last_piece = text.rsplit('\n').next().unwrap_or_default().chars().count();
+            after = Cursor {
+                row: start.row + added,
+                col: if added == 0 {
+                    start.col + last_piece
+                } else {
+                    last_piece
+                },
+            };
+            confirmation.end_row += added;
+            confirmation.count += 1;
+            if confirmation.last_row != Some(start.row) {
+                confirmation.lines += 1;
+            }
+            confirmation.last_row = Some(after.row);
+        }
+        confirmation.from = if !confirmation.global {
+            Cursor {
+                row: after.row + 1,
+                col: 0,
+            }
+        } else if start == end {
+            // Step over an empty match so it is not found again.
+            Cursor {
+                col: after.col + 1,
+                ..after
+            }
+        } else {
+            after
+        };
+    }
+
+    /// Ends a confirmed substitution, reporting what was replaced.
+    fn finish(editor: &mut Editor, confirmation: Confirmation) {
+        editor.search.candidate = None;
+        editor.set_status("");
+        if confirmation.count > 0 {
+            Self::report(
+                editor,
+                &confirmation.pattern,
+                confirmation.count,
+                confirmation.lines,
+                confirmation.last_row,
+            );
+        }
+    }
+
+    fn report(
+        editor: &mut Editor,
+        pattern: &str,
+        count: usize,
+        lines: usize,
+        last_row: Option<usize>,
+    ) {
+        let Some(last_row) = last_row else {
+            editor.set_error(format!("Pattern not found: {}", pattern));
+            return;
+        };
+        editor.goto_line(last_row);
+        let first_non_blank = Motion::FirstNonBlank.target(&editor.buffer.lines, editor.cursor);
+        editor.goto_position(first_non_blank.row, first_non_blank.col);
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        editor.set_status(format!(
+            "{} substitution{} on {} line{}",
+            count,
+            plural(count),
+            lines,
+            plural(lines)
+        ));
+    }
+}
+
+impl Plugin for SubstitutePlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Some(mut confirmation) = self.confirm.take() else {
+            return EventResult::Ignored;
+        };
+        let Event::Key(key) = event else {
+            self.confirm = Some(confirmation);
+            return EventResult::Ignored;
+        };
+        let done = match key.code {
+            KeyCode::Char('y') => {
+                Self::answer(editor, &mut confirmation, true);
+                false
+            }
+            KeyCode::Char('n') => {
+                Self::answer(editor, &mut confirmation, false);
+                false
+            }
+            KeyCode::Char('a') => {
+                editor.begin_batch();
+                Self::answer(editor, &mut confirmation, true);
+                while Self::next_candidate(editor, &mut confirmation) {
+                    Self::answer(editor, &mut confirmation, true);
+                }
+                editor.end_batch();
+                true
+            }
+            KeyCode::Char('l') => {
+                Self::answer(editor, &mut confirmation, true);
+                true
+            }
+            KeyCode::Char('q') | KeyCode::Esc => true,
+            // Other keys are swallowed until the prompt is answered.
+            _ => {
+                self.confirm = Some(confirmation);
+                return EventResult::Consumed;
+            }
+        };
+        if done || !Self::next_candidate(editor, &mut confirmation) {
+            Self::finish(editor, confirmation);
+        } else {
+            self.confirm = Some(confirmation);
+        }
+        EventResult::Consumed
+    }
+
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (range, rest) = match ex::parse_range(command, editor) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                // Leave other commands' ranges for them to report.
+                if !ex::command_name(command).starts_with(['s', 'S']) {
+                    return EventResult::Ignored;
+                }
+                editor.set_error(message);
+                return EventResult::Consumed;
+            }
+        };
+        let Some(parsed) = Self::parse(rest) else {
+            return EventResult::Ignored;
+        };
+        if editor.is_loading() {
+            editor.set_error("Cannot edit while the file is still loading");
+            return EventResult::Consumed;
+        }
+        match parsed {
+            Ok(substitution) => {
+                let row = editor.cursor.row;
+                let range = range.unwrap_or(LineRange {
+                    start: row,
+                    end: row,
+                });
+                self.substitute(editor, range, substitution);
+            }
+            Err(message) => editor.set_error(message),
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "s",
+                "/{pattern}/{replacement}/[flags]",
+                "Substitute a regular expression in a range of lines",
+            ),
+            CommandInfo::new(
+                "S",
+                "/{word}/{replacement}/[flags]",
+                "Substitute a word in each case style, keeping the style",
+            ),
+        ];
+        COMMANDS
+    }
+}
+
+/// `:[range]d [x]` and `:[range]y [x]` delete or yank whole lines into a
+/// register; `:[range]m {address}` moves them and `:[range]t {address}`
+/// copies them below the addressed line.
+pub struct LineCommandPlugin;
+
+impl LineCommandPlugin {
+    fn delete_or_yank(editor: &mut Editor, range: LineRange, args: &str, yank: bool) {
+        let register = match args.chars().collect::<Vec<_>>()[..] {
+            [] => None,
+            [name] if Registers::is_valid(name) => Some(name),
+            _ => {
+                editor.set_error(format!("Invalid register name: {}", args));
+                return;
+            }
+        };
+        let count = range.end - range.start + 1;
+        let text = editor.buffer.lines[range.start..=range.end].join("\n") + "\n";
+        editor.record_register(register, Register::new(text, RegisterKind::Linewise), yank);
+        if yank {
+            if count > 2 {
+                editor.set_status(format!("{} lines yanked", count));
+            }
+            return;
+        }
+        editor.delete_lines(range.start, range.end);
+        Self::to_first_non_blank(editor, editor.cursor.row);
+        if count > 2 {
+            editor.set_status(format!("{} fewer lines", count));
+        }
+    }
+
+    fn move_or_copy(editor: &mut Editor, range: LineRange, args: &str, copy: bool) {
+        let destination = match ex::parse_destination(args, editor) {
+            Ok(destination) => destination,
+            Err(message) => {
+                editor.set_error(message);
+                return;
+            }
+        };
+        let count = range.end - range.start + 1;
+        let lines = editor.buffer.lines[range.start..=range.end].to_vec();
+        if copy {
+            editor.insert_lines(destination, lines);
+            Self::to_first_non_blank(editor, destination + count - 1);
+            if count > 2 {
+                editor.set_status(format!("{} more lines", count));
+            }
+            return;
+        }
+        if destination > range.start && destination <= range.end {
+            editor.set_error("Cannot move a range of lines into itself");
+            return;
+        }
+        let mut row = destination;
+        if destination != range.start && destination != range.end + 1 {
+            editor.begin_batch();
+            editor.delete_lines(range.start, range.end);
+            if destination > range.end {
+                row -= count;
+            }
+            editor.insert_lines(row, lines);
+            editor.end_batch();
+        } else {
+            row = range.start;
+        }
+        Self::to_first_non_blank(editor, row + count - 1);
+        if count > 2 {
+            editor.set_status(format!("{} lines moved", count));
+        }
+    }
+
+    /// Inserts the lines of file `path` above row `row`.
+    fn read(editor: &mut Editor, row: usize, path: &str) {
+        let path = match editor.expand_args(path) {
+            Ok(path) if !path.is_empty() => PathBuf::from(path),
+            Ok(_) => {
+                editor.set_error("Argument required");
+                return;
+            }
+            Err(message) => {
+                editor.set_error(message);
+                return;
+            }
+        };
+        let text = match read_file(&path) {
+            Ok(text) => text,
+            Err(err) => {
+                editor.set_error(format!("Can't open file {}: {}", path.display(), err));
+                return;
+            }
+        };
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        if lines.is_empty() {
+            return;
+        }
+        let count = lines.len();
+        editor.insert_lines(row, lines);
+        Self::to_first_non_blank(editor, row);
+        editor.set_status(format!("\"{}\" {} lines", path.display(), count));
+    }
+
+    fn to_first_non_blank(editor: &mut Editor, row: usize) {
+        editor.goto_position(row, 0);
+        let target = Motion::FirstNonBlank.target(&editor.buffer.lines, editor.cursor);
+        editor.goto_position(target.row, target.col);
+    }
+}
+
+impl Plugin for LineCommandPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let name = ex::command_name(command);
+        let kind = match name {
+            "d" | "de" | "del" | "dele" | "delet" | "delete" => 'd',
+            "y" | "ya" | "yan" | "yank" => 'y',
+            "m" | "mo" | "mov" | "move" => 'm',
+            "t" | "co" | "cop" | "copy" => 't',
+            "r" | "re" | "rea" | "read" => 'r',
+            _ => return EventResult::Ignored,
+        };
+        let (range, rest) = match ex::parse_range(command, editor) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                editor.set_error(message);
+                return EventResult::Consumed;
+            }
+        };
+        let args = rest.trim_start().strip_prefix(name).unwrap_or(rest).trim();
+        if kind != 'y' && editor.is_loading() {
+            editor.set_error("Cannot edit while the file is still loading");
+            return EventResult::Consumed;
+        }
+        let row = editor.cursor.row;
+        let range = range.unwrap_or(LineRange {
+            start: row,
+            end: row,
+        });
+        match kind {
+            'd' | 'y' => Self::delete_or_yank(editor, range, args, kind == 'y'),
+            'r' => {
+                // `:0r` reads the file in above the first line.
+                let top = command
+                    .trim_start()
+                    .strip_prefix('0')
+                    .is_some_and(|rest| rest.trim_start().starts_with(name));
+                Self::read(editor, if top { 0 } else { range.end + 1 }, args);
+            }
+            _ => Self::move_or_copy(editor, range, args, kind == 't'),
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new("d", "[x]", "Delete lines into register x"),
+            CommandInfo::new("y", "[x]", "Yank lines into register x"),
+            CommandInfo::new("m", "{address}", "Move lines below the addressed line"),
+            CommandInfo::new("t", "{address}", "Copy lines below the addressed line"),
+            CommandInfo::new("r", "{file}", "Insert a file below the line"),
+        ];
+        COMMANDS
+    }
+}
+
+/// `:[range]sort[!] [flags]` sorts lines in place: `!` reverses the order,
+/// `n` compares the first number on each line, `i` ignores case and `u`
+/// keeps only the first of equal lines.
+pub struct SortPlugin;
+
+impl SortPlugin {
+    fn sort(editor: &mut Editor, range: LineRange, reverse: bool, flags: &str) {
+        let numeric = flags.contains('n');
+        let ignore_case = flags.contains('i');
+        let number = Regex::new(r"-?\d+").expect("valid number pattern");
+        let key = |line: &str| -> (Option<i128>, String) {
+            if numeric {
+                let value = number.find(line).map(|m| m.as_str().parse().unwrap_or(0));
+                (value, String::new())
+            } else if ignore_case {
+                (None, line.to_lowercase())
+            } else {
+                (None, line.to_string())
+            }
+        };
+        let original = &editor.buffer.lines[range.start..=range.end];
+        let mut keyed: Vec<_> = original.iter().map(|line| (key(line), line)).collect();
+        // A stable sort keeps equal lines, and lines without a number when
+        // sorting numerically, in their original order.
+        keyed.sort_by(|a, b| a.0.cmp(&b.0));
+        if reverse {
+            keyed.reverse();
+        }
+        if flags.contains('u') {
+            keyed.dedup_by(|a, b| a.0 == b.0);
+        }
+        let sorted: Vec<String> = keyed.into_iter().map(|(_, line)| line.clone()).collect();
+        let removed = original.len() - sorted.len();
+        if sorted[..] != *original {
+            let end = Cursor {
+                row: range.end,
+                col: editor.buffer.lines[range.end].chars().count(),
+            };
+            editor.replace_range(
+                Cursor {
+                    row: range.start,
+                    col: 0,
+                },
+                end,
+                &sorted.join("\n"),
+            );
+        }
+        editor.goto_position(range.start, 0);
+        if removed > 2 {
+            editor.set_status(format!("{} fewer lines", removed));
+        }
+    }
+}
+
+impl Plugin for SortPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let name = ex::command_name(command);
+        if !matches!(name, "sor" | "sort") {
+            return EventResult::Ignored;
+        }
+        let (range, rest) = match ex::parse_range(command, editor) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                editor.set_error(message);
+                return EventResult::Consumed;
+            }
+        };
+        let args = rest.trim_start().strip_prefix(name).unwrap_or(rest);
+        let (reverse, flags) = match args.strip_prefix('!') {
+            Some(flags) => (true, flags.trim()),
+            None => (false, args.trim()),
+        };
+        if let Some(flag) = flags.chars().find(|ch| !"nui ".contains(*ch)) {
+            editor.set_error(format!("Invalid flag: {}", flag));
+            return EventResult::Consumed;
+        }
+        if editor.is_loading() {
+            editor.set_error("Cannot edit while the file is still loading");
+            return EventResult::Consumed;
+        }
+        let mut range = range.unwrap_or(LineRange {
+            start: 0,
+            end: editor.buffer.lines.len() - 1,
+        });
+        // The empty line after a file's final newline stays at the end.
+        if range.end > range.start
+            && range.end == editor.buffer.lines.len() - 1
+            && editor.buffer.lines[range.end].is_empty()
+        {
+            range.end -= 1;
+        }
+        Self::sort(editor, range, reverse, flags);
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "sort",
+            "[flags]",
+            "Sort lines, by number (n), ignoring case (i) or uniquely (u)",
+        )];
+        COMMANDS
+    }
+}
+
+/// `u` and `:undo` revert the last change; Ctrl-R and `:redo` reapply it.
+pub struct UndoPlugin;
+
+impl UndoPlugin {
+    fn undo(editor: &mut Editor) {
+        if !editor.undo() {
+            editor.set_status("Already at oldest change");
+        }
+    }
+
+    fn redo(editor: &mut Editor) {
+        if !editor.redo() {
+            editor.set_status("Already at newest change");
+        }
+    }
+
+    fn travel(editor: &mut Editor, arg: &str, back: bool) {
+        let span = match UndoSpan::parse(arg) {
+            Ok(span) => span,
+            Err(err) => {
+                editor.set_error(err);
+                return;
+            }
+        };
+        let status = match (editor.travel(span, back), back) {
+            (0, true) => "Already at oldest change".to_string(),
+            (0, false) => "Already at newest change".to_string(),
+            (1, true) => "1 change undone".to_string(),
+            (1, false) => "1 change redone".to_string(),
+            (n, true) => format!("{} changes undone", n),
+            (n, false) => format!("{} changes redone", n),
+        };
+        editor.set_status(status);
+    }
+}
+
+impl Plugin for UndoPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command) {
+            ("u" | "un" | "und" | "undo", "") => Self::undo(editor),
+            ("red" | "redo", "") => Self::redo(editor),
+            ("ea" | "earlier", arg) => Self::travel(editor, arg, true),
+            ("lat" | "later", arg) => Self::travel(editor, arg, false),
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match (editor.pending_keys.as_str(), key.code) {
+            ("", KeyCode::Char('u')) if !control => Self::undo(editor),
+            ("", KeyCode::Char('r')) if control => Self::redo(editor),
+            // The history has no branches, so `g-` and `g+` step through
+            // the same states as `u` and Ctrl-R.
+            ("g", KeyCode::Char('-')) => {
+                editor.pending_keys.clear();
+                Self::undo(editor);
+            }
+            ("g", KeyCode::Char('+')) => {
+                editor.pending_keys.clear();
+                Self::redo(editor);
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new("undo", "", "Undo the last change"),
+            CommandInfo::new("redo", "", "Redo the last undone change"),
+            CommandInfo::new(
+                "earlier",
+                "{count}",
+                "Undo N changes, or the changes made in a time such as 10s",
+            ),
+            CommandInfo::new(
+                "later",
+                "{count}",
+                "Redo N changes, or the changes made in a time such as 2m",
+            ),
+        ];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "u", "Undo the last change"),
+            KeyInfo::new(Mode::Normal, "<C-r>", "Redo the last undone change"),
+            KeyInfo::new(Mode::Normal, "g-", "Go to the previous text state"),
+            KeyInfo::new(Mode::Normal, "g+", "Go to the next text state"),
+        ];
+        KEYS
+    }
+}
+
+/// Queued by `:cdo` and `:cfdo` after each entry's commands to move on to
+/// the next entry.
+const QUICKFIX_BATCH_NEXT: &str = "cdo-next";
+
+/// The entries `:cdo` or `:cfdo` has yet to visit and the commands to run
+/// at each.
+struct QuickfixBatch {
+    targets: VecDeque<usize>,
+    commands: Vec<String>,
+}
+
+/// `:grep` fills the quickfix list; `:cnext`, `:cprevious` and `:cc` visit
+/// its entries, `:clist` shows them and `:cdo`/`:cfdo` run commands at each
+/// entry or file.
+pub struct QuickfixPlugin {
+    batch: Option<QuickfixBatch>,
+}
+
+impl QuickfixPlugin {
+    pub fn new() -> Self {
+        Self { batch: None }
+    }
+
+    /// Opens entry `index`, switching files if needed. Returns false if the
+    /// file could not be opened.
+    fn go_to(editor: &mut Editor, index: usize) -> bool {
+        let Some(entry) = editor.quickfix.entries.get(index).cloned() else {
+            return false;
+        };
+        editor.push_jump();
+        if editor.file_path.as_ref() != Some(&entry.path)
+            && !FileCommandPlugin::command_edit(editor, &entry.path.to_string_lossy(), false)
+        {
+            return false;
+        }
+        editor.goto_position(entry.row, entry.col);
+        editor.quickfix.index = index;
+        editor.set_status(format!(
+            "({} of {}) {}",
+            index + 1,
+            editor.quickfix.entries.len(),
+            entry.text
+        ));
+        true
+    }
+
+    /// Shows entry `index` in the preview window, leaving the buffer as it
+    /// is.
+    fn preview(editor: &mut Editor, index: usize) {
+        let entry = editor.quickfix.entries[index].clone();
+        if let Err(err) = editor.open_preview_window(&entry.path, entry.row) {
+            editor.set_error(format!("Cannot preview {}: {}", entry.path.display(), err));
+            return;
+        }
+        editor.quickfix.index = index;
+        editor.set_status(format!(
+            "({} of {}) {}",
+            index + 1,
+            editor.quickfix.entries.len(),
+            entry.text
+        ));
+    }
+
+    fn grep(editor: &mut Editor, args: &str) {
+        let mut words = args.split_whitespace();
+        let Some(pattern) = words.next() else {
+            editor.set_error("Argument required");
+            return;
+        };
+        let regex = match editor.compile_pattern(pattern) {
+            Ok(regex) => regex,
+            Err(message) => {
+                editor.set_error(message);
+                return;
+            }
+        };
+        let mut paths: Vec<PathBuf> = words.map(PathBuf::from).collect();
+        if paths.is_empty() {
+            paths = editor.file_index.files();
+        }
+        editor.quickfix = QuickfixList::new(quickfix::grep(&regex, &paths));
+        if editor.quickfix.entries.is_empty() {
+            editor.set_error(format!("No match: {}", pattern));
+        } else {
+            Self::go_to(editor, 0);
+        }
+    }
+
+    fn list(editor: &mut Editor) {
+        let lines = editor
+            .quickfix
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                format!(
+                    "{:>3} {}:{}:{} {}",
+                    i + 1,
+                    entry.path.display(),
+                    entry.row + 1,
+                    entry.col + 1,
+                    entry.text
+                )
+            })
+            .collect();
+        editor.open_scratch("quickfix", lines);
+    }
+
+    /// Visits the next entry of the running `:cdo` or `:cfdo` and queues its
+    /// commands, stopping if the entry's file cannot be opened.
+    fn run_batch(&mut self, editor: &mut Editor) {
+        let Some(batch) = self.batch.as_mut() else {
+            return;
+        };
+        let Some(index) = batch.targets.pop_front() else {
+            self.batch = None;
+            return;
+        };
+        if !Self::go_to(editor, index) {
+            self.batch = None;
+            return;
+        }
+        let mut commands = batch.commands.clone();
+        commands.push(QUICKFIX_BATCH_NEXT.to_string());
+        editor.push_commands_front(commands);
+    }
+}
+
+impl Plugin for QuickfixPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (name, args) = split_command(command);
+        if name == QUICKFIX_BATCH_NEXT {
+            self.run_batch(editor);
+            return EventResult::Consumed;
+        }
+        if matches!(name, "gr" | "grep") {
+            Self::grep(editor, args);
+            return EventResult::Consumed;
+        }
+        if !matches!(
+            name,
+            "cn" | "cnext"
+                | "cp"
+                | "cprevious"
+                | "cN"
+                | "cNext"
+                | "cc"
+                | "cl"
+                | "clist"
+                | "cope"
+                | "copen"
+                | "cdo"
+                | "cfdo"
+                | "cpreview"
+        ) {
+            return EventResult::Ignored;
+        }
+        let count = editor.quickfix.entries.len();
+        if count == 0 {
+            editor.set_status("Quickfix list is empty");
+            return EventResult::Consumed;
+        }
+        let index = editor.quickfix.index;
+        match name {
+            "cn" | "cnext" if index + 1 < count => {
+                Self::go_to(editor, index + 1);
+            }
+            "cp" | "cprevious" | "cN" | "cNext" if index > 0 => {
+                Self::go_to(editor, index - 1);
+            }
+            "cn" | "cnext" | "cp" | "cprevious" | "cN" | "cNext" => {
+                editor.set_error("No more items");
+            }
+            "cc" => match args.parse::<usize>() {
+                _ if args.is_empty() => {
+                    Self::go_to(editor, index);
+                }
+                Ok(number) if (1..=count).contains(&number) => {
+                    Self::go_to(editor, number - 1);
+                }
+                _ => editor.set_error(format!("Invalid entry: {}", args)),
+            },
+            "cpreview" => match args.parse::<usize>() {
+                _ if args.is_empty() => Self::preview(editor, index),
+                Ok(number) if (1..=count).contains(&number) => Self::preview(editor, number - 1),
+                _ => editor.set_error(format!("Invalid entry: {}", args)),
+            },
+            "cdo" | "cfdo" if args.is_empty() => editor.set_error("Argument required"),
+            "cdo" | "cfdo" => {
+                let targets = if name == "cdo" {
+                    (0..count).collect()
+                } else {
+                    editor.quickfix.first_in_each_file()
+                };
+                self.batch = Some(QuickfixBatch {
+                    targets: targets.into_iter().collect(),
+                    commands: ex::split_bar(args),
+                });
+                self.run_batch(editor);
+            }
+            _ => Self::list(editor),
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "grep",
+                "{pattern} [paths]",
+                "Search files and fill the quickfix list",
+            ),
+            CommandInfo::new("cnext", "", "Go to the next quickfix entry"),
+            CommandInfo::new("cprevious", "", "Go to the previous quickfix entry"),
+            CommandInfo::new("cc", "[N]", "Go to quickfix entry N, or the current one"),
+            CommandInfo::new("clist", "", "List the quickfix entries"),
+            CommandInfo::new(
+                "cpreview",
+                "[N]",
+                "Show quickfix entry N, or the current one, in the preview window",
+            ),
+            CommandInfo::new("cdo", "{cmd}", "Run a command at every quickfix entry"),
+            CommandInfo::new(
+                "cfdo",
+                "{cmd}",
+                "Run a command in every file of the quickfix list",
+            ),
+        ];
+        COMMANDS
+    }
+}
+
+/// The preview window below the main view: `:pedit file` opens it on a
+/// file, `:cpreview` and the `:browse oldfiles` picker on what they point
+/// at, and `:pclose` or `Ctrl-W z` close it.
+pub struct PreviewWindowPlugin;
+
+impl Plugin for PreviewWindowPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command) {
+            ("pc" | "pclose", "") => editor.close_preview_window(),
+            ("ped" | "pedit", "") => editor.set_error("Argument required"),
+            ("ped" | "pedit", file) => {
+                let file = match editor.expand_args(file) {
+                    Ok(file) => PathBuf::from(file),
+                    Err(message) => {
+                        editor.set_error(message);
+                        return EventResult::Consumed;
+                    }
+                };
+                if let Err(err) = editor.open_preview_window(&file, 0) {
+                    editor.set_error(format!("Cannot preview {}: {}", file.display(), err));
+                }
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match (editor.pending_keys.as_str(), key.code) {
+            ("", KeyCode::Char('w')) if ctrl => {
+                editor.pending_keys.push_str("<C-w>");
+                EventResult::Consumed
+            }
+            ("<C-w>", KeyCode::Char('z')) => {
+                editor.pending_keys.clear();
+                editor.close_preview_window();
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        let Some(preview) = editor.preview_window.as_ref() else {
+            return;
+        };
+        let height = editor.preview_window_height();
+        let width = ctx.width as usize;
+        if height == 0 || width == 0 {
+            return;
+        }
+        let top = editor.content_height();
+        let title = format!("[Preview] {}:{}", preview.path.display(), preview.row + 1);
+        ctx.set_line(top, format_status_line(&title, "", width));
+        ctx.set_spans(
+            top,
+            vec![StyledSpan {
+                start: 0,
+                len: width,
+                style: editor.theme.style(HighlightGroup::StatusLine),
+            }],
+        );
+        let body = (height - 1) as usize;
+        let first = preview.row.saturating_sub(body / 2);
+        for i in 0..body {
+            let screen_row = top + 1 + i as u16;
+            let row = first + i;
+            ctx.set_spans(screen_row, Vec::new());
+            let Some(line) = preview.lines.get(row) else {
+                ctx.set_line(screen_row, "~".to_string());
+                ctx.add_span(
+                    screen_row,
+                    StyledSpan {
+                        start: 0,
+                        len: 1,
+                        style: editor.theme.style(HighlightGroup::EndOfBuffer),
+                    },
+                );
+                continue;
+            };
+            ctx.set_line(
+                screen_row,
+                expand_line(line, 0, width, editor.options.tabstop),
+            );
+            if row == preview.row {
+                ctx.add_span(
+                    screen_row,
+                    StyledSpan {
+                        start: 0,
+                        len: width,
+                        style: editor.theme.style(HighlightGroup::Visual),
+                    },
+                );
+            }
+        }
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new("pedit", "{file}", "Show a file in the preview window"),
+            CommandInfo::new("pclose", "", "Close the preview window"),
+        ];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Normal,
+            "<C-w>z",
+            "Close the preview window",
+        )];
+        KEYS
+    }
+}
+
+/// `:FindReplace pattern replacement [paths]` previews every line the
+/// replacement would change across the workspace and applies the ones left
+/// selected.
+pub struct FindReplacePlugin {
+    hunks: Vec<Hunk>,
+    selected: usize,
+}
+
+impl FindReplacePlugin {
+    const TITLE: &str = "find and replace";
+
+    pub fn new() -> Self {
+        Self {
+            hunks: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    fn start(&mut self, editor: &mut Editor, args: &str) {
+        let mut words = args.split_whitespace();
+        let (Some(pattern), Some(replacement)) = (words.next(), words.next()) else {
+            editor.set_error("Usage: FindReplace {pattern} {replacement} [paths]");
+            return;
+        };
+        let regex = match editor.compile_pattern(pattern) {
+            Ok(regex) => regex,
+            Err(message) => {
+                editor.set_error(message);
+                return;
+            }
+        };
+        let mut paths: Vec<PathBuf> = words.map(PathBuf::from).collect();
+        if paths.is_empty() {
+            paths = editor.file_index.files();
+        }
+        self.hunks = find_replace::proposals(&regex, replacement, &paths);
+        self.selected = 0;
+        if self.hunks.is_empty() {
+            editor.set_error(format!("No match: {}", pattern));
+            return;
+        }
+        editor.open_scratch(Self::TITLE, Vec::new());
+        self.refresh(editor);
+    }
+
+    /// Redraws the preview and scrolls the selected change into view.
+    fn refresh(&self, editor: &mut Editor) {
+        let page = editor.content_height().max(1) as usize;
+        let Some(view) = editor.scratch.as_mut() else {
+            return;
+        };
+        let accepted = self.hunks.iter().filter(|hunk| hunk.accepted).count();
+        view.lines = vec![format!(
+            "{} of {} changes selected: Space toggles, a toggles all, Enter applies, q cancels",
+            accepted,
+            self.hunks.len()
+        )];
+        for (i, hunk) in self.hunks.iter().enumerate() {
+            view.lines.push(format!(
+                "{} [{}] {}:{}",
+                if i == self.selected { '>' } else { ' ' },
+                if hunk.accepted { 'x' } else { ' ' },
+                hunk.path.display(),
+                hunk.row + 1
+            ));
+            view.lines.push(format!("    - {}", hunk.before));
+            view.lines
+                .push(format!("    + {}", caret_notation(&hunk.after)));
+        }
+        let first = 1 + 3 * self.selected;
+        if first < view.scroll {
+            view.scroll = first;
+        } else if first + 3 > view.scroll + page {
+            view.scroll = (first + 3).saturating_sub(page);
+        }
+    }
+
+    /// Writes the accepted changes. The open file is changed in the buffer
+    /// rather than on disk, so it can be reviewed and undone.
+    fn apply(&mut self, editor: &mut Editor) {
+        let hunks = std::mem::take(&mut self.hunks);
+        let mut paths: Vec<&Path> = Vec::new();
+        for hunk in hunks.iter().filter(|hunk| hunk.accepted) {
+            if !paths.contains(&hunk.path.as_path()) {
+                paths.push(&hunk.path);
+            }
+        }
+        let (mut applied, mut files, mut skipped) = (0, 0, 0);
+        for path in paths {
+            let file_hunks: Vec<&Hunk> = hunks
+                .iter()
+                .filter(|hunk| hunk.accepted && hunk.path == path)
+                .collect();
+            let open = editor
+                .file_path
+                .as_deref()
+                .is_some_and(|open| same_file(open, path));
+            let done = if open {
+                editor.begin_batch();
+                let mut done = 0;
+                for hunk in &file_hunks {
+                    if editor.buffer.lines.get(hunk.row) == Some(&hunk.before) {
+                        let end = Cursor {
+                            row: hunk.row,
+                            col: hunk.before.chars().count(),
+                        };
+                        editor.replace_range(
+                            Cursor {
+                                row: hunk.row,
+                                col: 0,
+                            },
+                            end,
+                            &hunk.after,
+                        );
+                        done += 1;
+                    }
+                }
+                editor.end_batch();
+                done
+            } else {
+                match find_replace::apply_to_file(path, &file_hunks) {
+                    Ok(done) => done,
+                    Err(err) => {
+                        editor.set_error(format!("Write failed: {}: {}", path.display(), err));
+                        return;
+                    }
+                }
+            };
+            applied += done;
+            skipped += file_hunks.len() - done;
+            if done > 0 {
+                files += 1;
+            }
+        }
+        let mut status = format!("Changed {} lines in {} files", applied, files);
+        if skipped > 0 {
+            status.push_str(&format!(
+                " ({} skipped: the line changed since the preview)",
+                skipped
+            ));
+        }
+        editor.set_status(status);
+    }
+}
+
+impl Plugin for FindReplacePlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command) {
+            ("FindReplace", args) => {
+                self.start(editor, args);
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if self.hunks.is_empty() {
+            return EventResult::Ignored;
+        }
+        if editor
+            .scratch
+            .as_ref()
+            .is_none_or(|view| view.title != Self::TITLE)
+        {
+            // The preview was replaced by another view.
+            self.hunks.clear();
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.hunks.len() - 1);
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            KeyCode::Char(' ' | 'x') => {
+                let hunk = &mut self.hunks[self.selected];
+                hunk.accepted = !hunk.accepted;
+            }
+            KeyCode::Char('a') => {
+                let accept = !self.hunks.iter().all(|hunk| hunk.accepted);
+                for hunk in &mut self.hunks {
+                    hunk.accepted = accept;
+                }
+            }
+            KeyCode::Enter => {
+                editor.close_scratch();
+                self.apply(editor);
+                return EventResult::Consumed;
+            }
+            KeyCode::Esc | KeyCode::Char('q') => {
+                editor.close_scratch();
+                self.hunks.clear();
+                return EventResult::Consumed;
+            }
+            _ => return EventResult::Ignored,
+        }
+        self.refresh(editor);
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "FindReplace",
+            "{pattern} {replacement} [paths]",
+            "Preview and apply a replacement across files",
+        )];
+        COMMANDS
+    }
+}
+
+/// A directory listing opened with `:Explore` or by editing a directory.
+pub struct ExplorerPlugin {
+    dir: PathBuf,
+    /// Names in the listing, with a trailing `/` on directories.
+    entries: Vec<String>,
+    selected: usize,
+}
+
+impl ExplorerPlugin {
+    const TITLE: &str = "explorer";
+
+    pub fn new() -> Self {
+        Self {
+            dir: PathBuf::new(),
+            entries: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    /// Lists `dir`: `../` first, then directories and then files, each in
+    /// name order.
+    fn open(&mut self, editor: &mut Editor, dir: PathBuf) {
+        let read = match std::fs::read_dir(&dir) {
+            Ok(read) => read,
+            Err(err) => {
+                editor.set_error(format!("Cannot read {}: {}", dir.display(), err));
+                return;
+            }
+        };
+        let mut entries: Vec<(bool, String)> = read
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let is_dir = entry.path().is_dir();
+                (!is_dir, entry.file_name().to_string_lossy().into_owned())
+            })
+            .collect();
+        entries.sort();
+        self.entries = vec!["../".to_string()];
+        self.entries.extend(entries.into_iter().map(
+            |(is_file, name)| {
+                if is_file { name } else { name + "/" }
+            },
+        ));
+        self.selected = 0;
+        self.dir = dir;
+        editor.open_scratch(Self::TITLE, Vec::new());
+        self.refresh(editor);
+    }
+
+    /// Redraws the listing and scrolls the selected entry into view.
+    fn refresh(&self, editor: &mut Editor) {
+        let page = editor.content_height().max(1) as usize;
+        let Some(view) = editor.scratch.as_mut() else {
+            return;
+        };
+        view.lines = vec![format!(
+            "{}: Enter opens, - goes up, q closes",
+            self.dir.display()
+        )];
+        for (i, name) in self.entries.iter().enumerate() {
+            let marker = if i == self.selected { '>' } else { ' ' };
+            view.lines.push(format!("{} {}", marker, name));
+        }
+        let row = 1 + self.selected;
+        if row < view.scroll {
+            view.scroll = row;
+        } else if row >= view.scroll + page {
+            view.scroll = row + 1 - page;
+        }
+    }
+
+    /// The directory above `dir`, written without resolving symlinks.
+    fn parent(dir: &Path) -> PathBuf {
+        match (dir.file_name(), dir.parent()) {
+            (Some(_), Some(parent)) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            (Some(_), _) => PathBuf::from("."),
+            (None, _) => dir.join(".."),
+        }
+    }
+
+    /// Opens the selected entry: a directory replaces the listing and a file
+    /// is edited.
+    fn enter(&mut self, editor: &mut Editor) {
+        let name = &self.entries[self.selected];
+        if name == "../" {
+            self.open(editor, Self::parent(&self.dir));
+            return;
+        }
+        let path = self.dir.join(name.trim_end_matches('/'));
+        let path = path.strip_prefix(".").unwrap_or(&path).to_path_buf();
+        if name.ends_with('/') {
+            self.open(editor, path);
+        } else if FileCommandPlugin::command_edit(editor, &path.to_string_lossy(), false) {
+            editor.close_scratch();
+            self.entries.clear();
+        }
+    }
+}
+
+impl Plugin for ExplorerPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command) {
+            ("Explore" | "Ex", dir) => {
+                let dir = match dir {
+                    "" => editor
+                        .file_path
+                        .as_deref()
+                        .and_then(Path::parent)
+                        .filter(|parent| !parent.as_os_str().is_empty())
+                        .map_or_else(|| PathBuf::from("."), Path::to_path_buf),
+                    dir => PathBuf::from(dir),
+                };
+                self.open(editor, dir);
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if self.entries.is_empty() {
+            return EventResult::Ignored;
+        }
+        if editor
+            .scratch
+            .as_ref()
+            .is_none_or(|view| view.title != Self::TITLE)
+        {
+            // The listing was closed or replaced by another view.
+            self.entries.clear();
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.entries.len() - 1);
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            KeyCode::Enter | KeyCode::Char('l') => {
+                self.enter(editor);
+                return EventResult::Consumed;
+            }
+            KeyCode::Char('-' | 'h') => {
+                self.open(editor, Self::parent(&self.dir));
+                return EventResult::Consumed;
+            }
+            _ => return EventResult::Ignored,
+        }
+        self.refresh(editor);
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "Explore",
+            "[dir]",
+            "List a directory to open files from",
+        )];
+        COMMANDS
+    }
+}
+
+/// `:oldfiles` and the `:browse oldfiles` picker, which reopens a file at
+/// the position it was left at.
+pub struct OldFilesPlugin {
+    files: Vec<OldFile>,
+    selected: usize,
+}
+
+impl OldFilesPlugin {
+    const TITLE: &str = "browse oldfiles";
+
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    fn load(editor: &mut Editor) -> Vec<OldFile> {
+        let files = editor
+            .state_dir
+            .as_ref()
+            .map(|dir| oldfiles::load(&dir.join(oldfiles::OLDFILES_FILE)));
+        let files = files.unwrap_or_default();
+        if files.is_empty() {
+            editor.set_status("No old files");
+        }
+        files
+    }
+
+    fn list(editor: &mut Editor) {
+        let files = Self::load(editor);
+        if files.is_empty() {
+            return;
+        }
+        let lines = files
+            .iter()
+            .enumerate()
+            .map(|(i, file)| format!("{:>3}: {}", i + 1, file.path.display()))
+            .collect();
+        editor.open_scratch("oldfiles", lines);
+    }
+
+    /// Redraws the picker, previews the selected file where it was left and
+    /// scrolls it into view.
+    fn refresh(&self, editor: &mut Editor) {
+        let file = &self.files[self.selected];
+        if editor
+            .open_preview_window(&file.path, file.cursor.row)
+            .is_err()
+        {
+            editor.close_preview_window();
+        }
+        let page = editor.content_height().max(1) as usize;
+        let Some(view) = editor.scratch.as_mut() else {
+            return;
+        };
+        view.lines = vec!["Recent files: Enter opens, q closes".to_string()];
+        for (i, file) in self.files.iter().enumerate() {
+            view.lines.push(format!(
+                "{} {}:{}",
+                if i == self.selected { '>' } else { ' ' },
+                file.path.display(),
+                file.cursor.row + 1
+            ));
+        }
+        let row = 1 + self.selected;
+        if row < view.scroll {
+            view.scroll = row;
+        } else if row >= view.scroll + page {
+            view.scroll = row + 1 - page;
+        }
+    }
+
+    fn open(&mut self, editor: &mut Editor) {
+        let file = self.files[self.selected].clone();
+        if FileCommandPlugin::command_edit(editor, &file.path.to_string_lossy(), false) {
+            editor.close_scratch();
+            editor.close_preview_window();
+            self.files.clear();
+            editor.goto_position(file.cursor.row, file.cursor.col);
+        }
+    }
+}
+
+impl Plugin for OldFilesPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command) {
+            ("oldfiles" | "ol", "") => Self::list(editor),
+            ("browse" | "bro", "oldfiles" | "ol") => {
+                self.files = Self::load(editor);
+                self.selected = 0;
+                if !self.files.is_empty() {
+                    editor.open_scratch(Self::TITLE, Vec::new());
+                    self.refresh(editor);
+                }
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if self.files.is_empty() {
+            return EventResult::Ignored;
+        }
+        if editor
+            .scratch
+            .as_ref()
+            .is_none_or(|view| view.title != Self::TITLE)
+        {
+            // The picker was replaced by another view.
+            self.files.clear();
+            editor.close_preview_window();
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                editor.close_scratch();
+                editor.close_preview_window();
+                self.files.clear();
+                return EventResult::Consumed;
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.files.len() - 1);
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            KeyCode::Enter => {
+                self.open(editor);
+                return EventResult::Consumed;
+            }
+            _ => return EventResult::Ignored,
+        }
+        self.refresh(editor);
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new("oldfiles", "", "List recently edited files"),
+            CommandInfo::new(
+                "browse oldfiles",
+                "",
+                "Pick a recently edited file to reopen",
+            ),
+        ];
+        COMMANDS
+    }
+}
+
+pub struct JumpListPlugin;
+
+impl JumpListPlugin {
+    fn go_to(editor: &mut Editor, jump: Jump) {
+        if jump.path == editor.file_path {
+            editor.goto_position(jump.cursor.row, jump.cursor.col);
+            return;
+        }
+        let Some(path) = jump.path else {
+            editor.set_error("Jump target has no file name");
+            return;
+        };
+        let path = expand::escape(&path.to_string_lossy());
+        editor.push_command(format!("edit {}", path));
+        editor.push_command(format!(
+            "cursor {} {}",
+            jump.cursor.row + 1,
+            jump.cursor.col + 1
+        ));
+    }
+}
+
+impl Plugin for JumpListPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        if !matches!(command.trim(), "jumps" | "ju") {
+            return EventResult::Ignored;
+        }
+        // Like Vim, number entries by their distance from the current
+        // position and mark that position with `>`.
+        let index = editor.jumps.index;
+        let mut lines = vec![" jump line  col file/text".to_string()];
+        for (i, jump) in editor.jumps.entries.iter().enumerate() {
+            let text = if jump.path == editor.file_path {
+                editor
+                    .buffer
+                    .lines
+                    .get(jump.cursor.row)
+                    .map_or("", |l| l.trim())
+                    .to_string()
+            } else {
+                jump.path
+                    .as_ref()
+                    .map_or(String::new(), |path| path.display().to_string())
+            };
+            lines.push(format!(
+                "{}{:>4} {:>5} {:>4} {}",
+                if i == index { '>' } else { ' ' },
+                i.abs_diff(index),
+                jump.cursor.row + 1,
+                jump.cursor.col,
+                text
+            ));
+        }
+        if index >= editor.jumps.entries.len() {
+            lines.push(">".to_string());
+        }
+        editor.open_scratch("jumps", lines);
+        EventResult::Consumed
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal || !editor.pending_keys.is_empty() {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        let target = match key.code {
+            KeyCode::Char('o') if control => {
+                let current = editor.current_jump();
+                editor.jumps.back(current)
+            }
+            // Terminals send Ctrl-I as Tab.
+            KeyCode::Tab => editor.jumps.forward(),
+            KeyCode::Char('i') if control => editor.jumps.forward(),
+            _ => return EventResult::Ignored,
+        };
+        match target {
+            Some(jump) => Self::go_to(editor, jump),
+            None => editor.set_status("Already at the end of the jumplist"),
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new("jumps", "", "List the jumplist")];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(
+                Mode::Normal,
+                "<C-o>",
+                "Go back to the previous jump location",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "<C-i>",
+                "Go forward to the next jump location",
+            ),
+        ];
+        KEYS
+    }
+}
+
+/// `m{a-z}` sets a mark, `'{mark}` jumps to its line and `` `{mark} `` to
+/// its exact position; `:marks` lists them and `:delmarks` removes them.
+pub struct MarkPlugin;
+
+impl MarkPlugin {
+    /// Lists marks in name order. Uppercase marks in other files show the
+    /// file name in place of the line's text.
+    fn listing(editor: &Editor, wanted: &[char]) -> Vec<String> {
+        let local = editor.marks.iter().map(|(name, mark)| (*name, *mark, None));
+        let global = editor.global_marks.iter().map(|(name, mark)| {
+            let elsewhere = editor.mark(*name).is_none().then_some(mark.path.as_deref());
+            (*name, mark.cursor, elsewhere.flatten())
+        });
+        let mut marks: Vec<_> = local
+            .chain(global)
+            .filter(|(name, _, _)| wanted.is_empty() || wanted.contains(name))
+            .collect();
+        marks.sort_by_key(|(name, _, _)| *name);
+        marks
+            .into_iter()
+            .map(|(name, mark, path)| {
+                let text = match path {
+                    Some(path) => path.display().to_string(),
+                    None => editor
+                        .buffer
+                        .lines
+                        .get(mark.row)
+                        .map_or("", |l| l.trim())
+                        .to_string(),
+                };
+                format!(" {}  {:>5} {:>4} {}", name, mark.row + 1, mark.col, text)
+            })
+            .collect()
+    }
+
+    /// Expands `:delmarks` arguments such as `a b-d <` into mark names.
+    fn parse_names(args: &str) -> Result<Vec<char>, String> {
+        let chars: Vec<char> = args.chars().filter(|ch| !ch.is_whitespace()).collect();
+        let mut names = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let ch = chars[i];
+            if chars.get(i + 1) == Some(&'-') {
+                let Some(&end) = chars.get(i + 2) else {
+                    return Err(format!("Invalid argument: {}", args));
+                };
+                let same_case = (ch.is_ascii_lowercase() && end.is_ascii_lowercase())
+                    || (ch.is_ascii_uppercase() && end.is_ascii_uppercase());
+                if !same_case || end < ch {
+                    return Err(format!("Invalid argument: {}", args));
+                }
+                names.extend(ch..=end);
+                i += 3;
+            } else if ch.is_ascii_alphabetic() || matches!(ch, '<' | '>') {
+                names.push(ch);
+                i += 1;
+            } else {
+                return Err(format!("Invalid argument: {}", args));
+            }
+        }
+        Ok(names)
+    }
+
+    fn jump(editor: &mut Editor, name: char, exact: bool) {
+        let mark = match editor.mark(name) {
+            Some(mark) => {
+                editor.push_jump();
+                mark
+            }
+            None => match editor.global_marks.get(&name).cloned() {
+                Some(Jump {
+                    path: Some(path),
+                    cursor,
+                }) => {
+                    // The mark is in another file, which replaces this one.
+                    let jump = editor.current_jump();
+                    if !FileCommandPlugin::command_edit(editor, &path.to_string_lossy(), false) {
+                        return;
+                    }
+                    editor.jumps.push(jump);
+                    cursor
+                }
+                _ => {
+                    editor.set_error(format!("Mark not set: {}", name));
+                    return;
+                }
+            },
+        };
+        if exact {
+            editor.goto_position(mark.row, mark.col);
+        } else {
+            editor.goto_position(mark.row, 0);
+            let target = Motion::FirstNonBlank.target(&editor.buffer.lines, editor.cursor);
+            editor.goto_position(target.row, target.col);
+        }
+    }
+}
+
+impl Plugin for MarkPlugin {
+    fn on_init(&mut self, editor: &mut Editor) {
+        if let Some(dir) = &editor.state_dir {
+            editor.global_marks = global_marks::load(&dir.join(global_marks::GLOBAL_MARKS_FILE));
+        }
+    }
+
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (name, args) = split_command(command);
+        match name {
+            "marks" => {
+                let wanted: Vec<char> = args.chars().filter(|ch| !ch.is_whitespace()).collect();
+                let lines = Self::listing(editor, &wanted);
+                if lines.is_empty() {
+                    editor.set_status("No marks set");
+                } else {
+                    let mut view = vec!["mark  line  col text".to_string()];
+                    view.extend(lines);
+                    editor.open_scratch("marks", view);
+                }
+            }
+            "delmarks!" | "delm!" => editor.marks.retain(|name, _| !name.is_ascii_lowercase()),
+            "delmarks" | "delm" if args.is_empty() => editor.set_error("Argument required"),
+            "delmarks" | "delm" => match Self::parse_names(args) {
+                Ok(names) => {
+                    editor.marks.retain(|name, _| !names.contains(name));
+                    if names.iter().any(char::is_ascii_uppercase) {
+                        editor.update_global_marks(|marks| {
+                            marks.retain(|name, _| !names.contains(name))
+                        });
+                    }
+                }
+                Err(err) => editor.set_error(err),
+            },
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return EventResult::Ignored;
+        }
+        let KeyCode::Char(ch) = key.code else {
+            return EventResult::Ignored;
+        };
+        match editor.pending_keys.as_str() {
+            "" if matches!(ch, 'm' | '\'' | '`') => editor.pending_keys.push(ch),
+            "m" => {
+                editor.pending_keys.clear();
+                if ch.is_ascii_lowercase() {
+                    editor.marks.insert(ch, editor.cursor);
+                } else if ch.is_ascii_uppercase() {
+                    match editor.file_path.as_deref().map(std::path::absolute) {
+                        Some(Ok(path)) => {
+                            let mark = Jump {
+                                path: Some(path),
+                                cursor: editor.cursor,
+                            };
+                            editor.update_global_marks(|marks| {
+                                marks.insert(ch, mark.clone());
+                            });
+                        }
+                        Some(Err(err)) => editor.set_error(format!("Cannot set mark: {}", err)),
+                        None => editor.set_error("No file name"),
+                    }
+                } else {
+                    editor.set_error(format!("Invalid mark name: {}", ch));
+                }
+            }
+            "'" | "`" => {
+                let exact = editor.pending_keys == "`";
+                editor.pending_keys.clear();
+                Self::jump(editor, ch, exact);
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "marks",
+                "[names]",
+                "List marks with their position and text",
+            ),
+            CommandInfo::new("delmarks", "{marks}", "Delete marks"),
+        ];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(
+                Mode::Normal,
+                "m{mark}",
+                "Set a mark; an uppercase mark also remembers the file",
+            ),
+            KeyInfo::new(Mode::Normal, "'{mark}", "Jump to the line of a mark"),
+            KeyInfo::new(Mode::Normal, "`{mark}", "Jump to the position of a mark"),
+        ];
+        KEYS
+    }
+}
+
+pub struct ChangeListPlugin;
+
+impl Plugin for ChangeListPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal || editor.pending_keys != "g" {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+
+        let target = match key.code {
+            KeyCode::Char(';') => editor.changes.older().ok_or("At start of changelist"),
+            KeyCode::Char(',') => editor.changes.newer().ok_or("At end of changelist"),
+            _ => return EventResult::Ignored,
+        };
+        editor.pending_keys.clear();
+        match target {
+            Ok(cursor) => editor.goto_position(cursor.row, cursor.col),
+            Err(message) => editor.set_error(message),
+        }
+        EventResult::Consumed
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "g;", "Go to an older edit location"),
+            KeyInfo::new(Mode::Normal, "g,", "Go to a newer edit location"),
+        ];
+        KEYS
+    }
+}
+
+/// Runs linters with `:Lint {program}` and moves between what they report
+/// with `]d` and `[d`. The message of each is shown at the end of its line
+/// while the cursor is there.
+pub struct DiagnosticPlugin;
+
+impl DiagnosticPlugin {
+    /// Virtual text namespace of the messages.
+    const NAMESPACE: &'static str = "diagnostics";
+
+    fn lint(editor: &mut Editor, program: &str) {
+        if program.is_empty() {
+            editor.diagnostics.clear();
+            editor.virtual_text.clear(Self::NAMESPACE);
+            editor.set_status("Diagnostics cleared");
+            return;
+        }
+        let Some(path) = editor.file_path.clone() else {
+            editor.set_error("No file name");
+            return;
+        };
+        let mut args = program.split_whitespace();
+        let Some(bin) = args.next() else {
+            return;
+        };
+        FileCommandPlugin::autowrite(editor, false);
+        let output = match Command::new(bin).args(args).arg(&path).output() {
+            Ok(output) => output,
+            Err(err) => {
+                editor.set_error(format!("{}: {}", bin, err));
+                return;
+            }
+        };
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        editor.diagnostics = diagnostics::parse(&text, &path, &editor.buffer.lines);
+        editor.virtual_text.clear(Self::NAMESPACE);
+        for diagnostic in &editor.diagnostics {
+            let text = VirtualText {
+                col: None,
+                cursor_line: true,
+                text: diagnostic.message.clone(),
+                style: editor.theme.style(diagnostic.severity.group()),
+            };
+            editor
+                .virtual_text
+                .add(Self::NAMESPACE, diagnostic.row, text);
+        }
+        editor.set_status(match editor.diagnostics.len() {
+            0 => "No diagnostics".to_string(),
+            1 => "1 diagnostic".to_string(),
+            count => format!("{} diagnostics", count),
+        });
+    }
+}
+
+impl Plugin for DiagnosticPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command) {
+            ("Lint", program) => {
+                Self::lint(editor, program);
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let KeyCode::Char(ch) = key.code else {
+            return EventResult::Ignored;
+        };
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return EventResult::Ignored;
+        }
+        let target = match (editor.pending_keys.as_str(), ch) {
+            ("", ']' | '[') => {
+                editor.pending_keys.push(ch);
+                return EventResult::Consumed;
+            }
+            ("]", 'd') => diagnostics::next(&editor.diagnostics, editor.cursor),
+            ("[", 'd') => diagnostics::prev(&editor.diagnostics, editor.cursor),
+            _ => return EventResult::Ignored,
+        };
+        editor.pending_keys.clear();
+        match target.cloned() {
+            Some(diagnostic) => {
+                editor.push_jump();
+                editor.goto_position(diagnostic.row, diagnostic.col);
+                editor.set_status(diagnostic.message);
+            }
+            None => editor.set_status("No diagnostics"),
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "Lint",
+            "[program]",
+            "Run a linter and show its diagnostics, or clear them",
+        )];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "]d", "Go to the next diagnostic"),
+            KeyInfo::new(Mode::Normal, "[d", "Go to the previous diagnostic"),
+        ];
+        KEYS
+    }
+}
+
+/// Underlines the text diagnostics point at, in the color of their
+/// severity.
+pub struct DiagnosticRenderPlugin;
+
+impl Plugin for DiagnosticRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if editor.scratch.is_some() {
+            return;
+        }
+        let first = editor.viewport.row_offset;
+        let last = first + editor.content_height() as usize;
+        let visible = editor
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| (first..last).contains(&diagnostic.row));
+        for diagnostic in visible {
+            let Some(line) = editor.buffer.lines.get(diagnostic.row) else {
+                continue;
+            };
+            let row = (diagnostic.row - first) as u16;
+            let color = editor
+                .theme
+                .style(diagnostic.severity.group())
+                .foreground_color;
+            let Some(span) = buffer_span(
+                editor,
+                line,
+                diagnostic.col,
+                diagnostic.end_col,
+                ContentStyle::new(),
+            ) else {
+                continue;
+            };
+            // Underline each cell in its own color, keeping the text's.
+            for col in span.start..span.start + span.len {
+                let mut style = ctx.style_at(row, col);
+                style.attributes.set(Attribute::Underlined);
+                style.underline_color = color;
+                ctx.add_span(
+                    row,
+                    StyledSpan {
+                        start: col,
+                        len: 1,
+                        style,
+                    },
+                );
+            }
+        }
+    }
+}
+
+pub struct MotionPlugin;
+
+impl Plugin for MotionPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let command = command.trim();
+        if command == "$" {
+            editor.push_jump();
+            editor.goto_line(usize::MAX);
+            return EventResult::Consumed;
+        }
+        if let ("cursor", args) = split_command(command) {
+            let mut numbers = args.split_whitespace().map(str::parse::<usize>);
+            match (numbers.next(), numbers.next().unwrap_or(Ok(1))) {
+                (Some(Ok(line)), Ok(col)) => {
+                    editor.goto_position(line.saturating_sub(1), col.saturating_sub(1));
+                }
+                _ => editor.set_error("Usage: cursor {line} [col]"),
+            }
+            return EventResult::Consumed;
+        }
+        match command.parse::<usize>() {
+            Ok(line) => {
+                editor.push_jump();
+                editor.goto_line(line.saturating_sub(1));
+                EventResult::Consumed
+            }
+            Err(_) => EventResult::Ignored,
+        }
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if !matches!(editor.mode, Mode::Normal | Mode::Visual) {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return EventResult::Ignored;
+        }
+
+        if !editor.pending_keys.is_empty() {
+            return match (editor.pending_keys.as_str(), key.code) {
+                ("g", KeyCode::Char('g')) => {
+                    editor.pending_keys.clear();
+                    editor.push_jump();
+                    editor.goto_line(0);
+                    EventResult::Consumed
+                }
+                (keys @ ("]" | "["), KeyCode::Char('w')) => {
+                    let motion = if keys == "]" {
+                        Motion::SubwordForward
+                    } else {
+                        Motion::SubwordBackward
+                    };
+                    editor.pending_keys.clear();
+                    let target = motion.target(&editor.buffer.lines, editor.cursor);
+                    editor.goto_position(target.row, target.col);
+                    EventResult::Consumed
+                }
+                _ => EventResult::Ignored,
+            };
+        }
+
+        match key.code {
+            KeyCode::Char('g') => {
+                editor.pending_keys.push('g');
+                EventResult::Consumed
+            }
+            KeyCode::Char('G') => {
+                editor.push_jump();
+                editor.goto_line(usize::MAX);
+                EventResult::Consumed
+            }
+            KeyCode::Char('h') | KeyCode::Left => {
+                editor.move_left();
+                EventResult::Consumed
+            }
+            KeyCode::Char('l') | KeyCode::Right => {
+                editor.move_right();
+                EventResult::Consumed
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                editor.move_up();
+                EventResult::Consumed
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                editor.move_down();
+                EventResult::Consumed
+            }
+            KeyCode::Char('0') => {
+                editor.move_line_start();
+                EventResult::Consumed
+            }
+            KeyCode::Char('$') => {
+                editor.move_line_end();
+                EventResult::Consumed
+            }
+            KeyCode::Char(ch @ ('w' | 'W' | 'b' | 'B' | 'e' | 'E' | '{' | '}' | '^')) => {
+                let Parse::Done(motion) = Motion::parse(&ch.to_string()) else {
+                    return EventResult::Ignored;
+                };
+                if matches!(motion, Motion::ParagraphForward | Motion::ParagraphBackward) {
+                    editor.push_jump();
+                }
+                let target = motion.target(&editor.buffer.lines, editor.cursor);
+                editor.goto_position(target.row, target.col);
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new("$", "", "Go to the last line"),
+            CommandInfo::new(
+                "cursor",
+                "{line} [col]",
+                "Move the cursor to a line and column",
+            ),
+        ];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "h", "Move left"),
+            KeyInfo::new(Mode::Normal, "j", "Move down"),
+            KeyInfo::new(Mode::Normal, "k", "Move up"),
+            KeyInfo::new(Mode::Normal, "l", "Move right"),
+            KeyInfo::new(Mode::Normal, "0", "Go to the start of the line"),
+            KeyInfo::new(Mode::Normal, "^", "Go to the first non-blank character"),
+            KeyInfo::new(Mode::Normal, "$", "Go to the end of the line"),
+            KeyInfo::new(Mode::Normal, "w", "Go to the next word"),
+            KeyInfo::new(Mode::Normal, "b", "Go to the previous word"),
+            KeyInfo::new(Mode::Normal, "e", "Go to the end of the word"),
+            KeyInfo::new(Mode::Normal, "W", "Go to the next blank-separated word"),
+            KeyInfo::new(Mode::Normal, "B", "Go to the previous blank-separated word"),
+            KeyInfo::new(
+                Mode::Normal,
+                "E",
+                "Go to the end of the blank-separated word",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "]w",
+                "Go to the next camelCase or snake_case part",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "[w",
+                "Go to the previous camelCase or snake_case part",
+            ),
+            KeyInfo::new(Mode::Normal, "{", "Go to the previous paragraph break"),
+            KeyInfo::new(Mode::Normal, "}", "Go to the next paragraph break"),
+            KeyInfo::new(Mode::Normal, "gg", "Go to the first line"),
+            KeyInfo::new(Mode::Normal, "G", "Go to the last line"),
+        ];
+        KEYS
+    }
+}
+
+/// Inserts bracketed-paste text in one batch, bypassing per-key Insert-mode
+/// handling such as abbreviations.
+pub struct PastePlugin;
+
+impl PastePlugin {
+    /// Normalizes `\r\n` and lone `\r` line endings from terminals to `\n`.
+    fn normalize(text: &str) -> String {
+        text.replace("\r\n", "\n").replace('\r', "\n")
+    }
+}
+
+impl Plugin for PastePlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Paste(text) = event else {
+            return EventResult::Ignored;
+        };
+        let text = Self::normalize(text);
+        match editor.mode {
+            Mode::Command => {
+                let first_line = text.lines().next().unwrap_or_default();
+                editor.command_line.insert_str(first_line);
+            }
+            Mode::Normal | Mode::Insert if editor.scratch.is_none() => {
+                if editor.is_loading() {
+                    editor.set_error("Cannot edit while the file is still loading");
+                    return EventResult::Consumed;
+                }
+                editor.insert_text(&text);
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
+}
+
+/// `>>` / `<<` shift the current line by `shiftwidth` and `==` re-indents it;
+/// `>`, `<` and `=` do the same to the lines of a Visual selection.
+pub struct IndentPlugin;
+
+impl Plugin for IndentPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let KeyCode::Char(ch @ ('>' | '<' | '=')) = key.code else {
+            return EventResult::Ignored;
+        };
+        if editor.is_loading() {
+            return EventResult::Ignored;
+        }
+        let levels = if ch == '>' { 1 } else { -1 };
+        match editor.mode {
+            Mode::Visual => {
+                if let Some((start, end)) = editor.selection() {
+                    editor.begin_batch();
+                    for row in start.row..=end.row {
+                        match ch {
+                            '=' => editor.reindent_line(row),
+                            _ => editor.shift_line(row, levels),
+                        }
+                    }
+                    editor.end_batch();
+                }
+                editor.stop_visual();
+                EventResult::Consumed
+            }
+            Mode::Normal if editor.pending_keys.is_empty() => {
+                editor.pending_keys.push(ch);
+                EventResult::Consumed
+            }
+            Mode::Normal if editor.pending_keys == ch.to_string() => {
+                editor.pending_keys.clear();
+                match ch {
+                    '=' => editor.reindent_line(editor.cursor.row),
+                    _ => editor.shift_line(editor.cursor.row, levels),
+                }
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, ">>", "Shift the line right"),
+            KeyInfo::new(Mode::Normal, "<<", "Shift the line left"),
+            KeyInfo::new(Mode::Normal, "==", "Re-indent the line"),
+            KeyInfo::new(Mode::Visual, ">", "Shift the selected lines right"),
+            KeyInfo::new(Mode::Visual, "<", "Shift the selected lines left"),
+            KeyInfo::new(Mode::Visual, "=", "Re-indent the selected lines"),
+        ];
+        KEYS
+    }
+}
+
+/// Toggles line comments with `gcc`, `gc{motion}` and `gc` on a Visual
+/// selection.
+pub struct CommentPlugin;
+
+impl Plugin for CommentPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let KeyCode::Char(ch) = key.code else {
+            return EventResult::Ignored;
+        };
+        if !editor.pending_keys.starts_with('g') || editor.is_loading() {
+            return EventResult::Ignored;
+        }
+        match editor.mode {
+            Mode::Visual if editor.pending_keys == "g" && ch == 'c' => {
+                editor.pending_keys.clear();
+                if let Some((start, end)) = editor.selection() {
+                    editor.toggle_comment(start.row, end.row);
+                    editor.cursor = start;
+                    editor.clamp_cursor();
+                }
+                editor.stop_visual();
+                EventResult::Consumed
+            }
+            Mode::Normal if editor.pending_keys == "g" && ch == 'c' => {
+                editor.pending_keys.push(ch);
+                EventResult::Consumed
+            }
+            Mode::Normal if editor.pending_keys.starts_with("gc") => {
+                editor.pending_keys.push(ch);
+                let keys = &editor.pending_keys[2..];
+                let range = if keys == "c" {
+                    Some((editor.cursor.row, editor.cursor.row))
+                } else {
+                    match motion::parse_target(keys) {
+                        Parse::Pending => return EventResult::Consumed,
+                        Parse::Invalid => None,
+                        Parse::Done(target) => target
+                            .range(&editor.buffer.lines, editor.cursor)
+                            .map(|range| (range.start.row, range.end.row)),
+                    }
+                };
+                editor.pending_keys.clear();
+                if let Some((first, last)) = range {
+                    editor.toggle_comment(first, last);
+                    if first != editor.cursor.row {
+                        editor.goto_line(first);
+                    }
+                }
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "gcc", "Toggle a comment on the line"),
+            KeyInfo::new(Mode::Normal, "gc{motion}", "Toggle comments over a motion"),
+            KeyInfo::new(Mode::Visual, "gc", "Toggle comments on the selected lines"),
+        ];
+        KEYS
+    }
+}
+
+/// Adds, changes and deletes surrounding delimiters: `ys{motion}{char}`,
+/// `yss{char}` for the whole line, `cs{old}{new}`, `ds{char}`, and
+/// `S{char}` on a Visual selection.
+pub struct SurroundPlugin;
+
+impl SurroundPlugin {
+    fn surround(editor: &mut Editor, range: TextRange, ch: char) {
+        let Some((open, close)) = surround::delimiters(ch) else {
+            editor.set_error(format!("Cannot surround with {}", ch));
+            return;
+        };
+        let line_of = |row: usize| -> Vec<char> { editor.buffer.lines[row].chars().collect() };
+        let (mut start, mut end) = (range.start, range.end);
+        if range.linewise {
+            start.col = line_of(start.row)
+                .iter()
+                .take_while(|ch| ch.is_whitespace())
+                .count();
+            end.col = line_of(end.row).len();
+        }
+        // Like vim-surround, leave trailing whitespace (as from `aw`) outside.
+        let end_line = line_of(end.row);
+        while end.col > 0
+            && (end.row, end.col) > (start.row, start.col)
+            && end_line[end.col - 1].is_whitespace()
+        {
+            end.col -= 1;
+        }
+        editor.begin_batch();
+        editor.replace_range(end, end, &close);
+        editor.replace_range(start, start, &open);
+        editor.end_batch();
+    }
+
+    /// Replaces the delimiters named by `old` with those for `new`, or
+    /// deletes them when `new` is `None`.
+    fn change(editor: &mut Editor, old: char, new: Option<char>) {
+        let replacement = match new.map(surround::delimiters) {
+            Some(Some(pair)) => pair,
+            Some(None) => {
+                editor.set_error(format!("Cannot surround with {}", new.unwrap_or(old)));
+                return;
+            }
+            None => (String::new(), String::new()),
+        };
+        let Some((open, close)) = surround::find(&editor.buffer.lines, editor.cursor, old) else {
+            editor.set_error(format!("No surrounding {} found", old));
+            return;
+        };
+        editor.begin_batch();
+        editor.replace_range(close.start, close.end, &replacement.1);
+        editor.replace_range(open.start, open.end, &replacement.0);
+        editor.end_batch();
+    }
+}
+
+impl Plugin for SurroundPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let KeyCode::Char(ch) = key.code else {
+            return EventResult::Ignored;
+        };
+        if key.modifiers.contains(KeyModifiers::CONTROL) || editor.is_loading() {
+            return EventResult::Ignored;
+        }
+        if editor.mode == Mode::Visual {
+            return match editor.pending_keys.as_str() {
+                "" if ch == 'S' => {
+                    editor.pending_keys.push(ch);
+                    EventResult::Consumed
+                }
+                "S" => {
+                    editor.pending_keys.clear();
+                    if let Some((start, end)) = editor.selection() {
+                        let range = TextRange {
+                            start,
+                            end,
+                            linewise: false,
+                        };
+                        Self::surround(editor, range, ch);
+                    }
+                    editor.stop_visual();
+                    EventResult::Consumed
+                }
+                _ => EventResult::Ignored,
+            };
+        }
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+
+        match editor.pending_keys.as_str() {
+            "y" | "c" | "d" if ch == 's' => {
+                editor.pending_keys.push(ch);
+                return EventResult::Consumed;
+            }
+            "cs" => {
+                editor.pending_keys.push(ch);
+                return EventResult::Consumed;
+            }
+            "ds" => {
+                editor.pending_keys.clear();
+                Self::change(editor, ch, None);
+                return EventResult::Consumed;
+            }
+            keys if keys.starts_with("cs") => {
+                let old = keys.chars().nth(2).unwrap_or(ch);
+                editor.pending_keys.clear();
+                Self::change(editor, old, Some(ch));
+                return EventResult::Consumed;
+            }
+            keys if keys.starts_with("ys") => {}
+            _ => return EventResult::Ignored,
+        }
+
+        // The keys after `ys` name the text; the key after them names the
+        // delimiter.
+        let keys = editor.pending_keys[2..].to_string();
+        if keys.is_empty() {
+            editor.pending_keys.push(ch);
+            return EventResult::Consumed;
+        }
+        let range = if keys == "s" {
+            // `yss` surrounds the whole line.
+            Some(TextRange {
+                start: editor.cursor,
+                end: editor.cursor,
+                linewise: true,
+            })
+        } else {
+            match motion::parse_target(&keys) {
+                Parse::Done(target) => target.range(&editor.buffer.lines, editor.cursor),
+                Parse::Pending => {
+                    editor.pending_keys.push(ch);
+                    return EventResult::Consumed;
+                }
+                Parse::Invalid => {
+                    editor.pending_keys.clear();
+                    return EventResult::Consumed;
+                }
+            }
+        };
+        editor.pending_keys.clear();
+        match range {
+            Some(range) => Self::surround(editor, range, ch),
+            None => editor.set_error("Nothing to surround"),
+        }
+        EventResult::Consumed
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(
+                Mode::Normal,
+                "ys{motion}{char}",
+                "Surround text with a pair",
+            ),
+            KeyInfo::new(Mode::Normal, "yss{char}", "Surround the line with a pair"),
+            KeyInfo::new(Mode::Normal, "cs{old}{new}", "Change the surrounding pair"),
+            KeyInfo::new(Mode::Normal, "ds{char}", "Delete the surrounding pair"),
+            KeyInfo::new(
+                Mode::Visual,
+                "S{char}",
+                "Surround the selection with a pair",
+            ),
+        ];
+        KEYS
+    }
+}
+
+/// Shows control characters as `^J`, `^I` and so on so listings stay on one
+/// line.
+fn caret_notation(text: &str) -> String {
+    text.chars()
+        .map(|ch| match ch {
+            '\x7f' => "^?".to_string(),
+            ch if ch.is_ascii_control() => format!("^{}", char::from(ch as u8 + b'@')),
+            ch => ch.to_string(),
+        })
+        .collect()
+}
+
+/// Operators on the Visual-mode selection.
+pub struct VisualPlugin;
+
+impl Plugin for VisualPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Visual || !editor.pending_keys.is_empty() {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let operator = match key.code {
+            KeyCode::Char('x') => 'd',
+            KeyCode::Char(op @ ('d' | 'c' | 'y')) => op,
+            _ => return EventResult::Ignored,
+        };
+        let selection = editor.selection();
+        editor.stop_visual();
+        if let Some((start, end)) = selection {
+            let range = TextRange {
+                start,
+                end,
+                linewise: false,
+            };
+            OperatorPlugin::apply(editor, operator, range);
+        }
+        EventResult::Consumed
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Visual, "d", "Delete the selection"),
+            KeyInfo::new(Mode::Visual, "x", "Delete the selection"),
+            KeyInfo::new(Mode::Visual, "c", "Change the selection"),
+            KeyInfo::new(Mode::Visual, "y", "Yank the selection"),
+        ];
+        KEYS
+    }
+}
+
+/// `d`, `c` and `y` over a motion or text object (doubled, as in `dd`, they
+/// act on the line), `D`, `C`, `Y`, `x`, `p`/`P` to put, and `"{register}`
+/// to pick the register the next one uses.
+pub struct OperatorPlugin;
+
+impl OperatorPlugin {
+    /// Applies operator `d`, `c` or `y` to `range`, saving the text in the
+    /// pending register.
+    fn apply(editor: &mut Editor, operator: char, range: TextRange) {
+        if operator != 'y' && editor.is_loading() {
+            editor.set_error("Cannot edit while the file is still loading");
+            return;
+        }
+        let register = editor.pending_register.take();
+        let (first, last) = (range.start.row, range.end.row);
+        let lines = last - first + 1;
+        if range.linewise {
+            let text = editor.buffer.lines[first..=last].join("\n") + "\n";
+            let text = Register::new(text, RegisterKind::Linewise);
+            editor.record_register(register, text, operator == 'y');
+            match operator {
+                'y' => {
+                    if first != editor.cursor.row {
+                        editor.goto_position(first, editor.cursor.col);
+                    }
+                    if lines > 2 {
+                        editor.set_status(format!("{} lines yanked", lines));
+                    }
+                }
+                'c' => {
+                    let indent_len = editor.buffer.lines[first]
+                        .chars()
+                        .take_while(|ch| ch.is_whitespace())
+                        .count();
+                    let indent: String = editor.buffer.lines[first]
+                        .chars()
+                        .take(indent_len)
+                        .collect();
+                    let end = Cursor {
+                        row: last,
+                        col: editor.buffer.lines[last].chars().count(),
+                    };
+                    let indent = if editor.options.autoindent {
+                        indent
+                    } else {
+                        String::new()
+                    };
+                    editor.replace_range(Cursor { row: first, col: 0 }, end, &indent);
+                    editor.move_line_end();
+                    editor.mode = Mode::Insert;
+                }
+                _ => {
+                    editor.delete_lines(first, last);
+                    let col = Motion::FirstNonBlank
+                        .target(&editor.buffer.lines, editor.cursor)
+                        .col;
+                    editor.goto_position(editor.cursor.row, col);
+                    if lines > 2 {
+                        editor.set_status(format!("{} fewer lines", lines));
+                    }
+                }
+            }
+            return;
+        }
+        let text = if operator == 'y' {
+            let text = editor.text_in_range(range.start, range.end);
+            editor.goto_position(range.start.row, range.start.col);
+            text
+        } else {
+            editor.delete_range(range.start, range.end)
+        };
+        let text = Register::new(text, RegisterKind::Charwise);
+        editor.record_register(register, text, operator == 'y');
+        if operator == 'c' {
+            editor.mode = Mode::Insert;
+        }
+    }
+
+    /// The range `keys` after operator `operator` covers, `Err(true)` while
+    /// more keys are needed and `Err(false)` if the keys are invalid.
+    fn range(editor: &Editor, operator: char, keys: &str) -> Result<Option<TextRange>, bool> {
+        let cursor = editor.cursor;
+        if keys.len() == 1 && keys.starts_with(operator) {
+            return Ok(Some(TextRange {
+                start: cursor,
+                end: cursor,
+                linewise: true,
+            }));
+        }
+        let target = match motion::parse_target(keys) {
+            Parse::Done(target) => target,
+            Parse::Pending => return Err(true),
+            Parse::Invalid => return Err(false),
+        };
+        // Like Vim, `cw` on a word changes only to the end of the word.
+        let on_word = editor
+            .current_line()
+            .chars()
+            .nth(cursor.col)
+            .is_some_and(|ch| !ch.is_whitespace());
+        if operator == 'c'
+            && on_word
+            && let motion::Target::Motion(Motion::WordForward { big }) = target
+        {
+            let object = motion::TextObject::Word { big, around: false };
+            return Ok(object
+                .range(&editor.buffer.lines, cursor)
+                .map(|word| TextRange {
+                    start: cursor,
+                    ..word
+                }));
+        }
+        Ok(target.range(&editor.buffer.lines, cursor))
+    }
+
+    /// Puts the pending register's text after (`p`) or before (`P`) the
+    /// cursor; linewise text goes below or above the cursor line, shifted
+    /// to the cursor line's indent if `reindent` (`]p` and `[p`).
+    fn put(editor: &mut Editor, before: bool, reindent: bool) {
+        let name = editor.pending_register.take().unwrap_or('"');
+        let register = match editor.register(name) {
+            Ok(Some(register)) => register,
+            Ok(None) => {
+                editor.set_error(format!("Nothing in register {}", name));
+                return;
+            }
+            Err(err) => {
+                editor.set_error(err);
+                return;
+            }
+        };
+        if editor.is_loading() {
+            editor.set_error("Cannot edit while the file is still loading");
+            return;
+        }
+        let row = editor.cursor.row;
+        let text = register.text;
+        if register.kind == RegisterKind::Linewise {
+            let lines = text.strip_suffix('\n').unwrap_or(&text);
+            let target = editor.indent_width(row);
+            let (at, inserted, first_row) = if before {
+                (Cursor { row, col: 0 }, format!("{}\n", lines), row)
+            } else {
+                let end = Cursor {
+                    row,
+                    col: editor.current_line_len(),
+                };
+                (end, format!("\n{}", lines), row + 1)
+            };
+            editor.begin_batch();
+            editor.replace_range(at, at, &inserted);
+            if reindent {
+                Self::shift_indent(editor, first_row, lines.split('\n').count(), target);
+            }
+            editor.end_batch();
+            let col = Motion::FirstNonBlank
+                .target(
+                    &editor.buffer.lines,
+                    Cursor {
+                        row: first_row,
+                        col: 0,
+                    },
+                )
+                .col;
+            editor.goto_position(first_row, col);
+            return;
+        }
+        let col = if before {
+            editor.cursor.col
+        } else {
+            next_grapheme_boundary(editor.current_line(), editor.cursor.col)
+        };
+        let at = Cursor { row, col };
+        editor.replace_range(at, at, &text);
+        if !text.contains('\n') {
+            let len = text.chars().count();
+            editor.goto_position(row, col + len.saturating_sub(1));
+        }
+    }
+
+    /// Shifts the `count` lines from `first` so the first of them that is
+    /// not blank has an indent `target` columns wide, keeping the indents
+    /// of the others relative to it.
+    fn shift_indent(editor: &mut Editor, first: usize, count: usize, target: usize) {
+        let rows = first..first + count;
+        let Some(base) = rows
+            .clone()
+            .find(|&row| !editor.buffer.lines[row].trim().is_empty())
+            .map(|row| editor.indent_width(row))
+        else {
+            return;
+        };
+        for row in rows {
+            if !editor.buffer.lines[row].trim().is_empty() {
+                let width = (editor.indent_width(row) + target).saturating_sub(base);
+                editor.set_indent(row, width);
+            }
+        }
+    }
+}
+
+impl Plugin for OperatorPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (name, args) = split_command(command);
+        if !matches!(name, "registers" | "reg" | "display" | "di") {
+            return EventResult::Ignored;
+        }
+        let wanted: Vec<char> = args.chars().filter(|ch| !ch.is_whitespace()).collect();
+        let lines: Vec<String> = editor
+            .registers
+            .iter()
+            .filter(|(name, _)| wanted.is_empty() || wanted.contains(name))
+            .map(|(name, register)| {
+                let kind = if register.is_linewise() { 'l' } else { 'c' };
+                format!("{}  \"{}   {}", kind, name, caret_notation(&register.text))
+            })
+            .collect();
+        if lines.is_empty() {
+            editor.set_status("No registers to show");
+        } else {
+            editor.open_scratch("registers", lines);
+        }
+        EventResult::Consumed
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let KeyCode::Char(ch) = key.code else {
+            return EventResult::Ignored;
+        };
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return EventResult::Ignored;
+        }
+
+        let pending = editor.pending_keys.clone();
+        let Some(operator) = pending.chars().next() else {
+            let line_end = |editor: &Editor| TextRange {
+                start: editor.cursor,
+                end: Cursor {
+                    col: editor.current_line_len(),
+                    ..editor.cursor
+                },
+                linewise: false,
+            };
+            match ch {
+                '"' | 'd' | 'c' | 'y' => editor.pending_keys.push(ch),
+                'p' | 'P' => Self::put(editor, ch == 'P', false),
+                'x' => {
+                    let start = editor.cursor;
+                    let end = Cursor {
+                        col: next_grapheme_boundary(editor.current_line(), start.col),
+                        ..start
+                    };
+                    if end.col > start.col {
+                        let range = TextRange {
+                            start,
+                            end,
+                            linewise: false,
+                        };
+                        Self::apply(editor, 'd', range);
+                    }
+                }
+                'D' => Self::apply(editor, 'd', line_end(editor)),
+                'C' => Self::apply(editor, 'c', line_end(editor)),
+                'Y' => {
+                    let cursor = editor.cursor;
+                    let range = TextRange {
+                        start: cursor,
+                        end: cursor,
+                        linewise: true,
+                    };
+                    Self::apply(editor, 'y', range);
+                }
+                _ => return EventResult::Ignored,
+            }
+            return EventResult::Consumed;
+        };
+
+        if matches!(operator, ']' | '[') && pending.len() == 1 && matches!(ch, 'p' | 'P') {
+            editor.pending_keys.clear();
+            Self::put(editor, operator == '[' || ch == 'P', true);
+            return EventResult::Consumed;
+        }
+        if operator == '"' {
+            editor.pending_keys.clear();
+            if Registers::is_valid(ch) {
+                editor.pending_register = Some(ch);
+            } else {
+                editor.set_error(format!("Invalid register name: {}", ch));
+            }
+            return EventResult::Consumed;
+        }
+        if !matches!(operator, 'd' | 'c' | 'y') {
+            return EventResult::Ignored;
+        }
+        let keys = format!("{}{}", &pending[1..], ch);
+        match Self::range(editor, operator, &keys) {
+            Ok(range) => {
+                editor.pending_keys.clear();
+                if let Some(range) = range {
+                    Self::apply(editor, operator, range);
+                }
+            }
+            Err(true) => editor.pending_keys.push(ch),
+            Err(false) => editor.pending_keys.clear(),
+        }
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "registers",
+            "[names]",
+            "List register contents",
+        )];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "x", "Delete the character under the cursor"),
+            KeyInfo::new(
+                Mode::Normal,
+                "d{motion}",
+                "Delete over a motion or text object",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "c{motion}",
+                "Change over a motion or text object",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "y{motion}",
+                "Yank over a motion or text object",
+            ),
+            KeyInfo::new(Mode::Normal, "dd", "Delete the line"),
+            KeyInfo::new(Mode::Normal, "cc", "Change the line"),
+            KeyInfo::new(Mode::Normal, "yy", "Yank the line"),
+            KeyInfo::new(Mode::Normal, "D", "Delete to the end of the line"),
+            KeyInfo::new(Mode::Normal, "C", "Change to the end of the line"),
+            KeyInfo::new(Mode::Normal, "Y", "Yank the line"),
+            KeyInfo::new(Mode::Normal, "p", "Put after the cursor"),
+            KeyInfo::new(Mode::Normal, "P", "Put before the cursor"),
+            KeyInfo::new(
+                Mode::Normal,
+                "]p",
+                "Put lines below, at the cursor line's indent",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "[p",
+                "Put lines above, at the cursor line's indent",
+            ),
+            KeyInfo::new(
+                Mode::Normal,
+                "\"{register}",
+                "Use a register for the next yank, delete or put",
+            ),
+        ];
+        KEYS
+    }
+}
+
+/// Lines moved per scroll-wheel notch.
+const MOUSE_SCROLL_LINES: usize = 3;
+
+/// Clicks move the cursor, drags select in Visual mode and the wheel
+/// scrolls. Clicks outside the text area are left for other plugins.
+pub struct MousePlugin;
+
+impl MousePlugin {
+    /// Buffer position under a screen cell, if it is in the text area.
+    fn buffer_position(editor: &Editor, column: u16, row: u16) -> Option<(usize, usize)> {
+        if row >= editor.content_height() || editor.scratch.is_some() {
+            return None;
+        }
+        let buffer_row = editor.viewport.row_offset + row as usize;
+        let line = editor
+            .buffer
+            .lines
+            .get(buffer_row)
+            .map_or("", String::as_str);
+        let column = (column as usize).saturating_sub(editor.sign_column_width());
+        let col = char_col_at(
+            line,
+            editor.viewport.col_offset + column,
+            editor.options.tabstop,
+        );
+        Some((buffer_row, col))
+    }
+}
+
+impl Plugin for MousePlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        let Event::Mouse(mouse) = event else {
+            return EventResult::Ignored;
+        };
+        if editor.mode == Mode::Command {
+            return EventResult::Ignored;
+        }
+        match mouse.kind {
+            MouseEventKind::ScrollDown => {
+                editor.scroll_viewport(MOUSE_SCROLL_LINES as isize);
+                EventResult::Consumed
+            }
+            MouseEventKind::ScrollUp => {
+                editor.scroll_viewport(-(MOUSE_SCROLL_LINES as isize));
+                EventResult::Consumed
+            }
+            MouseEventKind::Down(MouseButton::Left) => {
+                let Some((row, col)) = Self::buffer_position(editor, mouse.column, mouse.row)
+                else {
+                    return EventResult::Ignored;
+                };
+                editor.pending_keys.clear();
+                editor.stop_visual();
+                editor.goto_position(row, col);
+                EventResult::Consumed
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                let Some((row, col)) = Self::buffer_position(editor, mouse.column, mouse.row)
+                else {
+                    return EventResult::Ignored;
+                };
+                if editor.mode == Mode::Normal {
+                    editor.start_visual();
+                }
+                if editor.mode == Mode::Visual {
+                    editor.goto_position(row, col);
+                }
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+}
+
+/// Auto-closes brackets and quotes in Insert mode when `autopairs` is set.
+pub struct AutoPairsPlugin;
+
+impl AutoPairsPlugin {
+    const PAIRS: [(char, char); 6] = [
+        ('(', ')'),
+        ('[', ']'),
+        ('{', '}'),
+        ('"', '"'),
+        ('\'', '\''),
+        ('`', '`'),
+    ];
+
+    fn closer_for(open: char) -> Option<char> {
+        Self::PAIRS
+            .iter()
+            .find(|(candidate, _)| *candidate == open)
+            .map(|(_, close)| *close)
+    }
+
+    fn is_closer(ch: char) -> bool {
+        Self::PAIRS.iter().any(|(_, close)| *close == ch)
+    }
+
+    /// Characters before and after the cursor.
+    fn neighbors(editor: &Editor) -> (Option<char>, Option<char>) {
+        let line = editor.current_line();
+        let col = editor.cursor.col;
+        let before = col.checked_sub(1).and_then(|prev| line.chars().nth(prev));
+        (before, line.chars().nth(col))
+    }
+
+    fn type_char(editor: &mut Editor, ch: char) -> bool {
+        let (before, after) = Self::neighbors(editor);
+        if Self::is_closer(ch) && after == Some(ch) {
+            editor.move_right();
+            return true;
+        }
+        let Some(close) = Self::closer_for(ch) else {
+            return false;
+        };
+        // Quotes after a word are apostrophes or string prefixes; a
+        // backslash escapes the character.
+        let is_quote = ch == close;
+        if (is_quote && before.is_some_and(is_word_char)) || before == Some('\\') {
+            return false;
+        }
+        // Don't pair in front of a word, so wrapping existing text works.
+        if after.is_some_and(is_word_char) {
+            return false;
+        }
+        editor.begin_batch();
+        editor.insert_char(ch);
+        editor.insert_char(close);
+        editor.move_left();
+        editor.end_batch();
+        true
+    }
+
+    fn between_pair(editor: &Editor) -> Option<(char, char)> {
+        let (Some(before), Some(after)) = Self::neighbors(editor) else {
+            return None;
+        };
+        (Self::closer_for(before) == Some(after)).then_some((before, after))
+    }
+}
+
+impl Plugin for AutoPairsPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Insert || !editor.options.autopairs {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if key
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+        {
+            return EventResult::Ignored;
+        }
+        let handled = match key.code {
+            KeyCode::Char(ch) => Self::type_char(editor, ch),
+            KeyCode::Backspace if Self::between_pair(editor).is_some() => {
+                editor.begin_batch();
+                editor.delete_char();
+                editor.backspace();
+                editor.end_batch();
+                true
+            }
+            KeyCode::Enter if matches!(Self::between_pair(editor), Some(('{' | '[' | '(', _))) => {
+                // Put the closer on its own line and the cursor on an
+                // indented line between.
+                editor.begin_batch();
+                editor.insert_newline_indented();
+                editor.open_line(false);
+                editor.end_batch();
+                true
+            }
+            _ => false,
+        };
+        if handled {
+            EventResult::Consumed
+        } else {
+            EventResult::Ignored
+        }
+    }
+}
+
+pub struct InsertPlugin {
+    /// Word characters typed consecutively, ending at `word_end`.
+    typed_word: String,
+    word_end: Option<Cursor>,
+}
+
+impl InsertPlugin {
+    pub fn new() -> Self {
+        Self {
+            typed_word: String::new(),
+            word_end: None,
+        }
+    }
+
+    /// Replaces the just-typed word with its abbreviation, if it has one and
+    /// starts at a word boundary.
+    fn expand_abbreviation(&mut self, editor: &mut Editor) {
+        let word = std::mem::take(&mut self.typed_word);
+        if self.word_end.take() != Some(editor.cursor) {
+            return;
+        }
+        let Some(expansion) = editor.abbreviations.get(&word).cloned() else {
+            return;
+        };
+        let word_len = word.chars().count();
+        let start = editor.cursor.col - word_len;
+        let preceded_by_word = start > 0
+            && editor.buffer.lines[editor.cursor.row]
+                .chars()
+                .nth(start - 1)
+                .is_some_and(is_word_char);
+        if preceded_by_word {
+            return;
+        }
+        let row = editor.cursor.row;
+        editor.begin_batch();
+        editor.delete_range(Cursor { row, col: start }, editor.cursor);
+        editor.insert_text(&expansion);
+        editor.end_batch();
+    }
+
+    /// Inserts `ch` and, while the first word of a line is typed, re-indents
+    /// the line when it starts or stops being a block closer like `}`.
+    fn insert_reindenting(editor: &mut Editor, ch: char) {
+        let first_word = editor.options.smartindent
+            && editor
+                .current_line()
+                .chars()
+                .take(editor.cursor.col)
+                .skip_while(|ch| ch.is_whitespace())
+                .all(|ch| !ch.is_whitespace());
+        let closed_before = first_word && editor.current_line_closes_block();
+        editor.begin_batch();
+        editor.insert_char(ch);
+        if first_word && editor.current_line_closes_block() != closed_before {
+            editor.reindent_line(editor.cursor.row);
+        }
+        editor.end_batch();
+    }
+
+    /// Updates the typed-word state after `ch` is inserted.
+    fn track_char(&mut self, editor: &Editor, ch: char) {
+        if self.word_end != Some(editor.cursor) {
+            self.typed_word.clear();
+        }
+        self.typed_word.push(ch);
+    }
+}
+
+impl Plugin for InsertPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Insert {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+
+        if key.modifiers.contains(KeyModifiers::CONTROL)
+            || key.modifiers.contains(KeyModifiers::ALT)
+        {
+            return EventResult::Ignored;
+        }
+
+        match key.code {
+            KeyCode::Char(ch) if is_word_char(ch) => {
+                self.track_char(editor, ch);
+                Self::insert_reindenting(editor, ch);
+                self.word_end = Some(editor.cursor);
+                EventResult::Consumed
+            }
+            KeyCode::Char(ch) => {
+                self.expand_abbreviation(editor);
+                Self::insert_reindenting(editor, ch);
+                EventResult::Consumed
+            }
+            KeyCode::Enter => {
+                self.expand_abbreviation(editor);
+                editor.insert_newline_indented();
+                EventResult::Consumed
+            }
+            KeyCode::Backspace => {
+                editor.backspace_soft_tab();
+                if self.typed_word.pop().is_some() && !self.typed_word.is_empty() {
+                    self.word_end = Some(editor.cursor);
+                } else {
+                    self.word_end = None;
+                }
+                EventResult::Consumed
+            }
+            KeyCode::Delete => {
+                editor.delete_char();
+                EventResult::Consumed
+            }
+            KeyCode::Tab => {
+                self.expand_abbreviation(editor);
+                editor.insert_tab();
+                EventResult::Consumed
+            }
+            KeyCode::Left => {
+                editor.move_left();
+                EventResult::Consumed
+            }
+            KeyCode::Right => {
+                editor.move_right();
+                EventResult::Consumed
+            }
+            KeyCode::Up => {
+                editor.move_up();
+                EventResult::Consumed
+            }
+            KeyCode::Down => {
+                editor.move_down();
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Insert,
+            "<Tab>",
+            "Indent to the next softtabstop column",
+        )];
+        KEYS
+    }
+}
+
+/// Completes the word before the cursor from buffer words with Ctrl-N /
+/// Ctrl-P, from the `dictionary` files with Ctrl-X Ctrl-K and from the
+/// `thesaurus` files with Ctrl-X Ctrl-T.
+pub struct CompletionPlugin {
+    session: Option<CompletionSession>,
+    /// Whether Ctrl-X was pressed, waiting for the key that says where to
+    /// complete from.
+    ctrl_x: bool,
+}
+
+/// Where the candidates of a completion come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompletionSource {
+    Buffer,
+    Dictionary,
+    Thesaurus,
+}
+
+/// The word being completed; its candidates are in `Editor::menu`.
+struct CompletionSession {
+    row: usize,
+    start: usize,
+    prefix: String,
+}
+
+impl CompletionPlugin {
+    pub fn new() -> Self {
+        Self {
+            session: None,
+            ctrl_x: false,
+        }
+    }
+
+    /// Words starting with `prefix` in the order a forward search from the
+    /// cursor meets them, wrapping at the end of the buffer. Ctrl-P walks the
+    /// same list from the back, so the nearest earlier word comes first.
+    fn candidates(editor: &Editor, start: usize, prefix: &str) -> Vec<String> {
+        let row = editor.cursor.row;
+        let lines = &editor.buffer.lines;
+        let words_in = |line: &str| -> Vec<(usize, String)> {
+            let mut words = Vec::new();
+            let mut current = String::new();
+            let mut word_start = 0;
+            for (col, ch) in line.chars().chain(std::iter::once(' ')).enumerate() {
+                if is_word_char(ch) {
+                    if current.is_empty() {
+                        word_start = col;
+                    }
+                    current.push(ch);
+                } else if !current.is_empty() {
+                    words.push((word_start, std::mem::take(&mut current)));
+                }
+            }
+            words
+        };
+
+        let mut ordered = Vec::new();
+        let current_words = words_in(&lines[row]);
+        ordered.extend(
+            current_words
+                .iter()
+                .filter(|(col, _)| *col > start)
+                .cloned(),
+        );
+        for offset in 1..lines.len() {
+            let other = (row + offset) % lines.len();
+            ordered.extend(words_in(&lines[other]));
+        }
+        ordered.extend(
+            current_words
+                .iter()
+                .filter(|(col, _)| *col < start)
+                .cloned(),
+        );
+
+        let mut candidates: Vec<String> = Vec::new();
+        for (_, word) in ordered {
+            if word.len() > prefix.len() && word.starts_with(prefix) && !candidates.contains(&word)
+            {
+                candidates.push(word);
+            }
+        }
+        candidates
+    }
+
+    /// The candidates `source` offers for `prefix`, and the kind they are
+    /// shown as.
+    fn source_candidates(
+        editor: &mut Editor,
+        source: CompletionSource,
+        start: usize,
+        prefix: &str,
+    ) -> Result<(Vec<String>, ItemKind), String> {
+        match source {
+            CompletionSource::Buffer => {
+                Ok((Self::candidates(editor, start, prefix), ItemKind::Word))
+            }
+            CompletionSource::Dictionary => {
+                if editor.options.dictionary.is_empty() {
+                    return Err("'dictionary' option is empty".to_string());
+                }
+                editor.load_dictionary()?;
+                let words = if prefix.is_empty() {
+                    Vec::new()
+                } else {
+                    editor.dictionary.completions(prefix)
+                };
+                Ok((words, ItemKind::Dictionary))
+            }
+            CompletionSource::Thesaurus => {
+                if editor.options.thesaurus.is_empty() {
+                    return Err("'thesaurus' option is empty".to_string());
+                }
+                let words = if prefix.is_empty() {
+                    Vec::new()
+                } else {
+                    spell::related_words(&editor.options.thesaurus, prefix)?
+                };
+                Ok((words, ItemKind::Thesaurus))
+            }
+        }
+    }
+
+    /// Starts completing the word before the cursor from `source`, opening
+    /// the menu if there is anything to offer.
+    fn start(&mut self, editor: &mut Editor, source: CompletionSource) -> bool {
+        let row = editor.cursor.row;
+        let line: Vec<char> = editor.buffer.lines[row].chars().collect();
+        let mut start = editor.cursor.col.min(line.len());
+        while start > 0 && is_word_char(line[start - 1]) {
+            start -= 1;
+        }
+        let prefix: String = line[start..editor.cursor.col].iter().collect();
+        let (words, kind) = match Self::source_candidates(editor, source, start, &prefix) {
+            Ok(found) => found,
+            Err(err) => {
+                editor.set_error(err);
+                return false;
+            }
+        };
+        let items: Vec<MenuItem> = words
+            .into_iter()
+            .map(|text| MenuItem { text, kind })
+            .collect();
+        if items.is_empty() {
+            editor.set_error("Pattern not found");
+            return false;
+        }
+        editor.menu = Some(Menu::new(items, Cursor { row, col: start }));
+        self.session = Some(CompletionSession { row, start, prefix });
+        true
+    }
+
+    /// Replaces the word being completed with the selected item, or with
+    /// what was typed when nothing is selected.
+    fn show_selection(&self, editor: &mut Editor) {
+        let (Some(session), Some(menu)) = (self.session.as_ref(), editor.menu.as_ref()) else {
+            return;
+        };
+        let (text, status) = match menu.selected {
+            Some(index) => (
+                menu.items[index].text.clone(),
+                format!("match {} of {}", index + 1, menu.items.len()),
+            ),
+            None => (session.prefix.clone(), "Back at original".to_string()),
+        };
+        if editor.cursor.row != session.row {
+            return;
+        }
+        let start = Cursor {
+            row: session.row,
+            col: session.start,
+        };
+        editor.begin_batch();
+        editor.delete_range(start, editor.cursor);
+        editor.insert_text(&text);
+        editor.end_batch();
+        editor.set_status(status);
+    }
+
+    /// Starts completing from `source` and selects the first candidate, or
+    /// the last going backward.
+    fn open(&mut self, editor: &mut Editor, source: CompletionSource, forward: bool) {
+        if self.start(editor, source)
+            && let Some(menu) = editor.menu.as_mut()
+        {
+            menu.step(forward);
+            self.show_selection(editor);
+        }
+    }
+
+    fn close(&mut self, editor: &mut Editor) {
+        self.session = None;
+        self.ctrl_x = false;
+        editor.menu = None;
+    }
+}
+
+impl Plugin for CompletionPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Insert {
+            self.close(editor);
+            return EventResult::Ignored;
+        }
+        let key = match event {
+            Event::Key(key) => key,
+            Event::Mouse(_) => {
+                self.close(editor);
+                return EventResult::Ignored;
+            }
+            _ => return EventResult::Ignored,
+        };
+        if let Some(menu) = editor.menu.as_mut() {
+            match menu.handle_key(key) {
+                MenuAction::Moved(_) => self.show_selection(editor),
+                MenuAction::Accept(Some(_)) => self.close(editor),
+                MenuAction::Cancel => {
+                    menu.selected = None;
+                    self.show_selection(editor);
+                    self.close(editor);
+                }
+                MenuAction::Accept(None) | MenuAction::Other => {
+                    self.close(editor);
+                    return EventResult::Ignored;
+                }
+            }
+            return EventResult::Consumed;
+        }
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        if std::mem::take(&mut self.ctrl_x) {
+            let source = match key.code {
+                KeyCode::Char('k') if control => CompletionSource::Dictionary,
+                KeyCode::Char('t') if control => CompletionSource::Thesaurus,
+                KeyCode::Char('n' | 'p') if control => CompletionSource::Buffer,
+                _ => {
+                    editor.set_status("");
+                    return EventResult::Ignored;
+                }
+            };
+            let forward = key.code != KeyCode::Char('p');
+            self.open(editor, source, forward);
+            return EventResult::Consumed;
+        }
+        if !control {
+            return EventResult::Ignored;
+        }
+        match key.code {
+            KeyCode::Char('n') => self.open(editor, CompletionSource::Buffer, true),
+            KeyCode::Char('p') => self.open(editor, CompletionSource::Buffer, false),
+            KeyCode::Char('x') => {
+                self.ctrl_x = true;
+                editor.set_status("-- ^X mode (^K dictionary, ^T thesaurus, ^N/^P buffer)");
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(
+                Mode::Insert,
+                "<C-n>",
+                "Complete the word from the buffer, next match",
+            ),
+            KeyInfo::new(
+                Mode::Insert,
+                "<C-p>",
+                "Complete the word from the buffer, previous match",
+            ),
+            KeyInfo::new(
+                Mode::Insert,
+                "<C-x><C-k>",
+                "Complete the word from the dictionary files",
+            ),
+            KeyInfo::new(
+                Mode::Insert,
+                "<C-x><C-t>",
+                "Complete the word with related words from the thesaurus files",
+            ),
+        ];
+        KEYS
+    }
+}
+
+/// Draws the completion menu under the word being completed, or above it
+/// when there is more room there.
+pub struct MenuRenderPlugin;
+
+impl Plugin for MenuRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        let Some(menu) = editor.menu.as_ref() else {
+            return;
+        };
+        if editor.mode != Mode::Insert || menu.items.is_empty() {
+            return;
+        }
+        let Some(anchor_row) = menu.anchor.row.checked_sub(editor.viewport.row_offset) else {
+            return;
+        };
+        let content_height = editor.content_height() as usize;
+        let below = content_height.saturating_sub(anchor_row + 1);
+        let above = anchor_row.min(content_height);
+        let wanted = menu.items.len().min(MENU_MAX_HEIGHT);
+        let (top, height) = if wanted <= below || below >= above {
+            (anchor_row + 1, wanted.min(below))
+        } else {
+            (anchor_row - wanted.min(above), wanted.min(above))
+        };
+        if height == 0 {
+            return;
+        }
+        let line = editor
+            .buffer
+            .lines
+            .get(menu.anchor.row)
+            .map_or("", String::as_str);
+        let anchor_col = display_col(line, menu.anchor.col, editor.options.tabstop)
+            .saturating_sub(editor.viewport.col_offset)
+            + editor.sign_column_width();
+        // A space, the kind letter, a space, the text and a space.
+        let text_width = menu
+            .items
+            .iter()
+            .map(|item| display_width(&item.text))
+            .max()
+            .unwrap_or(0)
+            .min((ctx.width as usize).saturating_sub(4));
+        let width = text_width + 4;
+        let left = anchor_col.min((ctx.width as usize).saturating_sub(width));
+        let first = menu.first_visible(height);
+        for (offset, index) in (first..first + height).enumerate() {
+            let item = &menu.items[index];
+            let group = if menu.selected == Some(index) {
+                HighlightGroup::PmenuSel
+            } else {
+                HighlightGroup::Pmenu
+            };
+            let style = editor.theme.style(group);
+            let text: String = item
+                .text
+                .graphemes(true)
+                .scan(0, |used, grapheme| {
+                    *used += grapheme_width(grapheme);
+                    (*used <= text_width).then_some(grapheme)
+                })
+                .collect();
+            let padding = text_width - display_width(&text);
+            let row = (top + offset) as u16;
+            let entry = format!(" {} {}{} ", item.kind.icon(), text, " ".repeat(padding));
+            ctx.draw_text(row, left, &entry, style);
+            let kind = editor.theme.style(HighlightGroup::PmenuKind);
+            ctx.add_span(
+                row,
+                StyledSpan {
+                    start: left + 1,
+                    len: 1,
+                    style: ContentStyle {
+                        background_color: kind.background_color.or(style.background_color),
+                        ..kind
+                    },
+                },
+            );
+        }
+    }
+}
+
+/// `Ctrl-R {register}` inserts a register's text at the cursor in Insert
+/// mode, or its first line on the command line.
+pub struct RegisterInsertPlugin {
+    /// Whether Ctrl-R was pressed and the register name is next.
+    pending: bool,
+}
+
+impl RegisterInsertPlugin {
+    pub fn new() -> Self {
+        Self { pending: false }
+    }
+
+    fn insert(editor: &mut Editor, name: char) {
+        let text = match editor.register(name) {
+            Ok(Some(register)) => register.text,
+            Ok(None) => return,
+            Err(err) => {
+                editor.set_error(err);
+                return;
+            }
+        };
+        if editor.mode == Mode::Command {
+            let first_line = text.lines().next().unwrap_or_default();
+            editor.command_line.insert_str(first_line);
+        } else if editor.is_loading() {
+            editor.set_error("Cannot edit while the file is still loading");
+        } else {
+            editor.insert_text(&text);
+        }
+    }
+}
+
+impl Plugin for RegisterInsertPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if !matches!(editor.mode, Mode::Insert | Mode::Command) {
+            self.pending = false;
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        if !std::mem::take(&mut self.pending) {
+            if key.code == KeyCode::Char('r') && control {
+                self.pending = true;
+                return EventResult::Consumed;
+            }
+            return EventResult::Ignored;
+        }
+        let KeyCode::Char(name) = key.code else {
+            return EventResult::Ignored;
+        };
+        if Registers::is_valid(name) && !control {
+            Self::insert(editor, name);
+        }
+        EventResult::Consumed
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(
+                Mode::Insert,
+                "<C-r>{register}",
+                "Insert the text of a register",
+            ),
+            KeyInfo::new(
+                Mode::Command,
+                "<C-r>{register}",
+                "Insert the text of a register, up to its line break",
+            ),
+        ];
+        KEYS
+    }
+}
+
+/// Inserts RFC 1345 digraphs typed as `Ctrl-K {char1}{char2}` in Insert mode.
+pub struct DigraphPlugin {
+    /// `Some` after Ctrl-K, holding the first character once typed.
+    pending: Option<Option<char>>,
+}
+
+impl DigraphPlugin {
+    const PER_ROW: usize = 8;
+
+    pub fn new() -> Self {
+        Self { pending: None }
+    }
+
+    fn listing() -> Vec<String> {
+        digraphs::DIGRAPHS
+            .chunks(Self::PER_ROW)
+            .map(|row| {
+                row.iter()
+                    .map(|(keys, ch)| format!("{} {}", keys, ch))
+                    .collect::<Vec<_>>()
+                    .join("   ")
+            })
+            .collect()
+    }
+}
+
+impl Plugin for DigraphPlugin {
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Insert {
+            self.pending = None;
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+
+        let Some(first) = self.pending else {
+            if key.code == KeyCode::Char('k') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                self.pending = Some(None);
+                return EventResult::Consumed;
+            }
+            return EventResult::Ignored;
+        };
+        let KeyCode::Char(ch) = key.code else {
+            self.pending = None;
+            return EventResult::Ignored;
+        };
+        match first {
+            None => self.pending = Some(Some(ch)),
+            Some(first) => {
+                self.pending = None;
+                // Like vim, an unknown digraph inserts its second character.
+                editor.insert_char(digraphs::lookup(first, ch).unwrap_or(ch));
+            }
+        }
+        EventResult::Consumed
+    }
+
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command).0 {
+            "digraphs" | "digraph" | "dig" => {
+                editor.open_scratch("digraphs", Self::listing());
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "digraphs",
+            "",
+            "List the available digraphs",
+        )];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[KeyInfo::new(
+            Mode::Insert,
+            "<C-k>{char1}{char2}",
+            "Insert a digraph",
+        )];
+        KEYS
+    }
+}
+
+pub struct AbbreviationPlugin;
+
+impl AbbreviationPlugin {
+    fn list(editor: &mut Editor, prefix: &str) {
+        let matching: Vec<String> = editor
+            .abbreviations
+            .iter()
+            .filter(|(lhs, _)| lhs.starts_with(prefix))
+            .map(|(lhs, rhs)| format!("i  {}  {}", lhs, rhs))
+            .collect();
+        match matching.len() {
+            0 => editor.set_status("No abbreviation found"),
+            1 => editor.set_status(matching[0].clone()),
+            _ => editor.open_scratch("abbreviations", matching),
+        }
+    }
+}
+
+impl Plugin for AbbreviationPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        let (verb, args) = split_command(command);
+        match verb {
+            "iabbrev" | "iabbr" | "iab" => {
+                match args.split_once(char::is_whitespace) {
+                    Some((lhs, rhs)) => {
+                        editor
+                            .abbreviations
+                            .insert(lhs.to_string(), rhs.trim().to_string());
+                    }
+                    None => Self::list(editor, args),
+                }
+                EventResult::Consumed
+            }
+            "iunabbrev" | "iunabbr" | "iuna" => {
+                if editor.abbreviations.remove(args).is_none() {
+                    editor.set_error(format!("No such abbreviation: {}", args));
+                }
+                EventResult::Consumed
+            }
+            "iabclear" | "iabc" => {
+                editor.abbreviations.clear();
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[
+            CommandInfo::new(
+                "iabbrev",
+                "[lhs] [rhs]",
+                "Add an Insert-mode abbreviation, or list them",
+            ),
+            CommandInfo::new("iunabbrev", "{lhs}", "Remove an Insert-mode abbreviation"),
+            CommandInfo::new("iabclear", "", "Remove all Insert-mode abbreviations"),
+        ];
+        COMMANDS
+    }
+}
+
+pub struct BufferRenderPlugin;
+
+impl Plugin for BufferRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if editor.scratch.is_some() {
+            return;
+        }
+        let content_height = editor.content_height();
+        let width = ctx.width as usize;
+        for row in 0..content_height {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            if buffer_row < editor.buffer.lines.len() {
+                let line = &editor.buffer.lines[buffer_row];
+                let slice = expand_line(
+                    line,
+                    editor.viewport.col_offset,
+                    width,
+                    editor.options.tabstop,
+                );
+                ctx.set_line(row, slice);
+            } else {
+                ctx.set_line(row, "~".to_string());
+                ctx.add_span(
+                    row,
+                    StyledSpan {
+                        start: 0,
+                        len: 1,
+                        style: editor.theme.style(HighlightGroup::EndOfBuffer),
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Highlights the buffer once syntect's assets, loaded in the background,
+/// arrive; until then the text is drawn plain.
+pub struct SyntaxHighlightPlugin {
+    assets: Option<Receiver<Assets>>,
+    cache: Option<SyntaxCache>,
+    last_revision: u64,
+}
+
+impl SyntaxHighlightPlugin {
+    pub fn new() -> Self {
+        Self {
+            assets: None,
+            cache: None,
+            last_revision: u64::MAX,
+        }
+    }
+
+    /// Takes the assets if they have been loaded.
+    fn receive_assets(&mut self) {
+        let Some(assets) = &self.assets else {
+            return;
+        };
+        match assets.try_recv() {
+            Ok(assets) => {
+                self.cache = Some(SyntaxCache::new(assets));
+                self.assets = None;
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.assets = None,
+        }
+    }
+
+    /// Brings the cache up to date with the buffer. Returns it, or `None`
+    /// if there is nothing to highlight.
+    fn sync(&mut self, editor: &Editor) -> Option<&mut SyntaxCache> {
+        if editor.scratch.is_some() || editor.is_loading() {
+            return None;
+        }
+        let cache = self.cache.as_mut()?;
+        cache.configure(editor.file_path.as_deref(), editor.options.tabstop);
+        if editor.revision() != self.last_revision {
+            match editor.deltas.since(self.last_revision) {
+                Some(deltas) => {
+                    if let Some(row) = deltas.map(|delta| delta.start.row).min() {
+                        cache.invalidate_from(row);
+                    }
+                }
+                None => cache.invalidate(&editor.buffer.lines),
+            }
+            self.last_revision = editor.revision();
+        }
+        Some(cache)
+    }
+
+    fn slice_spans(spans: &[StyledSpan], col_offset: usize, width: usize) -> Vec<StyledSpan> {
+        if width == 0 {
+            return Vec::new();
+        }
+        let end = col_offset.saturating_add(width);
+        let mut visible = Vec::new();
+        for span in spans {
+            let span_start = span.start;
+            let span_end = span.start + span.len;
+            if span_end <= col_offset || span_start >= end {
+                continue;
+            }
+            let start = span_start.max(col_offset) - col_offset;
+            let end = span_end.min(end) - col_offset;
+            let len = end.saturating_sub(start);
+            if len == 0 {
+                continue;
+            }
+            visible.push(StyledSpan {
+                start,
+                len,
+                style: span.style,
+            });
+        }
+        visible
+    }
+}
+
+impl Plugin for SyntaxHighlightPlugin {
+    fn on_init(&mut self, editor: &mut Editor) {
+        self.assets = Some(Assets::load_in_background(editor.waker.clone()));
+    }
+
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        self.receive_assets();
+        let Some(cache) = self.sync(editor) else {
+            return;
+        };
+        let content_height = editor.content_height();
+        let end = editor.viewport.row_offset + content_height as usize;
+        cache.highlight_to(&editor.buffer.lines, end);
+
+        let width = ctx.width as usize;
+        for row in 0..content_height {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            let Some(spans) = cache.spans(buffer_row) else {
+                continue;
+            };
+            let spans = Self::slice_spans(spans, editor.viewport.col_offset, width);
+            ctx.set_spans(row, spans);
+        }
+    }
+
+    fn on_idle(&mut self, editor: &Editor) -> bool {
+        self.receive_assets();
+        self.sync(editor)
+            .is_some_and(|cache| cache.highlight_more(&editor.buffer.lines))
+    }
+}
+
+/// Character drawn for an indent guide.
+const INDENT_GUIDE: char = '│';
+
+/// With `indentguides`, draws a guide at every `shiftwidth` columns of each
+/// line's leading whitespace.
+pub struct IndentGuidePlugin;
+
+impl Plugin for IndentGuidePlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if !editor.options.indentguides || editor.scratch.is_some() {
+            return;
+        }
+        let step = editor.options.shiftwidth().max(1);
+        let col_offset = editor.viewport.col_offset;
+        let guide = editor.theme.style(HighlightGroup::IndentGuide);
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            if buffer_row >= editor.buffer.lines.len() {
+                break;
+            }
+            let indent = editor.indent_width(buffer_row);
+            let first = col_offset.div_ceil(step) * step;
+            let Some(text) = ctx.lines.get(row as usize) else {
+                break;
+            };
+            // Leading whitespace is drawn as spaces, one char per column.
+            let mut line: Vec<char> = text.chars().collect();
+            for col in (first..indent).step_by(step) {
+                let Some(cell) = line.get_mut(col - col_offset).filter(|cell| **cell == ' ') else {
+                    break;
+                };
+                *cell = INDENT_GUIDE;
+                let under = ctx.style_at(row, col - col_offset);
+                ctx.add_span(
+                    row,
+                    StyledSpan {
+                        start: col - col_offset,
+                        len: 1,
+                        style: ContentStyle {
+                            foreground_color: guide.foreground_color,
+                            background_color: guide.background_color.or(under.background_color),
+                            attributes: guide.attributes,
+                            ..under
+                        },
+                    },
+                );
+            }
+            ctx.set_line(row, line.into_iter().collect());
+        }
+    }
+}
+
+/// Colors `()`, `[]` and `{}` by nesting depth in files whose extension
+/// the `rainbow` option lists, keeping the syntax colors' background.
+pub struct RainbowParenPlugin {
+    revision: u64,
+    /// Nesting depth at the start of each row, for the rows scanned so far.
+    depths: Vec<usize>,
+}
+
+impl RainbowParenPlugin {
+    pub fn new() -> Self {
+        Self {
+            revision: u64::MAX,
+            depths: Vec::new(),
+        }
+    }
+
+    /// Depth before the first char of every row up to `end`.
+    fn scan_to(&mut self, lines: &[String], end: usize) {
+        if self.depths.is_empty() {
+            self.depths.push(0);
+        }
+        while self.depths.len() <= end.min(lines.len()) {
+            let row = self.depths.len() - 1;
+            let mut depth = self.depths[row];
+            for ch in lines[row].chars() {
+                match ch {
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+            self.depths.push(depth);
+        }
+    }
+}
+
+impl Plugin for RainbowParenPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if editor.scratch.is_some() || !editor.options.rainbow_for(editor.file_extension()) {
+            return;
+        }
+        if editor.revision() != self.revision {
+            // Depths hold up to the first edited row.
+            match editor.deltas.since(self.revision) {
+                Some(deltas) => {
+                    if let Some(row) = deltas.map(|delta| delta.start.row).min() {
+                        self.depths.truncate(row + 1);
+                    }
+                }
+                None => self.depths.clear(),
+            }
+            self.revision = editor.revision();
+        }
+        let content_height = editor.content_height();
+        let top = editor.viewport.row_offset;
+        self.scan_to(&editor.buffer.lines, top + content_height as usize);
+
+        for row in 0..content_height {
+            let buffer_row = top + row as usize;
+            let Some(line) = editor.buffer.lines.get(buffer_row) else {
+                break;
+            };
+            let mut depth = self.depths[buffer_row];
+            for (col, ch) in line.chars().enumerate() {
+                let level = match ch {
+                    '(' | '[' | '{' => {
+                        depth += 1;
+                        depth - 1
+                    }
+                    ')' | ']' | '}' => {
+                        depth = depth.saturating_sub(1);
+                        depth
+                    }
+                    _ => continue,
+                };
+                let rainbow = editor.theme.style(HighlightGroup::rainbow(level));
+                let Some(mut span) = buffer_span(editor, line, col, col + 1, rainbow) else {
+                    continue;
+                };
+                let under = ctx.style_at(row, span.start);
+                span.style = ContentStyle {
+                    foreground_color: rainbow.foreground_color.or(under.foreground_color),
+                    background_color: rainbow.background_color.or(under.background_color),
+                    attributes: under.attributes | rainbow.attributes,
+                    ..under
+                };
+                ctx.add_span(row, span);
+            }
+        }
+    }
+}
+
+/// A span over chars `from..to` of buffer `line`, in the display columns of
+/// its screen row, or `None` if it is scrolled out of view.
+fn buffer_span(
+    editor: &Editor,
+    line: &str,
+    from: usize,
+    to: usize,
+    style: ContentStyle,
+) -> Option<StyledSpan> {
+    let tabstop = editor.options.tabstop;
+    let col_offset = editor.viewport.col_offset;
+    let start = display_col(line, from, tabstop).max(col_offset);
+    let end = display_col(line, to, tabstop);
+    (end > start).then(|| StyledSpan {
+        start: start - col_offset,
+        len: end - start,
+        style,
+    })
+}
+
+/// Highlights the Visual-mode selection.
+pub struct SelectionRenderPlugin;
+
+impl Plugin for SelectionRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        let Some((start, end)) = editor.selection() else {
+            return;
+        };
+        if editor.scratch.is_some() {
+            return;
+        }
+        let style = editor.theme.style(HighlightGroup::Visual);
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            if buffer_row < start.row || buffer_row > end.row {
+                continue;
+            }
+            let line = &editor.buffer.lines[buffer_row];
+            let from = if buffer_row == start.row {
+                start.col
+            } else {
+                0
+            };
+            let to = if buffer_row == end.row {
+                end.col
+            } else {
+                usize::MAX
+            };
+            if let Some(span) = buffer_span(editor, line, from, to, style) {
+                ctx.add_span(row, span);
+            }
+        }
+    }
+}
+
+/// `:CsvAlign [delimiter]` lines up the fields of delimiter-separated
+/// files, which `CsvRenderPlugin` draws, and `]f` / `[f` move between
+/// fields.
+pub struct CsvPlugin;
+
+impl CsvPlugin {
+    /// The delimiter of the buffer: the one `:CsvAlign` uses, or a guess.
+    fn delimiter(editor: &Editor) -> char {
+        editor.csv_align.unwrap_or_else(|| {
+            let first = editor.buffer.lines.first().map_or("", String::as_str);
+            csv::detect_delimiter(editor.file_path.as_deref(), first)
+        })
+    }
+
+    fn command_align(editor: &mut Editor, arg: &str) {
+        let delimiter = match arg {
+            "" if editor.csv_align.is_some() => {
+                editor.csv_align = None;
+                editor.set_status("CsvAlign off");
+                return;
+            }
+            "" => Self::delimiter(editor),
+            "tab" | "\\t" => '\t',
+            _ => {
+                let mut chars = arg.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => ch,
+                    _ => {
+                        editor.set_error("CsvAlign takes one delimiter character");
+                        return;
+                    }
+                }
+            }
+        };
+        editor.csv_align = Some(delimiter);
+        let name = match delimiter {
+            '\t' => "tab".to_string(),
+            ch => format!("'{}'", ch),
+        };
+        editor.set_status(format!("CsvAlign on, fields separated by {}", name));
+    }
+
+    /// Moves to the start of the next field, on the next line after the
+    /// last, or with `back` to the start of the previous one.
+    fn move_field(editor: &mut Editor, back: bool) {
+        let delimiter = Self::delimiter(editor);
+        let Cursor { row, col } = editor.cursor;
+        let line = |row: usize| editor.buffer.lines[row].as_str();
+        let target = if back {
+            csv::prev_field(line(row), delimiter, col)
+                .map(|col| (row, col))
+                .or_else(|| {
+                    let above = row.checked_sub(1)?;
+                    let last = csv::delimiters(line(above), delimiter)
+                        .last()
+                        .map_or(0, |delim| delim + 1);
+                    Some((above, last))
+                })
+        } else {
+            csv::next_field(line(row), delimiter, col)
+                .map(|col| (row, col))
+                .or_else(|| (row + 1 < editor.buffer.lines.len()).then_some((row + 1, 0)))
+        };
+        if let Some((row, col)) = target {
+            editor.goto_position(row, col);
+        }
+    }
+}
+
+impl Plugin for CsvPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        match split_command(command) {
+            ("CsvAlign", arg) => {
+                Self::command_align(editor, arg);
+                EventResult::Consumed
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Normal {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if key.code != KeyCode::Char('f') || key.modifiers.contains(KeyModifiers::CONTROL) {
+            return EventResult::Ignored;
+        }
+        let back = match editor.pending_keys.as_str() {
+            "]" => false,
+            "[" => true,
+            _ => return EventResult::Ignored,
+        };
+        editor.pending_keys.clear();
+        Self::move_field(editor, back);
+        EventResult::Consumed
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "CsvAlign",
+            "[delimiter]",
+            "Line up the fields of a CSV or TSV file, or stop",
+        )];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Normal, "]f", "Go to the next CSV field"),
+            KeyInfo::new(Mode::Normal, "[f", "Go to the previous CSV field"),
+        ];
+        KEYS
+    }
+}
+
+/// Pipe tables: `:TableFormat` lines up the columns of the table under the
+/// cursor, and with `tablemode` set, typing `|` or `Tab` in a table row in
+/// Insert mode does it as you go.
+pub struct TablePlugin;
+
+impl TablePlugin {
+    /// Formats the table around the cursor, keeping the cursor at the same
+    /// place in its cell. Returns the table's rows, or `None` when the
+    /// cursor is not in a table.
+    fn realign(editor: &mut Editor) -> Option<std::ops::Range<usize>> {
+        let row = editor.cursor.row;
+        let range = table::bounds(&editor.buffer.lines, row)?;
+        let col = editor.cursor.col;
+        let place = table::cell_at(&editor.buffer.lines[row], col);
+        let formatted = table::format(&editor.buffer.lines[range.clone()]);
+        editor.begin_batch();
+        for (row, line) in range.clone().zip(formatted) {
+            let len = editor.buffer.lines[row].chars().count();
+            if editor.buffer.lines[row] != line {
+                editor.replace_range(Cursor { row, col: 0 }, Cursor { row, col: len }, &line);
+            }
+        }
+        editor.end_batch();
+        let col = match place {
+            Some((cell, offset)) => table::position(&editor.buffer.lines[row], cell, offset),
+            None => col,
+        };
+        editor.cursor = Cursor { row, col };
+        editor.clamp_cursor();
+        editor.ensure_cursor_visible();
+        Some(range)
+    }
+
+    /// Realigns the table and moves to the start of the next cell, on the
+    /// next row after the last one.
+    fn next_cell(editor: &mut Editor) -> bool {
+        let Some(range) = Self::realign(editor) else {
+            return false;
+        };
+        let row = editor.cursor.row;
+        let line = &editor.buffer.lines[row];
+        let cell = table::cell_at(line, editor.cursor.col).map_or(0, |(cell, _)| cell + 1);
+        if table::has_cell(line, cell) {
+            editor.cursor.col = table::position(line, cell, 0);
+        } else if let Some(next) =
+            (row + 1..range.end).find(|next| table::is_body(&editor.buffer.lines[*next]))
+        {
+            let col = table::position(&editor.buffer.lines[next], 0, 0);
+            editor.cursor = Cursor { row: next, col };
+        }
+        editor.ensure_cursor_visible();
+        true
+    }
+}
+
+impl Plugin for TablePlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        if command.trim() != "TableFormat" {
+            return EventResult::Ignored;
+        }
+        if Self::realign(editor).is_none() {
+            editor.set_error("Not in a table");
+        }
+        EventResult::Consumed
+    }
+
+    fn on_event(&mut self, editor: &mut Editor, event: &Event) -> EventResult {
+        if editor.mode != Mode::Insert || !editor.options.tablemode {
+            return EventResult::Ignored;
+        }
+        let Event::Key(key) = event else {
+            return EventResult::Ignored;
+        };
+        if key
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+        {
+            return EventResult::Ignored;
+        }
+        let line = editor.current_line();
+        let handled = match key.code {
+            // A `|` typed before any text starts a row.
+            KeyCode::Char('|')
+                if table::is_row(line)
+                    || line
+                        .chars()
+                        .take(editor.cursor.col)
+                        .all(char::is_whitespace) =>
+            {
+                editor.begin_batch();
+                editor.insert_char('|');
+                Self::realign(editor);
+                editor.end_batch();
+                true
+            }
+            KeyCode::Tab => Self::next_cell(editor),
+            _ => false,
+        };
+        if handled {
+            EventResult::Consumed
+        } else {
+            EventResult::Ignored
+        }
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "TableFormat",
+            "",
+            "Line up the columns of the pipe table under the cursor",
+        )];
+        COMMANDS
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        const KEYS: &[KeyInfo] = &[
+            KeyInfo::new(Mode::Insert, "|", "Realign a table row with tablemode"),
+            KeyInfo::new(
+                Mode::Insert,
+                "<Tab>",
+                "Realign a table and go to the next cell with tablemode",
+            ),
+        ];
+        KEYS
+    }
+}
+
+/// Underlines the words the `dictionary` files do not list while `spell`
+/// is set.
+pub struct SpellRenderPlugin;
+
+impl Plugin for SpellRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if !editor.options.spell || editor.dictionary.is_empty() || editor.scratch.is_some() {
+            return;
+        }
+        let style = editor.theme.style(HighlightGroup::SpellBad);
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            let Some(line) = editor.buffer.lines.get(buffer_row) else {
+                break;
+            };
+            for (start, end) in spell::misspelled(line, &editor.dictionary) {
+                if let Some(span) = buffer_span(editor, line, start, end, style) {
+                    ctx.add_span(row, span);
+                }
+            }
+        }
+    }
+}
+
+/// Draws the buffer with `:CsvAlign` on: spaces before each delimiter line
+/// the fields up in columns, and the first line is styled as a header. It
+/// runs after the cursor is placed, which the spaces push along, and before
+/// virtual text.
+pub struct CsvRenderPlugin {
+    /// What `widths` were measured for: the revision, delimiter and
+    /// tabstop.
+    measured: Option<(u64, char, usize)>,
+    widths: Vec<usize>,
+}
+
+impl CsvRenderPlugin {
+    pub fn new() -> Self {
+        Self {
+            measured: None,
+            widths: Vec::new(),
+        }
+    }
+}
+
+impl Plugin for CsvRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        let Some(delimiter) = editor.csv_align else {
+            return;
+        };
+        if editor.scratch.is_some() {
+            return;
+        }
+        let tabstop = editor.options.tabstop;
+        let key = (editor.revision(), delimiter, tabstop);
+        if self.measured != Some(key) {
+            self.widths = csv::column_widths(&editor.buffer.lines, delimiter, tabstop);
+            self.measured = Some(key);
+        }
+        let col_offset = editor.viewport.col_offset;
+        let header = editor.theme.style(HighlightGroup::CsvHeader);
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            let Some(line) = editor.buffer.lines.get(buffer_row) else {
+                break;
+            };
+            if buffer_row == 0 {
+                let width = ctx
+                    .lines
+                    .get(row as usize)
+                    .map_or(0, |text| display_width(text));
+                for col in 0..width {
+                    let under = ctx.style_at(row, col);
+                    ctx.add_span(
+                        row,
+                        StyledSpan {
+                            start: col,
+                            len: 1,
+                            style: ContentStyle {
+                                foreground_color: header
+                                    .foreground_color
+                                    .or(under.foreground_color),
+                                background_color: header
+                                    .background_color
+                                    .or(under.background_color),
+                                attributes: under.attributes | header.attributes,
+                                ..under
+                            },
+                        },
+                    );
+                }
+            }
+            // Right to left, so each insert leaves the columns before it alone.
+            for (col, spaces) in csv::padding(line, delimiter, &self.widths, tabstop)
+                .into_iter()
+                .rev()
+            {
+                if col >= col_offset {
+                    ctx.insert_text(
+                        row,
+                        col - col_offset,
+                        &" ".repeat(spaces),
+                        ContentStyle::new(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Marks the trailing whitespace of visible lines in Normal mode and strips
+/// it from the whole buffer with `:TrimWhitespace`; the
+/// `trim_trailing_whitespace` option strips it on every write.
+pub struct TrailingWhitespacePlugin;
+
+impl Plugin for TrailingWhitespacePlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        if command.trim() != "TrimWhitespace" {
+            return EventResult::Ignored;
+        }
+        if editor.is_loading() {
+            editor.set_error("Cannot edit while the file is still loading");
+            return EventResult::Consumed;
+        }
+        match editor.trim_trailing_whitespace() {
+            0 => editor.set_status("No trailing whitespace"),
+            1 => editor.set_status("Trimmed 1 line"),
+            lines => editor.set_status(format!("Trimmed {} lines", lines)),
+        }
+        EventResult::Consumed
+    }
+
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if editor.mode != Mode::Normal || editor.scratch.is_some() {
+            return;
+        }
+        let style = editor.theme.style(HighlightGroup::TrailingWhitespace);
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            let Some(line) = editor.buffer.lines.get(buffer_row) else {
+                break;
+            };
+            let kept = line.trim_end().chars().count();
+            let len = line.chars().count();
+            if kept < len
+                && let Some(span) = buffer_span(editor, line, kept, len, style)
+            {
+                ctx.add_span(row, span);
+            }
+        }
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "TrimWhitespace",
+            "",
+            "Strip trailing whitespace from every line",
+        )];
+        COMMANDS
+    }
+}
+
+/// Highlights every visible match of the last search pattern until `:noh`.
+pub struct HighlightSearchPlugin;
+
+impl HighlightSearchPlugin {
+    fn render_matches(editor: &Editor, ctx: &mut RenderContext) {
+        let Some(Ok(regex)) = editor
+            .search
+            .pattern
+            .as_deref()
+            .map(|pattern| editor.compile_pattern(pattern))
+        else {
+            return;
+        };
+        let style = editor.theme.style(HighlightGroup::Search);
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            let Some(line) = editor.buffer.lines.get(buffer_row) else {
+                break;
+            };
+            for (from, to) in editor
+                .line_matches(buffer_row, &regex)
+                .into_iter()
+                .filter(|(from, to)| to > from)
+            {
+                if let Some(span) = buffer_span(editor, line, from, to, style) {
+                    ctx.add_span(row, span);
+                }
+            }
+        }
+    }
+
+    /// Draws the match `:s///c` is asking about over the other matches.
+    fn render_candidate(editor: &Editor, ctx: &mut RenderContext) {
+        let Some((start, end)) = editor.search.candidate else {
+            return;
+        };
+        let Some(row) = start.row.checked_sub(editor.viewport.row_offset) else {
+            return;
+        };
+        let (Some(line), true) = (
+            editor.buffer.lines.get(start.row),
+            row < editor.content_height() as usize,
+        ) else {
+            return;
+        };
+        let style = editor.theme.style(HighlightGroup::CurSearch);
+        let to = end.col.max(start.col + 1);
+        if let Some(span) = buffer_span(editor, line, start.col, to, style) {
+            ctx.add_span(row as u16, span);
+        }
+    }
+}
+
+impl Plugin for HighlightSearchPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if editor.scratch.is_some() {
+            return;
+        }
+        if editor.search.highlight {
+            Self::render_matches(editor, ctx);
+        }
+        Self::render_candidate(editor, ctx);
+    }
+}
+
+/// With `inccommand`, draws the lines in view as the `:s` being typed
+/// would leave them, over the buffer's own text: the buffer is not touched
+/// until the command is run.
+pub struct SubstitutePreviewPlugin;
+
+impl SubstitutePreviewPlugin {
+    /// The range, regex and arguments of the `:s` on the command line, if
+    /// it would run.
+    fn typed(editor: &Editor) -> Option<(LineRange, Regex, Substitution)> {
+        let input = editor.command_line.input.as_str();
+        let (range, rest) = ex::parse_range(input, editor).ok()?;
+        let substitution = SubstitutePlugin::parse(rest)?.ok()?;
+        if substitution.pattern.is_empty() && !substitution.replacing {
+            return None;
+        }
+        let (_, regex) = SubstitutePlugin::compile(editor, &substitution).ok()?;
+        let row = editor.cursor.row;
+        let range = range.unwrap_or(LineRange {
+            start: row,
+            end: row,
+        });
+        Some((range, regex, substitution))
+    }
+
+    /// `line` as `substitution` leaves it, and the char ranges of the
+    /// replacements in it; a line break in a replacement shows as `⏎`.
+    /// Before the replacement is typed, the matches are left as they are.
+    fn preview_line(
+        line: &str,
+        regex: &Regex,
+        substitution: &Substitution,
+    ) -> (String, Vec<(usize, usize)>) {
+        let limit = if substitution.global { usize::MAX } else { 1 };
+        let mut text = String::new();
+        let mut replaced = Vec::new();
+        let mut last = 0;
+        for caps in regex.captures_iter(line).take(limit) {
+            let found = caps.get(0).expect("group 0 is the whole match");
+            text.push_str(&line[last..found.start()]);
+            let start = text.chars().count();
+            if substitution.replacing {
+                let replacement =
+                    replacement_for(&substitution.replacement, substitution.subvert, &caps);
+                text.push_str(&replacement.replace('\n', "⏎"));
+            } else {
+                text.push_str(found.as_str());
+            }
+            replaced.push((start, text.chars().count()));
+            last = found.end();
+        }
+        text.push_str(&line[last..]);
+        (text, replaced)
+    }
+}
+
+impl Plugin for SubstitutePreviewPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if !editor.options.inccommand
+            || editor.mode != Mode::Command
+            || editor.command_line.prompt != ':'
+            || editor.scratch.is_some()
+            || editor.is_loading()
+        {
+            return;
+        }
+        let Some((range, regex, substitution)) = Self::typed(editor) else {
+            return;
+        };
+        let style = editor.theme.style(HighlightGroup::Substitute);
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            if buffer_row > range.end {
+                break;
+            }
+            let Some(line) = editor.buffer.lines.get(buffer_row) else {
+                break;
+            };
+            if buffer_row < range.start {
+                continue;
+            }
+            let (text, replaced) = Self::preview_line(line, &regex, &substitution);
+            if replaced.is_empty() {
+                continue;
+            }
+            let visible = expand_line(
+                &text,
+                editor.viewport.col_offset,
+                ctx.width as usize,
+                editor.options.tabstop,
+            );
+            ctx.set_line(row, visible);
+            // The text's highlighting no longer lines up with it.
+            ctx.set_spans(row, Vec::new());
+            for (from, to) in replaced {
+                if let Some(span) = buffer_span(editor, &text, from, to, style) {
+                    ctx.add_span(row, span);
+                }
+            }
+        }
+    }
+}
+
+pub struct StatusBarPlugin;
+
+impl Plugin for StatusBarPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if ctx.height == 0 {
+            return;
+        }
+
+        let mode_label = match editor.mode {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Command => "COMMAND",
+            Mode::Visual => "VISUAL",
+        };
+
+        let name = match (editor.scratch.as_ref(), editor.file_path.as_ref()) {
+            (Some(view), _) => format!("[Scratch] {}", view.title),
+            (None, Some(path)) if editor.is_scratch_buffer() => {
+                format!("[Scratch] {}", path.display())
+            }
+            (None, Some(path)) => path.display().to_string(),
+            (None, None) if editor.is_scratch_buffer() => "[Scratch]".to_string(),
+            (None, None) => "[No Name]".to_string(),
+        };
+        let dirty = if editor.dirty { " [+]" } else { "" };
+        let readonly = if editor.options.readonly && editor.scratch.is_none() {
+            " [RO]"
+        } else {
+            ""
+        };
+
+        let left = format!("{} {}{}{}", mode_label, name, readonly, dirty);
+        let right = if let Some(percent) = editor.load_progress() {
+            format!("Loading… {}%", percent)
+        } else if editor.status.is_empty() {
+            format!(
+                "Ln {}, Col {}",
+                editor.cursor.row + 1,
+                editor.cursor.col + 1
+            )
+        } else {
+            editor.status.clone()
+        };
+
+        let width = ctx.width as usize;
+        let line = format_status_line(&left, &right, width);
+        ctx.set_line(editor.status_row(), line);
+        ctx.set_spans(
+            editor.status_row(),
+            vec![StyledSpan {
+                start: 0,
+                len: width,
+                style: editor.theme.style(HighlightGroup::StatusLine),
+            }],
+        );
+    }
+}
+
+pub struct CommandLineRenderPlugin;
+
+impl Plugin for CommandLineRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if !editor.command_line.active || ctx.height == 0 {
+            return;
+        }
+        let width = ctx.width as usize;
+        let (prompt, _) = editor.command_line.view(width);
+        let padding = width.saturating_sub(display_width(&prompt));
+        ctx.set_line(
+            editor.command_row(),
+            format!("{}{}", prompt, " ".repeat(padding)),
+        );
+        ctx.set_spans(
+            editor.command_row(),
+            vec![StyledSpan {
+                start: 0,
+                len: width,
+                style: editor.theme.style(HighlightGroup::MsgArea),
+            }],
+        );
+    }
+}
+
+pub struct CursorRenderPlugin;
+
+impl Plugin for CursorRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if ctx.height == 0 || ctx.width == 0 || editor.scratch.is_some() {
+            return;
+        }
+        if editor.command_line.active {
+            let row = editor.command_row().min(ctx.height.saturating_sub(1));
+            let (_, col) = editor.command_line.view(ctx.width as usize);
+            let clamped = (col as u16).min(ctx.width.saturating_sub(1));
+            ctx.set_cursor(row, clamped);
+            return;
+        }
+
+        let cursor_row = editor.cursor.row.saturating_sub(editor.viewport.row_offset) as u16;
+        let line = editor
+            .buffer
+            .lines
+            .get(editor.cursor.row)
+            .map_or("", String::as_str);
+        let cursor_col = display_col(line, editor.cursor.col, editor.options.tabstop)
+            .saturating_sub(editor.viewport.col_offset) as u16;
+        let row = cursor_row.min(ctx.height.saturating_sub(1));
+        let col = cursor_col.min(ctx.width.saturating_sub(1));
+        ctx.set_cursor(row, col);
+    }
+}
+
+/// Draws `Editor::virtual_text` into the buffer lines. It runs last, so
+/// the text it inserts pushes aside the spans and cursor drawn before it.
+pub struct VirtualTextRenderPlugin;
+
+impl Plugin for VirtualTextRenderPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        if editor.scratch.is_some() {
+            return;
+        }
+        let tabstop = editor.options.tabstop;
+        let col_offset = editor.viewport.col_offset;
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            let Some(line) = editor.buffer.lines.get(buffer_row) else {
+                break;
+            };
+            // Text after the end of the line goes first, left to right, each
+            // a space after the line or the text before it.
+            let mut end = display_col(line, line.chars().count(), tabstop) + 1;
+            let mut inline: Vec<(usize, &VirtualText)> = Vec::new();
+            let texts = editor
+                .virtual_text
+                .on_row(buffer_row)
+                .filter(|text| !text.cursor_line || buffer_row == editor.cursor.row);
+            for text in texts {
+                match text.col {
+                    Some(col) => inline.push((display_col(line, col, tabstop), text)),
+                    None => {
+                        if end >= col_offset {
+                            ctx.insert_text(row, end - col_offset, &text.text, text.style);
+                        }
+                        end += display_width(&text.text) + 1;
+                    }
+                }
+            }
+            // Inline text right to left, so each insert leaves the columns
+            // before it alone; texts at one column keep the order added.
+            inline.sort_by_key(|(col, _)| *col);
+            for (col, text) in inline.into_iter().rev() {
+                if col >= col_offset {
+                    ctx.insert_text(row, col - col_offset, &text.text, text.style);
+                }
+            }
+        }
+    }
+}
+
+/// `:Preview` shows a Markdown buffer rendered in a pane on the right half
+/// of the window, redrawn as the text changes and scrolled along with it.
+pub struct MarkdownPreviewPlugin {
+    /// What `lines` were rendered for: the revision, width and styles.
+    rendered: Option<(u64, usize, PreviewStyles)>,
+    lines: Vec<PreviewLine>,
+}
+
+impl MarkdownPreviewPlugin {
+    /// Extensions of the files `:Preview` shows.
+    const EXTENSIONS: &'static [&'static str] = &["md", "markdown", "mkd", "mdown"];
+
+    pub fn new() -> Self {
+        Self {
+            rendered: None,
+            lines: Vec::new(),
+        }
+    }
+}
+
+impl Plugin for MarkdownPreviewPlugin {
+    fn on_command(&mut self, editor: &mut Editor, command: &str) -> EventResult {
+        if command.trim() != "Preview" {
+            return EventResult::Ignored;
+        }
+        if !editor.preview && !Self::EXTENSIONS.contains(&editor.file_extension()) {
+            editor.set_error("Preview shows Markdown files");
+            return EventResult::Consumed;
+        }
+        editor.preview = !editor.preview;
+        editor.ensure_cursor_visible();
+        EventResult::Consumed
+    }
+
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        let width = editor.preview_width();
+        if width < 2 || editor.scratch.is_some() {
+            return;
+        }
+        let left = editor.screen_width as usize - width;
+        let text_width = width - 1;
+        let styles = PreviewStyles {
+            heading: editor.theme.style(HighlightGroup::PreviewHeading),
+            code: editor.theme.style(HighlightGroup::PreviewCode),
+        };
+        let key = (editor.revision(), text_width, styles);
+        if self.rendered != Some(key) {
+            self.lines = markdown::render(&editor.buffer.lines, text_width, styles);
+            self.rendered = Some(key);
+        }
+        let top = self
+            .lines
+            .iter()
+            .position(|line| line.source_row >= editor.viewport.row_offset)
+            .unwrap_or(self.lines.len());
+        let border = editor.theme.style(HighlightGroup::FloatBorder);
+        for row in 0..editor.content_height() {
+            ctx.draw_text(row, left, "│", border);
+            let (text, spans) = match self.lines.get(top + row as usize) {
+                Some(line) => (line.text.as_str(), line.spans.as_slice()),
+                None => ("", &[][..]),
+            };
+            let fill = " ".repeat(text_width.saturating_sub(display_width(text)));
+            ctx.draw_text(
+                row,
+                left + 1,
+                &format!("{}{}", text, fill),
+                ContentStyle::new(),
+            );
+            for span in spans {
+                ctx.add_span(
+                    row,
+                    StyledSpan {
+                        start: left + 1 + span.start,
+                        ..*span
+                    },
+                );
+            }
+        }
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        const COMMANDS: &[CommandInfo] = &[CommandInfo::new(
+            "Preview",
+            "",
+            "Show the Markdown buffer rendered beside it, or stop",
+        )];
+        COMMANDS
+    }
+}
+
+/// Draws the sign column while there are diagnostics: the letter of the
+/// most serious one on each line. It runs after everything else has drawn
+/// the lines, and pushes them right.
+pub struct SignColumnPlugin;
+
+impl Plugin for SignColumnPlugin {
+    fn on_render(&mut self, editor: &Editor, ctx: &mut RenderContext) {
+        let width = editor.sign_column_width();
+        if width == 0 || editor.scratch.is_some() {
+            return;
+        }
+        for row in 0..editor.content_height() {
+            let buffer_row = editor.viewport.row_offset + row as usize;
+            match diagnostics::worst_on_row(&editor.diagnostics, buffer_row) {
+                Some(severity) => {
+                    let sign = format!("{:<width$}", severity.sign());
+                    let style = editor.theme.style(severity.group());
+                    ctx.insert_text(row, 0, &sign, style);
+                }
+                None => ctx.insert_text(row, 0, &" ".repeat(width), ContentStyle::new()),
+            }
+        }
+    }
+}
+
+/// Splits an ex command into its verb and the trimmed remainder.
+fn split_command(command: &str) -> (&str, &str) {
+    let command = command.trim();
+    match command.split_once(char::is_whitespace) {
+        Some((verb, rest)) => (verb, rest.trim()),
+        None => (command, ""),
+    }
+}
+
+/// Removes backspace overstrikes and ANSI escape sequences from program output.
+fn strip_terminal_formatting(line: &str) -> String {
+    let mut out: Vec<char> = Vec::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\u{8}' => {
+                out.pop();
+            }
+            '\u{1b}' => {
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for next in chars.by_ref() {
+                        if next.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => {
+                let pad = 8 - out.len() % 8;
+                out.extend(std::iter::repeat_n(' ', pad));
+            }
+            _ => out.push(ch),
+        }
+    }
+    out.into_iter().collect()
+}
+
+/// The part of a buffer line visible from display column `col_offset` in
+/// `width` columns, with tabs expanded to spaces.
+fn expand_line(line: &str, col_offset: usize, width: usize, tabstop: usize) -> String {
+    let mut visible = String::new();
+    let mut col = 0;
+    for grapheme in line.graphemes(true) {
+        let columns = grapheme_columns(grapheme, col, tabstop);
+        if grapheme == "\t" {
+            let start = col.max(col_offset);
+            let end = (col + columns).min(col_offset + width);
+            visible.extend(std::iter::repeat_n(' ', end.saturating_sub(start)));
+            col += columns;
+            if col >= col_offset + width {
+                break;
+            }
+            continue;
+        }
+        if col < col_offset {
+            col += columns;
+            if col > col_offset {
+                visible.extend(std::iter::repeat_n(' ', (col - col_offset).min(width)));
+            }
+            continue;
+        }
+        if col + columns > col_offset + width {
+            break;
+        }
+        visible.push_str(grapheme);
+        col += columns;
+    }
+    visible
+}
+
+/// The part of `line` visible from display column `col_offset` in `width`
+/// columns. A wide grapheme cut by the left edge shows as spaces.
+fn slice_line(line: &str, col_offset: usize, width: usize) -> String {
+    let mut visible = String::new();
+    let mut col = 0;
+    for grapheme in line.graphemes(true) {
+        let grapheme_width = grapheme_width(grapheme);
+        if col < col_offset {
+            col += grapheme_width;
+            if col > col_offset {
+                visible.extend(std::iter::repeat_n(' ', (col - col_offset).min(width)));
+            }
+            continue;
+        }
+        if col + grapheme_width > col_offset + width {
+            break;
+        }
+        visible.push_str(grapheme);
+        col += grapheme_width;
+    }
+    visible
+}
+
+fn format_status_line(left: &str, right: &str, width: usize) -> String {
+    if width == 0 {
+        return String::new();
+    }
+    let right_len = display_width(right);
+
+    if right_len >= width {
+        return slice_line(right, 0, width);
+    }
+
+    let available_left = width.saturating_sub(right_len + 1);
+    let left_trimmed = slice_line(left, 0, available_left);
+    let padding = width.saturating_sub(display_width(&left_trimmed) + right_len);
+    format!("{}{}{}", left_trimmed, " ".repeat(padding), right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wakeup::{self, Wakeup};
+    use crossterm::event::KeyEvent;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Event {
+        Event::Key(KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn slice_line_respects_offset_and_width() {
+        let line = "abcdef";
+        let slice = slice_line(line, 2, 3);
+        assert_eq!(slice, "cde");
+    }
+
+    #[test]
+    fn slice_line_uses_display_columns() {
+        assert_eq!(slice_line("日本語", 0, 5), "日本");
+        assert_eq!(slice_line("日本語", 1, 4), " 本");
+        assert_eq!(slice_line("a日本", 1, 4), "日本");
+    }
+
+    #[test]
+    fn expand_line_replaces_tabs_with_spaces() {
+        assert_eq!(expand_line("\tx", 0, 10, 4), "    x");
+        assert_eq!(expand_line("ab\tx", 0, 10, 4), "ab  x");
+        assert_eq!(expand_line("ab\tx", 3, 10, 4), " x");
+        assert_eq!(expand_line("\t\tx", 0, 6, 4), "      ");
+    }
+
+    #[test]
+    fn strip_terminal_formatting_removes_overstrike_and_escapes() {
+        assert_eq!(strip_terminal_formatting("N\u{8}NA\u{8}AME"), "NAME");
+        assert_eq!(strip_terminal_formatting("_\u{8}x"), "x");
+        assert_eq!(strip_terminal_formatting("\u{1b}[1mbold\u{1b}[0m"), "bold");
+    }
+
+    #[test]
+    fn line_number_commands_move_cursor() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut plugin = MotionPlugin;
+        assert_eq!(plugin.on_command(&mut editor, "2"), EventResult::Consumed);
+        assert_eq!(editor.cursor.row, 1);
+        assert_eq!(plugin.on_command(&mut editor, "$"), EventResult::Consumed);
+        assert_eq!(editor.cursor.row, 2);
+        assert_eq!(plugin.on_command(&mut editor, "99"), EventResult::Consumed);
+        assert_eq!(editor.cursor.row, 2);
+        assert_eq!(plugin.on_command(&mut editor, "0"), EventResult::Consumed);
+        assert_eq!(editor.cursor.row, 0);
+        assert_eq!(plugin.on_command(&mut editor, "wq"), EventResult::Ignored);
+    }
+
+    #[test]
+    fn session_commands_restore_file_cursor_and_options() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("/tmp/notes.txt")));
+        editor.options.keywordprg = Some("pydoc3".to_string());
+        editor.cursor = Cursor { row: 4, col: 2 };
+        let commands = SessionPlugin::session_commands(&editor).unwrap();
+        assert_eq!(
+            commands[1..],
+            [
+                "set keywordprg=pydoc3",
+                "edit! /tmp/notes.txt",
+                "cursor 5 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn search_commands_repeat_last_pattern() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["one two".to_string(), "two one".to_string()];
+        let mut plugin = SearchPlugin;
+        assert_eq!(
+            plugin.on_command(&mut editor, "/two"),
+            EventResult::Consumed
+        );
+        assert_eq!((editor.cursor.row, editor.cursor.col), (0, 4));
+        plugin.on_command(&mut editor, "/");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (1, 0));
+        plugin.on_command(&mut editor, "?");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (0, 4));
+        assert!(!editor.search.forward);
+        plugin.on_command(&mut editor, "/missing");
+        assert_eq!(editor.status, "Pattern not found: missing");
+    }
+
+    #[test]
+    fn searches_say_when_they_wrap_and_stop_without_wrapscan() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["one two".to_string(), "two one".to_string()];
+        let mut plugin = SearchPlugin;
+        editor.cursor = Cursor { row: 1, col: 0 };
+        plugin.on_command(&mut editor, "/one");
+        assert_eq!(editor.status, "/one");
+        type_keys(&mut plugin, &mut editor, "n");
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 0 });
+        assert_eq!(editor.status, "search hit BOTTOM, continuing at TOP");
+        type_keys(&mut plugin, &mut editor, "N");
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 4 });
+        assert_eq!(editor.status, "search hit TOP, continuing at BOTTOM");
+
+        editor.options.wrapscan = false;
+        type_keys(&mut plugin, &mut editor, "n");
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 4 });
+        assert_eq!(editor.status, "Search hit BOTTOM without match for: one");
+        type_keys(&mut plugin, &mut editor, "NN");
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 0 });
+        assert_eq!(editor.status, "Search hit TOP without match for: one");
+        plugin.on_command(&mut editor, "/missing");
+        assert_eq!(editor.status, "Pattern not found: missing");
+    }
+
+    #[test]
+    fn star_and_hash_search_for_the_whole_word_under_the_cursor() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["foo foobar x.foo", "bar foo"].map(String::from).to_vec();
+        editor.cursor.col = 1;
+        let mut plugin = SearchPlugin;
+        type_keys(&mut plugin, &mut editor, "*");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (0, 13));
+        assert_eq!(editor.search.pattern.as_deref(), Some(r"\bfoo\b"));
+        assert!(editor.search.highlight);
+        type_keys(&mut plugin, &mut editor, "n");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (1, 4));
+        editor.cursor.col = 6;
+        type_keys(&mut plugin, &mut editor, "#");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (0, 13));
+        assert!(!editor.search.forward);
+        type_keys(&mut plugin, &mut editor, "nN");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (0, 13));
+        editor.buffer.lines = vec!["  ".to_string()];
+        editor.cursor = Cursor { row: 0, col: 0 };
+        type_keys(&mut plugin, &mut editor, "*");
+        assert_eq!(editor.status, "No string under cursor");
+    }
+
+    #[test]
+    fn search_uses_regex_and_reports_bad_patterns() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["one two".to_string(), "three 42".to_string()];
+        SearchPlugin.on_command(&mut editor, r"/\d+|xyz");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (1, 6));
+        SearchPlugin.on_command(&mut editor, "/^t");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (1, 0));
+        SearchPlugin.on_command(&mut editor, "/(two");
+        assert_eq!(editor.status, "Invalid pattern: unclosed group");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (1, 0));
+    }
+
+    #[test]
+    fn ignorecase_and_smartcase_apply_to_search_and_substitute() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["Foo foo".to_string()];
+        editor.options.ignorecase = true;
+        SearchPlugin.on_command(&mut editor, "/FOO");
+        assert_eq!(editor.cursor.col, 4);
+        SearchPlugin.on_command(&mut editor, "/foo");
+        assert_eq!(editor.cursor.col, 0);
+
+        editor.options.smartcase = true;
+        SearchPlugin.on_command(&mut editor, "/FOO");
+        assert_eq!(editor.status, "Pattern not found: FOO");
+        SubstitutePlugin::new().on_command(&mut editor, "s/foo/x/g");
+        assert_eq!(editor.buffer.lines[0], "x x");
+        SubstitutePlugin::new().on_command(&mut editor, "s/X/y/");
+        assert_eq!(editor.status, "Pattern not found: X");
+        SubstitutePlugin::new().on_command(&mut editor, "s/X/y/i");
+        assert_eq!(editor.buffer.lines[0], "y x");
+    }
+
+    #[test]
+    fn substitute_confirm_asks_at_each_match() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["a a", "b", "a"].map(String::from).to_vec();
+        let mut plugin = SubstitutePlugin::new();
+
+        plugin.on_command(&mut editor, "%s/a/xy/gc");
+        assert_eq!(editor.status, "replace? (y/n/a/q/l)");
+        let candidate = |row, from, to| Some((Cursor { row, col: from }, Cursor { row, col: to }));
+        assert_eq!(editor.search.candidate, candidate(0, 0, 1));
+        type_keys(&mut plugin, &mut editor, "y");
+        assert_eq!(editor.buffer.lines[0], "xy a");
+        assert_eq!(editor.search.candidate, candidate(0, 3, 4));
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 3 });
+        type_keys(&mut plugin, &mut editor, "zn");
+        assert_eq!(editor.buffer.lines[0], "xy a");
+        assert_eq!(editor.search.candidate, candidate(2, 0, 1));
+        type_keys(&mut plugin, &mut editor, "y");
+        assert_eq!(editor.buffer.lines, ["xy a", "b", "xy"]);
+        assert_eq!(editor.search.candidate, None);
+        assert_eq!(editor.status, "2 substitutions on 2 lines");
+        assert_eq!(
+            plugin.on_event(&mut editor, &key(KeyCode::Char('y'), KeyModifiers::NONE)),
+            EventResult::Ignored
+        );
+
+        plugin.on_command(&mut editor, "%s/xy/z/c");
+        type_keys(&mut plugin, &mut editor, "a");
+        assert_eq!(editor.buffer.lines, ["z a", "b", "z"]);
+        plugin.on_command(&mut editor, "%s/z/w/c");
+        type_keys(&mut plugin, &mut editor, "l");
+        assert_eq!(editor.buffer.lines, ["w a", "b", "z"]);
+        assert_eq!(editor.status, "1 substitution on 1 line");
+        plugin.on_command(&mut editor, "%s/a/b/c");
+        type_keys(&mut plugin, &mut editor, "q");
+        assert_eq!(editor.buffer.lines[0], "w a");
+        assert_eq!(editor.status, "");
+    }
+
+    #[test]
+    fn substitute_replaces_over_ranges_with_groups() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["a=1 b=2", "  c=3", "d"].map(String::from).to_vec();
+        let mut plugin = SubstitutePlugin::new();
+
+        plugin.on_command(&mut editor, r"s/(\w)=(\d)/\2=\1/");
+        assert_eq!(editor.buffer.lines[0], "1=a b=2");
+        plugin.on_command(&mut editor, r"%s/(\w)=(\d)/\2=\1/g");
+        assert_eq!(editor.buffer.lines[..2], ["1=a 2=b", "  3=c"]);
+        assert_eq!(editor.status, "2 substitutions on 2 lines");
+        assert_eq!((editor.cursor.row, editor.cursor.col), (1, 2));
+
+        plugin.on_command(&mut editor, r"3s#d#x\r[&]#");
+        assert_eq!(editor.buffer.lines[2..], ["x", "[d]"]);
+        plugin.on_command(&mut editor, "1,2s/z/y/");
+        assert_eq!(editor.status, "Pattern not found: z");
+        plugin.on_command(&mut editor, "s/A/b/gI");
+        assert_eq!(editor.status, "Pattern not found: A");
+        plugin.on_command(&mut editor, "1s/A/b/i");
+        assert_eq!(editor.buffer.lines[0], "1=b 2=b");
+        plugin.on_command(&mut editor, "s/a/b/q");
+        assert_eq!(editor.status, "Invalid flag: q");
+        plugin.on_command(&mut editor, "1,9s/a/b/");
+        assert_eq!(editor.status, "Invalid range");
+        assert_eq!(
+            plugin.on_command(&mut editor, "set ts=4"),
+            EventResult::Ignored
+        );
+    }
+
+    #[test]
+    fn subvert_keeps_the_case_style_of_each_match() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = [
+            "let user_name = UserName::new(userName);",
+            "const USER_NAME: &str = \"user-name\";",
+        ]
+        .map(String::from)
+        .to_vec();
+        let mut plugin = SubstitutePlugin::new();
+        plugin.on_command(&mut editor, "%S/userName/account_id/g");
+        assert_eq!(
+            editor.buffer.lines,
+            [
+                "let account_id = AccountId::new(accountId);",
+                "const ACCOUNT_ID: &str = \"account-id\";",
+            ]
+        );
+        assert_eq!(editor.status, "5 substitutions on 2 lines");
+
+        plugin.on_command(&mut editor, "1Subvert/account/Login/gc");
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 4 });
+        type_keys(&mut plugin, &mut editor, "a");
+        assert_eq!(
+            editor.buffer.lines[0],
+            "let login_id = LoginId::new(loginId);"
+        );
+        // `n` finds the other case styles too.
+        assert!(
+            editor
+                .compile_pattern(editor.search.pattern.as_deref().unwrap())
+                .unwrap()
+                .is_match("ACCOUNT")
+        );
+    }
+
+    #[test]
+    fn typed_substitutions_are_previewed_without_changing_the_buffer() {
+        let mut editor = Editor::new(20, 5, None);
+        editor.buffer.lines = ["a=1 a=2", "a=3", "b"].map(String::from).to_vec();
+        editor.mode = Mode::Command;
+        let preview = |editor: &mut Editor, input: &str| {
+            editor.command_line.input = input.to_string();
+            let mut ctx = RenderContext::new(20, 5);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            SubstitutePreviewPlugin.on_render(editor, &mut ctx);
+            ctx
+        };
+
+        // Until the replacement is typed, the matches are only highlighted.
+        let ctx = preview(&mut editor, "%s/a=");
+        assert_eq!(ctx.lines[..3], ["a=1 a=2", "a=3", "b"]);
+        assert_eq!(ctx.spans[0].len(), 1);
+        assert_eq!((ctx.spans[0][0].start, ctx.spans[0][0].len), (0, 2));
+        let ctx = preview(&mut editor, "%s/a=/x");
+        assert_eq!(ctx.lines[..3], ["x1 a=2", "x3", "b"]);
+
+        let ctx = preview(&mut editor, r"%s/a=(\d)/<\1>/g");
+        assert_eq!(ctx.lines[..3], ["<1> <2>", "<3>", "b"]);
+        assert_eq!(
+            ctx.style_at(0, 4),
+            editor.theme.style(HighlightGroup::Substitute)
+        );
+        assert_eq!(ctx.style_at(0, 3), ContentStyle::new());
+        let ctx = preview(&mut editor, r"s/=/\r");
+        assert_eq!(ctx.lines[..2], ["a⏎1 a=2", "a=3"]);
+        assert_eq!(editor.buffer.lines, ["a=1 a=2", "a=3", "b"]);
+
+        editor.options.inccommand = false;
+        let ctx = preview(&mut editor, "%s/a/x/");
+        assert_eq!(ctx.lines[0], "a=1 a=2");
+    }
+
+    #[test]
+    fn search_matches_are_highlighted_until_noh() {
+        let mut editor = Editor::new(20, 5, None);
+        editor.buffer.lines = vec!["a\tab ab".to_string(), "b".to_string()];
+        let render = |editor: &Editor| {
+            let mut ctx = RenderContext::new(20, 5);
+            HighlightSearchPlugin.on_render(editor, &mut ctx);
+            ctx.spans[0]
+                .iter()
+                .map(|span| (span.start, span.len))
+                .collect::<Vec<_>>()
+        };
+        assert!(render(&editor).is_empty());
+
+        SearchPlugin.on_command(&mut editor, "/ab");
+        assert!(editor.search.highlight);
+        assert_eq!(render(&editor), vec![(8, 2), (11, 2)]);
+
+        SearchPlugin.on_command(&mut editor, "noh");
+        assert!(render(&editor).is_empty());
+        SearchPlugin.on_event(&mut editor, &key(KeyCode::Char('n'), KeyModifiers::NONE));
+        assert_eq!(render(&editor).len(), 2);
+    }
+
+    #[test]
+    fn jump_motions_return_with_ctrl_o() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = (0..50).map(|n| n.to_string()).collect();
+        editor.goto_position(10, 0);
+        let mut motion = MotionPlugin;
+        let mut jumps = JumpListPlugin;
+
+        motion.on_event(&mut editor, &key(KeyCode::Char('G'), KeyModifiers::SHIFT));
+        assert_eq!(editor.cursor.row, 49);
+        motion.on_event(&mut editor, &key(KeyCode::Char('g'), KeyModifiers::NONE));
+        motion.on_event(&mut editor, &key(KeyCode::Char('g'), KeyModifiers::NONE));
+        assert_eq!(editor.cursor.row, 0);
+        assert!(editor.pending_keys.is_empty());
+
+        jumps.on_event(&mut editor, &key(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert_eq!(editor.cursor.row, 49);
+        jumps.on_event(&mut editor, &key(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert_eq!(editor.cursor.row, 10);
+        jumps.on_event(&mut editor, &key(KeyCode::Tab, KeyModifiers::NONE));
+        assert_eq!(editor.cursor.row, 49);
+        jumps.on_event(&mut editor, &key(KeyCode::Tab, KeyModifiers::NONE));
+        assert_eq!(editor.cursor.row, 0);
+    }
+
+    fn type_keys(plugin: &mut impl Plugin, editor: &mut Editor, keys: &str) {
+        for ch in keys.chars() {
+            let code = if ch == '\n' {
+                KeyCode::Enter
+            } else {
+                KeyCode::Char(ch)
+            };
+            plugin.on_event(editor, &key(code, KeyModifiers::NONE));
+        }
+    }
+
+    fn register_text(editor: &Editor, name: char) -> Option<&str> {
+        editor
+            .registers
+            .register(name)
+            .map(|register| register.text.as_str())
+    }
+
+    /// Sends keys through `plugins` the way the main loop does.
+    fn type_keys_through(plugins: &mut [&mut dyn Plugin], editor: &mut Editor, keys: &str) {
+        for ch in keys.chars() {
+            let event = key(KeyCode::Char(ch), KeyModifiers::NONE);
+            let consumed = plugins
+                .iter_mut()
+                .any(|plugin| plugin.on_event(editor, &event) == EventResult::Consumed);
+            if !consumed {
+                editor.pending_keys.clear();
+            }
+        }
+    }
+
+    #[test]
+    fn abbreviations_expand_after_whole_words() {
+        let mut editor = Editor::new(80, 24, None);
+        let mut abbreviations = AbbreviationPlugin;
+        abbreviations.on_command(&mut editor, "iabbrev teh the");
+        abbreviations.on_command(&mut editor, "iab sig Best regards");
+        editor.mode = Mode::Insert;
+        let mut insert = InsertPlugin::new();
+
+        type_keys(&mut insert, &mut editor, "teh xteh teh.\nsig\n");
+        assert_eq!(
+            editor.buffer.lines,
+            vec!["the xteh the.", "Best regards", ""]
+        );
+
+        abbreviations.on_command(&mut editor, "iunabbrev teh");
+        type_keys(&mut insert, &mut editor, "teh ");
+        assert_eq!(editor.buffer.lines[2], "teh ");
+    }
+
+    #[test]
+    fn ctrl_n_and_ctrl_p_cycle_through_buffer_words() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec![
+            "format foreach".to_string(),
+            "fo".to_string(),
+            "for_each forward".to_string(),
+        ];
+        editor.cursor = Cursor { row: 1, col: 2 };
+        editor.mode = Mode::Insert;
+        let mut plugin = CompletionPlugin::new();
+        let ctrl = |ch| key(KeyCode::Char(ch), KeyModifiers::CONTROL);
+
+        plugin.on_event(&mut editor, &ctrl('n'));
+        assert_eq!(editor.buffer.lines[1], "for_each");
+        plugin.on_event(&mut editor, &ctrl('n'));
+        assert_eq!(editor.buffer.lines[1], "forward");
+        plugin.on_event(&mut editor, &ctrl('p'));
+        plugin.on_event(&mut editor, &ctrl('p'));
+        assert_eq!(editor.buffer.lines[1], "fo");
+        plugin.on_event(&mut editor, &ctrl('p'));
+        assert_eq!(editor.buffer.lines[1], "foreach");
+        assert_eq!(editor.cursor.col, 7);
+    }
+
+    #[test]
+    fn palette_filters_entries_and_runs_the_selection() {
+        let mut editor = Editor::new(40, 10, None);
+        editor.registry.add(&MotionPlugin);
+        editor.registry.add(&TrailingWhitespacePlugin);
+        let mut plugin = PalettePlugin;
+        let type_text = |editor: &mut Editor, plugin: &mut PalettePlugin, text: &str| {
+            for ch in text.chars() {
+                plugin.on_event(editor, &key(KeyCode::Char(ch), KeyModifiers::NONE));
+            }
+        };
+
+        plugin.on_command(&mut editor, "palette");
+        type_text(&mut editor, &mut plugin, "trimw");
+        let mut ctx = RenderContext::new(40, 10);
+        PaletteRenderPlugin.on_render(&editor, &mut ctx);
+        assert_eq!(ctx.lines[0], "┌─Command Palette──────────────────────┐");
+        assert_eq!(ctx.lines[1], "│> trimw                               │");
+        assert_eq!(ctx.lines[3], "│ :TrimWhitespace  Strip trailing white│");
+        assert_eq!(ctx.lines[4], "└─1/1──────────────────────────────────┘");
+        assert_eq!(ctx.cursor, Some((1, 8)));
+        assert_eq!(
+            ctx.style_at(3, 1),
+            editor.theme.style(HighlightGroup::PmenuSel)
+        );
+        plugin.on_event(&mut editor, &key(KeyCode::Enter, KeyModifiers::NONE));
+        assert!(editor.palette.is_none());
+        assert_eq!(editor.next_command().as_deref(), Some("TrimWhitespace"));
+
+        // A key binding is typed as if pressed; a command needing arguments
+        // is left on the command line.
+        plugin.on_event(&mut editor, &key(KeyCode::Char('P'), KeyModifiers::CONTROL));
+        type_text(&mut editor, &mut plugin, "first line");
+        plugin.on_event(&mut editor, &key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(
+            editor.next_input(),
+            Some(key(KeyCode::Char('g'), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            editor.next_input(),
+            Some(key(KeyCode::Char('g'), KeyModifiers::NONE))
+        );
+        plugin.on_command(&mut editor, "palette");
+        type_text(&mut editor, &mut plugin, ":cursor");
+        plugin.on_event(&mut editor, &key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(editor.mode, Mode::Command);
+        assert_eq!(editor.command_line.input, "cursor ");
+    }
+
+    #[test]
+    fn which_key_lists_continuations_of_pending_keys_after_the_delay() {
+        let mut editor = Editor::new(80, 8, None);
+        editor.registry.add(&MotionPlugin);
+        editor.registry.add(&UndoPlugin);
+        editor.registry.add(&ChangeListPlugin);
+        let mut plugin = WhichKeyPlugin::new();
+        let render = |editor: &Editor, plugin: &mut WhichKeyPlugin| {
+            let mut ctx = RenderContext::new(80, 8);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            plugin.on_render(editor, &mut ctx);
+            ctx
+        };
+
+        editor.pending_keys.push('g');
+        assert_eq!(render(&editor, &mut plugin).lines[1], "~");
+        // Pretend the delay has passed.
+        plugin.pending = Some(("g".to_string(), Instant::now() - Duration::from_secs(1)));
+        let ctx = render(&editor, &mut plugin);
+        assert_eq!(ctx.lines[2], format!("┌─g{}┐", "─".repeat(76)));
+        assert_eq!(
+            ctx.lines[3],
+            format!(
+                "│ +  Go to the next text state       ,  Go to a newer edit location{}│",
+                " ".repeat(12)
+            )
+        );
+        assert_eq!(
+            ctx.lines[5],
+            format!("│ g  Go to the first line{}│", " ".repeat(54))
+        );
+        assert!(ctx.style_at(5, 2).attributes.has(Attribute::Bold));
+        assert!(!ctx.style_at(5, 3).attributes.has(Attribute::Bold));
+        assert_eq!(ctx.lines[6], format!("└{}┘", "─".repeat(78)));
+
+        editor.options.apply("whichkey=0").unwrap();
+        assert_eq!(render(&editor, &mut plugin).lines[1], "~");
+    }
+
+    #[test]
+    fn cheat_sheet_lists_registered_keys_by_mode() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.registry.add(&UndoPlugin);
+        editor.registry.add(&VisualPlugin);
+        editor.registry.add(&CheatSheetPlugin);
+        let question = key(KeyCode::Char('?'), KeyModifiers::NONE);
+
+        MotionPlugin.on_event(&mut editor, &key(KeyCode::Char('g'), KeyModifiers::NONE));
+        assert_eq!(
+            CheatSheetPlugin.on_event(&mut editor, &question),
+            EventResult::Consumed
+        );
+        assert!(editor.pending_keys.is_empty());
+        let view = editor.scratch.as_ref().unwrap();
+        assert_eq!(
+            view.lines[..6],
+            [
+                "Normal mode",
+                "  u      Undo the last change",
+                "  <C-r>  Redo the last undone change",
+                "  g-     Go to the previous text state",
+                "  g+     Go to the next text state",
+                "  g?     Show the key bindings of every mode",
+            ]
+        );
+        assert_eq!(view.lines[6..8], ["", "Visual mode"]);
+        CheatSheetPlugin.on_event(&mut editor, &question);
+        assert!(editor.scratch.is_none());
+        // Without `g` first, `?` is left to start a backward search.
+        assert_eq!(
+            CheatSheetPlugin.on_event(&mut editor, &question),
+            EventResult::Ignored
+        );
+    }
+
+    #[test]
+    fn completion_menu_lists_candidates_under_the_word() {
+        let mut editor = Editor::new(20, 6, None);
+        editor.buffer.lines = ["a foo food", "  fo"].map(String::from).to_vec();
+        editor.cursor = Cursor { row: 1, col: 4 };
+        editor.mode = Mode::Insert;
+        let mut plugin = CompletionPlugin::new();
+        let render = |editor: &Editor| {
+            let mut ctx = RenderContext::new(20, 6);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            MenuRenderPlugin.on_render(editor, &mut ctx);
+            ctx
+        };
+
+        plugin.on_event(&mut editor, &key(KeyCode::Char('n'), KeyModifiers::CONTROL));
+        plugin.on_event(&mut editor, &key(KeyCode::Down, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines[1], "  food");
+        let ctx = render(&editor);
+        assert_eq!(ctx.lines[2], "~  w foo  ");
+        assert_eq!(ctx.lines[3], "~  w food ");
+        assert_eq!(
+            ctx.style_at(3, 6),
+            editor.theme.style(HighlightGroup::PmenuSel)
+        );
+        assert_eq!(
+            ctx.style_at(2, 6),
+            editor.theme.style(HighlightGroup::Pmenu)
+        );
+
+        // Esc puts back what was typed and stays in Insert mode.
+        plugin.on_event(&mut editor, &key(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines[1], "  fo");
+        assert!(editor.menu.is_none());
+        assert_eq!(render(&editor).lines[2], "~");
+
+        // Enter takes the selected item without starting a new line.
+        plugin.on_event(&mut editor, &key(KeyCode::Char('p'), KeyModifiers::CONTROL));
+        let consumed = plugin.on_event(&mut editor, &key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(consumed, EventResult::Consumed);
+        assert_eq!(editor.buffer.lines, ["a foo food", "  food"]);
+        assert!(editor.menu.is_none());
+    }
+
+    #[test]
+    fn ctrl_x_completes_from_dictionary_and_thesaurus_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let words = dir.path().join("words");
+        std::fs::write(&words, "prose\nprosody\nprost\npro\n").unwrap();
+        let thesaurus = dir.path().join("thesaurus");
+        std::fs::write(&thesaurus, "happy glad cheerful\nsad unhappy\n").unwrap();
+        let mut editor = Editor::new(30, 6, None);
+        editor.options.dictionary = words.display().to_string();
+        editor.buffer.lines = vec!["pros".to_string()];
+        editor.cursor.col = 4;
+        editor.mode = Mode::Insert;
+        let mut plugin = CompletionPlugin::new();
+        let ctrl = |ch| key(KeyCode::Char(ch), KeyModifiers::CONTROL);
+
+        plugin.on_event(&mut editor, &ctrl('x'));
+        plugin.on_event(&mut editor, &ctrl('k'));
+        let menu = editor.menu.as_ref().unwrap();
+        let texts: Vec<&str> = menu.items.iter().map(|item| item.text.as_str()).collect();
+        assert_eq!(texts, ["prose", "prosody", "prost"]);
+        assert_eq!(menu.items[0].kind, ItemKind::Dictionary);
+        assert_eq!(editor.buffer.lines[0], "prose");
+        plugin.on_event(&mut editor, &key(KeyCode::Enter, KeyModifiers::NONE));
+        assert!(editor.menu.is_none());
+
+        editor.buffer.lines = vec!["so glad".to_string()];
+        editor.cursor.col = 7;
+        plugin.on_event(&mut editor, &ctrl('x'));
+        plugin.on_event(&mut editor, &ctrl('t'));
+        assert_eq!(editor.status, "'thesaurus' option is empty");
+        editor.options.thesaurus = thesaurus.display().to_string();
+        plugin.on_event(&mut editor, &ctrl('x'));
+        plugin.on_event(&mut editor, &ctrl('t'));
+        let menu = editor.menu.as_ref().unwrap();
+        let texts: Vec<&str> = menu.items.iter().map(|item| item.text.as_str()).collect();
+        assert_eq!(texts, ["happy", "cheerful"]);
+        assert_eq!(editor.buffer.lines[0], "so happy");
+        plugin.on_event(&mut editor, &ctrl('n'));
+        assert_eq!(editor.buffer.lines[0], "so cheerful");
+
+        // Another key after Ctrl-X does its usual job.
+        plugin.on_event(&mut editor, &key(KeyCode::Enter, KeyModifiers::NONE));
+        plugin.on_event(&mut editor, &ctrl('x'));
+        let result = plugin.on_event(&mut editor, &key(KeyCode::Char('a'), KeyModifiers::NONE));
+        assert_eq!(result, EventResult::Ignored);
+        assert!(editor.menu.is_none());
+    }
+
+    #[test]
+    fn mouse_click_moves_cursor_and_drag_selects() {
+        use crossterm::event::MouseEvent;
+
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["first line".to_string(), "second".to_string()];
+        let mut plugin = MousePlugin;
+        let mouse = |kind, column, row| {
+            Event::Mouse(MouseEvent {
+                kind,
+                column,
+                row,
+                modifiers: KeyModifiers::NONE,
+            })
+        };
+
+        plugin.on_event(
+            &mut editor,
+            &mouse(MouseEventKind::Down(MouseButton::Left), 40, 1),
+        );
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 6 });
+        plugin.on_event(
+            &mut editor,
+            &mouse(MouseEventKind::Down(MouseButton::Left), 2, 0),
+        );
+        plugin.on_event(
+            &mut editor,
+            &mouse(MouseEventKind::Drag(MouseButton::Left), 3, 1),
+        );
+        assert_eq!(editor.mode, Mode::Visual);
+        assert_eq!(
+            editor.selection(),
+            Some((Cursor { row: 0, col: 2 }, Cursor { row: 1, col: 4 }))
+        );
+
+        let on_status_row = mouse(MouseEventKind::Down(MouseButton::Left), 0, 23);
+        assert_eq!(
+            plugin.on_event(&mut editor, &on_status_row),
+            EventResult::Ignored
+        );
+        plugin.on_event(
+            &mut editor,
+            &mouse(MouseEventKind::Down(MouseButton::Left), 0, 0),
+        );
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn autochdir_skips_remote_files() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("scp://host/dir/notes.txt")));
+        editor.options.autochdir = true;
+        DirectoryPlugin.on_autocmd(&mut editor, AutoEvent::BufReadPost, "");
+        assert_eq!(editor.status, "");
+        assert_eq!(
+            editor.file_path,
+            Some(PathBuf::from("scp://host/dir/notes.txt"))
+        );
+    }
+
+    #[test]
+    fn a_file_that_fails_to_load_is_not_written_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.txt");
+        // Large enough to load in the background, and not UTF-8 at the end.
+        let mut contents = "line\n"
+            .repeat(crate::editor::ASYNC_LOAD_THRESHOLD as usize / 5 + 1)
+            .into_bytes();
+        contents.extend_from_slice(b"\xff\xfe\n");
+        std::fs::write(&path, &contents).unwrap();
+
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        FileCommandPlugin.on_init(&mut editor);
+        assert!(editor.is_loading());
+        while editor.is_loading() {
+            editor.poll_loading();
+            std::thread::yield_now();
+        }
+        assert!(editor.status.starts_with("Open failed"));
+        FileCommandPlugin.on_command(&mut editor, "w");
+        assert_eq!(editor.status, "No file name");
+        assert_eq!(std::fs::read(&path).unwrap(), contents);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sudo_write_pipes_the_buffer_to_tee_in_the_foreground() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        editor.buffer.lines = vec!["127.0.0.1 localhost".to_string()];
+        editor.dirty = true;
+        FileCommandPlugin.on_command(&mut editor, "SudoWrite");
+        let mut job = editor.terminal_job.take().unwrap();
+        assert_eq!(job.command.get_program(), "sudo");
+        let args: Vec<_> = job.command.get_args().collect();
+        assert_eq!(args, ["tee".as_ref(), "--".as_ref(), path.as_os_str()]);
+        assert_eq!(job.input, b"127.0.0.1 localhost\n");
+
+        // Run the same pipe without the sudo.
+        job.command = Command::new("tee");
+        job.command.arg(&path).stdout(Stdio::null());
+        job.run(&mut editor);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "127.0.0.1 localhost\n"
+        );
+        assert!(!editor.dirty);
+        assert!(editor.status.contains("with sudo"));
+
+        FileCommandPlugin.on_command(&mut editor, "SudoWrite");
+        let mut job = editor.terminal_job.take().unwrap();
+        job.command = Command::new("false");
+        job.run(&mut editor);
+        assert!(editor.status.starts_with("SudoWrite failed"));
+
+        // Compressed files are piped to tee compressed.
+        FileCommandPlugin.on_command(&mut editor, &format!("SudoWrite {}.gz", path.display()));
+        let job = editor.terminal_job.take().unwrap();
+        let text = Compression::Gzip.decompress(&job.input).unwrap();
+        assert_eq!(text, b"127.0.0.1 localhost\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sudo_write_expands_its_file_name_and_refuses_scratch_buffers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        FileCommandPlugin.on_command(&mut editor, "SudoWrite %.bak");
+        let job = editor.terminal_job.take().unwrap();
+        let args: Vec<_> = job.command.get_args().collect();
+        let backup = dir.path().join("hosts.bak");
+        assert_eq!(args, ["tee".as_ref(), "--".as_ref(), backup.as_os_str()]);
+
+        OptionsPlugin.on_command(&mut editor, "set bt=nofile");
+        FileCommandPlugin.on_command(&mut editor, &format!("SudoWrite {}", path.display()));
+        assert!(editor.terminal_job.is_none());
+        assert_eq!(editor.status, "Cannot write, 'buftype' is nofile");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn encrypted_files_pass_through_their_cipher_in_the_foreground() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.age");
+        std::fs::write(&path, "hidden\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        FileCommandPlugin.on_init(&mut editor);
+        assert_eq!(
+            editor.file_path, None,
+            "not the buffer's file until decrypted"
+        );
+        let mut job = editor.terminal_job.take().unwrap();
+        assert_eq!(job.command.get_program(), "age");
+        assert_eq!(job.input, b"hidden\n");
+
+        // Stand-ins for age: cat "decrypts", tr "encrypts".
+        job.command = Command::new("cat");
+        job.command.stdout(Stdio::piped());
+        job.run(&mut editor);
+        assert_eq!(editor.buffer.lines, ["hidden"]);
+        assert_eq!(editor.file_path.as_deref(), Some(path.as_path()));
+
+        editor.buffer.lines = vec!["plain".to_string()];
+        editor.dirty = true;
+        FileCommandPlugin.on_command(&mut editor, "w");
+        let mut job = editor.terminal_job.take().unwrap();
+        assert_eq!(job.input, b"plain\n");
+        job.command = Command::new("tr");
+        job.command.args(["a-z", "A-Z"]).stdout(Stdio::piped());
+        job.run(&mut editor);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "PLAIN\n");
+        assert!(!editor.dirty);
+
+        FileCommandPlugin.on_command(&mut editor, "SudoWrite");
+        assert!(editor.terminal_job.is_none());
+        FileCommandPlugin.on_command(&mut editor, "wq");
+        let mut job = editor.terminal_job.take().unwrap();
+        job.command = Command::new("false");
+        job.run(&mut editor);
+        assert!(editor.status.starts_with("Encrypt failed"));
+        assert!(!editor.should_quit);
+    }
+
+    #[test]
+    fn readonly_buffers_are_written_only_with_a_bang() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "old\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        FileCommandPlugin.on_init(&mut editor);
+        assert!(!editor.options.readonly);
+        editor.options.readonly = true;
+        editor.buffer.lines = vec!["new".to_string()];
+        editor.dirty = true;
+
+        FileCommandPlugin.on_command(&mut editor, "w");
+        assert!(editor.status.contains("readonly"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+        FileCommandPlugin.on_command(&mut editor, "w!");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        // Other files may be written.
+        let copy = dir.path().join("copy.txt");
+        FileCommandPlugin.on_command(&mut editor, &format!("w {}", copy.display()));
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn paste_inserts_text_with_one_revision() {
+        let mut editor = Editor::new(80, 24, None);
+        editor
+            .abbreviations
+            .insert("teh".to_string(), "the".to_string());
+        editor.mode = Mode::Insert;
+        let revision = editor.revision();
+
+        let paste = Event::Paste("teh one\r\n    two\rthree".to_string());
+        assert_eq!(
+            PastePlugin.on_event(&mut editor, &paste),
+            EventResult::Consumed
+        );
+        assert_eq!(editor.buffer.lines, vec!["teh one", "    two", "three"]);
+        assert_eq!(editor.cursor, Cursor { row: 2, col: 5 });
+        assert_eq!(editor.revision(), revision + 1);
+
+        editor.mode = Mode::Command;
+        editor.command_line.open(':');
+        PastePlugin.on_event(&mut editor, &Event::Paste("w out.txt\nq".to_string()));
+        assert_eq!(editor.command_line.input, "w out.txt");
+    }
+
+    #[test]
+    fn shift_keys_indent_lines() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["a".to_string(), "b".to_string()];
+        let mut plugin = IndentPlugin;
+        let press = |ch| key(KeyCode::Char(ch), KeyModifiers::NONE);
+
+        plugin.on_event(&mut editor, &press('>'));
+        plugin.on_event(&mut editor, &press('>'));
+        assert_eq!(editor.buffer.lines, vec!["    a", "b"]);
+
+        editor.start_visual();
+        editor.cursor.row = 1;
+        plugin.on_event(&mut editor, &press('>'));
+        assert_eq!(editor.buffer.lines, vec!["        a", "    b"]);
+        assert_eq!(editor.mode, Mode::Normal);
+
+        plugin.on_event(&mut editor, &press('<'));
+        plugin.on_event(&mut editor, &press('<'));
+        assert_eq!(editor.buffer.lines[1], "b");
+    }
+
+    #[test]
+    fn gc_toggles_comments_over_lines_motions_and_selections() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("main.rs")));
+        editor.buffer.lines = ["fn f() {", "    a();", "    b();", "}", "", "x"]
+            .map(String::from)
+            .to_vec();
+        editor.cursor = Cursor { row: 1, col: 4 };
+
+        editor.pending_keys.push('g');
+        type_keys(&mut CommentPlugin, &mut editor, "cc");
+        assert_eq!(editor.buffer.lines[1], "    // a();");
+        assert_eq!(editor.cursor.col, 7);
+        assert!(editor.pending_keys.is_empty());
+
+        editor.pending_keys.push('g');
+        type_keys(&mut CommentPlugin, &mut editor, "cj");
+        assert_eq!(editor.buffer.lines[1..3], ["    // // a();", "    // b();"]);
+
+        editor.cursor = Cursor { row: 2, col: 0 };
+        editor.pending_keys.push('g');
+        type_keys(&mut CommentPlugin, &mut editor, "cip");
+        assert_eq!(
+            editor.buffer.lines[..4],
+            ["// fn f() {", "//     // // a();", "//     // b();", "// }"]
+        );
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 0 });
+
+        editor.start_visual();
+        editor.cursor.row = 3;
+        editor.pending_keys.push('g');
+        type_keys(&mut CommentPlugin, &mut editor, "c");
+        assert_eq!(editor.buffer.lines[0], "fn f() {");
+        assert_eq!(editor.buffer.lines[3], "}");
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn subword_motions_and_objects_work_with_operators() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["let parseHttpHeader = max_line_len;".to_string()];
+        editor.cursor.col = 4;
+        let mut plugins: [&mut dyn Plugin; 3] = [
+            &mut DiagnosticPlugin,
+            &mut OperatorPlugin,
+            &mut MotionPlugin,
+        ];
+
+        type_keys_through(&mut plugins, &mut editor, "]w]w");
+        assert_eq!(editor.cursor.col, 13);
+        type_keys_through(&mut plugins, &mut editor, "[w");
+        assert_eq!(editor.cursor.col, 9);
+        assert!(editor.pending_keys.is_empty());
+
+        type_keys_through(&mut plugins, &mut editor, "d]w");
+        assert_eq!(editor.buffer.lines[0], "let parseHeader = max_line_len;");
+        editor.cursor.col = 24;
+        type_keys_through(&mut plugins, &mut editor, "dav");
+        assert_eq!(editor.buffer.lines[0], "let parseHeader = max_len;");
+        type_keys_through(&mut plugins, &mut editor, "civ");
+        assert_eq!(editor.mode, Mode::Insert);
+        assert_eq!(editor.buffer.lines[0], "let parseHeader = max_;");
+    }
+
+    #[test]
+    fn surround_adds_changes_and_deletes_delimiters() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["say hello world".to_string()];
+        editor.cursor.col = 5;
+
+        type_keys_through(
+            &mut [&mut SurroundPlugin, &mut OperatorPlugin],
+            &mut editor,
+            "ysiw\"",
+        );
+        assert_eq!(editor.buffer.lines[0], "say \"hello\" world");
+        assert_eq!(editor.cursor.col, 4);
+        assert!(editor.pending_keys.is_empty());
+
+        type_keys_through(
+            &mut [&mut SurroundPlugin, &mut OperatorPlugin],
+            &mut editor,
+            "cs\"'",
+        );
+        assert_eq!(editor.buffer.lines[0], "say 'hello' world");
+
+        type_keys_through(
+            &mut [&mut SurroundPlugin, &mut OperatorPlugin],
+            &mut editor,
+            "cs'(",
+        );
+        assert_eq!(editor.buffer.lines[0], "say ( hello ) world");
+
+        type_keys_through(
+            &mut [&mut SurroundPlugin, &mut OperatorPlugin],
+            &mut editor,
+            "ds(",
+        );
+        assert_eq!(editor.buffer.lines[0], "say hello world");
+
+        type_keys_through(
+            &mut [&mut SurroundPlugin, &mut OperatorPlugin],
+            &mut editor,
+            "yssb",
+        );
+        assert_eq!(editor.buffer.lines[0], "(say hello world)");
+
+        type_keys_through(
+            &mut [&mut SurroundPlugin, &mut OperatorPlugin],
+            &mut editor,
+            "ds[",
+        );
+        assert_eq!(editor.status, "No surrounding [ found");
+        assert_eq!(editor.buffer.lines[0], "(say hello world)");
+
+        editor.cursor.col = 1;
+        editor.start_visual();
+        editor.cursor.col = 3;
+        type_keys_through(
+            &mut [&mut SurroundPlugin, &mut OperatorPlugin],
+            &mut editor,
+            "S*",
+        );
+        assert_eq!(editor.buffer.lines[0], "(*say* hello world)");
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn operators_yank_delete_and_put_through_registers() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["one two", "  three", "four"].map(String::from).to_vec();
+
+        type_keys(&mut OperatorPlugin, &mut editor, "yw");
+        assert_eq!(register_text(&editor, '"'), Some("one "));
+        assert_eq!(register_text(&editor, '0'), Some("one "));
+        type_keys(&mut OperatorPlugin, &mut editor, "\"add");
+        assert_eq!(register_text(&editor, 'a'), Some("one two\n"));
+        assert_eq!(editor.buffer.lines, vec!["  three", "four"]);
+        assert_eq!(editor.cursor.col, 2);
+
+        type_keys(&mut OperatorPlugin, &mut editor, "x");
+        assert_eq!(register_text(&editor, '1'), Some("t"));
+        assert_eq!(register_text(&editor, '2'), None);
+        type_keys(&mut OperatorPlugin, &mut editor, "\"ap");
+        assert_eq!(editor.buffer.lines, vec!["  hree", "one two", "four"]);
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 0 });
+        type_keys(&mut OperatorPlugin, &mut editor, "P");
+        assert_eq!(editor.buffer.lines[1], "tone two");
+        assert_eq!(editor.cursor.col, 0);
+
+        editor.cursor.col = 1;
+        type_keys(&mut OperatorPlugin, &mut editor, "cwX");
+        assert_eq!(editor.buffer.lines[1], "t two");
+        assert_eq!(editor.mode, Mode::Insert);
+        editor.mode = Mode::Normal;
+
+        type_keys(&mut OperatorPlugin, &mut editor, "dj");
+        assert_eq!(editor.buffer.lines, vec!["  hree"]);
+        type_keys(&mut OperatorPlugin, &mut editor, "\"9p");
+        assert_eq!(editor.status, "Nothing in register 9");
+        type_keys(&mut OperatorPlugin, &mut editor, "\"!");
+        assert_eq!(editor.status, "Invalid register name: !");
+    }
+
+    #[test]
+    fn visual_operators_use_the_selection() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["abc def".to_string()];
+        editor.start_visual();
+        editor.cursor.col = 2;
+        VisualPlugin.on_event(&mut editor, &key(KeyCode::Char('y'), KeyModifiers::NONE));
+        assert_eq!(register_text(&editor, '"'), Some("abc"));
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.cursor.col, 0);
+
+        editor.cursor.col = 4;
+        editor.start_visual();
+        editor.cursor.col = 6;
+        VisualPlugin.on_event(&mut editor, &key(KeyCode::Char('c'), KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines[0], "abc ");
+        assert_eq!(register_text(&editor, '"'), Some("def"));
+        assert_eq!(editor.mode, Mode::Insert);
+    }
+
+    #[test]
+    fn bracket_put_matches_the_cursor_line_indent() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["if a {", "    if b {", "        x();", "", "    }", "}"]
+            .map(String::from)
+            .to_vec();
+        editor.goto_position(1, 0);
+        type_keys(&mut OperatorPlugin, &mut editor, "yj");
+        editor.goto_position(0, 0);
+        let mut plugins: [&mut dyn Plugin; 2] = [&mut DiagnosticPlugin, &mut OperatorPlugin];
+        type_keys_through(&mut plugins, &mut editor, "]p");
+        assert_eq!(editor.buffer.lines[1..3], ["if b {", "    x();"]);
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 0 });
+
+        editor.goto_position(7, 0);
+        type_keys_through(&mut plugins, &mut editor, "\"0[p");
+        assert_eq!(editor.buffer.lines[7..], ["if b {", "    x();", "}"]);
+
+        // Charwise text is put as `p` and `P` would.
+        editor.registers.record(
+            None,
+            Register::new("z".to_string(), RegisterKind::Charwise),
+            false,
+        );
+        type_keys_through(&mut plugins, &mut editor, "]p");
+        assert_eq!(editor.buffer.lines[7], "izf b {");
+    }
+
+    #[test]
+    fn uppercase_registers_append_and_keep_the_register_type() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["one two", "three", "four"].map(String::from).to_vec();
+
+        type_keys(&mut OperatorPlugin, &mut editor, "\"ayw");
+        editor.cursor.col = 4;
+        type_keys(&mut OperatorPlugin, &mut editor, "\"Ayw");
+        assert_eq!(register_text(&editor, 'a'), Some("one two"));
+        assert_eq!(register_text(&editor, '"'), Some("one two"));
+        type_keys(&mut OperatorPlugin, &mut editor, "\"Ayy");
+        let register = editor.registers.register('A').unwrap();
+        assert_eq!(register.text, "one two\none two\n");
+        assert_eq!(register.kind, RegisterKind::Linewise);
+
+        // Charwise text ending in a newline is still put within the line.
+        editor.cursor = Cursor { row: 1, col: 4 };
+        editor.registers.record(
+            Some('b'),
+            Register::new("X\n".to_string(), RegisterKind::Charwise),
+            true,
+        );
+        type_keys(&mut OperatorPlugin, &mut editor, "\"bp");
+        assert_eq!(editor.buffer.lines[1..], ["threeX", "", "four"]);
+
+        editor.cursor = Cursor { row: 0, col: 0 };
+        type_keys(&mut OperatorPlugin, &mut editor, "\"ap");
+        assert_eq!(editor.buffer.lines[..3], ["one two", "one two", "one two"]);
+    }
+
+    #[test]
+    fn registers_listing_shows_control_characters() {
+        let mut editor = Editor::new(80, 24, None);
+        OperatorPlugin.on_command(&mut editor, "registers");
+        assert_eq!(editor.status, "No registers to show");
+
+        editor
+            .registers
+            .record(Some('a'), Register::guess("x\ty\n".to_string()), true);
+        editor
+            .registers
+            .record(Some('b'), Register::guess("b".to_string()), true);
+        OperatorPlugin.on_command(&mut editor, "reg");
+        let scratch = editor.scratch.take().expect("register listing");
+        assert_eq!(
+            scratch.lines,
+            vec!["c  \"\"   b", "l  \"a   x^Iy^J", "c  \"b   b"]
+        );
+
+        OperatorPlugin.on_command(&mut editor, "reg a");
+        let scratch = editor.scratch.take().expect("register listing");
+        assert_eq!(scratch.lines, vec!["l  \"a   x^Iy^J"]);
+    }
+
+    #[test]
+    fn marks_are_set_jumped_to_listed_and_deleted() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["one".to_string(), "  two three".to_string()];
+        editor.cursor = Cursor { row: 1, col: 6 };
+        type_keys(&mut MarkPlugin, &mut editor, "mbmc");
+        editor.cursor = Cursor { row: 0, col: 2 };
+        type_keys(&mut MarkPlugin, &mut editor, "'b");
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 2 });
+        type_keys(&mut MarkPlugin, &mut editor, "`c");
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 6 });
+        type_keys(&mut MarkPlugin, &mut editor, "'z");
+        assert_eq!(editor.status, "Mark not set: z");
+        type_keys(&mut MarkPlugin, &mut editor, "m1");
+        assert_eq!(editor.status, "Invalid mark name: 1");
+
+        MarkPlugin.on_command(&mut editor, "marks");
+        let scratch = editor.scratch.take().expect("mark listing");
+        assert_eq!(
+            scratch.lines,
+            vec![
+                "mark  line  col text",
+                " b      2    6 two three",
+                " c      2    6 two three",
+            ]
+        );
+        MarkPlugin.on_command(&mut editor, "delmarks a-b");
+        assert_eq!(editor.marks.keys().collect::<Vec<_>>(), vec![&'c']);
+        MarkPlugin.on_command(&mut editor, "delm!");
+        MarkPlugin.on_command(&mut editor, "marks");
+        assert_eq!(editor.status, "No marks set");
+    }
+
+    #[test]
+    fn uppercase_marks_reopen_their_file_and_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        std::fs::write(&a, "one\n  two\n").unwrap();
+        std::fs::write(&b, "bee\n").unwrap();
+        let mut editor = Editor::new(80, 24, None);
+        editor.state_dir = Some(dir.path().join("state"));
+        type_keys(&mut MarkPlugin, &mut editor, "mA");
+        assert_eq!(editor.status, "No file name");
+
+        FileCommandPlugin.on_command(&mut editor, &format!("e {}", a.display()));
+        editor.cursor = Cursor { row: 1, col: 3 };
+        type_keys(&mut MarkPlugin, &mut editor, "mA");
+        assert_eq!(ex::parse_range("'A", &editor).unwrap().0.unwrap().start, 1);
+        FileCommandPlugin.on_command(&mut editor, &format!("e {}", b.display()));
+        MarkPlugin.on_command(&mut editor, "marks A");
+        let scratch = editor.scratch.take().expect("mark listing");
+        assert_eq!(scratch.lines[1], format!(" A      2    3 {}", a.display()));
+
+        // A new session reads the mark back.
+        let mut editor = Editor::new(80, 24, Some(b.clone()));
+        editor.state_dir = Some(dir.path().join("state"));
+        MarkPlugin.on_init(&mut editor);
+        type_keys(&mut MarkPlugin, &mut editor, "`A");
+        assert_eq!(editor.file_path, Some(a.clone()));
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 3 });
+        assert_eq!(editor.jumps.entries[0].path, Some(b));
+        type_keys(&mut MarkPlugin, &mut editor, "'A");
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 2 });
+
+        MarkPlugin.on_command(&mut editor, "delmarks A-C");
+        MarkPlugin.on_init(&mut editor);
+        assert!(editor.global_marks.is_empty());
+    }
+
+    #[test]
+    fn colon_in_visual_mode_prefills_the_selection_range() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        editor.start_visual();
+        editor.cursor.row = 1;
+        ModePlugin.on_event(&mut editor, &key(KeyCode::Char(':'), KeyModifiers::NONE));
+        assert_eq!(editor.mode, Mode::Command);
+        assert_eq!(editor.command_line.input, "'<,'>");
+        assert_eq!(editor.marks.get(&'>'), Some(&Cursor { row: 1, col: 0 }));
+    }
+
+    #[test]
+    fn jumps_listing_marks_the_current_position() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["first".to_string(), "  second".to_string()];
+        editor.push_jump();
+        editor.cursor = Cursor { row: 1, col: 3 };
+        editor.push_jump();
+        JumpListPlugin.on_command(&mut editor, "jumps");
+        let scratch = editor.scratch.take().expect("jump listing");
+        assert_eq!(
+            scratch.lines,
+            vec![
+                " jump line  col file/text",
+                "    2     1    0 first",
+                "    1     2    3 second",
+                ">",
+            ]
+        );
+
+        let control_o = key(KeyCode::Char('o'), KeyModifiers::CONTROL);
+        JumpListPlugin.on_event(&mut editor, &control_o);
+        JumpListPlugin.on_command(&mut editor, "ju");
+        let scratch = editor.scratch.take().expect("jump listing");
+        assert_eq!(scratch.lines[1], ">   0     1    0 first");
+        assert_eq!(scratch.lines[2], "    1     2    3 second");
+        assert_eq!(scratch.lines.len(), 3);
+    }
+
+    #[test]
+    fn line_commands_delete_yank_move_and_copy() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["one", "  two", "three", "four"].map(String::from).to_vec();
+        LineCommandPlugin.on_command(&mut editor, "2,3y a");
+        assert_eq!(register_text(&editor, 'a'), Some("  two\nthree\n"));
+        LineCommandPlugin.on_command(&mut editor, "1m$");
+        assert_eq!(editor.buffer.lines, ["  two", "three", "four", "one"]);
+        assert_eq!(editor.cursor.row, 3);
+        LineCommandPlugin.on_command(&mut editor, "1t.");
+        assert_eq!(
+            editor.buffer.lines,
+            ["  two", "three", "four", "one", "  two"]
+        );
+        assert_eq!(editor.cursor, Cursor { row: 4, col: 2 });
+        LineCommandPlugin.on_command(&mut editor, "1,2m1");
+        assert_eq!(editor.status, "Cannot move a range of lines into itself");
+        LineCommandPlugin.on_command(&mut editor, "2,$delete");
+        assert_eq!(editor.buffer.lines, ["  two"]);
+        assert_eq!(
+            register_text(&editor, '1'),
+            Some("three\nfour\none\n  two\n")
+        );
+        assert_eq!(editor.status, "4 fewer lines");
+        LineCommandPlugin.on_command(&mut editor, "d !");
+        assert_eq!(editor.status, "Invalid register name: !");
+        assert_eq!(
+            LineCommandPlugin.on_command(&mut editor, "delmarks a"),
+            EventResult::Ignored
+        );
+    }
+
+    #[test]
+    fn sort_orders_lines_with_flags() {
+        let mut editor = Editor::new(80, 24, None);
+        let lines = ["b10", "a2", "B1", "x", "a2", ""];
+        editor.buffer.lines = lines.map(String::from).to_vec();
+        SortPlugin.on_command(&mut editor, "sort");
+        assert_eq!(editor.buffer.lines, ["B1", "a2", "a2", "b10", "x", ""]);
+        SortPlugin.on_command(&mut editor, "sort! u");
+        assert_eq!(editor.buffer.lines, ["x", "b10", "a2", "B1", ""]);
+        SortPlugin.on_command(&mut editor, "%sort n");
+        assert_eq!(editor.buffer.lines, ["x", "B1", "a2", "b10", ""]);
+        SortPlugin.on_command(&mut editor, "2,4sort i");
+        assert_eq!(editor.buffer.lines, ["x", "a2", "B1", "b10", ""]);
+        SortPlugin.on_command(&mut editor, "sort q");
+        assert_eq!(editor.status, "Invalid flag: q");
+    }
+
+    #[test]
+    fn quickfix_commands_walk_the_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("a.txt");
+        let second = dir.path().join("b.txt");
+        std::fs::write(&first, "one\n  two\n").unwrap();
+        std::fs::write(&second, "two\n").unwrap();
+        let mut editor = Editor::new(80, 24, None);
+        let mut plugin = QuickfixPlugin::new();
+
+        plugin.on_command(&mut editor, "cnext");
+        assert_eq!(editor.status, "Quickfix list is empty");
+        plugin.on_command(&mut editor, &format!("grep tw {}", dir.path().display()));
+        assert_eq!(editor.status, "(1 of 2) two");
+        assert_eq!(editor.file_path.as_ref(), Some(&first));
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 2 });
+        plugin.on_command(&mut editor, "cn");
+        assert_eq!(editor.file_path.as_ref(), Some(&second));
+        plugin.on_command(&mut editor, "cn");
+        assert_eq!(editor.status, "No more items");
+        plugin.on_command(&mut editor, "cc 1");
+        assert_eq!(editor.file_path.as_ref(), Some(&first));
+        plugin.on_command(&mut editor, "cc 3");
+        assert_eq!(editor.status, "Invalid entry: 3");
+
+        plugin.on_command(&mut editor, "cdo s/w/W/ | d");
+        assert_eq!(
+            editor.next_command().as_deref(),
+            Some("s/w/W/"),
+            "commands run at the first entry"
+        );
+        assert_eq!(editor.next_command().as_deref(), Some("d"));
+        assert_eq!(editor.next_command().as_deref(), Some(QUICKFIX_BATCH_NEXT));
+        editor.dirty = true;
+        plugin.on_command(&mut editor, QUICKFIX_BATCH_NEXT);
+        assert_eq!(
+            editor.status,
+            "No write since last change (add ! to override)"
+        );
+        assert_eq!(editor.next_command(), None);
+    }
+
+    #[test]
+    fn preview_window_shows_entries_below_the_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("a.txt");
+        let second = dir.path().join("b.txt");
+        std::fs::write(&first, "one\ntwo\n").unwrap();
+        std::fs::write(&second, "x\ny\nthree\n").unwrap();
+        let mut editor = Editor::new(40, 10, None);
+        let mut quickfix = QuickfixPlugin::new();
+        let mut preview = PreviewWindowPlugin;
+        quickfix.on_command(&mut editor, &format!("grep t {}", dir.path().display()));
+        assert_eq!(editor.content_height(), 9);
+
+        quickfix.on_command(&mut editor, "cpreview 2");
+        assert_eq!(editor.status, "(2 of 2) three");
+        assert_eq!(editor.file_path.as_ref(), Some(&first));
+        assert_eq!(editor.quickfix.index, 1);
+        assert_eq!(editor.content_height(), 5);
+        let mut ctx = RenderContext::new(40, 10);
+        preview.on_render(&editor, &mut ctx);
+        assert!(ctx.lines[5].starts_with("[Preview] "));
+        assert!(ctx.lines[5].trim_end().ends_with("b.txt:3"));
+        assert_eq!(ctx.lines[6..9], ["y", "three", "~"]);
+        assert_eq!(
+            ctx.style_at(7, 0),
+            editor.theme.style(HighlightGroup::Visual)
+        );
+
+        type_keys(&mut preview, &mut editor, "z");
+        assert!(editor.preview_window.is_some());
+        preview.on_event(&mut editor, &key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        type_keys(&mut preview, &mut editor, "z");
+        assert!(editor.preview_window.is_none());
+        assert!(editor.pending_keys.is_empty());
+
+        preview.on_command(&mut editor, &format!("pedit {}", second.display()));
+        assert_eq!(editor.preview_window.as_ref().unwrap().row, 0);
+        preview.on_command(&mut editor, "pclose");
+        assert_eq!(editor.content_height(), 9);
+        preview.on_command(&mut editor, "pedit missing.txt");
+        assert!(editor.status.starts_with("Cannot preview missing.txt"));
+    }
+
+    #[test]
+    fn find_replace_previews_and_applies_selected_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = dir.path().join("a.txt");
+        let other = dir.path().join("b.txt");
+        std::fs::write(&open, "old\nkeep\n").unwrap();
+        std::fs::write(&other, "old old\nold\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(open.clone()));
+        editor.load_from_path(&open).unwrap();
+        let mut plugin = FindReplacePlugin::new();
+
+        plugin.on_command(
+            &mut editor,
+            &format!("FindReplace o(l)d n\\1w {}", dir.path().display()),
+        );
+        let view = editor.scratch.as_ref().expect("preview");
+        assert_eq!(
+            view.lines[0].split(':').next(),
+            Some("3 of 3 changes selected")
+        );
+        assert!(view.lines[1].starts_with("> [x] "));
+        assert_eq!(view.lines[2..4], ["    - old", "    + nlw"]);
+
+        type_keys(&mut plugin, &mut editor, "j ");
+        let view = editor.scratch.as_ref().expect("preview");
+        assert!(view.lines[4].starts_with("> [ ] "));
+        assert!(view.lines[4].ends_with("b.txt:1"));
+        type_keys(&mut plugin, &mut editor, "\n");
+        assert!(editor.scratch.is_none());
+        assert_eq!(editor.status, "Changed 2 lines in 2 files");
+        assert_eq!(editor.buffer.lines[0], "nlw");
+        assert!(editor.dirty);
+        assert_eq!(std::fs::read_to_string(&open).unwrap(), "old\nkeep\n");
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "old old\nnlw\n");
+
+        plugin.on_command(
+            &mut editor,
+            &format!("FindReplace zzz y {}", dir.path().display()),
+        );
+        assert_eq!(editor.status, "No match: zzz");
+    }
+
+    #[test]
+    fn opening_a_directory_lists_it_in_the_explorer() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "bee\n").unwrap();
+        std::fs::write(dir.path().join("a.txt"), "ay\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(dir.path().to_path_buf()));
+        FileCommandPlugin.on_init(&mut editor);
+        assert_eq!(editor.file_path, None);
+        let command = editor.next_command().expect("explore command");
+        let mut plugin = ExplorerPlugin::new();
+        plugin.on_command(&mut editor, &command);
+        let view = editor.scratch.as_ref().expect("listing");
+        assert_eq!(view.lines[1..], ["> ../", "  sub/", "  a.txt"]);
+
+        type_keys(&mut plugin, &mut editor, "j\n");
+        let view = editor.scratch.as_ref().expect("listing");
+        assert_eq!(view.lines[1..], ["> ../", "  b.txt"]);
+        type_keys(&mut plugin, &mut editor, "j\n");
+        assert!(editor.scratch.is_none());
+        assert_eq!(editor.file_path, Some(dir.path().join("sub/b.txt")));
+        assert_eq!(editor.buffer.lines[0], "bee");
+
+        plugin.on_command(&mut editor, "Explore");
+        let view = editor.scratch.as_ref().expect("listing");
+        assert!(view.lines[0].starts_with(&dir.path().join("sub").display().to_string()));
+        type_keys(&mut plugin, &mut editor, "-");
+        let view = editor.scratch.as_ref().expect("listing");
+        assert_eq!(view.lines[2], "  sub/");
+    }
+
+    #[test]
+    fn oldfiles_reopen_files_where_they_were_left() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        std::fs::write(&a, "one\ntwo\nthree\n").unwrap();
+        std::fs::write(&b, "bee\n").unwrap();
+        let mut editor = Editor::new(80, 24, None);
+        editor.state_dir = Some(dir.path().to_path_buf());
+        let mut plugin = OldFilesPlugin::new();
+        plugin.on_command(&mut editor, "oldfiles");
+        assert_eq!(editor.status, "No old files");
+
+        FileCommandPlugin.on_command(&mut editor, &format!("e {}", a.display()));
+        editor.goto_position(2, 3);
+        FileCommandPlugin.on_command(&mut editor, &format!("e {}", b.display()));
+        FileCommandPlugin.on_command(&mut editor, "q");
+        plugin.on_command(&mut editor, "oldfiles");
+        let view = editor.scratch.as_ref().expect("list");
+        assert_eq!(view.lines[0], format!("  1: {}", b.display()));
+
+        plugin.on_command(&mut editor, "browse oldfiles");
+        let view = editor.scratch.as_ref().expect("picker");
+        assert_eq!(view.lines[2], format!("  {}:3", a.display()));
+        type_keys(&mut plugin, &mut editor, "j\n");
+        assert!(editor.scratch.is_none());
+        assert_eq!(editor.file_path.as_deref(), Some(a.as_path()));
+        assert_eq!(editor.cursor, Cursor { row: 2, col: 3 });
+    }
+
+    #[test]
+    fn syntax_highlighting_starts_once_the_assets_arrive() {
+        let mut editor = Editor::new(20, 4, Some(PathBuf::from("main.rs")));
+        editor.buffer.lines = vec!["fn main() {}".to_string()];
+        let (waker, wakeups) = wakeup::channel();
+        editor.set_waker(waker);
+        let mut plugin = SyntaxHighlightPlugin::new();
+        plugin.on_init(&mut editor);
+        assert!(matches!(wakeups.recv(), Ok(Wakeup::Redraw)));
+        assert!(plugin.on_idle(&editor));
+        assert!(!plugin.on_idle(&editor));
+        let mut ctx = RenderContext::new(20, 4);
+        plugin.on_render(&editor, &mut ctx);
+        assert!(ctx.spans[0].len() > 1);
+    }
+
+    #[test]
+    fn rainbow_colors_brackets_by_depth_in_listed_file_types() {
+        let mut editor = Editor::new(20, 4, Some(PathBuf::from("a.lisp")));
+        editor.buffer.lines = ["(a [b", "c] {})"].map(String::from).to_vec();
+        let mut plugin = RainbowParenPlugin::new();
+        let mut ctx = RenderContext::new(20, 4);
+        plugin.on_render(&editor, &mut ctx);
+        assert!(ctx.spans[0].is_empty());
+
+        editor.options.apply("rainbow=rs,lisp").unwrap();
+        let under = ContentStyle {
+            background_color: Some(crossterm::style::Color::Black),
+            ..ContentStyle::new()
+        };
+        ctx.set_spans(
+            1,
+            vec![StyledSpan {
+                start: 0,
+                len: 6,
+                style: under,
+            }],
+        );
+        plugin.on_render(&editor, &mut ctx);
+        let color = |row: usize, col: usize| {
+            let span = ctx.spans[row]
+                .iter()
+                .find(|span| span.start == col)
+                .unwrap();
+            span.style.foreground_color
+        };
+        let depth = |level| {
+            editor
+                .theme
+                .style(HighlightGroup::rainbow(level))
+                .foreground_color
+        };
+        assert_eq!(color(0, 0), depth(0));
+        assert_eq!(color(0, 3), depth(1));
+        assert_eq!(ctx.spans[1][1].start, 1);
+        assert_eq!(ctx.spans[1][1].style.foreground_color, depth(1));
+        assert_eq!(
+            ctx.spans[1][1].style.background_color,
+            under.background_color
+        );
+        assert_eq!(ctx.spans[1][2].style.foreground_color, depth(1));
+        assert_eq!(ctx.spans[1][4].style.foreground_color, depth(0));
+    }
+
+    #[test]
+    fn indent_guides_mark_each_level_of_leading_whitespace() {
+        let mut editor = Editor::new(20, 6, None);
+        editor.buffer.lines = ["fn a() {", "    if x {", "\t    y", "", "  z"]
+            .map(String::from)
+            .to_vec();
+        let render = |editor: &Editor| {
+            let mut ctx = RenderContext::new(20, 6);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            IndentGuidePlugin.on_render(editor, &mut ctx);
+            ctx
+        };
+        assert_eq!(render(&editor).lines[1], "    if x {");
+
+        editor.options.apply("indentguides").unwrap();
+        let ctx = render(&editor);
+        assert_eq!(ctx.lines[0], "fn a() {");
+        assert_eq!(ctx.lines[1], "│   if x {");
+        assert_eq!(ctx.lines[2], "│   │   │   y");
+        assert_eq!(ctx.lines[4], "│ z");
+        assert_eq!(ctx.spans[2].len(), 3);
+        assert_eq!(ctx.spans[2][1].start, 4);
+
+        editor.viewport.col_offset = 2;
+        assert_eq!(render(&editor).lines[2], "  │   │   y");
+    }
+
+    #[test]
+    fn csv_align_pads_fields_into_columns_without_editing() {
+        let mut editor = Editor::new(30, 6, Some(PathBuf::from("people.csv")));
+        editor.buffer.lines = ["name,age,city", "Alexandra,7,Oslo", "Bo,42"]
+            .map(String::from)
+            .to_vec();
+        editor.cursor = Cursor { row: 1, col: 10 };
+        let mut csv_render = CsvRenderPlugin::new();
+        let mut render = |editor: &Editor| {
+            let mut ctx = RenderContext::new(30, 6);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            CursorRenderPlugin.on_render(editor, &mut ctx);
+            csv_render.on_render(editor, &mut ctx);
+            ctx
+        };
+        assert_eq!(render(&editor).lines[0], "name,age,city");
+
+        CsvPlugin.on_command(&mut editor, "CsvAlign");
+        assert_eq!(editor.csv_align, Some(','));
+        let ctx = render(&editor);
+        assert_eq!(ctx.lines[0], "name     ,age,city");
+        assert_eq!(ctx.lines[1], "Alexandra,7  ,Oslo");
+        assert_eq!(ctx.lines[2], "Bo       ,42");
+        assert_eq!(ctx.cursor, Some((1, 10)));
+        let header = ctx.style_at(0, 0);
+        assert!(header.attributes.has(Attribute::Bold));
+        assert_eq!(editor.buffer.lines[2], "Bo,42");
+
+        editor.cursor = Cursor { row: 2, col: 4 };
+        assert_eq!(render(&editor).cursor, Some((2, 11)));
+
+        let press = |editor: &mut Editor, prefix: &str| {
+            editor.pending_keys = prefix.to_string();
+            CsvPlugin.on_event(editor, &key(KeyCode::Char('f'), KeyModifiers::NONE))
+        };
+        editor.cursor = Cursor { row: 0, col: 0 };
+        press(&mut editor, "]");
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 5 });
+        press(&mut editor, "]");
+        press(&mut editor, "]");
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 0 });
+        press(&mut editor, "[");
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 9 });
+
+        CsvPlugin.on_command(&mut editor, "CsvAlign");
+        assert_eq!(editor.csv_align, None);
+        CsvPlugin.on_command(&mut editor, "CsvAlign tab");
+        assert_eq!(editor.csv_align, Some('\t'));
+    }
+
+    #[test]
+    fn markdown_preview_is_drawn_beside_the_buffer_and_follows_edits() {
+        let mut editor = Editor::new(30, 5, Some(PathBuf::from("notes.txt")));
+        editor.buffer.lines = ["# Notes", "- **one**"].map(String::from).to_vec();
+        let mut preview = MarkdownPreviewPlugin::new();
+        preview.on_command(&mut editor, "Preview");
+        assert!(!editor.preview);
+        assert_eq!(editor.status, "Preview shows Markdown files");
+
+        editor.file_path = Some(PathBuf::from("notes.md"));
+        preview.on_command(&mut editor, "Preview");
+        assert_eq!(editor.preview_width(), 15);
+        let render = |preview: &mut MarkdownPreviewPlugin, editor: &Editor| {
+            let mut ctx = RenderContext::new(30, 5);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            preview.on_render(editor, &mut ctx);
+            ctx
+        };
+        let ctx = render(&mut preview, &editor);
+        assert_eq!(ctx.lines[0], "# Notes        │Notes         ");
+        assert_eq!(ctx.lines[1], "- **one**      │• one         ");
+        assert_eq!(
+            ctx.style_at(0, 16).foreground_color,
+            Some(crossterm::style::Color::Magenta)
+        );
+        assert!(ctx.style_at(1, 18).attributes.has(Attribute::Bold));
+
+        editor.cursor = Cursor { row: 1, col: 9 };
+        editor.insert_char('!');
+        assert_eq!(
+            render(&mut preview, &editor).lines[1],
+            "- **one**!     │• one!        "
+        );
+
+        preview.on_command(&mut editor, "Preview");
+        assert!(!editor.preview);
+        assert_eq!(render(&mut preview, &editor).lines[0], "# Notes");
+    }
+
+    #[test]
+    fn virtual_text_is_drawn_without_entering_the_buffer() {
+        let mut editor = Editor::new(30, 4, None);
+        editor.buffer.lines = ["let x = f(a);", "\tb"].map(String::from).to_vec();
+        editor.cursor = Cursor { row: 0, col: 10 };
+        let mut hint = ContentStyle::new();
+        hint.foreground_color = Some(crossterm::style::Color::DarkGrey);
+        let mut bold = ContentStyle::new();
+        bold.attributes.set(crossterm::style::Attribute::Bold);
+        let text = |col, text: &str| VirtualText {
+            col,
+            cursor_line: false,
+            text: text.to_string(),
+            style: hint,
+        };
+        editor.virtual_text.add("hints", 0, text(Some(10), "n: "));
+        editor.virtual_text.add("hints", 0, text(Some(5), ": i32"));
+        editor.virtual_text.add("lint", 0, text(None, "unused"));
+        editor.virtual_text.add("lint", 0, text(None, "!"));
+        editor.virtual_text.add("lint", 1, text(Some(1), "<"));
+        let render = |editor: &Editor| {
+            let mut ctx = RenderContext::new(30, 4);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            ctx.add_span(
+                0,
+                StyledSpan {
+                    start: 8,
+                    len: 5,
+                    style: bold,
+                },
+            );
+            CursorRenderPlugin.on_render(editor, &mut ctx);
+            VirtualTextRenderPlugin.on_render(editor, &mut ctx);
+            ctx
+        };
+        let ctx = render(&editor);
+        assert_eq!(ctx.lines[0], "let x: i32 = f(n: a); unused !");
+        assert_eq!(ctx.lines[1], "        <b");
+        assert_eq!(editor.buffer.lines[0], "let x = f(a);");
+        // The cursor stays on `a`, and the bold span is split around the hint.
+        assert_eq!(ctx.cursor, Some((0, 18)));
+        assert_eq!(ctx.style_at(0, 14), bold);
+        assert_eq!(ctx.style_at(0, 15), hint);
+        assert_eq!(ctx.style_at(0, 18), bold);
+
+        editor.viewport.col_offset = 6;
+        assert_eq!(render(&editor).lines[0], "= f(n: a); unused !");
+        editor.virtual_text.clear("hints");
+        assert_eq!(render(&editor).lines[0], "= f(a); unused !");
+    }
+
+    #[test]
+    fn lint_diagnostics_are_underlined_signed_and_visited() {
+        let mut editor = Editor::new(30, 5, Some(PathBuf::from("main.rs")));
+        editor.buffer.lines = ["fn main() {", "    let x;", "}"]
+            .map(String::from)
+            .to_vec();
+        DiagnosticPlugin.on_command(&mut editor, "Lint echo main.rs:2:9: warning: unused");
+        assert_eq!(editor.status, "1 diagnostic");
+        assert_eq!(editor.diagnostics[0].col, 8);
+
+        let render = |editor: &Editor| {
+            let mut ctx = RenderContext::new(30, 5);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            DiagnosticRenderPlugin.on_render(editor, &mut ctx);
+            CursorRenderPlugin.on_render(editor, &mut ctx);
+            VirtualTextRenderPlugin.on_render(editor, &mut ctx);
+            SignColumnPlugin.on_render(editor, &mut ctx);
+            ctx
+        };
+        let ctx = render(&editor);
+        assert_eq!(ctx.lines[0], "  fn main() {");
+        assert_eq!(ctx.lines[1], "W     let x;");
+        assert_eq!(ctx.cursor, Some((0, 2)));
+        assert!(ctx.style_at(1, 10).attributes.has(Attribute::Underlined));
+        assert!(!ctx.style_at(1, 11).attributes.has(Attribute::Underlined));
+
+        type_keys(&mut DiagnosticPlugin, &mut editor, "]d");
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 8 });
+        assert_eq!(editor.status, "unused main.rs");
+        let ctx = render(&editor);
+        assert_eq!(ctx.lines[1], "W     let x; unused main.rs");
+        assert_eq!(ctx.cursor, Some((1, 10)));
+
+        type_keys_through(&mut [&mut DiagnosticPlugin], &mut editor, "[x");
+        assert!(editor.pending_keys.is_empty());
+        DiagnosticPlugin.on_command(&mut editor, "Lint");
+        assert_eq!(render(&editor).lines[1], "    let x;");
+    }
+
+    #[test]
+    fn z_equals_replaces_a_misspelled_word_with_a_suggestion() {
+        let dir = tempfile::tempdir().unwrap();
+        let words = dir.path().join("words");
+        std::fs::write(&words, "a\nthe\nword\nwood\nworld\n").unwrap();
+        let mut editor = Editor::new(40, 10, None);
+        editor.buffer.lines = vec!["the wrod of a wrld".to_string()];
+        editor.reset_undo();
+        editor.cursor.col = 5;
+        let mut spell = SpellPlugin::new();
+        type_keys(&mut spell, &mut editor, "z=");
+        assert_eq!(editor.status, "Spell checking is not enabled");
+
+        let set = format!("set spell dictionary={}", words.display());
+        OptionsPlugin.on_command(&mut editor, &set);
+        let mut ctx = RenderContext::new(40, 10);
+        BufferRenderPlugin.on_render(&editor, &mut ctx);
+        SpellRenderPlugin.on_render(&editor, &mut ctx);
+        assert!(ctx.style_at(0, 4).attributes.has(Attribute::Underlined));
+        assert!(!ctx.style_at(0, 0).attributes.has(Attribute::Underlined));
+        assert!(!ctx.style_at(0, 12).attributes.has(Attribute::Underlined));
+
+        type_keys(&mut spell, &mut editor, "z=");
+        let popup = editor.popup.as_ref().unwrap();
+        assert_eq!(popup.title, "Change \"wrod\" to");
+        assert_eq!(popup.rows(30, ContentStyle::new())[0].0, "1 word");
+        type_keys(&mut spell, &mut editor, "j1");
+        assert_eq!(editor.buffer.lines[0], "the word of a wrld");
+        assert!(editor.popup.is_none());
+        editor.commit_undo();
+        editor.undo();
+        assert_eq!(editor.buffer.lines[0], "the wrod of a wrld");
+
+        editor.cursor.col = 0;
+        type_keys(&mut spell, &mut editor, "z=");
+        assert!(editor.popup.is_none());
+        assert_eq!(editor.status, "No suggestions for \"the\"");
+    }
+
+    #[test]
+    fn help_popup_is_drawn_by_the_cursor_and_scrolls() {
+        let mut editor = Editor::new(30, 10, None);
+        editor.buffer.lines = ["one", "two"].map(String::from).to_vec();
+        HelpPlugin.on_command(&mut editor, "help nosuchtopicanywhere");
+        assert_eq!(editor.status, "No help for nosuchtopicanywhere");
+        HelpPlugin.on_command(&mut editor, "h ]d");
+        assert_eq!(editor.popup.as_ref().unwrap().title, "help ]d");
+
+        editor.popup = Some(Popup::markdown("doc", "# Title\n`a` b\nc\nd\ne\nf\ng\nh"));
+        let render = |editor: &Editor| {
+            let mut ctx = RenderContext::new(30, 10);
+            BufferRenderPlugin.on_render(editor, &mut ctx);
+            CursorRenderPlugin.on_render(editor, &mut ctx);
+            PopupRenderPlugin.on_render(editor, &mut ctx);
+            ctx
+        };
+        let ctx = render(&editor);
+        assert_eq!(ctx.lines[0], "one");
+        assert_eq!(ctx.lines[1], "┌─doc───┐");
+        assert_eq!(ctx.lines[2], "│Title  │");
+        assert_eq!(ctx.lines[3], "│a b    │");
+        assert_eq!(ctx.lines[8], "└─1-6/8─┘");
+        assert_eq!(
+            ctx.style_at(3, 1).foreground_color,
+            Some(crossterm::style::Color::Cyan)
+        );
+
+        type_keys(&mut PopupPlugin, &mut editor, "jjjjjj");
+        assert_eq!(editor.popup.as_ref().unwrap().scroll, 6);
+        assert_eq!(render(&editor).lines[8], "└─3-8/8─┘");
+        type_keys(&mut PopupPlugin, &mut editor, "x");
+        assert!(editor.popup.is_none());
+    }
+
+    #[test]
+    fn trailing_whitespace_is_marked_and_trimmed() {
+        let mut editor = Editor::new(20, 4, None);
+        editor.buffer.lines = ["ab  ", "cd", "\t"].map(String::from).to_vec();
+        editor.cursor = Cursor { row: 0, col: 3 };
+        let mut ctx = RenderContext::new(20, 4);
+        TrailingWhitespacePlugin.on_render(&editor, &mut ctx);
+        assert_eq!(ctx.spans[0].len(), 1);
+        assert_eq!((ctx.spans[0][0].start, ctx.spans[0][0].len), (2, 2));
+        assert!(ctx.spans[1].is_empty());
+
+        TrailingWhitespacePlugin.on_command(&mut editor, "TrimWhitespace");
+        assert_eq!(editor.buffer.lines, ["ab", "cd", ""]);
+        assert_eq!(editor.status, "Trimmed 2 lines");
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 2 });
+        TrailingWhitespacePlugin.on_command(&mut editor, "TrimWhitespace");
+        assert_eq!(editor.status, "No trailing whitespace");
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_buffer() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["abc".to_string(), "def".to_string()];
+        editor.reset_undo();
+        LineCommandPlugin.on_command(&mut editor, "1d");
+        editor.commit_undo();
+        editor.cursor.col = 3;
+        editor.insert_char('!');
+        assert_eq!(editor.buffer.lines, ["def!"]);
+
+        type_keys(&mut UndoPlugin, &mut editor, "u");
+        assert_eq!(editor.buffer.lines, ["def"]);
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 3 });
+        UndoPlugin.on_command(&mut editor, "undo");
+        assert_eq!(editor.buffer.lines, ["abc", "def"]);
+        type_keys(&mut UndoPlugin, &mut editor, "u");
+        assert_eq!(editor.status, "Already at oldest change");
+
+        let control_r = key(KeyCode::Char('r'), KeyModifiers::CONTROL);
+        UndoPlugin.on_event(&mut editor, &control_r);
+        assert_eq!(editor.buffer.lines, ["def"]);
+        UndoPlugin.on_command(&mut editor, "redo");
+        UndoPlugin.on_event(&mut editor, &control_r);
+        assert_eq!(editor.buffer.lines, ["def!"]);
+        assert_eq!(editor.status, "Already at newest change");
+    }
+
+    #[test]
+    fn earlier_and_later_travel_by_count_and_time() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["a".to_string()];
+        editor.reset_undo();
+        for ch in ['b', 'c', 'd'] {
+            editor.cursor.col = editor.buffer.lines[0].len();
+            editor.insert_char(ch);
+            editor.commit_undo();
+        }
+        assert_eq!(editor.buffer.lines, ["abcd"]);
+        UndoPlugin.on_command(&mut editor, "earlier 2");
+        assert_eq!(editor.buffer.lines, ["ab"]);
+        assert_eq!(editor.status, "2 changes undone");
+        UndoPlugin.on_command(&mut editor, "later 1h");
+        assert_eq!(editor.buffer.lines, ["abcd"]);
+        assert_eq!(editor.status, "2 changes redone");
+        UndoPlugin.on_command(&mut editor, "ea 10m");
+        assert_eq!(editor.buffer.lines, ["a"]);
+        UndoPlugin.on_command(&mut editor, "earlier 1s");
+        assert_eq!(editor.status, "Already at oldest change");
+        editor.pending_keys.push('g');
+        type_keys(&mut UndoPlugin, &mut editor, "+");
+        assert_eq!(editor.buffer.lines, ["ab"]);
+        UndoPlugin.on_command(&mut editor, "later 1y");
+        assert_eq!(editor.status, "Invalid argument: 1y");
+    }
+
+    #[test]
+    fn word_motions_move_the_cursor() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["let x = y;".to_string()];
+        type_keys(&mut MotionPlugin, &mut editor, "ww");
+        assert_eq!(editor.cursor.col, 6);
+        type_keys(&mut MotionPlugin, &mut editor, "e");
+        assert_eq!(editor.cursor.col, 8);
+        type_keys(&mut MotionPlugin, &mut editor, "B");
+        assert_eq!(editor.cursor.col, 6);
+    }
+
+    #[test]
+    fn typing_a_closer_dedents_the_line() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("script.rb")));
+        editor.buffer.lines = vec!["def greet".to_string()];
+        editor.cursor.col = 9;
+        editor.mode = Mode::Insert;
+        let mut plugin = InsertPlugin::new();
+
+        type_keys(&mut plugin, &mut editor, "\nputs 1\nend");
+        assert_eq!(editor.buffer.lines, vec!["def greet", "    puts 1", "end"]);
+        type_keys(&mut plugin, &mut editor, "ing = 2");
+        assert_eq!(editor.buffer.lines[2], "    ending = 2");
+    }
+
+    #[test]
+    fn auto_pairs_close_skip_and_delete_pairs() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("main.rs")));
+        editor.mode = Mode::Insert;
+        let mut insert = InsertPlugin::new();
+        let mut type_str = |editor: &mut Editor, text: &str| {
+            for ch in text.chars() {
+                let event = key(KeyCode::Char(ch), KeyModifiers::NONE);
+                if AutoPairsPlugin.on_event(editor, &event) == EventResult::Ignored {
+                    insert.on_event(editor, &event);
+                }
+            }
+        };
+
+        type_str(&mut editor, "f(\"a");
+        assert_eq!(editor.buffer.lines[0], "f(\"a\")");
+        type_str(&mut editor, "\")");
+        assert_eq!(editor.buffer.lines[0], "f(\"a\")");
+        assert_eq!(editor.cursor.col, 6);
+        type_str(&mut editor, " it's");
+        assert_eq!(editor.buffer.lines[0], "f(\"a\") it's");
+
+        type_str(&mut editor, "[");
+        AutoPairsPlugin.on_event(&mut editor, &key(KeyCode::Backspace, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines[0], "f(\"a\") it's");
+
+        editor.options.autopairs = false;
+        type_str(&mut editor, "(");
+        assert_eq!(editor.buffer.lines[0], "f(\"a\") it's(");
+    }
+
+    #[test]
+    fn table_mode_realigns_rows_as_they_are_typed() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("notes.md")));
+        editor.mode = Mode::Insert;
+        editor.options.tablemode = true;
+        let mut insert = InsertPlugin::new();
+        let mut type_str = |editor: &mut Editor, text: &str| {
+            type_keys_through(&mut [&mut TablePlugin, &mut insert], editor, text)
+        };
+        type_str(&mut editor, "|name|qty|");
+        assert_eq!(editor.buffer.lines[0], "| name | qty |");
+        assert_eq!(editor.cursor.col, 14);
+        editor.insert_newline();
+        type_str(&mut editor, "|apple|3|");
+        assert_eq!(editor.buffer.lines, ["| name  | qty |", "| apple | 3   |"]);
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 15 });
+
+        let tab = key(KeyCode::Tab, KeyModifiers::NONE);
+        editor.cursor = Cursor { row: 0, col: 2 };
+        TablePlugin.on_event(&mut editor, &tab);
+        assert_eq!(editor.cursor, Cursor { row: 0, col: 10 });
+        TablePlugin.on_event(&mut editor, &tab);
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 2 });
+
+        editor.cursor.col = editor.current_line_len();
+        editor.insert_newline();
+        type_str(&mut editor, "a|b");
+        assert_eq!(editor.buffer.lines[2], "a|b");
+
+        editor.mode = Mode::Normal;
+        editor.options.tablemode = false;
+        editor.buffer.lines = ["|a|b|", "|-|:-:|", "|ccc|d|"].map(String::from).to_vec();
+        editor.cursor = Cursor { row: 2, col: 5 };
+        TablePlugin.on_command(&mut editor, "TableFormat");
+        assert_eq!(
+            editor.buffer.lines,
+            ["| a   | b |", "|-----|:-:|", "| ccc | d |"]
+        );
+        assert_eq!(editor.cursor, Cursor { row: 2, col: 8 });
+        editor.buffer.lines.push("text".to_string());
+        editor.cursor = Cursor { row: 3, col: 0 };
+        TablePlugin.on_command(&mut editor, "TableFormat");
+        assert_eq!(editor.status, "Not in a table");
+    }
+
+    #[test]
+    fn enter_between_braces_opens_an_indented_line() {
+        let mut editor = Editor::new(80, 24, Some(PathBuf::from("main.rs")));
+        editor.buffer.lines = vec!["fn main() {}".to_string()];
+        editor.cursor.col = 11;
+        editor.mode = Mode::Insert;
+        AutoPairsPlugin.on_event(&mut editor, &key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["fn main() {", "    ", "}"]);
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 4 });
+    }
+
+    #[test]
+    fn ctrl_r_inserts_a_register_in_insert_mode_and_on_the_command_line() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = vec!["x".to_string()];
+        editor
+            .registers
+            .record(Some('a'), Register::guess("one\ntwo\n".to_string()), true);
+        editor
+            .registers
+            .record(None, Register::guess("word".to_string()), true);
+        editor.mode = Mode::Insert;
+        let mut plugin = RegisterInsertPlugin::new();
+        let ctrl_r = key(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+        plugin.on_event(&mut editor, &ctrl_r);
+        type_keys(&mut plugin, &mut editor, "\"");
+        assert_eq!(editor.buffer.lines, ["wordx"]);
+        plugin.on_event(&mut editor, &ctrl_r);
+        type_keys(&mut plugin, &mut editor, "a");
+        assert_eq!(editor.buffer.lines, ["wordone", "two", "x"]);
+        assert_eq!(editor.cursor, Cursor { row: 2, col: 0 });
+
+        // An empty or unknown register inserts nothing.
+        plugin.on_event(&mut editor, &ctrl_r);
+        let result = plugin.on_event(&mut editor, &key(KeyCode::Char('z'), KeyModifiers::NONE));
+        assert_eq!(result, EventResult::Consumed);
+        plugin.on_event(&mut editor, &ctrl_r);
+        plugin.on_event(&mut editor, &key(KeyCode::Char('%'), KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, ["wordone", "two", "x"]);
+        let result = plugin.on_event(&mut editor, &key(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert_eq!(result, EventResult::Ignored);
+
+        editor.mode = Mode::Command;
+        editor.command_line.open(':');
+        editor.command_line.insert_str("e ");
+        plugin.on_event(&mut editor, &ctrl_r);
+        type_keys(&mut plugin, &mut editor, "a");
+        assert_eq!(editor.command_line.input, "e one");
+        assert!(Registers::is_valid('+') && Registers::is_valid('*'));
+    }
+
+    #[test]
+    fn command_history_is_kept_only_in_the_state_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = Editor::new(80, 24, None);
+        assert_eq!(CommandLinePlugin::history_path(&editor, ':'), None);
+        editor.state_dir = Some(dir.path().join("state"));
+        CommandLinePlugin::record_history(&mut editor, ':', "w");
+        assert!(dir.path().join("state/command_history").exists());
+
+        let mut editor = Editor::new(80, 24, None);
+        editor.state_dir = Some(dir.path().join("state"));
+        CommandLinePlugin.on_init(&mut editor);
+        assert_eq!(editor.command_history.entries, ["w"]);
+    }
+
+    #[test]
+    fn command_line_edits_at_its_cursor() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.mode = Mode::Command;
+        editor.command_line.open(':');
+        let mut plugin = CommandLinePlugin;
+        type_keys(&mut plugin, &mut editor, "s/foo bar/baz");
+        let press = |plugin: &mut CommandLinePlugin, editor: &mut Editor, code, mods| {
+            plugin.on_event(editor, &key(code, mods));
+        };
+        press(
+            &mut plugin,
+            &mut editor,
+            KeyCode::Char('w'),
+            KeyModifiers::CONTROL,
+        );
+        assert_eq!(editor.command_line.input, "s/foo bar/");
+        press(
+            &mut plugin,
+            &mut editor,
+            KeyCode::Char('w'),
+            KeyModifiers::CONTROL,
+        );
+        assert_eq!(editor.command_line.input, "s/foo bar");
+        press(&mut plugin, &mut editor, KeyCode::Left, KeyModifiers::NONE);
+        press(&mut plugin, &mut editor, KeyCode::Left, KeyModifiers::NONE);
+        type_keys(&mut plugin, &mut editor, "X");
+        assert_eq!(editor.command_line.input, "s/foo bXar");
+        press(
+            &mut plugin,
+            &mut editor,
+            KeyCode::Delete,
+            KeyModifiers::NONE,
+        );
+        press(
+            &mut plugin,
+            &mut editor,
+            KeyCode::Backspace,
+            KeyModifiers::NONE,
+        );
+        assert_eq!(editor.command_line.input, "s/foo br");
+        press(
+            &mut plugin,
+            &mut editor,
+            KeyCode::Char('a'),
+            KeyModifiers::CONTROL,
+        );
+        type_keys(&mut plugin, &mut editor, "%");
+        press(
+            &mut plugin,
+            &mut editor,
+            KeyCode::Char('e'),
+            KeyModifiers::CONTROL,
+        );
+        type_keys(&mut plugin, &mut editor, "/");
+        assert_eq!(editor.command_line.input, "%s/foo br/");
+        assert_eq!(editor.command_line.before_cursor(), "%s/foo br/");
+        press(&mut plugin, &mut editor, KeyCode::Home, KeyModifiers::NONE);
+        press(&mut plugin, &mut editor, KeyCode::Right, KeyModifiers::NONE);
+        assert_eq!(editor.command_line.before_cursor(), "%");
+        press(
+            &mut plugin,
+            &mut editor,
+            KeyCode::Char('u'),
+            KeyModifiers::CONTROL,
+        );
+        assert_eq!(editor.command_line.input, "s/foo br/");
+        press(&mut plugin, &mut editor, KeyCode::End, KeyModifiers::NONE);
+        press(
+            &mut plugin,
+            &mut editor,
+            KeyCode::Char('u'),
+            KeyModifiers::CONTROL,
+        );
+        assert_eq!(editor.command_line.input, "");
+    }
+
+    #[test]
+    fn user_commands_run_their_replacement_and_complete() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.buffer.lines = ["a", "b", "c", "d"].map(String::from).to_vec();
+        editor.cursor.row = 1;
+        editor.registry.add(&SortPlugin);
+        let mut plugin = UserCommandPlugin;
+        let mut run = |editor: &mut Editor, command: &str| {
+            let result = plugin.on_command(editor, command);
+            (result, editor.next_command())
+        };
+        run(&mut editor, "command -range Del <line1>,<line2>d");
+        run(&mut editor, "command -nargs=1 -range=% Say echo <q-args>");
+        assert_eq!(
+            run(&mut editor, "2,3Del"),
+            (EventResult::Consumed, Some("2,3d".to_string()))
+        );
+        assert_eq!(run(&mut editor, "Del").1, Some("2,2d".to_string()));
+        assert_eq!(
+            run(&mut editor, "Say hi there").1,
+            Some("echo \"hi there\"".to_string())
+        );
+        assert_eq!(run(&mut editor, "Say"), (EventResult::Consumed, None));
+        assert_eq!(editor.status, "Argument required");
+        assert_eq!(run(&mut editor, "Del!").1, None);
+        assert_eq!(editor.status, "No ! allowed");
+        assert_eq!(run(&mut editor, "Nope").0, EventResult::Ignored);
+        run(&mut editor, "command sort x");
+        assert!(editor.status.contains("uppercase"));
+        run(&mut editor, "command Say x");
+        assert!(editor.status.contains("already exists"));
+
+        let palette = Palette::new(&editor.registry);
+        assert!(palette.matches().any(|entry| entry.label == ":Say {args}"));
+
+        editor.mode = Mode::Command;
+        editor.command_line.open(':');
+        let mut command_line = CommandLinePlugin;
+        let tab = key(KeyCode::Tab, KeyModifiers::NONE);
+        run(&mut editor, "command Dump echo");
+        type_keys(&mut command_line, &mut editor, "%D");
+        command_line.on_event(&mut editor, &tab);
+        assert_eq!(editor.command_line.input, "%D");
+        assert_eq!(editor.status, "Del  Dump");
+        type_keys(&mut command_line, &mut editor, "u");
+        command_line.on_event(&mut editor, &tab);
+        assert_eq!(editor.command_line.input, "%Dump ");
+        editor.command_line.set_input("so".to_string());
+        command_line.on_event(&mut editor, &tab);
+        assert_eq!(editor.command_line.input, "sort ");
+
+        run(&mut editor, "delcommand Say");
+        assert_eq!(run(&mut editor, "Say x").0, EventResult::Ignored);
+        run(&mut editor, "comclear");
+        assert!(editor.registry.user_commands.is_empty());
+    }
+
+    #[test]
+    fn edit_without_a_file_reloads_the_open_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let mut editor = Editor::new(80, 24, None);
+        let mut files = FileCommandPlugin;
+        files.on_command(&mut editor, "e");
+        assert_eq!(editor.status, "No file name");
+
+        files.on_command(&mut editor, &format!("e {}", path.display()));
+        editor.goto_position(2, 4);
+        editor.buffer.lines[2] = "changed".to_string();
+        editor.dirty = true;
+        files.on_command(&mut editor, "e");
+        assert_eq!(
+            editor.status,
+            "No write since last change (add ! to override)"
+        );
+
+        std::fs::write(&path, "one\ntwo\nabc\n").unwrap();
+        let revision = editor.deltas.revision();
+        files.on_command(&mut editor, "e!");
+        assert_eq!(editor.buffer.lines, ["one", "two", "abc"]);
+        assert!(!editor.dirty);
+        assert_eq!(editor.cursor, Cursor { row: 2, col: 3 });
+        assert!(editor.deltas.since(revision).is_none());
+        assert_eq!(editor.status, format!("Reloaded {}", path.display()));
+    }
+
+    #[test]
+    fn range_and_append_writes_leave_the_buffer_unsaved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let part = dir.path().join("part.txt");
+        let log = dir.path().join("log.txt");
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        std::fs::write(&log, "start\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(path.clone()));
+        let mut files = FileCommandPlugin;
+        files.on_init(&mut editor);
+        editor.buffer.lines[0] = "ONE".to_string();
+        editor.dirty = true;
+
+        files.on_command(&mut editor, &format!("2,3w {}", part.display()));
+        assert_eq!(std::fs::read_to_string(&part).unwrap(), "two\nthree\n");
+        assert_eq!(
+            editor.status,
+            format!("Wrote 2 lines to {}", part.display())
+        );
+        files.on_command(&mut editor, &format!("w >> {}", log.display()));
+        files.on_command(&mut editor, &format!("1w>>{}", log.display()));
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "start\nONE\ntwo\nthree\nONE\n"
+        );
+        assert!(editor.dirty);
+        assert_eq!(editor.file_path.as_deref(), Some(path.as_path()));
+
+        files.on_command(
+            &mut editor,
+            &format!("w >> {}", dir.path().join("new").display()),
+        );
+        assert!(editor.status.starts_with("Write failed"));
+        files.on_command(&mut editor, "1w");
+        assert_eq!(editor.status, "Use ! to write partial buffer");
+        files.on_command(&mut editor, "1w!");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ONE\n");
+        assert!(editor.dirty);
+    }
+
+    #[test]
+    fn ctrl_caret_toggles_to_the_alternate_file_and_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.txt");
+        let other = dir.path().join("other.txt");
+        std::fs::write(&notes, "one\ntwo\nthree\n").unwrap();
+        std::fs::write(&other, "alpha\nbeta\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(notes.clone()));
+        let mut files = FileCommandPlugin;
+        files.on_init(&mut editor);
+        let ctrl_caret = key(KeyCode::Char('^'), KeyModifiers::CONTROL);
+        files.on_event(&mut editor, &ctrl_caret);
+        assert_eq!(editor.status, "No alternate file");
+
+        editor.goto_position(2, 1);
+        files.on_command(&mut editor, &format!("e {}", other.display()));
+        editor.goto_position(1, 2);
+        files.on_event(&mut editor, &ctrl_caret);
+        assert_eq!(editor.file_path, Some(notes.clone()));
+        assert_eq!(editor.cursor, Cursor { row: 2, col: 1 });
+        assert_eq!(editor.alternate_file, Some(other.clone()));
+
+        let ctrl_6 = key(KeyCode::Char('6'), KeyModifiers::CONTROL);
+        files.on_event(&mut editor, &ctrl_6);
+        assert_eq!(editor.file_path, Some(other));
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 2 });
+
+        editor.dirty = true;
+        files.on_event(&mut editor, &ctrl_6);
+        assert_eq!(
+            editor.status,
+            "No write since last change (add ! to override)"
+        );
+    }
+
+    #[test]
+    fn hidden_buffers_keep_changes_until_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.txt");
+        let other = dir.path().join("other.txt");
+        std::fs::write(&notes, "one\ntwo\n").unwrap();
+        std::fs::write(&other, "alpha\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(notes.clone()));
+        let mut files = FileCommandPlugin;
+        files.on_init(&mut editor);
+        editor.buffer.lines[1] = "changed".to_string();
+        editor.goto_position(1, 3);
+        editor.dirty = true;
+        let edit_other = format!("e {}", other.display());
+        files.on_command(&mut editor, &edit_other);
+        assert_eq!(
+            editor.status,
+            "No write since last change (add ! to override)"
+        );
+
+        editor.options.hidden = true;
+        files.on_command(&mut editor, &edit_other);
+        assert_eq!(editor.buffer.lines, ["alpha"]);
+        assert!(!editor.dirty);
+        files.on_command(&mut editor, "q");
+        assert_eq!(
+            editor.status,
+            format!(
+                "No write since last change for buffer \"{}\" (add ! to override)",
+                notes.display()
+            )
+        );
+        assert!(!editor.should_quit);
+
+        files.on_command(&mut editor, &format!("e {}", notes.display()));
+        assert_eq!(editor.buffer.lines, ["one", "changed"]);
+        assert!(editor.dirty);
+        assert_eq!(editor.cursor, Cursor { row: 1, col: 3 });
+        assert!(editor.hidden_buffers.is_empty());
+
+        // Deleting the open buffer switches to the alternate file; deleting
+        // the last leaves an empty one.
+        files.on_command(&mut editor, "bd");
+        assert_eq!(
+            editor.status,
+            "No write since last change (add ! to override)"
+        );
+        files.on_command(&mut editor, "bd!");
+        assert_eq!(editor.file_path, Some(other.clone()));
+        assert_eq!(editor.alternate_file, None);
+        files.on_command(&mut editor, "bd");
+        assert_eq!(editor.file_path, None);
+        assert_eq!(editor.buffer.lines, [""]);
+        assert_eq!(editor.status, format!("Deleted buffer {}", other.display()));
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "one\ntwo\n");
+
+        files.on_command(&mut editor, &format!("e {}", notes.display()));
+        editor.dirty = true;
+        files.on_command(&mut editor, &edit_other);
+        files.on_command(&mut editor, &format!("bd {}", notes.display()));
+        assert!(
+            editor
+                .status
+                .starts_with("No write since last change for buffer")
+        );
+        files.on_command(&mut editor, &format!("bd! {}", notes.display()));
+        assert!(editor.hidden_buffers.is_empty());
+        files.on_command(&mut editor, "q");
+        assert!(editor.should_quit);
+    }
+
+    #[test]
+    fn scratch_buffers_are_never_modified_or_written_over_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "one\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(notes.clone()));
+        let mut files = FileCommandPlugin;
+        files.on_init(&mut editor);
+
+        editor.open_scratch("registers", vec!["\"a   alpha".to_string()]);
+        ScratchViewPlugin.on_event(&mut editor, &key(KeyCode::Char('e'), KeyModifiers::NONE));
+        assert!(editor.scratch.is_none());
+        assert!(editor.is_scratch_buffer());
+        assert_eq!(editor.buffer.lines, ["\"a   alpha"]);
+        assert_eq!(editor.file_path, None);
+        assert_eq!(editor.alternate_file, Some(notes.clone()));
+
+        editor.insert_char('x');
+        assert!(!editor.dirty);
+        files.on_command(&mut editor, "w");
+        assert_eq!(editor.status, "No file name");
+        let out = dir.path().join("out.txt");
+        files.on_command(&mut editor, &format!("w {}", out.display()));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "x\"a   alpha\n");
+        assert_eq!(editor.file_path, None);
+
+        // Leaving it needs no `!`, and the file it replaced comes back.
+        files.on_command(&mut editor, &format!("e {}", notes.display()));
+        assert_eq!(editor.buffer.lines, ["one"]);
+        assert!(!editor.is_scratch_buffer());
+        OptionsPlugin.on_command(&mut editor, "set bt=nofile");
+        files.on_command(&mut editor, "w");
+        assert_eq!(editor.status, "Cannot write, 'buftype' is nofile");
+        OptionsPlugin.on_command(&mut editor, "set bt=help");
+        assert_eq!(editor.status, "Invalid argument: bt=help");
+        files.on_command(&mut editor, "scratch");
+        assert_eq!(editor.buffer.lines, [""]);
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "one\n");
+    }
+
+    #[test]
+    fn focus_changes_check_the_file_and_autosave() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "one\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(notes.clone()));
+        let mut files = FileCommandPlugin;
+        files.on_init(&mut editor);
+        editor.status.clear();
+        let result = files.on_event(&mut editor, &Event::FocusGained);
+        assert_eq!(result, EventResult::Ignored);
+        assert_eq!(editor.status, "");
+
+        std::fs::write(&notes, "two\n").unwrap();
+        let earlier = std::time::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let file = std::fs::File::options().write(true).open(&notes).unwrap();
+        file.set_modified(earlier).unwrap();
+        files.on_event(&mut editor, &Event::FocusGained);
+        assert_eq!(
+            editor.status,
+            format!(
+                "\"{}\" has changed since editing started; :e! reloads it",
+                notes.display()
+            )
+        );
+        files.on_command(&mut editor, "checktime");
+        assert_eq!(editor.status, "File unchanged on disk");
+
+        editor.buffer.lines[0] = "three".to_string();
+        editor.dirty = true;
+        files.on_event(&mut editor, &Event::FocusLost);
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "two\n");
+        editor.options.autosave = true;
+        files.on_event(&mut editor, &Event::FocusLost);
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "three\n");
+        assert!(!editor.dirty);
+        files.on_command(&mut editor, "checktime");
+        assert_eq!(editor.status, "File unchanged on disk");
+
+        std::fs::remove_file(&notes).unwrap();
+        files.on_command(&mut editor, "checktime");
+        assert_eq!(
+            editor.status,
+            format!("\"{}\" is no longer available", notes.display())
+        );
+    }
+
+    #[test]
+    fn autowrite_saves_before_leaving_the_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.txt");
+        let other = dir.path().join("other.txt");
+        std::fs::write(&notes, "one\n").unwrap();
+        std::fs::write(&other, "alpha\n").unwrap();
+        let mut editor = Editor::new(80, 24, Some(notes.clone()));
+        let mut files = FileCommandPlugin;
+        files.on_init(&mut editor);
+        editor.options.autowrite = true;
+        files.on_command(&mut editor, &format!("e {}", other.display()));
+        editor.buffer.lines[0] = "beta".to_string();
+        editor.dirty = true;
+
+        // `:e` needs autowriteall; Ctrl-^ and `:!` autowrite.
+        files.on_command(&mut editor, &format!("e {}", notes.display()));
+        assert_eq!(
+            editor.status,
+            "No write since last change (add ! to override)"
+        );
+        files.on_event(&mut editor, &key(KeyCode::Char('^'), KeyModifiers::CONTROL));
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "beta\n");
+        assert_eq!(editor.file_path, Some(notes.clone()));
+
+        editor.buffer.lines[0] = "two".to_string();
+        editor.dirty = true;
+        ShellPlugin.on_command(&mut editor, "!true");
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "two\n");
+        assert!(!editor.dirty);
+
+        // Suspending asks the main loop to stop, after writing unless `!`.
+        editor.buffer.lines[0] = "four".to_string();
+        editor.dirty = true;
+        ShellPlugin.on_command(&mut editor, "sus!");
+        assert!(editor.suspend_requested && editor.dirty);
+        editor.suspend_requested = false;
+        ShellPlugin.on_event(&mut editor, &key(KeyCode::Char('z'), KeyModifiers::CONTROL));
+        assert!(editor.suspend_requested);
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "four\n");
+
+        editor.options.autowrite = false;
+        editor.options.autowriteall = true;
+        editor.buffer.lines[0] = "three".to_string();
+        editor.dirty = true;
+        files.on_command(&mut editor, "q");
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "three\n");
+        assert!(editor.should_quit);
+    }
+
+    #[test]
+    fn file_commands_expand_the_current_and_alternate_file_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.txt");
+        let other = dir.path().join("other.md");
+        std::fs::write(&notes, "one\ntwo\n").unwrap();
+        std::fs::write(&other, "# title\n").unwrap();
+        let mut editor = Editor::new(80, 24, None);
+        let mut files = FileCommandPlugin;
+        assert_eq!(files.on_command(&mut editor, "w %"), EventResult::Consumed);
+        assert_eq!(editor.status, "No file name to substitute for '%'");
+
+        files.on_command(&mut editor, &format!("e {}", notes.display()));
+        files.on_command(&mut editor, "e %:h/other.md");
+        assert_eq!(editor.file_path, Some(other.clone()));
+        assert_eq!(editor.alternate_file, Some(notes.clone()));
+
+        LineCommandPlugin.on_command(&mut editor, "r #");
+        assert_eq!(editor.buffer.lines, ["# title", "one", "two"]);
+        assert_eq!(editor.cursor.row, 1);
+        LineCommandPlugin.on_command(&mut editor, "0r %");
+        assert_eq!(editor.buffer.lines[..2], ["# title", "# title"]);
+        files.on_command(&mut editor, "w %:r.bak");
+        let backup = dir.path().join("other.bak");
+        assert_eq!(
+            std::fs::read_to_string(&backup).unwrap(),
+            "# title\n# title\none\ntwo\n"
+        );
+
+        ShellPlugin.on_command(&mut editor, "!echo \\% #:t; exit 3");
+        assert_eq!(editor.status, "shell returned 3");
+        let scratch = editor.scratch.take().expect("shell output");
+        assert_eq!(scratch.lines, ["% notes.txt"]);
+    }
+
+    #[test]
+    fn gf_edits_the_file_under_the_cursor_found_in_path() {
+        assert_eq!(
+            GotoFilePlugin::name_at("see src/main.rs:42: here", 6),
+            Some(("src/main.rs".to_string(), Some(42)))
+        );
+        assert_eq!(
+            GotoFilePlugin::name_at("(notes.txt).", 3),
+            Some(("notes.txt".to_string(), None))
+        );
+        assert_eq!(GotoFilePlugin::name_at("end  ", 4), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(sub.join("target.txt"), "one\ntwo\nthree\n").unwrap();
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "see sub/target.txt:3\nand lib.txt:2\n").unwrap();
+        let mut editor = Editor::new(80, 24, None);
+        FileCommandPlugin::command_edit(&mut editor, &notes.to_string_lossy(), false);
+        let mut plugin = GotoFilePlugin;
+
+        editor.cursor.col = 4;
+        editor.pending_keys.push('g');
+        type_keys(&mut plugin, &mut editor, "F");
+        assert_eq!(editor.file_path, Some(sub.join("target.txt")));
+        assert_eq!(editor.cursor.row, 2);
+        assert_eq!(
+            editor.jumps.back(editor.current_jump()).unwrap().path,
+            Some(notes.clone())
+        );
+
+        FileCommandPlugin::command_edit(&mut editor, &notes.to_string_lossy(), false);
+        editor.cursor = Cursor { row: 1, col: 4 };
+        editor.pending_keys.push('g');
+        type_keys(&mut plugin, &mut editor, "f");
+        assert_eq!(editor.status, "Can't find file \"lib.txt\" in path");
+        std::fs::write(sub.join("lib.txt"), "x\n").unwrap();
+        editor.options.path = format!(".,{}", sub.display());
+        editor.pending_keys.push('g');
+        type_keys(&mut plugin, &mut editor, "f");
+        assert_eq!(editor.file_path, Some(sub.join("lib.txt")));
+        assert_eq!(editor.cursor.row, 0);
+    }
+
+    #[test]
+    fn ctrl_k_inserts_digraphs() {
+        let mut editor = Editor::new(80, 24, None);
+        editor.mode = Mode::Insert;
+        let mut plugin = DigraphPlugin::new();
+        for (first, second) in [('e', '\''), ('-', '>'), ('x', 'q')] {
+            plugin.on_event(&mut editor, &key(KeyCode::Char('k'), KeyModifiers::CONTROL));
+            plugin.on_event(&mut editor, &key(KeyCode::Char(first), KeyModifiers::NONE));
+            plugin.on_event(&mut editor, &key(KeyCode::Char(second), KeyModifiers::NONE));
+        }
+        assert_eq!(editor.buffer.lines[0], "é→q");
+        assert_eq!(
+            plugin.on_event(&mut editor, &key(KeyCode::Char('a'), KeyModifiers::NONE)),
+            EventResult::Ignored
+        );
+
+        plugin.on_command(&mut editor, "digraphs");
+        let scratch = editor.scratch.as_ref().expect("digraph listing");
+        assert!(scratch.lines[0].starts_with("NS"));
     }
 
     #[test]
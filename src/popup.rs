@@ -0,0 +1,367 @@
+//! Popups: bordered boxes drawn over the window next to the cursor, for
+//! hover documentation, suggestions and help excerpts. Their text is either
+//! preformatted or a little Markdown (headings, lists, emphasis, code), and
+//! scrolls when it is taller than the room on screen.
+
+use crossterm::style::{Attribute, Color, ContentStyle};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editor::{StyledSpan, display_width, grapheme_width};
+
+/// Most columns of text in a popup, not counting its border.
+pub const POPUP_MAX_WIDTH: usize = 78;
+
+/// How a run of text is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Plain,
+    Bold,
+    Italic,
+    Code,
+    Heading,
+}
+
+impl Role {
+    fn style(self, base: ContentStyle) -> ContentStyle {
+        let mut style = base;
+        match self {
+            Role::Plain => {}
+            Role::Bold => style.attributes.set(Attribute::Bold),
+            Role::Italic => style.attributes.set(Attribute::Italic),
+            Role::Code => style.foreground_color = Some(Color::Cyan),
+            Role::Heading => {
+                style.attributes.set(Attribute::Bold);
+                style.attributes.set(Attribute::Underlined);
+            }
+        }
+        style
+    }
+}
+
+/// One line of source text, split into runs by role.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Block {
+    runs: Vec<(String, Role)>,
+    /// Whether the line wraps at spaces; otherwise it is cut at the edge.
+    wrap: bool,
+    /// Columns that wrapped rows are indented by, to line up under a list
+    /// item's text.
+    hang: usize,
+}
+
+/// A row of a popup, ready to draw: its text and the styles over it.
+pub type PopupRow = (String, Vec<StyledSpan>);
+
+/// The text of a popup and how far it is scrolled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Popup {
+    /// Shown in the top border.
+    pub title: String,
+    blocks: Vec<Block>,
+    /// The first row shown.
+    pub scroll: usize,
+}
+
+impl Popup {
+    /// A popup showing `lines` as they are, such as program output.
+    pub fn plain(title: impl Into<String>, lines: Vec<String>) -> Self {
+        let blocks = lines
+            .into_iter()
+            .map(|line| Block {
+                runs: vec![(line, Role::Plain)],
+                wrap: false,
+                hang: 0,
+            })
+            .collect();
+        Self {
+            title: title.into(),
+            blocks,
+            scroll: 0,
+        }
+    }
+
+    /// A popup showing Markdown `text`: `#` headings, `-` and `*` list
+    /// items, `**bold**`, `*italic*`, `` `code` `` and fenced code blocks.
+    /// Paragraphs are wrapped to the popup's width.
+    pub fn markdown(title: impl Into<String>, text: &str) -> Self {
+        let mut blocks = Vec::new();
+        let mut in_fence = false;
+        for line in text.lines() {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+                continue;
+            }
+            if in_fence {
+                blocks.push(Block {
+                    runs: vec![(line.to_string(), Role::Code)],
+                    wrap: false,
+                    hang: 0,
+                });
+                continue;
+            }
+            let trimmed = line.trim_start();
+            let indent = line.len() - trimmed.len();
+            if let Some(heading) = trimmed.strip_prefix('#') {
+                let heading = heading.trim_start_matches('#').trim();
+                blocks.push(Block {
+                    runs: vec![(heading.to_string(), Role::Heading)],
+                    wrap: true,
+                    hang: 0,
+                });
+            } else if let Some(item) = trimmed
+                .strip_prefix("- ")
+                .or_else(|| trimmed.strip_prefix("* "))
+            {
+                let mut runs = vec![(format!("{}• ", " ".repeat(indent)), Role::Plain)];
+                runs.extend(inline_runs(item));
+                blocks.push(Block {
+                    runs,
+                    wrap: true,
+                    hang: indent + 2,
+                });
+            } else {
+                blocks.push(Block {
+                    runs: inline_runs(trimmed),
+                    wrap: true,
+                    hang: 0,
+                });
+            }
+        }
+        Self {
+            title: title.into(),
+            blocks,
+            scroll: 0,
+        }
+    }
+
+    /// Columns of text a popup may use on a screen `screen_width` wide.
+    pub fn text_width(screen_width: usize) -> usize {
+        POPUP_MAX_WIDTH.min(screen_width.saturating_sub(2)).max(1)
+    }
+
+    /// The text laid out `width` columns wide, with `base` as the style of
+    /// plain text.
+    pub fn rows(&self, width: usize, base: ContentStyle) -> Vec<PopupRow> {
+        let mut rows = Vec::new();
+        for block in &self.blocks {
+            if block.wrap {
+                wrap_block(block, width, base, &mut rows);
+            } else {
+                let mut row = RowBuilder::default();
+                for (text, role) in &block.runs {
+                    for grapheme in text.graphemes(true) {
+                        if !row.push(grapheme, role.style(base), width) {
+                            break;
+                        }
+                    }
+                }
+                rows.push(row.finish());
+            }
+        }
+        rows
+    }
+}
+
+/// Splits a line at inline Markdown markers. A marker without a closing one
+/// later in the line is kept as text.
+fn inline_runs(line: &str) -> Vec<(String, Role)> {
+    let mut runs: Vec<(String, Role)> = Vec::new();
+    let mut text = String::new();
+    let mut role = Role::Plain;
+    let mut rest = line;
+    while let Some(ch) = rest.chars().next() {
+        let marker = match (role, ch) {
+            (Role::Plain | Role::Code, '`') => Some(("`", Role::Code)),
+            (Role::Plain | Role::Bold, '*') if rest.starts_with("**") => Some(("**", Role::Bold)),
+            (Role::Plain | Role::Italic, '*') => Some(("*", Role::Italic)),
+            _ => None,
+        };
+        if let Some((marker, marked)) = marker {
+            let after = &rest[marker.len()..];
+            let opens = role == Role::Plain
+                && after.contains(marker)
+                && !after.starts_with(char::is_whitespace);
+            if opens || role == marked {
+                if !text.is_empty() {
+                    runs.push((std::mem::take(&mut text), role));
+                }
+                role = if opens { marked } else { Role::Plain };
+                rest = after;
+                continue;
+            }
+        }
+        text.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    if !text.is_empty() {
+        runs.push((text, role));
+    }
+    runs
+}
+
+/// Builds one row of text and its spans, merging neighbouring cells of the
+/// same style.
+#[derive(Default)]
+struct RowBuilder {
+    text: String,
+    width: usize,
+    spans: Vec<StyledSpan>,
+}
+
+impl RowBuilder {
+    /// Adds `grapheme` unless the row would grow past `limit` columns.
+    fn push(&mut self, grapheme: &str, style: ContentStyle, limit: usize) -> bool {
+        let columns = grapheme_width(grapheme);
+        if self.width + columns > limit {
+            return false;
+        }
+        self.text.push_str(grapheme);
+        match self.spans.last_mut() {
+            Some(span) if span.style == style && span.start + span.len == self.width => {
+                span.len += columns;
+            }
+            _ => self.spans.push(StyledSpan {
+                start: self.width,
+                len: columns,
+                style,
+            }),
+        }
+        self.width += columns;
+        true
+    }
+
+    fn finish(self) -> PopupRow {
+        (self.text, self.spans)
+    }
+}
+
+type StyledGrapheme<'a> = (&'a str, ContentStyle);
+
+/// Wraps a block at spaces, breaking words longer than a row.
+fn wrap_block(block: &Block, width: usize, base: ContentStyle, rows: &mut Vec<PopupRow>) {
+    let cells: Vec<StyledGrapheme> = block
+        .runs
+        .iter()
+        .flat_map(|(text, role)| text.graphemes(true).map(|g| (g, role.style(base))))
+        .collect();
+    // Words with the spaces before them, so runs of spaces in a list
+    // item's leading indent survive.
+    let mut words: Vec<(&[StyledGrapheme], &[StyledGrapheme])> = Vec::new();
+    let mut start = 0;
+    while start < cells.len() {
+        let spaces = cells[start..].iter().take_while(|(g, _)| *g == " ").count();
+        let word_start = start + spaces;
+        let len = cells[word_start..]
+            .iter()
+            .take_while(|(g, _)| *g != " ")
+            .count();
+        words.push((
+            &cells[start..word_start],
+            &cells[word_start..word_start + len],
+        ));
+        start = word_start + len;
+    }
+
+    let hang = block.hang.min(width.saturating_sub(1));
+    let mut row = RowBuilder::default();
+    // Where the text of the current row starts: continuation rows are
+    // indented by `hang`.
+    let mut line_start = 0;
+    let new_row = |row: &mut RowBuilder, rows: &mut Vec<PopupRow>| {
+        rows.push(std::mem::take(row).finish());
+        for _ in 0..hang {
+            row.push(" ", base, width);
+        }
+    };
+    for (spaces, word) in words {
+        let word_width: usize = word.iter().map(|(g, _)| grapheme_width(g)).sum();
+        if row.width > line_start && row.width + spaces.len() + word_width > width {
+            new_row(&mut row, rows);
+            line_start = hang;
+        }
+        if row.width > line_start || line_start == 0 {
+            for (grapheme, style) in spaces {
+                row.push(grapheme, *style, width);
+            }
+        }
+        for (grapheme, style) in word {
+            if !row.push(grapheme, *style, width) {
+                new_row(&mut row, rows);
+                line_start = hang;
+                row.push(grapheme, *style, width);
+            }
+        }
+    }
+    rows.push(row.finish());
+}
+
+/// A border row `width` columns wide between its corners, with `label` a
+/// column in from the left and cut to fit.
+pub fn border_row(left: char, label: &str, right: char, width: usize) -> String {
+    let mut row = RowBuilder::default();
+    row.push("─", ContentStyle::new(), width);
+    for grapheme in label.graphemes(true) {
+        if !row.push(grapheme, ContentStyle::new(), width) {
+            break;
+        }
+    }
+    while row.push("─", ContentStyle::new(), width) {}
+    format!("{}{}{}", left, row.text, right)
+}
+
+/// Columns of the widest row.
+pub fn rows_width(rows: &[PopupRow]) -> usize {
+    rows.iter()
+        .map(|(text, _)| display_width(text))
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(rows: &[PopupRow]) -> Vec<&str> {
+        rows.iter().map(|(text, _)| text.as_str()).collect()
+    }
+
+    #[test]
+    fn markdown_is_styled_and_wrapped() {
+        let popup = Popup::markdown(
+            "help",
+            "# Keys\n- `dd` deletes **one** line and *keeps* it\n```\nlet x = 1;\n```\na * b",
+        );
+        let rows = popup.rows(16, ContentStyle::new());
+        assert_eq!(
+            texts(&rows),
+            vec![
+                "Keys",
+                "• dd deletes one",
+                "  line and keeps",
+                "  it",
+                "let x = 1;",
+                "a * b"
+            ]
+        );
+        let styles: Vec<ContentStyle> = rows[1].1.iter().map(|span| span.style).collect();
+        assert_eq!(styles.len(), 4);
+        assert_eq!(styles[1], Role::Code.style(ContentStyle::new()));
+        assert!(styles[3].attributes.has(Attribute::Bold));
+        assert!(rows[2].1[1].style.attributes.has(Attribute::Italic));
+        assert_eq!(rows[4].1[0].style, Role::Code.style(ContentStyle::new()));
+        assert_eq!(rows_width(&rows), 16);
+        assert_eq!(border_row('┌', "help", '┐', 8), "┌─help───┐");
+        assert_eq!(border_row('└', "", '┘', 3), "└───┘");
+    }
+
+    #[test]
+    fn plain_text_is_cut_and_long_words_are_broken() {
+        let popup = Popup::plain("man", vec!["NAME  ls - list".to_string()]);
+        assert_eq!(texts(&popup.rows(8, ContentStyle::new())), vec!["NAME  ls"]);
+        let popup = Popup::markdown("x", "abcdefghij k");
+        assert_eq!(
+            texts(&popup.rows(4, ContentStyle::new())),
+            vec!["abcd", "efgh", "ij k"]
+        );
+    }
+}
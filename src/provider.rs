@@ -0,0 +1,283 @@
+//! Where files are read from and written to. Names are local paths unless
+//! they start with a URL scheme a provider handles, such as
+//! `scp://host/path`, which is fetched and written back over `ssh`, or
+//! `https://host/path`, which is downloaded with `curl` for reading only.
+//! `Editor::load_from_path` and `Editor::save_to_path` go through the
+//! provider for the file's name.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use crate::save;
+
+/// Reads and writes the files whose names it handles.
+pub trait FileProvider: Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Writes `contents`, flushing them to disk with `sync` where the
+    /// provider can, and with `force` over a file the user made read-only.
+    /// Returns a warning to show when the file was written but not quite
+    /// as asked.
+    fn write(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        sync: bool,
+        force: bool,
+    ) -> io::Result<Option<String>>;
+
+    /// Whether its files can only be read, so buffers opened from them are
+    /// `readonly`.
+    fn read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Files on the local disk.
+pub struct LocalFiles;
+
+impl FileProvider for LocalFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        sync: bool,
+        force: bool,
+    ) -> io::Result<Option<String>> {
+        save::write_file(path, contents, sync, force)
+    }
+}
+
+/// Files on another host, named `scp://[user@]host[:port]/path`, read with
+/// `ssh host cat` and written with `ssh host 'cat >'`. The path is relative
+/// to the remote home directory; `scp://host//etc/hosts` is absolute.
+pub struct ScpFiles;
+
+/// The parts of an `scp://` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemotePath<'a> {
+    /// `host` or `user@host`.
+    pub host: &'a str,
+    pub port: Option<&'a str>,
+    pub path: &'a str,
+}
+
+impl<'a> RemotePath<'a> {
+    pub fn parse(url: &'a str) -> Option<Self> {
+        let rest = url.strip_prefix("scp://")?;
+        let (authority, path) = rest.split_once('/')?;
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+                (host, Some(port))
+            }
+            _ => (authority, None),
+        };
+        if host.is_empty() || path.is_empty() {
+            return None;
+        }
+        Some(Self { host, port, path })
+    }
+
+    /// `ssh` running `command` on the host. Batch mode makes it fail rather
+    /// than ask for a password over the editor's screen.
+    fn ssh(&self, command: &str) -> Command {
+        let mut ssh = Command::new("ssh");
+        ssh.args(["-o", "BatchMode=yes"]);
+        if let Some(port) = self.port {
+            ssh.args(["-p", port]);
+        }
+        ssh.arg("--").arg(self.host).arg(command);
+        ssh
+    }
+}
+
+impl ScpFiles {
+    fn remote(path: &Path) -> io::Result<RemotePath<'_>> {
+        path.to_str().and_then(RemotePath::parse).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expected scp://[user@]host[:port]/path",
+            )
+        })
+    }
+}
+
+impl FileProvider for ScpFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let remote = Self::remote(path)?;
+        let output = remote
+            .ssh(&format!("cat -- {}", shell_quote(remote.path)))
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| spawn_error("ssh", err))?;
+        if !output.status.success() {
+            return Err(command_error("ssh", &output));
+        }
+        Ok(output.stdout)
+    }
+
+    fn write(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        _sync: bool,
+        _force: bool,
+    ) -> io::Result<Option<String>> {
+        let remote = Self::remote(path)?;
+        let mut child = remote
+            .ssh(&format!("cat > {}", shell_quote(remote.path)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| spawn_error("ssh", err))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A refused connection closes the pipe early; the status says why.
+            let _ = stdin.write_all(contents);
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(command_error("ssh", &output));
+        }
+        Ok(None)
+    }
+}
+
+/// Files on the web, named `http://` or `https://` URLs and downloaded with
+/// `curl`. They cannot be written back.
+pub struct WebFiles;
+
+impl FileProvider for WebFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let output = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location", "--"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| spawn_error("curl", err))?;
+        if !output.status.success() {
+            return Err(command_error("curl", &output));
+        }
+        Ok(output.stdout)
+    }
+
+    fn write(
+        &self,
+        path: &Path,
+        _contents: &[u8],
+        _sync: bool,
+        _force: bool,
+    ) -> io::Result<Option<String>> {
+        Err(io::Error::other(format!(
+            "{} can only be read",
+            path.display()
+        )))
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+}
+
+/// The providers of URL schemes; other names are local files.
+static PROVIDERS: &[(&str, &dyn FileProvider)] = &[
+    ("scp", &ScpFiles),
+    ("http", &WebFiles),
+    ("https", &WebFiles),
+];
+
+/// The provider for the file at `path`.
+pub fn provider_for(path: &Path) -> &'static dyn FileProvider {
+    scheme(path)
+        .and_then(|scheme| {
+            PROVIDERS
+                .iter()
+                .find(|(handled, _)| *handled == scheme)
+                .map(|(_, provider)| *provider)
+        })
+        .unwrap_or(&LocalFiles)
+}
+
+/// Whether `path` is a URL of a provider rather than a local path.
+pub fn is_remote(path: &Path) -> bool {
+    scheme(path).is_some_and(|scheme| PROVIDERS.iter().any(|(handled, _)| *handled == scheme))
+}
+
+/// The scheme of a URL like `scp://host/path`.
+fn scheme(path: &Path) -> Option<&str> {
+    let (scheme, _) = path.to_str()?.split_once("://")?;
+    scheme
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'-')
+        .then_some(scheme)
+}
+
+/// `text` quoted for a POSIX shell.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Why `program` could not run, not mistaken for a missing remote file.
+pub fn spawn_error(program: &str, err: io::Error) -> io::Error {
+    io::Error::other(format!("cannot run {}: {}", program, err))
+}
+
+/// The error a failed program reported on its last line of stderr, as
+/// `NotFound` when that says the file does not exist.
+pub fn command_error(program: &str, output: &Output) -> io::Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .map(|line| line.trim().to_string())
+        .unwrap_or_else(|| format!("{} {}", program, output.status));
+    let kind = if message.contains("No such file") {
+        io::ErrorKind::NotFound
+    } else {
+        io::ErrorKind::Other
+    };
+    io::Error::new(kind, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scp_urls_name_a_host_port_and_path() {
+        assert_eq!(
+            RemotePath::parse("scp://me@box:2222/notes/todo.txt"),
+            Some(RemotePath {
+                host: "me@box",
+                port: Some("2222"),
+                path: "notes/todo.txt",
+            })
+        );
+        let absolute = RemotePath::parse("scp://box//etc/hosts").unwrap();
+        assert_eq!((absolute.host, absolute.port), ("box", None));
+        assert_eq!(absolute.path, "/etc/hosts");
+        assert_eq!(RemotePath::parse("scp://box/"), None);
+        assert_eq!(RemotePath::parse("scp:///path"), None);
+
+        let ssh = absolute.ssh("cat -- '/etc/hosts'");
+        let args: Vec<_> = ssh.get_args().collect();
+        assert_eq!(
+            args,
+            ["-o", "BatchMode=yes", "--", "box", "cat -- '/etc/hosts'"]
+        );
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+
+        assert!(is_remote(Path::new("scp://box/a.txt")));
+        assert!(provider_for(Path::new("https://example.com/a.txt")).read_only());
+        assert!(!provider_for(Path::new("scp://box/a.txt")).read_only());
+        assert!(!is_remote(Path::new("notes/a.txt")));
+        assert!(!is_remote(Path::new("weird://box/a.txt")));
+    }
+}
@@ -0,0 +1,135 @@
+//! The quickfix list: locations across files, filled by `:grep` and
+//! visited with `:cnext`, `:cc`, `:cdo` and friends.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+/// Most matches `:grep` collects before it stops searching.
+pub const GREP_LIMIT: usize = 10_000;
+
+/// One location in the list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuickfixEntry {
+    pub path: PathBuf,
+    pub row: usize,
+    /// Character column of the match.
+    pub col: usize,
+    pub text: String,
+}
+
+/// The entries of the last `:grep` and the one last visited.
+#[derive(Debug, Clone, Default)]
+pub struct QuickfixList {
+    pub entries: Vec<QuickfixEntry>,
+    pub index: usize,
+}
+
+impl QuickfixList {
+    pub fn new(entries: Vec<QuickfixEntry>) -> Self {
+        Self { entries, index: 0 }
+    }
+
+    /// Indexes of the first entry in each file, in list order.
+    pub fn first_in_each_file(&self) -> Vec<usize> {
+        let mut seen: Vec<&Path> = Vec::new();
+        let mut firsts = Vec::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            if !seen.contains(&entry.path.as_path()) {
+                seen.push(&entry.path);
+                firsts.push(index);
+            }
+        }
+        firsts
+    }
+}
+
+/// Searches `files(paths)` line by line for `regex`, skipping files that
+/// are not UTF-8 text. Stops after `GREP_LIMIT` matches.
+pub fn grep(regex: &Regex, paths: &[PathBuf]) -> Vec<QuickfixEntry> {
+    let mut entries = Vec::new();
+    for file in files(paths) {
+        let Ok(contents) = fs::read_to_string(&file) else {
+            continue;
+        };
+        let path = file.strip_prefix(".").unwrap_or(&file).to_path_buf();
+        for (row, line) in contents.lines().enumerate() {
+            if let Some(found) = regex.find(line) {
+                entries.push(QuickfixEntry {
+                    path: path.clone(),
+                    row,
+                    col: line[..found.start()].chars().count(),
+                    text: line.trim().to_string(),
+                });
+                if entries.len() >= GREP_LIMIT {
+                    return entries;
+                }
+            }
+        }
+    }
+    entries
+}
+
+/// `paths` with directories replaced by the files below them, in name
+/// order. Hidden files and directories are skipped.
+pub fn files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files(path, &mut files);
+    }
+    files
+}
+
+/// Adds `path`, or the files below it in name order, to `files`.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return;
+    }
+    let Ok(dir) = fs::read_dir(path) else {
+        return;
+    };
+    let mut children: Vec<PathBuf> = dir
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect();
+    children.sort();
+    for child in children {
+        collect_files(&child, files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grep_walks_directories_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join("b.txt"), "foo\nbar foo\n").unwrap();
+        fs::write(dir.path().join("sub/a.txt"), "  x foo\n").unwrap();
+        fs::write(dir.path().join(".git/c.txt"), "foo\n").unwrap();
+        fs::write(dir.path().join("bin"), [0xff, 0xfe, b'f']).unwrap();
+
+        let regex = Regex::new("foo").unwrap();
+        let entries = grep(&regex, &[dir.path().to_path_buf()]);
+        let found: Vec<_> = entries
+            .iter()
+            .map(|e| (e.path.strip_prefix(dir.path()).unwrap(), e.row, e.col))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Path::new("b.txt"), 0, 0),
+                (Path::new("b.txt"), 1, 4),
+                (Path::new("sub/a.txt"), 0, 4),
+            ]
+        );
+        assert_eq!(entries[2].text, "x foo");
+        assert_eq!(QuickfixList::new(entries).first_in_each_file(), [0, 2]);
+    }
+}
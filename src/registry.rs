@@ -0,0 +1,525 @@
+//! What the plugins offer: the ex commands and key bindings each one
+//! handles, with a short description, collected at startup so the command
+//! palette can list and run them. Plugins declare theirs through
+//! `Plugin::commands` and `Plugin::keys`; users add commands of their own
+//! with `:command`.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::autocmd::AutoEvent;
+use crate::editor::{Mode, Plugin};
+
+/// An ex command a plugin handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandInfo {
+    /// The name typed after `:`, as in `w` or `FindReplace`.
+    pub name: &'static str,
+    /// The arguments in `[optional]` / `{required}` notation; empty when the
+    /// command takes none.
+    pub args: &'static str,
+    pub description: &'static str,
+}
+
+impl CommandInfo {
+    pub const fn new(name: &'static str, args: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            args,
+            description,
+        }
+    }
+
+    /// Whether the command needs arguments before it can run: it has some,
+    /// and the first is not `[optional]`.
+    pub fn needs_args(&self) -> bool {
+        !self.args.is_empty() && !self.args.starts_with('[')
+    }
+
+    /// How the command is typed, as in `:sort [flags]` or
+    /// `:s/{pattern}/{replacement}/[flags]`.
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() || self.args.starts_with('/') {
+            format!(":{}{}", self.name, self.args)
+        } else {
+            format!(":{} {}", self.name, self.args)
+        }
+    }
+
+    /// The command line to leave for the user to finish: the usage up to its
+    /// first `{placeholder}`, without the colon.
+    pub fn template(&self) -> String {
+        let usage = self.usage();
+        let end = usage.find('{').unwrap_or(usage.len());
+        usage[1..end].to_string()
+    }
+}
+
+/// A key binding a plugin handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub mode: Mode,
+    /// The keys in Vim notation: `gg`, `<C-o>`, `d{motion}`.
+    pub keys: &'static str,
+    pub description: &'static str,
+}
+
+impl KeyInfo {
+    pub const fn new(mode: Mode, keys: &'static str, description: &'static str) -> Self {
+        Self {
+            mode,
+            keys,
+            description,
+        }
+    }
+
+    /// The keys as Vim's help names them: `gg` in Normal mode, `i_<C-n>` in
+    /// Insert mode, `v_d` in Visual mode and `c_<Up>` on the command line.
+    pub fn label(&self) -> String {
+        let prefix = match self.mode {
+            Mode::Normal => "",
+            Mode::Insert => "i_",
+            Mode::Visual => "v_",
+            Mode::Command => "c_",
+        };
+        format!("{}{}", prefix, self.keys)
+    }
+}
+
+/// How many arguments a user command takes, as `-nargs` gives it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nargs {
+    /// `0`, the default.
+    Zero,
+    /// `1`: the whole argument, spaces and all.
+    One,
+    /// `?`
+    Optional,
+    /// `*`
+    Any,
+    /// `+`
+    Some,
+}
+
+impl Nargs {
+    fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "0" => Self::Zero,
+            "1" => Self::One,
+            "?" => Self::Optional,
+            "*" => Self::Any,
+            "+" => Self::Some,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Zero => "0",
+            Self::One => "1",
+            Self::Optional => "?",
+            Self::Any => "*",
+            Self::Some => "+",
+        }
+    }
+
+    /// Whether `args` is an acceptable argument string.
+    pub fn accepts(self, args: &str) -> bool {
+        match self {
+            Self::Zero => args.is_empty(),
+            Self::One | Self::Some => !args.is_empty(),
+            Self::Optional => !args.contains(char::is_whitespace),
+            Self::Any => true,
+        }
+    }
+
+    /// Usage notation for the palette, as `CommandInfo::args` writes it.
+    fn usage(self) -> &'static str {
+        match self {
+            Self::Zero => "",
+            Self::One | Self::Some => "{args}",
+            Self::Optional | Self::Any => "[args]",
+        }
+    }
+}
+
+/// The lines a user command runs on when typed without a range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeDefault {
+    /// The command takes no range.
+    None,
+    /// `-range`: the cursor line.
+    Line,
+    /// `-range=%`: the whole buffer.
+    File,
+}
+
+/// A command defined with `:command Name replacement`. Running it runs
+/// the replacement, with `<args>`, `<q-args>` (the arguments as a quoted
+/// string), `<line1>`, `<line2>`, `<bang>` and `<lt>` filled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserCommand {
+    pub name: String,
+    pub replacement: String,
+    pub nargs: Nargs,
+    pub range: RangeDefault,
+    /// `-bang`: the command may be typed as `Name!`.
+    pub bang: bool,
+}
+
+impl UserCommand {
+    /// Parses the arguments of `:command`: attributes such as `-nargs=1`,
+    /// `-range`, `-range=%` and `-bang`, then the name and the replacement.
+    /// The replacement may be empty, which only `:command Name` (listing
+    /// the command) allows.
+    pub fn parse(definition: &str) -> Result<Self, String> {
+        let mut command = Self {
+            name: String::new(),
+            replacement: String::new(),
+            nargs: Nargs::Zero,
+            range: RangeDefault::None,
+            bang: false,
+        };
+        let mut rest = definition.trim_start();
+        while let Some(attribute) = rest.strip_prefix('-') {
+            let end = attribute
+                .find(char::is_whitespace)
+                .unwrap_or(attribute.len());
+            match attribute[..end].split_once('=') {
+                Some(("nargs", value)) => {
+                    command.nargs = Nargs::parse(value)
+                        .ok_or_else(|| format!("Invalid number of arguments: {}", value))?;
+                }
+                None if &attribute[..end] == "range" => command.range = RangeDefault::Line,
+                Some(("range", "%")) => command.range = RangeDefault::File,
+                None if &attribute[..end] == "bang" => command.bang = true,
+                _ => return Err(format!("Invalid attribute: -{}", &attribute[..end])),
+            }
+            rest = attribute[end..].trim_start();
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let name = &rest[..end];
+        if !name.starts_with(|ch: char| ch.is_ascii_uppercase())
+            || !name.chars().all(|ch| ch.is_ascii_alphanumeric())
+        {
+            return Err("User defined commands must start with an uppercase letter".to_string());
+        }
+        command.name = name.to_string();
+        command.replacement = rest[end..].trim().to_string();
+        Ok(command)
+    }
+
+    /// The command as `:command` lists it: name, arguments, range and
+    /// replacement.
+    pub fn describe(&self) -> String {
+        let range = match self.range {
+            RangeDefault::None => "",
+            RangeDefault::Line => ".",
+            RangeDefault::File => "%",
+        };
+        format!(
+            "{}{:<16} {:<5} {:<5} {}",
+            if self.bang { "!" } else { " " },
+            self.name,
+            self.nargs.name(),
+            range,
+            self.replacement
+        )
+    }
+
+    /// The palette's usage notation for the arguments.
+    pub fn args(&self) -> &'static str {
+        self.nargs.usage()
+    }
+
+    /// The replacement with the arguments, the range's first and last
+    /// line numbers and the bang filled in.
+    pub fn expand(&self, args: &str, lines: (usize, usize), bang: bool) -> String {
+        let quoted = format!("\"{}\"", args.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut expanded = String::new();
+        let mut rest = self.replacement.as_str();
+        while let Some(start) = rest.find('<') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let value = [
+                ("<args>", args.to_string()),
+                ("<q-args>", quoted.clone()),
+                ("<line1>", lines.0.to_string()),
+                ("<line2>", lines.1.to_string()),
+                ("<bang>", if bang { "!" } else { "" }.to_string()),
+                ("<lt>", "<".to_string()),
+            ]
+            .into_iter()
+            .find(|(name, _)| rest.starts_with(name));
+            match value {
+                Some((name, value)) => {
+                    expanded.push_str(&value);
+                    rest = &rest[name.len()..];
+                }
+                None => {
+                    expanded.push('<');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+/// Every command and key binding of the loaded plugins, in plugin order,
+/// and the commands the user defined.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    pub commands: Vec<CommandInfo>,
+    pub keys: Vec<KeyInfo>,
+    /// Sorted by name.
+    pub user_commands: Vec<UserCommand>,
+    /// The autocommand events some plugin handles.
+    pub events: Vec<AutoEvent>,
+}
+
+impl Registry {
+    pub fn add(&mut self, plugin: &dyn Plugin) {
+        self.commands.extend_from_slice(plugin.commands());
+        self.keys.extend_from_slice(plugin.keys());
+        self.events.extend_from_slice(plugin.events());
+    }
+
+    /// Adds a user command, replacing one of the same name only when
+    /// `replace` is set. Plugin commands cannot be replaced.
+    pub fn define(&mut self, command: UserCommand, replace: bool) -> Result<(), String> {
+        if self.commands.iter().any(|info| info.name == command.name) {
+            return Err(format!(
+                "Cannot redefine a built-in command: {}",
+                command.name
+            ));
+        }
+        match self
+            .user_commands
+            .binary_search_by(|known| known.name.cmp(&command.name))
+        {
+            Ok(index) if replace => self.user_commands[index] = command,
+            Ok(_) => {
+                return Err(format!(
+                    "Command already exists: {} (add ! to replace it)",
+                    command.name
+                ));
+            }
+            Err(index) => self.user_commands.insert(index, command),
+        }
+        Ok(())
+    }
+
+    /// Removes the user command `name`; false if there is none.
+    pub fn undefine(&mut self, name: &str) -> bool {
+        let before = self.user_commands.len();
+        self.user_commands.retain(|command| command.name != name);
+        self.user_commands.len() < before
+    }
+
+    pub fn user_command(&self, name: &str) -> Option<&UserCommand> {
+        self.user_commands
+            .iter()
+            .find(|command| command.name == name)
+    }
+
+    /// The names of plugin and user commands starting with `prefix`,
+    /// sorted, for completing the command line.
+    pub fn command_names(&self, prefix: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .commands
+            .iter()
+            .map(|info| info.name)
+            .chain(
+                self.user_commands
+                    .iter()
+                    .map(|command| command.name.as_str()),
+            )
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// The bindings of `mode` that continue the keys `typed` so far, as the
+    /// rest of their keys and their description, sorted by those keys:
+    /// after `g`, `gg` is listed as `g`.
+    pub fn continuations(&self, mode: Mode, typed: &str) -> Vec<(&'static str, &'static str)> {
+        let mut found: Vec<(&'static str, &'static str)> = self
+            .keys
+            .iter()
+            .filter(|info| info.mode == mode && info.keys.len() > typed.len())
+            .filter_map(|info| {
+                let rest = info.keys.strip_prefix(typed)?;
+                Some((rest, info.description))
+            })
+            .collect();
+        found.sort();
+        found.dedup_by_key(|(rest, _)| *rest);
+        found
+    }
+}
+
+/// The key presses `notation` stands for, up to the first `{placeholder}`
+/// (a `{` without a closing brace is the key itself):
+/// `d{motion}` gives just `d`, leaving the motion to be typed. Understands
+/// `<C-x>`, `<C-S-x>`, `<Esc>`, `<CR>`, `<Tab>`, `<BS>`, `<Space>` and the arrows.
+pub fn parse_keys(notation: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '{' && rest.contains('}') {
+            break;
+        }
+        if ch == '<'
+            && let Some(end) = rest.find('>')
+            && let Some(key) = parse_special(&rest[1..end])
+        {
+            keys.push(key);
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let modifiers = if ch.is_ascii_uppercase() {
+            KeyModifiers::SHIFT
+        } else {
+            KeyModifiers::NONE
+        };
+        keys.push(KeyEvent::new(KeyCode::Char(ch), modifiers));
+        rest = &rest[ch.len_utf8()..];
+    }
+    keys
+}
+
+/// One `<...>` key name, without the brackets.
+fn parse_special(name: &str) -> Option<KeyEvent> {
+    if let Some(key) = name.strip_prefix("C-") {
+        let (key, modifiers) = match key.strip_prefix("S-") {
+            Some(key) => (key, KeyModifiers::CONTROL | KeyModifiers::SHIFT),
+            None => (key, KeyModifiers::CONTROL),
+        };
+        let mut chars = key.chars();
+        let (Some(ch), None) = (chars.next(), chars.next()) else {
+            return None;
+        };
+        return Some(KeyEvent::new(
+            KeyCode::Char(ch.to_ascii_lowercase()),
+            modifiers,
+        ));
+    }
+    let code = match name {
+        "Esc" => KeyCode::Esc,
+        "CR" | "Enter" => KeyCode::Enter,
+        "Tab" => KeyCode::Tab,
+        "BS" => KeyCode::Backspace,
+        "Space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "Up" => KeyCode::Up,
+        "Down" => KeyCode::Down,
+        "Left" => KeyCode::Left,
+        "Right" => KeyCode::Right,
+        _ => return None,
+    };
+    Some(KeyEvent::new(code, KeyModifiers::NONE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_notation_is_parsed_up_to_a_placeholder() {
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        assert_eq!(
+            parse_keys("gG"),
+            vec![
+                key(KeyCode::Char('g'), KeyModifiers::NONE),
+                key(KeyCode::Char('G'), KeyModifiers::SHIFT),
+            ]
+        );
+        assert_eq!(
+            parse_keys("<C-o><Tab>"),
+            vec![
+                key(KeyCode::Char('o'), KeyModifiers::CONTROL),
+                key(KeyCode::Tab, KeyModifiers::NONE),
+            ]
+        );
+        assert_eq!(
+            parse_keys("ys{motion}{char}"),
+            vec![
+                key(KeyCode::Char('y'), KeyModifiers::NONE),
+                key(KeyCode::Char('s'), KeyModifiers::NONE),
+            ]
+        );
+        assert_eq!(
+            parse_keys("<<"),
+            vec![key(KeyCode::Char('<'), KeyModifiers::NONE); 2]
+        );
+        assert_eq!(
+            parse_keys("{"),
+            vec![key(KeyCode::Char('{'), KeyModifiers::NONE)]
+        );
+        assert_eq!(
+            KeyInfo::new(Mode::Insert, "<C-n>", "next").label(),
+            "i_<C-n>"
+        );
+        let substitute = CommandInfo::new("s", "/{pattern}/{replacement}/[flags]", "");
+        assert_eq!(substitute.usage(), ":s/{pattern}/{replacement}/[flags]");
+        assert_eq!(substitute.template(), "s/");
+        assert!(substitute.needs_args());
+        let registry = Registry {
+            commands: Vec::new(),
+            keys: vec![
+                KeyInfo::new(Mode::Normal, "gg", "first line"),
+                KeyInfo::new(Mode::Normal, "gc{motion}", "comment"),
+                KeyInfo::new(Mode::Normal, "G", "last line"),
+                KeyInfo::new(Mode::Visual, "gc", "comment selection"),
+            ],
+            ..Registry::default()
+        };
+        assert_eq!(
+            registry.continuations(Mode::Normal, "g"),
+            [("c{motion}", "comment"), ("g", "first line")]
+        );
+        assert!(registry.continuations(Mode::Normal, "gg").is_empty());
+        let sort = CommandInfo::new("sort", "[flags]", "");
+        assert_eq!(sort.template(), "sort [flags]");
+        assert!(!sort.needs_args());
+    }
+
+    #[test]
+    fn user_commands_expand_their_arguments_and_range() {
+        let command = UserCommand::parse(
+            "-nargs=* -range=% -bang Grep grep<bang> <q-args> <line1>,<line2> <lt>x>",
+        )
+        .unwrap();
+        assert_eq!(command.name, "Grep");
+        assert_eq!(command.nargs, Nargs::Any);
+        assert_eq!(command.range, RangeDefault::File);
+        assert!(command.bang);
+        assert_eq!(
+            command.expand("say \"hi\"", (1, 4), true),
+            "grep! \"say \\\"hi\\\"\" 1,4 <x>"
+        );
+        assert!(Nargs::Optional.accepts("one") && !Nargs::Optional.accepts("one two"));
+        assert!(Nargs::Some.accepts("x") && !Nargs::Some.accepts(""));
+        assert!(UserCommand::parse("lower x").is_err());
+        assert!(UserCommand::parse("-nargs=2 Two x").is_err());
+        assert!(UserCommand::parse("-complete=file File x").is_err());
+
+        let mut registry = Registry {
+            commands: vec![CommandInfo::new("w", "[file]", "Write")],
+            ..Registry::default()
+        };
+        let define = |text: &str| UserCommand::parse(text).unwrap();
+        assert!(registry.define(define("W w"), false).is_ok());
+        assert!(registry.define(define("W update"), false).is_err());
+        assert!(registry.define(define("W update"), true).is_ok());
+        assert!(registry.define(define("Ab x"), false).is_ok());
+        assert_eq!(registry.user_command("W").unwrap().replacement, "update");
+        assert_eq!(registry.command_names(""), ["Ab", "W", "w"]);
+        assert!(registry.undefine("W"));
+        assert!(!registry.undefine("W"));
+        assert_eq!(registry.command_names("A"), ["Ab"]);
+    }
+}
@@ -0,0 +1,418 @@
+//! Turning a `RenderContext` into cells and drawing the cells that changed
+//! since the last frame through a `RenderBackend`.
+
+use std::io::{self, Write};
+
+use crossterm::{
+    cursor, queue,
+    style::{ContentStyle, Print, PrintStyledContent},
+    terminal::{self, Clear, ClearType},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::color::ColorDepth;
+use crate::editor::{self, Editor, RenderContext, StyledSpan};
+
+/// A single rendered cell holding one grapheme cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    pub symbol: String,
+    pub style: ContentStyle,
+}
+
+impl Cell {
+    pub fn blank() -> Self {
+        Self::new(" ")
+    }
+
+    pub fn new(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            style: ContentStyle::new(),
+        }
+    }
+
+    /// Placeholder for the second column of a double-width grapheme.
+    pub fn continuation() -> Self {
+        Self::new("")
+    }
+
+    pub fn is_continuation(&self) -> bool {
+        self.symbol.is_empty()
+    }
+}
+
+/// Where frames are drawn: the terminal, or anything else that can show a
+/// grid of styled cells.
+pub trait RenderBackend {
+    /// Blanks the whole display.
+    fn clear(&mut self) -> io::Result<()>;
+
+    /// Scrolls the top `height` rows by `delta` lines (positive moves content
+    /// up), blanking the exposed rows. Returns false if the backend cannot
+    /// scroll, in which case the rows are redrawn instead.
+    fn scroll(&mut self, _height: u16, _delta: isize) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Draws `cells` from column `col` of `row`.
+    fn draw_cells(&mut self, row: u16, col: u16, cells: &[Cell]) -> io::Result<()>;
+
+    /// Shows the cursor at `(row, col)`, or hides it.
+    fn set_cursor(&mut self, cursor: Option<(u16, u16)>) -> io::Result<()>;
+
+    /// Makes everything drawn so far visible.
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Draws with crossterm escape sequences, queued until `flush`, using only
+/// the colors of the terminal's palette.
+pub struct TerminalBackend<W: Write> {
+    out: W,
+    depth: ColorDepth,
+}
+
+impl<W: Write> TerminalBackend<W> {
+    pub fn new(out: W, depth: ColorDepth) -> Self {
+        Self { out, depth }
+    }
+}
+
+impl<W: Write> RenderBackend for TerminalBackend<W> {
+    fn clear(&mut self) -> io::Result<()> {
+        queue!(self.out, Clear(ClearType::All))
+    }
+
+    fn scroll(&mut self, height: u16, delta: isize) -> io::Result<bool> {
+        let amount = delta.unsigned_abs() as u16;
+        queue!(self.out, Print(format!("\x1b[1;{}r", height)))?;
+        if delta > 0 {
+            queue!(self.out, terminal::ScrollUp(amount))?;
+        } else {
+            queue!(self.out, terminal::ScrollDown(amount))?;
+        }
+        queue!(self.out, Print("\x1b[r"))?;
+        Ok(true)
+    }
+
+    fn draw_cells(&mut self, row: u16, col: u16, cells: &[Cell]) -> io::Result<()> {
+        queue!(self.out, cursor::MoveTo(col, row))?;
+        let mut idx = 0;
+        while idx < cells.len() {
+            let style = cells[idx].style;
+            let run_len = cells[idx..]
+                .iter()
+                .take_while(|cell| cell.style == style)
+                .count();
+            let text: String = cells[idx..idx + run_len]
+                .iter()
+                .map(|cell| cell.symbol.as_str())
+                .collect();
+            if style == ContentStyle::new() {
+                queue!(self.out, Print(text))?;
+            } else {
+                let style = self.depth.adapt_style(style);
+                queue!(self.out, PrintStyledContent(style.apply(text)))?;
+            }
+            idx += run_len;
+        }
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, cursor: Option<(u16, u16)>) -> io::Result<()> {
+        match cursor {
+            Some((row, col)) => queue!(self.out, cursor::MoveTo(col, row), cursor::Show),
+            None => queue!(self.out, cursor::Hide),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The cell grid last drawn, so frames only repaint what changed.
+pub struct Screen {
+    rows: Vec<Vec<Cell>>,
+    /// Buffer row shown at the top of the content area in the last frame.
+    row_offset: Option<usize>,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            rows: Vec::new(),
+            row_offset: None,
+        }
+    }
+
+    /// Forgets the previous frame so the next render repaints everything.
+    pub fn invalidate(&mut self) {
+        self.rows.clear();
+        self.row_offset = None;
+    }
+
+    /// Scrolls the first `height` rows of the previous frame by `delta` lines
+    /// (positive moves content up), blanking the exposed rows.
+    fn scroll(&mut self, height: usize, delta: isize) {
+        let height = height.min(self.rows.len());
+        let width = self.rows.first().map_or(0, Vec::len);
+        let region = &mut self.rows[..height];
+        let amount = delta.unsigned_abs().min(height);
+        if delta > 0 {
+            region.rotate_left(amount);
+            for row in &mut region[height - amount..] {
+                *row = vec![Cell::blank(); width];
+            }
+        } else {
+            region.rotate_right(amount);
+            for row in &mut region[..amount] {
+                *row = vec![Cell::blank(); width];
+            }
+        }
+    }
+
+    /// Draws the frame in `ctx` through `backend`, repainting only the cells
+    /// that differ from the previous frame.
+    pub fn draw(
+        &mut self,
+        editor: &Editor,
+        ctx: &RenderContext,
+        backend: &mut impl RenderBackend,
+    ) -> io::Result<()> {
+        let width = ctx.width as usize;
+        let frame: Vec<Vec<Cell>> = ctx
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(row, line)| {
+                let spans = ctx.spans.get(row).map(Vec::as_slice).unwrap_or(&[]);
+                layout_line(line, spans, width)
+            })
+            .collect();
+
+        backend.set_cursor(None)?;
+        if self.rows.len() != frame.len() || self.rows.iter().any(|row| row.len() != width) {
+            backend.clear()?;
+            self.rows = vec![vec![Cell::blank(); width]; frame.len()];
+            self.row_offset = None;
+        }
+
+        let content_height = editor.content_height() as usize;
+        let row_offset = editor
+            .scratch
+            .is_none()
+            .then_some(editor.viewport.row_offset);
+        if let Some(delta) = scroll_delta(self.row_offset, row_offset, content_height)
+            && backend.scroll(content_height as u16, delta)?
+        {
+            self.scroll(content_height, delta);
+        }
+        self.row_offset = row_offset;
+
+        for (row, (old, new)) in self.rows.iter().zip(frame.iter()).enumerate() {
+            if let Some((start, end)) = changed_range(old, new) {
+                backend.draw_cells(row as u16, start as u16, &new[start..end])?;
+            }
+        }
+        self.rows = frame;
+
+        backend.set_cursor(ctx.cursor)?;
+        backend.flush()
+    }
+}
+
+/// Returns how far the content area should scroll to reuse the previous frame,
+/// or `None` when a scroll would not save any repainting.
+fn scroll_delta(previous: Option<usize>, current: Option<usize>, height: usize) -> Option<isize> {
+    let delta = current? as isize - previous? as isize;
+    if delta == 0 || delta.unsigned_abs() * 2 > height {
+        return None;
+    }
+    Some(delta)
+}
+
+/// Converts a rendered line and its spans (in display columns) into exactly
+/// `width` cells. Double-width characters take a cell plus a continuation.
+fn layout_line(line: &str, spans: &[StyledSpan], width: usize) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = Vec::with_capacity(width);
+    for grapheme in line.graphemes(true) {
+        let grapheme_width = editor::grapheme_width(grapheme);
+        if cells.len() + grapheme_width > width {
+            break;
+        }
+        // Raw control characters would move the terminal cursor.
+        let symbol = if grapheme.chars().any(char::is_control) {
+            " "
+        } else {
+            grapheme
+        };
+        cells.push(Cell::new(symbol));
+        if grapheme_width == 2 {
+            cells.push(Cell::continuation());
+        }
+    }
+    let line_len = cells.len();
+    cells.resize(width, Cell::blank());
+
+    for span in spans {
+        let start = span.start.min(line_len);
+        let end = span.start.saturating_add(span.len).min(line_len);
+        for cell in &mut cells[start..end] {
+            cell.style = span.style;
+        }
+    }
+    cells
+}
+
+/// Returns the half-open column range that differs between two rows,
+/// widened so it never starts or ends inside a double-width character.
+fn changed_range(old: &[Cell], new: &[Cell]) -> Option<(usize, usize)> {
+    let mut start = old.iter().zip(new).position(|(a, b)| a != b)?;
+    let mut end = old
+        .iter()
+        .zip(new)
+        .rposition(|(a, b)| a != b)
+        .map_or(start, |idx| idx + 1);
+    while start > 0 && (old[start].is_continuation() || new[start].is_continuation()) {
+        start -= 1;
+    }
+    while end < new.len() && (old[end].is_continuation() || new[end].is_continuation()) {
+        end += 1;
+    }
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::Color;
+
+    fn row(text: &str) -> Vec<Cell> {
+        layout_line(text, &[], text.chars().count())
+    }
+
+    /// Records what was drawn, as a test harness frontend would.
+    #[derive(Default)]
+    struct RecordingBackend {
+        draws: Vec<(u16, u16, String)>,
+        clears: usize,
+        cursor: Option<(u16, u16)>,
+    }
+
+    impl RenderBackend for RecordingBackend {
+        fn clear(&mut self) -> io::Result<()> {
+            self.clears += 1;
+            Ok(())
+        }
+
+        fn draw_cells(&mut self, row: u16, col: u16, cells: &[Cell]) -> io::Result<()> {
+            let text = cells.iter().map(|cell| cell.symbol.as_str()).collect();
+            self.draws.push((row, col, text));
+            Ok(())
+        }
+
+        fn set_cursor(&mut self, cursor: Option<(u16, u16)>) -> io::Result<()> {
+            self.cursor = cursor;
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn screens_draw_only_changed_cells_through_the_backend() {
+        let mut editor = Editor::new(6, 4, None);
+        let mut ctx = RenderContext::new(6, 4);
+        ctx.set_line(0, "hello".to_string());
+        ctx.set_line(1, "world".to_string());
+        ctx.cursor = Some((1, 2));
+        let mut screen = Screen::new();
+        let mut backend = RecordingBackend::default();
+        screen.draw(&editor, &ctx, &mut backend).unwrap();
+        assert_eq!(backend.clears, 1);
+        assert_eq!(backend.draws[0], (0, 0, "hello".to_string()));
+        assert_eq!(backend.cursor, Some((1, 2)));
+
+        // Without scrolling support, a scrolled view is redrawn row by row.
+        backend.draws.clear();
+        editor.viewport.row_offset = 1;
+        ctx.set_line(0, "world".to_string());
+        ctx.set_line(1, "wordy".to_string());
+        ctx.cursor = None;
+        screen.draw(&editor, &ctx, &mut backend).unwrap();
+        assert_eq!(backend.clears, 1);
+        assert_eq!(
+            backend.draws,
+            [(0, 0, "world".to_string()), (1, 3, "dy".to_string())]
+        );
+        assert_eq!(backend.cursor, None);
+    }
+
+    #[test]
+    fn layout_line_pads_and_styles_cells() {
+        let mut style = ContentStyle::new();
+        style.foreground_color = Some(Color::Red);
+        let spans = [StyledSpan {
+            start: 1,
+            len: 10,
+            style,
+        }];
+        let cells = layout_line("abc", &spans, 5);
+        assert_eq!(cells.len(), 5);
+        assert_eq!(cells[0], Cell::new("a"));
+        assert_eq!(
+            cells[2],
+            Cell {
+                symbol: "c".to_string(),
+                style
+            }
+        );
+        assert_eq!(cells[4], Cell::blank());
+    }
+
+    #[test]
+    fn layout_line_gives_wide_characters_two_cells() {
+        let cells = layout_line("a日本", &[], 4);
+        let symbols: Vec<&str> = cells.iter().map(|cell| cell.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["a", "日", "", " "]);
+
+        let cells = layout_line("e\u{301}x", &[], 3);
+        assert_eq!(cells[0].symbol, "e\u{301}");
+        assert_eq!(cells[1].symbol, "x");
+
+        let old = layout_line("日本", &[], 4);
+        let new = layout_line("日x", &[], 4);
+        assert_eq!(changed_range(&old, &new), Some((2, 4)));
+        let new = layout_line("a 本", &[], 4);
+        assert_eq!(changed_range(&old, &new), Some((0, 2)));
+    }
+
+    #[test]
+    fn scroll_delta_skips_large_jumps() {
+        assert_eq!(scroll_delta(Some(10), Some(11), 20), Some(1));
+        assert_eq!(scroll_delta(Some(10), Some(7), 20), Some(-3));
+        assert_eq!(scroll_delta(Some(10), Some(10), 20), None);
+        assert_eq!(scroll_delta(Some(0), Some(15), 20), None);
+        assert_eq!(scroll_delta(None, Some(1), 20), None);
+    }
+
+    #[test]
+    fn screen_scroll_shifts_rows_and_blanks_exposed() {
+        let mut screen = Screen::new();
+        screen.rows = vec![row("a"), row("b"), row("c"), row("s")];
+        screen.scroll(3, 1);
+        assert_eq!(screen.rows, vec![row("b"), row("c"), row(" "), row("s")]);
+        screen.scroll(3, -2);
+        assert_eq!(screen.rows, vec![row(" "), row(" "), row("b"), row("s")]);
+    }
+
+    #[test]
+    fn changed_range_covers_only_differences() {
+        assert_eq!(changed_range(&row("hello"), &row("hello")), None);
+        assert_eq!(changed_range(&row("hello"), &row("hxllo")), Some((1, 2)));
+        assert_eq!(changed_range(&row("hello"), &row("jelly")), Some((0, 5)));
+    }
+}
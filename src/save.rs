@@ -0,0 +1,288 @@
+//! Writing files so that a failure part way leaves the old text intact: the
+//! new text goes to a temporary file beside the old one, which takes its
+//! place once complete, with the old file's permissions and owner. With
+//! `sync` the text is flushed to disk before a write counts as done. A file
+//! that may not be written as it is is only replaced when the write is
+//! forced.
+
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Writes `contents` to `path`, following symlinks, and with `sync` flushes
+/// the file and its directory to disk. A read-only file, or one the user
+/// may not write, is refused unless `force`d. Returns a warning to show when
+/// the file was written but could not keep its owner.
+pub fn write_file(
+    path: &Path,
+    contents: &[u8],
+    sync: bool,
+    force: bool,
+) -> io::Result<Option<String>> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            write_in_place(&path, contents, sync)?;
+            if sync {
+                sync_dir(&path)?;
+            }
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    if !force {
+        // Replacing the file would get round its permissions.
+        check_writable(&path, &metadata)?;
+    }
+    if has_other_links(&metadata) {
+        // Replacing the file would split it from its other names.
+        write_in_place(&path, contents, sync)?;
+        return Ok(None);
+    }
+    let temp = temp_path(&path);
+    let file = match create_replacement(&temp, &metadata) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            // The directory is not writable; the file may be.
+            write_in_place(&path, contents, sync)?;
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    let kept_owner = match write_replacement(file, &temp, contents, &metadata, sync) {
+        Ok(kept_owner) => kept_owner,
+        Err(err) => {
+            // The old file is left as it was.
+            let _ = fs::remove_file(&temp);
+            return Err(err);
+        }
+    };
+    if !kept_owner {
+        // Writing in place keeps the owner, when the file allows it.
+        if write_in_place(&path, contents, sync).is_ok() {
+            let _ = fs::remove_file(&temp);
+            return Ok(None);
+        }
+    }
+    if let Err(err) = fs::rename(&temp, &path) {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+    if sync {
+        sync_dir(&path)?;
+    }
+    Ok((!kept_owner).then(|| "could not keep the file's owner".to_string()))
+}
+
+/// A name for the temporary file beside `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, process::id()))
+}
+
+/// Overwrites the file at `path`, or creates it, keeping its permissions
+/// and owner.
+fn write_in_place(path: &Path, contents: &[u8], sync: bool) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    if sync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Fails with `PermissionDenied` if the file at `path`, described by
+/// `metadata`, is read-only or may not be opened for writing.
+fn check_writable(path: &Path, metadata: &Metadata) -> io::Result<()> {
+    let denied = || {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the file is read-only, add ! to override",
+        )
+    };
+    if metadata.permissions().readonly() {
+        return Err(denied());
+    }
+    match OpenOptions::new().write(true).open(path) {
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Err(denied()),
+        _ => Ok(()),
+    }
+}
+
+/// Creates the new file at `temp` with the permissions of the file
+/// described by `metadata`, so no more people can read the text while it
+/// is being written than could read the old file.
+fn create_replacement(temp: &Path, metadata: &Metadata) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(metadata.permissions().mode() & 0o7777);
+    }
+    let file = options.open(temp)?;
+    // The umask may have taken bits away from the mode.
+    if let Err(err) = file.set_permissions(metadata.permissions()) {
+        let _ = fs::remove_file(temp);
+        return Err(err);
+    }
+    Ok(file)
+}
+
+/// Writes `contents` to `file`, the new file at `temp`, and gives it the
+/// owner of the file described by `metadata`. Returns whether the owner
+/// was kept.
+fn write_replacement(
+    mut file: File,
+    temp: &Path,
+    contents: &[u8],
+    metadata: &Metadata,
+    sync: bool,
+) -> io::Result<bool> {
+    file.write_all(contents)?;
+    let kept_owner = copy_owner(temp, metadata);
+    if sync {
+        file.sync_all()?;
+    }
+    Ok(kept_owner)
+}
+
+/// Flushes the directory holding `path`, so a file created or renamed
+/// there survives a crash.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Directories cannot be opened to flush them here; the file itself was.
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_owner(temp: &Path, metadata: &Metadata) -> bool {
+    use std::os::unix::fs::{MetadataExt, chown};
+    let Ok(current) = fs::metadata(temp) else {
+        return false;
+    };
+    if current.uid() == metadata.uid() && current.gid() == metadata.gid() {
+        return true;
+    }
+    chown(temp, Some(metadata.uid()), Some(metadata.gid())).is_ok()
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_temp: &Path, _metadata: &Metadata) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn has_other_links(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn has_other_links(_metadata: &Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_keep_mode_links_and_symlink_targets() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("run.sh");
+        fs::write(&script, "echo old\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+        assert_eq!(
+            write_file(&script, b"echo new\n", true, false).unwrap(),
+            None
+        );
+        assert_eq!(fs::read_to_string(&script).unwrap(), "echo new\n");
+        let mode = fs::metadata(&script).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+
+        let link = dir.path().join("link.sh");
+        symlink(&script, &link).unwrap();
+        write_file(&link, b"echo linked\n", false, false).unwrap();
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_to_string(&script).unwrap(), "echo linked\n");
+
+        let hard = dir.path().join("hard.sh");
+        fs::hard_link(&script, &hard).unwrap();
+        write_file(&script, b"echo both\n", true, false).unwrap();
+        assert_eq!(fs::read_to_string(&hard).unwrap(), "echo both\n");
+
+        let entries = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(entries, 3, "no temporary file is left behind");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replacements_are_never_more_readable_than_the_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("secret.txt");
+        fs::write(&secret, "old\n").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o600)).unwrap();
+        let metadata = fs::metadata(&secret).unwrap();
+        let temp = temp_path(&secret);
+        // The contents go to a file already limited to the owner.
+        let file = create_replacement(&temp, &metadata).unwrap();
+        let mode = fs::metadata(&temp).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        write_replacement(file, &temp, b"new\n", &metadata, false).unwrap();
+        fs::remove_file(&temp).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_files_are_replaced_only_when_forced() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("locked.txt");
+        fs::write(&path, "old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444)).unwrap();
+        let err = write_file(&path, b"new\n", false, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+
+        write_file(&path, b"new\n", false, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o444);
+    }
+
+    #[test]
+    fn a_failed_replacement_leaves_the_old_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "old\n").unwrap();
+        // The temporary file cannot be created, and not for want of
+        // permission, so the file is not written in place either.
+        fs::create_dir(temp_path(&path)).unwrap();
+        let err = write_file(&path, b"new\n", false, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+    }
+}
@@ -0,0 +1,273 @@
+//! Word lists for spell checking and completion: dictionaries with one
+//! word per line, such as `/usr/share/dict/words`, and thesauruses with a
+//! group of related words per line. Words are letters with apostrophes
+//! between them; spelling suggestions are the listed words fewest edits
+//! away.
+
+use std::collections::HashSet;
+use std::fs;
+
+/// Most suggestions `suggest` returns.
+pub const MAX_SUGGESTIONS: usize = 9;
+
+/// The words of the `dictionary` files.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    /// The `dictionary` option value the words were read for.
+    source: String,
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    /// Reads the comma-separated word list files in `files`.
+    pub fn load(files: &str) -> Result<Self, String> {
+        let mut words = HashSet::new();
+        for path in files.split(',').filter(|path| !path.is_empty()) {
+            let contents = fs::read_to_string(path)
+                .map_err(|err| format!("Cannot read dictionary {}: {}", path, err))?;
+            words.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|word| !word.is_empty())
+                    .map(String::from),
+            );
+        }
+        Ok(Self {
+            source: files.to_string(),
+            words,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The listed words that start with `prefix` and are longer, sorted.
+    pub fn completions(&self, prefix: &str) -> Vec<String> {
+        let mut words: Vec<String> = self
+            .words
+            .iter()
+            .filter(|word| word.len() > prefix.len() && word.starts_with(prefix))
+            .cloned()
+            .collect();
+        words.sort();
+        words
+    }
+
+    /// Whether `word` is listed as it is or, capitalized at the start of a
+    /// sentence, in lowercase.
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(word) || self.words.contains(&word.to_lowercase())
+    }
+
+    /// Listed words close to `word`, closest first: those one edit away
+    /// (a letter added, removed, changed or two swapped), then two. Among
+    /// equals, words with the same letters in another order, then those
+    /// with the same first letter, come first. A capitalized word gets
+    /// capitalized suggestions.
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let target: Vec<char> = lower.chars().collect();
+        let first = target.first().copied();
+        let letters = |chars: &[char]| {
+            let mut sorted = chars.to_vec();
+            sorted.sort_unstable();
+            sorted
+        };
+        let target_letters = letters(&target);
+        let mut ranked: Vec<(usize, bool, bool, usize, &str)> = self
+            .words
+            .iter()
+            .filter(|candidate| candidate.chars().count().abs_diff(target.len()) <= 2)
+            .filter_map(|candidate| {
+                let chars: Vec<char> = candidate.to_lowercase().chars().collect();
+                let distance = edit_distance(&target, &chars);
+                (distance <= 2 && distance > 0).then(|| {
+                    (
+                        distance,
+                        letters(&chars) != target_letters,
+                        chars.first().copied() != first,
+                        chars.len().abs_diff(target.len()),
+                        candidate.as_str(),
+                    )
+                })
+            })
+            .collect();
+        ranked.sort();
+        let capitalized = word.chars().next().is_some_and(char::is_uppercase);
+        let mut suggestions: Vec<String> = Vec::new();
+        for (_, _, _, _, candidate) in ranked {
+            let suggestion = if capitalized {
+                capitalize(candidate)
+            } else {
+                candidate.to_string()
+            };
+            if !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+            if suggestions.len() == MAX_SUGGESTIONS {
+                break;
+            }
+        }
+        suggestions
+    }
+}
+
+/// The words that share a line of the comma-separated `files` with a word
+/// starting with `prefix`, in the order the files list them. Words on a
+/// line are separated by commas or whitespace.
+pub fn related_words(files: &str, prefix: &str) -> Result<Vec<String>, String> {
+    let mut related: Vec<String> = Vec::new();
+    for path in files.split(',').filter(|path| !path.is_empty()) {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read thesaurus {}: {}", path, err))?;
+        for line in contents.lines() {
+            let words: Vec<&str> = line
+                .split(|ch: char| ch == ',' || ch.is_whitespace())
+                .filter(|word| !word.is_empty())
+                .collect();
+            if !words.iter().any(|word| word.starts_with(prefix)) {
+                continue;
+            }
+            for word in words {
+                if word != prefix && !related.iter().any(|known| known == word) {
+                    related.push(word.to_string());
+                }
+            }
+        }
+    }
+    Ok(related)
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+/// Edits that turn `a` into `b`: letters added, removed or changed, and
+/// neighbors swapped.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let change = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + change);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// The words of `line` as char column ranges: letters, with apostrophes
+/// between them.
+pub fn words(line: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut found = Vec::new();
+    let mut col = 0;
+    while col < chars.len() {
+        if !chars[col].is_alphabetic() {
+            col += 1;
+            continue;
+        }
+        let start = col;
+        while col < chars.len()
+            && (chars[col].is_alphabetic()
+                || (chars[col] == '\'' && chars.get(col + 1).is_some_and(|ch| ch.is_alphabetic())))
+        {
+            col += 1;
+        }
+        found.push((start, col));
+    }
+    found
+}
+
+/// The word of `line` at char column `col`.
+pub fn word_at(line: &str, col: usize) -> Option<(usize, usize)> {
+    words(line)
+        .into_iter()
+        .find(|(start, end)| (*start..*end).contains(&col))
+}
+
+/// The words of `line` that `dictionary` does not list. Single letters,
+/// words with capitals after the first letter (acronyms, camelCase) and
+/// words joined to digits or underscores, as in identifiers, are skipped.
+pub fn misspelled(line: &str, dictionary: &Dictionary) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = line.chars().collect();
+    let joined = |col: Option<usize>| {
+        col.and_then(|col| chars.get(col))
+            .is_some_and(|ch| ch.is_ascii_digit() || *ch == '_')
+    };
+    words(line)
+        .into_iter()
+        .filter(|(start, end)| {
+            let word: String = chars[*start..*end].iter().collect();
+            end - start > 1
+                && !word.chars().skip(1).any(char::is_uppercase)
+                && !joined(start.checked_sub(1))
+                && !joined(Some(*end))
+                && !dictionary.contains(&word)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary(words: &[&str]) -> Dictionary {
+        Dictionary {
+            source: String::new(),
+            words: words.iter().map(|word| word.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn unlisted_words_are_flagged_with_suggestions_by_edits() {
+        let words = dictionary(&[
+            "the", "word", "world", "wood", "ward", "sword", "don't", "Paris",
+        ]);
+        let line = "The wrod, don't paris x HTTP camelCase foo_bar2 wrld";
+        let flagged: Vec<&str> = misspelled(line, &words)
+            .into_iter()
+            .map(|(start, end)| &line[start..end])
+            .collect();
+        assert_eq!(flagged, ["wrod", "paris", "wrld"]);
+        assert_eq!(word_at(line, 5), Some((4, 8)));
+        assert_eq!(word_at(line, 8), None);
+
+        assert_eq!(
+            words.suggest("wrod"),
+            ["word", "wood", "ward", "world", "sword"]
+        );
+        assert_eq!(words.suggest("Wrld")[0], "World");
+        assert!(words.suggest("xyzzy").is_empty());
+        assert_eq!(words.completions("wo"), ["wood", "word", "world"]);
+    }
+
+    #[test]
+    fn thesaurus_lines_offer_their_other_words() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("thesaurus");
+        fs::write(&path, "big large,huge\nsmall little tiny\nbigot zealot\n").unwrap();
+        let files = path.to_str().unwrap();
+        assert_eq!(
+            related_words(files, "big").unwrap(),
+            ["large", "huge", "bigot", "zealot"]
+        );
+        assert_eq!(related_words(files, "tiny").unwrap(), ["small", "little"]);
+        assert!(related_words("/nonexistent/thesaurus", "big").is_err());
+    }
+}
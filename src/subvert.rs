@@ -0,0 +1,166 @@
+//! Case-preserving substitution for `:S`, after abolish.vim's `:Subvert`:
+//! the pattern matches a word or identifier in each of its case styles,
+//! `foo_bar`, `fooBar`, `FooBar`, `FOO_BAR` and `foo-bar`, and each match
+//! is replaced by the replacement written in the same style. A single word
+//! comes as `foo`, `Foo` and `FOO`.
+
+use regex::Regex;
+
+/// How the words of an identifier are cased and joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    /// `foo_bar`, and `foo` for a single word.
+    Snake,
+    /// `fooBar`.
+    Camel,
+    /// `FooBar`, and `Foo`.
+    Pascal,
+    /// `FOO_BAR`, and `FOO`.
+    Upper,
+    /// `foo-bar`.
+    Kebab,
+}
+
+impl Style {
+    const ALL: [Style; 5] = [
+        Style::Snake,
+        Style::Camel,
+        Style::Pascal,
+        Style::Upper,
+        Style::Kebab,
+    ];
+
+    /// The style `text` is written in.
+    fn of(text: &str) -> Self {
+        let has_upper = text.chars().any(char::is_uppercase);
+        let has_lower = text.chars().any(char::is_lowercase);
+        if text.contains('-') {
+            Style::Kebab
+        } else if has_upper && !has_lower {
+            Style::Upper
+        } else if text.contains('_') || !has_upper {
+            Style::Snake
+        } else if text.starts_with(char::is_uppercase) {
+            Style::Pascal
+        } else {
+            Style::Camel
+        }
+    }
+
+    /// `words`, lowercase, written in this style.
+    fn render(self, words: &[String]) -> String {
+        let capitalized = || {
+            words
+                .iter()
+                .map(|word| capitalize(word))
+                .collect::<String>()
+        };
+        match self {
+            Style::Snake => words.join("_"),
+            Style::Kebab => words.join("-"),
+            Style::Upper => words.join("_").to_uppercase(),
+            Style::Pascal => capitalized(),
+            Style::Camel => match words.split_first() {
+                Some((first, rest)) => {
+                    first.clone() + &rest.iter().map(|word| capitalize(word)).collect::<String>()
+                }
+                None => String::new(),
+            },
+        }
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+/// The words of `text` in lowercase, split at `_`, `-`, spaces and camelCase
+/// humps; a run of capitals is one word, as `HTTP` in `HTTPServer`.
+fn words(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &ch) in chars.iter().enumerate() {
+        if matches!(ch, '_' | '-' | ' ') {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        let previous = i.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(i + 1);
+        let hump = ch.is_uppercase()
+            && previous.is_some_and(|previous| {
+                previous.is_lowercase()
+                    || previous.is_ascii_digit()
+                    || (previous.is_uppercase() && next.is_some_and(|next| next.is_lowercase()))
+            });
+        if hump && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.extend(ch.to_lowercase());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// A regex matching `text` in each case style, longest variants first.
+pub fn pattern(text: &str) -> Result<Regex, String> {
+    let split = words(text);
+    let mut variants: Vec<String> = if split.is_empty() {
+        vec![text.to_string()]
+    } else {
+        Style::ALL
+            .iter()
+            .map(|style| style.render(&split))
+            .collect()
+    };
+    variants.sort_by_key(|variant| std::cmp::Reverse(variant.len()));
+    variants.dedup();
+    let alternatives: Vec<String> = variants
+        .iter()
+        .map(|variant| regex::escape(variant))
+        .collect();
+    Regex::new(&alternatives.join("|")).map_err(|err| format!("Invalid pattern: {}", err))
+}
+
+/// `replacement` written in the case style of `matched`.
+pub fn replace(matched: &str, replacement: &str) -> String {
+    let split = words(replacement);
+    if split.is_empty() {
+        return replacement.to_string();
+    }
+    Style::of(matched).render(&split)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_keep_their_case_style() {
+        let regex = pattern("fooBar").unwrap();
+        let line = "foo_bar fooBar FooBar FOO_BAR foo-bar foobar";
+        let replaced =
+            regex.replace_all(line, |caps: &regex::Captures| replace(&caps[0], "baz_qux"));
+        assert_eq!(replaced, "baz_qux bazQux BazQux BAZ_QUX baz-qux foobar");
+
+        let regex = pattern("foo").unwrap();
+        let replaced = regex.replace_all("foo Foo FOO", |caps: &regex::Captures| {
+            replace(&caps[0], "bar")
+        });
+        assert_eq!(replaced, "bar Bar BAR");
+        assert_eq!(replace("foo", "newName"), "new_name");
+        assert_eq!(replace("Foo", "new name"), "NewName");
+
+        assert_eq!(words("HTTPServer2Go"), ["http", "server2", "go"]);
+        assert_eq!(words("parse_URL"), ["parse", "url"]);
+        assert!(pattern("a.b").unwrap().is_match("a.b"));
+        assert!(!pattern("a.b").unwrap().is_match("axb"));
+    }
+}
@@ -0,0 +1,115 @@
+//! Delimiters added, changed and deleted by `ys`, `cs` and `ds`.
+
+use crate::editor::Cursor;
+use crate::motion::{TextObject, TextRange, pair_for};
+
+/// Opening and closing text `ys` and `cs` insert for a typed character. An
+/// opening bracket pads the text with spaces; a closing one (or `b`, `B`)
+/// does not. Other punctuation surrounds with itself.
+pub fn delimiters(ch: char) -> Option<(String, String)> {
+    let padded = matches!(ch, '(' | '{' | '[');
+    match pair_for(ch) {
+        Some((open, close)) if padded => Some((format!("{} ", open), format!(" {}", close))),
+        Some((open, close)) => Some((open.to_string(), close.to_string())),
+        None if ch.is_ascii_punctuation() => Some((ch.to_string(), ch.to_string())),
+        None => None,
+    }
+}
+
+/// The existing opening and closing delimiters named by `ch` around
+/// `cursor`, as the ranges `ds` deletes. Naming a pair by its opening
+/// bracket also takes the whitespace just inside it.
+pub fn find(lines: &[String], cursor: Cursor, ch: char) -> Option<(TextRange, TextRange)> {
+    let one_char = |at: Cursor| TextRange {
+        start: at,
+        end: Cursor {
+            col: at.col + 1,
+            ..at
+        },
+        linewise: false,
+    };
+    let (open, close) = match pair_for(ch) {
+        Some((open_ch, close_ch)) => {
+            let around = TextObject::Pair {
+                open: open_ch,
+                close: close_ch,
+                around: true,
+            }
+            .range(lines, cursor)?;
+            let close = Cursor {
+                col: around.end.col - 1,
+                ..around.end
+            };
+            (one_char(around.start), one_char(close))
+        }
+        None if ch.is_ascii_punctuation() => {
+            let inner = TextObject::Quote {
+                quote: ch,
+                around: false,
+            }
+            .range(lines, cursor)?;
+            let open = Cursor {
+                col: inner.start.col - 1,
+                ..inner.start
+            };
+            (one_char(open), one_char(inner.end))
+        }
+        None => return None,
+    };
+    if !matches!(ch, '(' | '{' | '[') {
+        return Some((open, close));
+    }
+    let chars = |row: usize| -> Vec<char> { lines[row].chars().collect() };
+    let open_line = chars(open.start.row);
+    let mut open = open;
+    while open.end.col < open_line.len()
+        && open_line[open.end.col] == ' '
+        && (open.end.row, open.end.col) < (close.start.row, close.start.col)
+    {
+        open.end.col += 1;
+    }
+    let close_line = chars(close.start.row);
+    let mut close = close;
+    while close.start.col > 0
+        && close_line[close.start.col - 1] == ' '
+        && (close.start.row, close.start.col) > (open.end.row, open.end.col)
+    {
+        close.start.col -= 1;
+    }
+    Some((open, close))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Vec<String> {
+        vec![text.to_string()]
+    }
+
+    fn cols(range: TextRange) -> (usize, usize) {
+        (range.start.col, range.end.col)
+    }
+
+    #[test]
+    fn delimiters_pad_for_opening_brackets() {
+        assert_eq!(delimiters('('), Some(("( ".to_string(), " )".to_string())));
+        assert_eq!(delimiters('b'), Some(("(".to_string(), ")".to_string())));
+        assert_eq!(delimiters('>'), Some(("<".to_string(), ">".to_string())));
+        assert_eq!(delimiters('*'), Some(("*".to_string(), "*".to_string())));
+        assert_eq!(delimiters('x'), None);
+    }
+
+    #[test]
+    fn find_locates_pairs_and_quotes() {
+        let text = line("call( \"a b\" )");
+        let at = Cursor { row: 0, col: 8 };
+        let (open, close) = find(&text, at, '"').unwrap();
+        assert_eq!((cols(open), cols(close)), ((6, 7), (10, 11)));
+        let (open, close) = find(&text, at, ')').unwrap();
+        assert_eq!((cols(open), cols(close)), ((4, 5), (12, 13)));
+        let (open, close) = find(&text, at, '(').unwrap();
+        assert_eq!((cols(open), cols(close)), ((4, 6), (11, 13)));
+        assert_eq!(find(&text, at, '['), None);
+    }
+}
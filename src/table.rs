@@ -0,0 +1,256 @@
+//! Pipe tables, as Markdown and org write them: rows of cells between `|`
+//! characters, with separator rows of dashes. Formatting pads every cell of
+//! a column to the same width, so the pipes line up.
+
+use std::ops::Range;
+
+use unicode_width::UnicodeWidthStr;
+
+/// Whether `line` is a table row: its first non-blank character is `|`.
+pub fn is_row(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+/// The rows of the table around `row`, if `row` is in one.
+pub fn bounds(lines: &[String], row: usize) -> Option<Range<usize>> {
+    if !lines.get(row).is_some_and(|line| is_row(line)) {
+        return None;
+    }
+    let start = lines[..row]
+        .iter()
+        .rposition(|line| !is_row(line))
+        .map_or(0, |above| above + 1);
+    let end = lines[row..]
+        .iter()
+        .position(|line| !is_row(line))
+        .map_or(lines.len(), |below| row + below);
+    Some(start..end)
+}
+
+/// Whether `line` separates the header from the body, as `|---|:--:|` or
+/// org's `|----+----|` do.
+fn is_separator(line: &str) -> bool {
+    let text = line.trim();
+    text.contains('-')
+        && text
+            .chars()
+            .all(|ch| matches!(ch, '|' | '+' | '-' | ':' | ' '))
+}
+
+/// Char columns of the pipes that divide `line` into cells; `\|` is a pipe
+/// in the text. In separator rows a `+` divides cells too.
+pub fn pipes(line: &str) -> Vec<usize> {
+    let separator = is_separator(line);
+    let mut found = Vec::new();
+    let mut escaped = false;
+    for (col, ch) in line.chars().enumerate() {
+        if (ch == '|' && !escaped) || (separator && ch == '+' && !found.is_empty()) {
+            found.push(col);
+        }
+        escaped = ch == '\\' && !escaped;
+    }
+    found
+}
+
+/// The trimmed text of each cell of `line`. Text after the last pipe is a
+/// cell only if there is some.
+fn cells(line: &str) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    let pipes = pipes(line);
+    let mut cells: Vec<String> = pipes
+        .windows(2)
+        .map(|pair| chars[pair[0] + 1..pair[1]].iter().collect::<String>())
+        .map(|cell| cell.trim().to_string())
+        .collect();
+    if let Some(last) = pipes.last() {
+        let rest: String = chars[last + 1..].iter().collect();
+        if !rest.trim().is_empty() {
+            cells.push(rest.trim().to_string());
+        }
+    }
+    cells
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// How a separator cell such as `:--:` aligns its column.
+fn align_of(cell: &str) -> Align {
+    match (cell.starts_with(':'), cell.len() > 1 && cell.ends_with(':')) {
+        (true, true) => Align::Center,
+        (false, true) => Align::Right,
+        _ => Align::Left,
+    }
+}
+
+/// `rows` with every cell padded to its column's width, the pipes lined up
+/// and each row closed with a pipe. Rows keep the first row's indent and
+/// their number of cells, so a row being typed gains none; separator rows
+/// span every column. Cells are aligned as the separator row's colons ask.
+pub fn format(rows: &[String]) -> Vec<String> {
+    let Some(first) = rows.first() else {
+        return Vec::new();
+    };
+    let indent: String = first.chars().take_while(|ch| ch.is_whitespace()).collect();
+    let table: Vec<(bool, Vec<String>)> = rows
+        .iter()
+        .map(|row| (is_separator(row), cells(row)))
+        .collect();
+    let columns = table
+        .iter()
+        .map(|(_, cells)| cells.len())
+        .max()
+        .unwrap_or(0);
+    let mut widths = vec![1; columns];
+    let mut aligns = vec![Align::Left; columns];
+    for (separator, cells) in &table {
+        for (column, cell) in cells.iter().enumerate() {
+            if *separator {
+                aligns[column] = align_of(cell);
+            } else {
+                widths[column] = widths[column].max(cell.width());
+            }
+        }
+    }
+    table
+        .iter()
+        .zip(rows)
+        .map(|((separator, cells), row)| {
+            let mut line = indent.clone();
+            line.push('|');
+            let count = if *separator { columns } else { cells.len() };
+            for column in 0..count {
+                let cell = cells.get(column).map_or("", String::as_str);
+                let width = widths[column];
+                if *separator {
+                    let (left, right) = match aligns[column] {
+                        Align::Left if cell.starts_with(':') => (":", "-"),
+                        Align::Left => ("-", "-"),
+                        Align::Center => (":", ":"),
+                        Align::Right => ("-", ":"),
+                    };
+                    line.push_str(left);
+                    line.push_str(&"-".repeat(width));
+                    line.push_str(right);
+                    // Org joins the dashes of its separators with `+`.
+                    let org = row.trim().trim_matches('|').contains('+');
+                    line.push(if org && column + 1 < columns {
+                        '+'
+                    } else {
+                        '|'
+                    });
+                } else {
+                    let pad = width - cell.width();
+                    let before = match aligns[column] {
+                        Align::Left => 0,
+                        Align::Center => pad / 2,
+                        Align::Right => pad,
+                    };
+                    line.push(' ');
+                    line.push_str(&" ".repeat(before));
+                    line.push_str(cell);
+                    line.push_str(&" ".repeat(pad - before));
+                    line.push_str(" |");
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+/// Where char column `col` of a table row is: the index of its cell and
+/// the offset into the cell's trimmed text, or `None` before the first
+/// pipe.
+pub fn cell_at(line: &str, col: usize) -> Option<(usize, usize)> {
+    let pipes = pipes(line);
+    let cell = pipes
+        .iter()
+        .filter(|pipe| **pipe < col)
+        .count()
+        .checked_sub(1)?;
+    let chars: Vec<char> = line.chars().collect();
+    let start = pipes[cell] + 1;
+    let end = pipes.get(cell + 1).copied().unwrap_or(chars.len());
+    let text = &chars[start..end];
+    let lead = text.iter().take_while(|ch| **ch == ' ').count();
+    let trail = text[lead..]
+        .iter()
+        .rev()
+        .take_while(|ch| **ch == ' ')
+        .count();
+    let len = text.len() - lead - trail;
+    let offset = (col - start).saturating_sub(lead).min(len);
+    Some((cell, offset))
+}
+
+/// The char column `offset` into cell `cell` of a formatted row; past the
+/// last cell, the end of the row.
+pub fn position(line: &str, cell: usize, offset: usize) -> usize {
+    let pipes = pipes(line);
+    match (pipes.get(cell), pipes.get(cell + 1)) {
+        (Some(start), Some(end)) => (start + 2 + offset).min(end - 1).max(start + 1),
+        _ => line.chars().count(),
+    }
+}
+
+/// Whether a formatted row has a cell `cell`.
+pub fn has_cell(line: &str, cell: usize) -> bool {
+    cell + 1 < pipes(line).len()
+}
+
+/// Whether `line` is a row of cells rather than a separator.
+pub fn is_body(line: &str) -> bool {
+    is_row(line) && !is_separator(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn tables_are_padded_into_columns_and_cursors_keep_their_cell() {
+        let table = lines(&[
+            "  | name | qty|",
+            "|:-|--:|",
+            "| apple \\| pear | 3",
+            "|kiwi|12|x|",
+        ]);
+        assert_eq!(bounds(&table, 2), Some(0..4));
+        assert_eq!(
+            format(&table),
+            [
+                "  | name          | qty |",
+                "  |:--------------|----:|---|",
+                "  | apple \\| pear |   3 |",
+                "  | kiwi          |  12 | x |",
+            ]
+        );
+        assert_eq!(
+            format(&lines(&["| a | bb |", "|---+---|"])),
+            ["| a | bb |", "|---+----|"]
+        );
+
+        let row = "|kiwi|12|x|";
+        assert_eq!(cell_at(row, 0), None);
+        assert_eq!(cell_at(row, 3), Some((0, 2)));
+        assert_eq!(cell_at(row, 5), Some((0, 4)));
+        assert_eq!(cell_at(row, 6), Some((1, 0)));
+        assert_eq!(cell_at(row, 11), Some((3, 0)));
+        let formatted = "| kiwi | 12 | x |";
+        assert_eq!(position(formatted, 0, 2), 4);
+        assert_eq!(position(formatted, 1, 0), 9);
+        assert_eq!(position(formatted, 3, 0), 17);
+        assert!(has_cell(formatted, 2));
+        assert!(!has_cell(formatted, 3));
+        assert!(is_body(formatted));
+        assert!(!is_body("|---|"));
+    }
+}
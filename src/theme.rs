@@ -0,0 +1,424 @@
+//! Colors and attributes of the editor's own UI elements, changed with
+//! `:highlight` (usually from the config file).
+
+use std::collections::BTreeMap;
+
+use crossterm::style::{Attribute, Attributes, Color, ContentStyle};
+
+/// A UI element whose look `:highlight` can change. Names follow Vim's
+/// highlight groups where Vim has one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HighlightGroup {
+    /// The status bar.
+    StatusLine,
+    /// The command line while typing `:` or `/`.
+    MsgArea,
+    /// The `~` lines past the end of the buffer.
+    EndOfBuffer,
+    /// The Visual-mode selection.
+    Visual,
+    /// Matches of the last search.
+    Search,
+    /// The match `:s///c` is asking about.
+    CurSearch,
+    /// Replacements previewed while `:s` is typed, with `inccommand`.
+    Substitute,
+    /// Trailing whitespace in Normal mode.
+    TrailingWhitespace,
+    /// Guides drawn in leading whitespace with `indentguides`.
+    IndentGuide,
+    /// Diagnostics, by severity: the sign, the message and the color of
+    /// the underline.
+    DiagnosticError,
+    DiagnosticWarn,
+    DiagnosticInfo,
+    DiagnosticHint,
+    /// The text and the border of popups.
+    NormalFloat,
+    FloatBorder,
+    /// The completion menu, its selected item and the kind letters.
+    Pmenu,
+    PmenuSel,
+    PmenuKind,
+    /// Brackets colored by nesting depth with `rainbow`, cycling through
+    /// these six.
+    Rainbow1,
+    Rainbow2,
+    Rainbow3,
+    Rainbow4,
+    Rainbow5,
+    Rainbow6,
+    /// The first line of a file shown with `:CsvAlign`.
+    CsvHeader,
+    /// Headings and code in the Markdown `:Preview`.
+    PreviewHeading,
+    PreviewCode,
+    /// Words `spell` does not find in the dictionary.
+    SpellBad,
+}
+
+impl HighlightGroup {
+    pub const ALL: [HighlightGroup; 28] = [
+        HighlightGroup::StatusLine,
+        HighlightGroup::MsgArea,
+        HighlightGroup::EndOfBuffer,
+        HighlightGroup::Visual,
+        HighlightGroup::Search,
+        HighlightGroup::CurSearch,
+        HighlightGroup::Substitute,
+        HighlightGroup::TrailingWhitespace,
+        HighlightGroup::IndentGuide,
+        HighlightGroup::DiagnosticError,
+        HighlightGroup::DiagnosticWarn,
+        HighlightGroup::DiagnosticInfo,
+        HighlightGroup::DiagnosticHint,
+        HighlightGroup::NormalFloat,
+        HighlightGroup::FloatBorder,
+        HighlightGroup::Pmenu,
+        HighlightGroup::PmenuSel,
+        HighlightGroup::PmenuKind,
+        HighlightGroup::Rainbow1,
+        HighlightGroup::Rainbow2,
+        HighlightGroup::Rainbow3,
+        HighlightGroup::Rainbow4,
+        HighlightGroup::Rainbow5,
+        HighlightGroup::Rainbow6,
+        HighlightGroup::CsvHeader,
+        HighlightGroup::PreviewHeading,
+        HighlightGroup::PreviewCode,
+        HighlightGroup::SpellBad,
+    ];
+
+    const RAINBOW: [HighlightGroup; 6] = [
+        HighlightGroup::Rainbow1,
+        HighlightGroup::Rainbow2,
+        HighlightGroup::Rainbow3,
+        HighlightGroup::Rainbow4,
+        HighlightGroup::Rainbow5,
+        HighlightGroup::Rainbow6,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HighlightGroup::StatusLine => "StatusLine",
+            HighlightGroup::MsgArea => "MsgArea",
+            HighlightGroup::EndOfBuffer => "EndOfBuffer",
+            HighlightGroup::Visual => "Visual",
+            HighlightGroup::Search => "Search",
+            HighlightGroup::CurSearch => "CurSearch",
+            HighlightGroup::Substitute => "Substitute",
+            HighlightGroup::TrailingWhitespace => "TrailingWhitespace",
+            HighlightGroup::IndentGuide => "IndentGuide",
+            HighlightGroup::DiagnosticError => "DiagnosticError",
+            HighlightGroup::DiagnosticWarn => "DiagnosticWarn",
+            HighlightGroup::DiagnosticInfo => "DiagnosticInfo",
+            HighlightGroup::DiagnosticHint => "DiagnosticHint",
+            HighlightGroup::NormalFloat => "NormalFloat",
+            HighlightGroup::FloatBorder => "FloatBorder",
+            HighlightGroup::Pmenu => "Pmenu",
+            HighlightGroup::PmenuSel => "PmenuSel",
+            HighlightGroup::PmenuKind => "PmenuKind",
+            HighlightGroup::Rainbow1 => "Rainbow1",
+            HighlightGroup::Rainbow2 => "Rainbow2",
+            HighlightGroup::Rainbow3 => "Rainbow3",
+            HighlightGroup::Rainbow4 => "Rainbow4",
+            HighlightGroup::Rainbow5 => "Rainbow5",
+            HighlightGroup::Rainbow6 => "Rainbow6",
+            HighlightGroup::CsvHeader => "CsvHeader",
+            HighlightGroup::PreviewHeading => "PreviewHeading",
+            HighlightGroup::PreviewCode => "PreviewCode",
+            HighlightGroup::SpellBad => "SpellBad",
+        }
+    }
+
+    /// The group for brackets nested `depth` levels deep.
+    pub fn rainbow(depth: usize) -> Self {
+        Self::RAINBOW[depth % Self::RAINBOW.len()]
+    }
+
+    /// Looks a group up by name, ignoring case as Vim does.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|group| group.name().eq_ignore_ascii_case(name))
+    }
+
+    fn default_style(self) -> ContentStyle {
+        let colors = |foreground, background| ContentStyle {
+            foreground_color: foreground,
+            background_color: background,
+            ..ContentStyle::new()
+        };
+        match self {
+            HighlightGroup::StatusLine => ContentStyle {
+                attributes: Attribute::Reverse.into(),
+                ..ContentStyle::new()
+            },
+            HighlightGroup::MsgArea => ContentStyle::new(),
+            HighlightGroup::EndOfBuffer => colors(Some(Color::Blue), None),
+            HighlightGroup::Visual => ContentStyle {
+                attributes: Attribute::Reverse.into(),
+                ..ContentStyle::new()
+            },
+            HighlightGroup::Search => colors(Some(Color::Black), Some(Color::Yellow)),
+            HighlightGroup::CurSearch => colors(Some(Color::Black), Some(Color::Cyan)),
+            HighlightGroup::Substitute => colors(Some(Color::Black), Some(Color::Green)),
+            HighlightGroup::TrailingWhitespace => colors(None, Some(Color::Red)),
+            HighlightGroup::IndentGuide => colors(Some(Color::DarkGrey), None),
+            HighlightGroup::DiagnosticError => colors(Some(Color::Red), None),
+            HighlightGroup::DiagnosticWarn => colors(Some(Color::Yellow), None),
+            HighlightGroup::DiagnosticInfo => colors(Some(Color::Blue), None),
+            HighlightGroup::DiagnosticHint => colors(Some(Color::Cyan), None),
+            HighlightGroup::NormalFloat => ContentStyle::new(),
+            HighlightGroup::FloatBorder => colors(Some(Color::DarkGrey), None),
+            HighlightGroup::Pmenu => colors(Some(Color::White), Some(Color::DarkGrey)),
+            HighlightGroup::PmenuSel => colors(Some(Color::Black), Some(Color::Grey)),
+            HighlightGroup::PmenuKind => colors(Some(Color::Cyan), None),
+            HighlightGroup::Rainbow1 => colors(Some(Color::Yellow), None),
+            HighlightGroup::Rainbow2 => colors(Some(Color::Magenta), None),
+            HighlightGroup::Rainbow3 => colors(Some(Color::Cyan), None),
+            HighlightGroup::Rainbow4 => colors(Some(Color::Green), None),
+            HighlightGroup::Rainbow5 => colors(Some(Color::Blue), None),
+            HighlightGroup::Rainbow6 => colors(Some(Color::Red), None),
+            HighlightGroup::CsvHeader => ContentStyle {
+                attributes: Attributes::from(Attribute::Bold) | Attribute::Underlined,
+                ..ContentStyle::new()
+            },
+            HighlightGroup::PreviewHeading => colors(Some(Color::Magenta), None),
+            HighlightGroup::PreviewCode => colors(Some(Color::Green), None),
+            HighlightGroup::SpellBad => ContentStyle {
+                foreground_color: Some(Color::Red),
+                attributes: Attributes::from(Attribute::Underlined),
+                ..ContentStyle::new()
+            },
+        }
+    }
+}
+
+/// Attribute names accepted by `:highlight`, with the attribute each sets.
+const ATTRIBUTE_NAMES: &[(&str, Attribute)] = &[
+    ("bold", Attribute::Bold),
+    ("italic", Attribute::Italic),
+    ("underline", Attribute::Underlined),
+    ("reverse", Attribute::Reverse),
+    ("inverse", Attribute::Reverse),
+    ("dim", Attribute::Dim),
+    ("strikethrough", Attribute::CrossedOut),
+];
+
+/// Color names accepted by `:highlight`, besides `#rrggbb` and 0-255.
+const COLOR_NAMES: &[(&str, Color)] = &[
+    ("black", Color::Black),
+    ("darkred", Color::DarkRed),
+    ("darkgreen", Color::DarkGreen),
+    ("darkyellow", Color::DarkYellow),
+    ("brown", Color::DarkYellow),
+    ("darkblue", Color::DarkBlue),
+    ("darkmagenta", Color::DarkMagenta),
+    ("darkcyan", Color::DarkCyan),
+    ("gray", Color::Grey),
+    ("grey", Color::Grey),
+    ("lightgray", Color::Grey),
+    ("darkgray", Color::DarkGrey),
+    ("darkgrey", Color::DarkGrey),
+    ("red", Color::Red),
+    ("green", Color::Green),
+    ("yellow", Color::Yellow),
+    ("blue", Color::Blue),
+    ("magenta", Color::Magenta),
+    ("cyan", Color::Cyan),
+    ("white", Color::White),
+];
+
+/// The style of every highlight group, starting from the built-in defaults.
+#[derive(Debug, Clone, Default)]
+pub struct UiTheme {
+    /// Groups changed by `:highlight`.
+    styles: BTreeMap<HighlightGroup, ContentStyle>,
+}
+
+impl UiTheme {
+    pub fn style(&self, group: HighlightGroup) -> ContentStyle {
+        self.styles
+            .get(&group)
+            .copied()
+            .unwrap_or_else(|| group.default_style())
+    }
+
+    /// Applies the arguments of `:highlight`: a group and `key=value`
+    /// settings (`fg`, `bg` and `attrs`, or Vim's `guifg`, `ctermfg`, `gui`
+    /// and so on). `Group clear` restores a group's default and `clear`
+    /// every group's. A group alone describes it instead.
+    pub fn apply(&mut self, args: &str) -> Result<Option<String>, String> {
+        let mut words = args.split_whitespace();
+        let Some(name) = words.next() else {
+            return Err("Argument required".to_string());
+        };
+        if name == "clear" && words.clone().next().is_none() {
+            self.styles.clear();
+            return Ok(None);
+        }
+        let group = HighlightGroup::from_name(name)
+            .ok_or_else(|| format!("No such highlight group: {}", name))?;
+        let settings: Vec<&str> = words.collect();
+        match settings.as_slice() {
+            [] => return Ok(Some(self.describe(group))),
+            ["clear"] => {
+                self.styles.remove(&group);
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        let mut style = self.style(group);
+        for setting in settings {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("Missing equal sign: {}", setting))?;
+            match key {
+                "fg" | "guifg" | "ctermfg" => style.foreground_color = parse_color(value)?,
+                "bg" | "guibg" | "ctermbg" => style.background_color = parse_color(value)?,
+                "attrs" | "gui" | "cterm" => style.attributes = parse_attributes(value)?,
+                _ => return Err(format!("Illegal argument: {}", setting)),
+            }
+        }
+        self.styles.insert(group, style);
+        Ok(None)
+    }
+
+    /// One line per group, as `:highlight` lists them.
+    pub fn describe_all(&self) -> Vec<String> {
+        HighlightGroup::ALL
+            .into_iter()
+            .map(|group| self.describe(group))
+            .collect()
+    }
+
+    fn describe(&self, group: HighlightGroup) -> String {
+        let style = self.style(group);
+        let mut settings = Vec::new();
+        if let Some(color) = style.foreground_color {
+            settings.push(format!("fg={}", color_name(color)));
+        }
+        if let Some(color) = style.background_color {
+            settings.push(format!("bg={}", color_name(color)));
+        }
+        let attrs: Vec<&str> = ATTRIBUTE_NAMES
+            .iter()
+            .filter(|(name, attr)| *name != "inverse" && style.attributes.has(*attr))
+            .map(|(name, _)| *name)
+            .collect();
+        if !attrs.is_empty() {
+            settings.push(format!("attrs={}", attrs.join(",")));
+        }
+        if settings.is_empty() {
+            settings.push("cleared".to_string());
+        }
+        format!("{:<18} {}", group.name(), settings.join(" "))
+    }
+}
+
+/// Parses a color name, `#rrggbb` or a 256-color palette index. `NONE`
+/// means the terminal's default.
+fn parse_color(value: &str) -> Result<Option<Color>, String> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    if let Some(hex) = value.strip_prefix('#')
+        && hex.len() == 6
+        && let Ok(rgb) = u32::from_str_radix(hex, 16)
+    {
+        return Ok(Some(Color::Rgb {
+            r: (rgb >> 16) as u8,
+            g: (rgb >> 8) as u8,
+            b: rgb as u8,
+        }));
+    }
+    if let Ok(index) = value.parse::<u8>() {
+        return Ok(Some(Color::AnsiValue(index)));
+    }
+    COLOR_NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map(|(_, color)| Some(*color))
+        .ok_or_else(|| format!("Invalid color: {}", value))
+}
+
+/// Parses a comma-separated list of attribute names, or `NONE`.
+fn parse_attributes(value: &str) -> Result<Attributes, String> {
+    let mut attributes = Attributes::default();
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(attributes);
+    }
+    for name in value.split(',') {
+        let (_, attr) = ATTRIBUTE_NAMES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Invalid attribute: {}", name))?;
+        attributes.set(*attr);
+    }
+    Ok(attributes)
+}
+
+fn color_name(color: Color) -> String {
+    match color {
+        Color::Rgb { r, g, b } => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        Color::AnsiValue(index) => index.to_string(),
+        color => COLOR_NAMES
+            .iter()
+            .find(|(_, named)| *named == color)
+            .map_or_else(|| format!("{:?}", color), |(name, _)| name.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_sets_and_clears_group_styles() {
+        let mut theme = UiTheme::default();
+        assert_eq!(
+            theme.style(HighlightGroup::Visual).attributes,
+            Attribute::Reverse.into()
+        );
+        theme
+            .apply("visual guibg=#203040 fg=White attrs=NONE")
+            .unwrap();
+        let style = theme.style(HighlightGroup::Visual);
+        assert_eq!(
+            style.background_color,
+            Some(Color::Rgb {
+                r: 0x20,
+                g: 0x30,
+                b: 0x40
+            })
+        );
+        assert_eq!(style.foreground_color, Some(Color::White));
+        assert!(style.attributes.is_empty());
+        assert_eq!(
+            theme.apply("Visual").unwrap().unwrap(),
+            "Visual             fg=white bg=#203040"
+        );
+
+        theme
+            .apply("StatusLine ctermfg=236 cterm=bold,italic")
+            .unwrap();
+        assert_eq!(
+            theme.apply("StatusLine").unwrap().unwrap(),
+            "StatusLine         fg=236 attrs=bold,italic"
+        );
+        theme.apply("Visual clear").unwrap();
+        assert_eq!(theme.style(HighlightGroup::Visual).background_color, None);
+        theme.apply("clear").unwrap();
+        assert_eq!(
+            theme.style(HighlightGroup::StatusLine),
+            HighlightGroup::StatusLine.default_style()
+        );
+
+        assert!(theme.apply("Nope fg=red").is_err());
+        assert!(theme.apply("Search fg=mauve").is_err());
+        assert!(theme.apply("Search fg").is_err());
+        assert!(theme.apply("Search blink=1").is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Undo history, stored as the lines each change replaced rather than as
+//! whole-buffer copies.
+
+use std::time::{Duration, Instant};
+
+/// Rows `start..start + before.len()` of one buffer state, replaced by
+/// `after` in the next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub start: usize,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    /// When the change was made, for `:earlier` and `:later`.
+    pub time: Instant,
+}
+
+impl Change {
+    /// Column of the first character that differs on the first changed row,
+    /// where `u` and Ctrl-R leave the cursor.
+    pub fn column(&self) -> usize {
+        match (self.before.first(), self.after.first()) {
+            (Some(before), Some(after)) => before
+                .chars()
+                .zip(after.chars())
+                .take_while(|(a, b)| a == b)
+                .count(),
+            _ => 0,
+        }
+    }
+}
+
+/// Steps between buffer states, oldest first. The last `entries.len() -
+/// position` steps have been undone and can be redone until a new change
+/// discards them.
+#[derive(Debug, Clone, Default)]
+pub struct UndoHistory {
+    /// The buffer as of the last recorded step.
+    base: Vec<String>,
+    entries: Vec<Change>,
+    position: usize,
+}
+
+impl UndoHistory {
+    pub fn new(lines: &[String]) -> Self {
+        Self {
+            base: lines.to_vec(),
+            entries: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Records the difference between the last recorded state and `lines` as
+    /// one step. Returns false if nothing changed.
+    pub fn commit(&mut self, lines: &[String]) -> bool {
+        self.commit_at(lines, Instant::now())
+    }
+
+    fn commit_at(&mut self, lines: &[String], time: Instant) -> bool {
+        let prefix = self
+            .base
+            .iter()
+            .zip(lines)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if prefix == self.base.len() && prefix == lines.len() {
+            return false;
+        }
+        let suffix = self
+            .base
+            .iter()
+            .rev()
+            .zip(lines.iter().rev())
+            .take(self.base.len().min(lines.len()) - prefix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let after = lines[prefix..lines.len() - suffix].to_vec();
+        let before: Vec<String> = self
+            .base
+            .splice(prefix..self.base.len() - suffix, after.iter().cloned())
+            .collect();
+        self.entries.truncate(self.position);
+        self.entries.push(Change {
+            start: prefix,
+            before,
+            after,
+            time,
+        });
+        self.position = self.entries.len();
+        true
+    }
+
+    /// Steps back one change, returning it so the caller can apply it in
+    /// reverse.
+    pub fn undo(&mut self) -> Option<&Change> {
+        self.position = self.position.checked_sub(1)?;
+        let change = &self.entries[self.position];
+        let end = change.start + change.after.len();
+        self.base
+            .splice(change.start..end, change.before.iter().cloned());
+        Some(change)
+    }
+
+    /// How many steps `:earlier {span}` undoes: every change made within
+    /// `span` of the current state.
+    pub fn steps_back_within(&self, span: Duration) -> usize {
+        let Some(current) = self.position.checked_sub(1).map(|i| self.entries[i].time) else {
+            return 0;
+        };
+        let since = current.checked_sub(span);
+        self.entries[..self.position]
+            .iter()
+            .rev()
+            .take_while(|change| since.is_none_or(|since| change.time > since))
+            .count()
+    }
+
+    /// How many steps `:later {span}` redoes: every undone change made
+    /// within `span` after the current state.
+    pub fn steps_forward_within(&self, span: Duration) -> usize {
+        let current = match self.position.checked_sub(1) {
+            Some(i) => self.entries[i].time,
+            None => match self.entries.first() {
+                Some(change) => change.time,
+                None => return 0,
+            },
+        };
+        self.entries[self.position..]
+            .iter()
+            .take_while(|change| change.time <= current + span)
+            .count()
+    }
+
+    /// Steps forward over the most recently undone change.
+    pub fn redo(&mut self) -> Option<&Change> {
+        let change = self.entries.get(self.position)?;
+        self.position += 1;
+        let end = change.start + change.before.len();
+        self.base
+            .splice(change.start..end, change.after.iter().cloned());
+        Some(change)
+    }
+}
+
+/// How far `:earlier` and `:later` travel: a number of changes, or a time
+/// span such as `10s`, `2m`, `1h` or `1d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoSpan {
+    Steps(usize),
+    Time(Duration),
+}
+
+impl UndoSpan {
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        let arg = arg.trim();
+        if arg.is_empty() {
+            return Ok(Self::Steps(1));
+        }
+        let invalid = || format!("Invalid argument: {}", arg);
+        let digits = arg.chars().take_while(char::is_ascii_digit).count();
+        let count: u64 = arg[..digits].parse().map_err(|_| invalid())?;
+        let seconds = match &arg[digits..] {
+            "" => return Ok(Self::Steps(count as usize)),
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        Ok(Self::Time(Duration::from_secs(
+            count.saturating_mul(seconds),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split('\n').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn commit_records_only_the_changed_rows() {
+        let mut history = UndoHistory::new(&lines("a\nb\nc\nd"));
+        assert!(!history.commit(&lines("a\nb\nc\nd")));
+        assert!(history.commit(&lines("a\nx\ny\nd")));
+        let change = &history.entries[0];
+        assert_eq!(change.start, 1);
+        assert_eq!(
+            (&change.before, &change.after),
+            (&lines("b\nc"), &lines("x\ny"))
+        );
+        assert!(history.commit(&lines("a\nd")));
+        assert_eq!(history.entries[1].before, lines("x\ny"));
+        assert!(history.entries[1].after.is_empty());
+    }
+
+    #[test]
+    fn undo_and_redo_walk_the_steps() {
+        let mut history = UndoHistory::new(&lines("one"));
+        history.commit(&lines("one two"));
+        history.commit(&lines("one two\nthree"));
+        let change = history.undo().unwrap();
+        assert_eq!((change.start, change.after.len()), (1, 1));
+        assert_eq!(history.undo().unwrap().column(), 3);
+        assert!(history.undo().is_none());
+        assert_eq!(history.redo().unwrap().after, lines("one two"));
+
+        // A new change drops the undone step.
+        history.commit(&lines("zero"));
+        assert!(history.redo().is_none());
+        assert_eq!(history.undo().unwrap().before, lines("one two"));
+    }
+
+    #[test]
+    fn spans_count_changes_by_time() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut history = UndoHistory::new(&lines("0"));
+        for (n, seconds) in [(1, 0), (2, 5), (3, 30), (4, 32)] {
+            history.commit_at(&lines(&n.to_string()), at(seconds));
+        }
+        assert_eq!(history.steps_back_within(Duration::from_secs(10)), 2);
+        assert_eq!(history.steps_back_within(Duration::from_secs(3600)), 4);
+        history.undo();
+        history.undo();
+        history.undo();
+        assert_eq!(history.steps_forward_within(Duration::from_secs(4)), 0);
+        assert_eq!(history.steps_forward_within(Duration::from_secs(30)), 2);
+        assert_eq!(UndoSpan::parse(""), Ok(UndoSpan::Steps(1)));
+        assert_eq!(UndoSpan::parse("3"), Ok(UndoSpan::Steps(3)));
+        assert_eq!(
+            UndoSpan::parse("2m"),
+            Ok(UndoSpan::Time(Duration::from_secs(120)))
+        );
+        assert_eq!(
+            UndoSpan::parse("5x"),
+            Err("Invalid argument: 5x".to_string())
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! Virtual text: annotations that plugins attach to buffer lines, such as
+//! end-of-line messages and inline hints. It is drawn with its own style
+//! between or after the line's characters but never enters the buffer.
+
+use std::collections::BTreeMap;
+
+use crossterm::style::ContentStyle;
+
+/// One piece of text drawn in a buffer line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualText {
+    /// Char column the text is drawn before, or `None` to draw it after the
+    /// end of the line.
+    pub col: Option<usize>,
+    /// Only drawn while the cursor is on the line.
+    pub cursor_line: bool,
+    pub text: String,
+    pub style: ContentStyle,
+}
+
+/// Virtual text by buffer row, kept per namespace so each plugin replaces
+/// only its own. Owners clear and re-add theirs when the lines change.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualTextSet {
+    namespaces: BTreeMap<&'static str, BTreeMap<usize, Vec<VirtualText>>>,
+}
+
+impl VirtualTextSet {
+    /// Attaches `text` to `row` under `namespace`, after any already there.
+    pub fn add(&mut self, namespace: &'static str, row: usize, text: VirtualText) {
+        self.namespaces
+            .entry(namespace)
+            .or_default()
+            .entry(row)
+            .or_default()
+            .push(text);
+    }
+
+    /// Removes everything added under `namespace`.
+    pub fn clear(&mut self, namespace: &'static str) {
+        self.namespaces.remove(namespace);
+    }
+
+    /// The text attached to `row`, by namespace and then in the order added.
+    pub fn on_row(&self, row: usize) -> impl Iterator<Item = &VirtualText> {
+        self.namespaces
+            .values()
+            .filter_map(move |rows| rows.get(&row))
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(col: Option<usize>, text: &str) -> VirtualText {
+        VirtualText {
+            col,
+            cursor_line: false,
+            text: text.to_string(),
+            style: ContentStyle::new(),
+        }
+    }
+
+    #[test]
+    fn namespaces_are_cleared_separately() {
+        let mut set = VirtualTextSet::default();
+        set.add("lint", 2, text(None, "unused"));
+        set.add("hints", 2, text(Some(4), ": i32"));
+        set.add("lint", 2, text(None, "shadowed"));
+        let row: Vec<&str> = set.on_row(2).map(|vt| vt.text.as_str()).collect();
+        assert_eq!(row, vec![": i32", "unused", "shadowed"]);
+        set.clear("lint");
+        assert_eq!(set.on_row(2).count(), 1);
+        assert_eq!(set.on_row(3).count(), 0);
+    }
+}
@@ -0,0 +1,175 @@
+//! The channel that wakes the main loop: terminal input, read on its own
+//! thread, requests from background work to collect its results and
+//! redraw, and signals asking the editor to exit.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::event::{self, Event};
+
+/// A reason for the main loop to run.
+#[derive(Debug)]
+pub enum Wakeup {
+    /// A terminal event, or the error that stopped the input thread.
+    Input(io::Result<Event>),
+    /// Background work has something to show.
+    Redraw,
+    /// The named signal, SIGHUP or SIGTERM, asks the editor to exit.
+    Terminate(&'static str),
+}
+
+/// Sends wakeups to the main loop. The default waker, used when there is no
+/// loop to wake (headless runs and tests), does nothing.
+#[derive(Debug, Clone, Default)]
+pub struct Waker(Option<Sender<Wakeup>>);
+
+impl Waker {
+    /// Asks the main loop to poll background work and redraw.
+    pub fn wake(&self) {
+        if let Some(sender) = &self.0 {
+            let _ = sender.send(Wakeup::Redraw);
+        }
+    }
+
+    /// Reads terminal events on a background thread and sends each one to
+    /// the main loop, until reading fails or the loop is gone. The returned
+    /// gate pauses the reading while another program uses the terminal.
+    pub fn forward_input(&self) -> InputGate {
+        let gate = InputGate::default();
+        let Some(sender) = self.0.clone() else {
+            gate.stop();
+            return gate;
+        };
+        let thread_gate = gate.clone();
+        thread::spawn(move || {
+            loop {
+                thread_gate.wait_open();
+                // Poll rather than block, so a closed gate is noticed.
+                let event = match event::poll(INPUT_POLL_INTERVAL) {
+                    Ok(false) => continue,
+                    Ok(true) => event::read(),
+                    Err(err) => Err(err),
+                };
+                let failed = event.is_err();
+                if sender.send(Wakeup::Input(event)).is_err() || failed {
+                    break;
+                }
+            }
+            thread_gate.stop();
+        });
+        gate
+    }
+}
+
+impl Waker {
+    /// Sends SIGHUP and SIGTERM to the main loop instead of letting them
+    /// kill the editor at once, so it can save the modified buffers and
+    /// restore the terminal before exiting.
+    #[cfg(unix)]
+    pub fn forward_signals(&self) -> io::Result<()> {
+        use signal_hook::consts::{SIGHUP, SIGTERM};
+
+        let Some(sender) = self.0.clone() else {
+            return Ok(());
+        };
+        let mut signals = signal_hook::iterator::Signals::new([SIGHUP, SIGTERM])?;
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                let name = if signal == SIGHUP {
+                    "SIGHUP"
+                } else {
+                    "SIGTERM"
+                };
+                if sender.send(Wakeup::Terminate(name)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn forward_signals(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How long the input thread waits for an event before checking its gate.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum GateState {
+    #[default]
+    Open,
+    /// Asked to close; the input thread has not yet stopped reading.
+    Closing,
+    Closed,
+    /// There is no input thread to stop.
+    Stopped,
+}
+
+/// Lets the main loop stop the input thread reading the terminal, so a
+/// program run in the foreground, such as `sudo` asking for a password,
+/// gets the keys instead.
+#[derive(Debug, Clone, Default)]
+pub struct InputGate(Arc<(Mutex<GateState>, Condvar)>);
+
+impl InputGate {
+    /// Stops the input thread, returning once it has stopped reading.
+    pub fn close(&self) {
+        let (state, changed) = &*self.0;
+        let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+        if *state == GateState::Stopped {
+            return;
+        }
+        *state = GateState::Closing;
+        while *state == GateState::Closing {
+            state = changed.wait(state).unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    /// Lets the input thread read again.
+    pub fn open(&self) {
+        self.set(GateState::Open);
+    }
+
+    /// Records that the input thread is gone, or never started.
+    fn stop(&self) {
+        self.set(GateState::Stopped);
+    }
+
+    fn set(&self, new: GateState) {
+        let (state, changed) = &*self.0;
+        let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+        if *state != GateState::Stopped {
+            *state = new;
+        }
+        changed.notify_all();
+    }
+
+    /// Waits while the gate is closed, on the input thread.
+    fn wait_open(&self) {
+        let (state, changed) = &*self.0;
+        let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+        loop {
+            match *state {
+                GateState::Open | GateState::Stopped => return,
+                GateState::Closing => {
+                    *state = GateState::Closed;
+                    changed.notify_all();
+                }
+                GateState::Closed => {}
+            }
+            state = changed.wait(state).unwrap_or_else(|err| err.into_inner());
+        }
+    }
+}
+
+/// A waker and the receiver the main loop waits on.
+pub fn channel() -> (Waker, Receiver<Wakeup>) {
+    let (sender, receiver) = mpsc::channel();
+    (Waker(Some(sender)), receiver)
+}
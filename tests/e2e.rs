@@ -14,6 +14,10 @@ fn spawn_minivim(path: &Path) -> Session {
     let mut cmd = Command::new(bin);
     cmd.arg(path);
     cmd.env("TERM", "xterm-256color");
+    if let Some(dir) = path.parent() {
+        cmd.env("MINIVIM_STATE_DIR", dir.join("state"));
+        cmd.env("MINIVIM_CONFIG", dir.join("minivimrc"));
+    }
     Session::spawn(cmd).expect("spawn minivim")
 }
 
@@ -73,7 +77,7 @@ fn insert_and_write() {
     session.expect(Eof).unwrap();
 
     let text = std::fs::read_to_string(path).expect("read file");
-    assert_eq!(text, "hello world");
+    assert_eq!(text, "hello world\n");
 }
 
 #[test]
@@ -97,3 +101,183 @@ fn render_shows_typed_text() {
 
     assert!(contents.contains("hello"));
 }
+
+#[test]
+fn scrolling_keeps_screen_in_sync() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("lines.txt");
+    let contents: Vec<String> = (1..=100).map(|n| format!("line {}", n)).collect();
+    std::fs::write(&path, contents.join("\n")).expect("write file");
+
+    let mut session = spawn_minivim(&path);
+    session.set_expect_timeout(Some(Duration::from_secs(2)));
+
+    let mut parser = Parser::new(24, 80, 0);
+    drain_output(&mut session, &mut parser, Duration::from_millis(200)).unwrap();
+
+    session.send("j".repeat(30)).unwrap();
+    assert!(wait_for_text(&mut session, &mut parser, "line 31", Duration::from_secs(2)).unwrap());
+    session.send("k".repeat(25)).unwrap();
+    drain_output(&mut session, &mut parser, Duration::from_millis(300)).unwrap();
+
+    let screen = parser.screen();
+    let rows: Vec<String> = screen.rows(0, 80).take(23).collect();
+    session.send(":q!\r").unwrap();
+    session.expect(Eof).unwrap();
+
+    let expected: Vec<String> = (6..=28).map(|n| format!("line {}", n)).collect();
+    assert_eq!(rows, expected);
+}
+
+#[test]
+fn command_history_recalls_by_prefix() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("history.txt");
+    let other = dir.path().join("other.txt");
+
+    let mut session = spawn_minivim(&path);
+    session.set_expect_timeout(Some(Duration::from_secs(2)));
+    let mut parser = Parser::new(24, 80, 0);
+    drain_output(&mut session, &mut parser, Duration::from_millis(200)).unwrap();
+
+    session.send(format!(":w {}\r", other.display())).unwrap();
+    session.send(":set kp=man\r").unwrap();
+    drain_output(&mut session, &mut parser, Duration::from_millis(100)).unwrap();
+    // Up with "w" typed skips the newer `set` entry.
+    session.send(":w\x1b[A").unwrap();
+    let recalled = format!(":w {}", other.display());
+    assert!(wait_for_text(&mut session, &mut parser, &recalled, Duration::from_secs(1)).unwrap());
+    session.send("\x1b").unwrap();
+    // Keep Esc and `:` in separate reads so they are not parsed as Alt-:.
+    wait_for_text(&mut session, &mut parser, "NORMAL", Duration::from_secs(1)).unwrap();
+    session.send(":q!\r").unwrap();
+    session.expect(Eof).unwrap();
+
+    let saved = std::fs::read_to_string(dir.path().join("state/command_history")).unwrap();
+    assert!(saved.contains("set kp=man"));
+}
+
+#[test]
+fn bracketed_paste_keeps_lines_intact() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("paste.txt");
+
+    let mut session = spawn_minivim(&path);
+    session.set_expect_timeout(Some(Duration::from_secs(2)));
+
+    let mut parser = Parser::new(24, 80, 0);
+    drain_output(&mut session, &mut parser, Duration::from_millis(200)).unwrap();
+
+    session.send("i").unwrap();
+    session
+        .send("\x1b[200~fn main() {\r    println!(\"hi\");\r}\x1b[201~")
+        .unwrap();
+    assert!(wait_for_text(&mut session, &mut parser, "println", Duration::from_secs(2)).unwrap());
+    session.send("\x1b").unwrap();
+    wait_for_text(&mut session, &mut parser, "NORMAL", Duration::from_secs(1)).unwrap();
+
+    session.send(":wq\r").unwrap();
+    session.expect(Eof).unwrap();
+
+    let text = std::fs::read_to_string(path).expect("read file");
+    assert_eq!(text, "fn main() {\n    println!(\"hi\");\n}\n");
+}
+
+#[test]
+fn sigterm_saves_unsaved_changes_for_recovery() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("draft.txt");
+    std::fs::write(&path, "one\n").expect("write file");
+
+    let mut session = spawn_minivim(&path);
+    session.set_expect_timeout(Some(Duration::from_secs(2)));
+
+    let mut parser = Parser::new(24, 80, 0);
+    drain_output(&mut session, &mut parser, Duration::from_millis(200)).unwrap();
+
+    session.send("otwo").unwrap();
+    session.send("\x1b").unwrap();
+    assert!(wait_for_text(&mut session, &mut parser, "two", Duration::from_secs(1)).unwrap());
+
+    let pid = session.get_process().pid().to_string();
+    let status = Command::new("kill")
+        .args(["-TERM", &pid])
+        .status()
+        .expect("run kill");
+    assert!(status.success());
+    session.expect(Eof).unwrap();
+
+    let name = path.to_string_lossy().replace('/', "%");
+    let copy = dir.path().join("state").join("recovery").join(name);
+    assert_eq!(
+        std::fs::read_to_string(copy).expect("read recovery copy"),
+        "one\ntwo\n"
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\n");
+}
+
+#[test]
+fn ctrl_z_gives_back_the_terminal_and_redraws_on_resume() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("suspend.txt");
+    std::fs::write(&path, "kept text\n").expect("write file");
+
+    let mut session = spawn_minivim(&path);
+    session.set_expect_timeout(Some(Duration::from_secs(2)));
+
+    let mut parser = Parser::new(24, 80, 0);
+    assert!(
+        wait_for_text(
+            &mut session,
+            &mut parser,
+            "kept text",
+            Duration::from_secs(2)
+        )
+        .unwrap()
+    );
+
+    // Stop, resize the terminal while stopped, and continue as `fg` does.
+    session.send("\x1a").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    session.get_process_mut().set_window_size(100, 30).unwrap();
+    let pid = session.get_process().pid().to_string();
+    let status = Command::new("kill")
+        .args(["-CONT", &pid])
+        .status()
+        .expect("run kill");
+    assert!(status.success());
+
+    let mut output = Vec::new();
+    let start = Instant::now();
+    let mut buf = [0u8; 8192];
+    while start.elapsed() < Duration::from_secs(1) {
+        match session.try_read(&mut buf) {
+            Ok(n) if n > 0 => output.extend_from_slice(&buf[..n]),
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    let find = |needle: &[u8]| {
+        output
+            .windows(needle.len())
+            .position(|window| window == needle)
+    };
+    let left = find(b"\x1b[?1049l").expect("alternate screen left");
+    let entered = find(b"\x1b[?1049h").expect("alternate screen entered again");
+    assert!(left < entered);
+    assert!(!session.get_process().get_echo().unwrap(), "raw mode again");
+
+    let mut resumed = Parser::new(30, 100, 0);
+    resumed.process(&output[entered..]);
+    let screen = resumed.screen().contents();
+    assert!(screen.contains("kept text"), "{}", screen);
+    assert!(
+        !resumed
+            .screen()
+            .contents_between(29, 0, 29, 100)
+            .trim()
+            .is_empty()
+    );
+
+    session.send(":q!\r").unwrap();
+    session.expect(Eof).unwrap();
+}
@@ -0,0 +1,292 @@
+use std::process::Command;
+
+use tempfile::tempdir;
+
+fn minivim() -> Command {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("minivim"));
+    cmd.arg("--clean");
+    cmd
+}
+
+#[test]
+fn headless_runs_commands_in_order() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("input.txt");
+    let copy = dir.path().join("copy.txt");
+    std::fs::write(&path, "alpha\nbeta\n").expect("write file");
+
+    let output = minivim()
+        .arg("--headless")
+        .arg("-c")
+        .arg(format!("w {}", copy.display()))
+        .arg("-c")
+        .arg("q")
+        .arg(&path)
+        .output()
+        .expect("run minivim");
+
+    assert!(output.status.success());
+    let text = std::fs::read_to_string(copy).expect("read copy");
+    assert_eq!(text, "alpha\nbeta\n");
+}
+
+#[test]
+fn headless_reads_script_file() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("input.txt");
+    let script = dir.path().join("script.vim");
+    let copy = dir.path().join("copy.txt");
+    std::fs::write(&path, "hello").expect("write file");
+    std::fs::write(
+        &script,
+        format!(
+            "\" copy the file as it is\nset nofixeol\n:w {}\nq\n",
+            copy.display()
+        ),
+    )
+    .expect("write script");
+
+    let status = minivim()
+        .arg("--headless")
+        .arg("--script")
+        .arg(&script)
+        .arg(&path)
+        .status()
+        .expect("run minivim");
+
+    assert!(status.success());
+    assert_eq!(std::fs::read_to_string(copy).expect("read copy"), "hello");
+}
+
+#[test]
+fn headless_reports_unknown_commands() {
+    let output = minivim()
+        .args(["--headless", "-c", "frobnicate"])
+        .output()
+        .expect("run minivim");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Not an editor command: frobnicate"));
+}
+
+#[test]
+fn headless_refuses_to_run_against_a_file_that_failed_to_load() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("binary.txt");
+    std::fs::write(&path, b"\xff\xfe\n").expect("write file");
+
+    let output = minivim()
+        .args(["--headless", "-c", "w"])
+        .arg(&path)
+        .output()
+        .expect("run minivim");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Open failed"), "stderr: {}", stderr);
+    assert_eq!(std::fs::read(&path).expect("read file"), b"\xff\xfe\n");
+}
+
+#[test]
+fn headless_fails_when_a_command_reports_an_error() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("input.txt");
+    std::fs::write(&path, "alpha\n").expect("write file");
+
+    let output = minivim()
+        .args(["--headless", "-c", "s/beta/gamma/", "-c", "q"])
+        .arg(&path)
+        .output()
+        .expect("run minivim");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Pattern not found: beta"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn session_round_trip_restores_file_and_options() {
+    let dir = tempdir().expect("tempdir");
+    // Characters that ex arguments expand are kept in the file name.
+    let path = dir.path().join("in%put#$1.txt");
+    let session = dir.path().join("work.session");
+    let copy = dir.path().join("copy.txt");
+    std::fs::write(&path, "one\ntwo\n").expect("write file");
+
+    let status = minivim()
+        .arg("--headless")
+        .arg("+2")
+        .arg("-c")
+        .arg("set kp=pydoc3")
+        .arg("-c")
+        .arg(format!("mksession {}", session.display()))
+        .arg(&path)
+        .status()
+        .expect("run minivim");
+    assert!(status.success());
+
+    let output = minivim()
+        .arg("--headless")
+        .arg("-S")
+        .arg(&session)
+        .arg("-c")
+        .arg("set kp?")
+        .arg("-c")
+        .arg(format!("w {}", copy.display()))
+        .output()
+        .expect("run minivim");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("kp=pydoc3"), "stderr: {}", stderr);
+    assert_eq!(
+        std::fs::read_to_string(copy).expect("read copy"),
+        "one\ntwo\n"
+    );
+}
+
+#[test]
+fn config_file_runs_before_startup_commands() {
+    let dir = tempdir().expect("tempdir");
+    let config = dir.path().join("minivimrc");
+    std::fs::write(&config, "\" user settings\nset kp=pydoc3\n").expect("write config");
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("minivim"))
+        .env("MINIVIM_CONFIG", &config)
+        .args(["--headless", "-c", "set kp?"])
+        .output()
+        .expect("run minivim");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("kp=pydoc3"));
+
+    let output = minivim()
+        .env("MINIVIM_CONFIG", &config)
+        .args(["--headless", "-c", "set kp?"])
+        .output()
+        .expect("run minivim");
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("kp=pydoc3"));
+}
+
+#[test]
+fn headless_substitutes_with_regex() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("input.txt");
+    std::fs::write(&path, "x = 1\ny = 22\n").expect("write file");
+
+    let status = minivim()
+        .arg("--headless")
+        .arg("-c")
+        .arg(r"%s/(\w) = (\d+)/\2 -> \1/")
+        .arg("-c")
+        .arg("wq")
+        .arg(&path)
+        .status()
+        .expect("run minivim");
+
+    assert!(status.success());
+    let text = std::fs::read_to_string(&path).expect("read file");
+    assert_eq!(text, "1 -> x\n22 -> y\n");
+}
+
+#[test]
+fn headless_moves_copies_and_undoes_lines() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("input.txt");
+    std::fs::write(&path, "a\nb\nc\nd\n").expect("write file");
+
+    let status = minivim()
+        .arg("--headless")
+        .args([
+            "-c", "1m3", "-c", "1t0", "-c", "$d", "-c", "undo", "-c", "wq",
+        ])
+        .arg(&path)
+        .status()
+        .expect("run minivim");
+
+    assert!(status.success());
+    let text = std::fs::read_to_string(&path).expect("read file");
+    assert_eq!(text, "b\nb\nc\na\nd\n");
+}
+
+#[test]
+fn headless_cfdo_edits_every_grep_match_file() {
+    let dir = tempdir().expect("tempdir");
+    std::fs::write(dir.path().join("a.txt"), "foo\nkeep\nfoo foo\n").expect("write file");
+    std::fs::write(dir.path().join("b.txt"), "x foo\n").expect("write file");
+    std::fs::write(dir.path().join("c.txt"), "nothing\n").expect("write file");
+
+    let status = minivim()
+        .current_dir(dir.path())
+        .arg("--headless")
+        .args(["-c", "grep foo", "-c", "cfdo %s/foo/bar/g | w", "-c", "q"])
+        .status()
+        .expect("run minivim");
+
+    assert!(status.success());
+    let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).expect("read file");
+    assert_eq!(read("a.txt"), "bar\nkeep\nbar bar\n");
+    assert_eq!(read("b.txt"), "x bar\n");
+    assert_eq!(read("c.txt"), "nothing\n");
+}
+
+#[test]
+fn headless_trims_trailing_whitespace_on_write() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("input.txt");
+    std::fs::write(&path, "a  \n\tb\t\n").expect("write file");
+
+    let status = minivim()
+        .arg("--headless")
+        .args(["-c", "set trim_trailing_whitespace", "-c", "wq"])
+        .arg(&path)
+        .status()
+        .expect("run minivim");
+
+    assert!(status.success());
+    let text = std::fs::read_to_string(&path).expect("read file");
+    assert_eq!(text, "a\n\tb\n");
+}
+
+#[test]
+fn headless_cd_resolves_relative_paths_against_the_new_directory() {
+    let dir = tempdir().expect("tempdir");
+    std::fs::create_dir(dir.path().join("sub")).expect("create dir");
+    std::fs::write(dir.path().join("input.txt"), "alpha\n").expect("write file");
+
+    let status = minivim()
+        .current_dir(dir.path())
+        .arg("--headless")
+        .args([
+            "-c",
+            "cd sub",
+            "-c",
+            "s/alpha/beta/",
+            "-c",
+            "w",
+            "-c",
+            "w copy.txt",
+            "-c",
+            "cd -",
+            "-c",
+            "set autochdir",
+            "-c",
+            "e sub/new.txt",
+            "-c",
+            "w other.txt",
+            "-c",
+            "q",
+        ])
+        .arg("input.txt")
+        .status()
+        .expect("run minivim");
+
+    assert!(status.success());
+    let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).expect("read file");
+    assert_eq!(read("input.txt"), "beta\n");
+    assert_eq!(read("sub/copy.txt"), "beta\n");
+    assert!(dir.path().join("sub/other.txt").exists());
+    assert!(!dir.path().join("sub/input.txt").exists());
+}